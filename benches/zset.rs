@@ -0,0 +1,77 @@
+//! Times rank and range lookups on a large sorted set, like a leaderboard, and
+//! compares them with a `BTreeSet` that has to count its way to a rank. Run
+//! with `cargo bench --bench zset`.
+
+use std::collections::BTreeSet;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use redis_clone::string::RedisString;
+use redis_clone::zset::{Score, SortedSet};
+
+const MEMBERS: u32 = 100_000;
+const LOOKUPS: u32 = 1_000;
+
+fn main() {
+    let elements: Vec<_> = (0..MEMBERS)
+        .map(|i| {
+            // Spread scores out so they aren't inserted in order.
+            let score = f64::from(i.wrapping_mul(2_654_435_761) % MEMBERS);
+            (
+                Score::new(score).unwrap(),
+                RedisString::from(format!("player:{i}")),
+            )
+        })
+        .collect();
+
+    let zset: SortedSet = elements.iter().map(|(s, m)| (m.clone(), *s)).collect();
+    let btree: BTreeSet<_> = elements.iter().cloned().collect();
+    let probes: Vec<_> = (0..LOOKUPS)
+        .map(|i| &elements[(i * (MEMBERS / LOOKUPS)) as usize])
+        .collect();
+
+    println!("{MEMBERS} members, {LOOKUPS} lookups each");
+
+    report(
+        "ZRANK",
+        time(|| {
+            for (_, member) in &probes {
+                black_box(zset.rank(member));
+            }
+        }),
+        time(|| {
+            for probe in &probes {
+                black_box(btree.range(..*probe).count());
+            }
+        }),
+    );
+
+    report(
+        "ZRANGE start start+10",
+        time(|| {
+            for i in 0..LOOKUPS {
+                let start = (i * (MEMBERS / LOOKUPS)) as usize;
+                black_box(zset.range_by_rank(start..start + 10).count());
+            }
+        }),
+        time(|| {
+            for i in 0..LOOKUPS {
+                let start = (i * (MEMBERS / LOOKUPS)) as usize;
+                black_box(btree.iter().skip(start).take(10).count());
+            }
+        }),
+    );
+}
+
+fn time(f: impl Fn()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn report(name: &str, skip_list: Duration, btree: Duration) {
+    println!(
+        "{name:<24} skip list: {skip_list:>10.2?}  BTreeSet scan: {btree:>10.2?}  ({:.0}x)",
+        btree.as_secs_f64() / skip_list.as_secs_f64()
+    );
+}
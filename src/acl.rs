@@ -0,0 +1,923 @@
+//! Access control lists. See <https://redis.io/docs/management/security/acl/>.
+//!
+//! ACLs limit what each user can do: which commands they can run, which keys
+//! they can access, and which pub/sub channels they can use.
+//!
+//! Users are changed with `ACL SETUSER` rules like Redis': `on`, `>password`,
+//! `~pattern`, `&pattern`, `+command`, `-@category` and so on, applied in
+//! order. The `default` user is the one clients are authenticated as unless
+//! they `AUTH` with a username, and its password is `requirepass`.
+//!
+//! Users can be saved to and loaded from an ACL file, which has a `user
+//! <name> <rule> ...` line for each user. Denied commands and failed `AUTH`s
+//! are recorded in the `ACL LOG`.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+
+use crate::command_table;
+use crate::config::ServerConfig;
+use crate::glob;
+use crate::sha256::{sha256, to_hex};
+
+/// The name of the user clients start out as, which can't be deleted.
+pub const DEFAULT_USER: &str = "default";
+
+/// How many entries `ACL LOG` keeps by default, like Redis' `acllog-max-len`.
+pub const DEFAULT_LOG_MAX_LEN: usize = 128;
+
+/// Denials of the same thing within this many milliseconds of the first are
+/// grouped into one `ACL LOG` entry, like Redis does.
+const LOG_GROUPING_MILLIS: i64 = 60_000;
+
+/// A group of related commands, which rules like `+@read` allow or deny all
+/// at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Keyspace,
+    Read,
+    Write,
+    Set,
+    SortedSet,
+    List,
+    Hash,
+    String,
+    Bitmap,
+    Geo,
+    Stream,
+    PubSub,
+    Admin,
+    Fast,
+    Slow,
+    Blocking,
+    Dangerous,
+    Connection,
+    Scripting,
+}
+
+impl Category {
+    pub const ALL: [Self; 19] = [
+        Self::Keyspace,
+        Self::Read,
+        Self::Write,
+        Self::Set,
+        Self::SortedSet,
+        Self::List,
+        Self::Hash,
+        Self::String,
+        Self::Bitmap,
+        Self::Geo,
+        Self::Stream,
+        Self::PubSub,
+        Self::Admin,
+        Self::Fast,
+        Self::Slow,
+        Self::Blocking,
+        Self::Dangerous,
+        Self::Connection,
+        Self::Scripting,
+    ];
+
+    /// The category's name, without the `@`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Keyspace => "keyspace",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Set => "set",
+            Self::SortedSet => "sortedset",
+            Self::List => "list",
+            Self::Hash => "hash",
+            Self::String => "string",
+            Self::Bitmap => "bitmap",
+            Self::Geo => "geo",
+            Self::Stream => "stream",
+            Self::PubSub => "pubsub",
+            Self::Admin => "admin",
+            Self::Fast => "fast",
+            Self::Slow => "slow",
+            Self::Blocking => "blocking",
+            Self::Dangerous => "dangerous",
+            Self::Connection => "connection",
+            Self::Scripting => "scripting",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|category| category.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// The commands in a category, like `ACL CAT category` lists.
+pub fn category_commands(category: Category) -> Vec<&'static str> {
+    command_table::COMMANDS
+        .iter()
+        .filter(|spec| spec.categories().contains(&category))
+        .map(|spec| spec.name)
+        .collect()
+}
+
+/// A key pattern a user can access, from `~pattern` (reads and writes),
+/// `%R~pattern` (reads) or `%W~pattern` (writes).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    /// Formats the pattern the way it's given in rules.
+    fn describe(&self) -> String {
+        match (self.read, self.write) {
+            (true, true) => format!("~{}", self.pattern),
+            (true, false) => format!("%R~{}", self.pattern),
+            _ => format!("%W~{}", self.pattern),
+        }
+    }
+}
+
+/// A user, and what it's allowed to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    enabled: bool,
+
+    /// Whether any password authenticates the user.
+    nopass: bool,
+
+    /// SHA-256 hashes of the user's passwords.
+    passwords: Vec<[u8; 32]>,
+
+    /// The lower-cased names of the commands the user may run.
+    commands: BTreeSet<&'static str>,
+
+    /// The command rules that produced `commands`, for describing the user.
+    /// They're reset by rules that allow or deny every command.
+    command_rules: Vec<String>,
+
+    keys: Vec<KeyPattern>,
+
+    /// Patterns for the pub/sub channels the user may use.
+    channels: Vec<String>,
+}
+
+impl User {
+    /// A new user, which like Redis starts out disabled and allowed to do
+    /// nothing.
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            commands: BTreeSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            keys: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// Whether the user is enabled and `password` is one of its passwords.
+    /// Passwords are compared by their hashes in constant time, so how long a
+    /// wrong guess takes doesn't reveal how close it was.
+    pub fn authenticates(&self, password: &[u8]) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let hash = sha256(password);
+        // Every password is checked, so the time taken doesn't reveal which
+        // one matched.
+        let matches = self
+            .passwords
+            .iter()
+            .filter(|stored| {
+                stored
+                    .iter()
+                    .zip(hash)
+                    .fold(0, |diff, (x, y)| diff | (x ^ y))
+                    == 0
+            })
+            .count();
+        self.nopass || matches > 0
+    }
+
+    pub const fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether the user has no password, so any password authenticates it.
+    pub const fn nopass(&self) -> bool {
+        self.nopass
+    }
+
+    /// Whether the user may run the command with the lower-cased `name`.
+    pub fn can_run(&self, name: &str) -> bool {
+        self.commands.contains(name)
+    }
+
+    /// Whether the user may read, or if `write` is set write, `key`.
+    pub fn can_access_key(&self, key: &[u8], write: bool) -> bool {
+        self.keys.iter().any(|pattern| {
+            (if write { pattern.write } else { pattern.read })
+                && glob::matches(pattern.pattern.as_bytes(), key)
+        })
+    }
+
+    /// Whether the user may use `channel`. For `PSUBSCRIBE`, `is_pattern` is
+    /// set and like Redis the pattern must be one of the user's patterns
+    /// exactly, since it could match channels the user can't use otherwise.
+    pub fn can_access_channel(&self, channel: &[u8], is_pattern: bool) -> bool {
+        self.channels.iter().any(|allowed| {
+            allowed == "*"
+                || if is_pattern {
+                    allowed.as_bytes() == channel
+                } else {
+                    glob::matches(allowed.as_bytes(), channel)
+                }
+        })
+    }
+
+    /// Applies an `ACL SETUSER` rule, or describes why it's invalid.
+    fn apply_rule(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_ascii_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.passwords.clear();
+                self.nopass = true;
+            }
+            "resetpass" => {
+                self.passwords.clear();
+                self.nopass = false;
+            }
+            "allkeys" => return self.apply_rule("~*"),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => return self.apply_rule("&*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => return self.apply_rule("+@all"),
+            "nocommands" => return self.apply_rule("-@all"),
+            "reset" => {
+                for rule in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.apply_rule(rule)?;
+                }
+            }
+            _ => return self.apply_pattern_rule(rule),
+        }
+        Ok(())
+    }
+
+    fn apply_pattern_rule(&mut self, rule: &str) -> Result<(), String> {
+        if let Some(password) = rule.strip_prefix('>') {
+            let hash = sha256(password.as_bytes());
+            if !self.passwords.contains(&hash) {
+                self.passwords.push(hash);
+            }
+            self.nopass = false;
+        } else if let Some(password) = rule.strip_prefix('<') {
+            self.remove_password(&sha256(password.as_bytes()))?;
+        } else if let Some(hex) = rule.strip_prefix('#') {
+            let hash = parse_hash(hex)?;
+            if !self.passwords.contains(&hash) {
+                self.passwords.push(hash);
+            }
+            self.nopass = false;
+        } else if let Some(hex) = rule.strip_prefix('!') {
+            self.remove_password(&parse_hash(hex)?)?;
+        } else if let Some(pattern) = rule.strip_prefix('&') {
+            if self.channels.iter().any(|channel| channel == "*") {
+                return Err(pattern_after_star("resetchannels"));
+            }
+            if pattern == "*" {
+                self.channels.clear();
+            }
+            self.channels.push(pattern.to_string());
+        } else if rule.starts_with('~') || rule.starts_with('%') {
+            self.add_key_pattern(rule)?;
+        } else if let Some(name) = rule.strip_prefix('+') {
+            self.set_commands(name, true)?;
+            self.command_rules.push(rule.to_ascii_lowercase());
+        } else if let Some(name) = rule.strip_prefix('-') {
+            self.set_commands(name, false)?;
+            self.command_rules.push(rule.to_ascii_lowercase());
+        } else {
+            return Err("Syntax error".to_string());
+        }
+        Ok(())
+    }
+
+    fn remove_password(&mut self, hash: &[u8; 32]) -> Result<(), String> {
+        let len = self.passwords.len();
+        self.passwords.retain(|password| password != hash);
+        if self.passwords.len() == len {
+            return Err(
+                "The password you are trying to remove from the user does not exist".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    fn add_key_pattern(&mut self, rule: &str) -> Result<(), String> {
+        let (permissions, pattern) = rule
+            .split_once('~')
+            .ok_or_else(|| "Syntax error".to_string())?;
+        let (read, write) = match permissions.to_ascii_uppercase().as_str() {
+            "" | "%RW" | "%WR" => (true, true),
+            "%R" => (true, false),
+            "%W" => (false, true),
+            _ => return Err("Syntax error".to_string()),
+        };
+        if self
+            .keys
+            .iter()
+            .any(|key| key.pattern == "*" && key.read && key.write)
+        {
+            return Err(pattern_after_star("resetkeys"));
+        }
+        let pattern = KeyPattern {
+            pattern: pattern.to_string(),
+            read,
+            write,
+        };
+        if pattern.pattern == "*" && read && write {
+            self.keys.clear();
+        }
+        if !self.keys.contains(&pattern) {
+            self.keys.push(pattern);
+        }
+        Ok(())
+    }
+
+    /// Allows or denies a command, or with `@category` every command in the
+    /// category.
+    fn set_commands(&mut self, name: &str, allow: bool) -> Result<(), String> {
+        let name = name.to_ascii_lowercase();
+        let commands: Vec<&'static str> = match name.strip_prefix('@') {
+            Some("all") => {
+                self.command_rules.clear();
+                command_table::COMMANDS
+                    .iter()
+                    .map(|spec| spec.name)
+                    .collect()
+            }
+            Some(category) => category_commands(
+                Category::parse(category).ok_or("Unknown command or category name in ACL")?,
+            ),
+            None => {
+                let spec =
+                    command_table::find(&name).ok_or("Unknown command or category name in ACL")?;
+                vec![spec.name]
+            }
+        };
+        for command in commands {
+            if allow {
+                self.commands.insert(command);
+            } else {
+                self.commands.remove(command);
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes the user like `ACL GETUSER`, as its flags, password hashes,
+    /// command rules, key patterns and channel patterns.
+    pub fn describe(&self) -> UserDescription {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        let channels: Vec<String> = self
+            .channels
+            .iter()
+            .map(|channel| format!("&{channel}"))
+            .collect();
+        UserDescription {
+            flags,
+            passwords: self.passwords.iter().map(to_hex).collect(),
+            commands: self.command_rules.join(" "),
+            keys: self
+                .keys
+                .iter()
+                .map(KeyPattern::describe)
+                .collect::<Vec<_>>()
+                .join(" "),
+            channels: channels.join(" "),
+        }
+    }
+
+    /// The rules that recreate the user from scratch, like `ACL LIST` shows
+    /// and the ACL file has. Passwords are given as their hashes.
+    pub fn rules(&self) -> String {
+        let description = self.describe();
+        let mut rules: Vec<String> = description.flags.iter().map(ToString::to_string).collect();
+        rules.extend(description.passwords.iter().map(|hash| format!("#{hash}")));
+        rules.extend(
+            [description.keys, description.channels, description.commands]
+                .into_iter()
+                .filter(|rule| !rule.is_empty()),
+        );
+        rules.join(" ")
+    }
+}
+
+/// A user, as described by `ACL GETUSER`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserDescription {
+    pub flags: Vec<&'static str>,
+
+    /// The hex SHA-256 hashes of the user's passwords.
+    pub passwords: Vec<String>,
+    pub commands: String,
+    pub keys: String,
+    pub channels: String,
+}
+
+fn pattern_after_star(reset: &str) -> String {
+    format!(
+        "Adding a pattern after the * pattern (or the 'all' flag) is not valid and does not \
+         have any effect. Try '{reset}' to start with an empty list of patterns"
+    )
+}
+
+fn parse_hash(hex: &str) -> Result<[u8; 32], String> {
+    let invalid = || {
+        "The password hash must be exactly 64 characters and contain only lowercase \
+         hexadecimal characters"
+            .to_string()
+    };
+    if hex.len() != 64 || !hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return Err(invalid());
+    }
+    let mut hash = [0; 32];
+    for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(hash)
+}
+
+/// Why an operation was denied, as shown in `ACL LOG`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenialReason {
+    /// The user may not run the command.
+    Command,
+
+    /// The user may not access a key the command uses.
+    Key,
+
+    /// The user may not use a channel the command uses.
+    Channel,
+
+    /// `AUTH` was given the wrong password or a disabled user.
+    Auth,
+}
+
+impl DenialReason {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Command => "command",
+            Self::Key => "key",
+            Self::Channel => "channel",
+            Self::Auth => "auth",
+        }
+    }
+}
+
+/// An operation that was denied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    pub reason: DenialReason,
+
+    /// The command, key or channel that was denied, or `AUTH`.
+    pub object: String,
+    pub username: String,
+
+    /// Describes the client, like `id=3 addr=127.0.0.1:51234`.
+    pub client_info: String,
+}
+
+/// An entry in the `ACL LOG`, which counts denials of the same thing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub denial: Denial,
+    pub count: usize,
+
+    /// Increases with each entry, so clients can tell entries apart.
+    pub entry_id: usize,
+
+    /// When the first and the latest denials happened, in milliseconds
+    /// since the Unix epoch.
+    pub created: i64,
+    pub updated: i64,
+}
+
+/// Every user, by name, and the log of what they were denied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Users {
+    by_name: BTreeMap<String, User>,
+
+    /// The most recent entries first.
+    log: VecDeque<LogEntry>,
+    log_max_len: usize,
+    next_entry_id: usize,
+}
+
+impl Default for Users {
+    /// Only the default user, which like Redis can do anything without a
+    /// password.
+    fn default() -> Self {
+        let mut default = User::new();
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            default
+                .apply_rule(rule)
+                .expect("default user's rules are valid");
+        }
+        Self {
+            by_name: BTreeMap::from([(DEFAULT_USER.to_string(), default)]),
+            log: VecDeque::new(),
+            log_max_len: DEFAULT_LOG_MAX_LEN,
+            next_entry_id: 0,
+        }
+    }
+}
+
+impl Users {
+    /// The users, with the default user's password set to `requirepass`.
+    pub fn with_requirepass(requirepass: Option<&str>) -> Self {
+        let mut users = Self::default();
+        users.set_requirepass(requirepass);
+        users
+    }
+
+    /// The users for a server's settings, before any are loaded from the
+    /// ACL file.
+    pub fn with_config(config: &ServerConfig) -> Self {
+        let mut users = Self::with_requirepass(config.requirepass.as_deref());
+        users.set_log_max_len(config.acllog_max_len);
+        users
+    }
+
+    pub fn get(&self, name: &str) -> Option<&User> {
+        self.by_name.get(name)
+    }
+
+    /// Sets the default user's password, like Redis does when `requirepass`
+    /// changes. No password means any password works.
+    pub fn set_requirepass(&mut self, requirepass: Option<&str>) {
+        let default = self
+            .by_name
+            .get_mut(DEFAULT_USER)
+            .expect("the default user can't be deleted");
+        let result = match requirepass {
+            Some(password) => default
+                .apply_rule("resetpass")
+                .and_then(|()| default.apply_rule(&format!(">{password}"))),
+            None => default.apply_rule("nopass"),
+        };
+        result.expect("password rules are valid");
+    }
+
+    /// Creates or changes a user, like `ACL SETUSER`. Either every rule is
+    /// applied, or none are and the error is the message for the client.
+    pub fn set_user(&mut self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut user = self.by_name.get(name).cloned().unwrap_or_else(User::new);
+        for rule in rules {
+            user.apply_rule(rule)
+                .map_err(|reason| format!("Error in ACL SETUSER modifier '{rule}': {reason}"))?;
+        }
+        self.by_name.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// Deletes users, like `ACL DELUSER`, returning how many existed.
+    pub fn delete(&mut self, names: &[String]) -> Result<usize, String> {
+        if names.iter().any(|name| name == DEFAULT_USER) {
+            return Err("The 'default' user cannot be removed".to_string());
+        }
+        Ok(names
+            .iter()
+            .filter(|name| self.by_name.remove(name.as_str()).is_some())
+            .count())
+    }
+
+    /// Every user as a `user <name> <rule> ...` line, like `ACL LIST`.
+    pub fn list(&self) -> Vec<String> {
+        self.by_name
+            .iter()
+            .map(|(name, user)| format!("user {name} {}", user.rules()))
+            .collect()
+    }
+
+    /// Replaces the users with those in an ACL file, like `ACL LOAD`. Like
+    /// Redis, the default user stays as it is if the file doesn't have it,
+    /// and nothing changes if any line is invalid.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        let mut users = BTreeMap::new();
+        for (i, line) in contents.lines().enumerate() {
+            let error = |reason: String| eyre!("{}:{}: {reason}", path.display(), i + 1);
+            let mut args = line.split_whitespace();
+            let Some(keyword) = args.next() else {
+                continue;
+            };
+            if keyword != "user" {
+                return Err(error("line should start with user keyword".to_string()));
+            }
+            let name = args
+                .next()
+                .ok_or_else(|| error("user name missing".to_string()))?;
+            if users.contains_key(name) {
+                return Err(error(format!("Duplicate user '{name}' found")));
+            }
+            let mut user = User::new();
+            for rule in args {
+                user.apply_rule(rule).map_err(|reason| {
+                    error(format!("Error in user declaration '{rule}': {reason}"))
+                })?;
+            }
+            users.insert(name.to_string(), user);
+        }
+        if let Some(default) = self.by_name.get(DEFAULT_USER) {
+            users
+                .entry(DEFAULT_USER.to_string())
+                .or_insert_with(|| default.clone());
+        }
+        self.by_name = users;
+        Ok(())
+    }
+
+    /// Writes the users to an ACL file, like `ACL SAVE`. The file is
+    /// replaced atomically, so it's never left half written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut contents = self.list().join("\n");
+        contents.push('\n');
+        let temp_path = path.with_file_name(format!("temp-acl-{}.acl", std::process::id()));
+        fs::write(&temp_path, contents)
+            .wrap_err_with(|| format!("failed to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, path).wrap_err_with(|| {
+            format!(
+                "failed to rename {} to {}",
+                temp_path.display(),
+                path.display()
+            )
+        })
+    }
+
+    /// Records a denied operation in the log. Like Redis, it's grouped with
+    /// an entry for the same thing from the last minute rather than getting
+    /// its own entry.
+    pub fn log_denial(&mut self, denial: Denial, now: i64) {
+        let existing = self.log.iter().position(|entry| {
+            entry.denial.reason == denial.reason
+                && entry.denial.object == denial.object
+                && entry.denial.username == denial.username
+                && now.saturating_sub(entry.created) < LOG_GROUPING_MILLIS
+        });
+        let entry = if let Some(entry) = existing.and_then(|i| self.log.remove(i)) {
+            LogEntry {
+                denial,
+                count: entry.count + 1,
+                updated: now,
+                ..entry
+            }
+        } else {
+            self.next_entry_id += 1;
+            LogEntry {
+                denial,
+                count: 1,
+                entry_id: self.next_entry_id - 1,
+                created: now,
+                updated: now,
+            }
+        };
+        self.log.push_front(entry);
+        self.log.truncate(self.log_max_len);
+    }
+
+    /// The log's entries, the most recent first.
+    pub fn log(&self) -> impl Iterator<Item = &LogEntry> {
+        self.log.iter()
+    }
+
+    pub fn reset_log(&mut self) {
+        self.log.clear();
+    }
+
+    /// Sets how many entries the log keeps, like `acllog-max-len`.
+    pub fn set_log_max_len(&mut self, max_len: usize) {
+        self.log_max_len = max_len;
+        self.log.truncate(max_len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha256::sha256_hex;
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn default_user_can_do_anything() {
+        let users = Users::default();
+        let default = users.get(DEFAULT_USER).unwrap();
+        assert!(default.authenticates(b"anything"));
+        assert!(default.can_run("flushall"));
+        assert!(default.can_access_key(b"key", true));
+        assert!(default.can_access_channel(b"news", false));
+        assert!(default.can_access_channel(b"n*", true));
+        assert_eq!(
+            default.describe(),
+            UserDescription {
+                flags: vec!["on", "nopass"],
+                passwords: vec![],
+                commands: "+@all".to_string(),
+                keys: "~*".to_string(),
+                channels: "&*".to_string(),
+            }
+        );
+
+        let users = Users::with_requirepass(Some("secret"));
+        let default = users.get(DEFAULT_USER).unwrap();
+        assert!(default.authenticates(b"secret"));
+        assert!(!default.authenticates(b"wrong"));
+    }
+
+    #[test]
+    fn set_user_applies_rules() {
+        let mut users = Users::default();
+        users
+            .set_user(
+                "alice",
+                &rules(&[
+                    "on",
+                    ">p1",
+                    ">p2",
+                    "<p1",
+                    "~cache:*",
+                    "%R~config:*",
+                    "&news.*",
+                    "+@read",
+                    "-@dangerous",
+                    "+SET",
+                ]),
+            )
+            .unwrap();
+        let alice = users.get("alice").unwrap();
+        assert!(alice.authenticates(b"p2"));
+        assert!(!alice.authenticates(b"p1"));
+        assert!(alice.can_run("get"));
+        assert!(alice.can_run("set"));
+        assert!(!alice.can_run("del"));
+        assert!(!alice.can_run("info"));
+        assert!(alice.can_access_key(b"cache:1", true));
+        assert!(alice.can_access_key(b"config:1", false));
+        assert!(!alice.can_access_key(b"config:1", true));
+        assert!(!alice.can_access_key(b"other", false));
+        assert!(alice.can_access_channel(b"news.tech", false));
+        assert!(alice.can_access_channel(b"news.*", true));
+        assert!(!alice.can_access_channel(b"news.t*", true));
+        assert_eq!(
+            alice.describe(),
+            UserDescription {
+                flags: vec!["on"],
+                passwords: vec![sha256_hex(b"p2")],
+                commands: "-@all +@read -@dangerous +set".to_string(),
+                keys: "~cache:* %R~config:*".to_string(),
+                channels: "&news.*".to_string(),
+            }
+        );
+
+        // Disabled users can't authenticate, and rules are applied all or
+        // nothing.
+        users.set_user("alice", &rules(&["off"])).unwrap();
+        assert!(!users.get("alice").unwrap().authenticates(b"p2"));
+        let before = users.clone();
+        let err = users
+            .set_user("alice", &rules(&["on", "+nope"]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Error in ACL SETUSER modifier '+nope': Unknown command or category name in ACL"
+        );
+        assert_eq!(users, before);
+
+        for rule in ["bogus", "<p3", "#abc", "%X~key", "-@nope"] {
+            assert!(users.set_user("alice", &rules(&[rule])).is_err(), "{rule}");
+        }
+        assert!(users
+            .set_user("alice", &rules(&["allkeys", "~more"]))
+            .is_err());
+        users
+            .set_user(
+                "alice",
+                &rules(&["reset", &format!("#{}", sha256_hex(b"p4"))]),
+            )
+            .unwrap();
+        let alice = users.get("alice").unwrap();
+        assert!(!alice.can_run("get"));
+        assert!(!alice.can_access_key(b"cache:1", true));
+        assert_eq!(alice.describe().passwords, [sha256_hex(b"p4")]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("redis-clone-acl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("users.acl");
+
+        let mut users = Users::with_requirepass(Some("secret"));
+        users
+            .set_user(
+                "alice",
+                &rules(&["on", ">p1", "%R~cache:*", "&news", "+@read", "-get"]),
+            )
+            .unwrap();
+        assert_eq!(
+            users.list(),
+            [
+                format!(
+                    "user alice on #{} %R~cache:* &news -@all +@read -get",
+                    sha256_hex(b"p1")
+                ),
+                format!("user default on #{} ~* &* +@all", sha256_hex(b"secret")),
+            ]
+        );
+        users.save(&path).unwrap();
+
+        // The default user is kept when the file doesn't have it.
+        let mut loaded = Users::default();
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.list(), users.list());
+        fs::write(&path, "user bob on nopass\n\n").unwrap();
+        loaded.load(&path).unwrap();
+        assert!(loaded.get("alice").is_none());
+        assert!(loaded.get("bob").unwrap().authenticates(b"anything"));
+        assert!(loaded.get(DEFAULT_USER).unwrap().authenticates(b"secret"));
+
+        // Invalid files change nothing.
+        for contents in ["carol on\n", "user a\nuser a\n"] {
+            fs::write(&path, contents).unwrap();
+            let before = loaded.clone();
+            assert!(loaded.load(&path).is_err(), "{contents}");
+            assert_eq!(loaded, before);
+        }
+        fs::write(&path, "user carol on\nuser dave +nope\n").unwrap();
+        let before = loaded.clone();
+        assert_eq!(
+            loaded.load(&path).unwrap_err().to_string(),
+            format!(
+                "{}:2: Error in user declaration '+nope': Unknown command or category name in \
+                 ACL",
+                path.display()
+            )
+        );
+        assert_eq!(loaded, before);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn log_groups_denials() {
+        let mut users = Users::default();
+        let denial = |object: &str| Denial {
+            reason: DenialReason::Key,
+            object: object.to_string(),
+            username: "alice".to_string(),
+            client_info: "id=0".to_string(),
+        };
+        users.log_denial(denial("a"), 1000);
+        users.log_denial(denial("b"), 2000);
+        users.log_denial(denial("a"), 3000);
+        let entries: Vec<_> = users
+            .log()
+            .map(|entry| (entry.denial.object.as_str(), entry.count, entry.entry_id))
+            .collect();
+        assert_eq!(entries, [("a", 2, 0), ("b", 1, 1)]);
+
+        // Denials more than a minute after the first get a new entry.
+        users.log_denial(denial("a"), 1000 + LOG_GROUPING_MILLIS);
+        assert_eq!(users.log().count(), 3);
+        users.set_log_max_len(1);
+        let entries: Vec<_> = users.log().map(|entry| entry.entry_id).collect();
+        assert_eq!(entries, [2]);
+        users.reset_log();
+        assert_eq!(users.log().count(), 0);
+    }
+
+    #[test]
+    fn delete_users() {
+        let mut users = Users::default();
+        users.set_user("a", &[]).unwrap();
+        users.set_user("b", &[]).unwrap();
+        assert_eq!(users.delete(&rules(&["a", "b", "c"])), Ok(2));
+        assert!(users.get("a").is_none());
+        assert!(users.delete(&rules(&["default"])).is_err());
+    }
+}
@@ -0,0 +1,895 @@
+//! The append-only file (AOF), which logs every write command so the keyspace
+//! can be rebuilt by replaying them when the server starts.
+//!
+//! Commands are appended in RESP format by a dedicated writer thread, which
+//! syncs the file to disk according to the `appendfsync` policy.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Report, Result, WrapErr};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+use crate::command::{
+    Command, CommandResponse, Expiration, Expire, HSet, ListEnd, Push, Restore, SAdd, Select, Set,
+    XAdd, XClaim, XGroup, ZAdd,
+};
+use crate::db::{unix_time_millis, Db, Value};
+use crate::rdb;
+use crate::resp::Message;
+use crate::stream::{NewId, ReadId, Stream, StreamId};
+use crate::string::RedisString;
+
+/// The AOF a server uses unless configured otherwise, like Redis' default
+/// `appendfilename`.
+pub const DEFAULT_AOF_PATH: &str = "appendonly.aof";
+
+/// When the AOF is synced to disk, like Redis' `appendfsync` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every write command, before it is acknowledged. The slowest and
+    /// safest policy.
+    Always,
+
+    /// Once per second, so at most a second of writes is lost on a crash.
+    EverySec,
+
+    /// Whenever the operating system decides to flush its buffers.
+    No,
+}
+
+impl FsyncPolicy {
+    /// The policy's name in `appendfsync`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::EverySec => "everysec",
+            Self::No => "no",
+        }
+    }
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "everysec" => Ok(Self::EverySec),
+            "no" => Ok(Self::No),
+            _ => Err(eyre!("invalid appendfsync policy {s:?}")),
+        }
+    }
+}
+
+/// Settings for the AOF, which is disabled unless a server is given these.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AofConfig {
+    pub path: PathBuf,
+    pub fsync: FsyncPolicy,
+
+    /// Whether rewrites start the AOF with an RDB snapshot of the dataset
+    /// instead of commands, like Redis' `aof-use-rdb-preamble`. Snapshots are
+    /// smaller and much faster to load.
+    pub use_rdb_preamble: bool,
+
+    /// Whether an AOF that ends with a truncated command is loaded anyway,
+    /// like Redis' `aof-load-truncated`.
+    pub load_truncated: bool,
+}
+
+impl Default for AofConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from(DEFAULT_AOF_PATH),
+            fsync: FsyncPolicy::EverySec,
+            use_rdb_preamble: true,
+            load_truncated: true,
+        }
+    }
+}
+
+/// An `Aof` forwards write commands to the AOF writer thread, and rewrites the
+/// AOF for `BGREWRITEAOF`.
+#[derive(Debug)]
+pub struct Aof {
+    config: AofConfig,
+    writer: Writer,
+
+    /// The database the commands in the log currently apply to, which changes
+    /// with a `SELECT` in the log.
+    db: Option<usize>,
+
+    /// The rewrite in progress, if any.
+    rewrite: Option<Rewrite>,
+}
+
+/// A rewrite running on a background thread, which writes the dataset as it
+/// was when the rewrite started to a temporary file.
+#[derive(Debug)]
+struct Rewrite {
+    temp_path: PathBuf,
+    handle: JoinHandle<Result<()>>,
+
+    /// Commands appended since the rewrite started, which are added to the
+    /// end of the rewritten AOF.
+    buffer: Vec<u8>,
+}
+
+impl Aof {
+    /// Opens the AOF for appending, creating it if needed, and starts the
+    /// writer thread. The thread exits once the `Aof` is dropped and every
+    /// pending command is written.
+    pub fn open(config: &AofConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            writer: Writer::start(config)?,
+            db: None,
+            rewrite: None,
+        })
+    }
+
+    /// Applies new settings to the open AOF. The path can't change, since
+    /// that would need a rewrite.
+    pub fn set_config(&mut self, config: &AofConfig) -> Result<()> {
+        let fsync_changed = config.fsync != self.config.fsync;
+        self.config = AofConfig {
+            path: self.config.path.clone(),
+            ..config.clone()
+        };
+        if fsync_changed {
+            // The new writer gets nothing to write until the old one has
+            // written everything it was sent, so writes stay in order.
+            let old = std::mem::replace(&mut self.writer, Writer::start(&self.config)?);
+            old.stop();
+        }
+        Ok(())
+    }
+
+    /// Appends a command that was run against database `db`. With
+    /// `appendfsync always`, this waits until the command is on disk.
+    pub fn append(&mut self, db: usize, command: &Command) {
+        let mut bytes = Vec::new();
+        if self.db != Some(db) {
+            let index = i64::try_from(db).expect("database index fits in i64");
+            bytes.extend(Command::Select(Select { index }).to_resp().to_bytes());
+            self.db = Some(db);
+        }
+        bytes.extend(command.to_resp().to_bytes());
+        if let Some(rewrite) = &mut self.rewrite {
+            rewrite.buffer.extend_from_slice(&bytes);
+        }
+        self.writer.write(bytes);
+        self.poll_rewrite();
+    }
+
+    /// Rewrites the AOF on a background thread as the shortest sequence of
+    /// commands that recreates `dbs`, or as an RDB preamble if enabled. Commands appended in the meantime keep
+    /// going to the old AOF, and are added to the new one before it replaces
+    /// the old one.
+    pub fn start_rewrite(&mut self, dbs: &[Db]) -> Result<()> {
+        if self.rewrite_in_progress() {
+            return Err(eyre!(
+                "Background append only file rewriting already in progress"
+            ));
+        }
+        let dbs = dbs.to_vec();
+        let temp_path = self
+            .config
+            .path
+            .with_file_name(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+        let path = temp_path.clone();
+        let use_rdb_preamble = self.config.use_rdb_preamble;
+        let handle = thread::spawn(move || {
+            let file = File::create(&path)
+                .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+            let mut writer = BufWriter::new(file);
+            if use_rdb_preamble {
+                write_preamble(&mut writer, dbs)?;
+            } else {
+                write_dataset(&mut writer, &dbs)?;
+            }
+            let file = writer
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)?;
+            file.sync_all()?;
+            Ok(())
+        });
+        // The rewritten AOF ends in an unknown database, so the buffered
+        // commands have to start with a `SELECT`.
+        self.db = None;
+        self.rewrite = Some(Rewrite {
+            temp_path,
+            handle,
+            buffer: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Whether a rewrite is still running.
+    pub fn rewrite_in_progress(&mut self) -> bool {
+        self.poll_rewrite();
+        self.rewrite.is_some()
+    }
+
+    /// Waits for the rewrite in progress, if any, and swaps in the rewritten
+    /// AOF. Returns whether it succeeded.
+    pub fn wait_for_rewrite(&mut self) -> bool {
+        let Some(rewrite) = self.rewrite.take() else {
+            return true;
+        };
+        self.finish_rewrite(rewrite)
+    }
+
+    /// Waits for the rewrite in progress, if any, and for every appended
+    /// command to be written and synced to disk.
+    pub fn close(mut self) {
+        self.wait_for_rewrite();
+        self.writer.stop();
+    }
+
+    /// Swaps in the rewritten AOF once the rewrite is done.
+    fn poll_rewrite(&mut self) {
+        if self
+            .rewrite
+            .as_ref()
+            .is_some_and(|rewrite| rewrite.handle.is_finished())
+        {
+            let rewrite = self.rewrite.take().expect("rewrite exists");
+            self.finish_rewrite(rewrite);
+        }
+    }
+
+    fn finish_rewrite(&mut self, rewrite: Rewrite) -> bool {
+        let Rewrite {
+            temp_path,
+            handle,
+            buffer,
+        } = rewrite;
+        let finished = match handle.join() {
+            Ok(Ok(())) => self.swap_rewritten(&temp_path, &buffer),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(eyre!("rewrite thread panicked")),
+        };
+        match finished {
+            Ok(()) => {
+                log::info!("background AOF rewrite finished successfully");
+                true
+            }
+            Err(e) => {
+                log::error!("background AOF rewrite failed: {e:?}");
+                let _ = fs::remove_file(&temp_path);
+                false
+            }
+        }
+    }
+
+    /// Appends the commands buffered during the rewrite to the rewritten AOF,
+    /// and atomically replaces the old AOF with it. The old writer thread
+    /// exits once it's replaced.
+    fn swap_rewritten(&mut self, temp_path: &Path, buffer: &[u8]) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(temp_path)
+            .wrap_err_with(|| format!("failed to open {}", temp_path.display()))?;
+        file.write_all(buffer)?;
+        file.sync_all()?;
+        fs::rename(temp_path, &self.config.path).wrap_err_with(|| {
+            format!(
+                "failed to rename {} to {}",
+                temp_path.display(),
+                self.config.path.display()
+            )
+        })?;
+        self.writer = Writer::start(&self.config)?;
+        Ok(())
+    }
+}
+
+/// The sending end of an AOF writer thread.
+#[derive(Debug)]
+struct Writer {
+    sender: Sender<Vec<u8>>,
+    handle: JoinHandle<()>,
+
+    /// With `appendfsync always`, signaled once each command is on disk.
+    synced: Option<Receiver<()>>,
+}
+
+impl Writer {
+    /// Opens the AOF for appending and starts a thread that writes commands
+    /// to it. The thread exits once the `Writer` is dropped.
+    fn start(config: &AofConfig) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)
+            .wrap_err_with(|| format!("failed to open {}", config.path.display()))?;
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let (synced_sender, synced) = match config.fsync {
+            FsyncPolicy::Always => {
+                let (sender, receiver) = crossbeam_channel::unbounded();
+                (Some(sender), Some(receiver))
+            }
+            FsyncPolicy::EverySec | FsyncPolicy::No => (None, None),
+        };
+        let fsync = config.fsync;
+        let handle = thread::spawn(move || {
+            if let Err(e) = write_commands(file, fsync, &receiver, synced_sender.as_ref()) {
+                log::error!("error writing AOF: {e:?}");
+            }
+        });
+        Ok(Self {
+            sender,
+            handle,
+            synced,
+        })
+    }
+
+    /// Waits for the thread to write everything it was sent and exit.
+    fn stop(self) {
+        let Self { sender, handle, .. } = self;
+        drop(sender);
+        if handle.join().is_err() {
+            log::error!("AOF writer thread panicked");
+        }
+    }
+
+    /// Queues bytes to be written. With `appendfsync always`, this waits until
+    /// they're on disk.
+    fn write(&self, bytes: Vec<u8>) {
+        if self.sender.send(bytes).is_err() {
+            log::error!("AOF writer thread is not running, dropping write");
+        } else if let Some(synced) = &self.synced {
+            if synced.recv().is_err() {
+                log::error!("AOF writer thread stopped before syncing");
+            }
+        }
+    }
+}
+
+/// Writes commands from `receiver` to the AOF until every sender is dropped.
+fn write_commands(
+    file: File,
+    fsync: FsyncPolicy,
+    receiver: &Receiver<Vec<u8>>,
+    synced: Option<&Sender<()>>,
+) -> Result<()> {
+    let mut writer = BufWriter::new(file);
+    let mut last_sync = Instant::now();
+    loop {
+        // With `everysec`, wake up once a second to sync writes that are
+        // still only in the OS buffers.
+        let next = match fsync {
+            FsyncPolicy::EverySec => receiver.recv_timeout(Duration::from_secs(1)),
+            FsyncPolicy::Always | FsyncPolicy::No => {
+                receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            }
+        };
+        let mut written = 0;
+        match next {
+            Ok(bytes) => {
+                writer.write_all(&bytes)?;
+                written += 1;
+                // Write out everything that's queued before flushing.
+                for bytes in receiver.try_iter() {
+                    writer.write_all(&bytes)?;
+                    written += 1;
+                }
+                writer.flush()?;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        match fsync {
+            FsyncPolicy::Always if written > 0 => {
+                writer.get_ref().sync_data()?;
+                if let Some(synced) = synced {
+                    for _ in 0..written {
+                        // The `Aof` is gone if nobody is waiting.
+                        let _ = synced.send(());
+                    }
+                }
+            }
+            FsyncPolicy::EverySec if last_sync.elapsed() >= Duration::from_secs(1) => {
+                writer.get_ref().sync_data()?;
+                last_sync = Instant::now();
+            }
+            _ => {}
+        }
+    }
+    writer.flush()?;
+    writer.get_ref().sync_data()?;
+    Ok(())
+}
+
+/// Something read from an AOF while replaying it.
+#[derive(Debug)]
+pub enum Record {
+    /// The databases loaded from the AOF's RDB preamble, which come before
+    /// any command.
+    Snapshot(Vec<Db>),
+
+    /// A command and the database it was run against.
+    Command(usize, Command),
+}
+
+/// Replays the AOF, calling `apply` with the RDB preamble if it has one, and
+/// then with each command. Returns `false` if there is no AOF yet.
+///
+/// An AOF that ends in the middle of a command, like after a crash during a
+/// write, fails to load unless `load_truncated` is set. Then the incomplete
+/// command is cut off the end of the file and loading continues.
+pub fn replay<F>(config: &AofConfig, num_databases: usize, mut apply: F) -> Result<bool>
+where
+    F: FnMut(Record),
+{
+    let path = &config.path;
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).wrap_err_with(|| format!("failed to open {}", path.display())),
+    };
+    let start = Instant::now();
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    if reader.fill_buf()?.starts_with(b"REDIS") {
+        let dbs = rdb::read_snapshot(&mut reader, num_databases)
+            .wrap_err_with(|| format!("failed to load RDB preamble of {}", path.display()))?;
+        apply(Record::Snapshot(dbs));
+    }
+
+    let mut db = 0;
+    let mut commands = 0;
+    let mut offset = reader.stream_position()?;
+    let replayed = (|| loop {
+        offset = reader.stream_position()?;
+        let message = match Message::parse_resp(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => return Ok(None),
+            // Running out of data mid-command means the file is truncated,
+            // rather than corrupted.
+            Err(e) if reader.stream_position()? == len => return Ok(Some(e)),
+            Err(e) => return Err(e),
+        };
+        match Command::parse_resp(&message)? {
+            Command::Select(Select { index }) => {
+                db = usize::try_from(index)
+                    .ok()
+                    .filter(|&index| index < num_databases)
+                    .ok_or_else(|| eyre!("database index {index} is out of range"))?;
+            }
+            command => apply(Record::Command(db, command)),
+        }
+        commands += 1;
+    })();
+    let context = || {
+        format!(
+            "failed to load AOF {} after {commands} commands, at offset {offset}",
+            path.display()
+        )
+    };
+    if let Some(e) = replayed.wrap_err_with(context)? {
+        if !config.load_truncated {
+            return Err(e.wrap_err(Truncated { offset }).wrap_err(context()));
+        }
+        discard_truncated(path, offset, len)?;
+    }
+    log::info!(
+        "replayed {commands} commands from {} in {:?}",
+        path.display(),
+        start.elapsed()
+    );
+    Ok(true)
+}
+
+/// The error when an AOF ends with a truncated command, and `load_truncated`
+/// isn't set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncated {
+    /// Where the truncated command starts.
+    pub offset: u64,
+}
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the AOF ends with a truncated command at offset {}",
+            self.offset
+        )
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+/// Cuts off the truncated command at the end of the AOF, which starts at
+/// `offset`, logging what's discarded.
+fn discard_truncated(path: &Path, offset: u64, len: u64) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .wrap_err_with(|| format!("failed to open {}", path.display()))?;
+    let mut discarded = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_to_end(&mut discarded)?;
+    log::warn!(
+        "AOF {} ends with a truncated command, discarding the last {} bytes at offset {offset}: {:?}",
+        path.display(),
+        len - offset,
+        RedisString::from(discarded),
+    );
+    file.set_len(offset)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Writes `dbs` as an RDB snapshot, followed by the commands that recreate
+/// their streams, which can't be stored in RDB snapshots.
+pub fn write_preamble<W: Write>(writer: &mut W, mut dbs: Vec<Db>) -> Result<()> {
+    let streams: Vec<Db> = dbs
+        .iter_mut()
+        .map(|db| {
+            let stream_keys: Vec<RedisString> = db
+                .entries()
+                .iter()
+                .filter(|(_, entry)| matches!(entry.value, Value::Stream(_)))
+                .map(|(key, _)| key.clone())
+                .collect();
+            let mut streams_db = Db::default();
+            for key in stream_keys {
+                let expires_at = db.expires_at(&key);
+                if let Some(entry) = db.remove(&key) {
+                    streams_db.insert(key.clone(), entry);
+                    streams_db.set_expires_at(&key, expires_at);
+                }
+            }
+            streams_db
+        })
+        .collect();
+    rdb::write_snapshot(&mut *writer, &dbs)?;
+    write_dataset(writer, &streams)
+}
+
+/// How many elements each command of a rewritten AOF adds at most, like Redis'
+/// `AOF_REWRITE_ITEMS_PER_CMD`.
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// Writes the commands that recreate `dbs`, followed by a `PEXPIREAT` for each
+/// key with an expiration time. Expired keys are left out.
+pub fn write_dataset<W: Write>(writer: &mut W, dbs: &[Db]) -> Result<()> {
+    let now = unix_time_millis();
+    for (index, db) in dbs.iter().enumerate() {
+        let mut entries = db
+            .entries()
+            .iter()
+            .filter(|(key, _)| !db.is_expired(key, now))
+            .peekable();
+        if entries.peek().is_none() {
+            continue;
+        }
+        let index = i64::try_from(index).expect("database index fits in i64");
+        Command::Select(Select { index })
+            .to_resp()
+            .serialize_resp(writer)?;
+        for (key, entry) in entries {
+            for command in value_commands(key, &entry.value) {
+                command.to_resp().serialize_resp(writer)?;
+            }
+            if let Some(expires_at) = db.expires_at(key) {
+                let expire = Command::Expire(Expire {
+                    key: key.clone(),
+                    time: Expiration::UnixMilliseconds(expires_at),
+                    existence: None,
+                    comparison: None,
+                });
+                expire.to_resp().serialize_resp(writer)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The commands that create `key` holding `value`.
+///
+/// Streams are recreated with their entries, consumer groups and pending
+/// entries. Consumers without pending entries are left out, and so is a last
+/// ID beyond the last entry, which can't be set with the commands we support.
+fn value_commands(key: &RedisString, value: &Value) -> Vec<Command> {
+    match value {
+        Value::String(s) => vec![Command::Set(Set {
+            key: key.clone(),
+            value: s.to_redis_string().into_owned(),
+        })],
+        Value::List(list) => {
+            let elements: Vec<_> = list.iter().cloned().collect();
+            elements
+                .chunks(ITEMS_PER_COMMAND)
+                .map(|chunk| {
+                    Command::Push(Push {
+                        key: key.clone(),
+                        end: ListEnd::Right,
+                        elements: chunk.to_vec(),
+                    })
+                })
+                .collect()
+        }
+        Value::Set(set) => {
+            let members: Vec<_> = set.iter().map(Cow::into_owned).collect();
+            members
+                .chunks(ITEMS_PER_COMMAND)
+                .map(|chunk| {
+                    Command::SAdd(SAdd {
+                        key: key.clone(),
+                        members: chunk.to_vec(),
+                    })
+                })
+                .collect()
+        }
+        Value::Hash(hash) => {
+            let pairs: Vec<_> = hash.iter().map(|(f, v)| (f.clone(), v.clone())).collect();
+            pairs
+                .chunks(ITEMS_PER_COMMAND)
+                .map(|chunk| {
+                    Command::HSet(HSet {
+                        key: key.clone(),
+                        pairs: chunk.to_vec(),
+                    })
+                })
+                .collect()
+        }
+        Value::ZSet(zset) => {
+            let members: Vec<_> = zset.iter().map(|(m, s)| (s, m.clone())).collect();
+            members
+                .chunks(ITEMS_PER_COMMAND)
+                .map(|chunk| {
+                    Command::ZAdd(ZAdd {
+                        key: key.clone(),
+                        existence: None,
+                        comparison: None,
+                        ch: false,
+                        incr: false,
+                        members: chunk.to_vec(),
+                    })
+                })
+                .collect()
+        }
+        Value::Stream(stream) => stream_commands(key, stream),
+    }
+}
+
+fn stream_commands(key: &RedisString, stream: &Stream) -> Vec<Command> {
+    let mut commands: Vec<_> = stream
+        .after(StreamId::MIN)
+        .map(|(id, fields)| {
+            Command::XAdd(XAdd {
+                key: key.clone(),
+                id: NewId::Explicit(*id),
+                fields: fields.clone(),
+            })
+        })
+        .collect();
+    if stream.last_entry().map_or(StreamId::MIN, |(id, _)| *id) != stream.last_id() {
+        log::warn!("AOF rewrite can't preserve the last ID of stream {key:?}");
+    }
+    if stream.is_empty() && stream.groups().is_empty() {
+        log::warn!("AOF rewrite can't preserve empty stream {key:?}");
+    }
+
+    for (group, consumer_group) in stream.groups() {
+        commands.push(Command::XGroup(XGroup::Create {
+            key: key.clone(),
+            group: group.clone(),
+            id: ReadId::After(consumer_group.last_delivered),
+            mkstream: true,
+        }));
+        // Claiming with `FORCE` adds an entry to the pending entries list,
+        // and `TIME` and `RETRYCOUNT` restore its delivery time and count.
+        // Pending entries whose stream entry was deleted can't be claimed.
+        for (id, pending) in &consumer_group.pending {
+            if stream.get(*id).is_none() {
+                continue;
+            }
+            commands.push(Command::XClaim(XClaim {
+                key: key.clone(),
+                group: group.clone(),
+                consumer: pending.consumer.clone(),
+                min_idle: 0,
+                ids: vec![*id],
+                idle: None,
+                time: Some(pending.delivered_at),
+                retry_count: Some(pending.deliveries),
+                force: true,
+                justid: true,
+            }));
+        }
+    }
+    commands
+}
+
+/// Rewrites a successful write command so that replaying it has the same
+/// effect it had when it ran: relative expiration times become absolute, and
+/// stream IDs generated by `XADD` are spelled out.
+pub fn deterministic(command: Command, response: &CommandResponse, now_millis: i64) -> Command {
+    match command {
+        Command::Expire(Expire {
+            key,
+            time: time @ (Expiration::Seconds(_) | Expiration::Milliseconds(_)),
+            existence,
+            comparison,
+        }) => Command::Expire(Expire {
+            key,
+            time: time
+                .to_unix_millis(now_millis)
+                .map_or(time, Expiration::UnixMilliseconds),
+            existence,
+            comparison,
+        }),
+        Command::Restore(restore) if restore.ttl > 0 && !restore.absttl => {
+            Command::Restore(Restore {
+                ttl: now_millis.saturating_add(restore.ttl),
+                absttl: true,
+                ..restore
+            })
+        }
+        Command::XAdd(XAdd {
+            key,
+            id: id @ (NewId::Auto | NewId::AutoSeq(_)),
+            fields,
+        }) => {
+            let generated = match response {
+                CommandResponse::BulkString(Some(generated)) => NewId::parse(generated),
+                _ => None,
+            };
+            Command::XAdd(XAdd {
+                key,
+                id: generated.unwrap_or(id),
+                fields,
+            })
+        }
+        command => command,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("redis-clone-aof-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(DEFAULT_AOF_PATH)
+    }
+
+    fn set(key: &str, value: &str) -> Command {
+        Command::Set(Set {
+            key: RedisString::from(key),
+            value: RedisString::from(value),
+        })
+    }
+
+    #[test]
+    fn append_and_replay() {
+        let path = test_path("replay");
+        let mut config = AofConfig {
+            path: path.clone(),
+            use_rdb_preamble: false,
+            // The file is read while it's being written below.
+            load_truncated: false,
+            ..AofConfig::default()
+        };
+        assert!(!replay(&config, 16, |_| panic!("no AOF yet")).unwrap());
+
+        for fsync in [FsyncPolicy::Always, FsyncPolicy::EverySec, FsyncPolicy::No] {
+            std::fs::remove_file(&path).ok();
+            config.fsync = fsync;
+            let mut aof = Aof::open(&config).unwrap();
+            aof.append(0, &set("a", "1"));
+            aof.append(3, &set("b", "2"));
+            aof.append(3, &set("c", "3"));
+            // Dropping the `Aof` stops the writer thread, but doesn't wait for
+            // it, so wait for the file to be written.
+            drop(aof);
+            let expected = [(0, set("a", "1")), (3, set("b", "2")), (3, set("c", "3"))];
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                let mut replayed = Vec::new();
+                let loaded = replay(&config, 16, |record| match record {
+                    Record::Command(db, command) => replayed.push((db, command)),
+                    Record::Snapshot(_) => panic!("no RDB preamble"),
+                });
+                if loaded.is_ok() && replayed == expected {
+                    break;
+                }
+                assert!(Instant::now() < deadline, "replayed {replayed:?}");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        std::fs::write(
+            &path,
+            Command::Select(Select { index: 20 }).to_resp().to_bytes(),
+        )
+        .unwrap();
+        let err = replay(&config, 16, |_| {}).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "database index 20 is out of range"
+        );
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn load_truncated() {
+        let path = test_path("truncated");
+        let complete = [
+            set("a", "1").to_resp().to_bytes(),
+            set("b", "2").to_resp().to_bytes(),
+        ]
+        .concat();
+        let truncated = set("c", "3").to_resp().to_bytes();
+        let contents = [complete.as_slice(), &truncated[..truncated.len() - 3]].concat();
+        std::fs::write(&path, &contents).unwrap();
+
+        let mut config = AofConfig {
+            path: path.clone(),
+            load_truncated: false,
+            ..AofConfig::default()
+        };
+        let err = replay(&config, 16, |_| {}).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "failed to load AOF {} after 2 commands, at offset {}",
+                path.display(),
+                complete.len()
+            )
+        );
+        assert_eq!(
+            err.downcast_ref::<Truncated>(),
+            Some(&Truncated {
+                offset: complete.len() as u64
+            })
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), contents);
+
+        config.load_truncated = true;
+        let mut replayed = Vec::new();
+        replay(&config, 16, |record| replayed.push(record)).unwrap();
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), complete);
+
+        // Corruption in the middle of the file is never tolerated.
+        let corrupted = [b"*1\r\n$4\r\nPING\r\nnonsense\r\n".as_slice(), &complete].concat();
+        std::fs::write(&path, corrupted).unwrap();
+        assert!(replay(&config, 16, |_| {}).is_err());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn parse_fsync_policy() {
+        assert_eq!(
+            "always".parse::<FsyncPolicy>().unwrap(),
+            FsyncPolicy::Always
+        );
+        assert_eq!(
+            "EVERYSEC".parse::<FsyncPolicy>().unwrap(),
+            FsyncPolicy::EverySec
+        );
+        assert_eq!("no".parse::<FsyncPolicy>().unwrap(), FsyncPolicy::No);
+        assert!("sometimes".parse::<FsyncPolicy>().is_err());
+        for policy in [FsyncPolicy::Always, FsyncPolicy::EverySec, FsyncPolicy::No] {
+            assert_eq!(policy.name().parse::<FsyncPolicy>().unwrap(), policy);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Checks an append-only file for corruption offline, like `redis-check-aof`.
+//! With `--fix`, a truncated command at the end of the file is cut off.
+//!
+//! Usage: `check-aof [--fix] <file>`
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use color_eyre::eyre::{eyre, Result};
+use simple_logger::SimpleLogger;
+
+use redis_clone::aof::{self, AofConfig, Record, Truncated};
+use redis_clone::server::DEFAULT_DATABASES;
+
+fn main() -> Result<ExitCode> {
+    color_eyre::install()?;
+    SimpleLogger::new().init()?;
+
+    let mut fix = false;
+    let mut path = None;
+    for arg in std::env::args_os().skip(1) {
+        match arg.to_str() {
+            Some("--fix") => fix = true,
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(eyre!("usage: check-aof [--fix] <file>")),
+        }
+    }
+    let Some(path) = path else {
+        return Err(eyre!("usage: check-aof [--fix] <file>"));
+    };
+
+    let config = AofConfig {
+        path,
+        load_truncated: fix,
+        ..AofConfig::default()
+    };
+    let len = fs::metadata(&config.path).map(|metadata| metadata.len());
+    let mut keys = 0;
+    let mut commands = 0;
+    let checked = aof::replay(&config, DEFAULT_DATABASES, |record| match record {
+        Record::Snapshot(dbs) => keys = dbs.iter().map(|db| db.entries().len()).sum(),
+        Record::Command(..) => commands += 1,
+    });
+    match checked {
+        Ok(true) => {
+            let fixed_len = fs::metadata(&config.path)?.len();
+            if len.is_ok_and(|len| len != fixed_len) {
+                println!("Successfully truncated AOF to {fixed_len} bytes");
+            }
+            println!("AOF looks OK: {keys} keys in the RDB preamble and {commands} commands");
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(false) => Err(eyre!("{} doesn't exist", config.path.display())),
+        Err(e) if e.downcast_ref::<Truncated>().is_some() => {
+            println!("AOF is truncated: {e:#}");
+            println!("Run with --fix to discard the truncated command");
+            Ok(ExitCode::FAILURE)
+        }
+        Err(e) => {
+            println!("AOF is corrupted: {e:#}");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
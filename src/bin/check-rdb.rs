@@ -0,0 +1,36 @@
+//! Checks a snapshot file for corruption offline, like `redis-check-rdb`.
+//!
+//! Usage: `check-rdb <file>`
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use color_eyre::eyre::{eyre, Result};
+use simple_logger::SimpleLogger;
+
+use redis_clone::server::DEFAULT_DATABASES;
+use redis_clone::snapshot;
+
+fn main() -> Result<ExitCode> {
+    color_eyre::install()?;
+    SimpleLogger::new().init()?;
+
+    let mut args = std::env::args_os().skip(1);
+    let (Some(path), None) = (args.next(), args.next()) else {
+        return Err(eyre!("usage: check-rdb <file>"));
+    };
+    let path = PathBuf::from(path);
+
+    match snapshot::load(&path, DEFAULT_DATABASES) {
+        Ok(Some(dbs)) => {
+            let keys: usize = dbs.iter().map(|db| db.entries().len()).sum();
+            println!("RDB looks OK: {keys} keys");
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(None) => Err(eyre!("{} doesn't exist", path.display())),
+        Err(e) => {
+            println!("RDB is corrupted: {e:#}");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
@@ -1,18 +1,53 @@
+//! Sends a few example commands to a server and logs the responses.
+//!
+//! Usage: `client [-h <host>] [-p <port>] [--tls --cacert <file> [--cert
+//! <file> --key <file>]]`
+
 use std::io::{BufReader, BufWriter, Write};
 use std::net::TcpStream;
+use std::path::PathBuf;
 
 use color_eyre::eyre::{eyre, Context, Result};
 use simple_logger::SimpleLogger;
 
 use redis_clone::command::{Command, CommandResponse, Get, Set};
+use redis_clone::config::DEFAULT_PORT;
 use redis_clone::resp::Message;
 use redis_clone::string::RedisString;
+use redis_clone::tls::{self, ClientTlsConfig, Stream};
+
+const USAGE: &str =
+    "usage: client [-h <host>] [-p <port>] [--tls --cacert <file> [--cert <file> --key <file>]]";
 
 fn main() -> Result<()> {
     color_eyre::install()?;
     SimpleLogger::new().init()?;
 
-    let stream = TcpStream::connect("127.0.0.1:6379")?;
+    let mut host = "127.0.0.1".to_string();
+    let mut port = DEFAULT_PORT;
+    let mut use_tls = false;
+    let mut ca_cert_file = None;
+    let mut tls_config = ClientTlsConfig::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| eyre!(USAGE));
+        match arg.as_str() {
+            "-h" => host = value()?,
+            "-p" => port = value()?.parse().wrap_err("invalid -p")?,
+            "--tls" => use_tls = true,
+            "--cacert" => ca_cert_file = Some(PathBuf::from(value()?)),
+            "--cert" => tls_config.cert_file = Some(PathBuf::from(value()?)),
+            "--key" => tls_config.key_file = Some(PathBuf::from(value()?)),
+            _ => return Err(eyre!(USAGE)),
+        }
+    }
+
+    let stream = if use_tls {
+        tls_config.ca_cert_file = ca_cert_file.ok_or_else(|| eyre!("--tls needs --cacert"))?;
+        tls::connect(&host, port, &tls_config)?
+    } else {
+        Stream::Tcp(TcpStream::connect((host.as_str(), port))?)
+    };
     let mut write_stream = stream.try_clone().expect("failed to clone stream");
     let mut writer = BufWriter::new(&mut write_stream);
     let mut reader = BufReader::new(stream.try_clone().wrap_err("failed to clone stream")?);
@@ -37,9 +72,9 @@ fn main() -> Result<()> {
         let response = Message::parse_resp(&mut reader)
             .wrap_err(eyre!("failed to parse response"))?
             .ok_or(eyre!("response was empty"))?;
-        let response = CommandResponse::parse_resp(response.clone())
+        CommandResponse::parse_resp(response.clone())
             .wrap_err(eyre!("failed to parse {response:?}"))?;
-        log::info!("Response: {response:?}");
+        log::info!("Response:\n{response}");
     }
 
     Ok(())
@@ -0,0 +1,71 @@
+//! Monitors a master and fails over to one of its replicas when it goes down,
+//! like a minimal `redis-sentinel`. Run one monitor per machine, each listing
+//! the others with `--peer`.
+//!
+//! Usage: `sentinel [--port <port>] [--quorum <n>] [--down-after-ms <ms>]
+//! [--failover-timeout-ms <ms>] [--peer <host:port>]... <master host:port>`
+
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use simple_logger::SimpleLogger;
+
+use redis_clone::sentinel::{Addr, Config, Sentinel};
+
+const USAGE: &str = "usage: sentinel [--port <port>] [--quorum <n>] [--down-after-ms <ms>] \
+                     [--failover-timeout-ms <ms>] [--peer <host:port>]... <master host:port>";
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    SimpleLogger::new().init()?;
+
+    let mut port = 26379;
+    let mut master = None;
+    let mut quorum = None;
+    let mut down_after = None;
+    let mut failover_timeout = None;
+    let mut peers = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| eyre!(USAGE));
+        match arg.as_str() {
+            "--port" => port = value()?.parse().wrap_err("invalid --port")?,
+            "--quorum" => quorum = Some(value()?.parse().wrap_err("invalid --quorum")?),
+            "--down-after-ms" => {
+                let millis = value()?.parse().wrap_err("invalid --down-after-ms")?;
+                down_after = Some(Duration::from_millis(millis));
+            }
+            "--failover-timeout-ms" => {
+                let millis = value()?.parse().wrap_err("invalid --failover-timeout-ms")?;
+                failover_timeout = Some(Duration::from_millis(millis));
+            }
+            "--peer" => peers.push(Addr::parse(&value()?)?),
+            _ if master.is_none() && !arg.starts_with("--") => master = Some(Addr::parse(&arg)?),
+            _ => return Err(eyre!(USAGE)),
+        }
+    }
+    let Some(master) = master else {
+        return Err(eyre!(USAGE));
+    };
+
+    let mut config = Config::new(master);
+    // By default a majority of the monitors must agree the master is down.
+    let monitors = peers.len() + 1;
+    config.quorum = quorum.unwrap_or(monitors / 2 + 1);
+    config.down_after = down_after.unwrap_or(config.down_after);
+    config.failover_timeout = failover_timeout.unwrap_or(config.failover_timeout);
+    config.peers = peers;
+
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .wrap_err_with(|| format!("failed to listen on port {port}"))?;
+    let sentinel = Arc::new(Sentinel::new(config));
+    log::info!(
+        "monitoring {} as {} on port {port}",
+        sentinel.master(),
+        sentinel.id()
+    );
+    sentinel.run(listener);
+    Ok(())
+}
@@ -1,14 +1,105 @@
-use color_eyre::eyre::Result;
+//! Runs the server, like `redis-server`.
+//!
+//! Usage: `server [--config <redis.conf>] [--port <port>] [--bind <addr>...]
+//! [--dir <dir>] [--requirepass <password>] [--daemonize yes|no]
+//! [--<directive> <value>...]...`
+//!
+//! Any config file directive can be given as a flag, which overrides the
+//! config file.
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
 use simple_logger::SimpleLogger;
 
+use redis_clone::config::ServerConfig;
 use redis_clone::server::Server;
 
+const USAGE: &str = "usage: server [--config <redis.conf>] [--port <port>] [--bind <addr>...] \
+                     [--dir <dir>] [--requirepass <password>] [--daemonize yes|no] \
+                     [--<directive> <value>...]...";
+
+/// The parsed command line.
+#[derive(Debug, Default)]
+struct Args {
+    config_file: Option<PathBuf>,
+
+    /// Directives that override the config file, in the order they're given.
+    overrides: Vec<(String, Vec<String>)>,
+}
+
+impl Args {
+    /// Parses `--name value...` flags. Every argument up to the next flag is
+    /// a value, like `--bind 127.0.0.1 ::1`.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--").filter(|name| !name.is_empty()) else {
+                return Err(eyre!("unexpected argument '{arg}'\n{USAGE}"));
+            };
+            let mut values = Vec::new();
+            while let Some(value) = args.next_if(|arg| !arg.starts_with("--")) {
+                values.push(value);
+            }
+            match (name, values.as_slice()) {
+                ("config", [path]) => parsed.config_file = Some(PathBuf::from(path)),
+                (_, []) | ("config", _) => {
+                    return Err(eyre!("wrong number of values for --{name}\n{USAGE}"));
+                }
+                _ => parsed.overrides.push((name.to_string(), values)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
 fn main() -> Result<()> {
     color_eyre::install()?;
     SimpleLogger::new().init()?;
 
-    let mut server = Server::new();
-    server.start("127.0.0.1:6379")?;
+    if std::env::args().skip(1).any(|arg| arg == "--help") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let args = Args::parse(std::env::args().skip(1))?;
+
+    let mut config = match args.config_file {
+        // The path is made absolute so `CONFIG REWRITE` still finds the file
+        // after the server changes to `dir`.
+        Some(path) => ServerConfig::load(std::path::absolute(path)?)?,
+        None => ServerConfig::default(),
+    };
+    for (name, values) in &args.overrides {
+        config
+            .set_directive(name, values)
+            .map_err(|reason| eyre!("invalid --{name}: {reason}"))?;
+    }
+
+    if config.daemonize {
+        return daemonize();
+    }
+    std::env::set_current_dir(&config.dir)
+        .wrap_err_with(|| format!("failed to change to directory {}", config.dir.display()))?;
+
+    Server::with_config(config).start_bound()?;
+
+    Ok(())
+}
 
+/// Starts the server again in the background with the same arguments, and
+/// exits. There's no `fork` in the standard library, so unlike Redis the
+/// background server is a new process rather than a copy of this one.
+fn daemonize() -> Result<()> {
+    let child = Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .args(["--daemonize", "no"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .wrap_err("failed to start the server in the background")?;
+    println!("server running in the background with pid {}", child.id());
     Ok(())
 }
@@ -0,0 +1,151 @@
+//! Bit operations on strings, which commands like `SETBIT` and `BITCOUNT` treat
+//! as bitmaps. See <https://redis.io/docs/data-types/bitmaps/>.
+//!
+//! Bit 0 is the most significant bit of the first byte.
+
+use std::ops::Range;
+
+/// The largest offset `SETBIT` and `GETBIT` accept, which limits bitmaps to
+/// 512 MB like Redis' default `proto-max-bulk-len`.
+pub const MAX_OFFSET: u64 = (512 << 20) * 8 - 1;
+
+pub fn get_bit(bytes: &[u8], offset: usize) -> bool {
+    bytes
+        .get(offset / 8)
+        .is_some_and(|byte| byte & mask(offset) != 0)
+}
+
+/// Sets or clears a bit, padding `bytes` with zeros if it's too short. Returns
+/// the bit's previous value.
+pub fn set_bit(bytes: &mut Vec<u8>, offset: usize, value: bool) -> bool {
+    let index = offset / 8;
+    if index >= bytes.len() {
+        bytes.resize(index + 1, 0);
+    }
+    let previous = bytes[index] & mask(offset) != 0;
+    if value {
+        bytes[index] |= mask(offset);
+    } else {
+        bytes[index] &= !mask(offset);
+    }
+    previous
+}
+
+/// Counts the set bits in a range of bit offsets, which must be within
+/// `bytes`.
+pub fn count_ones(bytes: &[u8], bits: Range<usize>) -> usize {
+    if bits.is_empty() {
+        return 0;
+    }
+    let first = bits.start / 8;
+    let last = (bits.end - 1) / 8;
+    let head_mask = 0xff >> (bits.start % 8);
+    let tail_mask = 0xff << (7 - (bits.end - 1) % 8);
+    if first == last {
+        return (bytes[first] & head_mask & tail_mask).count_ones() as usize;
+    }
+    (bytes[first] & head_mask).count_ones() as usize
+        + popcount(&bytes[first + 1..last])
+        + (bytes[last] & tail_mask).count_ones() as usize
+}
+
+/// Finds the first bit set to `bit` in a range of bit offsets, which must be
+/// within `bytes`.
+pub fn position(bytes: &[u8], bit: bool, bits: Range<usize>) -> Option<usize> {
+    let skip = if bit { 0x00 } else { 0xff };
+    let mut offset = bits.start;
+    while offset < bits.end {
+        // Skip whole bytes that can't contain the bit.
+        if offset.is_multiple_of(8) && offset + 8 <= bits.end && bytes[offset / 8] == skip {
+            offset += 8;
+            continue;
+        }
+        if get_bit(bytes, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+    None
+}
+
+/// Converts an inclusive range of possibly negative indexes into a range over
+/// `len` bytes or bits, like `BITCOUNT` and `BITPOS` do.
+///
+/// Unlike `LRANGE`, an end before the start of the string is treated as the
+/// first index.
+pub fn clamp_range(len: usize, start: i64, end: i64) -> Range<usize> {
+    let len = i64::try_from(len).expect("length fits in i64");
+    let resolve = |i: i64| if i < 0 { (i + len).max(0) } else { i };
+    let start = resolve(start);
+    let end = resolve(end).min(len - 1);
+    if start > end {
+        return 0..0;
+    }
+    let to_usize = |i: i64| usize::try_from(i).expect("index was clamped to the string");
+    to_usize(start)..to_usize(end) + 1
+}
+
+const fn mask(offset: usize) -> u8 {
+    0x80 >> (offset % 8)
+}
+
+/// Counts set bits a word at a time.
+fn popcount(bytes: &[u8]) -> usize {
+    let words = bytes.chunks_exact(8);
+    let rest: usize = words
+        .remainder()
+        .iter()
+        .map(|byte| byte.count_ones() as usize)
+        .sum();
+    let words: usize = words
+        .map(|word| {
+            let word = u64::from_ne_bytes(word.try_into().expect("chunks are 8 bytes"));
+            word.count_ones() as usize
+        })
+        .sum();
+    words + rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_and_set_bits() {
+        let mut bytes = Vec::new();
+        assert!(!set_bit(&mut bytes, 7, true));
+        assert_eq!(bytes, [0x01]);
+        assert!(!set_bit(&mut bytes, 17, true));
+        assert_eq!(bytes, [0x01, 0x00, 0x40]);
+        assert!(set_bit(&mut bytes, 7, false));
+        assert_eq!(bytes, [0x00, 0x00, 0x40]);
+        assert!(get_bit(&bytes, 17));
+        assert!(!get_bit(&bytes, 16));
+        assert!(!get_bit(&bytes, 1000));
+    }
+
+    #[test]
+    fn count_and_find_bits() {
+        let bytes = b"foobar foobar";
+        assert_eq!(count_ones(bytes, 0..bytes.len() * 8), 53);
+        assert_eq!(count_ones(bytes, 8..16), 6);
+        assert_eq!(count_ones(bytes, 5..31), 17);
+        assert_eq!(count_ones(bytes, 3..3), 0);
+
+        let bytes = [0x00, 0xff, 0xf0];
+        assert_eq!(position(&bytes, true, 0..24), Some(8));
+        assert_eq!(position(&bytes, false, 8..24), Some(20));
+        assert_eq!(position(&bytes, false, 8..20), None);
+        assert_eq!(position(&bytes, true, 0..8), None);
+    }
+
+    #[test]
+    fn clamp_ranges() {
+        assert_eq!(clamp_range(6, 0, -1), 0..6);
+        assert_eq!(clamp_range(6, -2, 100), 4..6);
+        assert_eq!(clamp_range(6, -100, -100), 0..1);
+        assert_eq!(clamp_range(6, 3, 1), 0..0);
+        assert_eq!(clamp_range(6, 6, 10), 0..0);
+        assert_eq!(clamp_range(0, 0, -1), 0..0);
+    }
+}
@@ -0,0 +1,105 @@
+//! Bookkeeping for clients parked by blocking commands like `BLPOP`, which wait
+//! until one of their keys is ready or they time out, and by `WAIT`, which
+//! waits for replicas to acknowledge writes.
+
+use std::time::Instant;
+
+use crate::command::{Command, Wait};
+use crate::string::RedisString;
+
+/// A client waiting for one of `keys` to be ready.
+#[derive(Debug)]
+pub struct BlockedClient {
+    /// The ID of the client's thread, used to route its response.
+    pub client: usize,
+
+    /// The database the client had selected when it blocked.
+    pub db: usize,
+    pub keys: Vec<RedisString>,
+
+    /// The command that blocked. It is processed again once a key is ready.
+    pub command: Command,
+
+    /// When the client times out, or `None` to wait forever.
+    pub deadline: Option<Instant>,
+
+    /// For `WAIT`, the replication offset replicas must acknowledge.
+    pub replication_offset: Option<u64>,
+}
+
+/// All blocked clients, in the order they blocked so that clients waiting on
+/// the same key are served first-in, first-out.
+#[derive(Debug, Default)]
+pub struct BlockedClients {
+    clients: Vec<BlockedClient>,
+}
+
+impl BlockedClients {
+    pub fn block(&mut self, client: BlockedClient) {
+        self.clients.push(client);
+    }
+
+    pub fn is_blocked_on(&self, db: usize, key: &RedisString) -> bool {
+        self.clients
+            .iter()
+            .any(|client| client.db == db && client.keys.contains(key))
+    }
+
+    /// The clients blocked on `key`, in the order they blocked.
+    pub fn blocked_on(&self, db: usize, key: &RedisString) -> Vec<usize> {
+        self.clients
+            .iter()
+            .filter(|client| client.db == db && client.keys.contains(key))
+            .map(|client| client.client)
+            .collect()
+    }
+
+    /// The command a client is blocked on.
+    pub fn command(&self, client: usize) -> Option<&Command> {
+        self.clients
+            .iter()
+            .find(|c| c.client == client)
+            .map(|c| &c.command)
+    }
+
+    pub fn unblock(&mut self, client: usize) -> Option<BlockedClient> {
+        let index = self.clients.iter().position(|c| c.client == client)?;
+        Some(self.clients.remove(index))
+    }
+
+    /// The earliest deadline of any blocked client.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.clients.iter().filter_map(|c| c.deadline).min()
+    }
+
+    /// Unblocks and returns the clients blocked on `WAIT` whose offset enough
+    /// replicas have acknowledged, given the number of replicas that have
+    /// acknowledged an offset.
+    pub fn take_acknowledged(&mut self, num_acked: impl Fn(u64) -> usize) -> Vec<BlockedClient> {
+        let (acknowledged, waiting) =
+            std::mem::take(&mut self.clients)
+                .into_iter()
+                .partition(|c| match (&c.command, c.replication_offset) {
+                    (Command::Wait(Wait { num_replicas, .. }), Some(offset)) => {
+                        num_acked(offset) >= *num_replicas
+                    }
+                    _ => false,
+                });
+        self.clients = waiting;
+        acknowledged
+    }
+
+    /// Unblocks and returns the clients whose deadline has passed.
+    pub fn take_timed_out(&mut self, now: Instant) -> Vec<BlockedClient> {
+        let (timed_out, waiting) = std::mem::take(&mut self.clients)
+            .into_iter()
+            .partition(|c| c.deadline.is_some_and(|deadline| deadline <= now));
+        self.clients = waiting;
+        timed_out
+    }
+
+    /// Unblocks and returns every client, like when the server shuts down.
+    pub fn take_all(&mut self) -> Vec<BlockedClient> {
+        std::mem::take(&mut self.clients)
+    }
+}
@@ -0,0 +1,468 @@
+//! Cluster mode, where the keyspace is split into hash slots spread across
+//! nodes.
+//!
+//! A node only serves keys in the 16384 slots it owns, and redirects clients
+//! to the owner of any other slot. See
+//! <https://redis.io/docs/reference/cluster-spec/>.
+//!
+//! There's no cluster bus yet: every node is told about the others and who
+//! owns each slot, either up front with `ClusterState::add_node` and
+//! `ClusterState::assign_slots`, or with `CLUSTER ADDSLOTS` and `CLUSTER
+//! SETSLOT`.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use crate::command::{ErrorCode, ErrorReply, SlotState};
+use crate::crc16::crc16;
+
+pub const NUM_SLOTS: u16 = 16384;
+
+/// The hash slot a key belongs to.
+///
+/// If the key has a non-empty hash tag, which is the part between the first
+/// `{` and the next `}`, only the tag is hashed, so keys like `{user:1}:name`
+/// and `{user:1}:email` share a slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    crc16(hash_tag(key).unwrap_or(key)) % NUM_SLOTS
+}
+
+fn hash_tag(key: &[u8]) -> Option<&[u8]> {
+    let start = key.iter().position(|&b| b == b'{')? + 1;
+    let len = key[start..].iter().position(|&b| b == b'}')?;
+    (len > 0).then(|| &key[start..start + len])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    /// The 40 character name other nodes know this one by.
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Node {
+    /// The address clients are redirected to, like `127.0.0.1:7000`.
+    fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// What a client has told this node about the command being routed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Routing<'a> {
+    /// The client sent `ASKING` after an `-ASK` redirect, so the command may
+    /// use a slot this node is importing.
+    pub asking: bool,
+
+    /// The address of the master this node replicates, if the command only
+    /// reads and the client sent `READONLY`. Such commands are served here
+    /// for the slots the master owns.
+    pub replica_of: Option<(&'a str, u16)>,
+}
+
+/// What this node knows about the cluster: the nodes in it, which node owns
+/// each slot, and which slots are moving between nodes.
+#[derive(Debug, Clone)]
+pub struct ClusterState {
+    /// Every known node. This node is always the first.
+    nodes: Vec<Node>,
+
+    /// The index in `nodes` of each slot's owner.
+    slots: Box<[Option<usize>]>,
+
+    /// Slots this node owns that are moving to another node, and where to.
+    migrating: HashMap<u16, usize>,
+
+    /// Slots another node owns that are moving to this one, and where from.
+    importing: HashMap<u16, usize>,
+}
+
+impl ClusterState {
+    /// Creates the state for a node named `id` that clients reach at
+    /// `host:port` and that owns no slots yet.
+    pub fn new(id: impl Into<String>, host: impl Into<String>, port: u16) -> Self {
+        Self {
+            nodes: vec![Node {
+                id: id.into(),
+                host: host.into(),
+                port,
+            }],
+            slots: vec![None; usize::from(NUM_SLOTS)].into_boxed_slice(),
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        }
+    }
+
+    pub fn myself(&self) -> &Node {
+        &self.nodes[0]
+    }
+
+    /// Every known node, starting with this one.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The runs of consecutive slots owned by the same node, in slot order.
+    pub fn slot_ranges(&self) -> Vec<(RangeInclusive<u16>, &Node)> {
+        let mut ranges: Vec<(RangeInclusive<u16>, usize)> = Vec::new();
+        for (slot, owner) in (0..NUM_SLOTS).zip(self.slots.iter()) {
+            let Some(owner) = *owner else {
+                continue;
+            };
+            match ranges.last_mut() {
+                Some((range, node)) if *node == owner && *range.end() + 1 == slot => {
+                    *range = *range.start()..=slot;
+                }
+                _ => ranges.push((slot..=slot, owner)),
+            }
+        }
+        ranges
+            .into_iter()
+            .map(|(range, node)| (range, &self.nodes[node]))
+            .collect()
+    }
+
+    /// The slot ranges owned by `node`.
+    pub fn node_slot_ranges(&self, node: &Node) -> Vec<RangeInclusive<u16>> {
+        self.slot_ranges()
+            .into_iter()
+            .filter(|(_, owner)| owner.id == node.id)
+            .map(|(range, _)| range)
+            .collect()
+    }
+
+    /// The reply to `CLUSTER INFO`.
+    pub fn info(&self) -> String {
+        let assigned = self.slots.iter().filter(|owner| owner.is_some()).count();
+        let state = if assigned == usize::from(NUM_SLOTS) {
+            "ok"
+        } else {
+            "fail"
+        };
+        let size = (0..self.nodes.len())
+            .filter(|node| self.slots.contains(&Some(*node)))
+            .count();
+        let lines = [
+            format!("cluster_state:{state}"),
+            format!("cluster_slots_assigned:{assigned}"),
+            format!("cluster_slots_ok:{assigned}"),
+            "cluster_slots_pfail:0".to_string(),
+            "cluster_slots_fail:0".to_string(),
+            format!("cluster_known_nodes:{}", self.nodes.len()),
+            format!("cluster_size:{size}"),
+            "cluster_current_epoch:0".to_string(),
+            "cluster_my_epoch:0".to_string(),
+        ];
+        lines.join("\r\n") + "\r\n"
+    }
+
+    /// The reply to `CLUSTER NODES`: a line per node, in the format of Redis'
+    /// `nodes.conf`.
+    pub fn describe_nodes(&self) -> String {
+        let mut description = String::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let flags = if index == 0 {
+                "myself,master"
+            } else {
+                "master"
+            };
+            // There's no cluster bus, so the bus port is always 0.
+            let _ = write!(
+                description,
+                "{} {}:{}@0 {flags} - 0 0 0 connected",
+                node.id, node.host, node.port
+            );
+            for range in self.node_slot_ranges(node) {
+                if range.start() == range.end() {
+                    let _ = write!(description, " {}", range.start());
+                } else {
+                    let _ = write!(description, " {}-{}", range.start(), range.end());
+                }
+            }
+            if index == 0 {
+                let mut moving: Vec<_> = self
+                    .migrating
+                    .iter()
+                    .map(|(slot, target)| (slot, "->-", target))
+                    .chain(
+                        self.importing
+                            .iter()
+                            .map(|(slot, source)| (slot, "-<-", source)),
+                    )
+                    .collect();
+                moving.sort_unstable();
+                for (slot, arrow, node) in moving {
+                    let _ = write!(description, " [{slot}{arrow}{}]", self.nodes[*node].id);
+                }
+            }
+            description.push('\n');
+        }
+        description
+    }
+
+    /// Adds another node, or updates its address if it's already known.
+    pub fn add_node(&mut self, id: impl Into<String>, host: impl Into<String>, port: u16) {
+        let node = Node {
+            id: id.into(),
+            host: host.into(),
+            port,
+        };
+        match self.node_index(&node.id) {
+            Some(index) => self.nodes[index] = node,
+            None => self.nodes.push(node),
+        }
+    }
+
+    /// Gives the slots to the node named `id`.
+    pub fn assign_slots(
+        &mut self,
+        slots: impl IntoIterator<Item = u16>,
+        id: &str,
+    ) -> Result<(), ErrorReply> {
+        let node = self.known_node(id)?;
+        for slot in slots {
+            *self.slot_mut(slot)? = Some(node);
+        }
+        Ok(())
+    }
+
+    /// `CLUSTER ADDSLOTS`: takes ownership of slots nobody owns yet.
+    pub fn add_slots(&mut self, slots: &[u16]) -> Result<(), ErrorReply> {
+        for &slot in slots {
+            if self.slot_mut(slot)?.is_some() {
+                return Err(ErrorReply::err(format!("Slot {slot} is already busy")));
+            }
+        }
+        self.assign_slots(slots.iter().copied(), &self.myself().id.clone())
+    }
+
+    /// `CLUSTER SETSLOT`: starts or finishes moving a slot between nodes.
+    pub fn set_slot(&mut self, slot: u16, state: &SlotState) -> Result<(), ErrorReply> {
+        let owner = *self.slot_mut(slot)?;
+        match state {
+            SlotState::Migrating(id) => {
+                if owner != Some(0) {
+                    return Err(ErrorReply::err(format!(
+                        "I'm not the owner of hash slot {slot}"
+                    )));
+                }
+                let node = self.known_node(id)?;
+                self.migrating.insert(slot, node);
+            }
+            SlotState::Importing(id) => {
+                if owner == Some(0) {
+                    return Err(ErrorReply::err(format!(
+                        "I'm already the owner of hash slot {slot}"
+                    )));
+                }
+                let node = self.known_node(id)?;
+                self.importing.insert(slot, node);
+            }
+            SlotState::Node(id) => {
+                let node = self.known_node(id)?;
+                *self.slot_mut(slot)? = Some(node);
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+            SlotState::Stable => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+        }
+        Ok(())
+    }
+
+    /// Where a command for keys in `slot` should go, if not to this node.
+    /// `keys_present` says whether this node has all of the command's keys,
+    /// which matters while the slot migrates away: keys that were already
+    /// moved are served by the target instead.
+    pub fn redirect(
+        &self,
+        slot: u16,
+        routing: Routing<'_>,
+        keys_present: impl FnOnce() -> bool,
+    ) -> Option<ErrorReply> {
+        if routing.asking && self.importing.contains_key(&slot) {
+            return None;
+        }
+        match self.slots[usize::from(slot)] {
+            None => Some(ErrorReply::new(
+                ErrorCode::ClusterDown,
+                "Hash slot not served",
+            )),
+            Some(0) => {
+                let target = self.migrating.get(&slot).filter(|_| !keys_present())?;
+                Some(ErrorReply::new(
+                    ErrorCode::Ask,
+                    format!("{slot} {}", self.nodes[*target].addr()),
+                ))
+            }
+            Some(owner) => {
+                let node = &self.nodes[owner];
+                if routing
+                    .replica_of
+                    .is_some_and(|(host, port)| node.host == host && node.port == port)
+                {
+                    return None;
+                }
+                Some(ErrorReply::new(
+                    ErrorCode::Moved,
+                    format!("{slot} {}", node.addr()),
+                ))
+            }
+        }
+    }
+
+    fn node_index(&self, id: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.id == id)
+    }
+
+    fn known_node(&self, id: &str) -> Result<usize, ErrorReply> {
+        self.node_index(id)
+            .ok_or_else(|| ErrorReply::err(format!("I don't know about node {id}")))
+    }
+
+    fn slot_mut(&mut self, slot: u16) -> Result<&mut Option<usize>, ErrorReply> {
+        self.slots
+            .get_mut(usize::from(slot))
+            .ok_or_else(|| ErrorReply::err("Invalid or out of range slot"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_slots() {
+        // From the cluster spec and `CLUSTER KEYSLOT` in Redis.
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_eq!(key_slot(b"{user1000}.followers"), key_slot(b"user1000"));
+        // Only the first tag counts, and empty or unclosed tags don't.
+        assert_eq!(key_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % NUM_SLOTS);
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+        assert_eq!(key_slot(b"foo{bar}{zap}"), key_slot(b"bar"));
+        assert_eq!(key_slot(b"foo{bar"), crc16(b"foo{bar") % NUM_SLOTS);
+    }
+
+    fn cluster() -> ClusterState {
+        let mut cluster = ClusterState::new("a".repeat(40), "127.0.0.1", 7000);
+        cluster.add_node("b".repeat(40), "127.0.0.1", 7001);
+        cluster.add_slots(&[0, 1]).unwrap();
+        cluster.assign_slots(2..=3, &"b".repeat(40)).unwrap();
+        cluster
+    }
+
+    #[test]
+    fn redirects_to_slot_owner() {
+        let cluster = cluster();
+        assert_eq!(cluster.redirect(0, Routing::default(), || false), None);
+        assert_eq!(
+            cluster.redirect(2, Routing::default(), || true),
+            Some(ErrorReply::new(ErrorCode::Moved, "2 127.0.0.1:7001"))
+        );
+        assert_eq!(
+            cluster
+                .redirect(4, Routing::default(), || true)
+                .map(|e| e.code),
+            Some(ErrorCode::ClusterDown)
+        );
+    }
+
+    #[test]
+    fn describe_slots() {
+        let mut cluster = cluster();
+        cluster.assign_slots([5], &"b".repeat(40)).unwrap();
+        cluster
+            .set_slot(1, &SlotState::Migrating("b".repeat(40)))
+            .unwrap();
+        let ranges: Vec<_> = cluster
+            .slot_ranges()
+            .into_iter()
+            .map(|(range, node)| (range, node.port))
+            .collect();
+        assert_eq!(ranges, [(0..=1, 7000), (2..=3, 7001), (5..=5, 7001)]);
+
+        assert_eq!(
+            cluster.describe_nodes(),
+            format!(
+                "{a} 127.0.0.1:7000@0 myself,master - 0 0 0 connected 0-1 [1->-{b}]\n\
+                 {b} 127.0.0.1:7001@0 master - 0 0 0 connected 2-3 5\n",
+                a = "a".repeat(40),
+                b = "b".repeat(40),
+            )
+        );
+        assert!(cluster
+            .info()
+            .starts_with("cluster_state:fail\r\ncluster_slots_assigned:5\r\n"));
+        assert!(cluster.info().contains("cluster_size:2\r\n"));
+    }
+
+    #[test]
+    fn migrating_slots_ask_for_missing_keys() {
+        let mut cluster = cluster();
+        let b = "b".repeat(40);
+        assert!(cluster
+            .set_slot(2, &SlotState::Migrating(b.clone()))
+            .is_err());
+        assert!(cluster
+            .set_slot(1, &SlotState::Migrating("c".repeat(40)))
+            .is_err());
+        cluster
+            .set_slot(1, &SlotState::Migrating(b.clone()))
+            .unwrap();
+
+        assert_eq!(cluster.redirect(1, Routing::default(), || true), None);
+        assert_eq!(
+            cluster.redirect(1, Routing::default(), || false),
+            Some(ErrorReply::new(ErrorCode::Ask, "1 127.0.0.1:7001"))
+        );
+
+        // Once the slot has moved, its keys are served by the new owner.
+        cluster.set_slot(1, &SlotState::Node(b)).unwrap();
+        assert_eq!(
+            cluster.redirect(1, Routing::default(), || true),
+            Some(ErrorReply::new(ErrorCode::Moved, "1 127.0.0.1:7001"))
+        );
+        assert!(cluster.add_slots(&[1]).is_err());
+        assert!(cluster.add_slots(&[NUM_SLOTS]).is_err());
+    }
+
+    #[test]
+    fn asking_and_replica_reads() {
+        let mut cluster = cluster();
+        let asking = Routing {
+            asking: true,
+            ..Routing::default()
+        };
+        assert_eq!(
+            cluster.redirect(2, asking, || false).map(|e| e.code),
+            Some(ErrorCode::Moved)
+        );
+        cluster
+            .set_slot(2, &SlotState::Importing("b".repeat(40)))
+            .unwrap();
+        assert_eq!(cluster.redirect(2, asking, || false), None);
+        assert_eq!(
+            cluster
+                .redirect(2, Routing::default(), || false)
+                .map(|e| e.code),
+            Some(ErrorCode::Moved)
+        );
+
+        let replica_read = |port| Routing {
+            replica_of: Some(("127.0.0.1", port)),
+            ..Routing::default()
+        };
+        assert_eq!(cluster.redirect(3, replica_read(7001), || false), None);
+        assert_eq!(
+            cluster
+                .redirect(3, replica_read(7002), || false)
+                .map(|e| e.code),
+            Some(ErrorCode::Moved)
+        );
+    }
+}
@@ -1,10 +1,18 @@
 //! Implements Redis commands. See <https://redis.io/commands/>
 
+use std::fmt;
+use std::time::Duration;
+
 use crate::resp::Message;
 
-use color_eyre::eyre::{eyre, Result, WrapErr};
+use color_eyre::eyre::{eyre, Report, Result, WrapErr};
 
+use crate::bitmap;
+use crate::geo::{Coordinates, DistanceUnit, Shape};
+use crate::stream::{Fields, GroupReadId, NewId, RangeBound, ReadId, StreamId, Trim, TrimStrategy};
 use crate::string::RedisString;
+use crate::tracking::TrackingMode;
+use crate::zset::{LexBound, LexRange, Score, ScoreBound, ScoreRange};
 
 /// A `Command` is a well-formed Redis command.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -12,6 +20,123 @@ pub enum Command {
     Ping,
     Get(Get),
     Set(Set),
+    Expire(Expire),
+    Ttl(Ttl),
+    ExpireTime(ExpireTime),
+    Persist(Persist),
+    Scan(Scan),
+    FlushDb(Flush),
+    FlushAll(Flush),
+    Save,
+    BgSave,
+    LastSave,
+    BgRewriteAof,
+    ReplConf(ReplConf),
+    PSync(PSync),
+    ReplicaOf(ReplicaOf),
+    Wait(Wait),
+    Info(Info),
+    Command(CommandQuery),
+    Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    PSubscribe(PSubscribe),
+    PUnsubscribe(PUnsubscribe),
+    Publish(Publish),
+    Script(Script),
+    Eval(Eval),
+    EvalSha(EvalSha),
+    FCall(FCall),
+    Cluster(Cluster),
+    Asking,
+    ReadOnly,
+    ReadWrite,
+    Memory(Memory),
+    Latency(Latency),
+    Debug(DebugCommand),
+    Config(Config),
+    Acl(Acl),
+    Auth(Auth),
+    Hello(Hello),
+    Del(Del),
+    Unlink(Unlink),
+    Touch(Touch),
+    Dump(Dump),
+    Restore(Restore),
+    Object(Object),
+    Select(Select),
+    Client(Client),
+    Move(Move),
+    Sort(Sort),
+    SortRo(Sort),
+    Push(Push),
+    Pop(Pop),
+    LLen(LLen),
+    LMPop(LMPop),
+    BPop(BPop),
+    LMove(LMove),
+    BLMove(BLMove),
+    BLMPop(BLMPop),
+    HSet(HSet),
+    HGet(HGet),
+    HDel(HDel),
+    HGetAll(HGetAll),
+    HMGet(HMGet),
+    HKeys(HKeys),
+    HVals(HVals),
+    HLen(HLen),
+    HExists(HExists),
+    HSetNx(HSetNx),
+    HStrLen(HStrLen),
+    HScan(HScan),
+    SAdd(SAdd),
+    SRem(SRem),
+    SMembers(SMembers),
+    SIsMember(SIsMember),
+    SMIsMember(SMIsMember),
+    SCard(SCard),
+    SetOp(SetOp),
+    SInterCard(SInterCard),
+    SScan(SScan),
+    ZAdd(ZAdd),
+    ZScore(ZScore),
+    ZRem(ZRem),
+    ZCard(ZCard),
+    ZRange(ZRange),
+    ZRank(ZRank),
+    ZIncrBy(ZIncrBy),
+    ZCount(ZCount),
+    ZSetOp(ZSetOp),
+    ZRandMember(ZRandMember),
+    ZMScore(ZMScore),
+    ZScan(ZScan),
+    SetBit(SetBit),
+    GetBit(GetBit),
+    BitCount(BitCount),
+    BitPos(BitPos),
+    XAdd(XAdd),
+    XLen(XLen),
+    XRange(XRange),
+    XRead(XRead),
+    XGroup(XGroup),
+    XReadGroup(XReadGroup),
+    XAck(XAck),
+    XPending(XPending),
+    XClaim(XClaim),
+    XAutoClaim(XAutoClaim),
+    XTrim(XTrim),
+    XDel(XDel),
+    XInfo(XInfo),
+    GeoAdd(GeoAdd),
+    GeoSearch(GeoSearch),
+    GeoDist(GeoDist),
+    GeoPos(GeoPos),
+    GeoHash(GeoHash),
+    LRange(LRange),
+    LIndex(LIndex),
+    LSet(LSet),
+    LInsert(LInsert),
+    LRem(LRem),
+    LTrim(LRange),
 
     /// `RawCommand` is a command that is not supported by this library.
     RawCommand(Vec<Message>),
@@ -28,148 +153,7746 @@ pub struct Set {
     pub value: RedisString,
 }
 
-impl Command {
-    pub fn to_resp(&self) -> Message {
-        let args = match self {
-            Self::Ping => vec![Message::bulk_string("PING")],
-            Self::Get(get) => vec![
-                Message::bulk_string("GET"),
-                Message::BulkString(Some(get.key.clone())),
-            ],
-            Self::Set(set) => vec![
-                Message::bulk_string("SET"),
-                Message::BulkString(Some(set.key.clone())),
-                Message::BulkString(Some(set.value.clone())),
-            ],
-            Self::RawCommand(args) => args.clone(),
-        };
-        Message::Array(args)
-    }
+/// `EXPIRE`, `PEXPIRE`, `EXPIREAT` and `PEXPIREAT` all share this struct. The
+/// command name is determined by the `Expiration` variant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expire {
+    pub key: RedisString,
+    pub time: Expiration,
+    pub existence: Option<Existence>,
+    pub comparison: Option<Comparison>,
+}
 
-    pub fn parse_resp(resp: &Message) -> Result<Self> {
-        let Message::Array(elems) = resp else { return Err(eyre!("commands must be an array")) };
+/// An expiration time, either relative to now or as an absolute Unix
+/// timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiration {
+    Seconds(i64),
+    Milliseconds(i64),
+    UnixSeconds(i64),
+    UnixMilliseconds(i64),
+}
 
-        let Some((cmd_message, args)) = elems.split_first() else { return Err(eyre!("commands must have at least one element")) };
+impl Expiration {
+    /// Converts the expiration time to an absolute Unix timestamp in
+    /// milliseconds. Returns `None` on overflow.
+    pub fn to_unix_millis(self, now_millis: i64) -> Option<i64> {
+        match self {
+            Self::Seconds(s) => s.checked_mul(1000)?.checked_add(now_millis),
+            Self::Milliseconds(ms) => ms.checked_add(now_millis),
+            Self::UnixSeconds(s) => s.checked_mul(1000),
+            Self::UnixMilliseconds(ms) => Some(ms),
+        }
+    }
+}
 
-        let cmd_str: String = match cmd_message {
-            Message::SimpleString(cmd_str) => cmd_str.clone(),
-            Message::BulkString(Some(cmd_str)) => {
-                String::try_from(cmd_str.clone()).wrap_err("command name must be valid UTF-8")?
-            }
-            _ => return Err(eyre!("command name must be bulk or simple string")),
-        };
+/// The `NX` and `XX` flags: only act if the target does not (`NX`) or does
+/// (`XX`) already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Existence {
+    Nx,
+    Xx,
+}
 
-        match cmd_str.to_uppercase().as_str() {
-            "PING" => expect_no_args(Self::Ping, "PING", args),
-            "GET" => match args {
-                [Message::BulkString(Some(key))] => Ok(Self::Get(Get { key: key.clone() })),
-                _ => Err(eyre!("GET must have a single key argument")),
-            },
-            "SET" => match args {
-                [Message::BulkString(Some(key)), Message::BulkString(Some(value))] => {
-                    Ok(Self::Set(Set {
-                        key: key.clone(),
-                        value: value.clone(),
-                    }))
-                }
-                _ => Err(eyre!("SET must have a key and value argument")),
-            },
-            _ => Err(eyre!("unknown command: {cmd_str}")),
+impl Existence {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Nx => "NX",
+            Self::Xx => "XX",
         }
     }
 }
 
-/// Helper function to ensure that a command has no arguments.
-fn expect_no_args(cmd: Command, cmd_str: &str, args: &[Message]) -> Result<Command> {
-    if !args.is_empty() {
-        return Err(eyre!("{cmd_str} takes no arguments"));
+/// The `GT` and `LT` flags: only update if the new value is greater than
+/// (`GT`) or less than (`LT`) the current one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Gt,
+    Lt,
+}
+
+impl Comparison {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Gt => "GT",
+            Self::Lt => "LT",
+        }
     }
-    Ok(cmd)
 }
 
-/// A `CommandResponse` is a valid response to a command from Redis.
-#[derive(Debug, PartialEq, Eq)]
-pub enum CommandResponse {
-    Pong,
-    Ok,
-    Error(String),
-    BulkString(Option<RedisString>),
+/// `TTL` and `PTTL`, which only differ in the unit of the response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ttl {
+    pub key: RedisString,
+    pub unit: TimeUnit,
 }
 
-impl CommandResponse {
-    pub fn to_resp(&self) -> Message {
+/// `EXPIRETIME` and `PEXPIRETIME`, which return the absolute Unix timestamp at
+/// which a key expires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpireTime {
+    pub key: RedisString,
+    pub unit: TimeUnit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Persist {
+    pub key: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scan {
+    pub cursor: u64,
+    pub pattern: Option<RedisString>,
+    pub count: Option<usize>,
+    pub value_type: Option<RedisString>,
+}
+
+/// `FLUSHDB` and `FLUSHALL`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flush {
+    pub mode: Option<FlushMode>,
+}
+
+/// Whether a flush frees memory before replying (`SYNC`) or in the background
+/// (`ASYNC`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushMode {
+    Sync,
+    Async,
+}
+
+impl FlushMode {
+    const fn as_str(self) -> &'static str {
         match self {
-            Self::Pong => Message::SimpleString("PONG".to_string()),
-            Self::Ok => Message::SimpleString("OK".to_string()),
-            Self::Error(e) => Message::Error(e.clone()),
-            Self::BulkString(s) => Message::BulkString(s.clone()),
+            Self::Sync => "SYNC",
+            Self::Async => "ASYNC",
         }
     }
+}
 
-    pub fn parse_resp(resp: Message) -> Result<Self> {
-        match resp {
-            Message::SimpleString(s) => match s.as_str() {
-                "PONG" => Ok(Self::Pong),
-                "OK" => Ok(Self::Ok),
-                _ => Err(eyre!("unknown simple string response: {s}")),
-            },
-            Message::Error(e) => Ok(Self::Error(e)),
-            Message::BulkString(s) => Ok(Self::BulkString(s)),
-            Message::Array(_) => Err(eyre!("array response not supported for command responses")),
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Del {
+    pub keys: Vec<RedisString>,
+}
+
+/// `UNLINK` is like `DEL`, but values are freed in the background.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unlink {
+    pub keys: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Touch {
+    pub keys: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    pub key: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restore {
+    pub key: RedisString,
+
+    /// TTL in milliseconds, or 0 for no expiration. With `ABSTTL` this is a
+    /// Unix timestamp in milliseconds instead.
+    pub ttl: i64,
+    pub payload: RedisString,
+    pub replace: bool,
+    pub absttl: bool,
+
+    /// Idle time in seconds.
+    pub idletime: Option<i64>,
+}
+
+/// `OBJECT` subcommands for inspecting how values are stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    Encoding { key: RedisString },
+    RefCount { key: RedisString },
+    IdleTime { key: RedisString },
+    Freq { key: RedisString },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Select {
+    pub index: i64,
+}
+
+/// `CLIENT` subcommands for managing the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Client {
+    /// `CLIENT TRACKING ON|OFF [REDIRECT client-id] [BCAST] [PREFIX prefix
+    /// ...]`, which is `None` when turning tracking off.
+    Tracking(Option<ClientTracking>),
+
+    /// `CLIENT PAUSE timeout [WRITE|ALL]` holds back commands from every
+    /// client until the timeout passes or `CLIENT UNPAUSE`.
+    Pause(ClientPause),
+
+    /// `CLIENT UNPAUSE` ends a pause early.
+    Unpause,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientTracking {
+    pub mode: TrackingMode,
+
+    /// The client invalidation messages are sent to instead.
+    pub redirect: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientPause {
+    pub timeout: Duration,
+    pub mode: PauseMode,
+}
+
+/// Which commands `CLIENT PAUSE` holds back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PauseMode {
+    /// Only commands that may write to the keyspace, so clients can keep
+    /// reading.
+    Write,
+
+    /// Every command.
+    All,
+}
+
+/// `REPLCONF`, which replicas use to configure their connection to the
+/// master.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplConf {
+    /// Options sent during the handshake, like `listening-port 6380` or
+    /// `capa psync2`, as lower-cased names and their values. Several can be
+    /// sent at once.
+    Options(Vec<(String, RedisString)>),
+
+    /// `REPLCONF ACK <offset>`, which replicas send to report how much of the
+    /// replication stream they've processed.
+    Ack(u64),
+
+    /// `REPLCONF GETACK *`, which masters send to ask replicas for an `ACK`.
+    GetAck,
+}
+
+/// `PSYNC <replication id> <offset>`, which turns the connection into a
+/// replication link. Replicas send `PSYNC ? -1` to ask for a full sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PSync {
+    pub repl_id: RedisString,
+    pub offset: i64,
+}
+
+/// `REPLICAOF host port` (or `SLAVEOF`) makes the server a replica of
+/// another one, and `REPLICAOF NO ONE` makes it a master again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaOf {
+    /// The master's host and port, or `None` for `NO ONE`.
+    pub master: Option<(String, u16)>,
+}
+
+/// `WAIT numreplicas timeout` blocks until `num_replicas` replicas have
+/// acknowledged the writes made so far, or the timeout passes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wait {
+    pub num_replicas: usize,
+
+    /// How long to wait, or zero to wait forever.
+    pub timeout: Duration,
+}
+
+/// `INFO [section ...]`, with the section names lower-cased. No sections
+/// means the default ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    pub sections: Vec<String>,
+}
+
+/// `COMMAND` and its subcommands, which describe the commands the server
+/// has. Command names are lower-cased.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandQuery {
+    /// Every command's details, from `COMMAND` without a subcommand.
+    All,
+    Count,
+
+    /// The details of the named commands, or of every command if none are
+    /// named.
+    Info {
+        names: Vec<String>,
+    },
+
+    /// The documentation of the named commands, or of every command if none
+    /// are named.
+    Docs {
+        names: Vec<String>,
+    },
+
+    /// The keys in a command, given as its name and arguments.
+    GetKeys {
+        args: Vec<RedisString>,
+    },
+
+    /// Like `GetKeys`, with what the command does with each key.
+    GetKeysAndFlags {
+        args: Vec<RedisString>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscribe {
+    pub channels: Vec<RedisString>,
+}
+
+/// `UNSUBSCRIBE [channel ...]`. No channels means all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsubscribe {
+    pub channels: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PSubscribe {
+    pub patterns: Vec<RedisString>,
+}
+
+/// `PUNSUBSCRIBE [pattern ...]`. No patterns means all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PUnsubscribe {
+    pub patterns: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publish {
+    pub channel: RedisString,
+    pub message: RedisString,
+}
+
+/// `SCRIPT` subcommands for managing the script cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Script {
+    Load { script: RedisString },
+    Exists { shas: Vec<RedisString> },
+    Flush(Flush),
+}
+
+/// `EVAL script numkeys [key ...] [arg ...]` runs a script, and `EVAL_RO`
+/// runs one that may only read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eval {
+    pub script: RedisString,
+    pub keys: Vec<RedisString>,
+    pub args: Vec<RedisString>,
+    pub read_only: bool,
+}
+
+/// `EVALSHA sha1 numkeys [key ...] [arg ...]` runs a cached script, and
+/// `EVALSHA_RO` runs one that may only read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalSha {
+    pub sha: RedisString,
+    pub keys: Vec<RedisString>,
+    pub args: Vec<RedisString>,
+    pub read_only: bool,
+}
+
+/// `FCALL function numkeys [key ...] [arg ...]` calls a function registered
+/// by the crate embedding the server, and `FCALL_RO` calls one that may only
+/// read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FCall {
+    pub function: RedisString,
+    pub keys: Vec<RedisString>,
+    pub args: Vec<RedisString>,
+    pub read_only: bool,
+}
+
+/// `CLUSTER` subcommands for managing which node serves each hash slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cluster {
+    AddSlots { slots: Vec<u16> },
+    SetSlot { slot: u16, state: SlotState },
+    KeySlot { key: RedisString },
+    Info,
+    MyId,
+    Nodes,
+    Slots,
+    Shards,
+}
+
+/// `MEMORY` subcommands for inspecting memory usage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Memory {
+    /// How many bytes a key and its value take up. Collections are
+    /// estimated from `samples` of their elements, or all of them if it's 0.
+    Usage {
+        key: RedisString,
+        samples: Option<usize>,
+    },
+    Stats,
+    Doctor,
+}
+
+/// `LATENCY` subcommands for inspecting the latency spikes the latency monitor
+/// recorded. Events are named like `command` or `fork`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Latency {
+    /// Every event's latest spike and worst latency.
+    Latest,
+    History {
+        event: String,
+    },
+
+    /// Forgets the named events' spikes, or every event's if none are named.
+    Reset {
+        events: Vec<String>,
+    },
+    Doctor,
+}
+
+/// `DEBUG` subcommands, which test harnesses use. They're only allowed if
+/// `enable-debug-command` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugCommand {
+    /// Blocks the server for a while, like a slow command would.
+    Sleep { duration: Duration },
+
+    /// Low-level details of a key's value, like its encoding.
+    Object { key: RedisString },
+
+    /// Turns removing expired keys in the background on or off.
+    SetActiveExpire { enabled: bool },
+
+    /// Fuzz tests glob pattern matching.
+    StringMatchLen,
+}
+
+/// `CONFIG` subcommands for reading and changing the server's settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Config {
+    /// The parameters matching any of the glob patterns, and their values.
+    Get {
+        patterns: Vec<String>,
+    },
+    Set {
+        pairs: Vec<(String, String)>,
+    },
+
+    /// Saves the settings to the config file.
+    Rewrite,
+
+    /// Clears the statistics reported by `INFO`.
+    ResetStat,
+}
+
+/// `ACL` subcommands for managing users and their permissions.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Acl {
+    /// Creates or changes a user by applying rules like `on`, `>password`,
+    /// `~pattern` and `+@category`.
+    SetUser {
+        username: String,
+        rules: Vec<String>,
+    },
+    GetUser {
+        username: String,
+    },
+    DelUser {
+        usernames: Vec<String>,
+    },
+
+    /// Every user and its rules.
+    List,
+
+    /// The user the connection is authenticated as.
+    WhoAmI,
+
+    /// The categories, or with one the commands in it.
+    Cat {
+        category: Option<String>,
+    },
+
+    /// The most recent `count` denied operations, or all of them.
+    Log {
+        count: Option<usize>,
+    },
+    LogReset,
+
+    /// Replaces the users with those in the ACL file.
+    Load,
+
+    /// Writes the users to the ACL file.
+    Save,
+}
+
+// Rules can contain passwords, which are kept out of the logs.
+impl fmt::Debug for Acl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SetUser { username, .. } => f
+                .debug_struct("SetUser")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::GetUser { username } => f
+                .debug_struct("GetUser")
+                .field("username", username)
+                .finish(),
+            Self::DelUser { usernames } => f
+                .debug_struct("DelUser")
+                .field("usernames", usernames)
+                .finish(),
+            Self::List => f.write_str("List"),
+            Self::WhoAmI => f.write_str("WhoAmI"),
+            Self::Cat { category } => f.debug_struct("Cat").field("category", category).finish(),
+            Self::Log { count } => f.debug_struct("Log").field("count", count).finish(),
+            Self::LogReset => f.write_str("LogReset"),
+            Self::Load => f.write_str("Load"),
+            Self::Save => f.write_str("Save"),
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// `AUTH [username] password`. Without a username, the password is the
+/// `default` user's, which is `requirepass`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Auth {
+    pub username: Option<RedisString>,
+    pub password: RedisString,
+}
 
-    fn assert_command_round_trip(cmd: &Command, expected: &[Message]) {
-        let expected = Message::Array(expected.to_vec());
-        let got = cmd.to_resp();
-        assert_eq!(got, expected);
-        let cmd2 = Command::parse_resp(&got).unwrap();
-        assert_eq!(cmd, &cmd2);
+// Passwords are kept out of the logs.
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
     }
+}
 
-    fn assert_command_response_round_trip(response: &CommandResponse, expected: &Message) {
-        let got = response.to_resp();
-        assert_eq!(&got, expected);
-        let response2 = CommandResponse::parse_resp(got).unwrap();
-        assert_eq!(response, &response2);
-    }
+/// `HELLO [protover [AUTH username password]]`, which describes the server
+/// and optionally authenticates the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub protover: Option<i64>,
+    pub auth: Option<Auth>,
+}
 
-    #[test]
-    fn ping_round_trip() {
-        assert_command_round_trip(&Command::Ping, &[Message::bulk_string("PING")]);
-    }
+/// The argument to `CLUSTER SETSLOT slot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotState {
+    /// `MIGRATING node-id`: the slot is moving from this node to another.
+    Migrating(String),
 
-    #[test]
-    fn get_round_trip() {
-        let cmd = Command::Get(Get {
-            key: RedisString::from("foo"),
-        });
-        assert_command_round_trip(
-            &cmd,
-            &[Message::bulk_string("GET"), Message::bulk_string("foo")],
-        );
-    }
+    /// `IMPORTING node-id`: the slot is moving from another node to this one.
+    Importing(String),
 
-    #[test]
-    fn set_round_trip() {
-        let cmd = Command::Set(Set {
-            key: RedisString::from("foo"),
-            value: RedisString::from("bar"),
-        });
-        assert_command_round_trip(
-            &cmd,
-            &[
-                Message::bulk_string("SET"),
-                Message::bulk_string("foo"),
-                Message::bulk_string("bar"),
-            ],
-        );
-    }
+    /// `NODE node-id`: the slot now belongs to the node.
+    Node(String),
+
+    /// `STABLE`: the slot isn't moving after all.
+    Stable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub key: RedisString,
+    pub db: i64,
+}
+
+/// `SORT` and `SORT_RO`. `SORT_RO` doesn't allow `STORE`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sort {
+    pub key: RedisString,
+
+    /// Pattern for looking up the weights to sort by. A pattern without a `*`
+    /// (conventionally `nosort`) skips sorting.
+    pub by: Option<RedisString>,
+    pub limit: Option<Limit>,
+
+    /// Patterns for looking up the values to return instead of the elements
+    /// themselves. `#` returns the element itself.
+    pub get: Vec<RedisString>,
+    pub desc: bool,
+    pub alpha: bool,
+    pub store: Option<RedisString>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub offset: i64,
+    pub count: i64,
+}
+
+/// `LPUSH` and `RPUSH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Push {
+    pub key: RedisString,
+    pub end: ListEnd,
+    pub elements: Vec<RedisString>,
+}
+
+/// `LPOP` and `RPOP`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pop {
+    pub key: RedisString,
+    pub end: ListEnd,
+
+    /// With a count, the reply is an array even if only one element is
+    /// popped.
+    pub count: Option<usize>,
+}
+
+/// Which end of a list to operate on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Left => "LEFT",
+            Self::Right => "RIGHT",
+        }
+    }
+}
+
+/// Pops from the first non-empty list among `keys`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LMPop {
+    pub keys: Vec<RedisString>,
+    pub end: ListEnd,
+    pub count: Option<usize>,
+}
+
+/// `BLPOP` and `BRPOP`, which block until one of the lists has an element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BPop {
+    pub keys: Vec<RedisString>,
+    pub end: ListEnd,
+
+    /// How long to block for, or zero to block forever.
+    pub timeout: Duration,
+}
+
+/// Atomically pops an element from one list and pushes it onto another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LMove {
+    pub source: RedisString,
+    pub destination: RedisString,
+    pub from: ListEnd,
+    pub to: ListEnd,
+}
+
+/// `BLMOVE` is `LMOVE` that blocks until the source list has an element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BLMove {
+    pub lmove: LMove,
+    pub timeout: Duration,
+}
+
+/// `BLMPOP` is `LMPOP` that blocks until one of the lists has an element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BLMPop {
+    pub lmpop: LMPop,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LLen {
+    pub key: RedisString,
+}
+
+/// Indexes in list commands can be negative to count from the end of the
+/// list, so -1 is the last element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LRange {
+    pub key: RedisString,
+    pub start: i64,
+    pub stop: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LIndex {
+    pub key: RedisString,
+    pub index: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LSet {
+    pub key: RedisString,
+    pub index: i64,
+    pub element: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LInsert {
+    pub key: RedisString,
+    pub position: InsertPosition,
+    pub pivot: RedisString,
+    pub element: RedisString,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertPosition {
+    Before,
+    After,
+}
+
+/// Removes elements equal to `element`. A positive `count` removes at most
+/// that many starting from the head, a negative `count` starts from the tail,
+/// and 0 removes all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LRem {
+    pub key: RedisString,
+    pub count: i64,
+    pub element: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HSet {
+    pub key: RedisString,
+    pub pairs: Vec<(RedisString, RedisString)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HGet {
+    pub key: RedisString,
+    pub field: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HDel {
+    pub key: RedisString,
+    pub fields: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HGetAll {
+    pub key: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HMGet {
+    pub key: RedisString,
+    pub fields: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HKeys {
+    pub key: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HVals {
+    pub key: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HLen {
+    pub key: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HExists {
+    pub key: RedisString,
+    pub field: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HSetNx {
+    pub key: RedisString,
+    pub field: RedisString,
+    pub value: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HStrLen {
+    pub key: RedisString,
+    pub field: RedisString,
+}
+
+/// `HSCAN` iterates over the fields of a hash like `SCAN` does for keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HScan {
+    pub key: RedisString,
+    pub cursor: u64,
+    pub pattern: Option<RedisString>,
+    pub count: Option<usize>,
+
+    /// Only return fields, not their values.
+    pub novalues: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SAdd {
+    pub key: RedisString,
+    pub members: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SRem {
+    pub key: RedisString,
+    pub members: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SMembers {
+    pub key: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SIsMember {
+    pub key: RedisString,
+    pub member: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SMIsMember {
+    pub key: RedisString,
+    pub members: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SCard {
+    pub key: RedisString,
+}
+
+/// `SINTER`, `SUNION` and `SDIFF`, plus their `STORE` variants when `store` is
+/// set. The command name is determined by `op` and `store`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetOp {
+    pub op: SetOperation,
+    pub keys: Vec<RedisString>,
+
+    /// The destination key for `SINTERSTORE`, `SUNIONSTORE` and `SDIFFSTORE`.
+    pub store: Option<RedisString>,
+}
+
+/// `SINTERCARD` counts the members of the intersection of `keys`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SInterCard {
+    pub keys: Vec<RedisString>,
+
+    /// Stop counting once the count reaches this limit. 0 means no limit.
+    pub limit: Option<usize>,
+}
+
+/// `SSCAN` iterates over the members of a set like `SCAN` does for keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SScan {
+    pub key: RedisString,
+    pub cursor: u64,
+    pub pattern: Option<RedisString>,
+    pub count: Option<usize>,
+}
+
+/// `ZADD` adds members to a sorted set or updates their scores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZAdd {
+    pub key: RedisString,
+    pub existence: Option<Existence>,
+    pub comparison: Option<Comparison>,
+
+    /// Count changed members in the reply, not just added ones.
+    pub ch: bool,
+
+    /// Increment the score of the single member instead of setting it, like
+    /// `ZINCRBY`.
+    pub incr: bool,
+    pub members: Vec<(Score, RedisString)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZScore {
+    pub key: RedisString,
+    pub member: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZRem {
+    pub key: RedisString,
+    pub members: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZCard {
+    pub key: RedisString,
+}
+
+/// `ZRANGE`, which also represents the legacy `ZRANGEBYSCORE`,
+/// `ZREVRANGEBYSCORE`, `ZREVRANGE`, `ZRANGEBYLEX` and `ZREVRANGEBYLEX`
+/// commands. It is always serialized using the unified `ZRANGE` syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZRange {
+    pub key: RedisString,
+    pub by: ZRangeBy,
+
+    /// Return members from highest to lowest score.
+    pub rev: bool,
+
+    /// Only allowed for score and lexicographical ranges.
+    pub limit: Option<Limit>,
+    pub withscores: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZRangeBy {
+    /// An inclusive range of possibly negative ranks, like `LRANGE`. With
+    /// `rev`, ranks count from the highest score.
+    Rank {
+        start: i64,
+        stop: i64,
+    },
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+/// `ZRANK` and `ZREVRANK`. The command name is determined by `rev`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZRank {
+    pub key: RedisString,
+    pub member: RedisString,
+
+    /// Rank members from highest to lowest score.
+    pub rev: bool,
+
+    /// Reply with the member's score along with its rank.
+    pub withscore: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZIncrBy {
+    pub key: RedisString,
+    pub increment: Score,
+    pub member: RedisString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZCount {
+    pub key: RedisString,
+    pub range: ScoreRange,
+}
+
+/// `ZUNION`, `ZINTER` and `ZDIFF`, plus their `STORE` variants when `store`
+/// is set. The command name is determined by `op` and `store`.
+///
+/// Plain sets can be used as inputs, with every member's score being 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZSetOp {
+    pub op: SetOperation,
+    pub keys: Vec<RedisString>,
+
+    /// Multiplication factors for each input's scores. Empty means all 1.
+    /// Not allowed for `ZDIFF`.
+    pub weights: Vec<Score>,
+
+    /// How to combine the scores of a member that's in several inputs.
+    /// Defaults to `SUM`. Not allowed for `ZDIFF`.
+    pub aggregate: Option<Aggregate>,
+    pub store: Option<RedisString>,
+
+    /// Not allowed with `store`.
+    pub withscores: bool,
+}
+
+/// `ZRANDMEMBER` returns random members of a sorted set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZRandMember {
+    pub key: RedisString,
+
+    /// Without a count, a single member is returned instead of an array. A
+    /// positive count returns distinct members, and a negative count allows
+    /// the same member to be returned more than once.
+    pub count: Option<i64>,
+
+    /// Only allowed with a count.
+    pub withscores: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZMScore {
+    pub key: RedisString,
+    pub members: Vec<RedisString>,
+}
+
+/// `ZSCAN` iterates over the members and scores of a sorted set like `SCAN`
+/// does for keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZScan {
+    pub key: RedisString,
+    pub cursor: u64,
+    pub pattern: Option<RedisString>,
+    pub count: Option<usize>,
+}
+
+/// `SETBIT` sets or clears a bit of a string, padding the string with zero
+/// bytes if it's too short.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetBit {
+    pub key: RedisString,
+    pub offset: u64,
+    pub value: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetBit {
+    pub key: RedisString,
+    pub offset: u64,
+}
+
+/// `BITCOUNT` counts the set bits in a string, or in part of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitCount {
+    pub key: RedisString,
+    pub range: Option<BitRange>,
+}
+
+/// An inclusive range of possibly negative byte or bit indexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitRange {
+    pub start: i64,
+    pub end: i64,
+    pub unit: BitUnit,
+}
+
+/// `BITPOS` finds the first bit of a string that is set to `bit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitPos {
+    pub key: RedisString,
+    pub bit: bool,
+
+    /// The search starts at the beginning of the string by default.
+    pub start: Option<i64>,
+
+    /// The search ends at the end of the string by default. Only allowed with
+    /// `start`.
+    pub end: Option<i64>,
+
+    /// Only meaningful with `end`.
+    pub unit: BitUnit,
+}
+
+/// `XADD` appends an entry to a stream, creating the stream if needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XAdd {
+    pub key: RedisString,
+    pub id: NewId,
+    pub fields: Fields,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XLen {
+    pub key: RedisString,
+}
+
+/// `XRANGE` and `XREVRANGE`. The command name is determined by `rev`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XRange {
+    pub key: RedisString,
+    pub start: RangeBound,
+    pub end: RangeBound,
+    pub count: Option<usize>,
+
+    /// Return entries from the end of the range. `XREVRANGE` takes the end of
+    /// the range before the start.
+    pub rev: bool,
+}
+
+/// `XREAD` returns entries added to streams after the given IDs, optionally
+/// blocking until there are some.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XRead {
+    /// The maximum number of entries to return per stream.
+    pub count: Option<usize>,
+
+    /// How long to wait for entries if there are none yet. Zero means wait
+    /// forever.
+    pub block: Option<Duration>,
+    pub keys: Vec<RedisString>,
+
+    /// The ID to read after for each key.
+    pub ids: Vec<ReadId>,
+}
+
+/// `XGROUP` subcommands for managing consumer groups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XGroup {
+    /// Creates a group that delivers entries after `id`. `mkstream` creates
+    /// the stream if it doesn't exist.
+    Create {
+        key: RedisString,
+        group: RedisString,
+        id: ReadId,
+        mkstream: bool,
+    },
+}
+
+/// `XREADGROUP` reads entries from streams on behalf of a consumer in a
+/// group. Unlike `XREAD`, it doesn't support `BLOCK`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XReadGroup {
+    pub group: RedisString,
+    pub consumer: RedisString,
+
+    /// The maximum number of entries to return per stream.
+    pub count: Option<usize>,
+
+    /// Don't add the entries to the group's pending entries.
+    pub noack: bool,
+    pub keys: Vec<RedisString>,
+    pub ids: Vec<GroupReadId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XAck {
+    pub key: RedisString,
+    pub group: RedisString,
+    pub ids: Vec<StreamId>,
+}
+
+/// `XPENDING` summarizes a group's pending entries, or lists them if
+/// `range` is given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XPending {
+    pub key: RedisString,
+    pub group: RedisString,
+    pub range: Option<PendingRange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRange {
+    /// Only list entries that have been idle for at least this many
+    /// milliseconds.
+    pub min_idle: Option<i64>,
+    pub start: RangeBound,
+    pub end: RangeBound,
+    pub count: usize,
+
+    /// Only list entries owned by this consumer.
+    pub consumer: Option<RedisString>,
+}
+
+/// `XCLAIM` transfers pending entries that have been idle for at least
+/// `min_idle` milliseconds to another consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XClaim {
+    pub key: RedisString,
+    pub group: RedisString,
+    pub consumer: RedisString,
+    pub min_idle: i64,
+    pub ids: Vec<StreamId>,
+
+    /// The idle time to set in milliseconds, instead of 0.
+    pub idle: Option<i64>,
+
+    /// The delivery time to set as a Unix timestamp in milliseconds, instead
+    /// of now.
+    pub time: Option<i64>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub justid: bool,
+}
+
+/// `XAUTOCLAIM` is like `XCLAIM`, but scans for idle pending entries
+/// starting at `start` instead of taking their IDs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XAutoClaim {
+    pub key: RedisString,
+    pub group: RedisString,
+    pub consumer: RedisString,
+    pub min_idle: i64,
+    pub start: StreamId,
+    pub count: Option<usize>,
+    pub justid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XTrim {
+    pub key: RedisString,
+    pub trim: Trim,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XDel {
+    pub key: RedisString,
+    pub ids: Vec<StreamId>,
+}
+
+/// `XINFO` subcommands for inspecting streams and their consumer groups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XInfo {
+    Stream {
+        key: RedisString,
+    },
+    Groups {
+        key: RedisString,
+    },
+    Consumers {
+        key: RedisString,
+        group: RedisString,
+    },
+}
+
+/// `GEOADD` adds members to a sorted set, with their locations' geohashes as
+/// scores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoAdd {
+    pub key: RedisString,
+    pub existence: Option<Existence>,
+
+    /// Count changed members in the reply, not just added ones.
+    pub ch: bool,
+    pub members: Vec<(Coordinates, RedisString)>,
+}
+
+/// `GEOSEARCH` finds the members of a sorted set located within a shape.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoSearch {
+    pub key: RedisString,
+    pub from: GeoOrigin,
+
+    /// The shape's dimensions are in `unit`.
+    pub shape: Shape,
+    pub unit: DistanceUnit,
+
+    /// Sort by distance from the origin. Sorts in ascending order by default
+    /// if there is a `count` without `any`.
+    pub order: Option<SortOrder>,
+    pub count: Option<usize>,
+
+    /// Return the first `count` matches found, instead of the closest ones.
+    pub any: bool,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoDist {
+    pub key: RedisString,
+    pub member1: RedisString,
+    pub member2: RedisString,
+    pub unit: DistanceUnit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoPos {
+    pub key: RedisString,
+    pub members: Vec<RedisString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoHash {
+    pub key: RedisString,
+    pub members: Vec<RedisString>,
+}
+
+/// The center of a `GEOSEARCH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeoOrigin {
+    Member(RedisString),
+    LonLat(Coordinates),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Whether bitmap ranges count bytes or bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Sum => "SUM",
+            Self::Min => "MIN",
+            Self::Max => "MAX",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl Command {
+    #[allow(clippy::too_many_lines)]
+    pub fn to_resp(&self) -> Message {
+        let args = match self {
+            Self::Ping => vec![Message::bulk_string("PING")],
+            Self::Get(get) => vec![
+                Message::bulk_string("GET"),
+                Message::BulkString(Some(get.key.clone())),
+            ],
+            Self::Set(set) => vec![
+                Message::bulk_string("SET"),
+                Message::BulkString(Some(set.key.clone())),
+                Message::BulkString(Some(set.value.clone())),
+            ],
+            Self::Expire(expire) => {
+                let (name, time) = match expire.time {
+                    Expiration::Seconds(t) => ("EXPIRE", t),
+                    Expiration::Milliseconds(t) => ("PEXPIRE", t),
+                    Expiration::UnixSeconds(t) => ("EXPIREAT", t),
+                    Expiration::UnixMilliseconds(t) => ("PEXPIREAT", t),
+                };
+                let mut args = vec![
+                    Message::bulk_string(name),
+                    Message::BulkString(Some(expire.key.clone())),
+                    Message::bulk_string(&time.to_string()),
+                ];
+                if let Some(existence) = expire.existence {
+                    args.push(Message::bulk_string(existence.as_str()));
+                }
+                if let Some(comparison) = expire.comparison {
+                    args.push(Message::bulk_string(comparison.as_str()));
+                }
+                args
+            }
+            Self::Ttl(ttl) => {
+                let name = match ttl.unit {
+                    TimeUnit::Seconds => "TTL",
+                    TimeUnit::Milliseconds => "PTTL",
+                };
+                vec![
+                    Message::bulk_string(name),
+                    Message::BulkString(Some(ttl.key.clone())),
+                ]
+            }
+            Self::ExpireTime(expire_time) => {
+                let name = match expire_time.unit {
+                    TimeUnit::Seconds => "EXPIRETIME",
+                    TimeUnit::Milliseconds => "PEXPIRETIME",
+                };
+                vec![
+                    Message::bulk_string(name),
+                    Message::BulkString(Some(expire_time.key.clone())),
+                ]
+            }
+            Self::Persist(persist) => vec![
+                Message::bulk_string("PERSIST"),
+                Message::BulkString(Some(persist.key.clone())),
+            ],
+            Self::Scan(scan) => {
+                let mut args = vec![
+                    Message::bulk_string("SCAN"),
+                    Message::bulk_string(&scan.cursor.to_string()),
+                ];
+                if let Some(pattern) = &scan.pattern {
+                    args.push(Message::bulk_string("MATCH"));
+                    args.push(Message::BulkString(Some(pattern.clone())));
+                }
+                if let Some(count) = scan.count {
+                    args.push(Message::bulk_string("COUNT"));
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                if let Some(value_type) = &scan.value_type {
+                    args.push(Message::bulk_string("TYPE"));
+                    args.push(Message::BulkString(Some(value_type.clone())));
+                }
+                args
+            }
+            Self::FlushDb(flush) | Self::FlushAll(flush) => {
+                let name = if matches!(self, Self::FlushDb(_)) {
+                    "FLUSHDB"
+                } else {
+                    "FLUSHALL"
+                };
+                let mut args = vec![Message::bulk_string(name)];
+                if let Some(mode) = flush.mode {
+                    args.push(Message::bulk_string(mode.as_str()));
+                }
+                args
+            }
+            Self::Save => vec![Message::bulk_string("SAVE")],
+            Self::BgSave => vec![Message::bulk_string("BGSAVE")],
+            Self::BgRewriteAof => vec![Message::bulk_string("BGREWRITEAOF")],
+            Self::LastSave => vec![Message::bulk_string("LASTSAVE")],
+            Self::ReplConf(ReplConf::Options(options)) => {
+                let mut args = vec![Message::bulk_string("REPLCONF")];
+                for (name, value) in options {
+                    args.push(Message::bulk_string(name));
+                    args.push(Message::BulkString(Some(value.clone())));
+                }
+                args
+            }
+            Self::ReplConf(ReplConf::Ack(offset)) => vec![
+                Message::bulk_string("REPLCONF"),
+                Message::bulk_string("ACK"),
+                Message::bulk_string(&offset.to_string()),
+            ],
+            Self::ReplConf(ReplConf::GetAck) => vec![
+                Message::bulk_string("REPLCONF"),
+                Message::bulk_string("GETACK"),
+                Message::bulk_string("*"),
+            ],
+            Self::ReplicaOf(ReplicaOf { master }) => {
+                let (host, port) = master.as_ref().map_or_else(
+                    || ("NO".to_string(), "ONE".to_string()),
+                    |(host, port)| (host.clone(), port.to_string()),
+                );
+                vec![
+                    Message::bulk_string("REPLICAOF"),
+                    Message::bulk_string(&host),
+                    Message::bulk_string(&port),
+                ]
+            }
+            Self::Wait(Wait {
+                num_replicas,
+                timeout,
+            }) => vec![
+                Message::bulk_string("WAIT"),
+                Message::bulk_string(&num_replicas.to_string()),
+                Message::bulk_string(&timeout.as_millis().to_string()),
+            ],
+            Self::Info(Info { sections }) => std::iter::once("INFO")
+                .chain(sections.iter().map(String::as_str))
+                .map(Message::bulk_string)
+                .collect(),
+            Self::Command(query) => {
+                let (subcommand, args) = match query {
+                    CommandQuery::All => (None, Vec::new()),
+                    CommandQuery::Count => (Some("COUNT"), Vec::new()),
+                    CommandQuery::Info { names } => (Some("INFO"), names_to_resp(names)),
+                    CommandQuery::Docs { names } => (Some("DOCS"), names_to_resp(names)),
+                    CommandQuery::GetKeys { args } => (Some("GETKEYS"), keys_to_resp(args)),
+                    CommandQuery::GetKeysAndFlags { args } => {
+                        (Some("GETKEYSANDFLAGS"), keys_to_resp(args))
+                    }
+                };
+                std::iter::once("COMMAND")
+                    .chain(subcommand)
+                    .map(Message::bulk_string)
+                    .chain(args)
+                    .collect()
+            }
+            Self::Subscribe(Subscribe { channels }) => with_keys("SUBSCRIBE", channels),
+            Self::Unsubscribe(Unsubscribe { channels }) => with_keys("UNSUBSCRIBE", channels),
+            Self::PSubscribe(PSubscribe { patterns }) => with_keys("PSUBSCRIBE", patterns),
+            Self::PUnsubscribe(PUnsubscribe { patterns }) => with_keys("PUNSUBSCRIBE", patterns),
+            Self::Publish(Publish { channel, message }) => vec![
+                Message::bulk_string("PUBLISH"),
+                Message::BulkString(Some(channel.clone())),
+                Message::BulkString(Some(message.clone())),
+            ],
+            Self::Script(Script::Load { script }) => vec![
+                Message::bulk_string("SCRIPT"),
+                Message::bulk_string("LOAD"),
+                Message::BulkString(Some(script.clone())),
+            ],
+            Self::Script(Script::Exists { shas }) => {
+                let mut args = with_keys("SCRIPT", shas);
+                args.insert(1, Message::bulk_string("EXISTS"));
+                args
+            }
+            Self::Script(Script::Flush(Flush { mode })) => {
+                let mut args = vec![
+                    Message::bulk_string("SCRIPT"),
+                    Message::bulk_string("FLUSH"),
+                ];
+                if let Some(mode) = mode {
+                    args.push(Message::bulk_string(mode.as_str()));
+                }
+                args
+            }
+            Self::Eval(Eval {
+                script,
+                keys,
+                args,
+                read_only,
+            }) => {
+                let name = if *read_only { "EVAL_RO" } else { "EVAL" };
+                script_call_to_resp(name, script, keys, args)
+            }
+            Self::EvalSha(EvalSha {
+                sha,
+                keys,
+                args,
+                read_only,
+            }) => {
+                let name = if *read_only { "EVALSHA_RO" } else { "EVALSHA" };
+                script_call_to_resp(name, sha, keys, args)
+            }
+            Self::FCall(FCall {
+                function,
+                keys,
+                args,
+                read_only,
+            }) => {
+                let name = if *read_only { "FCALL_RO" } else { "FCALL" };
+                script_call_to_resp(name, function, keys, args)
+            }
+            Self::Asking => vec![Message::bulk_string("ASKING")],
+            Self::ReadOnly => vec![Message::bulk_string("READONLY")],
+            Self::ReadWrite => vec![Message::bulk_string("READWRITE")],
+            Self::Memory(memory) => {
+                let mut args = vec![Message::bulk_string("MEMORY")];
+                match memory {
+                    Memory::Usage { key, samples } => {
+                        args.push(Message::bulk_string("USAGE"));
+                        args.push(Message::BulkString(Some(key.clone())));
+                        if let Some(samples) = samples {
+                            args.push(Message::bulk_string("SAMPLES"));
+                            args.push(Message::bulk_string(&samples.to_string()));
+                        }
+                    }
+                    Memory::Stats => args.push(Message::bulk_string("STATS")),
+                    Memory::Doctor => args.push(Message::bulk_string("DOCTOR")),
+                }
+                args
+            }
+            Self::Debug(debug) => {
+                let mut args = vec![Message::bulk_string("DEBUG")];
+                match debug {
+                    DebugCommand::Sleep { duration } => {
+                        args.push(Message::bulk_string("SLEEP"));
+                        args.push(Message::bulk_string(&duration.as_secs_f64().to_string()));
+                    }
+                    DebugCommand::Object { key } => {
+                        args.push(Message::bulk_string("OBJECT"));
+                        args.push(Message::BulkString(Some(key.clone())));
+                    }
+                    DebugCommand::SetActiveExpire { enabled } => {
+                        args.push(Message::bulk_string("SET-ACTIVE-EXPIRE"));
+                        args.push(Message::bulk_string(if *enabled { "1" } else { "0" }));
+                    }
+                    DebugCommand::StringMatchLen => {
+                        args.push(Message::bulk_string("STRINGMATCH-LEN"));
+                    }
+                }
+                args
+            }
+            Self::Latency(latency) => {
+                let (subcommand, events): (&str, &[String]) = match latency {
+                    Latency::Latest => ("LATEST", &[]),
+                    Latency::History { event } => ("HISTORY", std::slice::from_ref(event)),
+                    Latency::Reset { events } => ("RESET", events),
+                    Latency::Doctor => ("DOCTOR", &[]),
+                };
+                ["LATENCY", subcommand]
+                    .into_iter()
+                    .chain(events.iter().map(String::as_str))
+                    .map(Message::bulk_string)
+                    .collect()
+            }
+            Self::Auth(Auth { username, password }) => {
+                let mut args = vec![Message::bulk_string("AUTH")];
+                args.extend(
+                    username
+                        .iter()
+                        .map(|u| Message::BulkString(Some(u.clone()))),
+                );
+                args.push(Message::BulkString(Some(password.clone())));
+                args
+            }
+            Self::Hello(Hello { protover, auth }) => {
+                let mut args = vec![Message::bulk_string("HELLO")];
+                if let Some(protover) = protover {
+                    args.push(Message::bulk_string(&protover.to_string()));
+                }
+                if let Some(Auth { username, password }) = auth {
+                    args.push(Message::bulk_string("AUTH"));
+                    args.extend(
+                        username
+                            .iter()
+                            .map(|u| Message::BulkString(Some(u.clone()))),
+                    );
+                    args.push(Message::BulkString(Some(password.clone())));
+                }
+                args
+            }
+            Self::Config(config) => {
+                let mut args = vec![Message::bulk_string("CONFIG")];
+                match config {
+                    Config::Get { patterns } => {
+                        args.push(Message::bulk_string("GET"));
+                        args.extend(patterns.iter().map(|p| Message::bulk_string(p)));
+                    }
+                    Config::Set { pairs } => {
+                        args.push(Message::bulk_string("SET"));
+                        for (name, value) in pairs {
+                            args.push(Message::bulk_string(name));
+                            args.push(Message::bulk_string(value));
+                        }
+                    }
+                    Config::Rewrite => args.push(Message::bulk_string("REWRITE")),
+                    Config::ResetStat => args.push(Message::bulk_string("RESETSTAT")),
+                }
+                args
+            }
+            Self::Acl(acl) => {
+                let mut args = vec![Message::bulk_string("ACL")];
+                match acl {
+                    Acl::SetUser { username, rules } => {
+                        args.push(Message::bulk_string("SETUSER"));
+                        args.push(Message::bulk_string(username));
+                        args.extend(rules.iter().map(|r| Message::bulk_string(r)));
+                    }
+                    Acl::GetUser { username } => {
+                        args.push(Message::bulk_string("GETUSER"));
+                        args.push(Message::bulk_string(username));
+                    }
+                    Acl::DelUser { usernames } => {
+                        args.push(Message::bulk_string("DELUSER"));
+                        args.extend(usernames.iter().map(|u| Message::bulk_string(u)));
+                    }
+                    Acl::List => args.push(Message::bulk_string("LIST")),
+                    Acl::WhoAmI => args.push(Message::bulk_string("WHOAMI")),
+                    Acl::Cat { category } => {
+                        args.push(Message::bulk_string("CAT"));
+                        args.extend(category.iter().map(|c| Message::bulk_string(c)));
+                    }
+                    Acl::Log { count } => {
+                        args.push(Message::bulk_string("LOG"));
+                        args.extend(count.iter().map(|c| Message::bulk_string(&c.to_string())));
+                    }
+                    Acl::LogReset => {
+                        args.push(Message::bulk_string("LOG"));
+                        args.push(Message::bulk_string("RESET"));
+                    }
+                    Acl::Load => args.push(Message::bulk_string("LOAD")),
+                    Acl::Save => args.push(Message::bulk_string("SAVE")),
+                }
+                args
+            }
+            Self::Cluster(cluster) => {
+                let mut args = vec![Message::bulk_string("CLUSTER")];
+                match cluster {
+                    Cluster::AddSlots { slots } => {
+                        args.push(Message::bulk_string("ADDSLOTS"));
+                        args.extend(
+                            slots
+                                .iter()
+                                .map(|slot| Message::bulk_string(&slot.to_string())),
+                        );
+                    }
+                    Cluster::SetSlot { slot, state } => {
+                        args.push(Message::bulk_string("SETSLOT"));
+                        args.push(Message::bulk_string(&slot.to_string()));
+                        let (subcommand, node) = match state {
+                            SlotState::Migrating(node) => ("MIGRATING", Some(node)),
+                            SlotState::Importing(node) => ("IMPORTING", Some(node)),
+                            SlotState::Node(node) => ("NODE", Some(node)),
+                            SlotState::Stable => ("STABLE", None),
+                        };
+                        args.push(Message::bulk_string(subcommand));
+                        args.extend(node.map(|node| Message::bulk_string(node)));
+                    }
+                    Cluster::KeySlot { key } => {
+                        args.push(Message::bulk_string("KEYSLOT"));
+                        args.push(Message::BulkString(Some(key.clone())));
+                    }
+                    Cluster::Info => args.push(Message::bulk_string("INFO")),
+                    Cluster::MyId => args.push(Message::bulk_string("MYID")),
+                    Cluster::Nodes => args.push(Message::bulk_string("NODES")),
+                    Cluster::Slots => args.push(Message::bulk_string("SLOTS")),
+                    Cluster::Shards => args.push(Message::bulk_string("SHARDS")),
+                }
+                args
+            }
+            Self::PSync(PSync { repl_id, offset }) => vec![
+                Message::bulk_string("PSYNC"),
+                Message::BulkString(Some(repl_id.clone())),
+                Message::bulk_string(&offset.to_string()),
+            ],
+            Self::Del(Del { keys }) => with_keys("DEL", keys),
+            Self::Unlink(Unlink { keys }) => with_keys("UNLINK", keys),
+            Self::Touch(Touch { keys }) => with_keys("TOUCH", keys),
+            Self::Dump(Dump { key }) => with_keys("DUMP", std::slice::from_ref(key)),
+            Self::Restore(restore) => {
+                let mut args = vec![
+                    Message::bulk_string("RESTORE"),
+                    Message::BulkString(Some(restore.key.clone())),
+                    Message::bulk_string(&restore.ttl.to_string()),
+                    Message::BulkString(Some(restore.payload.clone())),
+                ];
+                if restore.replace {
+                    args.push(Message::bulk_string("REPLACE"));
+                }
+                if restore.absttl {
+                    args.push(Message::bulk_string("ABSTTL"));
+                }
+                if let Some(idletime) = restore.idletime {
+                    args.push(Message::bulk_string("IDLETIME"));
+                    args.push(Message::bulk_string(&idletime.to_string()));
+                }
+                args
+            }
+            Self::Object(object) => {
+                let (subcommand, key) = match object {
+                    Object::Encoding { key } => ("ENCODING", key),
+                    Object::RefCount { key } => ("REFCOUNT", key),
+                    Object::IdleTime { key } => ("IDLETIME", key),
+                    Object::Freq { key } => ("FREQ", key),
+                };
+                vec![
+                    Message::bulk_string("OBJECT"),
+                    Message::bulk_string(subcommand),
+                    Message::BulkString(Some(key.clone())),
+                ]
+            }
+            Self::Select(Select { index }) => vec![
+                Message::bulk_string("SELECT"),
+                Message::bulk_string(&index.to_string()),
+            ],
+            Self::Client(Client::Tracking(tracking)) => {
+                let mut args = vec![
+                    Message::bulk_string("CLIENT"),
+                    Message::bulk_string("TRACKING"),
+                    Message::bulk_string(if tracking.is_some() { "ON" } else { "OFF" }),
+                ];
+                if let Some(redirect) = tracking.as_ref().and_then(|tracking| tracking.redirect) {
+                    args.push(Message::bulk_string("REDIRECT"));
+                    args.push(Message::bulk_string(&redirect.to_string()));
+                }
+                if let Some(ClientTracking {
+                    mode: TrackingMode::Broadcast(prefixes),
+                    ..
+                }) = tracking
+                {
+                    args.push(Message::bulk_string("BCAST"));
+                    for prefix in prefixes {
+                        args.push(Message::bulk_string("PREFIX"));
+                        args.push(Message::BulkString(Some(prefix.clone())));
+                    }
+                }
+                args
+            }
+            Self::Client(Client::Pause(ClientPause { timeout, mode })) => vec![
+                Message::bulk_string("CLIENT"),
+                Message::bulk_string("PAUSE"),
+                Message::bulk_string(&timeout.as_millis().to_string()),
+                Message::bulk_string(match mode {
+                    PauseMode::Write => "WRITE",
+                    PauseMode::All => "ALL",
+                }),
+            ],
+            Self::Client(Client::Unpause) => vec![
+                Message::bulk_string("CLIENT"),
+                Message::bulk_string("UNPAUSE"),
+            ],
+            Self::Move(Move { key, db }) => vec![
+                Message::bulk_string("MOVE"),
+                Message::BulkString(Some(key.clone())),
+                Message::bulk_string(&db.to_string()),
+            ],
+            Self::Sort(sort) => sort_to_resp("SORT", sort),
+            Self::SortRo(sort) => sort_to_resp("SORT_RO", sort),
+            Self::Push(Push { key, end, elements }) => {
+                let name = match end {
+                    ListEnd::Left => "LPUSH",
+                    ListEnd::Right => "RPUSH",
+                };
+                let mut args = with_keys(name, std::slice::from_ref(key));
+                args.extend(
+                    elements
+                        .iter()
+                        .map(|e| Message::BulkString(Some(e.clone()))),
+                );
+                args
+            }
+            Self::Pop(Pop { key, end, count }) => {
+                let name = match end {
+                    ListEnd::Left => "LPOP",
+                    ListEnd::Right => "RPOP",
+                };
+                let mut args = with_keys(name, std::slice::from_ref(key));
+                if let Some(count) = count {
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                args
+            }
+            Self::LMPop(lmpop) => {
+                let mut args = vec![Message::bulk_string("LMPOP")];
+                args.extend(lmpop_args(lmpop));
+                args
+            }
+            Self::BLMPop(BLMPop { lmpop, timeout }) => {
+                let mut args = vec![
+                    Message::bulk_string("BLMPOP"),
+                    Message::bulk_string(&timeout.as_secs_f64().to_string()),
+                ];
+                args.extend(lmpop_args(lmpop));
+                args
+            }
+            Self::LMove(lmove) => {
+                let mut args = vec![Message::bulk_string("LMOVE")];
+                args.extend(lmove_args(lmove));
+                args
+            }
+            Self::BLMove(BLMove { lmove, timeout }) => {
+                let mut args = vec![Message::bulk_string("BLMOVE")];
+                args.extend(lmove_args(lmove));
+                args.push(Message::bulk_string(&timeout.as_secs_f64().to_string()));
+                args
+            }
+            Self::BPop(BPop { keys, end, timeout }) => {
+                let name = match end {
+                    ListEnd::Left => "BLPOP",
+                    ListEnd::Right => "BRPOP",
+                };
+                let mut args = with_keys(name, keys);
+                args.push(Message::bulk_string(&timeout.as_secs_f64().to_string()));
+                args
+            }
+            Self::LLen(LLen { key }) => with_keys("LLEN", std::slice::from_ref(key)),
+            Self::LRange(LRange { key, start, stop }) => {
+                let mut args = with_keys("LRANGE", std::slice::from_ref(key));
+                args.push(Message::bulk_string(&start.to_string()));
+                args.push(Message::bulk_string(&stop.to_string()));
+                args
+            }
+            Self::LIndex(LIndex { key, index }) => {
+                let mut args = with_keys("LINDEX", std::slice::from_ref(key));
+                args.push(Message::bulk_string(&index.to_string()));
+                args
+            }
+            Self::LSet(LSet {
+                key,
+                index,
+                element,
+            }) => {
+                let mut args = with_keys("LSET", std::slice::from_ref(key));
+                args.push(Message::bulk_string(&index.to_string()));
+                args.push(Message::BulkString(Some(element.clone())));
+                args
+            }
+            Self::LInsert(LInsert {
+                key,
+                position,
+                pivot,
+                element,
+            }) => {
+                let position = match position {
+                    InsertPosition::Before => "BEFORE",
+                    InsertPosition::After => "AFTER",
+                };
+                let mut args = with_keys("LINSERT", std::slice::from_ref(key));
+                args.push(Message::bulk_string(position));
+                args.push(Message::BulkString(Some(pivot.clone())));
+                args.push(Message::BulkString(Some(element.clone())));
+                args
+            }
+            Self::LRem(LRem {
+                key,
+                count,
+                element,
+            }) => {
+                let mut args = with_keys("LREM", std::slice::from_ref(key));
+                args.push(Message::bulk_string(&count.to_string()));
+                args.push(Message::BulkString(Some(element.clone())));
+                args
+            }
+            Self::LTrim(LRange { key, start, stop }) => {
+                let mut args = with_keys("LTRIM", std::slice::from_ref(key));
+                args.push(Message::bulk_string(&start.to_string()));
+                args.push(Message::bulk_string(&stop.to_string()));
+                args
+            }
+            Self::HSet(HSet { key, pairs }) => {
+                let mut args = with_keys("HSET", std::slice::from_ref(key));
+                for (field, value) in pairs {
+                    args.push(Message::BulkString(Some(field.clone())));
+                    args.push(Message::BulkString(Some(value.clone())));
+                }
+                args
+            }
+            Self::HGet(HGet { key, field }) => with_keys("HGET", &[key.clone(), field.clone()]),
+            Self::HDel(HDel { key, fields }) => {
+                let mut args = with_keys("HDEL", std::slice::from_ref(key));
+                args.extend(fields.iter().map(|f| Message::BulkString(Some(f.clone()))));
+                args
+            }
+            Self::HGetAll(HGetAll { key }) => with_keys("HGETALL", std::slice::from_ref(key)),
+            Self::HScan(hscan) => {
+                let mut args = vec![
+                    Message::bulk_string("HSCAN"),
+                    Message::BulkString(Some(hscan.key.clone())),
+                    Message::bulk_string(&hscan.cursor.to_string()),
+                ];
+                if let Some(pattern) = &hscan.pattern {
+                    args.push(Message::bulk_string("MATCH"));
+                    args.push(Message::BulkString(Some(pattern.clone())));
+                }
+                if let Some(count) = hscan.count {
+                    args.push(Message::bulk_string("COUNT"));
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                if hscan.novalues {
+                    args.push(Message::bulk_string("NOVALUES"));
+                }
+                args
+            }
+            Self::HMGet(HMGet { key, fields }) => {
+                let mut args = with_keys("HMGET", std::slice::from_ref(key));
+                args.extend(fields.iter().map(|f| Message::BulkString(Some(f.clone()))));
+                args
+            }
+            Self::HKeys(HKeys { key }) => with_keys("HKEYS", std::slice::from_ref(key)),
+            Self::HVals(HVals { key }) => with_keys("HVALS", std::slice::from_ref(key)),
+            Self::HLen(HLen { key }) => with_keys("HLEN", std::slice::from_ref(key)),
+            Self::HExists(HExists { key, field }) => {
+                with_keys("HEXISTS", &[key.clone(), field.clone()])
+            }
+            Self::HSetNx(HSetNx { key, field, value }) => {
+                with_keys("HSETNX", &[key.clone(), field.clone(), value.clone()])
+            }
+            Self::HStrLen(HStrLen { key, field }) => {
+                with_keys("HSTRLEN", &[key.clone(), field.clone()])
+            }
+            Self::SAdd(SAdd { key, members }) => {
+                let mut args = with_keys("SADD", std::slice::from_ref(key));
+                args.extend(members.iter().map(|m| Message::BulkString(Some(m.clone()))));
+                args
+            }
+            Self::SRem(SRem { key, members }) => {
+                let mut args = with_keys("SREM", std::slice::from_ref(key));
+                args.extend(members.iter().map(|m| Message::BulkString(Some(m.clone()))));
+                args
+            }
+            Self::SMembers(SMembers { key }) => with_keys("SMEMBERS", std::slice::from_ref(key)),
+            Self::SIsMember(SIsMember { key, member }) => {
+                with_keys("SISMEMBER", &[key.clone(), member.clone()])
+            }
+            Self::SMIsMember(SMIsMember { key, members }) => {
+                let mut args = with_keys("SMISMEMBER", std::slice::from_ref(key));
+                args.extend(members.iter().map(|m| Message::BulkString(Some(m.clone()))));
+                args
+            }
+            Self::SCard(SCard { key }) => with_keys("SCARD", std::slice::from_ref(key)),
+            Self::SInterCard(SInterCard { keys, limit }) => {
+                let mut args = vec![
+                    Message::bulk_string("SINTERCARD"),
+                    Message::bulk_string(&keys.len().to_string()),
+                ];
+                args.extend(
+                    keys.iter()
+                        .map(|key| Message::BulkString(Some(key.clone()))),
+                );
+                if let Some(limit) = limit {
+                    args.push(Message::bulk_string("LIMIT"));
+                    args.push(Message::bulk_string(&limit.to_string()));
+                }
+                args
+            }
+            Self::SetOp(SetOp { op, keys, store }) => {
+                let name = match (op, store) {
+                    (SetOperation::Inter, None) => "SINTER",
+                    (SetOperation::Union, None) => "SUNION",
+                    (SetOperation::Diff, None) => "SDIFF",
+                    (SetOperation::Inter, Some(_)) => "SINTERSTORE",
+                    (SetOperation::Union, Some(_)) => "SUNIONSTORE",
+                    (SetOperation::Diff, Some(_)) => "SDIFFSTORE",
+                };
+                let mut args = vec![Message::bulk_string(name)];
+                args.extend(
+                    store
+                        .iter()
+                        .chain(keys)
+                        .map(|key| Message::BulkString(Some(key.clone()))),
+                );
+                args
+            }
+            Self::SScan(sscan) => {
+                let mut args = vec![
+                    Message::bulk_string("SSCAN"),
+                    Message::BulkString(Some(sscan.key.clone())),
+                    Message::bulk_string(&sscan.cursor.to_string()),
+                ];
+                if let Some(pattern) = &sscan.pattern {
+                    args.push(Message::bulk_string("MATCH"));
+                    args.push(Message::BulkString(Some(pattern.clone())));
+                }
+                if let Some(count) = sscan.count {
+                    args.push(Message::bulk_string("COUNT"));
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                args
+            }
+            Self::ZAdd(zadd) => {
+                let mut args = with_keys("ZADD", std::slice::from_ref(&zadd.key));
+                if let Some(existence) = zadd.existence {
+                    args.push(Message::bulk_string(existence.as_str()));
+                }
+                if let Some(comparison) = zadd.comparison {
+                    args.push(Message::bulk_string(comparison.as_str()));
+                }
+                if zadd.ch {
+                    args.push(Message::bulk_string("CH"));
+                }
+                if zadd.incr {
+                    args.push(Message::bulk_string("INCR"));
+                }
+                for (score, member) in &zadd.members {
+                    args.push(Message::bulk_string(&score.to_string()));
+                    args.push(Message::BulkString(Some(member.clone())));
+                }
+                args
+            }
+            Self::ZScore(ZScore { key, member }) => {
+                with_keys("ZSCORE", &[key.clone(), member.clone()])
+            }
+            Self::ZRem(ZRem { key, members }) => {
+                let mut args = with_keys("ZREM", std::slice::from_ref(key));
+                args.extend(members.iter().map(|m| Message::BulkString(Some(m.clone()))));
+                args
+            }
+            Self::ZCard(ZCard { key }) => with_keys("ZCARD", std::slice::from_ref(key)),
+            Self::ZRange(zrange) => zrange_to_resp(zrange),
+            Self::ZRank(ZRank {
+                key,
+                member,
+                rev,
+                withscore,
+            }) => {
+                let name = if *rev { "ZREVRANK" } else { "ZRANK" };
+                let mut args = with_keys(name, &[key.clone(), member.clone()]);
+                if *withscore {
+                    args.push(Message::bulk_string("WITHSCORE"));
+                }
+                args
+            }
+            Self::ZIncrBy(ZIncrBy {
+                key,
+                increment,
+                member,
+            }) => {
+                let increment = RedisString::from(increment.to_string());
+                with_keys("ZINCRBY", &[key.clone(), increment, member.clone()])
+            }
+            Self::ZSetOp(zset_op) => {
+                let name = match (zset_op.op, &zset_op.store) {
+                    (SetOperation::Inter, None) => "ZINTER",
+                    (SetOperation::Union, None) => "ZUNION",
+                    (SetOperation::Diff, None) => "ZDIFF",
+                    (SetOperation::Inter, Some(_)) => "ZINTERSTORE",
+                    (SetOperation::Union, Some(_)) => "ZUNIONSTORE",
+                    (SetOperation::Diff, Some(_)) => "ZDIFFSTORE",
+                };
+                let mut args = vec![Message::bulk_string(name)];
+                args.extend(
+                    zset_op
+                        .store
+                        .iter()
+                        .map(|store| Message::BulkString(Some(store.clone()))),
+                );
+                args.push(Message::bulk_string(&zset_op.keys.len().to_string()));
+                args.extend(
+                    zset_op
+                        .keys
+                        .iter()
+                        .map(|key| Message::BulkString(Some(key.clone()))),
+                );
+                if !zset_op.weights.is_empty() {
+                    args.push(Message::bulk_string("WEIGHTS"));
+                    args.extend(
+                        zset_op
+                            .weights
+                            .iter()
+                            .map(|weight| Message::bulk_string(&weight.to_string())),
+                    );
+                }
+                if let Some(aggregate) = zset_op.aggregate {
+                    args.push(Message::bulk_string("AGGREGATE"));
+                    args.push(Message::bulk_string(aggregate.as_str()));
+                }
+                if zset_op.withscores {
+                    args.push(Message::bulk_string("WITHSCORES"));
+                }
+                args
+            }
+            Self::ZRandMember(ZRandMember {
+                key,
+                count,
+                withscores,
+            }) => {
+                let mut args = with_keys("ZRANDMEMBER", std::slice::from_ref(key));
+                if let Some(count) = count {
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                if *withscores {
+                    args.push(Message::bulk_string("WITHSCORES"));
+                }
+                args
+            }
+            Self::ZScan(zscan) => {
+                let mut args = vec![
+                    Message::bulk_string("ZSCAN"),
+                    Message::BulkString(Some(zscan.key.clone())),
+                    Message::bulk_string(&zscan.cursor.to_string()),
+                ];
+                if let Some(pattern) = &zscan.pattern {
+                    args.push(Message::bulk_string("MATCH"));
+                    args.push(Message::BulkString(Some(pattern.clone())));
+                }
+                if let Some(count) = zscan.count {
+                    args.push(Message::bulk_string("COUNT"));
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                args
+            }
+            Self::ZMScore(ZMScore { key, members }) => {
+                let mut args = with_keys("ZMSCORE", std::slice::from_ref(key));
+                args.extend(members.iter().map(|m| Message::BulkString(Some(m.clone()))));
+                args
+            }
+            Self::ZCount(ZCount { key, range }) => {
+                let mut args = with_keys("ZCOUNT", std::slice::from_ref(key));
+                args.push(Message::bulk_string(&range.min.to_string()));
+                args.push(Message::bulk_string(&range.max.to_string()));
+                args
+            }
+            Self::SetBit(SetBit { key, offset, value }) => {
+                let mut args = with_keys("SETBIT", std::slice::from_ref(key));
+                args.push(Message::bulk_string(&offset.to_string()));
+                args.push(Message::bulk_string(if *value { "1" } else { "0" }));
+                args
+            }
+            Self::GetBit(GetBit { key, offset }) => {
+                let mut args = with_keys("GETBIT", std::slice::from_ref(key));
+                args.push(Message::bulk_string(&offset.to_string()));
+                args
+            }
+            Self::BitCount(BitCount { key, range }) => {
+                let mut args = with_keys("BITCOUNT", std::slice::from_ref(key));
+                if let Some(range) = range {
+                    args.push(Message::bulk_string(&range.start.to_string()));
+                    args.push(Message::bulk_string(&range.end.to_string()));
+                    if range.unit == BitUnit::Bit {
+                        args.push(Message::bulk_string("BIT"));
+                    }
+                }
+                args
+            }
+            Self::BitPos(bitpos) => {
+                let mut args = with_keys("BITPOS", std::slice::from_ref(&bitpos.key));
+                args.push(Message::bulk_string(if bitpos.bit { "1" } else { "0" }));
+                if let Some(start) = bitpos.start {
+                    args.push(Message::bulk_string(&start.to_string()));
+                }
+                if let Some(end) = bitpos.end {
+                    args.push(Message::bulk_string(&end.to_string()));
+                    if bitpos.unit == BitUnit::Bit {
+                        args.push(Message::bulk_string("BIT"));
+                    }
+                }
+                args
+            }
+            Self::XAdd(XAdd { key, id, fields }) => {
+                let mut args = with_keys("XADD", &[key.clone(), id.to_redis_string()]);
+                for (field, value) in fields {
+                    args.push(Message::BulkString(Some(field.clone())));
+                    args.push(Message::BulkString(Some(value.clone())));
+                }
+                args
+            }
+            Self::XLen(XLen { key }) => with_keys("XLEN", std::slice::from_ref(key)),
+            Self::XRange(xrange) => {
+                let (name, first, second) = if xrange.rev {
+                    ("XREVRANGE", xrange.end, xrange.start)
+                } else {
+                    ("XRANGE", xrange.start, xrange.end)
+                };
+                let mut args = with_keys(
+                    name,
+                    &[
+                        xrange.key.clone(),
+                        first.to_redis_string(),
+                        second.to_redis_string(),
+                    ],
+                );
+                if let Some(count) = xrange.count {
+                    args.push(Message::bulk_string("COUNT"));
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                args
+            }
+            Self::XRead(xread) => {
+                let mut args = vec![Message::bulk_string("XREAD")];
+                if let Some(count) = xread.count {
+                    args.push(Message::bulk_string("COUNT"));
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                if let Some(block) = xread.block {
+                    args.push(Message::bulk_string("BLOCK"));
+                    args.push(Message::bulk_string(&block.as_millis().to_string()));
+                }
+                args.push(Message::bulk_string("STREAMS"));
+                args.extend(
+                    xread
+                        .keys
+                        .iter()
+                        .map(|key| Message::BulkString(Some(key.clone()))),
+                );
+                args.extend(
+                    xread
+                        .ids
+                        .iter()
+                        .map(|id| Message::BulkString(Some(id.to_redis_string()))),
+                );
+                args
+            }
+            Self::XGroup(XGroup::Create {
+                key,
+                group,
+                id,
+                mkstream,
+            }) => {
+                let mut args = vec![
+                    Message::bulk_string("XGROUP"),
+                    Message::bulk_string("CREATE"),
+                    Message::BulkString(Some(key.clone())),
+                    Message::BulkString(Some(group.clone())),
+                    Message::BulkString(Some(id.to_redis_string())),
+                ];
+                if *mkstream {
+                    args.push(Message::bulk_string("MKSTREAM"));
+                }
+                args
+            }
+            Self::XReadGroup(xreadgroup) => {
+                let mut args = with_keys(
+                    "XREADGROUP",
+                    &[
+                        RedisString::from("GROUP"),
+                        xreadgroup.group.clone(),
+                        xreadgroup.consumer.clone(),
+                    ],
+                );
+                if let Some(count) = xreadgroup.count {
+                    args.push(Message::bulk_string("COUNT"));
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                if xreadgroup.noack {
+                    args.push(Message::bulk_string("NOACK"));
+                }
+                args.push(Message::bulk_string("STREAMS"));
+                args.extend(
+                    xreadgroup
+                        .keys
+                        .iter()
+                        .map(|key| Message::BulkString(Some(key.clone()))),
+                );
+                args.extend(
+                    xreadgroup
+                        .ids
+                        .iter()
+                        .map(|id| Message::BulkString(Some(id.to_redis_string()))),
+                );
+                args
+            }
+            Self::XAck(XAck { key, group, ids }) => {
+                let mut args = with_keys("XACK", &[key.clone(), group.clone()]);
+                args.extend(ids.iter().map(|id| Message::bulk_string(&id.to_string())));
+                args
+            }
+            Self::XPending(XPending { key, group, range }) => {
+                let mut args = with_keys("XPENDING", &[key.clone(), group.clone()]);
+                if let Some(range) = range {
+                    if let Some(min_idle) = range.min_idle {
+                        args.push(Message::bulk_string("IDLE"));
+                        args.push(Message::bulk_string(&min_idle.to_string()));
+                    }
+                    args.push(Message::BulkString(Some(range.start.to_redis_string())));
+                    args.push(Message::BulkString(Some(range.end.to_redis_string())));
+                    args.push(Message::bulk_string(&range.count.to_string()));
+                    if let Some(consumer) = &range.consumer {
+                        args.push(Message::BulkString(Some(consumer.clone())));
+                    }
+                }
+                args
+            }
+            Self::XClaim(xclaim) => {
+                let mut args = with_keys(
+                    "XCLAIM",
+                    &[
+                        xclaim.key.clone(),
+                        xclaim.group.clone(),
+                        xclaim.consumer.clone(),
+                    ],
+                );
+                args.push(Message::bulk_string(&xclaim.min_idle.to_string()));
+                args.extend(
+                    xclaim
+                        .ids
+                        .iter()
+                        .map(|id| Message::bulk_string(&id.to_string())),
+                );
+                if let Some(idle) = xclaim.idle {
+                    args.push(Message::bulk_string("IDLE"));
+                    args.push(Message::bulk_string(&idle.to_string()));
+                }
+                if let Some(time) = xclaim.time {
+                    args.push(Message::bulk_string("TIME"));
+                    args.push(Message::bulk_string(&time.to_string()));
+                }
+                if let Some(retry_count) = xclaim.retry_count {
+                    args.push(Message::bulk_string("RETRYCOUNT"));
+                    args.push(Message::bulk_string(&retry_count.to_string()));
+                }
+                if xclaim.force {
+                    args.push(Message::bulk_string("FORCE"));
+                }
+                if xclaim.justid {
+                    args.push(Message::bulk_string("JUSTID"));
+                }
+                args
+            }
+            Self::XAutoClaim(xautoclaim) => {
+                let mut args = with_keys(
+                    "XAUTOCLAIM",
+                    &[
+                        xautoclaim.key.clone(),
+                        xautoclaim.group.clone(),
+                        xautoclaim.consumer.clone(),
+                    ],
+                );
+                args.push(Message::bulk_string(&xautoclaim.min_idle.to_string()));
+                args.push(Message::bulk_string(&xautoclaim.start.to_string()));
+                if let Some(count) = xautoclaim.count {
+                    args.push(Message::bulk_string("COUNT"));
+                    args.push(Message::bulk_string(&count.to_string()));
+                }
+                if xautoclaim.justid {
+                    args.push(Message::bulk_string("JUSTID"));
+                }
+                args
+            }
+            Self::XTrim(XTrim { key, trim }) => {
+                let mut args = with_keys("XTRIM", std::slice::from_ref(key));
+                match trim.strategy {
+                    TrimStrategy::MaxLen(max_len) => {
+                        args.push(Message::bulk_string("MAXLEN"));
+                        if trim.approx {
+                            args.push(Message::bulk_string("~"));
+                        }
+                        args.push(Message::bulk_string(&max_len.to_string()));
+                    }
+                    TrimStrategy::MinId(min_id) => {
+                        args.push(Message::bulk_string("MINID"));
+                        if trim.approx {
+                            args.push(Message::bulk_string("~"));
+                        }
+                        args.push(Message::bulk_string(&min_id.to_string()));
+                    }
+                }
+                if let Some(limit) = trim.limit {
+                    args.push(Message::bulk_string("LIMIT"));
+                    args.push(Message::bulk_string(&limit.to_string()));
+                }
+                args
+            }
+            Self::XDel(XDel { key, ids }) => {
+                let mut args = with_keys("XDEL", std::slice::from_ref(key));
+                args.extend(ids.iter().map(|id| Message::bulk_string(&id.to_string())));
+                args
+            }
+            Self::XInfo(xinfo) => {
+                let (subcommand, key, group) = match xinfo {
+                    XInfo::Stream { key } => ("STREAM", key, None),
+                    XInfo::Groups { key } => ("GROUPS", key, None),
+                    XInfo::Consumers { key, group } => ("CONSUMERS", key, Some(group)),
+                };
+                let mut args = vec![
+                    Message::bulk_string("XINFO"),
+                    Message::bulk_string(subcommand),
+                    Message::BulkString(Some(key.clone())),
+                ];
+                if let Some(group) = group {
+                    args.push(Message::BulkString(Some(group.clone())));
+                }
+                args
+            }
+            Self::GeoAdd(GeoAdd {
+                key,
+                existence,
+                ch,
+                members,
+            }) => {
+                let mut args = with_keys("GEOADD", std::slice::from_ref(key));
+                match existence {
+                    Some(Existence::Nx) => args.push(Message::bulk_string("NX")),
+                    Some(Existence::Xx) => args.push(Message::bulk_string("XX")),
+                    None => {}
+                }
+                if *ch {
+                    args.push(Message::bulk_string("CH"));
+                }
+                for (coords, member) in members {
+                    args.push(Message::bulk_string(&coords.longitude.to_string()));
+                    args.push(Message::bulk_string(&coords.latitude.to_string()));
+                    args.push(Message::BulkString(Some(member.clone())));
+                }
+                args
+            }
+            Self::GeoSearch(geosearch) => geosearch_to_resp(geosearch),
+            Self::GeoDist(GeoDist {
+                key,
+                member1,
+                member2,
+                unit,
+            }) => {
+                let mut args =
+                    with_keys("GEODIST", &[key.clone(), member1.clone(), member2.clone()]);
+                args.push(Message::bulk_string(unit.name()));
+                args
+            }
+            Self::GeoPos(GeoPos { key, members }) => {
+                let mut args = with_keys("GEOPOS", std::slice::from_ref(key));
+                args.extend(members.iter().map(|m| Message::BulkString(Some(m.clone()))));
+                args
+            }
+            Self::GeoHash(GeoHash { key, members }) => {
+                let mut args = with_keys("GEOHASH", std::slice::from_ref(key));
+                args.extend(members.iter().map(|m| Message::BulkString(Some(m.clone()))));
+                args
+            }
+            Self::RawCommand(args) => args.clone(),
+        };
+        Message::Array(args)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub fn parse_resp(resp: &Message) -> Result<Self> {
+        let Message::Array(elems) = resp else { return Err(eyre!("commands must be an array")) };
+
+        let Some((cmd_message, args)) = elems.split_first() else { return Err(eyre!("commands must have at least one element")) };
+
+        let cmd_str: String = match cmd_message {
+            Message::SimpleString(cmd_str) => cmd_str.clone(),
+            Message::BulkString(Some(cmd_str)) => {
+                String::try_from(cmd_str.clone()).wrap_err("command name must be valid UTF-8")?
+            }
+            _ => return Err(eyre!("command name must be bulk or simple string")),
+        };
+
+        match cmd_str.to_uppercase().as_str() {
+            "PING" => expect_no_args(Self::Ping, "PING", args),
+            "GET" => {
+                let mut args = Args::new("GET", args);
+                let key = args.next_string()?;
+                args.finish()?;
+                Ok(Self::Get(Get { key }))
+            }
+            "SET" => {
+                let mut args = Args::new("SET", args);
+                let key = args.next_string()?;
+                let value = args.next_string()?;
+                args.finish()?;
+                Ok(Self::Set(Set { key, value }))
+            }
+            "EXPIRE" => parse_expire("EXPIRE", args, Expiration::Seconds),
+            "PEXPIRE" => parse_expire("PEXPIRE", args, Expiration::Milliseconds),
+            "EXPIREAT" => parse_expire("EXPIREAT", args, Expiration::UnixSeconds),
+            "PEXPIREAT" => parse_expire("PEXPIREAT", args, Expiration::UnixMilliseconds),
+            "TTL" => parse_ttl("TTL", args, TimeUnit::Seconds),
+            "PTTL" => parse_ttl("PTTL", args, TimeUnit::Milliseconds),
+            "EXPIRETIME" => parse_expire_time("EXPIRETIME", args, TimeUnit::Seconds),
+            "PEXPIRETIME" => parse_expire_time("PEXPIRETIME", args, TimeUnit::Milliseconds),
+            "PERSIST" => {
+                let mut args = Args::new("PERSIST", args);
+                let key = args.next_string()?;
+                args.finish()?;
+                Ok(Self::Persist(Persist { key }))
+            }
+            "SCAN" => parse_scan(args),
+            "FLUSHDB" => Ok(Self::FlushDb(parse_flush("FLUSHDB", args)?)),
+            "FLUSHALL" => Ok(Self::FlushAll(parse_flush("FLUSHALL", args)?)),
+            "SAVE" => expect_no_args(Self::Save, "SAVE", args),
+            "BGSAVE" => expect_no_args(Self::BgSave, "BGSAVE", args),
+            "LASTSAVE" => expect_no_args(Self::LastSave, "LASTSAVE", args),
+            "BGREWRITEAOF" => expect_no_args(Self::BgRewriteAof, "BGREWRITEAOF", args),
+            "REPLCONF" => parse_replconf(args),
+            "REPLICAOF" => parse_replicaof("REPLICAOF", args),
+            "SLAVEOF" => parse_replicaof("SLAVEOF", args),
+            "PSYNC" => {
+                let mut args = Args::new("PSYNC", args);
+                let repl_id = args.next_string()?;
+                let offset = args.next_i64()?;
+                args.finish()?;
+                Ok(Self::PSync(PSync { repl_id, offset }))
+            }
+            "WAIT" => {
+                let mut args = Args::new("WAIT", args);
+                let num_replicas = usize::try_from(args.next_i64()?)
+                    .map_err(|_| eyre!("value is out of range, must be positive"))?;
+                let millis =
+                    u64::try_from(args.next_i64()?).map_err(|_| eyre!("timeout is negative"))?;
+                args.finish()?;
+                Ok(Self::Wait(Wait {
+                    num_replicas,
+                    timeout: Duration::from_millis(millis),
+                }))
+            }
+            "INFO" => {
+                let mut args = Args::new("INFO", args);
+                let mut sections = Vec::new();
+                while let Some(section) = args.next_option()? {
+                    sections.push(section.to_lowercase());
+                }
+                Ok(Self::Info(Info { sections }))
+            }
+            "SUBSCRIBE" => Ok(Self::Subscribe(Subscribe {
+                channels: parse_keys("SUBSCRIBE", args)?,
+            })),
+            "UNSUBSCRIBE" => Ok(Self::Unsubscribe(Unsubscribe {
+                channels: parse_optional_keys("UNSUBSCRIBE", args)?,
+            })),
+            "PSUBSCRIBE" => Ok(Self::PSubscribe(PSubscribe {
+                patterns: parse_keys("PSUBSCRIBE", args)?,
+            })),
+            "PUNSUBSCRIBE" => Ok(Self::PUnsubscribe(PUnsubscribe {
+                patterns: parse_optional_keys("PUNSUBSCRIBE", args)?,
+            })),
+            "SCRIPT" => parse_script(args),
+            "EVAL" => parse_eval("EVAL", args, false),
+            "EVAL_RO" => parse_eval("EVAL_RO", args, true),
+            "EVALSHA" => parse_evalsha("EVALSHA", args, false),
+            "EVALSHA_RO" => parse_evalsha("EVALSHA_RO", args, true),
+            "CLUSTER" => parse_cluster(args),
+            "ASKING" => expect_no_args(Self::Asking, "ASKING", args),
+            "READONLY" => expect_no_args(Self::ReadOnly, "READONLY", args),
+            "READWRITE" => expect_no_args(Self::ReadWrite, "READWRITE", args),
+            "COMMAND" => parse_command_query(args),
+            "MEMORY" => parse_memory(args),
+            "LATENCY" => parse_latency(args),
+            "DEBUG" => parse_debug(args),
+            "CONFIG" => parse_config(args),
+            "ACL" => parse_acl(args),
+            "AUTH" => parse_auth(args),
+            "HELLO" => parse_hello(args),
+            "FCALL" => parse_fcall("FCALL", args, false),
+            "FCALL_RO" => parse_fcall("FCALL_RO", args, true),
+            "PUBLISH" => {
+                let mut args = Args::new("PUBLISH", args);
+                let channel = args.next_string()?;
+                let message = args.next_string()?;
+                args.finish()?;
+                Ok(Self::Publish(Publish { channel, message }))
+            }
+            "DEL" => Ok(Self::Del(Del {
+                keys: parse_keys("DEL", args)?,
+            })),
+            "UNLINK" => Ok(Self::Unlink(Unlink {
+                keys: parse_keys("UNLINK", args)?,
+            })),
+            "TOUCH" => Ok(Self::Touch(Touch {
+                keys: parse_keys("TOUCH", args)?,
+            })),
+            "DUMP" => {
+                let mut args = Args::new("DUMP", args);
+                let key = args.next_string()?;
+                args.finish()?;
+                Ok(Self::Dump(Dump { key }))
+            }
+            "RESTORE" => parse_restore(args),
+            "OBJECT" => parse_object(args),
+            "SELECT" => {
+                let mut args = Args::new("SELECT", args);
+                let index = args.next_i64()?;
+                args.finish()?;
+                Ok(Self::Select(Select { index }))
+            }
+            "CLIENT" => parse_client(args),
+            "MOVE" => {
+                let mut args = Args::new("MOVE", args);
+                let key = args.next_string()?;
+                let db = args.next_i64()?;
+                args.finish()?;
+                Ok(Self::Move(Move { key, db }))
+            }
+            "SORT" => Ok(Self::Sort(parse_sort("SORT", args)?)),
+            "SORT_RO" => Ok(Self::SortRo(parse_sort("SORT_RO", args)?)),
+            "LPUSH" => parse_push("LPUSH", ListEnd::Left, args),
+            "RPUSH" => parse_push("RPUSH", ListEnd::Right, args),
+            "LPOP" => parse_pop("LPOP", ListEnd::Left, args),
+            "RPOP" => parse_pop("RPOP", ListEnd::Right, args),
+            "LMPOP" => {
+                let mut args = Args::new("LMPOP", args);
+                Ok(Self::LMPop(parse_lmpop(&mut args)?))
+            }
+            "BLMPOP" => {
+                let mut args = Args::new("BLMPOP", args);
+                let timeout = args.next_timeout()?;
+                let lmpop = parse_lmpop(&mut args)?;
+                Ok(Self::BLMPop(BLMPop { lmpop, timeout }))
+            }
+            "HSET" => {
+                let mut args = Args::new("HSET", args);
+                let key = args.next_string()?;
+                let mut pairs = vec![(args.next_string()?, args.next_string()?)];
+                while !args.is_empty() {
+                    pairs.push((args.next_string()?, args.next_string()?));
+                }
+                Ok(Self::HSet(HSet { key, pairs }))
+            }
+            "HGET" => {
+                let mut args = Args::new("HGET", args);
+                let key = args.next_string()?;
+                let field = args.next_string()?;
+                args.finish()?;
+                Ok(Self::HGet(HGet { key, field }))
+            }
+            "HDEL" => {
+                let mut args = Args::new("HDEL", args);
+                let key = args.next_string()?;
+                let mut fields = vec![args.next_string()?];
+                while !args.is_empty() {
+                    fields.push(args.next_string()?);
+                }
+                Ok(Self::HDel(HDel { key, fields }))
+            }
+            "HGETALL" => Ok(Self::HGetAll(HGetAll {
+                key: parse_key("HGETALL", args)?,
+            })),
+            "HMGET" => {
+                let mut args = Args::new("HMGET", args);
+                let key = args.next_string()?;
+                let mut fields = vec![args.next_string()?];
+                while !args.is_empty() {
+                    fields.push(args.next_string()?);
+                }
+                Ok(Self::HMGet(HMGet { key, fields }))
+            }
+            "HSCAN" => parse_hscan(args),
+            "HKEYS" => Ok(Self::HKeys(HKeys {
+                key: parse_key("HKEYS", args)?,
+            })),
+            "HVALS" => Ok(Self::HVals(HVals {
+                key: parse_key("HVALS", args)?,
+            })),
+            "HLEN" => Ok(Self::HLen(HLen {
+                key: parse_key("HLEN", args)?,
+            })),
+            "HEXISTS" => {
+                let mut args = Args::new("HEXISTS", args);
+                let key = args.next_string()?;
+                let field = args.next_string()?;
+                args.finish()?;
+                Ok(Self::HExists(HExists { key, field }))
+            }
+            "HSETNX" => {
+                let mut args = Args::new("HSETNX", args);
+                let key = args.next_string()?;
+                let field = args.next_string()?;
+                let value = args.next_string()?;
+                args.finish()?;
+                Ok(Self::HSetNx(HSetNx { key, field, value }))
+            }
+            "HSTRLEN" => {
+                let mut args = Args::new("HSTRLEN", args);
+                let key = args.next_string()?;
+                let field = args.next_string()?;
+                args.finish()?;
+                Ok(Self::HStrLen(HStrLen { key, field }))
+            }
+            "SADD" => {
+                let mut args = Args::new("SADD", args);
+                let key = args.next_string()?;
+                let mut members = vec![args.next_string()?];
+                while !args.is_empty() {
+                    members.push(args.next_string()?);
+                }
+                Ok(Self::SAdd(SAdd { key, members }))
+            }
+            "SREM" => {
+                let mut args = Args::new("SREM", args);
+                let key = args.next_string()?;
+                let mut members = vec![args.next_string()?];
+                while !args.is_empty() {
+                    members.push(args.next_string()?);
+                }
+                Ok(Self::SRem(SRem { key, members }))
+            }
+            "SMEMBERS" => Ok(Self::SMembers(SMembers {
+                key: parse_key("SMEMBERS", args)?,
+            })),
+            "SISMEMBER" => {
+                let mut args = Args::new("SISMEMBER", args);
+                let key = args.next_string()?;
+                let member = args.next_string()?;
+                args.finish()?;
+                Ok(Self::SIsMember(SIsMember { key, member }))
+            }
+            "SMISMEMBER" => {
+                let mut args = Args::new("SMISMEMBER", args);
+                let key = args.next_string()?;
+                let mut members = vec![args.next_string()?];
+                while !args.is_empty() {
+                    members.push(args.next_string()?);
+                }
+                Ok(Self::SMIsMember(SMIsMember { key, members }))
+            }
+            "SINTERCARD" => parse_sintercard(args),
+            "SCARD" => Ok(Self::SCard(SCard {
+                key: parse_key("SCARD", args)?,
+            })),
+            "SSCAN" => parse_sscan(args),
+            "ZADD" => parse_zadd(args),
+            "ZSCORE" => {
+                let mut args = Args::new("ZSCORE", args);
+                let key = args.next_string()?;
+                let member = args.next_string()?;
+                args.finish()?;
+                Ok(Self::ZScore(ZScore { key, member }))
+            }
+            "ZREM" => {
+                let mut args = Args::new("ZREM", args);
+                let key = args.next_string()?;
+                let mut members = vec![args.next_string()?];
+                while !args.is_empty() {
+                    members.push(args.next_string()?);
+                }
+                Ok(Self::ZRem(ZRem { key, members }))
+            }
+            "ZRANGE" => parse_zrange("ZRANGE", None, args),
+            "ZRANGEBYSCORE" => parse_zrange("ZRANGEBYSCORE", Some((RangeKind::Score, false)), args),
+            "ZREVRANGEBYSCORE" => {
+                parse_zrange("ZREVRANGEBYSCORE", Some((RangeKind::Score, true)), args)
+            }
+            "ZREVRANGE" => parse_zrange("ZREVRANGE", Some((RangeKind::Rank, true)), args),
+            "ZRANGEBYLEX" => parse_zrange("ZRANGEBYLEX", Some((RangeKind::Lex, false)), args),
+            "ZREVRANGEBYLEX" => parse_zrange("ZREVRANGEBYLEX", Some((RangeKind::Lex, true)), args),
+            "ZRANK" => parse_zrank("ZRANK", false, args),
+            "ZREVRANK" => parse_zrank("ZREVRANK", true, args),
+            "ZINCRBY" => {
+                let mut args = Args::new("ZINCRBY", args);
+                let key = args.next_string()?;
+                let increment = args.next_score()?;
+                let member = args.next_string()?;
+                args.finish()?;
+                Ok(Self::ZIncrBy(ZIncrBy {
+                    key,
+                    increment,
+                    member,
+                }))
+            }
+            "ZCOUNT" => {
+                let mut args = Args::new("ZCOUNT", args);
+                let key = args.next_string()?;
+                let min = args.next_score_bound()?;
+                let max = args.next_score_bound()?;
+                args.finish()?;
+                Ok(Self::ZCount(ZCount {
+                    key,
+                    range: ScoreRange { min, max },
+                }))
+            }
+            "ZINTER" => parse_zset_op("ZINTER", SetOperation::Inter, false, args),
+            "ZUNION" => parse_zset_op("ZUNION", SetOperation::Union, false, args),
+            "ZDIFF" => parse_zset_op("ZDIFF", SetOperation::Diff, false, args),
+            "ZINTERSTORE" => parse_zset_op("ZINTERSTORE", SetOperation::Inter, true, args),
+            "ZUNIONSTORE" => parse_zset_op("ZUNIONSTORE", SetOperation::Union, true, args),
+            "ZDIFFSTORE" => parse_zset_op("ZDIFFSTORE", SetOperation::Diff, true, args),
+            "ZRANDMEMBER" => {
+                let mut args = Args::new("ZRANDMEMBER", args);
+                let key = args.next_string()?;
+                let count = if args.is_empty() {
+                    None
+                } else {
+                    Some(args.next_i64()?)
+                };
+                let withscores = match args.next_option()?.as_deref() {
+                    None => false,
+                    Some("WITHSCORES") => true,
+                    Some(_) => return Err(eyre!("syntax error")),
+                };
+                args.finish()?;
+                Ok(Self::ZRandMember(ZRandMember {
+                    key,
+                    count,
+                    withscores,
+                }))
+            }
+            "ZSCAN" => parse_zscan(args),
+            "ZMSCORE" => {
+                let mut args = Args::new("ZMSCORE", args);
+                let key = args.next_string()?;
+                let mut members = vec![args.next_string()?];
+                while !args.is_empty() {
+                    members.push(args.next_string()?);
+                }
+                Ok(Self::ZMScore(ZMScore { key, members }))
+            }
+            "SETBIT" => {
+                let mut args = Args::new("SETBIT", args);
+                let key = args.next_string()?;
+                let offset = args.next_bit_offset()?;
+                let value = match args.next_string()?.as_bytes() {
+                    b"0" => false,
+                    b"1" => true,
+                    _ => return Err(eyre!("bit is not an integer or out of range")),
+                };
+                args.finish()?;
+                Ok(Self::SetBit(SetBit { key, offset, value }))
+            }
+            "GETBIT" => {
+                let mut args = Args::new("GETBIT", args);
+                let key = args.next_string()?;
+                let offset = args.next_bit_offset()?;
+                args.finish()?;
+                Ok(Self::GetBit(GetBit { key, offset }))
+            }
+            "BITCOUNT" => parse_bitcount(args),
+            "XADD" => parse_xadd(args),
+            "XLEN" => Ok(Self::XLen(XLen {
+                key: parse_key("XLEN", args)?,
+            })),
+            "XRANGE" => parse_xrange("XRANGE", false, args),
+            "XREVRANGE" => parse_xrange("XREVRANGE", true, args),
+            "XREAD" => parse_xread(args),
+            "XGROUP" => parse_xgroup(args),
+            "XREADGROUP" => parse_xreadgroup(args),
+            "XACK" => parse_xack(args),
+            "XPENDING" => parse_xpending(args),
+            "XCLAIM" => parse_xclaim(args),
+            "XAUTOCLAIM" => parse_xautoclaim(args),
+            "XTRIM" => parse_xtrim(args),
+            "XDEL" => parse_xdel(args),
+            "XINFO" => parse_xinfo(args),
+            "GEOADD" => parse_geoadd(args),
+            "GEOSEARCH" => parse_geosearch(args),
+            "GEODIST" => {
+                let mut args = Args::new("GEODIST", args);
+                let key = args.next_string()?;
+                let member1 = args.next_string()?;
+                let member2 = args.next_string()?;
+                let unit = if args.is_empty() {
+                    DistanceUnit::Meters
+                } else {
+                    args.next_distance_unit()?
+                };
+                args.finish()?;
+                Ok(Self::GeoDist(GeoDist {
+                    key,
+                    member1,
+                    member2,
+                    unit,
+                }))
+            }
+            "GEOPOS" => {
+                let mut args = Args::new("GEOPOS", args);
+                let key = args.next_string()?;
+                let mut members = Vec::new();
+                while !args.is_empty() {
+                    members.push(args.next_string()?);
+                }
+                Ok(Self::GeoPos(GeoPos { key, members }))
+            }
+            "GEOHASH" => {
+                let mut args = Args::new("GEOHASH", args);
+                let key = args.next_string()?;
+                let mut members = Vec::new();
+                while !args.is_empty() {
+                    members.push(args.next_string()?);
+                }
+                Ok(Self::GeoHash(GeoHash { key, members }))
+            }
+            "BITPOS" => parse_bitpos(args),
+            "ZCARD" => Ok(Self::ZCard(ZCard {
+                key: parse_key("ZCARD", args)?,
+            })),
+            "SINTER" => parse_set_op("SINTER", SetOperation::Inter, false, args),
+            "SUNION" => parse_set_op("SUNION", SetOperation::Union, false, args),
+            "SDIFF" => parse_set_op("SDIFF", SetOperation::Diff, false, args),
+            "SINTERSTORE" => parse_set_op("SINTERSTORE", SetOperation::Inter, true, args),
+            "SUNIONSTORE" => parse_set_op("SUNIONSTORE", SetOperation::Union, true, args),
+            "SDIFFSTORE" => parse_set_op("SDIFFSTORE", SetOperation::Diff, true, args),
+            "LMOVE" => {
+                let mut args = Args::new("LMOVE", args);
+                let lmove = parse_lmove(&mut args)?;
+                args.finish()?;
+                Ok(Self::LMove(lmove))
+            }
+            "BLMOVE" => {
+                let mut args = Args::new("BLMOVE", args);
+                let lmove = parse_lmove(&mut args)?;
+                let timeout = args.next_timeout()?;
+                args.finish()?;
+                Ok(Self::BLMove(BLMove { lmove, timeout }))
+            }
+            "BLPOP" => parse_bpop("BLPOP", ListEnd::Left, args),
+            "BRPOP" => parse_bpop("BRPOP", ListEnd::Right, args),
+            "LLEN" => {
+                let mut args = Args::new("LLEN", args);
+                let key = args.next_string()?;
+                args.finish()?;
+                Ok(Self::LLen(LLen { key }))
+            }
+            "LRANGE" => Ok(Self::LRange(parse_list_range("LRANGE", args)?)),
+            "LTRIM" => Ok(Self::LTrim(parse_list_range("LTRIM", args)?)),
+            "LINDEX" => {
+                let mut args = Args::new("LINDEX", args);
+                let key = args.next_string()?;
+                let index = args.next_i64()?;
+                args.finish()?;
+                Ok(Self::LIndex(LIndex { key, index }))
+            }
+            "LSET" => {
+                let mut args = Args::new("LSET", args);
+                let key = args.next_string()?;
+                let index = args.next_i64()?;
+                let element = args.next_string()?;
+                args.finish()?;
+                Ok(Self::LSet(LSet {
+                    key,
+                    index,
+                    element,
+                }))
+            }
+            "LINSERT" => {
+                let mut args = Args::new("LINSERT", args);
+                let key = args.next_string()?;
+                let position = match args.next_option()?.as_deref() {
+                    Some("BEFORE") => InsertPosition::Before,
+                    Some("AFTER") => InsertPosition::After,
+                    _ => return Err(eyre!("syntax error")),
+                };
+                let pivot = args.next_string()?;
+                let element = args.next_string()?;
+                args.finish()?;
+                Ok(Self::LInsert(LInsert {
+                    key,
+                    position,
+                    pivot,
+                    element,
+                }))
+            }
+            "LREM" => {
+                let mut args = Args::new("LREM", args);
+                let key = args.next_string()?;
+                let count = args.next_i64()?;
+                let element = args.next_string()?;
+                args.finish()?;
+                Ok(Self::LRem(LRem {
+                    key,
+                    count,
+                    element,
+                }))
+            }
+            _ => {
+                let args: String = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Message::BulkString(Some(arg)) => {
+                            format!("'{}' ", String::from_utf8_lossy(arg.as_bytes()))
+                        }
+                        arg => format!("'{arg:?}' "),
+                    })
+                    .collect();
+                Err(eyre!(
+                    "unknown command '{cmd_str}', with args beginning with: {args}"
+                ))
+            }
+        }
+    }
+}
+
+/// Helper function for serializing commands whose arguments are all keys.
+fn with_keys(cmd_str: &str, keys: &[RedisString]) -> Vec<Message> {
+    let mut args = vec![Message::bulk_string(cmd_str)];
+    args.extend(keys_to_resp(keys));
+    args
+}
+
+fn keys_to_resp(keys: &[RedisString]) -> Vec<Message> {
+    keys.iter()
+        .map(|key| Message::BulkString(Some(key.clone())))
+        .collect()
+}
+
+fn names_to_resp(names: &[String]) -> Vec<Message> {
+    names
+        .iter()
+        .map(|name| Message::bulk_string(name))
+        .collect()
+}
+
+/// Helper function for parsing commands that take a single key.
+fn parse_key(cmd_str: &'static str, args: &[Message]) -> Result<RedisString> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    args.finish()?;
+    Ok(key)
+}
+
+/// Helper function for parsing commands that take one or more keys.
+fn parse_keys(cmd_str: &'static str, args: &[Message]) -> Result<Vec<RedisString>> {
+    let mut args = Args::new(cmd_str, args);
+    let mut keys = vec![args.next_string()?];
+    while !args.is_empty() {
+        keys.push(args.next_string()?);
+    }
+    Ok(keys)
+}
+
+/// Helper function for parsing commands that take zero or more keys.
+fn parse_optional_keys(cmd_str: &'static str, args: &[Message]) -> Result<Vec<RedisString>> {
+    let mut args = Args::new(cmd_str, args);
+    let mut keys = Vec::new();
+    while !args.is_empty() {
+        keys.push(args.next_string()?);
+    }
+    Ok(keys)
+}
+
+/// Helper function to ensure that a command has no arguments.
+/// The error for a command called with the wrong number of arguments, worded
+/// like Redis so clients can match on it.
+fn wrong_number_of_arguments(cmd_str: &str) -> Report {
+    eyre!(
+        "wrong number of arguments for '{}' command",
+        cmd_str.to_lowercase()
+    )
+}
+
+fn expect_no_args<T>(cmd: T, cmd_str: &str, args: &[Message]) -> Result<T> {
+    if !args.is_empty() {
+        return Err(wrong_number_of_arguments(cmd_str));
+    }
+    Ok(cmd)
+}
+
+fn parse_expire(
+    cmd_str: &'static str,
+    args: &[Message],
+    time: fn(i64) -> Expiration,
+) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    let time = time(args.next_i64()?);
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "GT" => gt = true,
+            "LT" => lt = true,
+            _ => return Err(eyre!("unsupported {cmd_str} option: {option}")),
+        }
+    }
+
+    if nx && (xx || gt || lt) {
+        return Err(eyre!(
+            "NX and XX, GT or LT options at the same time are not compatible"
+        ));
+    }
+    if gt && lt {
+        return Err(eyre!(
+            "GT and LT options at the same time are not compatible"
+        ));
+    }
+    let existence = if nx {
+        Some(Existence::Nx)
+    } else if xx {
+        Some(Existence::Xx)
+    } else {
+        None
+    };
+    let comparison = if gt {
+        Some(Comparison::Gt)
+    } else if lt {
+        Some(Comparison::Lt)
+    } else {
+        None
+    };
+
+    Ok(Command::Expire(Expire {
+        key,
+        time,
+        existence,
+        comparison,
+    }))
+}
+
+fn parse_ttl(cmd_str: &'static str, args: &[Message], unit: TimeUnit) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    args.finish()?;
+    Ok(Command::Ttl(Ttl { key, unit }))
+}
+
+fn parse_expire_time(cmd_str: &'static str, args: &[Message], unit: TimeUnit) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    args.finish()?;
+    Ok(Command::ExpireTime(ExpireTime { key, unit }))
+}
+
+fn parse_scan(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("SCAN", args);
+    let cursor = args.next_cursor()?;
+
+    let mut pattern = None;
+    let mut count = None;
+    let mut value_type = None;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "MATCH" => pattern = Some(args.next_string()?),
+            "COUNT" => count = Some(args.next_count()?),
+            "TYPE" => value_type = Some(args.next_string()?),
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    Ok(Command::Scan(Scan {
+        cursor,
+        pattern,
+        count,
+        value_type,
+    }))
+}
+
+fn parse_hscan(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("HSCAN", args);
+    let key = args.next_string()?;
+    let cursor = args.next_cursor()?;
+
+    let mut pattern = None;
+    let mut count = None;
+    let mut novalues = false;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "MATCH" => pattern = Some(args.next_string()?),
+            "COUNT" => count = Some(args.next_count()?),
+            "NOVALUES" => novalues = true,
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    Ok(Command::HScan(HScan {
+        key,
+        cursor,
+        pattern,
+        count,
+        novalues,
+    }))
+}
+
+fn parse_zadd(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("ZADD", args);
+    let key = args.next_string()?;
+
+    // Flags come before the score/member pairs.
+    let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+        (false, false, false, false, false, false);
+    while let Some(option) = args.peek_option() {
+        match option.as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "GT" => gt = true,
+            "LT" => lt = true,
+            "CH" => ch = true,
+            "INCR" => incr = true,
+            _ => break,
+        }
+        args.next_string()?;
+    }
+
+    if args.is_empty() || !args.rest.len().is_multiple_of(2) {
+        return Err(eyre!("syntax error"));
+    }
+    let mut members = Vec::new();
+    while !args.is_empty() {
+        let score = args.next_score()?;
+        members.push((score, args.next_string()?));
+    }
+
+    if nx && xx {
+        return Err(eyre!(
+            "XX and NX options at the same time are not compatible"
+        ));
+    }
+    if (gt && lt) || (nx && (gt || lt)) {
+        return Err(eyre!(
+            "GT, LT, and/or NX options at the same time are not compatible"
+        ));
+    }
+    if incr && members.len() > 1 {
+        return Err(eyre!(
+            "INCR option supports a single increment-element pair"
+        ));
+    }
+
+    let existence = if nx {
+        Some(Existence::Nx)
+    } else if xx {
+        Some(Existence::Xx)
+    } else {
+        None
+    };
+    let comparison = if gt {
+        Some(Comparison::Gt)
+    } else if lt {
+        Some(Comparison::Lt)
+    } else {
+        None
+    };
+    Ok(Command::ZAdd(ZAdd {
+        key,
+        existence,
+        comparison,
+        ch,
+        incr,
+        members,
+    }))
+}
+
+fn zrange_to_resp(zrange: &ZRange) -> Vec<Message> {
+    // With REV, score and lexicographical ranges are written from max to min.
+    let (start, stop, by) = match &zrange.by {
+        ZRangeBy::Rank { start, stop } => (
+            RedisString::from(start.to_string()),
+            RedisString::from(stop.to_string()),
+            None,
+        ),
+        ZRangeBy::Score(ScoreRange { min, max }) => {
+            let (start, stop) = if zrange.rev { (max, min) } else { (min, max) };
+            (
+                RedisString::from(start.to_string()),
+                RedisString::from(stop.to_string()),
+                Some("BYSCORE"),
+            )
+        }
+        ZRangeBy::Lex(LexRange { min, max }) => {
+            let (start, stop) = if zrange.rev { (max, min) } else { (min, max) };
+            (
+                start.to_redis_string(),
+                stop.to_redis_string(),
+                Some("BYLEX"),
+            )
+        }
+    };
+
+    let mut args = with_keys("ZRANGE", &[zrange.key.clone(), start, stop]);
+    args.extend(by.map(Message::bulk_string));
+    if zrange.rev {
+        args.push(Message::bulk_string("REV"));
+    }
+    if let Some(limit) = zrange.limit {
+        args.push(Message::bulk_string("LIMIT"));
+        args.push(Message::bulk_string(&limit.offset.to_string()));
+        args.push(Message::bulk_string(&limit.count.to_string()));
+    }
+    if zrange.withscores {
+        args.push(Message::bulk_string("WITHSCORES"));
+    }
+    args
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeKind {
+    Rank,
+    Score,
+    Lex,
+}
+
+/// Parses `ZRANGE`, or one of the legacy range commands if `legacy` gives the
+/// kind of range and direction the command implies.
+fn parse_zrange(
+    cmd_str: &'static str,
+    legacy: Option<(RangeKind, bool)>,
+    args: &[Message],
+) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    let start = args.next_string()?;
+    let stop = args.next_string()?;
+
+    let (mut kind, mut rev) = legacy.unwrap_or((RangeKind::Rank, false));
+    let mut limit = None;
+    let mut withscores = false;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "BYSCORE" if legacy.is_none() => kind = RangeKind::Score,
+            "BYLEX" if legacy.is_none() => kind = RangeKind::Lex,
+            "REV" if legacy.is_none() => rev = true,
+            "LIMIT" if legacy.is_none_or(|(kind, _)| kind != RangeKind::Rank) => {
+                let offset = args.next_i64()?;
+                let count = args.next_i64()?;
+                limit = Some(Limit { offset, count });
+            }
+            "WITHSCORES" => withscores = true,
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    if limit.is_some() && kind == RangeKind::Rank {
+        return Err(eyre!(
+            "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+        ));
+    }
+    if withscores && kind == RangeKind::Lex {
+        return Err(eyre!(
+            "syntax error, WITHSCORES not supported in combination with BYLEX"
+        ));
+    }
+
+    // With REV, score and lexicographical ranges are given from max to min.
+    let (min, max) = if rev {
+        (&stop, &start)
+    } else {
+        (&start, &stop)
+    };
+    let by = match kind {
+        RangeKind::Rank => {
+            let parse = |s: &RedisString| {
+                s.to_i64()
+                    .ok_or_else(|| eyre!("value is not an integer or out of range"))
+            };
+            ZRangeBy::Rank {
+                start: parse(&start)?,
+                stop: parse(&stop)?,
+            }
+        }
+        RangeKind::Score => {
+            let parse = |s| ScoreBound::parse(s).ok_or_else(|| eyre!("min or max is not a float"));
+            ZRangeBy::Score(ScoreRange {
+                min: parse(min)?,
+                max: parse(max)?,
+            })
+        }
+        RangeKind::Lex => {
+            let parse = |s| {
+                LexBound::parse(s).ok_or_else(|| eyre!("min or max not valid string range item"))
+            };
+            ZRangeBy::Lex(LexRange {
+                min: parse(min)?,
+                max: parse(max)?,
+            })
+        }
+    };
+
+    Ok(Command::ZRange(ZRange {
+        key,
+        by,
+        rev,
+        limit,
+        withscores,
+    }))
+}
+
+fn parse_zset_op(
+    cmd_str: &'static str,
+    op: SetOperation,
+    store: bool,
+    args: &[Message],
+) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let store = if store {
+        Some(args.next_string()?)
+    } else {
+        None
+    };
+    let num_keys = args.next_num_keys()?;
+    let keys: Vec<_> = (0..num_keys)
+        .map(|_| args.next_string())
+        .collect::<Result<_>>()?;
+
+    let mut weights = Vec::new();
+    let mut aggregate = None;
+    let mut withscores = false;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "WEIGHTS" if op != SetOperation::Diff => {
+                weights = (0..keys.len())
+                    .map(|_| {
+                        let s = args.next_string().map_err(|_| eyre!("syntax error"))?;
+                        Score::parse(&s).ok_or_else(|| eyre!("weight value is not a float"))
+                    })
+                    .collect::<Result<_>>()?;
+            }
+            "AGGREGATE" if op != SetOperation::Diff => {
+                aggregate = match args.next_option()?.as_deref() {
+                    Some("SUM") => Some(Aggregate::Sum),
+                    Some("MIN") => Some(Aggregate::Min),
+                    Some("MAX") => Some(Aggregate::Max),
+                    _ => return Err(eyre!("syntax error")),
+                };
+            }
+            "WITHSCORES" if store.is_none() => withscores = true,
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    Ok(Command::ZSetOp(ZSetOp {
+        op,
+        keys,
+        weights,
+        aggregate,
+        store,
+        withscores,
+    }))
+}
+
+fn parse_zrank(cmd_str: &'static str, rev: bool, args: &[Message]) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    let member = args.next_string()?;
+    let withscore = match args.next_option()?.as_deref() {
+        None => false,
+        Some("WITHSCORE") => true,
+        Some(_) => return Err(eyre!("syntax error")),
+    };
+    args.finish()?;
+    Ok(Command::ZRank(ZRank {
+        key,
+        member,
+        rev,
+        withscore,
+    }))
+}
+
+fn parse_sintercard(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("SINTERCARD", args);
+    let num_keys = args.next_num_keys()?;
+    let keys = (0..num_keys)
+        .map(|_| args.next_string())
+        .collect::<Result<_>>()?;
+    let mut limit = None;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "LIMIT" if limit.is_none() => {
+                let n = args.next_i64()?;
+                let Ok(n) = usize::try_from(n) else {
+                    return Err(eyre!("LIMIT can't be negative"));
+                };
+                limit = Some(n);
+            }
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+    Ok(Command::SInterCard(SInterCard { keys, limit }))
+}
+
+fn parse_sscan(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("SSCAN", args);
+    let key = args.next_string()?;
+    let cursor = args.next_cursor()?;
+
+    let mut pattern = None;
+    let mut count = None;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "MATCH" => pattern = Some(args.next_string()?),
+            "COUNT" => count = Some(args.next_count()?),
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    Ok(Command::SScan(SScan {
+        key,
+        cursor,
+        pattern,
+        count,
+    }))
+}
+
+fn parse_zscan(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("ZSCAN", args);
+    let key = args.next_string()?;
+    let cursor = args.next_cursor()?;
+
+    let mut pattern = None;
+    let mut count = None;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "MATCH" => pattern = Some(args.next_string()?),
+            "COUNT" => count = Some(args.next_count()?),
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    Ok(Command::ZScan(ZScan {
+        key,
+        cursor,
+        pattern,
+        count,
+    }))
+}
+
+fn parse_bitcount(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("BITCOUNT", args);
+    let key = args.next_string()?;
+    let range = if args.is_empty() {
+        None
+    } else {
+        let start = args.next_i64()?;
+        if args.is_empty() {
+            return Err(eyre!("syntax error"));
+        }
+        let end = args.next_i64()?;
+        let unit = args.next_bit_unit()?;
+        Some(BitRange { start, end, unit })
+    };
+    args.finish()?;
+    Ok(Command::BitCount(BitCount { key, range }))
+}
+
+fn parse_bitpos(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("BITPOS", args);
+    let key = args.next_string()?;
+    let bit = match args.next_i64()? {
+        0 => false,
+        1 => true,
+        _ => return Err(eyre!("The bit argument must be 1 or 0.")),
+    };
+
+    let mut start = None;
+    let mut end = None;
+    let mut unit = BitUnit::Byte;
+    if !args.is_empty() {
+        start = Some(args.next_i64()?);
+    }
+    if !args.is_empty() {
+        end = Some(args.next_i64()?);
+        unit = args.next_bit_unit()?;
+    }
+    args.finish()?;
+
+    Ok(Command::BitPos(BitPos {
+        key,
+        bit,
+        start,
+        end,
+        unit,
+    }))
+}
+
+fn parse_xadd(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XADD", args);
+    let key = args.next_string()?;
+    let id = NewId::parse(&args.next_string()?).ok_or_else(invalid_stream_id)?;
+    if id == NewId::Explicit(StreamId::MIN) {
+        return Err(eyre!("The ID specified in XADD must be greater than 0-0"));
+    }
+
+    let mut fields = vec![(args.next_string()?, args.next_string()?)];
+    while !args.is_empty() {
+        fields.push((args.next_string()?, args.next_string()?));
+    }
+    Ok(Command::XAdd(XAdd { key, id, fields }))
+}
+
+fn parse_xrange(cmd_str: &'static str, rev: bool, args: &[Message]) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    let first = args.next_string()?;
+    let second = args.next_string()?;
+    let (start, end) = if rev {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    // An ID without a sequence number covers the whole millisecond.
+    let start = RangeBound::parse(&start, 0).ok_or_else(invalid_stream_id)?;
+    let end = RangeBound::parse(&end, u64::MAX).ok_or_else(invalid_stream_id)?;
+
+    let count = match args.next_option()?.as_deref() {
+        None => None,
+        // Like Redis, negative counts are treated as 0.
+        Some("COUNT") => Some(usize::try_from(args.next_i64()?).unwrap_or(0)),
+        Some(_) => return Err(eyre!("syntax error")),
+    };
+    args.finish()?;
+
+    Ok(Command::XRange(XRange {
+        key,
+        start,
+        end,
+        count,
+        rev,
+    }))
+}
+
+fn parse_xread(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XREAD", args);
+    let mut count = None;
+    let mut block = None;
+    loop {
+        match args.next_option()?.as_deref() {
+            // Like Redis, counts of 0 or less mean no limit.
+            Some("COUNT") => count = usize::try_from(args.next_i64()?).ok().filter(|c| *c > 0),
+            Some("BLOCK") => {
+                let millis = args.next_i64()?;
+                let millis = u64::try_from(millis).map_err(|_| eyre!("timeout is negative"))?;
+                block = Some(Duration::from_millis(millis));
+            }
+            Some("STREAMS") => break,
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    let mut rest = Vec::new();
+    while !args.is_empty() {
+        rest.push(args.next_string()?);
+    }
+    if rest.is_empty() || rest.len() % 2 != 0 {
+        return Err(eyre!(
+            "Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+        ));
+    }
+    let ids = rest.split_off(rest.len() / 2);
+    let ids = ids
+        .iter()
+        .map(|id| ReadId::parse(id).ok_or_else(invalid_stream_id))
+        .collect::<Result<_>>()?;
+
+    Ok(Command::XRead(XRead {
+        count,
+        block,
+        keys: rest,
+        ids,
+    }))
+}
+
+fn parse_xgroup(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XGROUP", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("XGROUP"))?;
+    if subcommand != "CREATE" {
+        return Err(eyre!("unknown subcommand '{subcommand}'"));
+    }
+    let key = args.next_string()?;
+    let group = args.next_string()?;
+    let id = ReadId::parse(&args.next_string()?).ok_or_else(invalid_stream_id)?;
+    let mkstream = match args.next_option()?.as_deref() {
+        None => false,
+        Some("MKSTREAM") => true,
+        Some(_) => return Err(eyre!("syntax error")),
+    };
+    args.finish()?;
+    Ok(Command::XGroup(XGroup::Create {
+        key,
+        group,
+        id,
+        mkstream,
+    }))
+}
+
+fn parse_xreadgroup(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XREADGROUP", args);
+    if args.next_option()?.as_deref() != Some("GROUP") {
+        return Err(eyre!("syntax error"));
+    }
+    let group = args.next_string()?;
+    let consumer = args.next_string()?;
+    let mut count = None;
+    let mut noack = false;
+    loop {
+        match args.next_option()?.as_deref() {
+            // Like Redis, counts of 0 or less mean no limit.
+            Some("COUNT") => count = usize::try_from(args.next_i64()?).ok().filter(|c| *c > 0),
+            Some("NOACK") => noack = true,
+            Some("STREAMS") => break,
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    let mut rest = Vec::new();
+    while !args.is_empty() {
+        rest.push(args.next_string()?);
+    }
+    if rest.is_empty() || rest.len() % 2 != 0 {
+        return Err(eyre!(
+            "Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified."
+        ));
+    }
+    let ids = rest.split_off(rest.len() / 2);
+    let ids = ids
+        .iter()
+        .map(|id| GroupReadId::parse(id).ok_or_else(invalid_stream_id))
+        .collect::<Result<_>>()?;
+
+    Ok(Command::XReadGroup(XReadGroup {
+        group,
+        consumer,
+        count,
+        noack,
+        keys: rest,
+        ids,
+    }))
+}
+
+fn parse_xack(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XACK", args);
+    let key = args.next_string()?;
+    let group = args.next_string()?;
+    let mut ids = vec![args.next_stream_id()?];
+    while !args.is_empty() {
+        ids.push(args.next_stream_id()?);
+    }
+    Ok(Command::XAck(XAck { key, group, ids }))
+}
+
+fn parse_xpending(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XPENDING", args);
+    let key = args.next_string()?;
+    let group = args.next_string()?;
+    if args.is_empty() {
+        return Ok(Command::XPending(XPending {
+            key,
+            group,
+            range: None,
+        }));
+    }
+
+    let min_idle = if args.peek_option().as_deref() == Some("IDLE") {
+        args.next_option()?;
+        Some(args.next_i64()?)
+    } else {
+        None
+    };
+    let start = RangeBound::parse(&args.next_string()?, 0).ok_or_else(invalid_stream_id)?;
+    let end = RangeBound::parse(&args.next_string()?, u64::MAX).ok_or_else(invalid_stream_id)?;
+    // Like Redis, negative counts are treated as 0.
+    let count = usize::try_from(args.next_i64()?).unwrap_or(0);
+    let consumer = if args.is_empty() {
+        None
+    } else {
+        Some(args.next_string()?)
+    };
+    args.finish()?;
+
+    Ok(Command::XPending(XPending {
+        key,
+        group,
+        range: Some(PendingRange {
+            min_idle,
+            start,
+            end,
+            count,
+            consumer,
+        }),
+    }))
+}
+
+fn parse_xclaim(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XCLAIM", args);
+    let key = args.next_string()?;
+    let group = args.next_string()?;
+    let consumer = args.next_string()?;
+    let min_idle = args.next_min_idle()?;
+
+    // IDs continue until the first option.
+    let mut ids = vec![args.next_stream_id()?];
+    while !args.is_empty() && !args.peek_option().is_some_and(|o| is_xclaim_option(&o)) {
+        ids.push(args.next_stream_id()?);
+    }
+
+    let mut xclaim = XClaim {
+        key,
+        group,
+        consumer,
+        min_idle,
+        ids,
+        idle: None,
+        time: None,
+        retry_count: None,
+        force: false,
+        justid: false,
+    };
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "IDLE" => xclaim.idle = Some(args.next_i64()?),
+            "TIME" => xclaim.time = Some(args.next_i64()?),
+            "RETRYCOUNT" => {
+                let retry_count = args.next_i64()?;
+                let retry_count = u64::try_from(retry_count)
+                    .map_err(|_| eyre!("Invalid RETRYCOUNT option argument for XCLAIM"))?;
+                xclaim.retry_count = Some(retry_count);
+            }
+            "FORCE" => xclaim.force = true,
+            "JUSTID" => xclaim.justid = true,
+            _ => return Err(eyre!("Unrecognized XCLAIM option '{option}'")),
+        }
+    }
+    Ok(Command::XClaim(xclaim))
+}
+
+fn is_xclaim_option(option: &str) -> bool {
+    matches!(option, "IDLE" | "TIME" | "RETRYCOUNT" | "FORCE" | "JUSTID")
+}
+
+fn parse_xautoclaim(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XAUTOCLAIM", args);
+    let key = args.next_string()?;
+    let group = args.next_string()?;
+    let consumer = args.next_string()?;
+    let min_idle = args.next_min_idle()?;
+    let start = args.next_stream_id()?;
+
+    let mut count = None;
+    let mut justid = false;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "COUNT" => {
+                let n = args.next_i64()?;
+                match usize::try_from(n) {
+                    Ok(n) if n > 0 => count = Some(n),
+                    _ => return Err(eyre!("COUNT must be > 0")),
+                }
+            }
+            "JUSTID" => justid = true,
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+    Ok(Command::XAutoClaim(XAutoClaim {
+        key,
+        group,
+        consumer,
+        min_idle,
+        start,
+        count,
+        justid,
+    }))
+}
+
+fn parse_xtrim(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XTRIM", args);
+    let key = args.next_string()?;
+    let strategy = args.next_option()?;
+    let approx = match args.peek_option().as_deref() {
+        Some(op @ ("~" | "=")) => {
+            let approx = op == "~";
+            args.next_option()?;
+            approx
+        }
+        _ => false,
+    };
+    let strategy = match strategy.as_deref() {
+        Some("MAXLEN") => {
+            let max_len = args.next_i64()?;
+            let max_len =
+                usize::try_from(max_len).map_err(|_| eyre!("The MAXLEN argument must be >= 0."))?;
+            TrimStrategy::MaxLen(max_len)
+        }
+        Some("MINID") => TrimStrategy::MinId(args.next_stream_id()?),
+        _ => return Err(eyre!("syntax error")),
+    };
+
+    let limit = match args.next_option()?.as_deref() {
+        None => None,
+        Some("LIMIT") => {
+            let limit = args.next_i64()?;
+            let limit =
+                usize::try_from(limit).map_err(|_| eyre!("The LIMIT argument must be >= 0."))?;
+            if !approx {
+                return Err(eyre!(
+                    "syntax error, LIMIT cannot be used without the special ~ option"
+                ));
+            }
+            Some(limit)
+        }
+        Some(_) => return Err(eyre!("syntax error")),
+    };
+    args.finish()?;
+
+    Ok(Command::XTrim(XTrim {
+        key,
+        trim: Trim {
+            strategy,
+            approx,
+            limit,
+        },
+    }))
+}
+
+fn parse_xdel(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XDEL", args);
+    let key = args.next_string()?;
+    let mut ids = vec![args.next_stream_id()?];
+    while !args.is_empty() {
+        ids.push(args.next_stream_id()?);
+    }
+    Ok(Command::XDel(XDel { key, ids }))
+}
+
+fn parse_client(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("CLIENT", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("CLIENT"))?;
+    match subcommand.as_str() {
+        "TRACKING" => {}
+        "PAUSE" => {
+            let pause = parse_client_pause(&mut args)?;
+            args.finish()?;
+            return Ok(Command::Client(Client::Pause(pause)));
+        }
+        "UNPAUSE" => {
+            args.finish()?;
+            return Ok(Command::Client(Client::Unpause));
+        }
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    }
+
+    let enabled = match args.next_option()?.as_deref() {
+        Some("ON") => true,
+        Some("OFF") => false,
+        _ => return Err(eyre!("syntax error")),
+    };
+    let mut redirect = None;
+    let mut bcast = false;
+    let mut prefixes = Vec::new();
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "REDIRECT" => redirect = Some(args.next_i64()?),
+            "BCAST" => bcast = true,
+            "PREFIX" => prefixes.push(args.next_string()?),
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+    if !bcast && !prefixes.is_empty() {
+        return Err(eyre!("PREFIX option requires BCAST mode to be enabled"));
+    }
+
+    // Like Redis, options are ignored when turning tracking off.
+    let mode = if bcast {
+        TrackingMode::Broadcast(prefixes)
+    } else {
+        TrackingMode::Default
+    };
+    let tracking = enabled.then_some(ClientTracking { mode, redirect });
+    Ok(Command::Client(Client::Tracking(tracking)))
+}
+
+fn parse_client_pause(args: &mut Args) -> Result<ClientPause> {
+    let millis = args
+        .next_i64()
+        .map_err(|_| eyre!("timeout is not an integer or out of range"))?;
+    let millis = u64::try_from(millis).map_err(|_| eyre!("timeout is negative"))?;
+    let mode = match args.next_option()?.as_deref() {
+        None | Some("ALL") => PauseMode::All,
+        Some("WRITE") => PauseMode::Write,
+        Some(_) => return Err(eyre!("syntax error")),
+    };
+    Ok(ClientPause {
+        timeout: Duration::from_millis(millis),
+        mode,
+    })
+}
+
+fn parse_replconf(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("REPLCONF", args);
+    let mut options = Vec::new();
+    while let Some(name) = args.next_option()? {
+        if name == "ACK" && options.is_empty() {
+            let offset = u64::try_from(args.next_i64()?)
+                .map_err(|_| eyre!("value is out of range, must be positive"))?;
+            args.finish()?;
+            return Ok(Command::ReplConf(ReplConf::Ack(offset)));
+        }
+        if name == "GETACK" && options.is_empty() {
+            args.next_string()?;
+            args.finish()?;
+            return Ok(Command::ReplConf(ReplConf::GetAck));
+        }
+        options.push((name.to_lowercase(), args.next_string()?));
+    }
+    if options.is_empty() {
+        return Err(wrong_number_of_arguments("REPLCONF"));
+    }
+    Ok(Command::ReplConf(ReplConf::Options(options)))
+}
+
+fn parse_replicaof(cmd_str: &'static str, args: &[Message]) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let host = String::try_from(args.next_string()?).wrap_err("host must be valid UTF-8")?;
+    let port = args.next_string()?;
+    args.finish()?;
+    if host.eq_ignore_ascii_case("NO") && port.as_bytes().eq_ignore_ascii_case(b"ONE") {
+        return Ok(Command::ReplicaOf(ReplicaOf { master: None }));
+    }
+    let port = String::from_utf8_lossy(port.as_bytes())
+        .parse()
+        .map_err(|_| eyre!("Invalid master port"))?;
+    Ok(Command::ReplicaOf(ReplicaOf {
+        master: Some((host, port)),
+    }))
+}
+
+fn parse_xinfo(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("XINFO", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("XINFO"))?;
+    let key = args.next_string()?;
+    let xinfo = match subcommand.as_str() {
+        "STREAM" => XInfo::Stream { key },
+        "GROUPS" => XInfo::Groups { key },
+        "CONSUMERS" => XInfo::Consumers {
+            key,
+            group: args.next_string()?,
+        },
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    args.finish()?;
+    Ok(Command::XInfo(xinfo))
+}
+
+fn parse_geoadd(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("GEOADD", args);
+    let key = args.next_string()?;
+
+    let (mut nx, mut xx, mut ch) = (false, false, false);
+    while let Some(option) = args.peek_option() {
+        match option.as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "CH" => ch = true,
+            _ => break,
+        }
+        args.next_string()?;
+    }
+    if nx && xx {
+        return Err(eyre!(
+            "XX and NX options at the same time are not compatible"
+        ));
+    }
+
+    if args.is_empty() || !args.rest.len().is_multiple_of(3) {
+        return Err(eyre!("syntax error"));
+    }
+    let mut members = Vec::new();
+    while !args.is_empty() {
+        let coords = args.next_coordinates()?;
+        members.push((coords, args.next_string()?));
+    }
+
+    let existence = if nx {
+        Some(Existence::Nx)
+    } else if xx {
+        Some(Existence::Xx)
+    } else {
+        None
+    };
+    Ok(Command::GeoAdd(GeoAdd {
+        key,
+        existence,
+        ch,
+        members,
+    }))
+}
+
+fn geosearch_to_resp(geosearch: &GeoSearch) -> Vec<Message> {
+    let mut args = with_keys("GEOSEARCH", std::slice::from_ref(&geosearch.key));
+    match &geosearch.from {
+        GeoOrigin::Member(member) => {
+            args.push(Message::bulk_string("FROMMEMBER"));
+            args.push(Message::BulkString(Some(member.clone())));
+        }
+        GeoOrigin::LonLat(coords) => {
+            args.push(Message::bulk_string("FROMLONLAT"));
+            args.push(Message::bulk_string(&coords.longitude.to_string()));
+            args.push(Message::bulk_string(&coords.latitude.to_string()));
+        }
+    }
+    match geosearch.shape {
+        Shape::Radius(radius) => {
+            args.push(Message::bulk_string("BYRADIUS"));
+            args.push(Message::bulk_string(&radius.to_string()));
+        }
+        Shape::Box { width, height } => {
+            args.push(Message::bulk_string("BYBOX"));
+            args.push(Message::bulk_string(&width.to_string()));
+            args.push(Message::bulk_string(&height.to_string()));
+        }
+    }
+    args.push(Message::bulk_string(geosearch.unit.name()));
+    match geosearch.order {
+        Some(SortOrder::Asc) => args.push(Message::bulk_string("ASC")),
+        Some(SortOrder::Desc) => args.push(Message::bulk_string("DESC")),
+        None => {}
+    }
+    if let Some(count) = geosearch.count {
+        args.push(Message::bulk_string("COUNT"));
+        args.push(Message::bulk_string(&count.to_string()));
+        if geosearch.any {
+            args.push(Message::bulk_string("ANY"));
+        }
+    }
+    if geosearch.with_coord {
+        args.push(Message::bulk_string("WITHCOORD"));
+    }
+    if geosearch.with_dist {
+        args.push(Message::bulk_string("WITHDIST"));
+    }
+    if geosearch.with_hash {
+        args.push(Message::bulk_string("WITHHASH"));
+    }
+    args
+}
+
+fn parse_geosearch(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("GEOSEARCH", args);
+    let key = args.next_string()?;
+
+    let mut from = None;
+    let mut by = None;
+    let mut order = None;
+    let mut count = None;
+    let mut any = false;
+    let (mut with_coord, mut with_dist, mut with_hash) = (false, false, false);
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "FROMMEMBER" if from.is_none() => from = Some(GeoOrigin::Member(args.next_string()?)),
+            "FROMLONLAT" if from.is_none() => {
+                from = Some(GeoOrigin::LonLat(args.next_coordinates()?));
+            }
+            "FROMMEMBER" | "FROMLONLAT" => {
+                return Err(eyre!(
+                    "exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+                ))
+            }
+            "BYRADIUS" if by.is_none() => {
+                let radius = args.next_score()?.value();
+                if radius < 0.0 {
+                    return Err(eyre!("radius cannot be negative"));
+                }
+                by = Some((Shape::Radius(radius), args.next_distance_unit()?));
+            }
+            "BYBOX" if by.is_none() => {
+                let width = args.next_score()?.value();
+                let height = args.next_score()?.value();
+                if width < 0.0 || height < 0.0 {
+                    return Err(eyre!("height or width cannot be negative"));
+                }
+                by = Some((Shape::Box { width, height }, args.next_distance_unit()?));
+            }
+            "BYRADIUS" | "BYBOX" => {
+                return Err(eyre!(
+                    "exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH"
+                ))
+            }
+            "ASC" => order = Some(SortOrder::Asc),
+            "DESC" => order = Some(SortOrder::Desc),
+            "COUNT" => {
+                let n = args.next_i64()?;
+                match usize::try_from(n) {
+                    Ok(n) if n > 0 => count = Some(n),
+                    _ => return Err(eyre!("COUNT must be > 0")),
+                }
+                if args.peek_option().as_deref() == Some("ANY") {
+                    args.next_string()?;
+                    any = true;
+                }
+            }
+            "WITHCOORD" => with_coord = true,
+            "WITHDIST" => with_dist = true,
+            "WITHHASH" => with_hash = true,
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    let Some(from) = from else {
+        return Err(eyre!(
+            "exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+        ));
+    };
+    let Some((shape, unit)) = by else {
+        return Err(eyre!(
+            "exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH"
+        ));
+    };
+    Ok(Command::GeoSearch(GeoSearch {
+        key,
+        from,
+        shape,
+        unit,
+        order,
+        count,
+        any,
+        with_coord,
+        with_dist,
+        with_hash,
+    }))
+}
+
+fn invalid_stream_id() -> Report {
+    eyre!("Invalid stream ID specified as stream command argument")
+}
+
+fn parse_set_op(
+    cmd_str: &'static str,
+    op: SetOperation,
+    store: bool,
+    args: &[Message],
+) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let store = if store {
+        Some(args.next_string()?)
+    } else {
+        None
+    };
+    let mut keys = vec![args.next_string()?];
+    while !args.is_empty() {
+        keys.push(args.next_string()?);
+    }
+    Ok(Command::SetOp(SetOp { op, keys, store }))
+}
+
+fn parse_flush(cmd_str: &'static str, args: &[Message]) -> Result<Flush> {
+    let mut args = Args::new(cmd_str, args);
+    let mode = match args.next_option()?.as_deref() {
+        None => None,
+        Some("SYNC") => Some(FlushMode::Sync),
+        Some("ASYNC") => Some(FlushMode::Async),
+        Some(_) => return Err(eyre!("syntax error")),
+    };
+    args.finish()?;
+    Ok(Flush { mode })
+}
+
+fn parse_restore(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("RESTORE", args);
+    let key = args.next_string()?;
+    let ttl = args.next_i64()?;
+    let payload = args.next_string()?;
+
+    let mut replace = false;
+    let mut absttl = false;
+    let mut idletime = None;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absttl = true,
+            "IDLETIME" => idletime = Some(args.next_i64()?),
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+
+    Ok(Command::Restore(Restore {
+        key,
+        ttl,
+        payload,
+        replace,
+        absttl,
+        idletime,
+    }))
+}
+
+fn parse_object(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("OBJECT", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("OBJECT"))?;
+    let key = args.next_string()?;
+    args.finish()?;
+
+    let object = match subcommand.as_str() {
+        "ENCODING" => Object::Encoding { key },
+        "REFCOUNT" => Object::RefCount { key },
+        "IDLETIME" => Object::IdleTime { key },
+        "FREQ" => Object::Freq { key },
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Object(object))
+}
+
+fn parse_script(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("SCRIPT", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("SCRIPT"))?;
+    let script = match subcommand.as_str() {
+        "LOAD" => {
+            let script = args.next_string()?;
+            args.finish()?;
+            Script::Load { script }
+        }
+        "EXISTS" => Script::Exists {
+            shas: parse_keys("SCRIPT", args.rest)?,
+        },
+        "FLUSH" => Script::Flush(parse_flush("SCRIPT", args.rest)?),
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Script(script))
+}
+
+/// A script, its SHA-1 or a function name, followed by the keys and arguments
+/// to call it with.
+type ScriptCall = (RedisString, Vec<RedisString>, Vec<RedisString>);
+
+/// Parses the arguments shared by `EVAL`, `EVALSHA`, `FCALL` and their
+/// read-only variants.
+fn parse_script_call(cmd_str: &'static str, args: &[Message]) -> Result<ScriptCall> {
+    let mut args = Args::new(cmd_str, args);
+    let script = args.next_string()?;
+    let num_keys =
+        usize::try_from(args.next_i64()?).map_err(|_| eyre!("Number of keys can't be negative"))?;
+    if num_keys > args.rest.len() {
+        return Err(eyre!("Number of keys can't be greater than number of args"));
+    }
+    let mut keys = Vec::with_capacity(num_keys);
+    for _ in 0..num_keys {
+        keys.push(args.next_string()?);
+    }
+    let args = parse_optional_keys(cmd_str, args.rest)?;
+    Ok((script, keys, args))
+}
+
+fn parse_eval(cmd_str: &'static str, args: &[Message], read_only: bool) -> Result<Command> {
+    let (script, keys, args) = parse_script_call(cmd_str, args)?;
+    Ok(Command::Eval(Eval {
+        script,
+        keys,
+        args,
+        read_only,
+    }))
+}
+
+fn parse_evalsha(cmd_str: &'static str, args: &[Message], read_only: bool) -> Result<Command> {
+    let (sha, keys, args) = parse_script_call(cmd_str, args)?;
+    Ok(Command::EvalSha(EvalSha {
+        sha,
+        keys,
+        args,
+        read_only,
+    }))
+}
+
+fn parse_cluster(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("CLUSTER", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("CLUSTER"))?;
+    let cluster = match subcommand.as_str() {
+        "ADDSLOTS" => {
+            let mut slots = vec![args.next_slot()?];
+            while !args.is_empty() {
+                slots.push(args.next_slot()?);
+            }
+            Cluster::AddSlots { slots }
+        }
+        "SETSLOT" => {
+            let slot = args.next_slot()?;
+            let state = match args.next_option()?.as_deref() {
+                Some("MIGRATING") => SlotState::Migrating(args.next_node_id()?),
+                Some("IMPORTING") => SlotState::Importing(args.next_node_id()?),
+                Some("NODE") => SlotState::Node(args.next_node_id()?),
+                Some("STABLE") => SlotState::Stable,
+                Some(_) => {
+                    return Err(eyre!(
+                        "Invalid CLUSTER SETSLOT action or number of arguments"
+                    ))
+                }
+                None => return Err(wrong_number_of_arguments("CLUSTER")),
+            };
+            args.finish()?;
+            Cluster::SetSlot { slot, state }
+        }
+        "KEYSLOT" => {
+            let key = args.next_string()?;
+            args.finish()?;
+            Cluster::KeySlot { key }
+        }
+        "INFO" => expect_no_args(Cluster::Info, "CLUSTER", args.rest)?,
+        "MYID" => expect_no_args(Cluster::MyId, "CLUSTER", args.rest)?,
+        "NODES" => expect_no_args(Cluster::Nodes, "CLUSTER", args.rest)?,
+        "SLOTS" => expect_no_args(Cluster::Slots, "CLUSTER", args.rest)?,
+        "SHARDS" => expect_no_args(Cluster::Shards, "CLUSTER", args.rest)?,
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Cluster(cluster))
+}
+
+fn parse_command_query(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("COMMAND", args);
+    let Some(subcommand) = args.next_option()? else {
+        return Ok(Command::Command(CommandQuery::All));
+    };
+    let mut names = || -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        while !args.is_empty() {
+            names.push(args.next_utf8()?.to_lowercase());
+        }
+        Ok(names)
+    };
+    let query = match subcommand.as_str() {
+        "COUNT" => expect_no_args(CommandQuery::Count, "COMMAND", args.rest)?,
+        "INFO" => CommandQuery::Info { names: names()? },
+        "DOCS" => CommandQuery::Docs { names: names()? },
+        "GETKEYS" => CommandQuery::GetKeys {
+            args: parse_keys("COMMAND", args.rest)?,
+        },
+        "GETKEYSANDFLAGS" => CommandQuery::GetKeysAndFlags {
+            args: parse_keys("COMMAND", args.rest)?,
+        },
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Command(query))
+}
+
+fn parse_memory(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("MEMORY", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("MEMORY"))?;
+    let memory = match subcommand.as_str() {
+        "USAGE" => {
+            let key = args.next_string()?;
+            let samples = match args.next_option()?.as_deref() {
+                None => None,
+                Some("SAMPLES") => {
+                    let samples = args.next_i64()?;
+                    Some(usize::try_from(samples).map_err(|_| eyre!("syntax error"))?)
+                }
+                Some(_) => return Err(eyre!("syntax error")),
+            };
+            args.finish()?;
+            Memory::Usage { key, samples }
+        }
+        "STATS" => expect_no_args(Memory::Stats, "MEMORY", args.rest)?,
+        "DOCTOR" => expect_no_args(Memory::Doctor, "MEMORY", args.rest)?,
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Memory(memory))
+}
+
+fn parse_latency(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("LATENCY", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("LATENCY"))?;
+    let latency = match subcommand.as_str() {
+        "LATEST" => expect_no_args(Latency::Latest, "LATENCY", args.rest)?,
+        "HISTORY" => {
+            let event = args.next_utf8()?;
+            args.finish()?;
+            Latency::History { event }
+        }
+        "RESET" => {
+            let mut events = Vec::new();
+            while !args.is_empty() {
+                events.push(args.next_utf8()?);
+            }
+            Latency::Reset { events }
+        }
+        "DOCTOR" => expect_no_args(Latency::Doctor, "LATENCY", args.rest)?,
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Latency(latency))
+}
+
+fn parse_debug(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("DEBUG", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("DEBUG"))?;
+    let debug = match subcommand.as_str() {
+        "SLEEP" => {
+            let duration = args.next_timeout()?;
+            args.finish()?;
+            DebugCommand::Sleep { duration }
+        }
+        "OBJECT" => {
+            let key = args.next_string()?;
+            args.finish()?;
+            DebugCommand::Object { key }
+        }
+        // Like Redis, any number but 0 turns it on.
+        "SET-ACTIVE-EXPIRE" => {
+            let enabled = args.next_i64()? != 0;
+            args.finish()?;
+            DebugCommand::SetActiveExpire { enabled }
+        }
+        "STRINGMATCH-LEN" => expect_no_args(DebugCommand::StringMatchLen, "DEBUG", args.rest)?,
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Debug(debug))
+}
+
+fn parse_config(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("CONFIG", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("CONFIG"))?;
+    let config = match subcommand.as_str() {
+        "GET" => {
+            let mut patterns = vec![args.next_utf8()?];
+            while !args.is_empty() {
+                patterns.push(args.next_utf8()?);
+            }
+            Config::Get { patterns }
+        }
+        "SET" => {
+            let mut pairs = vec![(args.next_utf8()?, args.next_utf8()?)];
+            while !args.is_empty() {
+                pairs.push((args.next_utf8()?, args.next_utf8()?));
+            }
+            Config::Set { pairs }
+        }
+        "REWRITE" => expect_no_args(Config::Rewrite, "CONFIG", args.rest)?,
+        "RESETSTAT" => expect_no_args(Config::ResetStat, "CONFIG", args.rest)?,
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Config(config))
+}
+
+fn parse_acl(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("ACL", args);
+    let subcommand = args
+        .next_option()?
+        .ok_or_else(|| wrong_number_of_arguments("ACL"))?;
+    let acl = match subcommand.as_str() {
+        "SETUSER" => {
+            let username = args.next_utf8()?;
+            let mut rules = Vec::new();
+            while !args.is_empty() {
+                rules.push(args.next_utf8()?);
+            }
+            Acl::SetUser { username, rules }
+        }
+        "GETUSER" => {
+            let username = args.next_utf8()?;
+            args.finish()?;
+            Acl::GetUser { username }
+        }
+        "DELUSER" => {
+            let mut usernames = vec![args.next_utf8()?];
+            while !args.is_empty() {
+                usernames.push(args.next_utf8()?);
+            }
+            Acl::DelUser { usernames }
+        }
+        "LIST" => expect_no_args(Acl::List, "ACL", args.rest)?,
+        "WHOAMI" => expect_no_args(Acl::WhoAmI, "ACL", args.rest)?,
+        "CAT" => {
+            let category = if args.is_empty() {
+                None
+            } else {
+                Some(args.next_utf8()?)
+            };
+            args.finish()?;
+            Acl::Cat { category }
+        }
+        "LOG" => {
+            let acl = match args.next_option()? {
+                None => Acl::Log { count: None },
+                Some(option) if option == "RESET" => Acl::LogReset,
+                Some(count) => Acl::Log {
+                    count: Some(
+                        count
+                            .parse()
+                            .map_err(|_| eyre!("value is out of range, must be positive"))?,
+                    ),
+                },
+            };
+            args.finish()?;
+            acl
+        }
+        "LOAD" => expect_no_args(Acl::Load, "ACL", args.rest)?,
+        "SAVE" => expect_no_args(Acl::Save, "ACL", args.rest)?,
+        _ => return Err(eyre!("unknown subcommand '{subcommand}'")),
+    };
+    Ok(Command::Acl(acl))
+}
+
+fn parse_auth(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("AUTH", args);
+    let first = args.next_string()?;
+    let auth = if args.is_empty() {
+        Auth {
+            username: None,
+            password: first,
+        }
+    } else {
+        let password = args.next_string()?;
+        args.finish()?;
+        Auth {
+            username: Some(first),
+            password,
+        }
+    };
+    Ok(Command::Auth(auth))
+}
+
+fn parse_hello(args: &[Message]) -> Result<Command> {
+    let mut args = Args::new("HELLO", args);
+    let mut hello = Hello {
+        protover: None,
+        auth: None,
+    };
+    if args.is_empty() {
+        return Ok(Command::Hello(hello));
+    }
+    hello.protover = Some(
+        args.next_i64()
+            .map_err(|_| eyre!("Protocol version is not an integer or out of range"))?,
+    );
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "AUTH" if args.rest.len() >= 2 => {
+                hello.auth = Some(Auth {
+                    username: Some(args.next_string()?),
+                    password: args.next_string()?,
+                });
+            }
+            _ => return Err(eyre!("Syntax error in HELLO option '{option}'")),
+        }
+    }
+    Ok(Command::Hello(hello))
+}
+
+fn parse_fcall(cmd_str: &'static str, args: &[Message], read_only: bool) -> Result<Command> {
+    let (function, keys, args) = parse_script_call(cmd_str, args)?;
+    Ok(Command::FCall(FCall {
+        function,
+        keys,
+        args,
+        read_only,
+    }))
+}
+
+fn script_call_to_resp(
+    cmd_str: &str,
+    script: &RedisString,
+    keys: &[RedisString],
+    args: &[RedisString],
+) -> Vec<Message> {
+    let mut message = vec![
+        Message::bulk_string(cmd_str),
+        Message::BulkString(Some(script.clone())),
+        Message::bulk_string(&keys.len().to_string()),
+    ];
+    message.extend(
+        keys.iter()
+            .chain(args)
+            .map(|arg| Message::BulkString(Some(arg.clone()))),
+    );
+    message
+}
+
+fn sort_to_resp(cmd_str: &str, sort: &Sort) -> Vec<Message> {
+    let mut args = vec![
+        Message::bulk_string(cmd_str),
+        Message::BulkString(Some(sort.key.clone())),
+    ];
+    if let Some(by) = &sort.by {
+        args.push(Message::bulk_string("BY"));
+        args.push(Message::BulkString(Some(by.clone())));
+    }
+    if let Some(limit) = sort.limit {
+        args.push(Message::bulk_string("LIMIT"));
+        args.push(Message::bulk_string(&limit.offset.to_string()));
+        args.push(Message::bulk_string(&limit.count.to_string()));
+    }
+    for pattern in &sort.get {
+        args.push(Message::bulk_string("GET"));
+        args.push(Message::BulkString(Some(pattern.clone())));
+    }
+    if sort.desc {
+        args.push(Message::bulk_string("DESC"));
+    }
+    if sort.alpha {
+        args.push(Message::bulk_string("ALPHA"));
+    }
+    if let Some(store) = &sort.store {
+        args.push(Message::bulk_string("STORE"));
+        args.push(Message::BulkString(Some(store.clone())));
+    }
+    args
+}
+
+fn parse_sort(cmd_str: &'static str, args: &[Message]) -> Result<Sort> {
+    let mut args = Args::new(cmd_str, args);
+    let mut sort = Sort {
+        key: args.next_string()?,
+        ..Sort::default()
+    };
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "ASC" => sort.desc = false,
+            "DESC" => sort.desc = true,
+            "ALPHA" => sort.alpha = true,
+            "BY" => sort.by = Some(args.next_string()?),
+            "GET" => sort.get.push(args.next_string()?),
+            "LIMIT" => {
+                let offset = args.next_i64()?;
+                let count = args.next_i64()?;
+                sort.limit = Some(Limit { offset, count });
+            }
+            "STORE" if cmd_str == "SORT" => sort.store = Some(args.next_string()?),
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+    Ok(sort)
+}
+
+/// Arguments to `LMPOP` and `BLMPOP` after the command name and timeout.
+fn lmpop_args(lmpop: &LMPop) -> Vec<Message> {
+    let mut args = vec![Message::bulk_string(&lmpop.keys.len().to_string())];
+    args.extend(
+        lmpop
+            .keys
+            .iter()
+            .map(|key| Message::BulkString(Some(key.clone()))),
+    );
+    args.push(Message::bulk_string(lmpop.end.as_str()));
+    if let Some(count) = lmpop.count {
+        args.push(Message::bulk_string("COUNT"));
+        args.push(Message::bulk_string(&count.to_string()));
+    }
+    args
+}
+
+/// Arguments to `LMOVE` and `BLMOVE` after the command name.
+fn lmove_args(lmove: &LMove) -> Vec<Message> {
+    vec![
+        Message::BulkString(Some(lmove.source.clone())),
+        Message::BulkString(Some(lmove.destination.clone())),
+        Message::bulk_string(lmove.from.as_str()),
+        Message::bulk_string(lmove.to.as_str()),
+    ]
+}
+
+fn parse_lmove(args: &mut Args) -> Result<LMove> {
+    Ok(LMove {
+        source: args.next_string()?,
+        destination: args.next_string()?,
+        from: args.next_list_end()?,
+        to: args.next_list_end()?,
+    })
+}
+
+fn parse_lmpop(args: &mut Args) -> Result<LMPop> {
+    let num_keys = args.next_num_keys()?;
+    let keys = (0..num_keys)
+        .map(|_| args.next_string())
+        .collect::<Result<_>>()?;
+    let end = args.next_list_end()?;
+    let mut count = None;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "COUNT" if count.is_none() => {
+                let n = args.next_i64()?;
+                match usize::try_from(n) {
+                    Ok(n) if n > 0 => count = Some(n),
+                    _ => return Err(eyre!("count should be greater than 0")),
+                }
+            }
+            _ => return Err(eyre!("syntax error")),
+        }
+    }
+    Ok(LMPop { keys, end, count })
+}
+
+fn parse_bpop(cmd_str: &'static str, end: ListEnd, args: &[Message]) -> Result<Command> {
+    // The timeout comes after a variable number of keys.
+    let Some((timeout, keys)) = args.split_last() else {
+        return Err(wrong_number_of_arguments(cmd_str));
+    };
+    let keys = parse_keys(cmd_str, keys)?;
+    let mut args = Args::new(cmd_str, std::slice::from_ref(timeout));
+    let timeout = args.next_timeout()?;
+    Ok(Command::BPop(BPop { keys, end, timeout }))
+}
+
+fn parse_list_range(cmd_str: &'static str, args: &[Message]) -> Result<LRange> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    let start = args.next_i64()?;
+    let stop = args.next_i64()?;
+    args.finish()?;
+    Ok(LRange { key, start, stop })
+}
+
+fn parse_push(cmd_str: &'static str, end: ListEnd, args: &[Message]) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    let mut elements = vec![args.next_string()?];
+    while !args.is_empty() {
+        elements.push(args.next_string()?);
+    }
+    Ok(Command::Push(Push { key, end, elements }))
+}
+
+fn parse_pop(cmd_str: &'static str, end: ListEnd, args: &[Message]) -> Result<Command> {
+    let mut args = Args::new(cmd_str, args);
+    let key = args.next_string()?;
+    let count = if args.is_empty() {
+        None
+    } else {
+        let count = args.next_i64()?;
+        let count =
+            usize::try_from(count).map_err(|_| eyre!("value is out of range, must be positive"))?;
+        Some(count)
+    };
+    args.finish()?;
+    Ok(Command::Pop(Pop { key, end, count }))
+}
+
+/// Helper for consuming a command's arguments in order.
+struct Args<'a> {
+    cmd_str: &'static str,
+    rest: &'a [Message],
+}
+
+impl<'a> Args<'a> {
+    const fn new(cmd_str: &'static str, args: &'a [Message]) -> Self {
+        Self {
+            cmd_str,
+            rest: args,
+        }
+    }
+
+    /// Consumes the next argument, which must be a bulk string.
+    fn next_string(&mut self) -> Result<RedisString> {
+        let Some((first, rest)) = self.rest.split_first() else {
+            return Err(wrong_number_of_arguments(self.cmd_str));
+        };
+        let Message::BulkString(Some(s)) = first else {
+            return Err(eyre!("{} arguments must be bulk strings", self.cmd_str));
+        };
+        self.rest = rest;
+        Ok(s.clone())
+    }
+
+    /// Consumes the next argument, which must be valid UTF-8.
+    fn next_utf8(&mut self) -> Result<String> {
+        String::try_from(self.next_string()?)
+            .map_err(|_| eyre!("{} arguments must be valid UTF-8", self.cmd_str))
+    }
+
+    /// Consumes the next argument, which must be a cluster hash slot.
+    fn next_slot(&mut self) -> Result<u16> {
+        self.next_string()?
+            .to_i64()
+            .and_then(|slot| u16::try_from(slot).ok())
+            .filter(|slot| *slot < 16384)
+            .ok_or_else(|| eyre!("Invalid or out of range slot"))
+    }
+
+    /// Consumes the next argument, which must be the name of a cluster node.
+    fn next_node_id(&mut self) -> Result<String> {
+        String::try_from(self.next_string()?).map_err(|_| eyre!("invalid node ID"))
+    }
+
+    /// Consumes the next argument, which must be an integer.
+    fn next_i64(&mut self) -> Result<i64> {
+        let s = self.next_string()?;
+        String::try_from(s)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or_else(|| eyre!("value is not an integer or out of range"))
+    }
+
+    /// Consumes the next argument, which must be the positive number of keys
+    /// that follow it.
+    fn next_num_keys(&mut self) -> Result<usize> {
+        let num_keys = self.next_i64()?;
+        match usize::try_from(num_keys) {
+            Ok(num_keys) if num_keys > 0 => Ok(num_keys),
+            _ => Err(eyre!("numkeys should be greater than 0")),
+        }
+    }
+
+    /// Consumes the next argument, which must be a non-negative timeout in
+    /// seconds for a blocking command. Like Redis, the timeout is rounded up to
+    /// whole milliseconds.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn next_timeout(&mut self) -> Result<Duration> {
+        let s = self.next_string()?;
+        let seconds = String::try_from(s)
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .filter(|seconds| seconds.is_finite())
+            .ok_or_else(|| eyre!("timeout is not a float or out of range"))?;
+        if seconds < 0.0 {
+            return Err(eyre!("timeout is negative"));
+        }
+        Ok(Duration::from_millis((seconds * 1000.0).ceil() as u64))
+    }
+
+    /// Consumes the next argument, which must be `LEFT` or `RIGHT`.
+    fn next_list_end(&mut self) -> Result<ListEnd> {
+        match self.next_option()?.as_deref() {
+            Some("LEFT") => Ok(ListEnd::Left),
+            Some("RIGHT") => Ok(ListEnd::Right),
+            _ => Err(eyre!("syntax error")),
+        }
+    }
+
+    /// Consumes the next argument, which must be a `SCAN`-style cursor.
+    fn next_cursor(&mut self) -> Result<u64> {
+        let s = self.next_string()?;
+        String::try_from(s)
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .ok_or_else(|| eyre!("invalid cursor"))
+    }
+
+    /// Consumes the next argument, which must be a positive `COUNT` value.
+    fn next_count(&mut self) -> Result<usize> {
+        let count = self.next_i64()?;
+        match usize::try_from(count) {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(eyre!("syntax error")),
+        }
+    }
+
+    /// Consumes the next argument, which must be a bit offset for `SETBIT` or
+    /// `GETBIT`.
+    fn next_bit_offset(&mut self) -> Result<u64> {
+        let s = self.next_string()?;
+        String::try_from(s)
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .filter(|offset| *offset <= bitmap::MAX_OFFSET)
+            .ok_or_else(|| eyre!("bit offset is not an integer or out of range"))
+    }
+
+    /// Consumes the next argument if there is one, which must be `BYTE` or
+    /// `BIT`.
+    fn next_bit_unit(&mut self) -> Result<BitUnit> {
+        match self.next_option()?.as_deref() {
+            None | Some("BYTE") => Ok(BitUnit::Byte),
+            Some("BIT") => Ok(BitUnit::Bit),
+            Some(_) => Err(eyre!("syntax error")),
+        }
+    }
+
+    /// Consumes the next argument, which must be a stream entry ID. A missing
+    /// sequence number is treated as 0.
+    fn next_stream_id(&mut self) -> Result<StreamId> {
+        let s = self.next_string()?;
+        StreamId::parse(&s, 0).ok_or_else(invalid_stream_id)
+    }
+
+    /// Consumes the next argument, which must be a minimum idle time in
+    /// milliseconds for `XCLAIM` or `XAUTOCLAIM`. Like Redis, negative times
+    /// are treated as 0.
+    fn next_min_idle(&mut self) -> Result<i64> {
+        let min_idle = self
+            .next_i64()
+            .map_err(|_| eyre!("Invalid min-idle-time argument for XCLAIM"))?;
+        Ok(min_idle.max(0))
+    }
+
+    /// Consumes the next two arguments, which must be a valid longitude and
+    /// latitude.
+    fn next_coordinates(&mut self) -> Result<Coordinates> {
+        let longitude = self.next_score()?.value();
+        let latitude = self.next_score()?.value();
+        Coordinates::new(longitude, latitude)
+            .ok_or_else(|| eyre!("invalid longitude,latitude pair {longitude:.6},{latitude:.6}"))
+    }
+
+    /// Consumes the next argument, which must be a unit for geo distances.
+    fn next_distance_unit(&mut self) -> Result<DistanceUnit> {
+        let s = self.next_string()?;
+        std::str::from_utf8(s.as_bytes())
+            .ok()
+            .and_then(DistanceUnit::parse)
+            .ok_or_else(|| eyre!("unsupported unit provided. please use M, KM, FT, MI"))
+    }
+
+    /// Consumes the next argument, which must be a sorted set score.
+    fn next_score(&mut self) -> Result<Score> {
+        let s = self.next_string()?;
+        Score::parse(&s).ok_or_else(|| eyre!("value is not a valid float"))
+    }
+
+    /// Consumes the next argument, which must be one end of a score range.
+    fn next_score_bound(&mut self) -> Result<ScoreBound> {
+        let s = self.next_string()?;
+        ScoreBound::parse(&s).ok_or_else(|| eyre!("min or max is not a float"))
+    }
+
+    /// Returns the next argument as an upper-cased option name without
+    /// consuming it, for options that are optional and come before other
+    /// arguments.
+    fn peek_option(&self) -> Option<String> {
+        let Some(Message::BulkString(Some(s))) = self.rest.first() else {
+            return None;
+        };
+        let s = std::str::from_utf8(s.as_bytes()).ok()?;
+        Some(s.to_uppercase())
+    }
+
+    /// Consumes the next argument as an upper-cased option name, or returns
+    /// `None` if there are no more arguments.
+    fn next_option(&mut self) -> Result<Option<String>> {
+        if self.rest.is_empty() {
+            return Ok(None);
+        }
+        let s = self.next_string()?;
+        let s = String::try_from(s).wrap_err("option must be valid UTF-8")?;
+        Ok(Some(s.to_uppercase()))
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    /// Ensures there are no arguments left.
+    fn finish(self) -> Result<()> {
+        if !self.rest.is_empty() {
+            return Err(wrong_number_of_arguments(self.cmd_str));
+        }
+        Ok(())
+    }
+}
+
+/// The code at the start of an error reply, which clients use to tell errors
+/// apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The generic code for errors that don't have a more specific one.
+    Err,
+    WrongType,
+    NoAuth,
+    WrongPass,
+    NoProto,
+    NoPerm,
+    BusyKey,
+    BusyGroup,
+    NoGroup,
+    ReadOnly,
+    NoScript,
+    Moved,
+    Ask,
+    ClusterDown,
+    CrossSlot,
+    Oom,
+    Denied,
+}
+
+impl ErrorCode {
+    const ALL: [Self; 17] = [
+        Self::Err,
+        Self::WrongType,
+        Self::NoAuth,
+        Self::WrongPass,
+        Self::NoProto,
+        Self::NoPerm,
+        Self::BusyKey,
+        Self::BusyGroup,
+        Self::NoGroup,
+        Self::ReadOnly,
+        Self::NoScript,
+        Self::Moved,
+        Self::Ask,
+        Self::ClusterDown,
+        Self::CrossSlot,
+        Self::Oom,
+        Self::Denied,
+    ];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Err => "ERR",
+            Self::WrongType => "WRONGTYPE",
+            Self::NoAuth => "NOAUTH",
+            Self::WrongPass => "WRONGPASS",
+            Self::NoProto => "NOPROTO",
+            Self::NoPerm => "NOPERM",
+            Self::BusyKey => "BUSYKEY",
+            Self::BusyGroup => "BUSYGROUP",
+            Self::NoGroup => "NOGROUP",
+            Self::ReadOnly => "READONLY",
+            Self::NoScript => "NOSCRIPT",
+            Self::Moved => "MOVED",
+            Self::Ask => "ASK",
+            Self::ClusterDown => "CLUSTERDOWN",
+            Self::CrossSlot => "CROSSSLOT",
+            Self::Oom => "OOM",
+            Self::Denied => "DENIED",
+        }
+    }
+}
+
+/// An error reply, sent as its code followed by a message, like `ERR syntax
+/// error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorReply {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorReply {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// An error with the generic `ERR` code.
+    pub fn err(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Err, message)
+    }
+
+    pub fn wrong_type() -> Self {
+        Self::new(
+            ErrorCode::WrongType,
+            "Operation against a key holding the wrong kind of value",
+        )
+    }
+
+    /// Parses an error reply. Errors without a known code are treated as
+    /// generic errors, keeping the whole reply as the message.
+    pub fn parse(s: &str) -> Self {
+        s.split_once(' ')
+            .and_then(|(code, message)| {
+                let code = ErrorCode::ALL.into_iter().find(|c| c.as_str() == code)?;
+                Some(Self::new(code, message))
+            })
+            .unwrap_or_else(|| Self::err(s))
+    }
+}
+
+impl fmt::Display for ErrorReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code.as_str(), self.message)
+    }
+}
+
+/// A `CommandResponse` is a valid response to a command from Redis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandResponse {
+    Pong,
+    Ok,
+
+    /// A status reply other than `OK` or `PONG`, like the one `BGSAVE` sends.
+    Status(String),
+    Error(ErrorReply),
+    Integer(i64),
+    BulkString(Option<RedisString>),
+    Array(Vec<Self>),
+    NullArray,
+
+    /// An out-of-band message sent outside the request/response cycle.
+    Push(Vec<Self>),
+}
+
+impl CommandResponse {
+    pub fn to_resp(&self) -> Message {
+        match self {
+            Self::Pong => Message::SimpleString("PONG".to_string()),
+            Self::Ok => Message::SimpleString("OK".to_string()),
+            Self::Status(s) => Message::SimpleString(s.clone()),
+            Self::Error(e) => Message::Error(e.to_string()),
+            Self::Integer(i) => Message::Integer(*i),
+            Self::BulkString(s) => Message::BulkString(s.clone()),
+            Self::Array(responses) => Message::Array(responses.iter().map(Self::to_resp).collect()),
+            Self::NullArray => Message::NullArray,
+            Self::Push(responses) => Message::Push(responses.iter().map(Self::to_resp).collect()),
+        }
+    }
+
+    pub fn parse_resp(resp: Message) -> Result<Self> {
+        match resp {
+            Message::SimpleString(s) => match s.as_str() {
+                "PONG" => Ok(Self::Pong),
+                "OK" => Ok(Self::Ok),
+                _ => Ok(Self::Status(s)),
+            },
+            Message::Error(e) => Ok(Self::Error(ErrorReply::parse(&e))),
+            Message::Integer(i) => Ok(Self::Integer(i)),
+            Message::BulkString(s) => Ok(Self::BulkString(s)),
+            Message::Array(elems) => elems
+                .into_iter()
+                .map(Self::parse_resp)
+                .collect::<Result<_>>()
+                .map(Self::Array),
+            Message::NullArray => Ok(Self::NullArray),
+            Message::Push(elems) => elems
+                .into_iter()
+                .map(Self::parse_resp)
+                .collect::<Result<_>>()
+                .map(Self::Push),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_command_round_trip(cmd: &Command, expected: &[Message]) {
+        let expected = Message::Array(expected.to_vec());
+        let got = cmd.to_resp();
+        assert_eq!(got, expected);
+        let cmd2 = Command::parse_resp(&got).unwrap();
+        assert_eq!(cmd, &cmd2);
+    }
+
+    fn assert_command_response_round_trip(response: &CommandResponse, expected: &Message) {
+        let got = response.to_resp();
+        assert_eq!(&got, expected);
+        let response2 = CommandResponse::parse_resp(got).unwrap();
+        assert_eq!(response, &response2);
+    }
+
+    #[test]
+    fn ping_round_trip() {
+        assert_command_round_trip(&Command::Ping, &[Message::bulk_string("PING")]);
+    }
+
+    #[test]
+    fn get_round_trip() {
+        let cmd = Command::Get(Get {
+            key: RedisString::from("foo"),
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[Message::bulk_string("GET"), Message::bulk_string("foo")],
+        );
+    }
+
+    #[test]
+    fn set_round_trip() {
+        let cmd = Command::Set(Set {
+            key: RedisString::from("foo"),
+            value: RedisString::from("bar"),
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[
+                Message::bulk_string("SET"),
+                Message::bulk_string("foo"),
+                Message::bulk_string("bar"),
+            ],
+        );
+    }
+
+    #[test]
+    fn expire_round_trip() {
+        let cmd = Command::Expire(Expire {
+            key: RedisString::from("foo"),
+            time: Expiration::Milliseconds(1500),
+            existence: Some(Existence::Xx),
+            comparison: Some(Comparison::Gt),
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[
+                Message::bulk_string("PEXPIRE"),
+                Message::bulk_string("foo"),
+                Message::bulk_string("1500"),
+                Message::bulk_string("XX"),
+                Message::bulk_string("GT"),
+            ],
+        );
+    }
+
+    #[test]
+    fn expire_incompatible_flags() {
+        let parse = |flags: &[&str]| {
+            let mut args = vec![
+                Message::bulk_string("EXPIRE"),
+                Message::bulk_string("foo"),
+                Message::bulk_string("10"),
+            ];
+            args.extend(flags.iter().map(|f| Message::bulk_string(f)));
+            Command::parse_resp(&Message::Array(args))
+        };
+
+        assert!(parse(&["NX"]).is_ok());
+        assert!(parse(&["xx", "lt"]).is_ok());
+        assert!(parse(&["NX", "XX"]).is_err());
+        assert!(parse(&["NX", "GT"]).is_err());
+        assert!(parse(&["GT", "LT"]).is_err());
+        assert!(parse(&["BLAH"]).is_err());
+    }
+
+    #[test]
+    fn ttl_round_trip() {
+        let cmd = Command::Ttl(Ttl {
+            key: RedisString::from("foo"),
+            unit: TimeUnit::Milliseconds,
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[Message::bulk_string("PTTL"), Message::bulk_string("foo")],
+        );
+    }
+
+    #[test]
+    fn expire_time_round_trip() {
+        let cmd = Command::ExpireTime(ExpireTime {
+            key: RedisString::from("foo"),
+            unit: TimeUnit::Seconds,
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[
+                Message::bulk_string("EXPIRETIME"),
+                Message::bulk_string("foo"),
+            ],
+        );
+    }
+
+    #[test]
+    fn persist_round_trip() {
+        let cmd = Command::Persist(Persist {
+            key: RedisString::from("foo"),
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[Message::bulk_string("PERSIST"), Message::bulk_string("foo")],
+        );
+    }
+
+    #[test]
+    fn scan_round_trip() {
+        let cmd = Command::Scan(Scan {
+            cursor: 0,
+            pattern: None,
+            count: None,
+            value_type: None,
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[Message::bulk_string("SCAN"), Message::bulk_string("0")],
+        );
+
+        let cmd = Command::Scan(Scan {
+            cursor: 1234,
+            pattern: Some(RedisString::from("user:*")),
+            count: Some(100),
+            value_type: Some(RedisString::from("string")),
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[
+                Message::bulk_string("SCAN"),
+                Message::bulk_string("1234"),
+                Message::bulk_string("MATCH"),
+                Message::bulk_string("user:*"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("100"),
+                Message::bulk_string("TYPE"),
+                Message::bulk_string("string"),
+            ],
+        );
+    }
+
+    #[test]
+    fn flush_round_trip() {
+        assert_command_round_trip(
+            &Command::FlushDb(Flush { mode: None }),
+            &[Message::bulk_string("FLUSHDB")],
+        );
+        assert_command_round_trip(
+            &Command::FlushAll(Flush {
+                mode: Some(FlushMode::Async),
+            }),
+            &[
+                Message::bulk_string("FLUSHALL"),
+                Message::bulk_string("ASYNC"),
+            ],
+        );
+    }
+
+    #[test]
+    fn save_round_trip() {
+        assert_command_round_trip(&Command::Save, &[Message::bulk_string("SAVE")]);
+        assert_command_round_trip(&Command::BgSave, &[Message::bulk_string("BGSAVE")]);
+        assert_command_round_trip(&Command::LastSave, &[Message::bulk_string("LASTSAVE")]);
+        assert_command_round_trip(
+            &Command::BgRewriteAof,
+            &[Message::bulk_string("BGREWRITEAOF")],
+        );
+        assert_command_response_round_trip(
+            &CommandResponse::Status("Background saving started".to_string()),
+            &Message::SimpleString("Background saving started".to_string()),
+        );
+    }
+
+    #[test]
+    fn replication_round_trip() {
+        assert_command_round_trip(
+            &Command::ReplConf(ReplConf::Options(vec![
+                ("listening-port".to_string(), RedisString::from("6380")),
+                ("capa".to_string(), RedisString::from("psync2")),
+            ])),
+            &[
+                Message::bulk_string("REPLCONF"),
+                Message::bulk_string("listening-port"),
+                Message::bulk_string("6380"),
+                Message::bulk_string("capa"),
+                Message::bulk_string("psync2"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::ReplConf(ReplConf::Ack(1234)),
+            &[
+                Message::bulk_string("REPLCONF"),
+                Message::bulk_string("ACK"),
+                Message::bulk_string("1234"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::ReplConf(ReplConf::GetAck),
+            &[
+                Message::bulk_string("REPLCONF"),
+                Message::bulk_string("GETACK"),
+                Message::bulk_string("*"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::ReplicaOf(ReplicaOf {
+                master: Some(("localhost".to_string(), 6379)),
+            }),
+            &[
+                Message::bulk_string("REPLICAOF"),
+                Message::bulk_string("localhost"),
+                Message::bulk_string("6379"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::ReplicaOf(ReplicaOf { master: None }),
+            &[
+                Message::bulk_string("REPLICAOF"),
+                Message::bulk_string("NO"),
+                Message::bulk_string("ONE"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::PSync(PSync {
+                repl_id: RedisString::from("?"),
+                offset: -1,
+            }),
+            &[
+                Message::bulk_string("PSYNC"),
+                Message::bulk_string("?"),
+                Message::bulk_string("-1"),
+            ],
+        );
+
+        assert_command_round_trip(
+            &Command::Wait(Wait {
+                num_replicas: 2,
+                timeout: Duration::from_millis(500),
+            }),
+            &[
+                Message::bulk_string("WAIT"),
+                Message::bulk_string("2"),
+                Message::bulk_string("500"),
+            ],
+        );
+
+        assert_command_round_trip(
+            &Command::Info(Info {
+                sections: vec!["replication".to_string()],
+            }),
+            &[
+                Message::bulk_string("INFO"),
+                Message::bulk_string("replication"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["REPLCONF"]).is_err());
+        assert!(parse(&["REPLCONF", "listening-port"]).is_err());
+        assert!(parse(&["REPLCONF", "ACK", "-1"]).is_err());
+        assert!(parse(&["PSYNC", "?"]).is_err());
+        assert!(parse(&["WAIT", "1", "-1"]).is_err());
+        assert!(parse(&["WAIT", "-1", "0"]).is_err());
+        assert!(parse(&["REPLICAOF", "localhost", "65536"]).is_err());
+        assert_eq!(
+            parse(&["SLAVEOF", "no", "one"]).unwrap(),
+            Command::ReplicaOf(ReplicaOf { master: None })
+        );
+    }
+
+    #[test]
+    fn pubsub_round_trip() {
+        assert_command_round_trip(
+            &Command::Subscribe(Subscribe {
+                channels: vec![RedisString::from("a"), RedisString::from("b")],
+            }),
+            &[
+                Message::bulk_string("SUBSCRIBE"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Unsubscribe(Unsubscribe { channels: vec![] }),
+            &[Message::bulk_string("UNSUBSCRIBE")],
+        );
+        assert_command_round_trip(
+            &Command::PSubscribe(PSubscribe {
+                patterns: vec![RedisString::from("news.*")],
+            }),
+            &[
+                Message::bulk_string("PSUBSCRIBE"),
+                Message::bulk_string("news.*"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::PUnsubscribe(PUnsubscribe {
+                patterns: vec![RedisString::from("news.*")],
+            }),
+            &[
+                Message::bulk_string("PUNSUBSCRIBE"),
+                Message::bulk_string("news.*"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Publish(Publish {
+                channel: RedisString::from("news.tech"),
+                message: RedisString::from("hello"),
+            }),
+            &[
+                Message::bulk_string("PUBLISH"),
+                Message::bulk_string("news.tech"),
+                Message::bulk_string("hello"),
+            ],
+        );
+
+        let message = Message::Array(vec![Message::bulk_string("PSUBSCRIBE")]);
+        assert!(Command::parse_resp(&message).is_err());
+    }
+
+    #[test]
+    fn script_round_trip() {
+        assert_command_round_trip(
+            &Command::Script(Script::Load {
+                script: RedisString::from("return 1"),
+            }),
+            &[
+                Message::bulk_string("SCRIPT"),
+                Message::bulk_string("LOAD"),
+                Message::bulk_string("return 1"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Script(Script::Exists {
+                shas: vec![RedisString::from("a"), RedisString::from("b")],
+            }),
+            &[
+                Message::bulk_string("SCRIPT"),
+                Message::bulk_string("EXISTS"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Script(Script::Flush(Flush {
+                mode: Some(FlushMode::Async),
+            })),
+            &[
+                Message::bulk_string("SCRIPT"),
+                Message::bulk_string("FLUSH"),
+                Message::bulk_string("ASYNC"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::EvalSha(EvalSha {
+                sha: RedisString::from("a"),
+                keys: vec![RedisString::from("key")],
+                args: vec![RedisString::from("arg")],
+                read_only: false,
+            }),
+            &[
+                Message::bulk_string("EVALSHA"),
+                Message::bulk_string("a"),
+                Message::bulk_string("1"),
+                Message::bulk_string("key"),
+                Message::bulk_string("arg"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::EvalSha(EvalSha {
+                sha: RedisString::from("a"),
+                keys: vec![],
+                args: vec![RedisString::from("arg")],
+                read_only: true,
+            }),
+            &[
+                Message::bulk_string("EVALSHA_RO"),
+                Message::bulk_string("a"),
+                Message::bulk_string("0"),
+                Message::bulk_string("arg"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Eval(Eval {
+                script: RedisString::from("return 1"),
+                keys: vec![RedisString::from("key")],
+                args: vec![],
+                read_only: true,
+            }),
+            &[
+                Message::bulk_string("EVAL_RO"),
+                Message::bulk_string("return 1"),
+                Message::bulk_string("1"),
+                Message::bulk_string("key"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::FCall(FCall {
+                function: RedisString::from("incr_by"),
+                keys: vec![RedisString::from("key")],
+                args: vec![RedisString::from("2")],
+                read_only: false,
+            }),
+            &[
+                Message::bulk_string("FCALL"),
+                Message::bulk_string("incr_by"),
+                Message::bulk_string("1"),
+                Message::bulk_string("key"),
+                Message::bulk_string("2"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["SCRIPT", "EXISTS"]).is_err());
+        assert!(parse(&["SCRIPT", "FLUSH", "LATER"]).is_err());
+        assert!(parse(&["EVALSHA", "a", "-1"]).is_err());
+        assert!(parse(&["EVALSHA", "a", "2", "key"]).is_err());
+    }
+
+    #[test]
+    fn cluster_round_trip() {
+        assert_command_round_trip(
+            &Command::Cluster(Cluster::AddSlots {
+                slots: vec![0, 16383],
+            }),
+            &[
+                Message::bulk_string("CLUSTER"),
+                Message::bulk_string("ADDSLOTS"),
+                Message::bulk_string("0"),
+                Message::bulk_string("16383"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Cluster(Cluster::SetSlot {
+                slot: 7,
+                state: SlotState::Migrating("abc".to_string()),
+            }),
+            &[
+                Message::bulk_string("CLUSTER"),
+                Message::bulk_string("SETSLOT"),
+                Message::bulk_string("7"),
+                Message::bulk_string("MIGRATING"),
+                Message::bulk_string("abc"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Cluster(Cluster::SetSlot {
+                slot: 7,
+                state: SlotState::Stable,
+            }),
+            &[
+                Message::bulk_string("CLUSTER"),
+                Message::bulk_string("SETSLOT"),
+                Message::bulk_string("7"),
+                Message::bulk_string("STABLE"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert_command_round_trip(
+            &Command::Cluster(Cluster::KeySlot {
+                key: RedisString::from("{user}:1"),
+            }),
+            &[
+                Message::bulk_string("CLUSTER"),
+                Message::bulk_string("KEYSLOT"),
+                Message::bulk_string("{user}:1"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Cluster(Cluster::Shards),
+            &[
+                Message::bulk_string("CLUSTER"),
+                Message::bulk_string("SHARDS"),
+            ],
+        );
+        assert!(parse(&["CLUSTER", "NODES", "extra"]).is_err());
+        assert!(parse(&["CLUSTER", "ADDSLOTS", "16384"]).is_err());
+        assert!(parse(&["CLUSTER", "ADDSLOTS", "-1"]).is_err());
+        assert!(parse(&["CLUSTER", "SETSLOT", "1", "NODE"]).is_err());
+        assert!(parse(&["CLUSTER", "SETSLOT", "1", "STABLE", "abc"]).is_err());
+
+        assert_command_round_trip(&Command::Asking, &[Message::bulk_string("ASKING")]);
+        assert_command_round_trip(&Command::ReadOnly, &[Message::bulk_string("READONLY")]);
+        assert_command_round_trip(&Command::ReadWrite, &[Message::bulk_string("READWRITE")]);
+        assert!(parse(&["READONLY", "extra"]).is_err());
+    }
+
+    #[test]
+    fn memory_round_trip() {
+        assert_command_round_trip(
+            &Command::Memory(Memory::Usage {
+                key: RedisString::from("foo"),
+                samples: Some(0),
+            }),
+            &[
+                Message::bulk_string("MEMORY"),
+                Message::bulk_string("USAGE"),
+                Message::bulk_string("foo"),
+                Message::bulk_string("SAMPLES"),
+                Message::bulk_string("0"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Memory(Memory::Doctor),
+            &[
+                Message::bulk_string("MEMORY"),
+                Message::bulk_string("DOCTOR"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["MEMORY", "USAGE", "foo", "SAMPLES", "-1"]).is_err());
+        assert!(parse(&["MEMORY", "USAGE", "foo", "SAMPLES"]).is_err());
+    }
+
+    #[test]
+    fn latency_round_trip() {
+        assert_command_round_trip(
+            &Command::Latency(Latency::History {
+                event: "command".to_string(),
+            }),
+            &[
+                Message::bulk_string("LATENCY"),
+                Message::bulk_string("HISTORY"),
+                Message::bulk_string("command"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Latency(Latency::Reset {
+                events: vec!["fork".to_string(), "expire-cycle".to_string()],
+            }),
+            &[
+                Message::bulk_string("LATENCY"),
+                Message::bulk_string("RESET"),
+                Message::bulk_string("fork"),
+                Message::bulk_string("expire-cycle"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Latency(Latency::Latest),
+            &[
+                Message::bulk_string("LATENCY"),
+                Message::bulk_string("LATEST"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["LATENCY"]).is_err());
+        assert!(parse(&["LATENCY", "HISTORY"]).is_err());
+        assert!(parse(&["LATENCY", "DOCTOR", "extra"]).is_err());
+    }
+
+    #[test]
+    fn debug_round_trip() {
+        assert_command_round_trip(
+            &Command::Debug(DebugCommand::Sleep {
+                duration: Duration::from_millis(500),
+            }),
+            &[
+                Message::bulk_string("DEBUG"),
+                Message::bulk_string("SLEEP"),
+                Message::bulk_string("0.5"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Debug(DebugCommand::Object {
+                key: RedisString::from("foo"),
+            }),
+            &[
+                Message::bulk_string("DEBUG"),
+                Message::bulk_string("OBJECT"),
+                Message::bulk_string("foo"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Debug(DebugCommand::SetActiveExpire { enabled: false }),
+            &[
+                Message::bulk_string("DEBUG"),
+                Message::bulk_string("SET-ACTIVE-EXPIRE"),
+                Message::bulk_string("0"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Debug(DebugCommand::StringMatchLen),
+            &[
+                Message::bulk_string("DEBUG"),
+                Message::bulk_string("STRINGMATCH-LEN"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["DEBUG", "SLEEP", "-1"]).is_err());
+        assert!(parse(&["DEBUG", "SET-ACTIVE-EXPIRE", "yes"]).is_err());
+        assert!(parse(&["DEBUG", "SEGFAULT"]).is_err());
+        assert!(parse(&["MEMORY", "STATS", "extra"]).is_err());
+    }
+
+    #[test]
+    fn auth_round_trip() {
+        assert_command_round_trip(
+            &Command::Auth(Auth {
+                username: None,
+                password: RedisString::from("secret"),
+            }),
+            &[Message::bulk_string("AUTH"), Message::bulk_string("secret")],
+        );
+        assert_command_round_trip(
+            &Command::Auth(Auth {
+                username: Some(RedisString::from("default")),
+                password: RedisString::from("secret"),
+            }),
+            &[
+                Message::bulk_string("AUTH"),
+                Message::bulk_string("default"),
+                Message::bulk_string("secret"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Hello(Hello {
+                protover: None,
+                auth: None,
+            }),
+            &[Message::bulk_string("HELLO")],
+        );
+        assert_command_round_trip(
+            &Command::Hello(Hello {
+                protover: Some(2),
+                auth: Some(Auth {
+                    username: Some(RedisString::from("default")),
+                    password: RedisString::from("secret"),
+                }),
+            }),
+            &[
+                Message::bulk_string("HELLO"),
+                Message::bulk_string("2"),
+                Message::bulk_string("AUTH"),
+                Message::bulk_string("default"),
+                Message::bulk_string("secret"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["AUTH"]).is_err());
+        assert!(parse(&["AUTH", "a", "b", "c"]).is_err());
+        assert!(parse(&["HELLO", "two"]).is_err());
+        assert!(parse(&["HELLO", "2", "AUTH", "default"]).is_err());
+        assert!(parse(&["HELLO", "2", "SETNAME", "me"]).is_err());
+
+        // Passwords are kept out of the logs.
+        let auth = parse(&["AUTH", "secret"]).unwrap();
+        assert!(!format!("{auth:?}").contains("secret"));
+    }
+
+    #[test]
+    fn acl_round_trip() {
+        assert_command_round_trip(
+            &Command::Acl(Acl::SetUser {
+                username: "alice".to_string(),
+                rules: vec![
+                    "on".to_string(),
+                    ">secret".to_string(),
+                    "+@read".to_string(),
+                ],
+            }),
+            &[
+                Message::bulk_string("ACL"),
+                Message::bulk_string("SETUSER"),
+                Message::bulk_string("alice"),
+                Message::bulk_string("on"),
+                Message::bulk_string(">secret"),
+                Message::bulk_string("+@read"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Acl(Acl::GetUser {
+                username: "alice".to_string(),
+            }),
+            &[
+                Message::bulk_string("ACL"),
+                Message::bulk_string("GETUSER"),
+                Message::bulk_string("alice"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Acl(Acl::DelUser {
+                usernames: vec!["alice".to_string(), "bob".to_string()],
+            }),
+            &[
+                Message::bulk_string("ACL"),
+                Message::bulk_string("DELUSER"),
+                Message::bulk_string("alice"),
+                Message::bulk_string("bob"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Acl(Acl::Cat {
+                category: Some("read".to_string()),
+            }),
+            &[
+                Message::bulk_string("ACL"),
+                Message::bulk_string("CAT"),
+                Message::bulk_string("read"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Acl(Acl::Log { count: Some(5) }),
+            &[
+                Message::bulk_string("ACL"),
+                Message::bulk_string("LOG"),
+                Message::bulk_string("5"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Acl(Acl::LogReset),
+            &[
+                Message::bulk_string("ACL"),
+                Message::bulk_string("LOG"),
+                Message::bulk_string("RESET"),
+            ],
+        );
+        for (acl, subcommand) in [
+            (Acl::List, "LIST"),
+            (Acl::WhoAmI, "WHOAMI"),
+            (Acl::Load, "LOAD"),
+            (Acl::Save, "SAVE"),
+        ] {
+            assert_command_round_trip(
+                &Command::Acl(acl),
+                &[
+                    Message::bulk_string("ACL"),
+                    Message::bulk_string(subcommand),
+                ],
+            );
+        }
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["ACL"]).is_err());
+        assert!(parse(&["ACL", "SETUSER"]).is_err());
+        assert!(parse(&["ACL", "GETUSER", "a", "b"]).is_err());
+        assert!(parse(&["ACL", "DELUSER"]).is_err());
+        assert!(parse(&["ACL", "LOG", "-1"]).is_err());
+        assert!(parse(&["ACL", "CAT", "read", "write"]).is_err());
+        assert!(parse(&["ACL", "WHOAMI", "extra"]).is_err());
+
+        // Passwords are kept out of the logs.
+        let setuser = parse(&["ACL", "SETUSER", "alice", ">secret"]).unwrap();
+        assert!(!format!("{setuser:?}").contains("secret"));
+    }
+
+    #[test]
+    fn command_query_round_trip() {
+        assert_command_round_trip(
+            &Command::Command(CommandQuery::All),
+            &[Message::bulk_string("COMMAND")],
+        );
+        assert_command_round_trip(
+            &Command::Command(CommandQuery::Count),
+            &[
+                Message::bulk_string("COMMAND"),
+                Message::bulk_string("COUNT"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Command(CommandQuery::Info {
+                names: vec!["get".to_string(), "set".to_string()],
+            }),
+            &[
+                Message::bulk_string("COMMAND"),
+                Message::bulk_string("INFO"),
+                Message::bulk_string("get"),
+                Message::bulk_string("set"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Command(CommandQuery::Docs { names: vec![] }),
+            &[
+                Message::bulk_string("COMMAND"),
+                Message::bulk_string("DOCS"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Command(CommandQuery::GetKeys {
+                args: vec![RedisString::from("GET"), RedisString::from("k")],
+            }),
+            &[
+                Message::bulk_string("COMMAND"),
+                Message::bulk_string("GETKEYS"),
+                Message::bulk_string("GET"),
+                Message::bulk_string("k"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Command(CommandQuery::GetKeysAndFlags {
+                args: vec![RedisString::from("DEL"), RedisString::from("k")],
+            }),
+            &[
+                Message::bulk_string("COMMAND"),
+                Message::bulk_string("GETKEYSANDFLAGS"),
+                Message::bulk_string("DEL"),
+                Message::bulk_string("k"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["COMMAND", "COUNT", "extra"]).is_err());
+        assert!(parse(&["COMMAND", "GETKEYS"]).is_err());
+        assert!(parse(&["COMMAND", "NOPE"]).is_err());
+    }
+
+    #[test]
+    fn config_round_trip() {
+        assert_command_round_trip(
+            &Command::Config(Config::Get {
+                patterns: vec!["maxmemory*".to_string(), "databases".to_string()],
+            }),
+            &[
+                Message::bulk_string("CONFIG"),
+                Message::bulk_string("GET"),
+                Message::bulk_string("maxmemory*"),
+                Message::bulk_string("databases"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Config(Config::Set {
+                pairs: vec![("maxmemory".to_string(), "1mb".to_string())],
+            }),
+            &[
+                Message::bulk_string("CONFIG"),
+                Message::bulk_string("SET"),
+                Message::bulk_string("maxmemory"),
+                Message::bulk_string("1mb"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Config(Config::Rewrite),
+            &[
+                Message::bulk_string("CONFIG"),
+                Message::bulk_string("REWRITE"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Config(Config::ResetStat),
+            &[
+                Message::bulk_string("CONFIG"),
+                Message::bulk_string("RESETSTAT"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["CONFIG", "GET"]).is_err());
+        assert!(parse(&["CONFIG", "SET", "maxmemory"]).is_err());
+        assert!(parse(&["CONFIG", "SET", "maxmemory", "1", "appendonly"]).is_err());
+        assert!(parse(&["CONFIG", "REWRITE", "extra"]).is_err());
+    }
+
+    #[test]
+    fn multi_key_round_trip() {
+        let keys = vec![RedisString::from("foo"), RedisString::from("bar")];
+        let expected = |name| {
+            [
+                Message::bulk_string(name),
+                Message::bulk_string("foo"),
+                Message::bulk_string("bar"),
+            ]
+        };
+        assert_command_round_trip(&Command::Del(Del { keys: keys.clone() }), &expected("DEL"));
+        assert_command_round_trip(
+            &Command::Unlink(Unlink { keys: keys.clone() }),
+            &expected("UNLINK"),
+        );
+        assert_command_round_trip(&Command::Touch(Touch { keys }), &expected("TOUCH"));
+
+        let no_keys = Message::Array(vec![Message::bulk_string("DEL")]);
+        assert!(Command::parse_resp(&no_keys).is_err());
+    }
+
+    #[test]
+    fn dump_restore_round_trip() {
+        assert_command_round_trip(
+            &Command::Dump(Dump {
+                key: RedisString::from("foo"),
+            }),
+            &[Message::bulk_string("DUMP"), Message::bulk_string("foo")],
+        );
+
+        let cmd = Command::Restore(Restore {
+            key: RedisString::from("foo"),
+            ttl: 0,
+            payload: RedisString::from(vec![0, 1, 2]),
+            replace: true,
+            absttl: false,
+            idletime: Some(10),
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[
+                Message::bulk_string("RESTORE"),
+                Message::bulk_string("foo"),
+                Message::bulk_string("0"),
+                Message::BulkString(Some(RedisString::from(vec![0, 1, 2]))),
+                Message::bulk_string("REPLACE"),
+                Message::bulk_string("IDLETIME"),
+                Message::bulk_string("10"),
+            ],
+        );
+    }
+
+    #[test]
+    fn object_round_trip() {
+        assert_command_round_trip(
+            &Command::Object(Object::Encoding {
+                key: RedisString::from("foo"),
+            }),
+            &[
+                Message::bulk_string("OBJECT"),
+                Message::bulk_string("ENCODING"),
+                Message::bulk_string("foo"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Object(Object::IdleTime {
+                key: RedisString::from("foo"),
+            }),
+            &[
+                Message::bulk_string("OBJECT"),
+                Message::bulk_string("IDLETIME"),
+                Message::bulk_string("foo"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Object(Object::Freq {
+                key: RedisString::from("foo"),
+            }),
+            &[
+                Message::bulk_string("OBJECT"),
+                Message::bulk_string("FREQ"),
+                Message::bulk_string("foo"),
+            ],
+        );
+    }
+
+    #[test]
+    fn select_round_trip() {
+        assert_command_round_trip(
+            &Command::Select(Select { index: 3 }),
+            &[Message::bulk_string("SELECT"), Message::bulk_string("3")],
+        );
+    }
+
+    #[test]
+    fn client_tracking_round_trip() {
+        assert_command_round_trip(
+            &Command::Client(Client::Tracking(Some(ClientTracking {
+                mode: TrackingMode::Default,
+                redirect: None,
+            }))),
+            &[
+                Message::bulk_string("CLIENT"),
+                Message::bulk_string("TRACKING"),
+                Message::bulk_string("ON"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Client(Client::Tracking(Some(ClientTracking {
+                mode: TrackingMode::Broadcast(vec![RedisString::from("user:")]),
+                redirect: Some(7),
+            }))),
+            &[
+                Message::bulk_string("CLIENT"),
+                Message::bulk_string("TRACKING"),
+                Message::bulk_string("ON"),
+                Message::bulk_string("REDIRECT"),
+                Message::bulk_string("7"),
+                Message::bulk_string("BCAST"),
+                Message::bulk_string("PREFIX"),
+                Message::bulk_string("user:"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Client(Client::Tracking(None)),
+            &[
+                Message::bulk_string("CLIENT"),
+                Message::bulk_string("TRACKING"),
+                Message::bulk_string("OFF"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["CLIENT", "TRACKING", "ON", "PREFIX", "a"]).is_err());
+        assert!(parse(&["CLIENT", "TRACKING", "MAYBE"]).is_err());
+        assert!(parse(&["CLIENT", "KILL"]).is_err());
+    }
+
+    #[test]
+    fn client_pause_round_trip() {
+        assert_command_round_trip(
+            &Command::Client(Client::Pause(ClientPause {
+                timeout: Duration::from_millis(500),
+                mode: PauseMode::Write,
+            })),
+            &[
+                Message::bulk_string("CLIENT"),
+                Message::bulk_string("PAUSE"),
+                Message::bulk_string("500"),
+                Message::bulk_string("WRITE"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Client(Client::Unpause),
+            &[
+                Message::bulk_string("CLIENT"),
+                Message::bulk_string("UNPAUSE"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert_eq!(
+            parse(&["CLIENT", "PAUSE", "10"]).unwrap(),
+            Command::Client(Client::Pause(ClientPause {
+                timeout: Duration::from_millis(10),
+                mode: PauseMode::All,
+            }))
+        );
+        assert!(parse(&["CLIENT", "PAUSE", "-1"]).is_err());
+        assert!(parse(&["CLIENT", "PAUSE", "10", "READ"]).is_err());
+        assert!(parse(&["CLIENT", "UNPAUSE", "now"]).is_err());
+    }
+
+    #[test]
+    fn move_round_trip() {
+        assert_command_round_trip(
+            &Command::Move(Move {
+                key: RedisString::from("foo"),
+                db: 1,
+            }),
+            &[
+                Message::bulk_string("MOVE"),
+                Message::bulk_string("foo"),
+                Message::bulk_string("1"),
+            ],
+        );
+    }
+
+    #[test]
+    fn sort_round_trip() {
+        let sort = Sort {
+            key: RedisString::from("list"),
+            ..Sort::default()
+        };
+        assert_command_round_trip(
+            &Command::SortRo(sort),
+            &[
+                Message::bulk_string("SORT_RO"),
+                Message::bulk_string("list"),
+            ],
+        );
+
+        let sort = Sort {
+            key: RedisString::from("list"),
+            by: Some(RedisString::from("weight_*")),
+            limit: Some(Limit {
+                offset: 0,
+                count: 10,
+            }),
+            get: vec![RedisString::from("#"), RedisString::from("obj_*->name")],
+            desc: true,
+            alpha: true,
+            store: Some(RedisString::from("dest")),
+        };
+        assert_command_round_trip(
+            &Command::Sort(sort),
+            &[
+                Message::bulk_string("SORT"),
+                Message::bulk_string("list"),
+                Message::bulk_string("BY"),
+                Message::bulk_string("weight_*"),
+                Message::bulk_string("LIMIT"),
+                Message::bulk_string("0"),
+                Message::bulk_string("10"),
+                Message::bulk_string("GET"),
+                Message::bulk_string("#"),
+                Message::bulk_string("GET"),
+                Message::bulk_string("obj_*->name"),
+                Message::bulk_string("DESC"),
+                Message::bulk_string("ALPHA"),
+                Message::bulk_string("STORE"),
+                Message::bulk_string("dest"),
+            ],
+        );
+
+        let store_ro = Message::Array(vec![
+            Message::bulk_string("SORT_RO"),
+            Message::bulk_string("list"),
+            Message::bulk_string("STORE"),
+            Message::bulk_string("dest"),
+        ]);
+        assert!(Command::parse_resp(&store_ro).is_err());
+    }
+
+    #[test]
+    fn list_round_trip() {
+        assert_command_round_trip(
+            &Command::Push(Push {
+                key: RedisString::from("list"),
+                end: ListEnd::Right,
+                elements: vec![RedisString::from("a"), RedisString::from("b")],
+            }),
+            &[
+                Message::bulk_string("RPUSH"),
+                Message::bulk_string("list"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::Pop(Pop {
+                key: RedisString::from("list"),
+                end: ListEnd::Left,
+                count: None,
+            }),
+            &[Message::bulk_string("LPOP"), Message::bulk_string("list")],
+        );
+        assert_command_round_trip(
+            &Command::Pop(Pop {
+                key: RedisString::from("list"),
+                end: ListEnd::Right,
+                count: Some(2),
+            }),
+            &[
+                Message::bulk_string("RPOP"),
+                Message::bulk_string("list"),
+                Message::bulk_string("2"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::LLen(LLen {
+                key: RedisString::from("list"),
+            }),
+            &[Message::bulk_string("LLEN"), Message::bulk_string("list")],
+        );
+
+        let no_elements = Message::Array(vec![
+            Message::bulk_string("LPUSH"),
+            Message::bulk_string("list"),
+        ]);
+        assert!(Command::parse_resp(&no_elements).is_err());
+    }
+
+    #[test]
+    fn list_index_round_trip() {
+        assert_command_round_trip(
+            &Command::LRange(LRange {
+                key: RedisString::from("list"),
+                start: 0,
+                stop: -1,
+            }),
+            &[
+                Message::bulk_string("LRANGE"),
+                Message::bulk_string("list"),
+                Message::bulk_string("0"),
+                Message::bulk_string("-1"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::LIndex(LIndex {
+                key: RedisString::from("list"),
+                index: -2,
+            }),
+            &[
+                Message::bulk_string("LINDEX"),
+                Message::bulk_string("list"),
+                Message::bulk_string("-2"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::LSet(LSet {
+                key: RedisString::from("list"),
+                index: 3,
+                element: RedisString::from("a"),
+            }),
+            &[
+                Message::bulk_string("LSET"),
+                Message::bulk_string("list"),
+                Message::bulk_string("3"),
+                Message::bulk_string("a"),
+            ],
+        );
+    }
+
+    #[test]
+    fn list_edit_round_trip() {
+        assert_command_round_trip(
+            &Command::LInsert(LInsert {
+                key: RedisString::from("list"),
+                position: InsertPosition::After,
+                pivot: RedisString::from("a"),
+                element: RedisString::from("b"),
+            }),
+            &[
+                Message::bulk_string("LINSERT"),
+                Message::bulk_string("list"),
+                Message::bulk_string("AFTER"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::LRem(LRem {
+                key: RedisString::from("list"),
+                count: -2,
+                element: RedisString::from("a"),
+            }),
+            &[
+                Message::bulk_string("LREM"),
+                Message::bulk_string("list"),
+                Message::bulk_string("-2"),
+                Message::bulk_string("a"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::LTrim(LRange {
+                key: RedisString::from("list"),
+                start: 1,
+                stop: -1,
+            }),
+            &[
+                Message::bulk_string("LTRIM"),
+                Message::bulk_string("list"),
+                Message::bulk_string("1"),
+                Message::bulk_string("-1"),
+            ],
+        );
+    }
+
+    #[test]
+    fn lmpop_round_trip() {
+        assert_command_round_trip(
+            &Command::LMPop(LMPop {
+                keys: vec![RedisString::from("a"), RedisString::from("b")],
+                end: ListEnd::Left,
+                count: Some(3),
+            }),
+            &[
+                Message::bulk_string("LMPOP"),
+                Message::bulk_string("2"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+                Message::bulk_string("LEFT"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("3"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["LMPOP", "0", "RIGHT"]).is_err());
+        assert!(parse(&["LMPOP", "2", "a", "RIGHT"]).is_err());
+        assert!(parse(&["LMPOP", "1", "a", "RIGHT", "COUNT", "0"]).is_err());
+    }
+
+    #[test]
+    fn bpop_round_trip() {
+        assert_command_round_trip(
+            &Command::BPop(BPop {
+                keys: vec![RedisString::from("a"), RedisString::from("b")],
+                end: ListEnd::Right,
+                timeout: Duration::from_millis(1500),
+            }),
+            &[
+                Message::bulk_string("BRPOP"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+                Message::bulk_string("1.5"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::BPop(BPop {
+                keys: vec![RedisString::from("a")],
+                end: ListEnd::Left,
+                timeout: Duration::ZERO,
+            }),
+            &[
+                Message::bulk_string("BLPOP"),
+                Message::bulk_string("a"),
+                Message::bulk_string("0"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["BLPOP", "a"]).is_err());
+        assert!(parse(&["BLPOP", "a", "-1"]).is_err());
+        assert!(parse(&["BLPOP", "a", "soon"]).is_err());
+    }
+
+    #[test]
+    fn lmove_round_trip() {
+        let lmove = LMove {
+            source: RedisString::from("a"),
+            destination: RedisString::from("b"),
+            from: ListEnd::Right,
+            to: ListEnd::Left,
+        };
+        assert_command_round_trip(
+            &Command::LMove(lmove.clone()),
+            &[
+                Message::bulk_string("LMOVE"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+                Message::bulk_string("RIGHT"),
+                Message::bulk_string("LEFT"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::BLMove(BLMove {
+                lmove,
+                timeout: Duration::from_millis(100),
+            }),
+            &[
+                Message::bulk_string("BLMOVE"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+                Message::bulk_string("RIGHT"),
+                Message::bulk_string("LEFT"),
+                Message::bulk_string("0.1"),
+            ],
+        );
+    }
+
+    #[test]
+    fn blmpop_round_trip() {
+        assert_command_round_trip(
+            &Command::BLMPop(BLMPop {
+                lmpop: LMPop {
+                    keys: vec![RedisString::from("a")],
+                    end: ListEnd::Right,
+                    count: None,
+                },
+                timeout: Duration::from_secs(2),
+            }),
+            &[
+                Message::bulk_string("BLMPOP"),
+                Message::bulk_string("2"),
+                Message::bulk_string("1"),
+                Message::bulk_string("a"),
+                Message::bulk_string("RIGHT"),
+            ],
+        );
+    }
+
+    #[test]
+    fn hash_round_trip() {
+        assert_command_round_trip(
+            &Command::HSet(HSet {
+                key: RedisString::from("hash"),
+                pairs: vec![
+                    (RedisString::from("a"), RedisString::from("1")),
+                    (RedisString::from("b"), RedisString::from("2")),
+                ],
+            }),
+            &[
+                Message::bulk_string("HSET"),
+                Message::bulk_string("hash"),
+                Message::bulk_string("a"),
+                Message::bulk_string("1"),
+                Message::bulk_string("b"),
+                Message::bulk_string("2"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::HGet(HGet {
+                key: RedisString::from("hash"),
+                field: RedisString::from("a"),
+            }),
+            &[
+                Message::bulk_string("HGET"),
+                Message::bulk_string("hash"),
+                Message::bulk_string("a"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::HDel(HDel {
+                key: RedisString::from("hash"),
+                fields: vec![RedisString::from("a"), RedisString::from("b")],
+            }),
+            &[
+                Message::bulk_string("HDEL"),
+                Message::bulk_string("hash"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::HGetAll(HGetAll {
+                key: RedisString::from("hash"),
+            }),
+            &[
+                Message::bulk_string("HGETALL"),
+                Message::bulk_string("hash"),
+            ],
+        );
+
+        let odd_pairs = Message::Array(vec![
+            Message::bulk_string("HSET"),
+            Message::bulk_string("hash"),
+            Message::bulk_string("a"),
+            Message::bulk_string("1"),
+            Message::bulk_string("b"),
+        ]);
+        assert!(Command::parse_resp(&odd_pairs).is_err());
+    }
+
+    #[test]
+    fn hash_field_round_trip() {
+        let key = || RedisString::from("hash");
+        let field = || RedisString::from("f");
+        let expected = |name: &str, rest: &[&str]| {
+            let mut args = vec![Message::bulk_string(name), Message::bulk_string("hash")];
+            args.extend(rest.iter().map(|a| Message::bulk_string(a)));
+            args
+        };
+        assert_command_round_trip(
+            &Command::HMGet(HMGet {
+                key: key(),
+                fields: vec![field(), RedisString::from("g")],
+            }),
+            &expected("HMGET", &["f", "g"]),
+        );
+        assert_command_round_trip(
+            &Command::HKeys(HKeys { key: key() }),
+            &expected("HKEYS", &[]),
+        );
+        assert_command_round_trip(
+            &Command::HVals(HVals { key: key() }),
+            &expected("HVALS", &[]),
+        );
+        assert_command_round_trip(&Command::HLen(HLen { key: key() }), &expected("HLEN", &[]));
+        assert_command_round_trip(
+            &Command::HExists(HExists {
+                key: key(),
+                field: field(),
+            }),
+            &expected("HEXISTS", &["f"]),
+        );
+        assert_command_round_trip(
+            &Command::HSetNx(HSetNx {
+                key: key(),
+                field: field(),
+                value: RedisString::from("v"),
+            }),
+            &expected("HSETNX", &["f", "v"]),
+        );
+        assert_command_round_trip(
+            &Command::HStrLen(HStrLen {
+                key: key(),
+                field: field(),
+            }),
+            &expected("HSTRLEN", &["f"]),
+        );
+    }
+
+    #[test]
+    fn hscan_round_trip() {
+        let cmd = Command::HScan(HScan {
+            key: RedisString::from("hash"),
+            cursor: 12,
+            pattern: Some(RedisString::from("f*")),
+            count: Some(5),
+            novalues: true,
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[
+                Message::bulk_string("HSCAN"),
+                Message::bulk_string("hash"),
+                Message::bulk_string("12"),
+                Message::bulk_string("MATCH"),
+                Message::bulk_string("f*"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("5"),
+                Message::bulk_string("NOVALUES"),
+            ],
+        );
+    }
+
+    #[test]
+    fn set_commands_round_trip() {
+        let key = || RedisString::from("set");
+        let members = || vec![RedisString::from("a"), RedisString::from("b")];
+        let expected = |name: &str, rest: &[&str]| {
+            let mut args = vec![Message::bulk_string(name), Message::bulk_string("set")];
+            args.extend(rest.iter().map(|a| Message::bulk_string(a)));
+            args
+        };
+        assert_command_round_trip(
+            &Command::SAdd(SAdd {
+                key: key(),
+                members: members(),
+            }),
+            &expected("SADD", &["a", "b"]),
+        );
+        assert_command_round_trip(
+            &Command::SRem(SRem {
+                key: key(),
+                members: members(),
+            }),
+            &expected("SREM", &["a", "b"]),
+        );
+        assert_command_round_trip(
+            &Command::SMembers(SMembers { key: key() }),
+            &expected("SMEMBERS", &[]),
+        );
+        assert_command_round_trip(
+            &Command::SIsMember(SIsMember {
+                key: key(),
+                member: RedisString::from("a"),
+            }),
+            &expected("SISMEMBER", &["a"]),
+        );
+        assert_command_round_trip(
+            &Command::SCard(SCard { key: key() }),
+            &expected("SCARD", &[]),
+        );
+    }
+
+    #[test]
+    fn set_op_round_trip() {
+        let keys = || vec![RedisString::from("a"), RedisString::from("b")];
+        assert_command_round_trip(
+            &Command::SetOp(SetOp {
+                op: SetOperation::Inter,
+                keys: keys(),
+                store: None,
+            }),
+            &[
+                Message::bulk_string("SINTER"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::SetOp(SetOp {
+                op: SetOperation::Diff,
+                keys: keys(),
+                store: Some(RedisString::from("dest")),
+            }),
+            &[
+                Message::bulk_string("SDIFFSTORE"),
+                Message::bulk_string("dest"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+            ],
+        );
+
+        let no_keys = Message::Array(vec![
+            Message::bulk_string("SUNIONSTORE"),
+            Message::bulk_string("dest"),
+        ]);
+        assert!(Command::parse_resp(&no_keys).is_err());
+    }
+
+    #[test]
+    fn smismember_and_sintercard_round_trip() {
+        assert_command_round_trip(
+            &Command::SMIsMember(SMIsMember {
+                key: RedisString::from("set"),
+                members: vec![RedisString::from("a"), RedisString::from("b")],
+            }),
+            &[
+                Message::bulk_string("SMISMEMBER"),
+                Message::bulk_string("set"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::SInterCard(SInterCard {
+                keys: vec![RedisString::from("a"), RedisString::from("b")],
+                limit: Some(3),
+            }),
+            &[
+                Message::bulk_string("SINTERCARD"),
+                Message::bulk_string("2"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+                Message::bulk_string("LIMIT"),
+                Message::bulk_string("3"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let mut message = vec![Message::bulk_string("SINTERCARD")];
+            message.extend(args.iter().map(|a| Message::bulk_string(a)));
+            Command::parse_resp(&Message::Array(message))
+        };
+        assert!(parse(&["0"]).is_err());
+        assert!(parse(&["2", "a"]).is_err());
+        assert!(parse(&["1", "a", "LIMIT", "-1"]).is_err());
+        assert!(parse(&["1", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn sscan_round_trip() {
+        let cmd = Command::SScan(SScan {
+            key: RedisString::from("set"),
+            cursor: 7,
+            pattern: Some(RedisString::from("m*")),
+            count: Some(20),
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[
+                Message::bulk_string("SSCAN"),
+                Message::bulk_string("set"),
+                Message::bulk_string("7"),
+                Message::bulk_string("MATCH"),
+                Message::bulk_string("m*"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("20"),
+            ],
+        );
+
+        let novalues = Message::Array(vec![
+            Message::bulk_string("SSCAN"),
+            Message::bulk_string("set"),
+            Message::bulk_string("0"),
+            Message::bulk_string("NOVALUES"),
+        ]);
+        assert!(Command::parse_resp(&novalues).is_err());
+    }
+
+    #[test]
+    fn zset_round_trip() {
+        let key = || RedisString::from("zset");
+        let score = |s: f64| Score::new(s).unwrap();
+        let expected = |name: &str, rest: &[&str]| {
+            let mut args = vec![Message::bulk_string(name), Message::bulk_string("zset")];
+            args.extend(rest.iter().map(|a| Message::bulk_string(a)));
+            args
+        };
+        assert_command_round_trip(
+            &Command::ZAdd(ZAdd {
+                key: key(),
+                existence: None,
+                comparison: None,
+                ch: false,
+                incr: false,
+                members: vec![
+                    (score(1.5), RedisString::from("a")),
+                    (score(f64::INFINITY), RedisString::from("b")),
+                ],
+            }),
+            &expected("ZADD", &["1.5", "a", "inf", "b"]),
+        );
+        assert_command_round_trip(
+            &Command::ZAdd(ZAdd {
+                key: key(),
+                existence: Some(Existence::Xx),
+                comparison: Some(Comparison::Gt),
+                ch: true,
+                incr: true,
+                members: vec![(score(-2.0), RedisString::from("a"))],
+            }),
+            &expected("ZADD", &["XX", "GT", "CH", "INCR", "-2", "a"]),
+        );
+        assert_command_round_trip(
+            &Command::ZScore(ZScore {
+                key: key(),
+                member: RedisString::from("a"),
+            }),
+            &expected("ZSCORE", &["a"]),
+        );
+        assert_command_round_trip(
+            &Command::ZRem(ZRem {
+                key: key(),
+                members: vec![RedisString::from("a"), RedisString::from("b")],
+            }),
+            &expected("ZREM", &["a", "b"]),
+        );
+        assert_command_round_trip(
+            &Command::ZCard(ZCard { key: key() }),
+            &expected("ZCARD", &[]),
+        );
+
+        let parse = |args: &[&str]| {
+            let mut message = vec![Message::bulk_string("ZADD"), Message::bulk_string("zset")];
+            message.extend(args.iter().map(|a| Message::bulk_string(a)));
+            Command::parse_resp(&Message::Array(message))
+        };
+        assert!(parse(&["1", "a", "2"]).is_err());
+        assert!(parse(&["NX"]).is_err());
+        assert!(parse(&["nan", "a"]).is_err());
+        assert!(parse(&["NX", "XX", "1", "a"]).is_err());
+        assert!(parse(&["NX", "GT", "1", "a"]).is_err());
+        assert!(parse(&["INCR", "1", "a", "2", "b"]).is_err());
+    }
+
+    #[test]
+    fn zrange_round_trip() {
+        let expected = |args: &[&str]| {
+            let mut message = vec![Message::bulk_string("ZRANGE"), Message::bulk_string("zset")];
+            message.extend(args.iter().map(|a| Message::bulk_string(a)));
+            message
+        };
+        let bound = |s: &str| ScoreBound::parse(&RedisString::from(s)).unwrap();
+        let lex = |s: &str| LexBound::parse(&RedisString::from(s)).unwrap();
+
+        assert_command_round_trip(
+            &Command::ZRange(ZRange {
+                key: RedisString::from("zset"),
+                by: ZRangeBy::Rank { start: 0, stop: -1 },
+                rev: false,
+                limit: None,
+                withscores: true,
+            }),
+            &expected(&["0", "-1", "WITHSCORES"]),
+        );
+        assert_command_round_trip(
+            &Command::ZRange(ZRange {
+                key: RedisString::from("zset"),
+                by: ZRangeBy::Score(ScoreRange {
+                    min: bound("(1"),
+                    max: bound("+inf"),
+                }),
+                rev: true,
+                limit: Some(Limit {
+                    offset: 1,
+                    count: 2,
+                }),
+                withscores: false,
+            }),
+            &expected(&["inf", "(1", "BYSCORE", "REV", "LIMIT", "1", "2"]),
+        );
+        assert_command_round_trip(
+            &Command::ZRange(ZRange {
+                key: RedisString::from("zset"),
+                by: ZRangeBy::Lex(LexRange {
+                    min: lex("[a"),
+                    max: lex("+"),
+                }),
+                rev: false,
+                limit: None,
+                withscores: false,
+            }),
+            &expected(&["[a", "+", "BYLEX"]),
+        );
+
+        // Legacy commands parse to the unified form.
+        let parse = |args: &[&str]| {
+            let message = args.iter().map(|a| Message::bulk_string(a)).collect();
+            Command::parse_resp(&Message::Array(message))
+        };
+        assert_eq!(
+            parse(&["ZREVRANGEBYSCORE", "zset", "5", "(1", "WITHSCORES"]).unwrap(),
+            Command::ZRange(ZRange {
+                key: RedisString::from("zset"),
+                by: ZRangeBy::Score(ScoreRange {
+                    min: bound("(1"),
+                    max: bound("5"),
+                }),
+                rev: true,
+                limit: None,
+                withscores: true,
+            })
+        );
+        assert_eq!(
+            parse(&["ZREVRANGE", "zset", "0", "1"]).unwrap(),
+            parse(&["ZRANGE", "zset", "0", "1", "REV"]).unwrap()
+        );
+        assert_eq!(
+            parse(&["ZRANGEBYLEX", "zset", "-", "(c", "LIMIT", "0", "1"]).unwrap(),
+            parse(&["ZRANGE", "zset", "-", "(c", "BYLEX", "LIMIT", "0", "1"]).unwrap()
+        );
+
+        assert!(parse(&["ZRANGE", "zset", "0", "1", "LIMIT", "0", "1"]).is_err());
+        assert!(parse(&["ZRANGE", "zset", "-", "+", "BYLEX", "WITHSCORES"]).is_err());
+        assert!(parse(&["ZRANGE", "zset", "a", "1", "BYSCORE"]).is_err());
+        assert!(parse(&["ZRANGE", "zset", "a", "c", "BYLEX"]).is_err());
+        assert!(parse(&["ZRANGEBYSCORE", "zset", "0", "1", "REV"]).is_err());
+        assert!(parse(&["ZREVRANGE", "zset", "0", "1", "LIMIT", "0", "1"]).is_err());
+    }
+
+    #[test]
+    fn zrank_zincrby_zcount_round_trip() {
+        let key = || RedisString::from("zset");
+        let expected = |name: &str, rest: &[&str]| {
+            let mut args = vec![Message::bulk_string(name), Message::bulk_string("zset")];
+            args.extend(rest.iter().map(|a| Message::bulk_string(a)));
+            args
+        };
+        assert_command_round_trip(
+            &Command::ZRank(ZRank {
+                key: key(),
+                member: RedisString::from("a"),
+                rev: false,
+                withscore: false,
+            }),
+            &expected("ZRANK", &["a"]),
+        );
+        assert_command_round_trip(
+            &Command::ZRank(ZRank {
+                key: key(),
+                member: RedisString::from("a"),
+                rev: true,
+                withscore: true,
+            }),
+            &expected("ZREVRANK", &["a", "WITHSCORE"]),
+        );
+        assert_command_round_trip(
+            &Command::ZIncrBy(ZIncrBy {
+                key: key(),
+                increment: Score::new(-1.5).unwrap(),
+                member: RedisString::from("a"),
+            }),
+            &expected("ZINCRBY", &["-1.5", "a"]),
+        );
+        let bound = |s: &str| ScoreBound::parse(&RedisString::from(s)).unwrap();
+        assert_command_round_trip(
+            &Command::ZCount(ZCount {
+                key: key(),
+                range: ScoreRange {
+                    min: bound("-inf"),
+                    max: bound("(5"),
+                },
+            }),
+            &expected("ZCOUNT", &["-inf", "(5"]),
+        );
+    }
+
+    #[test]
+    fn zset_op_round_trip() {
+        let keys = || vec![RedisString::from("a"), RedisString::from("b")];
+        let expected = |args: &[&str]| {
+            args.iter()
+                .map(|a| Message::bulk_string(a))
+                .collect::<Vec<_>>()
+        };
+        assert_command_round_trip(
+            &Command::ZSetOp(ZSetOp {
+                op: SetOperation::Union,
+                keys: keys(),
+                weights: vec![Score::new(2.0).unwrap(), Score::new(0.5).unwrap()],
+                aggregate: Some(Aggregate::Max),
+                store: Some(RedisString::from("dest")),
+                withscores: false,
+            }),
+            &expected(&[
+                "ZUNIONSTORE",
+                "dest",
+                "2",
+                "a",
+                "b",
+                "WEIGHTS",
+                "2",
+                "0.5",
+                "AGGREGATE",
+                "MAX",
+            ]),
+        );
+        assert_command_round_trip(
+            &Command::ZSetOp(ZSetOp {
+                op: SetOperation::Diff,
+                keys: keys(),
+                weights: vec![],
+                aggregate: None,
+                store: None,
+                withscores: true,
+            }),
+            &expected(&["ZDIFF", "2", "a", "b", "WITHSCORES"]),
+        );
+
+        let parse = |args: &[&str]| {
+            Command::parse_resp(&Message::Array(
+                args.iter().map(|a| Message::bulk_string(a)).collect(),
+            ))
+        };
+        assert!(parse(&["ZINTER", "2", "a", "b", "WEIGHTS", "1"]).is_err());
+        assert!(parse(&["ZINTER", "1", "a", "WEIGHTS", "x"]).is_err());
+        assert!(parse(&["ZINTER", "1", "a", "AGGREGATE", "AVG"]).is_err());
+        assert!(parse(&["ZDIFF", "1", "a", "WEIGHTS", "1"]).is_err());
+        assert!(parse(&["ZINTERSTORE", "dest", "1", "a", "WITHSCORES"]).is_err());
+        assert!(parse(&["ZUNIONSTORE", "dest", "0"]).is_err());
+    }
+
+    #[test]
+    fn zrandmember_and_zmscore_round_trip() {
+        let expected = |name: &str, rest: &[&str]| {
+            let mut args = vec![Message::bulk_string(name), Message::bulk_string("zset")];
+            args.extend(rest.iter().map(|a| Message::bulk_string(a)));
+            args
+        };
+        assert_command_round_trip(
+            &Command::ZRandMember(ZRandMember {
+                key: RedisString::from("zset"),
+                count: None,
+                withscores: false,
+            }),
+            &expected("ZRANDMEMBER", &[]),
+        );
+        assert_command_round_trip(
+            &Command::ZRandMember(ZRandMember {
+                key: RedisString::from("zset"),
+                count: Some(-5),
+                withscores: true,
+            }),
+            &expected("ZRANDMEMBER", &["-5", "WITHSCORES"]),
+        );
+        assert_command_round_trip(
+            &Command::ZMScore(ZMScore {
+                key: RedisString::from("zset"),
+                members: vec![RedisString::from("a"), RedisString::from("b")],
+            }),
+            &expected("ZMSCORE", &["a", "b"]),
+        );
+
+        let no_count = Message::Array(expected("ZRANDMEMBER", &["WITHSCORES"]));
+        assert!(Command::parse_resp(&no_count).is_err());
+    }
+
+    #[test]
+    fn zscan_round_trip() {
+        let cmd = Command::ZScan(ZScan {
+            key: RedisString::from("zset"),
+            cursor: 3,
+            pattern: Some(RedisString::from("a*")),
+            count: Some(100),
+        });
+        assert_command_round_trip(
+            &cmd,
+            &[
+                Message::bulk_string("ZSCAN"),
+                Message::bulk_string("zset"),
+                Message::bulk_string("3"),
+                Message::bulk_string("MATCH"),
+                Message::bulk_string("a*"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("100"),
+            ],
+        );
+    }
+
+    #[test]
+    fn bitmap_round_trip() {
+        assert_command_round_trip(
+            &Command::SetBit(SetBit {
+                key: RedisString::from("bits"),
+                offset: 7,
+                value: true,
+            }),
+            &[
+                Message::bulk_string("SETBIT"),
+                Message::bulk_string("bits"),
+                Message::bulk_string("7"),
+                Message::bulk_string("1"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::GetBit(GetBit {
+                key: RedisString::from("bits"),
+                offset: 100,
+            }),
+            &[
+                Message::bulk_string("GETBIT"),
+                Message::bulk_string("bits"),
+                Message::bulk_string("100"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::BitCount(BitCount {
+                key: RedisString::from("bits"),
+                range: Some(BitRange {
+                    start: 5,
+                    end: -1,
+                    unit: BitUnit::Bit,
+                }),
+            }),
+            &[
+                Message::bulk_string("BITCOUNT"),
+                Message::bulk_string("bits"),
+                Message::bulk_string("5"),
+                Message::bulk_string("-1"),
+                Message::bulk_string("BIT"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::BitPos(BitPos {
+                key: RedisString::from("bits"),
+                bit: false,
+                start: Some(2),
+                end: None,
+                unit: BitUnit::Byte,
+            }),
+            &[
+                Message::bulk_string("BITPOS"),
+                Message::bulk_string("bits"),
+                Message::bulk_string("0"),
+                Message::bulk_string("2"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["SETBIT", "bits", "1", "2"]).is_err());
+        assert!(parse(&["SETBIT", "bits", "-1", "1"]).is_err());
+        assert!(parse(&["GETBIT", "bits", "4294967296"]).is_err());
+        assert!(parse(&["GETBIT", "bits", "4294967295"]).is_ok());
+        assert!(parse(&["BITCOUNT", "bits", "0"]).is_err());
+        assert!(parse(&["BITCOUNT", "bits", "0", "1", "BITS"]).is_err());
+        assert!(parse(&["BITPOS", "bits", "2"]).is_err());
+        assert!(parse(&["BITPOS", "bits", "1", "0", "-1", "BYTE"]).is_ok());
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let id = |ms, seq| StreamId { ms, seq };
+        assert_command_round_trip(
+            &Command::XAdd(XAdd {
+                key: RedisString::from("stream"),
+                id: NewId::AutoSeq(5),
+                fields: vec![(RedisString::from("f"), RedisString::from("v"))],
+            }),
+            &[
+                Message::bulk_string("XADD"),
+                Message::bulk_string("stream"),
+                Message::bulk_string("5-*"),
+                Message::bulk_string("f"),
+                Message::bulk_string("v"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XLen(XLen {
+                key: RedisString::from("stream"),
+            }),
+            &[Message::bulk_string("XLEN"), Message::bulk_string("stream")],
+        );
+        assert_command_round_trip(
+            &Command::XRange(XRange {
+                key: RedisString::from("stream"),
+                start: RangeBound::Exclusive(id(1, 2)),
+                end: RangeBound::Max,
+                count: Some(10),
+                rev: false,
+            }),
+            &[
+                Message::bulk_string("XRANGE"),
+                Message::bulk_string("stream"),
+                Message::bulk_string("(1-2"),
+                Message::bulk_string("+"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("10"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XRange(XRange {
+                key: RedisString::from("stream"),
+                start: RangeBound::Min,
+                end: RangeBound::Inclusive(id(3, 0)),
+                count: None,
+                rev: true,
+            }),
+            &[
+                Message::bulk_string("XREVRANGE"),
+                Message::bulk_string("stream"),
+                Message::bulk_string("3-0"),
+                Message::bulk_string("-"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        let Ok(Command::XRange(xrange)) = parse(&["XRANGE", "s", "5", "6"]) else {
+            panic!("expected XRANGE");
+        };
+        assert_eq!(xrange.start, RangeBound::Inclusive(id(5, 0)));
+        assert_eq!(xrange.end, RangeBound::Inclusive(id(6, u64::MAX)));
+        assert!(parse(&["XADD", "s", "*", "f"]).is_err());
+        assert!(parse(&["XADD", "s", "0-0", "f", "v"]).is_err());
+        assert!(parse(&["XADD", "s", "abc", "f", "v"]).is_err());
+        assert!(parse(&["XRANGE", "s", "(-", "+"]).is_err());
+        assert!(parse(&["XRANGE", "s", "-", "+", "LIMIT", "1"]).is_err());
+    }
+
+    #[test]
+    fn xread_round_trip() {
+        assert_command_round_trip(
+            &Command::XRead(XRead {
+                count: Some(2),
+                block: Some(Duration::from_millis(1500)),
+                keys: vec![RedisString::from("a"), RedisString::from("b")],
+                ids: vec![ReadId::After(StreamId { ms: 1, seq: 0 }), ReadId::Last],
+            }),
+            &[
+                Message::bulk_string("XREAD"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("2"),
+                Message::bulk_string("BLOCK"),
+                Message::bulk_string("1500"),
+                Message::bulk_string("STREAMS"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+                Message::bulk_string("1-0"),
+                Message::bulk_string("$"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["XREAD", "STREAMS", "a", "b", "0"]).is_err());
+        assert!(parse(&["XREAD", "STREAMS"]).is_err());
+        assert!(parse(&["XREAD", "a", "0"]).is_err());
+        assert!(parse(&["XREAD", "BLOCK", "-1", "STREAMS", "a", "0"]).is_err());
+        assert!(parse(&["XREAD", "STREAMS", "a", "x"]).is_err());
+    }
+
+    #[test]
+    fn consumer_group_round_trip() {
+        let s = RedisString::from;
+        let id = |ms| StreamId { ms, seq: 0 };
+        assert_command_round_trip(
+            &Command::XGroup(XGroup::Create {
+                key: s("s"),
+                group: s("g"),
+                id: ReadId::Last,
+                mkstream: true,
+            }),
+            &[
+                Message::bulk_string("XGROUP"),
+                Message::bulk_string("CREATE"),
+                Message::bulk_string("s"),
+                Message::bulk_string("g"),
+                Message::bulk_string("$"),
+                Message::bulk_string("MKSTREAM"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XReadGroup(XReadGroup {
+                group: s("g"),
+                consumer: s("c"),
+                count: Some(3),
+                noack: true,
+                keys: vec![s("a"), s("b")],
+                ids: vec![GroupReadId::New, GroupReadId::After(id(5))],
+            }),
+            &[
+                Message::bulk_string("XREADGROUP"),
+                Message::bulk_string("GROUP"),
+                Message::bulk_string("g"),
+                Message::bulk_string("c"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("3"),
+                Message::bulk_string("NOACK"),
+                Message::bulk_string("STREAMS"),
+                Message::bulk_string("a"),
+                Message::bulk_string("b"),
+                Message::bulk_string(">"),
+                Message::bulk_string("5-0"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XAck(XAck {
+                key: s("s"),
+                group: s("g"),
+                ids: vec![id(1), id(2)],
+            }),
+            &[
+                Message::bulk_string("XACK"),
+                Message::bulk_string("s"),
+                Message::bulk_string("g"),
+                Message::bulk_string("1-0"),
+                Message::bulk_string("2-0"),
+            ],
+        );
+    }
+
+    #[test]
+    fn pending_round_trip() {
+        let s = RedisString::from;
+        let id = |ms| StreamId { ms, seq: 0 };
+        assert_command_round_trip(
+            &Command::XPending(XPending {
+                key: s("s"),
+                group: s("g"),
+                range: None,
+            }),
+            &[
+                Message::bulk_string("XPENDING"),
+                Message::bulk_string("s"),
+                Message::bulk_string("g"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XPending(XPending {
+                key: s("s"),
+                group: s("g"),
+                range: Some(PendingRange {
+                    min_idle: Some(100),
+                    start: RangeBound::Exclusive(id(1)),
+                    end: RangeBound::Max,
+                    count: 10,
+                    consumer: Some(s("c")),
+                }),
+            }),
+            &[
+                Message::bulk_string("XPENDING"),
+                Message::bulk_string("s"),
+                Message::bulk_string("g"),
+                Message::bulk_string("IDLE"),
+                Message::bulk_string("100"),
+                Message::bulk_string("(1-0"),
+                Message::bulk_string("+"),
+                Message::bulk_string("10"),
+                Message::bulk_string("c"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XClaim(XClaim {
+                key: s("s"),
+                group: s("g"),
+                consumer: s("c"),
+                min_idle: 3600,
+                ids: vec![id(1), id(2)],
+                idle: Some(5),
+                time: None,
+                retry_count: Some(2),
+                force: true,
+                justid: true,
+            }),
+            &[
+                Message::bulk_string("XCLAIM"),
+                Message::bulk_string("s"),
+                Message::bulk_string("g"),
+                Message::bulk_string("c"),
+                Message::bulk_string("3600"),
+                Message::bulk_string("1-0"),
+                Message::bulk_string("2-0"),
+                Message::bulk_string("IDLE"),
+                Message::bulk_string("5"),
+                Message::bulk_string("RETRYCOUNT"),
+                Message::bulk_string("2"),
+                Message::bulk_string("FORCE"),
+                Message::bulk_string("JUSTID"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XAutoClaim(XAutoClaim {
+                key: s("s"),
+                group: s("g"),
+                consumer: s("c"),
+                min_idle: 0,
+                start: id(0),
+                count: Some(25),
+                justid: false,
+            }),
+            &[
+                Message::bulk_string("XAUTOCLAIM"),
+                Message::bulk_string("s"),
+                Message::bulk_string("g"),
+                Message::bulk_string("c"),
+                Message::bulk_string("0"),
+                Message::bulk_string("0-0"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("25"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert!(parse(&["XPENDING", "s", "g", "-", "+"]).is_err());
+        assert!(parse(&["XCLAIM", "s", "g", "c", "0"]).is_err());
+        assert!(parse(&["XCLAIM", "s", "g", "c", "0", "1-0", "BOGUS"]).is_err());
+        assert!(parse(&["XAUTOCLAIM", "s", "g", "c", "0", "0", "COUNT", "0"]).is_err());
+        assert!(parse(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "a", "$"]).is_err());
+    }
+
+    #[test]
+    fn stream_admin_round_trip() {
+        let s = RedisString::from;
+        assert_command_round_trip(
+            &Command::XTrim(XTrim {
+                key: s("s"),
+                trim: Trim {
+                    strategy: TrimStrategy::MaxLen(10),
+                    approx: true,
+                    limit: Some(100),
+                },
+            }),
+            &[
+                Message::bulk_string("XTRIM"),
+                Message::bulk_string("s"),
+                Message::bulk_string("MAXLEN"),
+                Message::bulk_string("~"),
+                Message::bulk_string("10"),
+                Message::bulk_string("LIMIT"),
+                Message::bulk_string("100"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XTrim(XTrim {
+                key: s("s"),
+                trim: Trim {
+                    strategy: TrimStrategy::MinId(StreamId { ms: 5, seq: 1 }),
+                    approx: false,
+                    limit: None,
+                },
+            }),
+            &[
+                Message::bulk_string("XTRIM"),
+                Message::bulk_string("s"),
+                Message::bulk_string("MINID"),
+                Message::bulk_string("5-1"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XDel(XDel {
+                key: s("s"),
+                ids: vec![StreamId { ms: 1, seq: 2 }],
+            }),
+            &[
+                Message::bulk_string("XDEL"),
+                Message::bulk_string("s"),
+                Message::bulk_string("1-2"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::XInfo(XInfo::Consumers {
+                key: s("s"),
+                group: s("g"),
+            }),
+            &[
+                Message::bulk_string("XINFO"),
+                Message::bulk_string("CONSUMERS"),
+                Message::bulk_string("s"),
+                Message::bulk_string("g"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert_eq!(
+            parse(&["XTRIM", "s", "maxlen", "=", "3"]).unwrap(),
+            parse(&["XTRIM", "s", "MAXLEN", "3"]).unwrap()
+        );
+        assert!(parse(&["XTRIM", "s", "MAXLEN", "3", "LIMIT", "10"]).is_err());
+        assert!(parse(&["XTRIM", "s", "MAXLEN", "-1"]).is_err());
+        assert!(parse(&["XTRIM", "s", "LEN", "1"]).is_err());
+        assert!(parse(&["XINFO", "GROUPS", "s", "extra"]).is_err());
+        assert!(parse(&["XINFO", "CONSUMERS", "s"]).is_err());
+    }
+
+    #[test]
+    fn geo_round_trip() {
+        let coords = |lon, lat| Coordinates::new(lon, lat).unwrap();
+        assert_command_round_trip(
+            &Command::GeoAdd(GeoAdd {
+                key: RedisString::from("Sicily"),
+                existence: Some(Existence::Xx),
+                ch: true,
+                members: vec![(coords(13.361_389, 38.115_556), RedisString::from("Palermo"))],
+            }),
+            &[
+                Message::bulk_string("GEOADD"),
+                Message::bulk_string("Sicily"),
+                Message::bulk_string("XX"),
+                Message::bulk_string("CH"),
+                Message::bulk_string("13.361389"),
+                Message::bulk_string("38.115556"),
+                Message::bulk_string("Palermo"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::GeoSearch(GeoSearch {
+                key: RedisString::from("Sicily"),
+                from: GeoOrigin::LonLat(coords(15.0, 37.0)),
+                shape: Shape::Box {
+                    width: 400.0,
+                    height: 300.5,
+                },
+                unit: DistanceUnit::Kilometers,
+                order: Some(SortOrder::Desc),
+                count: Some(3),
+                any: true,
+                with_coord: true,
+                with_dist: true,
+                with_hash: false,
+            }),
+            &[
+                Message::bulk_string("GEOSEARCH"),
+                Message::bulk_string("Sicily"),
+                Message::bulk_string("FROMLONLAT"),
+                Message::bulk_string("15"),
+                Message::bulk_string("37"),
+                Message::bulk_string("BYBOX"),
+                Message::bulk_string("400"),
+                Message::bulk_string("300.5"),
+                Message::bulk_string("km"),
+                Message::bulk_string("DESC"),
+                Message::bulk_string("COUNT"),
+                Message::bulk_string("3"),
+                Message::bulk_string("ANY"),
+                Message::bulk_string("WITHCOORD"),
+                Message::bulk_string("WITHDIST"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert_eq!(
+            parse(&["GEOSEARCH", "k", "BYRADIUS", "1", "M", "FROMMEMBER", "m"]).unwrap(),
+            Command::GeoSearch(GeoSearch {
+                key: RedisString::from("k"),
+                from: GeoOrigin::Member(RedisString::from("m")),
+                shape: Shape::Radius(1.0),
+                unit: DistanceUnit::Meters,
+                order: None,
+                count: None,
+                any: false,
+                with_coord: false,
+                with_dist: false,
+                with_hash: false,
+            })
+        );
+        assert!(parse(&["GEOADD", "k", "181", "0", "m"]).is_err());
+        assert!(parse(&["GEOADD", "k", "0", "0"]).is_err());
+        assert!(parse(&["GEOADD", "k", "NX", "XX", "0", "0", "m"]).is_err());
+        assert!(parse(&["GEOSEARCH", "k", "FROMMEMBER", "m"]).is_err());
+        assert!(parse(&["GEOSEARCH", "k", "BYRADIUS", "1", "m"]).is_err());
+        assert!(parse(&["GEOSEARCH", "k", "FROMMEMBER", "m", "BYRADIUS", "1", "yd"]).is_err());
+        assert!(parse(&["GEOSEARCH", "k", "FROMMEMBER", "m", "BYRADIUS", "-1", "m"]).is_err());
+        assert!(parse(&[
+            "GEOSEARCH",
+            "k",
+            "FROMMEMBER",
+            "m",
+            "FROMLONLAT",
+            "0",
+            "0",
+            "BYRADIUS",
+            "1",
+            "m"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn geo_member_round_trip() {
+        let parse = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message)
+        };
+        assert_command_round_trip(
+            &Command::GeoDist(GeoDist {
+                key: RedisString::from("Sicily"),
+                member1: RedisString::from("Palermo"),
+                member2: RedisString::from("Catania"),
+                unit: DistanceUnit::Miles,
+            }),
+            &[
+                Message::bulk_string("GEODIST"),
+                Message::bulk_string("Sicily"),
+                Message::bulk_string("Palermo"),
+                Message::bulk_string("Catania"),
+                Message::bulk_string("mi"),
+            ],
+        );
+        assert_command_round_trip(
+            &Command::GeoHash(GeoHash {
+                key: RedisString::from("Sicily"),
+                members: vec![RedisString::from("Palermo")],
+            }),
+            &[
+                Message::bulk_string("GEOHASH"),
+                Message::bulk_string("Sicily"),
+                Message::bulk_string("Palermo"),
+            ],
+        );
+        assert_eq!(
+            parse(&["GEODIST", "k", "a", "b"]).unwrap(),
+            parse(&["GEODIST", "k", "a", "b", "M"]).unwrap()
+        );
+        assert!(parse(&["GEODIST", "k", "a", "b", "yd"]).is_err());
+        assert!(parse(&["GEODIST", "k", "a", "b", "m", "extra"]).is_err());
+    }
 
     #[test]
     fn pong_round_trip() {
@@ -186,4 +7909,71 @@ mod tests {
             &Message::SimpleString("OK".to_string()),
         );
     }
+
+    #[test]
+    fn integer_round_trip() {
+        assert_command_response_round_trip(&CommandResponse::Integer(-2), &Message::Integer(-2));
+    }
+
+    #[test]
+    fn null_array_round_trip() {
+        assert_command_response_round_trip(&CommandResponse::NullArray, &Message::NullArray);
+    }
+
+    #[test]
+    fn error_round_trip() {
+        assert_command_response_round_trip(
+            &CommandResponse::Error(ErrorReply::err("syntax error")),
+            &Message::Error("ERR syntax error".to_string()),
+        );
+        assert_command_response_round_trip(
+            &CommandResponse::Error(ErrorReply::wrong_type()),
+            &Message::Error(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+            ),
+        );
+        assert_eq!(ErrorReply::parse("oops"), ErrorReply::err("oops"));
+    }
+
+    #[test]
+    fn argument_errors() {
+        let parse_error = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            Command::parse_resp(&message).unwrap_err().to_string()
+        };
+        assert_eq!(
+            parse_error(&["GET"]),
+            "wrong number of arguments for 'get' command"
+        );
+        assert_eq!(
+            parse_error(&["get", "a", "b"]),
+            "wrong number of arguments for 'get' command"
+        );
+        assert_eq!(
+            parse_error(&["NOPE", "a", "b"]),
+            "unknown command 'NOPE', with args beginning with: 'a' 'b' "
+        );
+    }
+
+    #[test]
+    fn push_round_trip() {
+        assert_command_response_round_trip(
+            &CommandResponse::Push(vec![CommandResponse::BulkString(None)]),
+            &Message::Push(vec![Message::BulkString(None)]),
+        );
+    }
+
+    #[test]
+    fn array_round_trip() {
+        assert_command_response_round_trip(
+            &CommandResponse::Array(vec![
+                CommandResponse::Integer(1),
+                CommandResponse::Array(vec![CommandResponse::BulkString(None)]),
+            ]),
+            &Message::Array(vec![
+                Message::Integer(1),
+                Message::Array(vec![Message::BulkString(None)]),
+            ]),
+        );
+    }
 }
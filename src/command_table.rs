@@ -0,0 +1,1607 @@
+//! The command table: how many arguments each command takes, its flags,
+//! where its keys are and which ACL categories it's in.
+//!
+//! `COMMAND` reports it to clients, which use it to route commands to cluster
+//! nodes, and ACL rules like `+@read` are resolved with it. It follows Redis'
+//! command table; see <https://redis.io/commands/command/>. Keys are found
+//! with Redis' key specifications, described in
+//! <https://redis.io/docs/reference/key-specs/>.
+
+use crate::acl::Category;
+
+/// A command flag, as `COMMAND INFO` reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    /// The command may modify the keyspace.
+    Write,
+    ReadOnly,
+
+    /// The command may use more memory, so it's refused when `maxmemory` is
+    /// reached.
+    DenyOom,
+    Admin,
+    PubSub,
+    NoScript,
+    Blocking,
+
+    /// The command can run while the dataset is loading.
+    Loading,
+
+    /// The command can run on a replica whose link to its master is down.
+    Stale,
+    Fast,
+
+    /// The command can run before the client authenticates.
+    NoAuth,
+
+    /// The command may be replicated even though it doesn't write, like
+    /// `PUBLISH`.
+    MayReplicate,
+    NoMandatoryKeys,
+
+    /// The command's keys can't be found from its first and last key
+    /// positions. This follows from its key specs.
+    MovableKeys,
+}
+
+impl Flag {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Write => "write",
+            Self::ReadOnly => "readonly",
+            Self::DenyOom => "denyoom",
+            Self::Admin => "admin",
+            Self::PubSub => "pubsub",
+            Self::NoScript => "noscript",
+            Self::Blocking => "blocking",
+            Self::Loading => "loading",
+            Self::Stale => "stale",
+            Self::Fast => "fast",
+            Self::NoAuth => "no_auth",
+            Self::MayReplicate => "may_replicate",
+            Self::NoMandatoryKeys => "no_mandatory_keys",
+            Self::MovableKeys => "movablekeys",
+        }
+    }
+}
+
+/// What a command does with one of its keys, as `COMMAND GETKEYSANDFLAGS`
+/// reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFlag {
+    /// The key is read from and written to.
+    Rw,
+
+    /// The key is only read from.
+    Ro,
+
+    /// The key is overwritten without being read.
+    Ow,
+
+    /// The key is removed.
+    Rm,
+
+    /// The key's value is returned to the client.
+    Access,
+
+    /// Part of the key's value is changed.
+    Update,
+
+    /// Data is added to the key's value.
+    Insert,
+
+    /// Data is removed from the key's value.
+    Delete,
+
+    /// The spec may miss some of the command's keys.
+    Incomplete,
+}
+
+impl KeyFlag {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Rw => "RW",
+            Self::Ro => "RO",
+            Self::Ow => "OW",
+            Self::Rm => "RM",
+            Self::Access => "access",
+            Self::Update => "update",
+            Self::Insert => "insert",
+            Self::Delete => "delete",
+            Self::Incomplete => "incomplete",
+        }
+    }
+}
+
+/// Where some of a command's keys are: `begin` finds the first of them, and
+/// `find` the rest from there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySpec {
+    pub begin: BeginSearch,
+    pub find: FindKeys,
+    pub flags: &'static [KeyFlag],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BeginSearch {
+    /// The keys start at this argument.
+    Index(usize),
+
+    /// The keys start after `keyword`, which is searched for starting at
+    /// argument `start_from`, or backwards from the end if that's negative.
+    Keyword {
+        keyword: &'static str,
+        start_from: i64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindKeys {
+    /// The keys run up to `last_key` arguments after the first, or if it's
+    /// negative, up to that far from the end. With a `limit`, only that
+    /// fraction of the remaining arguments are keys, like the first half of
+    /// the arguments after `XREAD`'s `STREAMS`.
+    Range {
+        last_key: i64,
+        step: usize,
+        limit: usize,
+    },
+
+    /// The number of keys is the argument `key_num_index` after the first,
+    /// and the keys start `first_key` arguments after it.
+    KeyNum {
+        key_num_index: usize,
+        first_key: usize,
+        step: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandSpec {
+    /// The command's lower-cased name, which for subcommands includes their
+    /// container's, like `object|encoding`.
+    pub name: &'static str,
+
+    /// How many arguments the command takes, counting its name. A negative
+    /// arity means at least that many.
+    pub arity: i64,
+
+    /// The command's flags besides `movablekeys`. See `flags`.
+    flags: &'static [Flag],
+    pub key_specs: &'static [KeySpec],
+
+    /// The categories the command is in besides the ones its flags put it
+    /// in. See `categories`.
+    categories: &'static [Category],
+
+    /// Where `COMMAND DOCS` lists the command, like `string` or `generic`.
+    pub group: &'static str,
+
+    /// The Redis version that added the command.
+    pub since: &'static str,
+    pub summary: &'static str,
+}
+
+impl CommandSpec {
+    /// The command's flags, including `movablekeys` for commands whose keys
+    /// `legacy_key_range` doesn't describe.
+    pub fn flags(&self) -> Vec<Flag> {
+        let movable = self
+            .key_specs
+            .iter()
+            .any(|spec| legacy_range(spec).is_none());
+        let mut flags = self.flags.to_vec();
+        if movable {
+            flags.push(Flag::MovableKeys);
+        }
+        flags
+    }
+
+    /// The keys `COMMAND INFO` reports from before key specs, as the
+    /// positions of the first and last keys, and how many arguments apart
+    /// the keys are. A negative last key counts back from the end of the
+    /// arguments. This covers the keys at fixed positions, and is all zeros
+    /// for commands without any.
+    pub fn legacy_key_range(&self) -> (i64, i64, i64) {
+        let mut ranges = self.key_specs.iter().filter_map(legacy_range);
+        let Some((first_key, mut last_key, step)) = ranges.next() else {
+            return (0, 0, 0);
+        };
+        // Like Redis, later ranges are assumed to follow on from the first.
+        for (_, last, _) in ranges {
+            last_key = last;
+        }
+        (first_key, last_key, step)
+    }
+
+    /// The positions of the keys in `args`, which start with the command's
+    /// name, and the flags of the spec that found each one. Keys that would
+    /// be past the end of `args` are left out.
+    pub fn find_keys(&self, args: &[&[u8]]) -> Vec<(usize, &'static [KeyFlag])> {
+        let mut keys = Vec::new();
+        for spec in self.key_specs {
+            let Some(begin) = begin_search(&spec.begin, args) else {
+                continue;
+            };
+            let Some((first, last, step)) = find_keys(&spec.find, begin, args) else {
+                continue;
+            };
+            keys.extend(
+                (first..=last)
+                    .step_by(step)
+                    .map(|index| (index, spec.flags)),
+            );
+        }
+        keys
+    }
+
+    /// The categories the command is in. Like Redis, some follow from its
+    /// flags: writes are in `@write`, admin commands are `@dangerous`, and
+    /// commands that aren't `@fast` are `@slow`.
+    pub fn categories(&self) -> Vec<Category> {
+        let has = |flag| self.flags.contains(&flag);
+        Category::ALL
+            .into_iter()
+            .filter(|&category| {
+                self.categories.contains(&category)
+                    || match category {
+                        Category::Write => has(Flag::Write),
+                        // Read-only scripts can still be anything but
+                        // reads, depending on the script.
+                        Category::Read => {
+                            has(Flag::ReadOnly) && !self.categories.contains(&Category::Scripting)
+                        }
+                        Category::Admin | Category::Dangerous => has(Flag::Admin),
+                        Category::PubSub => has(Flag::PubSub),
+                        Category::Fast => has(Flag::Fast),
+                        Category::Slow => !has(Flag::Fast),
+                        Category::Blocking => has(Flag::Blocking),
+                        _ => false,
+                    }
+            })
+            .collect()
+    }
+
+    const fn doc(self, group: &'static str, since: &'static str, summary: &'static str) -> Self {
+        Self {
+            group,
+            since,
+            summary,
+            ..self
+        }
+    }
+}
+
+/// The first key, last key and step of a spec for keys at fixed positions,
+/// as `legacy_key_range` reports them.
+fn legacy_range(spec: &KeySpec) -> Option<(i64, i64, i64)> {
+    let (
+        BeginSearch::Index(index),
+        FindKeys::Range {
+            last_key,
+            step,
+            limit: 0,
+        },
+    ) = (spec.begin, spec.find)
+    else {
+        return None;
+    };
+    let first_key = i64::try_from(index).ok()?;
+    let last_key = if last_key < 0 {
+        last_key
+    } else {
+        first_key + last_key
+    };
+    Some((first_key, last_key, i64::try_from(step).ok()?))
+}
+
+/// The position of the argument a spec's keys start at, if any.
+fn begin_search(begin: &BeginSearch, args: &[&[u8]]) -> Option<usize> {
+    match *begin {
+        BeginSearch::Index(index) => (index < args.len()).then_some(index),
+        BeginSearch::Keyword {
+            keyword,
+            start_from,
+        } => {
+            let is_keyword = |&index: &usize| args[index].eq_ignore_ascii_case(keyword.as_bytes());
+            let found = if start_from >= 0 {
+                let start = usize::try_from(start_from).ok()?;
+                (start..args.len()).find(is_keyword)
+            } else {
+                let start = args
+                    .len()
+                    .checked_sub(start_from.unsigned_abs().try_into().ok()?)?;
+                (1..=start).rev().find(is_keyword)
+            };
+            found.map(|index| index + 1)
+        }
+    }
+}
+
+/// The positions of the first and last key a spec finds from `begin`, and
+/// the step between them.
+fn find_keys(find: &FindKeys, begin: usize, args: &[&[u8]]) -> Option<(usize, usize, usize)> {
+    let (first, last, step) = match *find {
+        FindKeys::Range {
+            last_key,
+            step,
+            limit,
+        } => {
+            let last = if last_key >= 0 {
+                begin + usize::try_from(last_key).ok()?
+            } else {
+                let end = (args.len() - begin)
+                    .checked_div(limit)
+                    .map_or(args.len(), |keys| begin + keys);
+                end.checked_sub(last_key.unsigned_abs().try_into().ok()?)?
+            };
+            (begin, last, step)
+        }
+        FindKeys::KeyNum {
+            key_num_index,
+            first_key,
+            step,
+        } => {
+            let num_keys: usize = std::str::from_utf8(args.get(begin + key_num_index)?)
+                .ok()?
+                .parse()
+                .ok()
+                .filter(|&num_keys| num_keys > 0)?;
+            let first = begin + first_key;
+            (first, first + (num_keys - 1) * step, step)
+        }
+    };
+    (first <= last && last < args.len()).then_some((first, last, step))
+}
+
+/// Describes a command, which `doc` then documents.
+const fn command(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [Flag],
+    key_specs: &'static [KeySpec],
+    categories: &'static [Category],
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        key_specs,
+        categories,
+        group: "",
+        since: "",
+        summary: "",
+    }
+}
+
+/// A spec for the key at `index`.
+const fn key(index: usize, flags: &'static [KeyFlag]) -> KeySpec {
+    keys(index, 0, flags)
+}
+
+/// A spec for the keys from `index` to `last_key`, which counts back from the
+/// end of the arguments if it's negative.
+const fn keys(index: usize, last_key: i64, flags: &'static [KeyFlag]) -> KeySpec {
+    KeySpec {
+        begin: BeginSearch::Index(index),
+        find: FindKeys::Range {
+            last_key,
+            step: 1,
+            limit: 0,
+        },
+        flags,
+    }
+}
+
+/// A spec for keys preceded by their number, which is at `index`.
+const fn key_num(index: usize, flags: &'static [KeyFlag]) -> KeySpec {
+    KeySpec {
+        begin: BeginSearch::Index(index),
+        find: FindKeys::KeyNum {
+            key_num_index: 0,
+            first_key: 1,
+            step: 1,
+        },
+        flags,
+    }
+}
+
+/// A spec for the streams after `STREAMS`, searched for from `start_from`.
+/// They're the first half of the remaining arguments, and their IDs the
+/// second half.
+const fn streams(start_from: i64, flags: &'static [KeyFlag]) -> KeySpec {
+    KeySpec {
+        begin: BeginSearch::Keyword {
+            keyword: "STREAMS",
+            start_from,
+        },
+        find: FindKeys::Range {
+            last_key: -1,
+            step: 1,
+            limit: 2,
+        },
+        flags,
+    }
+}
+
+/// Looks up a command by name, ignoring case.
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+/// Every command the server has.
+pub const COMMANDS: &[CommandSpec] = {
+    use Category::{
+        Bitmap, Connection, Dangerous, Geo, Hash, Keyspace, List, Read, Scripting, Set, SortedSet,
+        Stream, String,
+    };
+    use Flag::{
+        Admin, Blocking, DenyOom, Fast, Loading, MayReplicate, NoAuth, NoMandatoryKeys, NoScript,
+        PubSub, ReadOnly, Stale, Write,
+    };
+    use KeyFlag::{Access, Delete, Insert, Ow, Rm, Ro, Rw, Update};
+    // Specs for commands whose first argument is their only key.
+    const READ: &[KeySpec] = &[key(1, &[Ro, Access])];
+    const READ_META: &[KeySpec] = &[key(1, &[Ro])];
+    const READ_WRITE: &[KeySpec] = &[key(1, &[Rw, Access, Update])];
+    const UPDATE: &[KeySpec] = &[key(1, &[Rw, Update])];
+    const INSERT: &[KeySpec] = &[key(1, &[Rw, Insert])];
+    const DELETE: &[KeySpec] = &[key(1, &[Rw, Delete])];
+    const POP: &[KeySpec] = &[key(1, &[Rw, Access, Delete])];
+
+    const MOVE_ELEMENT: &[KeySpec] = &[key(1, &[Rw, Access, Delete]), key(2, &[Rw, Insert])];
+    const STORE_SET: &[KeySpec] = &[key(1, &[Ow, Update]), keys(2, -1, &[Ro, Access])];
+    const STORE_SORTED_SET: &[KeySpec] = &[key(1, &[Ow, Update]), key_num(2, &[Ro, Access])];
+    const SCRIPT: &[KeySpec] = &[key_num(2, &[Rw, Access, Update])];
+    const READ_ONLY_SCRIPT: &[KeySpec] = &[key_num(2, &[Ro, Access])];
+    &[
+        command("ping", -1, &[Fast], &[], &[Connection]).doc(
+            "connection",
+            "1.0.0",
+            "Returns the server's liveliness response.",
+        ),
+        command(
+            "auth",
+            -2,
+            &[NoScript, Loading, Stale, Fast, NoAuth],
+            &[],
+            &[Connection],
+        )
+        .doc("connection", "1.0.0", "Authenticates the connection."),
+        command(
+            "hello",
+            -1,
+            &[NoScript, Loading, Stale, Fast, NoAuth],
+            &[],
+            &[Connection],
+        )
+        .doc("connection", "6.0.0", "Handshakes with the Redis server."),
+        command("select", 2, &[Loading, Stale, Fast], &[], &[Connection]).doc(
+            "connection",
+            "1.0.0",
+            "Changes the selected database.",
+        ),
+        command("client", -2, &[], &[], &[Connection]).doc(
+            "connection",
+            "2.4.0",
+            "A container for client connection commands.",
+        ),
+        command("asking", 1, &[Fast], &[], &[Connection]).doc(
+            "cluster",
+            "3.0.0",
+            "Signals that a cluster client is following an -ASK redirect.",
+        ),
+        command("readonly", 1, &[Loading, Stale, Fast], &[], &[Connection]).doc(
+            "cluster",
+            "3.0.0",
+            "Enables read-only queries for a connection to a Redis Cluster replica node.",
+        ),
+        command("readwrite", 1, &[Loading, Stale, Fast], &[], &[Connection]).doc(
+            "cluster",
+            "3.0.0",
+            "Enables read-write queries for a connection to a Redis Cluster replica node.",
+        ),
+        command("wait", 3, &[NoScript], &[], &[Connection]).doc(
+            "generic",
+            "3.0.0",
+            "Blocks until the asynchronous replication of all preceding write commands sent by \
+             the connection is completed.",
+        ),
+        command("get", 2, &[ReadOnly, Fast], READ, &[String]).doc(
+            "string",
+            "1.0.0",
+            "Returns the string value of a key.",
+        ),
+        command("set", -3, &[Write, DenyOom], READ_WRITE, &[String]).doc(
+            "string",
+            "1.0.0",
+            "Sets the string value of a key, ignoring its type. The key is created if it doesn't \
+             exist.",
+        ),
+        command("expire", -3, &[Write, Fast], UPDATE, &[Keyspace]).doc(
+            "generic",
+            "1.0.0",
+            "Sets the expiration time of a key in seconds.",
+        ),
+        command("pexpire", -3, &[Write, Fast], UPDATE, &[Keyspace]).doc(
+            "generic",
+            "2.6.0",
+            "Sets the expiration time of a key in milliseconds.",
+        ),
+        command("expireat", -3, &[Write, Fast], UPDATE, &[Keyspace]).doc(
+            "generic",
+            "1.2.0",
+            "Sets the expiration time of a key to a Unix timestamp.",
+        ),
+        command("pexpireat", -3, &[Write, Fast], UPDATE, &[Keyspace]).doc(
+            "generic",
+            "2.6.0",
+            "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+        ),
+        command("ttl", 2, &[ReadOnly, Fast], READ, &[Keyspace]).doc(
+            "generic",
+            "1.0.0",
+            "Returns the expiration time in seconds of a key.",
+        ),
+        command("pttl", 2, &[ReadOnly, Fast], READ, &[Keyspace]).doc(
+            "generic",
+            "2.6.0",
+            "Returns the expiration time in milliseconds of a key.",
+        ),
+        command("expiretime", 2, &[ReadOnly, Fast], READ, &[Keyspace]).doc(
+            "generic",
+            "7.0.0",
+            "Returns the expiration time of a key as a Unix timestamp.",
+        ),
+        command("pexpiretime", 2, &[ReadOnly, Fast], READ, &[Keyspace]).doc(
+            "generic",
+            "7.0.0",
+            "Returns the expiration time of a key as a Unix milliseconds timestamp.",
+        ),
+        command("persist", 2, &[Write, Fast], UPDATE, &[Keyspace]).doc(
+            "generic",
+            "2.2.0",
+            "Removes the expiration time of a key.",
+        ),
+        command("scan", -2, &[ReadOnly], &[], &[Keyspace]).doc(
+            "generic",
+            "2.8.0",
+            "Iterates over the key names in the database.",
+        ),
+        command(
+            "del",
+            -2,
+            &[Write],
+            &[keys(1, -1, &[Rm, Delete])],
+            &[Keyspace],
+        )
+        .doc("generic", "1.0.0", "Deletes one or more keys."),
+        command(
+            "unlink",
+            -2,
+            &[Write, Fast],
+            &[keys(1, -1, &[Rm, Delete])],
+            &[Keyspace],
+        )
+        .doc(
+            "generic",
+            "4.0.0",
+            "Asynchronously deletes one or more keys.",
+        ),
+        command(
+            "touch",
+            -2,
+            &[ReadOnly, Fast],
+            &[keys(1, -1, &[Ro])],
+            &[Keyspace],
+        )
+        .doc(
+            "generic",
+            "3.2.1",
+            "Returns the number of existing keys out of those specified after updating the time \
+             they were last accessed.",
+        ),
+        command("dump", 2, &[ReadOnly], READ, &[Keyspace]).doc(
+            "generic",
+            "2.6.0",
+            "Returns a serialized representation of the value stored at a key.",
+        ),
+        command(
+            "restore",
+            -4,
+            &[Write, DenyOom],
+            &[key(1, &[Ow, Update])],
+            &[Keyspace, Dangerous],
+        )
+        .doc(
+            "generic",
+            "2.6.0",
+            "Creates a key from the serialized representation of a value.",
+        ),
+        command("object", -2, &[], &[], &[Keyspace, Read]).doc(
+            "generic",
+            "2.2.3",
+            "A container for object introspection commands.",
+        ),
+        command("move", 3, &[Write, Fast], POP, &[Keyspace]).doc(
+            "generic",
+            "1.0.0",
+            "Moves a key to another database.",
+        ),
+        command(
+            "sort",
+            -2,
+            &[Write, DenyOom],
+            &[
+                key(1, &[Ro, Access]),
+                KeySpec {
+                    begin: BeginSearch::Keyword {
+                        keyword: "STORE",
+                        start_from: 1,
+                    },
+                    find: FindKeys::Range {
+                        last_key: 0,
+                        step: 1,
+                        limit: 0,
+                    },
+                    flags: &[Ow, Update],
+                },
+            ],
+            &[Set, SortedSet, List, Dangerous],
+        )
+        .doc(
+            "generic",
+            "1.0.0",
+            "Sorts the elements in a list, a set, or a sorted set, optionally storing the result.",
+        ),
+        command("sort_ro", -2, &[ReadOnly], READ, &[Set, SortedSet, List]).doc(
+            "generic",
+            "7.0.0",
+            "Returns the sorted elements of a list, a set, or a sorted set.",
+        ),
+        command("flushdb", -1, &[Write], &[], &[Keyspace, Dangerous]).doc(
+            "server",
+            "1.0.0",
+            "Removes all keys from the current database.",
+        ),
+        command("flushall", -1, &[Write], &[], &[Keyspace, Dangerous]).doc(
+            "server",
+            "1.0.0",
+            "Removes all keys from all databases.",
+        ),
+        command("save", 1, &[Admin, NoScript], &[], &[]).doc(
+            "server",
+            "1.0.0",
+            "Synchronously saves the database(s) to disk.",
+        ),
+        command("bgsave", -1, &[Admin, NoScript], &[], &[]).doc(
+            "server",
+            "1.0.0",
+            "Asynchronously saves the database(s) to disk.",
+        ),
+        command(
+            "lastsave",
+            1,
+            &[Loading, Stale, Fast],
+            &[],
+            &[Category::Admin, Dangerous],
+        )
+        .doc(
+            "server",
+            "1.0.0",
+            "Returns the Unix timestamp of the last successful save to disk.",
+        ),
+        command("bgrewriteaof", 1, &[Admin, NoScript], &[], &[]).doc(
+            "server",
+            "1.0.0",
+            "Asynchronously rewrites the append-only file to disk.",
+        ),
+        command("replconf", -1, &[Admin, NoScript, Loading, Stale], &[], &[]).doc(
+            "server",
+            "3.0.0",
+            "An internal command for configuring the replication stream.",
+        ),
+        command("replicaof", 3, &[Admin, NoScript, Stale], &[], &[]).doc(
+            "server",
+            "5.0.0",
+            "Configures a server as replica of another, or promotes it to a master.",
+        ),
+        command("slaveof", 3, &[Admin, NoScript, Stale], &[], &[]).doc(
+            "server",
+            "1.0.0",
+            "Sets a Redis server as a replica of another, or promotes it to being a master.",
+        ),
+        command("psync", -3, &[Admin, NoScript], &[], &[]).doc(
+            "server",
+            "2.8.0",
+            "An internal command used in replication.",
+        ),
+        command("info", -1, &[Loading, Stale], &[], &[Dangerous]).doc(
+            "server",
+            "1.0.0",
+            "Returns information and statistics about the server.",
+        ),
+        command("config", -2, &[], &[], &[Category::Admin, Dangerous]).doc(
+            "server",
+            "2.0.0",
+            "A container for server configuration commands.",
+        ),
+        command("acl", -2, &[], &[], &[Category::Admin, Dangerous]).doc(
+            "server",
+            "6.0.0",
+            "A container for Access List Control commands.",
+        ),
+        command("command", -1, &[Loading, Stale], &[], &[Connection]).doc(
+            "server",
+            "2.8.13",
+            "Returns detailed information about all commands.",
+        ),
+        command("memory", -2, &[], &[], &[]).doc(
+            "server",
+            "4.0.0",
+            "A container for memory diagnostics commands.",
+        ),
+        command("debug", -2, &[Admin, NoScript, Loading, Stale], &[], &[]).doc(
+            "server",
+            "1.0.0",
+            "A container for debugging commands.",
+        ),
+        command("latency", -2, &[], &[], &[Category::Admin, Dangerous]).doc(
+            "server",
+            "2.8.13",
+            "A container for latency diagnostics commands.",
+        ),
+        command("cluster", -2, &[], &[], &[]).doc(
+            "cluster",
+            "3.0.0",
+            "A container for Redis Cluster commands.",
+        ),
+        command(
+            "subscribe",
+            -2,
+            &[PubSub, NoScript, Loading, Stale],
+            &[],
+            &[],
+        )
+        .doc(
+            "pubsub",
+            "2.0.0",
+            "Listens for messages published to channels.",
+        ),
+        command(
+            "unsubscribe",
+            -1,
+            &[PubSub, NoScript, Loading, Stale],
+            &[],
+            &[],
+        )
+        .doc(
+            "pubsub",
+            "2.0.0",
+            "Stops listening to messages posted to channels.",
+        ),
+        command(
+            "psubscribe",
+            -2,
+            &[PubSub, NoScript, Loading, Stale],
+            &[],
+            &[],
+        )
+        .doc(
+            "pubsub",
+            "2.0.0",
+            "Listens for messages published to channels that match one or more patterns.",
+        ),
+        command(
+            "punsubscribe",
+            -1,
+            &[PubSub, NoScript, Loading, Stale],
+            &[],
+            &[],
+        )
+        .doc(
+            "pubsub",
+            "2.0.0",
+            "Stops listening to messages published to channels that match one or more patterns.",
+        ),
+        command(
+            "publish",
+            3,
+            &[PubSub, Loading, Stale, Fast, MayReplicate],
+            &[],
+            &[],
+        )
+        .doc("pubsub", "2.0.0", "Posts a message to a channel."),
+        command("script", -2, &[], &[], &[Scripting]).doc(
+            "scripting",
+            "2.6.0",
+            "A container for Lua scripts management commands.",
+        ),
+        command(
+            "eval",
+            -3,
+            &[NoScript, Stale, MayReplicate, NoMandatoryKeys],
+            SCRIPT,
+            &[Scripting],
+        )
+        .doc("scripting", "2.6.0", "Executes a server-side Lua script."),
+        command(
+            "eval_ro",
+            -3,
+            &[NoScript, Stale, NoMandatoryKeys, ReadOnly],
+            READ_ONLY_SCRIPT,
+            &[Scripting],
+        )
+        .doc(
+            "scripting",
+            "7.0.0",
+            "Executes a read-only server-side Lua script.",
+        ),
+        command(
+            "evalsha",
+            -3,
+            &[NoScript, Stale, MayReplicate, NoMandatoryKeys],
+            SCRIPT,
+            &[Scripting],
+        )
+        .doc(
+            "scripting",
+            "2.6.0",
+            "Executes a server-side Lua script by SHA1 digest.",
+        ),
+        command(
+            "evalsha_ro",
+            -3,
+            &[NoScript, Stale, NoMandatoryKeys, ReadOnly],
+            READ_ONLY_SCRIPT,
+            &[Scripting],
+        )
+        .doc(
+            "scripting",
+            "7.0.0",
+            "Executes a read-only server-side Lua script by SHA1 digest.",
+        ),
+        command(
+            "fcall",
+            -3,
+            &[NoScript, Stale, MayReplicate, NoMandatoryKeys],
+            SCRIPT,
+            &[Scripting],
+        )
+        .doc("scripting", "7.0.0", "Invokes a function."),
+        command(
+            "fcall_ro",
+            -3,
+            &[NoScript, Stale, NoMandatoryKeys, ReadOnly],
+            READ_ONLY_SCRIPT,
+            &[Scripting],
+        )
+        .doc("scripting", "7.0.0", "Invokes a read-only function."),
+        command("lpush", -3, &[Write, DenyOom, Fast], INSERT, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Prepends one or more elements to a list. Creates the key if it doesn't exist.",
+        ),
+        command("rpush", -3, &[Write, DenyOom, Fast], INSERT, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Appends one or more elements to a list. Creates the key if it doesn't exist.",
+        ),
+        command("lpop", -2, &[Write, Fast], POP, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Returns the first elements in a list after removing it. Deletes the list if the \
+             last element was popped.",
+        ),
+        command("rpop", -2, &[Write, Fast], POP, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Returns and removes the last elements of the list. Deletes the list if the last \
+             element was popped.",
+        ),
+        command(
+            "lmpop",
+            -4,
+            &[Write],
+            &[key_num(1, &[Rw, Access, Delete])],
+            &[List],
+        )
+        .doc(
+            "list",
+            "7.0.0",
+            "Returns multiple elements from a list after removing them. Deletes the list if the \
+             last element was popped.",
+        ),
+        command(
+            "blmpop",
+            -5,
+            &[Write, Blocking],
+            &[key_num(2, &[Rw, Access, Delete])],
+            &[List],
+        )
+        .doc(
+            "list",
+            "7.0.0",
+            "Pops the first element from one of multiple lists. Blocks until an element is \
+             available otherwise. Deletes the list if the last element was popped.",
+        ),
+        command(
+            "blpop",
+            -3,
+            &[Write, Blocking],
+            &[keys(1, -2, &[Rw, Access, Delete])],
+            &[List],
+        )
+        .doc(
+            "list",
+            "2.0.0",
+            "Removes and returns the first element in a list. Blocks until an element is \
+             available otherwise. Deletes the list if the last element was popped.",
+        ),
+        command(
+            "brpop",
+            -3,
+            &[Write, Blocking],
+            &[keys(1, -2, &[Rw, Access, Delete])],
+            &[List],
+        )
+        .doc(
+            "list",
+            "2.0.0",
+            "Removes and returns the last element in a list. Blocks until an element is \
+             available otherwise. Deletes the list if the last element was popped.",
+        ),
+        command("lmove", 5, &[Write, DenyOom], MOVE_ELEMENT, &[List]).doc(
+            "list",
+            "6.2.0",
+            "Returns an element after popping it from one list and pushing it to another. \
+             Deletes the list if the last element was moved.",
+        ),
+        command(
+            "blmove",
+            6,
+            &[Write, DenyOom, Blocking],
+            MOVE_ELEMENT,
+            &[List],
+        )
+        .doc(
+            "list",
+            "6.2.0",
+            "Pops an element from a list, pushes it to another list and returns it. Blocks \
+             until an element is available otherwise. Deletes the list if the last element was \
+             moved.",
+        ),
+        command("llen", 2, &[ReadOnly, Fast], READ_META, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Returns the length of a list.",
+        ),
+        command("lrange", 4, &[ReadOnly], READ, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Returns a range of elements from a list.",
+        ),
+        command("ltrim", 4, &[Write], DELETE, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Removes elements from both ends a list. Deletes the list if all elements were \
+             trimmed.",
+        ),
+        command("lindex", 3, &[ReadOnly], READ, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Returns an element from a list by its index.",
+        ),
+        command("lset", 4, &[Write, DenyOom], UPDATE, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Sets the value of an element in a list by its index.",
+        ),
+        command("linsert", 5, &[Write, DenyOom], INSERT, &[List]).doc(
+            "list",
+            "2.2.0",
+            "Inserts an element before or after another element in a list.",
+        ),
+        command("lrem", 4, &[Write], DELETE, &[List]).doc(
+            "list",
+            "1.0.0",
+            "Removes elements from a list. Deletes the list if the last element was removed.",
+        ),
+        command("hset", -4, &[Write, DenyOom, Fast], UPDATE, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Creates or modifies the value of a field in a hash.",
+        ),
+        command("hsetnx", 4, &[Write, DenyOom, Fast], INSERT, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Sets the value of a field in a hash only when the field doesn't exist.",
+        ),
+        command("hget", 3, &[ReadOnly, Fast], READ, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Returns the value of a field in a hash.",
+        ),
+        command("hmget", -3, &[ReadOnly, Fast], READ, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Returns the values of all fields in a hash.",
+        ),
+        command("hdel", -3, &[Write, Fast], DELETE, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Deletes one or more fields and their values from a hash. Deletes the hash if no \
+             fields remain.",
+        ),
+        command("hgetall", 2, &[ReadOnly], READ, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Returns all fields and values in a hash.",
+        ),
+        command("hkeys", 2, &[ReadOnly], READ, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Returns all fields in a hash.",
+        ),
+        command("hvals", 2, &[ReadOnly], READ, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Returns all values in a hash.",
+        ),
+        command("hlen", 2, &[ReadOnly, Fast], READ_META, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Returns the number of fields in a hash.",
+        ),
+        command("hexists", 3, &[ReadOnly, Fast], READ_META, &[Hash]).doc(
+            "hash",
+            "2.0.0",
+            "Determines whether a field exists in a hash.",
+        ),
+        command("hstrlen", 3, &[ReadOnly, Fast], READ_META, &[Hash]).doc(
+            "hash",
+            "3.2.0",
+            "Returns the length of the value of a field.",
+        ),
+        command("hscan", -3, &[ReadOnly], READ, &[Hash]).doc(
+            "hash",
+            "2.8.0",
+            "Iterates over fields and values of a hash.",
+        ),
+        command("sadd", -3, &[Write, DenyOom, Fast], INSERT, &[Set]).doc(
+            "set",
+            "1.0.0",
+            "Adds one or more members to a set. Creates the key if it doesn't exist.",
+        ),
+        command("srem", -3, &[Write, Fast], DELETE, &[Set]).doc(
+            "set",
+            "1.0.0",
+            "Removes one or more members from a set. Deletes the set if the last member was \
+             removed.",
+        ),
+        command("smembers", 2, &[ReadOnly], READ, &[Set]).doc(
+            "set",
+            "1.0.0",
+            "Returns all members of a set.",
+        ),
+        command("sismember", 3, &[ReadOnly, Fast], READ_META, &[Set]).doc(
+            "set",
+            "1.0.0",
+            "Determines whether a member belongs to a set.",
+        ),
+        command("smismember", -3, &[ReadOnly, Fast], READ_META, &[Set]).doc(
+            "set",
+            "6.2.0",
+            "Determines whether multiple members belong to a set.",
+        ),
+        command("scard", 2, &[ReadOnly, Fast], READ_META, &[Set]).doc(
+            "set",
+            "1.0.0",
+            "Returns the number of members in a set.",
+        ),
+        command(
+            "sintercard",
+            -3,
+            &[ReadOnly],
+            &[key_num(1, &[Ro, Access])],
+            &[Set],
+        )
+        .doc(
+            "set",
+            "7.0.0",
+            "Returns the number of members of the intersect of multiple sets.",
+        ),
+        command("sscan", -3, &[ReadOnly], READ, &[Set]).doc(
+            "set",
+            "2.8.0",
+            "Iterates over members of a set.",
+        ),
+        command(
+            "sinter",
+            -2,
+            &[ReadOnly],
+            &[keys(1, -1, &[Ro, Access])],
+            &[Set],
+        )
+        .doc("set", "1.0.0", "Returns the intersect of multiple sets."),
+        command(
+            "sunion",
+            -2,
+            &[ReadOnly],
+            &[keys(1, -1, &[Ro, Access])],
+            &[Set],
+        )
+        .doc("set", "1.0.0", "Returns the union of multiple sets."),
+        command(
+            "sdiff",
+            -2,
+            &[ReadOnly],
+            &[keys(1, -1, &[Ro, Access])],
+            &[Set],
+        )
+        .doc("set", "1.0.0", "Returns the difference of multiple sets."),
+        command("sinterstore", -3, &[Write, DenyOom], STORE_SET, &[Set]).doc(
+            "set",
+            "1.0.0",
+            "Stores the intersect of multiple sets in a key.",
+        ),
+        command("sunionstore", -3, &[Write, DenyOom], STORE_SET, &[Set]).doc(
+            "set",
+            "1.0.0",
+            "Stores the union of multiple sets in a key.",
+        ),
+        command("sdiffstore", -3, &[Write, DenyOom], STORE_SET, &[Set]).doc(
+            "set",
+            "1.0.0",
+            "Stores the difference of multiple sets in a key.",
+        ),
+        command("zadd", -4, &[Write, DenyOom, Fast], UPDATE, &[SortedSet]).doc(
+            "sorted_set",
+            "1.2.0",
+            "Adds one or more members to a sorted set, or updates their scores. Creates the key \
+             if it doesn't exist.",
+        ),
+        command("zincrby", 4, &[Write, DenyOom, Fast], UPDATE, &[SortedSet]).doc(
+            "sorted_set",
+            "1.2.0",
+            "Increments the score of a member in a sorted set.",
+        ),
+        command("zrem", -3, &[Write, Fast], DELETE, &[SortedSet]).doc(
+            "sorted_set",
+            "1.2.0",
+            "Removes one or more members from a sorted set. Deletes the sorted set if all \
+             members were removed.",
+        ),
+        command("zscore", 3, &[ReadOnly, Fast], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "1.2.0",
+            "Returns the score of a member in a sorted set.",
+        ),
+        command("zmscore", -3, &[ReadOnly, Fast], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "6.2.0",
+            "Returns the score of one or more members in a sorted set.",
+        ),
+        command("zcard", 2, &[ReadOnly, Fast], READ_META, &[SortedSet]).doc(
+            "sorted_set",
+            "1.2.0",
+            "Returns the number of members in a sorted set.",
+        ),
+        command("zcount", 4, &[ReadOnly, Fast], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "2.0.0",
+            "Returns the count of members in a sorted set that have scores within a range.",
+        ),
+        command("zrank", -3, &[ReadOnly, Fast], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "2.0.0",
+            "Returns the index of a member in a sorted set ordered by ascending scores.",
+        ),
+        command("zrevrank", -3, &[ReadOnly, Fast], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "2.0.0",
+            "Returns the index of a member in a sorted set ordered by descending scores.",
+        ),
+        command("zrange", -4, &[ReadOnly], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "1.2.0",
+            "Returns members in a sorted set within a range of indexes.",
+        ),
+        command("zrevrange", -4, &[ReadOnly], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "1.2.0",
+            "Returns members in a sorted set within a range of indexes in reverse order.",
+        ),
+        command("zrangebyscore", -4, &[ReadOnly], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "1.0.5",
+            "Returns members in a sorted set within a range of scores.",
+        ),
+        command("zrevrangebyscore", -4, &[ReadOnly], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "2.2.0",
+            "Returns members in a sorted set within a range of scores in reverse order.",
+        ),
+        command("zrangebylex", -4, &[ReadOnly], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "2.8.9",
+            "Returns members in a sorted set within a lexicographical range.",
+        ),
+        command("zrevrangebylex", -4, &[ReadOnly], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "2.8.9",
+            "Returns members in a sorted set within a lexicographical range in reverse order.",
+        ),
+        command("zrandmember", -2, &[ReadOnly], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "6.2.0",
+            "Returns one or more random members from a sorted set.",
+        ),
+        command("zscan", -3, &[ReadOnly], READ, &[SortedSet]).doc(
+            "sorted_set",
+            "2.8.0",
+            "Iterates over members and scores of a sorted set.",
+        ),
+        command(
+            "zinter",
+            -3,
+            &[ReadOnly],
+            &[key_num(1, &[Ro, Access])],
+            &[SortedSet],
+        )
+        .doc(
+            "sorted_set",
+            "6.2.0",
+            "Returns the intersect of multiple sorted sets.",
+        ),
+        command(
+            "zunion",
+            -3,
+            &[ReadOnly],
+            &[key_num(1, &[Ro, Access])],
+            &[SortedSet],
+        )
+        .doc(
+            "sorted_set",
+            "6.2.0",
+            "Returns the union of multiple sorted sets.",
+        ),
+        command(
+            "zdiff",
+            -3,
+            &[ReadOnly],
+            &[key_num(1, &[Ro, Access])],
+            &[SortedSet],
+        )
+        .doc(
+            "sorted_set",
+            "6.2.0",
+            "Returns the difference between multiple sorted sets.",
+        ),
+        command(
+            "zinterstore",
+            -4,
+            &[Write, DenyOom],
+            STORE_SORTED_SET,
+            &[SortedSet],
+        )
+        .doc(
+            "sorted_set",
+            "2.0.0",
+            "Stores the intersect of multiple sorted sets in a key.",
+        ),
+        command(
+            "zunionstore",
+            -4,
+            &[Write, DenyOom],
+            STORE_SORTED_SET,
+            &[SortedSet],
+        )
+        .doc(
+            "sorted_set",
+            "2.0.0",
+            "Stores the union of multiple sorted sets in a key.",
+        ),
+        command(
+            "zdiffstore",
+            -4,
+            &[Write, DenyOom],
+            STORE_SORTED_SET,
+            &[SortedSet],
+        )
+        .doc(
+            "sorted_set",
+            "6.2.0",
+            "Stores the difference of multiple sorted sets in a key.",
+        ),
+        command("setbit", 4, &[Write, DenyOom], READ_WRITE, &[Bitmap]).doc(
+            "bitmap",
+            "2.2.0",
+            "Sets or clears the bit at offset of the string value. Creates the key if it doesn't \
+             exist.",
+        ),
+        command("getbit", 3, &[ReadOnly, Fast], READ, &[Bitmap]).doc(
+            "bitmap",
+            "2.2.0",
+            "Returns a bit value by offset.",
+        ),
+        command("bitcount", -2, &[ReadOnly], READ, &[Bitmap]).doc(
+            "bitmap",
+            "2.6.0",
+            "Counts the number of set bits (population counting) in a string.",
+        ),
+        command("bitpos", -3, &[ReadOnly], READ, &[Bitmap]).doc(
+            "bitmap",
+            "2.8.7",
+            "Finds the first set (1) or clear (0) bit in a string.",
+        ),
+        command("xadd", -5, &[Write, DenyOom, Fast], UPDATE, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Appends a new message to a stream. Creates the key if it doesn't exist.",
+        ),
+        command("xlen", 2, &[ReadOnly, Fast], READ_META, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Return the number of messages in a stream.",
+        ),
+        command("xrange", -4, &[ReadOnly], READ, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Returns the messages from a stream within a range of IDs.",
+        ),
+        command("xrevrange", -4, &[ReadOnly], READ, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Returns the messages from a stream within a range of IDs in reverse order.",
+        ),
+        command(
+            "xread",
+            -4,
+            &[ReadOnly, Blocking],
+            &[streams(1, &[Ro, Access])],
+            &[Stream],
+        )
+        .doc(
+            "stream",
+            "5.0.0",
+            "Returns messages from multiple streams with IDs greater than the ones requested. \
+             Blocks until a message is available otherwise.",
+        ),
+        command("xgroup", -2, &[], &[], &[Category::Write, Stream]).doc(
+            "stream",
+            "5.0.0",
+            "A container for consumer groups commands.",
+        ),
+        command(
+            "xreadgroup",
+            -7,
+            &[Write, Blocking],
+            &[streams(4, &[Rw, Access])],
+            &[Stream],
+        )
+        .doc(
+            "stream",
+            "5.0.0",
+            "Returns new or historical messages from a stream for a consumer in a group. Blocks \
+             until a message is available otherwise.",
+        ),
+        command("xack", -4, &[Write, Fast], UPDATE, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Returns the number of messages that were successfully acknowledged by the consumer \
+             group member of a stream.",
+        ),
+        command("xpending", -3, &[ReadOnly], READ, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Returns the information and entries from a stream consumer group's pending entries \
+             list.",
+        ),
+        command("xclaim", -6, &[Write, Fast], UPDATE, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Changes, or acquires, ownership of a message in a consumer group, as if the message \
+             was delivered a consumer group member.",
+        ),
+        command("xautoclaim", -6, &[Write, Fast], UPDATE, &[Stream]).doc(
+            "stream",
+            "6.2.0",
+            "Changes, or acquires, ownership of messages in a consumer group, as if the messages \
+             were delivered to as consumer group member.",
+        ),
+        command("xtrim", -4, &[Write], DELETE, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Deletes messages from the beginning of a stream.",
+        ),
+        command("xdel", -3, &[Write, Fast], DELETE, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Returns the number of messages after removing them from a stream.",
+        ),
+        command("xinfo", -2, &[], &[], &[Read, Stream]).doc(
+            "stream",
+            "5.0.0",
+            "A container for stream introspection commands.",
+        ),
+        command("geoadd", -5, &[Write, DenyOom], UPDATE, &[Geo]).doc(
+            "geo",
+            "3.2.0",
+            "Adds one or more members to a geospatial index. The key is created if it doesn't \
+             exist.",
+        ),
+        command("geosearch", -7, &[ReadOnly], READ, &[Geo]).doc(
+            "geo",
+            "6.2.0",
+            "Queries a geospatial index for members inside an area of a box or a circle.",
+        ),
+        command("geodist", -4, &[ReadOnly], READ, &[Geo]).doc(
+            "geo",
+            "3.2.0",
+            "Returns the distance between two members of a geospatial index.",
+        ),
+        command("geopos", -2, &[ReadOnly], READ, &[Geo]).doc(
+            "geo",
+            "3.2.0",
+            "Returns the longitude and latitude of members from a geospatial index.",
+        ),
+        command("geohash", -2, &[ReadOnly], READ, &[Geo]).doc(
+            "geo",
+            "3.2.0",
+            "Returns members from a geospatial index as geohash strings.",
+        ),
+    ]
+};
+
+/// The subcommands that take keys. `COMMAND GETKEYS` finds the keys of a
+/// container command's subcommands with these.
+pub const SUBCOMMANDS: &[CommandSpec] = {
+    use Category::{Keyspace, Read, Stream};
+    use Flag::{DenyOom, ReadOnly, Write};
+    use KeyFlag::{Access, Insert, Ro, Rw};
+    const KEY: &[KeySpec] = &[key(2, &[Ro])];
+    const STREAM: &[KeySpec] = &[key(2, &[Ro, Access])];
+    &[
+        command("object|encoding", 3, &[ReadOnly], KEY, &[Keyspace, Read]).doc(
+            "generic",
+            "2.2.3",
+            "Returns the internal encoding of a Redis object.",
+        ),
+        command("object|refcount", 3, &[ReadOnly], KEY, &[Keyspace, Read]).doc(
+            "generic",
+            "2.2.3",
+            "Returns the reference count of a value of a key.",
+        ),
+        command("object|idletime", 3, &[ReadOnly], KEY, &[Keyspace, Read]).doc(
+            "generic",
+            "2.2.3",
+            "Returns the time since the last access to a Redis object.",
+        ),
+        command("object|freq", 3, &[ReadOnly], KEY, &[Keyspace, Read]).doc(
+            "generic",
+            "4.0.0",
+            "Returns the logarithmic access frequency counter of a Redis object.",
+        ),
+        command("memory|usage", -3, &[ReadOnly], KEY, &[]).doc(
+            "server",
+            "4.0.0",
+            "Estimates the memory usage of a key.",
+        ),
+        command(
+            "xgroup|create",
+            -5,
+            &[Write, DenyOom],
+            &[key(2, &[Rw, Insert])],
+            &[Stream],
+        )
+        .doc("stream", "5.0.0", "Creates a consumer group."),
+        command("xinfo|stream", -3, &[ReadOnly], STREAM, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Returns information about a stream.",
+        ),
+        command("xinfo|groups", 3, &[ReadOnly], STREAM, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Returns a list of the consumer groups of a stream.",
+        ),
+        command("xinfo|consumers", 4, &[ReadOnly], STREAM, &[Stream]).doc(
+            "stream",
+            "5.0.0",
+            "Returns a list of the consumers in a consumer group.",
+        ),
+    ]
+};
+
+/// Looks up the command `args` call, which start with its name: the
+/// subcommand if it's one of `SUBCOMMANDS`, and otherwise the command.
+pub fn resolve(args: &[&[u8]]) -> Option<&'static CommandSpec> {
+    let name = std::str::from_utf8(args.first()?).ok()?;
+    let spec = find(name)?;
+    let subcommand = args
+        .get(1)
+        .and_then(|subcommand| std::str::from_utf8(subcommand).ok())
+        .map(|subcommand| format!("{}|{}", spec.name, subcommand.to_lowercase()))
+        .and_then(|name| SUBCOMMANDS.iter().find(|sub| sub.name == name));
+    Some(subcommand.unwrap_or(spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categories() {
+        let names = |spec: &CommandSpec| -> Vec<&str> {
+            spec.categories().into_iter().map(Category::name).collect()
+        };
+        assert_eq!(names(find("GET").unwrap()), ["read", "string", "fast"]);
+        assert_eq!(names(find("save").unwrap()), ["admin", "slow", "dangerous"]);
+        // Read-only scripts aren't counted as reads.
+        assert_eq!(names(find("eval_ro").unwrap()), ["slow", "scripting"]);
+        assert!(find("nope").is_none());
+    }
+
+    #[test]
+    fn key_specs() {
+        fn found_keys(args: &str) -> Vec<(&str, Vec<&str>)> {
+            let args: Vec<&[u8]> = args.split(' ').map(str::as_bytes).collect();
+            resolve(&args)
+                .unwrap()
+                .find_keys(&args)
+                .into_iter()
+                .map(|(index, flags)| {
+                    let key = std::str::from_utf8(args[index]).unwrap();
+                    (key, flags.iter().map(|flag| flag.name()).collect())
+                })
+                .collect()
+        }
+        assert_eq!(found_keys("get k"), [("k", vec!["RO", "access"])]);
+        assert_eq!(
+            found_keys("lmove a b LEFT RIGHT"),
+            [
+                ("a", vec!["RW", "access", "delete"]),
+                ("b", vec!["RW", "insert"])
+            ]
+        );
+        assert_eq!(
+            found_keys("blpop a b 0"),
+            [
+                ("a", vec!["RW", "access", "delete"]),
+                ("b", vec!["RW", "access", "delete"])
+            ]
+        );
+        assert_eq!(
+            found_keys("sort k by w store dest"),
+            [("k", vec!["RO", "access"]), ("dest", vec!["OW", "update"])]
+        );
+        assert_eq!(found_keys("sort k"), [("k", vec!["RO", "access"])]);
+        assert_eq!(
+            found_keys("eval script 2 a b arg"),
+            [
+                ("a", vec!["RW", "access", "update"]),
+                ("b", vec!["RW", "access", "update"])
+            ]
+        );
+        assert_eq!(found_keys("eval script 0 arg"), []);
+        assert_eq!(
+            found_keys("xread count 1 streams a b 0 0"),
+            [("a", vec!["RO", "access"]), ("b", vec!["RO", "access"])]
+        );
+        assert_eq!(found_keys("object encoding k"), [("k", vec!["RO"])]);
+        assert_eq!(found_keys("object help"), []);
+
+        // Only commands whose keys aren't at fixed positions have
+        // movablekeys.
+        let movable = |name| find(name).unwrap().flags().contains(&Flag::MovableKeys);
+        assert!(!movable("blpop"));
+        assert!(movable("sort"));
+        assert!(movable("zunionstore"));
+        assert_eq!(find("blpop").unwrap().legacy_key_range(), (1, -2, 1));
+        assert_eq!(find("sunionstore").unwrap().legacy_key_range(), (1, -1, 1));
+        assert_eq!(find("ping").unwrap().legacy_key_range(), (0, 0, 0));
+    }
+}
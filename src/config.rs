@@ -0,0 +1,1145 @@
+//! Server settings, which clients read and change at runtime with `CONFIG
+//! GET` and `CONFIG SET`, and save to the config file with `CONFIG REWRITE`.
+//! See <https://redis.io/docs/management/config/>.
+//!
+//! Each setting is a parameter with Redis' name, which is parsed from and
+//! formatted as a string the same way Redis does. Only parameters for
+//! features this server has are supported.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+
+use crate::acl::DEFAULT_LOG_MAX_LEN;
+use crate::aof::{AofConfig, FsyncPolicy};
+use crate::debug::DebugAccess;
+use crate::evict::{EvictionPolicy, MaxMemoryConfig};
+use crate::glob;
+use crate::resp::Limits;
+use crate::server::DEFAULT_DATABASES;
+use crate::snapshot::DEFAULT_SNAPSHOT_PATH;
+use crate::stats::DEFAULT_LATENCY_PERCENTILES;
+use crate::tls::{ClientAuth, TlsConfig};
+
+/// The port Redis listens on by default.
+pub const DEFAULT_PORT: u16 = 6379;
+
+/// An address from `bind`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindAddr {
+    pub host: String,
+
+    /// Whether the server starts without this address if it can't listen on
+    /// it, for addresses starting with `-`.
+    pub optional: bool,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    /// The addresses the server listens on, like Redis' `bind`. An address
+    /// starting with `-` is skipped if it isn't available. Without any, the
+    /// server binary listens on 127.0.0.1.
+    pub bind: Vec<String>,
+
+    /// The port the server listens on.
+    pub port: u16,
+
+    /// How many connections can wait to be accepted, like Redis'
+    /// `tcp-backlog`.
+    pub tcp_backlog: u32,
+
+    /// How often idle client connections are probed to check the client is
+    /// still there, or `None` to not probe them, like Redis' `tcp-keepalive`.
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Whether replies are sent right away instead of being batched into
+    /// fewer packets, by disabling Nagle's algorithm on client connections.
+    pub tcp_nodelay: bool,
+
+    /// The working directory, which relative paths like `dbfilename` are
+    /// relative to. The server binary changes to it when it starts.
+    pub dir: PathBuf,
+
+    /// Whether only loopback clients can connect when no `bind` address and
+    /// no password are set, like Redis' `protected-mode`.
+    pub protected_mode: bool,
+
+    /// The password clients must give to `AUTH`, if any.
+    pub requirepass: Option<String>,
+
+    /// The file users are loaded from when the server starts, and that `ACL
+    /// LOAD` and `ACL SAVE` use.
+    pub aclfile: Option<PathBuf>,
+
+    /// How many entries `ACL LOG` keeps.
+    pub acllog_max_len: usize,
+
+    /// Settings for the TLS listener.
+    pub tls: TlsConfig,
+
+    /// Whether the server binary runs in the background.
+    pub daemonize: bool,
+
+    /// Number of logical databases clients can `SELECT`.
+    pub databases: usize,
+
+    /// Limits on the size of client requests.
+    pub limits: Limits,
+
+    /// Where `SAVE` and `BGSAVE` write snapshots of the keyspace.
+    pub snapshot_path: PathBuf,
+
+    /// Whether write commands are logged to the append-only file.
+    pub appendonly: bool,
+
+    /// Settings for the append-only file, which apply when it's enabled.
+    pub aof: AofConfig,
+
+    /// How much memory the keyspace may use, and what to evict beyond that.
+    pub maxmemory: MaxMemoryConfig,
+
+    /// Whether command latencies are tracked for `INFO latencystats`, like
+    /// Redis' `latency-tracking`.
+    pub latency_tracking: bool,
+
+    /// The percentiles `INFO latencystats` reports.
+    pub latency_percentiles: Vec<f64>,
+
+    /// How long something has to take for the latency monitor to record it,
+    /// or `None` if the monitor is off.
+    pub latency_monitor_threshold: Option<Duration>,
+
+    /// Which clients may run `DEBUG`.
+    pub enable_debug_command: DebugAccess,
+
+    /// The file `CONFIG REWRITE` saves the settings to, if any.
+    pub config_file: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: Vec::new(),
+            port: DEFAULT_PORT,
+            tcp_backlog: 511,
+            tcp_keepalive: Some(Duration::from_mins(5)),
+            tcp_nodelay: true,
+            dir: PathBuf::from("."),
+            protected_mode: true,
+            requirepass: None,
+            aclfile: None,
+            acllog_max_len: DEFAULT_LOG_MAX_LEN,
+            tls: TlsConfig::default(),
+            daemonize: false,
+            databases: DEFAULT_DATABASES,
+            limits: Limits::default(),
+            snapshot_path: PathBuf::from(DEFAULT_SNAPSHOT_PATH),
+            appendonly: false,
+            aof: AofConfig::default(),
+            maxmemory: MaxMemoryConfig::default(),
+            latency_tracking: true,
+            latency_percentiles: DEFAULT_LATENCY_PERCENTILES.to_vec(),
+            latency_monitor_threshold: None,
+            enable_debug_command: DebugAccess::default(),
+            config_file: None,
+        }
+    }
+}
+
+/// A setting, as it's named and formatted in `CONFIG GET` and config files.
+struct Parameter {
+    name: &'static str,
+    get: fn(&ServerConfig) -> String,
+
+    /// Parses a value and applies it, or describes why it's invalid.
+    set: fn(&mut ServerConfig, &str) -> Result<(), String>,
+
+    /// Whether `CONFIG SET` can change the parameter. The rest can only be
+    /// set when the server starts.
+    mutable: bool,
+
+    /// Whether directives can give several values, which are joined with
+    /// spaces like the value for `CONFIG SET`.
+    multiple_values: bool,
+}
+
+const PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "bind",
+        get: |config| config.bind.join(" "),
+        set: |config, value| {
+            let addresses: Vec<String> = value.split_whitespace().map(String::from).collect();
+            if addresses.is_empty() {
+                return Err("bind needs at least one address".to_string());
+            }
+            config.bind = addresses;
+            Ok(())
+        },
+        // The listener is bound when the server starts.
+        mutable: false,
+        multiple_values: true,
+    },
+    Parameter {
+        name: "port",
+        get: |config| config.port.to_string(),
+        set: |config, value| {
+            config.port = parse_in_range(value, 0, i64::from(u16::MAX))?;
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "tcp-backlog",
+        get: |config| config.tcp_backlog.to_string(),
+        set: |config, value| {
+            config.tcp_backlog = parse_in_range(value, 0, i64::from(i32::MAX))?;
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "tcp-keepalive",
+        get: |config| {
+            config
+                .tcp_keepalive
+                .map_or(0, |interval| interval.as_secs())
+                .to_string()
+        },
+        set: |config, value| {
+            let seconds = parse_in_range(value, 0, i64::from(i32::MAX))?;
+            config.tcp_keepalive = (seconds > 0).then(|| Duration::from_secs(seconds));
+            Ok(())
+        },
+        // Socket options are set as connections are accepted, which happens
+        // outside the core.
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "tcp-nodelay",
+        get: |config| format_bool(config.tcp_nodelay),
+        set: |config, value| {
+            config.tcp_nodelay = parse_bool(value)?;
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "dir",
+        get: |config| config.dir.display().to_string(),
+        set: |config, value| {
+            if value.is_empty() {
+                return Err("dir can't be empty".to_string());
+            }
+            config.dir = PathBuf::from(value);
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "protected-mode",
+        get: |config| format_bool(config.protected_mode),
+        set: |config, value| {
+            config.protected_mode = parse_bool(value)?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "requirepass",
+        get: |config| config.requirepass.clone().unwrap_or_default(),
+        set: |config, value| {
+            // Like Redis, an empty password turns authentication off.
+            config.requirepass = (!value.is_empty()).then(|| value.to_string());
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "aclfile",
+        get: |config| format_path(config.aclfile.as_deref()),
+        set: |config, value| {
+            config.aclfile = parse_path(value);
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "acllog-max-len",
+        get: |config| config.acllog_max_len.to_string(),
+        set: |config, value| {
+            config.acllog_max_len = parse_in_range(value, 0, i64::from(i32::MAX))?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "tls-port",
+        get: |config| config.tls.port.to_string(),
+        set: |config, value| {
+            config.tls.port = parse_in_range(value, 0, i64::from(u16::MAX))?;
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "tls-cert-file",
+        get: |config| format_path(config.tls.cert_file.as_deref()),
+        set: |config, value| {
+            config.tls.cert_file = parse_path(value);
+            Ok(())
+        },
+        // Certificates are loaded when the TLS listener starts.
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "tls-key-file",
+        get: |config| format_path(config.tls.key_file.as_deref()),
+        set: |config, value| {
+            config.tls.key_file = parse_path(value);
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "tls-ca-cert-file",
+        get: |config| format_path(config.tls.ca_cert_file.as_deref()),
+        set: |config, value| {
+            config.tls.ca_cert_file = parse_path(value);
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "tls-auth-clients",
+        get: |config| config.tls.auth_clients.name().to_string(),
+        set: |config, value| {
+            config.tls.auth_clients = ClientAuth::parse(value)
+                .ok_or("argument(s) must be one of the following: yes, no, optional")?;
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "daemonize",
+        get: |config| format_bool(config.daemonize),
+        set: |config, value| {
+            config.daemonize = parse_bool(value)?;
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "databases",
+        get: |config| config.databases.to_string(),
+        set: |config, value| {
+            config.databases = parse_in_range(value, 1, i64::from(i32::MAX))?;
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "proto-max-bulk-len",
+        get: |config| config.limits.max_bulk_len.to_string(),
+        set: |config, value| {
+            config.limits.max_bulk_len = parse_memory(value)?;
+            Ok(())
+        },
+        // Client threads take a copy of the limits when they connect.
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "dbfilename",
+        get: |config| config.snapshot_path.display().to_string(),
+        set: |config, value| {
+            if value.is_empty() {
+                return Err("dbfilename can't be empty".to_string());
+            }
+            config.snapshot_path = PathBuf::from(value);
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "appendonly",
+        get: |config| format_bool(config.appendonly),
+        set: |config, value| {
+            config.appendonly = parse_bool(value)?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "appendfilename",
+        get: |config| config.aof.path.display().to_string(),
+        set: |config, value| {
+            if value.is_empty() {
+                return Err("appendfilename can't be empty".to_string());
+            }
+            config.aof.path = PathBuf::from(value);
+            Ok(())
+        },
+        mutable: false,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "appendfsync",
+        get: |config| config.aof.fsync.name().to_string(),
+        set: |config, value| {
+            config.aof.fsync = value
+                .parse::<FsyncPolicy>()
+                .map_err(|_| "argument(s) must be one of the following: always, everysec, no")?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "aof-use-rdb-preamble",
+        get: |config| format_bool(config.aof.use_rdb_preamble),
+        set: |config, value| {
+            config.aof.use_rdb_preamble = parse_bool(value)?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "aof-load-truncated",
+        get: |config| format_bool(config.aof.load_truncated),
+        set: |config, value| {
+            config.aof.load_truncated = parse_bool(value)?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "maxmemory",
+        get: |config| config.maxmemory.limit.to_string(),
+        set: |config, value| {
+            config.maxmemory.limit = parse_memory(value)?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "maxmemory-policy",
+        get: |config| config.maxmemory.policy.name().to_string(),
+        set: |config, value| {
+            config.maxmemory.policy = EvictionPolicy::parse(value).ok_or(
+                "argument(s) must be one of the following: volatile-lru, allkeys-lru, \
+                 volatile-lfu, allkeys-lfu, volatile-random, allkeys-random, noeviction",
+            )?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "maxmemory-samples",
+        get: |config| config.maxmemory.samples.to_string(),
+        set: |config, value| {
+            config.maxmemory.samples = parse_in_range(value, 1, 64)?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "lfu-log-factor",
+        get: |config| config.maxmemory.lfu.log_factor.to_string(),
+        set: |config, value| {
+            config.maxmemory.lfu.log_factor = parse_in_range(value, 0, i64::from(i32::MAX))?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "lfu-decay-time",
+        get: |config| config.maxmemory.lfu.decay_time.to_string(),
+        set: |config, value| {
+            config.maxmemory.lfu.decay_time = parse_in_range(value, 0, i64::from(i32::MAX))?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "latency-tracking",
+        get: |config| format_bool(config.latency_tracking),
+        set: |config, value| {
+            config.latency_tracking = parse_bool(value)?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "latency-tracking-info-percentiles",
+        get: |config| {
+            let percentiles: Vec<String> = config
+                .latency_percentiles
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            percentiles.join(" ")
+        },
+        set: |config, value| {
+            config.latency_percentiles = value
+                .split_whitespace()
+                .map(|percentile| {
+                    percentile
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|percentile| (0.0..=100.0).contains(percentile))
+                        .ok_or("latency-tracking-info-percentiles must be between 0.0 and 100.0")
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: true,
+    },
+    Parameter {
+        name: "latency-monitor-threshold",
+        get: |config| {
+            config
+                .latency_monitor_threshold
+                .map_or(0, |threshold| threshold.as_millis())
+                .to_string()
+        },
+        set: |config, value| {
+            let millis = parse_in_range(value, 0, i64::MAX)?;
+            config.latency_monitor_threshold = (millis > 0).then(|| Duration::from_millis(millis));
+            Ok(())
+        },
+        mutable: true,
+        multiple_values: false,
+    },
+    Parameter {
+        name: "enable-debug-command",
+        get: |config| config.enable_debug_command.name().to_string(),
+        set: |config, value| {
+            config.enable_debug_command = DebugAccess::parse(value)
+                .ok_or("argument(s) must be one of the following: no, yes, local")?;
+            Ok(())
+        },
+        // Like Redis, it can only be set in the config file, so clients that
+        // can run CONFIG SET can't enable it.
+        mutable: false,
+        multiple_values: false,
+    },
+];
+
+fn find_parameter(name: &str) -> Option<&'static Parameter> {
+    PARAMETERS
+        .iter()
+        .find(|parameter| parameter.name.eq_ignore_ascii_case(name))
+}
+
+/// How deeply `include` directives may nest, which stops include cycles.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+impl ServerConfig {
+    /// Loads settings from a config file in Redis' `redis.conf` format, on
+    /// top of the defaults. `CONFIG REWRITE` saves to the same file.
+    ///
+    /// Each line is a directive: a parameter name followed by its value,
+    /// which may be quoted. Lines starting with `#` are comments, and
+    /// `include path` loads another file in place. Directives for parameters
+    /// this server doesn't have are skipped with a warning, so existing
+    /// Redis config files can be reused.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut config = Self {
+            config_file: Some(path.clone()),
+            ..Self::default()
+        };
+        config.load_file(&path, 0)?;
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read config file {}", path.display()))?;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = |reason: &str| {
+                eyre!(
+                    "bad config file {} at line {}: >>> '{line}' - {reason}",
+                    path.display(),
+                    i + 1
+                )
+            };
+            let args = split_args(line).map_err(bad_line)?;
+            let Some((name, values)) = args.split_first() else {
+                continue;
+            };
+            if name.eq_ignore_ascii_case("include") {
+                let [include] = values else {
+                    return Err(bad_line("wrong number of arguments"));
+                };
+                if depth == MAX_INCLUDE_DEPTH {
+                    return Err(bad_line("includes are nested too deeply"));
+                }
+                self.load_file(Path::new(include), depth + 1)?;
+                continue;
+            }
+            if find_parameter(name).is_none() {
+                log::warn!(
+                    "ignoring unsupported directive '{name}' in config file {}",
+                    path.display()
+                );
+                continue;
+            }
+            self.set_directive(name, values)
+                .map_err(|reason| bad_line(&reason))?;
+        }
+        Ok(())
+    }
+
+    /// Sets a parameter from a config file directive or a command-line flag.
+    /// Unlike `CONFIG SET`, this can set parameters that can only be set
+    /// when the server starts.
+    pub fn set_directive(&mut self, name: &str, values: &[String]) -> Result<(), String> {
+        let parameter =
+            find_parameter(name).ok_or_else(|| format!("unsupported directive '{name}'"))?;
+        match values {
+            [value] => (parameter.set)(self, value),
+            [_, _, ..] if parameter.multiple_values => (parameter.set)(self, &values.join(" ")),
+            _ => Err("wrong number of arguments".to_string()),
+        }
+    }
+
+    /// The addresses `Server::start_bound` listens on: the `bind` addresses,
+    /// or 127.0.0.1 without any. Like Redis, `*` means every IPv4 address
+    /// and `::*` every IPv6 address.
+    pub fn bind_addrs(&self) -> Vec<BindAddr> {
+        if self.bind.is_empty() {
+            return vec![BindAddr {
+                host: "127.0.0.1".to_string(),
+                optional: false,
+            }];
+        }
+        self.bind
+            .iter()
+            .map(|address| {
+                let (address, optional) = address
+                    .strip_prefix('-')
+                    .map_or((address.as_str(), false), |address| (address, true));
+                let host = match address {
+                    "*" => "0.0.0.0",
+                    "::*" => "::",
+                    _ => address,
+                };
+                BindAddr {
+                    host: host.to_string(),
+                    optional,
+                }
+            })
+            .collect()
+    }
+
+    /// The parameters whose names match any of the glob `patterns`, with
+    /// their values, in the order they're defined. Like Redis, patterns are
+    /// matched case-insensitively.
+    pub fn get(&self, patterns: &[String]) -> Vec<(&'static str, String)> {
+        PARAMETERS
+            .iter()
+            .filter(|parameter| {
+                patterns.iter().any(|pattern| {
+                    glob::matches_nocase(pattern.as_bytes(), parameter.name.as_bytes())
+                })
+            })
+            .map(|parameter| (parameter.name, (parameter.get)(self)))
+            .collect()
+    }
+
+    /// Sets parameters at runtime, like `CONFIG SET`. Either every parameter
+    /// is set, or none are and the error is the message for the client.
+    pub fn set(&mut self, pairs: &[(String, String)]) -> Result<(), String> {
+        let mut config = self.clone();
+        for (i, (name, value)) in pairs.iter().enumerate() {
+            let failed = |reason: &str| {
+                format!("CONFIG SET failed (possibly related to argument '{name}') - {reason}")
+            };
+            let Some(parameter) = find_parameter(name) else {
+                return Err(format!(
+                    "Unknown option or number of arguments for CONFIG SET - '{name}'"
+                ));
+            };
+            if !parameter.mutable {
+                return Err(failed("can't set immutable config"));
+            }
+            if pairs[..i]
+                .iter()
+                .any(|(other, _)| other.eq_ignore_ascii_case(name))
+            {
+                return Err(failed("duplicate parameter"));
+            }
+            (parameter.set)(&mut config, value).map_err(|reason| failed(&reason))?;
+        }
+        *self = config;
+        Ok(())
+    }
+
+    /// Saves the settings to the config file, like `CONFIG REWRITE`. Like
+    /// Redis, lines for parameters already in the file are updated in place,
+    /// parameters that aren't are appended if they differ from their
+    /// defaults, and everything else in the file is left alone.
+    pub fn rewrite(&self) -> Result<()> {
+        let path = self
+            .config_file
+            .as_ref()
+            .ok_or_else(|| eyre!("The server is running without a config file"))?;
+        let existing = match fs::read_to_string(path) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).wrap_err_with(|| format!("failed to read {}", path.display()));
+            }
+        };
+
+        let defaults = Self::default();
+        let mut written = Vec::new();
+        let mut lines = Vec::new();
+        for line in existing.lines() {
+            let name = line.split_whitespace().next().unwrap_or_default();
+            let Some(parameter) = (!name.starts_with('#'))
+                .then(|| find_parameter(name))
+                .flatten()
+            else {
+                lines.push(line.to_string());
+                continue;
+            };
+            // Later lines for the same parameter are dropped, since the first
+            // one now has the current value.
+            if !written.contains(&parameter.name) {
+                written.push(parameter.name);
+                lines.push(format_line(parameter, self));
+            }
+        }
+        let mut generated = PARAMETERS
+            .iter()
+            .filter(|parameter| {
+                !written.contains(&parameter.name)
+                    && (parameter.get)(self) != (parameter.get)(&defaults)
+            })
+            .peekable();
+        if generated.peek().is_some() {
+            lines.push("# Generated by CONFIG REWRITE".to_string());
+            lines.extend(generated.map(|parameter| format_line(parameter, self)));
+        }
+
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        let temp_path = path.with_file_name(format!("temp-config-{}.conf", std::process::id()));
+        fs::write(&temp_path, contents)
+            .wrap_err_with(|| format!("failed to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, path).wrap_err_with(|| {
+            format!(
+                "failed to rename {} to {}",
+                temp_path.display(),
+                path.display()
+            )
+        })
+    }
+}
+
+/// Splits a config file line into arguments like Redis' `sdssplitargs`.
+/// Arguments are separated by whitespace and may be quoted. Double-quoted
+/// arguments can have escapes like `\n` and `\x41`, while single-quoted ones
+/// can only escape `'`.
+fn split_args(line: &str) -> Result<Vec<String>, &'static str> {
+    let bytes = line.as_bytes();
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        let arg = match bytes.get(i) {
+            None => return Ok(args),
+            Some(&quote @ (b'"' | b'\'')) => {
+                let arg = split_quoted(bytes, &mut i, quote)?;
+                // Like Redis, a closing quote must end the argument.
+                if bytes.get(i).is_some_and(|c| !c.is_ascii_whitespace()) {
+                    return Err("closing quote must be followed by a space");
+                }
+                arg
+            }
+            Some(_) => {
+                let start = i;
+                while bytes.get(i).is_some_and(|c| !c.is_ascii_whitespace()) {
+                    i += 1;
+                }
+                bytes[start..i].to_vec()
+            }
+        };
+        args.push(String::from_utf8(arg).map_err(|_| "argument must be valid UTF-8")?);
+    }
+}
+
+/// Parses the quoted argument starting at `bytes[*i]`, leaving `i` just past
+/// the closing quote.
+fn split_quoted(bytes: &[u8], i: &mut usize, quote: u8) -> Result<Vec<u8>, &'static str> {
+    let next = |i: &mut usize| {
+        let c = bytes.get(*i).copied();
+        *i += 1;
+        c.ok_or("unbalanced quotes in configuration line")
+    };
+    next(i)?;
+    let mut arg = Vec::new();
+    loop {
+        match next(i)? {
+            c if c == quote => return Ok(arg),
+            b'\\' if quote == b'"' => {
+                let escaped = match next(i)? {
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'b' => 0x08,
+                    b'a' => 0x07,
+                    b'x' if bytes
+                        .get(*i..*i + 2)
+                        .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit)) =>
+                    {
+                        let hex = [next(i)?, next(i)?];
+                        let hex = std::str::from_utf8(&hex).expect("hex digits are ASCII");
+                        u8::from_str_radix(hex, 16).expect("hex digits are valid")
+                    }
+                    c => c,
+                };
+                arg.push(escaped);
+            }
+            b'\\' if bytes.get(*i) == Some(&b'\'') => arg.push(next(i)?),
+            c => arg.push(c),
+        }
+    }
+}
+
+fn format_line(parameter: &Parameter, config: &ServerConfig) -> String {
+    let value = (parameter.get)(config);
+    // Values that would be split into several arguments are quoted.
+    if parameter.multiple_values && !value.is_empty() {
+        format!("{} {value}", parameter.name)
+    } else if value.is_empty()
+        || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+    {
+        format!("{} {value:?}", parameter.name)
+    } else {
+        format!("{} {value}", parameter.name)
+    }
+}
+
+fn format_bool(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+/// Formats an optional path, which is empty when it's not set.
+fn format_path(path: Option<&Path>) -> String {
+    path.map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+/// Parses an optional path, where an empty value unsets it.
+fn parse_path(value: &str) -> Option<PathBuf> {
+    (!value.is_empty()).then(|| PathBuf::from(value))
+}
+
+fn parse_in_range<T: TryFrom<i64>>(value: &str, min: i64, max: i64) -> Result<T, String> {
+    let n: i64 = value
+        .parse()
+        .map_err(|_| "argument couldn't be parsed into an integer")?;
+    if !(min..=max).contains(&n) {
+        return Err(format!(
+            "argument must be between {min} and {max} inclusive"
+        ));
+    }
+    T::try_from(n).map_err(|_| "argument is out of range".to_string())
+}
+
+/// Parses a number of bytes, which like Redis may have a unit: `k`, `m` and
+/// `g` are powers of 1000, and `kb`, `mb` and `gb` are powers of 1024.
+fn parse_memory(value: &str) -> Result<usize, String> {
+    let lower = value.to_ascii_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (digits, unit) = lower.split_at(split);
+    let multiplier: usize = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err("argument must be a memory value".to_string()),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(ToString::to_string).collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn get_matches_globs() {
+        let config = ServerConfig::default();
+        assert_eq!(
+            config.get(&patterns(&["MAXMEMORY"])),
+            [("maxmemory", "0".to_string())]
+        );
+        let names: Vec<_> = config
+            .get(&patterns(&["maxmemory*", "appendonly"]))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "appendonly",
+                "maxmemory",
+                "maxmemory-policy",
+                "maxmemory-samples"
+            ]
+        );
+        assert!(config.get(&patterns(&["nope"])).is_empty());
+    }
+
+    #[test]
+    fn set_is_atomic() {
+        let mut config = ServerConfig::default();
+        config
+            .set(&pairs(&[
+                ("maxmemory", "100mb"),
+                ("maxmemory-policy", "ALLKEYS-LRU"),
+                ("appendfsync", "always"),
+            ]))
+            .unwrap();
+        assert_eq!(config.maxmemory.limit, 100 * 1024 * 1024);
+        assert_eq!(config.maxmemory.policy, EvictionPolicy::AllKeysLru);
+        assert_eq!(config.aof.fsync, FsyncPolicy::Always);
+
+        let before = config.clone();
+        let err = config
+            .set(&pairs(&[("maxmemory", "1k"), ("maxmemory-samples", "100")]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "CONFIG SET failed (possibly related to argument 'maxmemory-samples') - argument must be between 1 and 64 inclusive"
+        );
+        assert_eq!(config, before);
+
+        let err = config.set(&pairs(&[("databases", "4")])).unwrap_err();
+        assert!(err.ends_with("can't set immutable config"));
+        let err = config.set(&pairs(&[("nope", "1")])).unwrap_err();
+        assert_eq!(
+            err,
+            "Unknown option or number of arguments for CONFIG SET - 'nope'"
+        );
+        let err = config
+            .set(&pairs(&[("maxmemory", "1"), ("MAXMEMORY", "2")]))
+            .unwrap_err();
+        assert!(err.ends_with("duplicate parameter"));
+        assert!(config.set(&pairs(&[("appendonly", "maybe")])).is_err());
+        assert!(config.set(&pairs(&[("maxmemory", "12xb")])).is_err());
+    }
+
+    #[test]
+    fn rewrite_updates_file() {
+        let dir =
+            std::env::temp_dir().join(format!("redis-clone-config-rewrite-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        fs::write(
+            &path,
+            "# My settings\nmaxmemory 1mb\nunknown-option 7\nmaxmemory 2mb\n",
+        )
+        .unwrap();
+
+        let mut config = ServerConfig {
+            config_file: Some(path.clone()),
+            ..ServerConfig::default()
+        };
+        config
+            .set(&pairs(&[
+                ("maxmemory", "5000"),
+                ("dbfilename", "my dump.rdb"),
+            ]))
+            .unwrap();
+        config.rewrite().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# My settings\nmaxmemory 5000\nunknown-option 7\n\
+             # Generated by CONFIG REWRITE\ndbfilename \"my dump.rdb\"\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(ServerConfig::default().rewrite().is_err());
+    }
+
+    #[test]
+    fn split_args_handles_quotes() {
+        assert_eq!(
+            split_args("  maxmemory   100mb ").unwrap(),
+            ["maxmemory", "100mb"]
+        );
+        assert_eq!(
+            split_args(r#"dbfilename "my \"dump\"\x21.rdb" 'it\'s' a"b"#).unwrap(),
+            ["dbfilename", "my \"dump\"!.rdb", "it's", "a\"b"]
+        );
+        assert_eq!(split_args(r#""\n\t\xzz""#).unwrap(), ["\n\txzz"]);
+        assert_eq!(split_args("''").unwrap(), [""]);
+        assert!(split_args(r#"dbfilename "dump.rdb"#).is_err());
+        assert!(split_args(r#"dbfilename "dump".rdb"#).is_err());
+    }
+
+    #[test]
+    fn load_reads_redis_conf() {
+        let dir =
+            std::env::temp_dir().join(format!("redis-clone-config-load-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        let included = dir.join("included.conf");
+        fs::write(&included, "maxmemory-policy allkeys-lru\nappendonly yes\n").unwrap();
+        fs::write(
+            &path,
+            format!(
+                "# Redis configuration file example.\n\n\
+                 bind 127.0.0.1 -::1\n\
+                 MAXMEMORY 2gb\n\
+                 databases 4\n\
+                 dbfilename \"my dump.rdb\"\n\
+                 include {}\n\
+                 appendonly no\n",
+                included.display()
+            ),
+        )
+        .unwrap();
+
+        let config = ServerConfig::load(&path).unwrap();
+        assert_eq!(config.maxmemory.limit, 2 * 1024 * 1024 * 1024);
+        assert_eq!(config.maxmemory.policy, EvictionPolicy::AllKeysLru);
+        assert_eq!(config.databases, 4);
+        assert_eq!(config.snapshot_path, PathBuf::from("my dump.rdb"));
+        assert!(!config.appendonly);
+        assert_eq!(config.config_file, Some(path.clone()));
+
+        fs::write(&path, "maxmemory 1mb\nmaxmemory-samples 0\n").unwrap();
+        let err = ServerConfig::load(&path).unwrap_err().to_string();
+        assert!(err.contains("at line 2"), "{err}");
+        fs::write(&path, "maxmemory 1mb 2mb\n").unwrap();
+        assert!(ServerConfig::load(&path).is_err());
+
+        // Files that include themselves are rejected rather than looping.
+        fs::write(&path, format!("include {}\n", path.display())).unwrap();
+        assert!(ServerConfig::load(&path).is_err());
+        assert!(ServerConfig::load(dir.join("missing.conf")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn set_directive_overrides() {
+        let mut config = ServerConfig::default();
+        let values =
+            |values: &[&str]| -> Vec<String> { values.iter().map(ToString::to_string).collect() };
+        let bind_addr = |host: &str, optional| BindAddr {
+            host: host.to_string(),
+            optional,
+        };
+        assert_eq!(config.bind_addrs(), [bind_addr("127.0.0.1", false)]);
+
+        config.set_directive("port", &values(&["7000"])).unwrap();
+        assert_eq!(config.port, 7000);
+        config
+            .set_directive("bind", &values(&["-::*", "127.0.0.1"]))
+            .unwrap();
+        assert_eq!(
+            config.bind_addrs(),
+            [bind_addr("::", true), bind_addr("127.0.0.1", false)]
+        );
+        assert_eq!(
+            config.get(&patterns(&["bind"])),
+            [("bind", "-::* 127.0.0.1".to_string())]
+        );
+
+        // Directives can set parameters `CONFIG SET` can't.
+        config
+            .set_directive("daemonize", &values(&["yes"]))
+            .unwrap();
+        assert!(config.daemonize);
+        assert!(config.set(&pairs(&[("daemonize", "no")])).is_err());
+
+        config
+            .set_directive("requirepass", &values(&["secret"]))
+            .unwrap();
+        assert_eq!(config.requirepass.as_deref(), Some("secret"));
+        config.set_directive("requirepass", &values(&[""])).unwrap();
+        assert_eq!(config.requirepass, None);
+
+        config
+            .set_directive("tls-auth-clients", &values(&["optional"]))
+            .unwrap();
+        assert_eq!(config.tls.auth_clients, ClientAuth::Optional);
+        assert!(config
+            .set_directive("tls-auth-clients", &values(&["maybe"]))
+            .is_err());
+
+        assert!(config.set_directive("port", &values(&["1", "2"])).is_err());
+        assert!(config.set_directive("port", &values(&[])).is_err());
+        assert!(config.set_directive("port", &values(&["70000"])).is_err());
+        assert!(config.set_directive("nope", &values(&["1"])).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+//! The CRC-16 variant used by Redis Cluster to map keys to hash slots
+//! (XMODEM: polynomial 0x1021, no reflection, no final XOR).
+
+const POLY: u16 = 0x1021;
+
+#[allow(clippy::cast_possible_truncation)]
+const TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ POLY
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        TABLE[usize::from((crc >> 8) as u8 ^ byte)] ^ (crc << 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        // Test vector from Redis' crc16.c
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+}
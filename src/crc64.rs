@@ -0,0 +1,49 @@
+//! The CRC-64 variant used by Redis for `DUMP` payloads and RDB files
+//! (reflected Jones polynomial, no final XOR).
+
+const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Updates `crc` with `data`. Start with a `crc` of 0.
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for &byte in data {
+        crc = TABLE[((crc ^ u64::from(byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        // Test vector from Redis' crc64.c
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn incremental() {
+        let crc = crc64(0, b"1234");
+        assert_eq!(crc64(crc, b"56789"), crc64(0, b"123456789"));
+    }
+}
@@ -0,0 +1,549 @@
+//! The keyspace of a single logical database.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::evict::{Frequency, LfuConfig};
+use crate::hamt::HashTrieMap;
+use crate::hash::Hash;
+use crate::mem_size::{
+    hash_field_size, list_element_size, set_member_size, stream_entry_size, zset_member_size,
+    MemSize,
+};
+use crate::random::Rng;
+use crate::set::Set;
+use crate::stream::{RangeBound, Stream};
+use crate::string::{RedisString, StringValue};
+use crate::zset::SortedSet;
+
+/// A `Db` is one of the numbered logical databases selected with `SELECT`.
+///
+/// Cloning a `Db` is cheap, since the keyspace is a persistent map. Background
+/// saves work on a clone while the core keeps modifying the original.
+#[derive(Debug, Default, Clone)]
+pub struct Db {
+    /// The keys and their values. Keys are only added and removed through
+    /// `insert` and `remove`, which keep `used_memory` up to date.
+    key_value: HashTrieMap<RedisString, Entry>,
+
+    /// Absolute expiration times of the keys that have one, as Unix
+    /// timestamps in milliseconds. Like Redis, they're kept apart from the
+    /// values so keys without a TTL don't pay for one, and expiring keys only
+    /// has to look at keys that can expire.
+    expires: HashTrieMap<RedisString, i64>,
+
+    /// The sum of every entry's cached size. See `Entry::mem_size`.
+    used_memory: usize,
+
+    /// Whether this is a replica's database. Replicas hide expired keys from
+    /// reads, but leave removing them to the master's `DEL` so that their
+    /// datasets don't drift apart.
+    pub replica: bool,
+
+    /// How to count accesses to keys, when an LFU eviction policy is
+    /// selected. Frequencies aren't tracked otherwise.
+    pub lfu: Option<LfuConfig>,
+
+    /// Keys lazily removed because they expired, whose removal hasn't been
+    /// propagated to the AOF and replicas yet.
+    expired: Vec<RedisString>,
+
+    /// Decides when to increment access frequencies.
+    rng: Rng,
+}
+
+impl Db {
+    pub const fn entries(&self) -> &HashTrieMap<RedisString, Entry> {
+        &self.key_value
+    }
+
+    pub const fn expires(&self) -> &HashTrieMap<RedisString, i64> {
+        &self.expires
+    }
+
+    /// Stores `entry` at `key`, returning the entry it replaced. Like
+    /// overwriting a key in Redis, this clears any TTL it had.
+    pub fn insert(&mut self, key: RedisString, mut entry: Entry) -> Option<Entry> {
+        self.expires.remove(&key);
+        entry.mem_size = entry.measure(&key);
+        self.used_memory += entry.mem_size;
+        let replaced = self.key_value.insert(key, entry);
+        if let Some(replaced) = &replaced {
+            self.used_memory -= replaced.mem_size;
+        }
+        replaced
+    }
+
+    pub fn remove(&mut self, key: &RedisString) -> Option<Entry> {
+        self.expires.remove(key);
+        let removed = self.key_value.remove(key)?;
+        self.used_memory -= removed.mem_size;
+        Some(removed)
+    }
+
+    /// The key's expiration time, even if it has already passed.
+    pub fn expires_at(&self, key: &RedisString) -> Option<i64> {
+        self.expires.get(key).copied()
+    }
+
+    /// Sets or clears the expiration time of `key`, if it exists, returning
+    /// the one it replaced.
+    pub fn set_expires_at(&mut self, key: &RedisString, expires_at: Option<i64>) -> Option<i64> {
+        match expires_at {
+            Some(t) if self.key_value.contains_key(key) => self.expires.insert(key.clone(), t),
+            Some(_) => None,
+            None => self.expires.remove(key),
+        }
+    }
+
+    pub fn is_expired(&self, key: &RedisString, now: i64) -> bool {
+        self.expires_at(key).is_some_and(|t| t <= now)
+    }
+
+    /// Measures the value at `key` again, after it may have been modified
+    /// in place.
+    pub fn refresh_mem_size(&mut self, key: &RedisString) {
+        if let Some(entry) = self.key_value.get_mut(key) {
+            let size = entry.measure(key);
+            self.used_memory = self.used_memory - entry.mem_size + size;
+            entry.mem_size = size;
+        }
+    }
+
+    /// Roughly how many bytes the keyspace takes up, as of the last time
+    /// each value was measured. See `Entry::mem_size`.
+    pub const fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    /// Removes every key, returning them so the caller can choose where to
+    /// free them. Settings like `replica` and `lfu` are kept.
+    #[must_use]
+    pub fn flush(&mut self) -> Self {
+        let empty = Self {
+            replica: self.replica,
+            lfu: self.lfu,
+            ..Self::default()
+        };
+        std::mem::replace(self, empty)
+    }
+
+    /// Looks up a key, lazily deleting it if it has expired. Updates the key's
+    /// last access time and access frequency.
+    pub fn get_entry(&mut self, key: &RedisString) -> Option<&mut Entry> {
+        let now = unix_time_millis();
+        if self.is_expired(key, now) {
+            self.expire(key);
+            return None;
+        }
+        let entry = self.key_value.get_mut(key)?;
+        entry.last_access = now;
+        if let Some(lfu) = &self.lfu {
+            entry.frequency.record_access(now, lfu, &mut self.rng);
+        }
+        Some(entry)
+    }
+
+    /// Like `get_entry`, but doesn't count as an access to the key. Used by
+    /// introspection commands like `TTL` and `OBJECT`.
+    pub fn peek_entry(&mut self, key: &RedisString) -> Option<&mut Entry> {
+        if self.is_expired(key, unix_time_millis()) {
+            self.expire(key);
+            return None;
+        }
+        self.key_value.get_mut(key)
+    }
+
+    /// Removes `key`, which has expired, unless this is a replica.
+    pub fn expire(&mut self, key: &RedisString) {
+        if !self.replica && self.remove(key).is_some() {
+            self.expired.push(key.clone());
+        }
+    }
+
+    /// Takes the keys lazily removed since the last call.
+    pub fn take_expired(&mut self) -> Vec<RedisString> {
+        std::mem::take(&mut self.expired)
+    }
+
+    /// Checks `count` keys with a TTL picked at random, or every one if there
+    /// are no more than that, and removes the ones that have expired. Returns
+    /// how many keys were checked and the names of the ones removed. Expired
+    /// keys are otherwise only removed when they're next accessed.
+    pub fn expire_sample(&mut self, now: i64, count: usize) -> (usize, Vec<RedisString>) {
+        let sample: Vec<(&RedisString, &i64)> = if self.expires.len() <= count {
+            self.expires.iter().collect()
+        } else {
+            // Like Redis, the same key may be picked twice.
+            (0..count)
+                .filter_map(|_| self.expires.random_entry(|n| self.rng.below(n)))
+                .collect()
+        };
+        let checked = sample.len();
+        let mut expired: Vec<RedisString> = sample
+            .into_iter()
+            .filter(|(_, &expires_at)| expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired.retain(|key| self.remove(key).is_some());
+        (checked, expired)
+    }
+
+    /// Removes the given keys, returning the entries that existed and hadn't
+    /// expired.
+    pub fn remove_keys(&mut self, keys: Vec<RedisString>) -> Vec<Entry> {
+        let now = unix_time_millis();
+        keys.into_iter()
+            .filter_map(|key| {
+                let expired = self.is_expired(&key, now);
+                self.remove(&key).filter(|_| !expired)
+            })
+            .collect()
+    }
+
+    /// Looks up a string. Fails if the key holds a different type.
+    pub fn get_string(&mut self, key: &RedisString) -> Result<Option<&mut StringValue>, WrongType> {
+        match self.get_entry(key) {
+            None => Ok(None),
+            Some(Entry {
+                value: Value::String(s),
+                ..
+            }) => Ok(Some(s)),
+            Some(_) => Err(WrongType),
+        }
+    }
+
+    /// Like `get_string`, but creates an empty string if the key doesn't
+    /// exist, and returns it raw so it can be edited in place.
+    pub fn get_or_create_string(
+        &mut self,
+        key: &RedisString,
+    ) -> Result<&mut RedisString, WrongType> {
+        if self.get_string(key)?.is_none() {
+            let entry = Entry::new(Value::String(StringValue::default()));
+            self.insert(key.clone(), entry);
+        }
+        let value = self.get_string(key)?.expect("string was just created");
+        Ok(value.make_raw())
+    }
+
+    /// Looks up a list. Fails if the key holds a different type.
+    pub fn get_list(
+        &mut self,
+        key: &RedisString,
+    ) -> Result<Option<&mut VecDeque<RedisString>>, WrongType> {
+        match self.get_entry(key) {
+            None => Ok(None),
+            Some(Entry {
+                value: Value::List(list),
+                ..
+            }) => Ok(Some(list)),
+            Some(_) => Err(WrongType),
+        }
+    }
+
+    /// Like `get_list`, but creates an empty list if the key doesn't exist.
+    /// Callers must not leave the list empty.
+    pub fn get_or_create_list(
+        &mut self,
+        key: &RedisString,
+    ) -> Result<&mut VecDeque<RedisString>, WrongType> {
+        if self.get_list(key)?.is_none() {
+            let entry = Entry::new(Value::List(VecDeque::new()));
+            self.insert(key.clone(), entry);
+        }
+        Ok(self.get_list(key)?.expect("list was just created"))
+    }
+
+    /// Looks up a hash. Fails if the key holds a different type.
+    pub fn get_hash(&mut self, key: &RedisString) -> Result<Option<&mut Hash>, WrongType> {
+        match self.get_entry(key) {
+            None => Ok(None),
+            Some(Entry {
+                value: Value::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(WrongType),
+        }
+    }
+
+    /// Like `get_hash`, but creates an empty hash if the key doesn't exist.
+    /// Callers must not leave the hash empty.
+    pub fn get_or_create_hash(&mut self, key: &RedisString) -> Result<&mut Hash, WrongType> {
+        if self.get_hash(key)?.is_none() {
+            let entry = Entry::new(Value::Hash(Hash::default()));
+            self.insert(key.clone(), entry);
+        }
+        Ok(self.get_hash(key)?.expect("hash was just created"))
+    }
+
+    /// Looks up a set. Fails if the key holds a different type.
+    pub fn get_set(&mut self, key: &RedisString) -> Result<Option<&mut Set>, WrongType> {
+        match self.get_entry(key) {
+            None => Ok(None),
+            Some(Entry {
+                value: Value::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(WrongType),
+        }
+    }
+
+    /// Like `get_set`, but creates an empty set if the key doesn't exist.
+    /// Callers must not leave the set empty.
+    pub fn get_or_create_set(&mut self, key: &RedisString) -> Result<&mut Set, WrongType> {
+        if self.get_set(key)?.is_none() {
+            let entry = Entry::new(Value::Set(Set::default()));
+            self.insert(key.clone(), entry);
+        }
+        Ok(self.get_set(key)?.expect("set was just created"))
+    }
+
+    /// Looks up a sorted set. Fails if the key holds a different type.
+    pub fn get_zset(&mut self, key: &RedisString) -> Result<Option<&mut SortedSet>, WrongType> {
+        match self.get_entry(key) {
+            None => Ok(None),
+            Some(Entry {
+                value: Value::ZSet(zset),
+                ..
+            }) => Ok(Some(zset)),
+            Some(_) => Err(WrongType),
+        }
+    }
+
+    /// Like `get_zset`, but creates an empty sorted set if the key doesn't
+    /// exist. Callers must not leave the sorted set empty.
+    pub fn get_or_create_zset(&mut self, key: &RedisString) -> Result<&mut SortedSet, WrongType> {
+        if self.get_zset(key)?.is_none() {
+            let entry = Entry::new(Value::ZSet(SortedSet::default()));
+            self.insert(key.clone(), entry);
+        }
+        Ok(self.get_zset(key)?.expect("sorted set was just created"))
+    }
+
+    /// Looks up a stream. Fails if the key holds a different type.
+    pub fn get_stream(&mut self, key: &RedisString) -> Result<Option<&mut Stream>, WrongType> {
+        match self.get_entry(key) {
+            None => Ok(None),
+            Some(Entry {
+                value: Value::Stream(stream),
+                ..
+            }) => Ok(Some(stream)),
+            Some(_) => Err(WrongType),
+        }
+    }
+
+    /// Like `get_stream`, but creates an empty stream if the key doesn't
+    /// exist.
+    pub fn get_or_create_stream(&mut self, key: &RedisString) -> Result<&mut Stream, WrongType> {
+        if self.get_stream(key)?.is_none() {
+            let entry = Entry::new(Value::Stream(Stream::default()));
+            self.insert(key.clone(), entry);
+        }
+        Ok(self.get_stream(key)?.expect("stream was just created"))
+    }
+
+    /// Looks up several sets at once, for commands that combine them. Fails
+    /// if any key holds a different type.
+    pub fn get_sets(&mut self, keys: &[RedisString]) -> Result<Vec<Option<&Set>>, WrongType> {
+        // Check types (and expire keys) first, since the sets are borrowed
+        // immutably below.
+        for key in keys {
+            self.get_set(key)?;
+        }
+        Ok(keys
+            .iter()
+            .map(|key| match self.key_value.get(key) {
+                Some(Entry {
+                    value: Value::Set(set),
+                    ..
+                }) => Some(set),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Deletes the key if it holds an empty collection, since Redis never
+    /// stores those.
+    pub fn remove_if_empty(&mut self, key: &RedisString) {
+        if self.key_value.get(key).is_some_and(|e| e.value.is_empty()) {
+            self.remove(key);
+        }
+    }
+}
+
+/// The bytes each key takes up besides its name and value, for the keyspace
+/// node, the entry's metadata and the key's header.
+pub const ENTRY_OVERHEAD: usize = 64;
+
+/// The error for operations against a key holding the wrong kind of value.
+#[derive(Debug, PartialEq, Eq)]
+pub struct WrongType;
+
+/// A value stored at a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(StringValue),
+    List(VecDeque<RedisString>),
+    Hash(Hash),
+    Set(Set),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// The name of the value's type, as reported by `TYPE`.
+    pub const fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::ZSet(_) => "zset",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    /// Like `mem_size`, but only looks at up to `samples` elements of a
+    /// collection and assumes the rest are the same size on average, like
+    /// `MEMORY USAGE key SAMPLES count`. 0 looks at every element.
+    pub fn sampled_size(&self, samples: usize) -> usize {
+        if samples == 0 {
+            return self.mem_size();
+        }
+        match self {
+            Self::String(s) => s.mem_size(),
+            Self::List(list) => {
+                extrapolate(list.len(), list.iter().take(samples).map(list_element_size))
+            }
+            Self::Hash(hash) => extrapolate(
+                hash.len(),
+                hash.iter()
+                    .take(samples)
+                    .map(|pair| hash_field_size(hash.is_listpack(), pair)),
+            ),
+            Self::Set(set) => extrapolate(
+                set.len(),
+                set.iter()
+                    .take(samples)
+                    .map(|member| set_member_size(set.intset_width(), &member)),
+            ),
+            Self::ZSet(zset) => extrapolate(
+                zset.len(),
+                zset.iter()
+                    .take(samples)
+                    .map(|(member, _)| zset_member_size(zset.is_listpack(), member)),
+            ),
+            Self::Stream(stream) => extrapolate(
+                stream.len(),
+                stream
+                    .range(RangeBound::Min, RangeBound::Max)
+                    .take(samples)
+                    .map(|(_, fields)| stream_entry_size(fields)),
+            ),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            // Empty streams are kept, since they remember their last ID.
+            Self::String(_) | Self::Stream(_) => false,
+            Self::List(list) => list.is_empty(),
+            Self::Hash(hash) => hash.is_empty(),
+            Self::Set(set) => set.is_empty(),
+            Self::ZSet(zset) => zset.is_empty(),
+        }
+    }
+}
+
+impl MemSize for Value {
+    fn mem_size(&self) -> usize {
+        match self {
+            Self::String(s) => s.mem_size(),
+            Self::List(list) => list.mem_size(),
+            Self::Hash(hash) => hash.mem_size(),
+            Self::Set(set) => set.mem_size(),
+            Self::ZSet(zset) => zset.mem_size(),
+            Self::Stream(stream) => stream.mem_size(),
+        }
+    }
+}
+
+/// A value in the key-value store along with its metadata.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub value: Value,
+
+    /// Last time the key was accessed as a Unix timestamp in milliseconds.
+    pub last_access: i64,
+
+    /// How often the key is accessed, for the LFU eviction policies.
+    pub frequency: Frequency,
+
+    /// Roughly how many bytes the entry takes up, measured when it was
+    /// stored or last written to. `Db` keeps this up to date.
+    mem_size: usize,
+}
+
+impl Entry {
+    pub fn new(value: Value) -> Self {
+        let now = unix_time_millis();
+        Self {
+            value,
+            last_access: now,
+            frequency: Frequency::new(now),
+            mem_size: 0,
+        }
+    }
+
+    /// Roughly how many bytes the entry took up when it was last measured.
+    /// This is an estimate from the size of the data, not what's actually
+    /// allocated, but it's what `maxmemory` is enforced against.
+    pub const fn mem_size(&self) -> usize {
+        self.mem_size
+    }
+
+    /// Estimates how many bytes the entry takes up when stored at `key`,
+    /// from `samples` of its elements. See `Value::sampled_size`.
+    pub fn sampled_size(&self, key: &RedisString, samples: usize) -> usize {
+        ENTRY_OVERHEAD + key.len() + self.value.sampled_size(samples)
+    }
+
+    fn measure(&self, key: &RedisString) -> usize {
+        self.sampled_size(key, 0)
+    }
+
+    /// How the value is stored, as reported by `OBJECT ENCODING`. Integers,
+    /// hashes, sets and sorted sets report how they're actually stored. Other
+    /// values are always stored the same way, so they report the encoding
+    /// Redis would use for compatibility.
+    pub fn encoding(&self) -> &'static str {
+        // Redis' cutoff for embedding strings in the object header.
+        const EMBSTR_SIZE_LIMIT: usize = 44;
+
+        match &self.value {
+            Value::String(StringValue::Int(_)) => "int",
+            Value::String(s) if s.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+            Value::String(_) => "raw",
+            Value::List(_) => "deque",
+            Value::Hash(hash) => hash.encoding(),
+            Value::Set(set) => set.encoding(),
+            Value::ZSet(zset) => zset.encoding(),
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+/// Estimates the total size of `len` elements from the sizes of some of them.
+fn extrapolate(len: usize, sampled: impl Iterator<Item = usize>) -> usize {
+    let (count, total) = sampled.fold((0, 0), |(count, total), size| (count + 1, total + size));
+    (total * len).checked_div(count).unwrap_or(0)
+}
+
+/// Returns the current time as a Unix timestamp in milliseconds.
+pub fn unix_time_millis() -> i64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the Unix epoch");
+    i64::try_from(since_epoch.as_millis()).expect("Unix time in millis overflows i64")
+}
@@ -0,0 +1,95 @@
+//! Support for the `DEBUG` command, which test harnesses use to poke at the
+//! server's internals. See <https://redis.io/commands/debug/>.
+//!
+//! Like Redis, `DEBUG` is disabled unless `enable-debug-command` allows it,
+//! since it can stall or crash the server.
+
+use std::net::SocketAddr;
+
+use crate::glob;
+use crate::random::Rng;
+
+/// The error clients get when `enable-debug-command` doesn't allow them to
+/// run `DEBUG`.
+pub const DEBUG_DISABLED_MESSAGE: &str = "DEBUG command not allowed. If the \
+    enable-debug-command option is set to \"local\", you can run it from a local connection, \
+    otherwise you need to set this option in the configuration file, and then restart the \
+    server.";
+
+/// Who may run `DEBUG`, like Redis' `enable-debug-command`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DebugAccess {
+    #[default]
+    No,
+    Yes,
+
+    /// Only clients connected over loopback.
+    Local,
+}
+
+impl DebugAccess {
+    const ALL: [Self; 3] = [Self::No, Self::Yes, Self::Local];
+
+    /// The setting's value in `enable-debug-command`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::No => "no",
+            Self::Yes => "yes",
+            Self::Local => "local",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|access| access.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether a client connected from `addr` may run `DEBUG`.
+    pub const fn allows(self, addr: SocketAddr) -> bool {
+        match self {
+            Self::No => false,
+            Self::Yes => true,
+            Self::Local => addr.ip().to_canonical().is_loopback(),
+        }
+    }
+}
+
+/// How many patterns `DEBUG STRINGMATCH-LEN` tries.
+const STRINGMATCH_CYCLES: usize = 100_000;
+
+/// Matches random patterns against random strings, like Redis' `DEBUG
+/// STRINGMATCH-LEN`. It checks that glob matching never crashes or blows up
+/// on malformed patterns. Returns how many matched.
+pub fn stringmatch_len_fuzz(rng: &mut Rng) -> usize {
+    fn random_bytes(rng: &mut Rng) -> Vec<u8> {
+        let len = rng.below(32);
+        (0..len)
+            .map(|_| u8::try_from(rng.below(128)).expect("ASCII fits in a byte"))
+            .collect()
+    }
+    (0..STRINGMATCH_CYCLES)
+        .filter(|_| {
+            let string = random_bytes(rng);
+            let pattern = random_bytes(rng);
+            glob::matches(&pattern, &string)
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access() {
+        let local: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let remote: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        assert!(!DebugAccess::No.allows(local));
+        assert!(DebugAccess::Yes.allows(remote));
+        assert!(DebugAccess::Local.allows(local));
+        assert!(!DebugAccess::Local.allows(remote));
+        assert_eq!(DebugAccess::parse("LOCAL"), Some(DebugAccess::Local));
+        assert_eq!(DebugAccess::parse("maybe"), None);
+    }
+}
@@ -0,0 +1,336 @@
+//! Evicting keys to keep the keyspace under `maxmemory`, like Redis'
+//! `evict.c`.
+//!
+//! Memory usage is estimated from the size of the data (see
+//! `mem_size`). Like Redis, LRU eviction is approximate: each
+//! eviction samples a few keys and evicts the least recently accessed of
+//! them, which is much cheaper than keeping every key in LRU order.
+//!
+//! The LFU policies evict the least frequently used key instead, by the same
+//! sampling. Access frequencies are kept in a logarithmic counter per key,
+//! like Redis' Morris counters, that decays while the key isn't accessed.
+
+use crate::db::{unix_time_millis, Db, Entry};
+use crate::random::Rng;
+use crate::string::RedisString;
+
+/// How many keys are sampled per eviction by default, like Redis'
+/// `maxmemory-samples`.
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// The access count new keys start with, so they aren't evicted before
+/// they've had a chance to be accessed, like Redis' `LFU_INIT_VAL`.
+const LFU_INIT_VAL: u8 = 5;
+
+const MILLIS_PER_MINUTE: i64 = 60_000;
+
+/// Which keys are evicted when the keyspace uses more than `maxmemory`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Don't evict anything. Commands that could use more memory fail.
+    NoEviction,
+    AllKeysLru,
+    /// Evict the least recently used keys that have an expiration.
+    VolatileLru,
+    AllKeysRandom,
+    /// Evict random keys that have an expiration.
+    VolatileRandom,
+    AllKeysLfu,
+    /// Evict the least frequently used keys that have an expiration.
+    VolatileLfu,
+}
+
+impl EvictionPolicy {
+    const ALL: [Self; 7] = [
+        Self::NoEviction,
+        Self::AllKeysLru,
+        Self::VolatileLru,
+        Self::AllKeysRandom,
+        Self::VolatileRandom,
+        Self::AllKeysLfu,
+        Self::VolatileLfu,
+    ];
+
+    /// The policy's name in `maxmemory-policy`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::VolatileLru => "volatile-lru",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileRandom => "volatile-random",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::VolatileLfu => "volatile-lfu",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether keys are evicted by access frequency, which is only tracked
+    /// then.
+    pub const fn is_lfu(self) -> bool {
+        matches!(self, Self::AllKeysLfu | Self::VolatileLfu)
+    }
+
+    const fn volatile_only(self) -> bool {
+        matches!(
+            self,
+            Self::VolatileLru | Self::VolatileRandom | Self::VolatileLfu
+        )
+    }
+}
+
+/// Settings for `maxmemory`. By default there's no limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaxMemoryConfig {
+    /// The most bytes the keyspace may use, or 0 for no limit.
+    pub limit: usize,
+    pub policy: EvictionPolicy,
+
+    /// How many keys each eviction samples. More samples approximate LRU
+    /// better, but make evictions slower.
+    pub samples: usize,
+
+    pub lfu: LfuConfig,
+}
+
+impl Default for MaxMemoryConfig {
+    fn default() -> Self {
+        Self {
+            limit: 0,
+            policy: EvictionPolicy::NoEviction,
+            samples: DEFAULT_SAMPLES,
+            lfu: LfuConfig::default(),
+        }
+    }
+}
+
+/// How access frequencies are counted for the LFU policies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LfuConfig {
+    /// How slowly the counter grows, like Redis' `lfu-log-factor`. With the
+    /// default of 10, it takes about a million accesses to saturate it.
+    pub log_factor: u32,
+
+    /// How many minutes without accesses it takes to decrement the counter,
+    /// like Redis' `lfu-decay-time`. 0 means the counter never decays.
+    pub decay_time: u32,
+}
+
+impl Default for LfuConfig {
+    fn default() -> Self {
+        Self {
+            log_factor: 10,
+            decay_time: 1,
+        }
+    }
+}
+
+/// A key's access frequency, as reported by `OBJECT FREQ`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frequency {
+    /// A logarithmic count of accesses, which saturates at 255.
+    counter: u8,
+
+    /// When the key was last accessed, in minutes since the Unix epoch.
+    accessed_at: i64,
+}
+
+impl Frequency {
+    pub const fn new(now: i64) -> Self {
+        Self {
+            counter: LFU_INIT_VAL,
+            accessed_at: now / MILLIS_PER_MINUTE,
+        }
+    }
+
+    /// The counter after decrementing it once for every `decay_time`
+    /// minutes since the key was last accessed.
+    pub fn count(&self, now: i64, config: &LfuConfig) -> u8 {
+        let idle_minutes = now / MILLIS_PER_MINUTE - self.accessed_at;
+        if config.decay_time == 0 || idle_minutes <= 0 {
+            return self.counter;
+        }
+        let periods = idle_minutes / i64::from(config.decay_time);
+        u8::try_from(periods).map_or(0, |periods| self.counter.saturating_sub(periods))
+    }
+
+    /// Counts an access. The counter is incremented with a probability that
+    /// falls as it grows, so it can count far more than 255 accesses.
+    pub fn record_access(&mut self, now: i64, config: &LfuConfig, rng: &mut Rng) {
+        self.counter = self.count(now, config);
+        self.accessed_at = now / MILLIS_PER_MINUTE;
+        if self.counter == u8::MAX {
+            return;
+        }
+        let base = f64::from(self.counter.saturating_sub(LFU_INIT_VAL));
+        if rng.next_f64() < 1.0 / base.mul_add(f64::from(config.log_factor), 1.0) {
+            self.counter += 1;
+        }
+    }
+}
+
+/// Picks the next key to evict from `dbs`, returning its database and name.
+/// Returns `None` if the policy doesn't allow evicting any of the keys.
+pub fn pick_victim(
+    dbs: &[Db],
+    config: &MaxMemoryConfig,
+    rng: &mut Rng,
+) -> Option<(usize, RedisString)> {
+    let policy = config.policy;
+    let mut candidates: Vec<(usize, &RedisString, &Entry)> = Vec::new();
+    for (index, db) in dbs.iter().enumerate() {
+        for _ in 0..config.samples {
+            // The volatile policies sample from the keys with an expiration,
+            // so they find one however rare they are.
+            let key = if policy.volatile_only() {
+                db.expires()
+                    .random_entry(|n| rng.below(n))
+                    .map(|(key, _)| key)
+            } else {
+                db.entries()
+                    .random_entry(|n| rng.below(n))
+                    .map(|(key, _)| key)
+            };
+            if let Some(key) = key {
+                if let Some(entry) = db.entries().get(key) {
+                    candidates.push((index, key, entry));
+                }
+            }
+        }
+    }
+
+    let now = unix_time_millis();
+    let victim = match policy {
+        EvictionPolicy::NoEviction => None,
+        EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru => candidates
+            .into_iter()
+            .min_by_key(|(_, _, entry)| entry.last_access),
+        EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu => candidates
+            .into_iter()
+            .min_by_key(|(_, _, entry)| entry.frequency.count(now, &config.lfu)),
+        EvictionPolicy::AllKeysRandom | EvictionPolicy::VolatileRandom => {
+            if candidates.is_empty() {
+                None
+            } else {
+                Some(candidates.swap_remove(rng.below(candidates.len())))
+            }
+        }
+    };
+    victim.map(|(index, key, _)| (index, key.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Value;
+    use crate::string::StringValue;
+
+    fn db(keys: &[(&str, i64, Option<i64>)]) -> Db {
+        let mut db = Db::default();
+        for (key, last_access, expires_at) in keys {
+            let key = RedisString::from(*key);
+            let mut entry = Entry::new(Value::String(StringValue::from("value")));
+            entry.last_access = *last_access;
+            db.insert(key.clone(), entry);
+            db.set_expires_at(&key, *expires_at);
+        }
+        db
+    }
+
+    #[test]
+    fn picks_victims_by_policy() {
+        let dbs = [
+            db(&[("a", 30, None), ("b", 10, None)]),
+            db(&[("c", 20, Some(i64::MAX)), ("d", 40, Some(i64::MAX))]),
+        ];
+        let mut rng = Rng::with_seed(1);
+        let pick = |policy, rng: &mut Rng| {
+            let config = MaxMemoryConfig {
+                limit: 1,
+                policy,
+                samples: 100,
+                lfu: LfuConfig::default(),
+            };
+            pick_victim(&dbs, &config, rng).map(|(db, key)| (db, String::try_from(key).unwrap()))
+        };
+
+        assert_eq!(pick(EvictionPolicy::NoEviction, &mut rng), None);
+        assert_eq!(
+            pick(EvictionPolicy::AllKeysLru, &mut rng),
+            Some((0, "b".to_string()))
+        );
+        assert_eq!(
+            pick(EvictionPolicy::VolatileLru, &mut rng),
+            Some((1, "c".to_string()))
+        );
+        for _ in 0..10 {
+            let (db, _) = pick(EvictionPolicy::VolatileRandom, &mut rng).unwrap();
+            assert_eq!(db, 1);
+        }
+
+        let dbs = [db(&[("a", 30, None)])];
+        let config = MaxMemoryConfig {
+            limit: 1,
+            policy: EvictionPolicy::VolatileRandom,
+            samples: 5,
+            lfu: LfuConfig::default(),
+        };
+        assert_eq!(pick_victim(&dbs, &config, &mut rng), None);
+    }
+
+    #[test]
+    fn parses_policy_names() {
+        for policy in EvictionPolicy::ALL {
+            assert_eq!(EvictionPolicy::parse(policy.name()), Some(policy));
+        }
+        assert_eq!(
+            EvictionPolicy::parse("ALLKEYS-LRU"),
+            Some(EvictionPolicy::AllKeysLru)
+        );
+        assert_eq!(
+            EvictionPolicy::parse("allkeys-lfu"),
+            Some(EvictionPolicy::AllKeysLfu)
+        );
+        assert_eq!(EvictionPolicy::parse("lfu"), None);
+    }
+
+    #[test]
+    fn frequency_grows_logarithmically_and_decays() {
+        let config = LfuConfig::default();
+        let mut rng = Rng::with_seed(3);
+        let now = 1000 * MILLIS_PER_MINUTE;
+        let mut frequency = Frequency::new(now);
+        assert_eq!(frequency.count(now, &config), LFU_INIT_VAL);
+
+        for _ in 0..100 {
+            frequency.record_access(now, &config, &mut rng);
+        }
+        let count = frequency.count(now, &config);
+        assert!((10..30).contains(&count), "count was {count}");
+        for _ in 0..10_000 {
+            frequency.record_access(now, &config, &mut rng);
+        }
+        assert!(frequency.count(now, &config) > count);
+
+        // The counter drops by one per idle minute, down to 0.
+        let later = now + 3 * MILLIS_PER_MINUTE;
+        assert_eq!(
+            frequency.count(later, &config),
+            frequency.count(now, &config) - 3
+        );
+        assert_eq!(frequency.count(now + 1000 * MILLIS_PER_MINUTE, &config), 0);
+        let no_decay = LfuConfig {
+            decay_time: 0,
+            ..config
+        };
+        assert_eq!(
+            frequency.count(later, &no_decay),
+            frequency.count(now, &config)
+        );
+    }
+}
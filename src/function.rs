@@ -0,0 +1,89 @@
+//! Server-side functions written in Rust, for crates that embed the server.
+//!
+//! A function is registered under a name with `Server::register_function`
+//! and called by clients with `FCALL name numkeys [key ...] [arg ...]`, like
+//! Redis functions but without Lua. Functions run on the core worker thread
+//! with direct access to the selected database, so they're atomic: no other
+//! command runs until they return. They can also run commands through their
+//! `Context`, like `redis.call` in Lua, which is how read-only functions are
+//! kept from writing: the command table says which commands are writes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::command::CommandResponse;
+use crate::db::Db;
+use crate::string::RedisString;
+
+pub trait ServerFunction: Send + Sync {
+    /// Runs the function. Like Redis functions, it should only touch the keys
+    /// it's called with, since those are what replication, client-side
+    /// caching and blocked clients are told about.
+    fn call(
+        &self,
+        context: &mut dyn Context,
+        keys: &[RedisString],
+        args: &[RedisString],
+    ) -> CommandResponse;
+
+    /// Whether the function never writes, so it can be called with
+    /// `FCALL_RO` and on replicas. Commands it runs with `Context::call` are
+    /// refused if they're writes.
+    fn read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Any closure with the right signature is a function that may write.
+impl<F> ServerFunction for F
+where
+    F: Fn(&mut dyn Context, &[RedisString], &[RedisString]) -> CommandResponse + Send + Sync,
+{
+    fn call(
+        &self,
+        context: &mut dyn Context,
+        keys: &[RedisString],
+        args: &[RedisString],
+    ) -> CommandResponse {
+        self(context, keys, args)
+    }
+}
+
+/// What a running function can do with the server.
+pub trait Context {
+    /// The database the function was called in. Read-only functions must
+    /// only read from it, since nothing checks what they do here.
+    fn db(&mut self) -> &mut Db;
+
+    /// Runs a command in the function's database, like `redis.call`, where
+    /// `args` start with the command's name. Errors are returned as replies.
+    /// Like in Redis, read-only functions can't run writes, and no function
+    /// can run commands that need a connection, like `SUBSCRIBE`.
+    fn call(&mut self, args: &[RedisString]) -> CommandResponse;
+}
+
+/// The registered functions by name.
+#[derive(Clone, Default)]
+pub struct Functions {
+    functions: HashMap<String, Arc<dyn ServerFunction>>,
+}
+
+impl Functions {
+    /// Registers `function` under `name`, replacing any function already
+    /// registered with that name.
+    pub fn register(&mut self, name: impl Into<String>, function: impl ServerFunction + 'static) {
+        self.functions.insert(name.into(), Arc::new(function));
+    }
+
+    pub fn get(&self, name: &RedisString) -> Option<Arc<dyn ServerFunction>> {
+        let name = std::str::from_utf8(name.as_bytes()).ok()?;
+        self.functions.get(name).cloned()
+    }
+}
+
+impl fmt::Debug for Functions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.functions.keys()).finish()
+    }
+}
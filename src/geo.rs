@@ -0,0 +1,378 @@
+//! Geospatial indexes, which store locations in sorted sets with geohashes as
+//! scores. See <https://redis.io/docs/data-types/geospatial/>.
+//!
+//! Like Redis, geohashes interleave 26 bits of latitude and 26 bits of
+//! longitude into a 52-bit integer, which a score can hold exactly. Latitudes
+//! are limited to the range of the Web Mercator projection.
+
+use std::ops::Range;
+
+use crate::zset::Score;
+
+pub const LONGITUDE_MIN: f64 = -180.0;
+pub const LONGITUDE_MAX: f64 = 180.0;
+pub const LATITUDE_MIN: f64 = -85.051_128_78;
+pub const LATITUDE_MAX: f64 = 85.051_128_78;
+
+/// Bits of precision for each of latitude and longitude.
+const STEP: u32 = 26;
+
+/// The Earth's radius as used by Redis, so distances match.
+const EARTH_RADIUS_METERS: f64 = 6_372_797.560_856;
+
+/// A longitude and latitude in degrees, within the ranges a geohash can hold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+impl Coordinates {
+    /// Returns `None` if the coordinates are out of range.
+    pub fn new(longitude: f64, latitude: f64) -> Option<Self> {
+        ((LONGITUDE_MIN..=LONGITUDE_MAX).contains(&longitude)
+            && (LATITUDE_MIN..=LATITUDE_MAX).contains(&latitude))
+        .then_some(Self {
+            longitude,
+            latitude,
+        })
+    }
+}
+
+// Coordinates are never NaN, since `new` checks their range.
+impl Eq for Coordinates {}
+
+/// Encodes coordinates as a 52-bit geohash.
+pub fn encode(coords: Coordinates) -> u64 {
+    let (lon, lat) = cell(coords, STEP);
+    interleave(lat, lon)
+}
+
+/// Decodes a geohash to the coordinates at the center of its area.
+pub fn decode(hash: u64) -> Coordinates {
+    let (lat, lon) = deinterleave(hash);
+    let cells = f64::from(1u32 << STEP);
+    let lat_min = (f64::from(lat) / cells).mul_add(LATITUDE_MAX - LATITUDE_MIN, LATITUDE_MIN);
+    let lat_max = (f64::from(lat + 1) / cells).mul_add(LATITUDE_MAX - LATITUDE_MIN, LATITUDE_MIN);
+    let lon_min = (f64::from(lon) / cells).mul_add(LONGITUDE_MAX - LONGITUDE_MIN, LONGITUDE_MIN);
+    let lon_max =
+        (f64::from(lon + 1) / cells).mul_add(LONGITUDE_MAX - LONGITUDE_MIN, LONGITUDE_MIN);
+    Coordinates {
+        longitude: f64::midpoint(lon_min, lon_max).clamp(LONGITUDE_MIN, LONGITUDE_MAX),
+        latitude: f64::midpoint(lat_min, lat_max).clamp(LATITUDE_MIN, LATITUDE_MAX),
+    }
+}
+
+/// Converts a geohash to a sorted set score, which holds it exactly.
+#[allow(clippy::cast_precision_loss)]
+pub fn to_score(hash: u64) -> Score {
+    Score::new(hash as f64).expect("geohashes aren't NaN")
+}
+
+/// Converts a sorted set score back to a geohash. Scores that weren't set by
+/// geo commands are truncated to some geohash.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub const fn from_score(score: Score) -> u64 {
+    score.value() as u64
+}
+
+/// Formats coordinates as a standard 11-character geohash string, like
+/// `GEOHASH` does.
+///
+/// Standard geohashes cover latitudes from -90 to 90 degrees, so the
+/// coordinates are encoded again with that range. Only 52 bits are encoded,
+/// so the last character is always `0`.
+pub fn to_geohash_string(coords: Coordinates) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+    let lon = index(coords.longitude, LONGITUDE_MIN, LONGITUDE_MAX, STEP);
+    let lat = index(coords.latitude, -90.0, 90.0, STEP);
+    let hash = interleave(lat, lon);
+    (0..11)
+        .map(|i| {
+            let bits = if i == 10 {
+                0
+            } else {
+                (hash >> (2 * STEP - (i + 1) * 5)) & 0x1f
+            };
+            char::from(ALPHABET[usize::try_from(bits).expect("5 bits fit in usize")])
+        })
+        .collect()
+}
+
+/// The great-circle distance between two points in meters, using the
+/// haversine formula.
+pub fn distance(from: Coordinates, to: Coordinates) -> f64 {
+    let (lat_from, lat_to) = (from.latitude.to_radians(), to.latitude.to_radians());
+    let lat_sin = ((lat_to - lat_from) / 2.0).sin();
+    let lon_sin = ((to.longitude - from.longitude).to_radians() / 2.0).sin();
+    let h = (lon_sin * lon_sin).mul_add(lat_from.cos() * lat_to.cos(), lat_sin * lat_sin);
+    2.0 * EARTH_RADIUS_METERS * h.sqrt().asin()
+}
+
+/// The area to search around a point for `GEOSEARCH`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+// Shapes are never NaN, since their dimensions are parsed as scores.
+impl Eq for Shape {}
+
+impl Shape {
+    /// Multiplies the shape's dimensions, for converting between units.
+    #[must_use]
+    pub fn scale(self, factor: f64) -> Self {
+        match self {
+            Self::Radius(radius) => Self::Radius(radius * factor),
+            Self::Box { width, height } => Self::Box {
+                width: width * factor,
+                height: height * factor,
+            },
+        }
+    }
+
+    /// Returns the distance from `center` to `point` in meters if the point is
+    /// inside the shape, whose dimensions must be in meters.
+    pub fn contains(self, center: Coordinates, point: Coordinates) -> Option<f64> {
+        match self {
+            Self::Radius(radius) => Some(distance(center, point)).filter(|d| *d <= radius),
+            Self::Box { width, height } => {
+                let lat_distance =
+                    EARTH_RADIUS_METERS * (point.latitude - center.latitude).to_radians().abs();
+                if lat_distance > height / 2.0 {
+                    return None;
+                }
+                let lon_distance = distance(
+                    Coordinates {
+                        latitude: point.latitude,
+                        ..center
+                    },
+                    point,
+                );
+                if lon_distance > width / 2.0 {
+                    return None;
+                }
+                Some(distance(center, point))
+            }
+        }
+    }
+
+    /// How far the shape extends from its center in degrees of longitude and
+    /// latitude, at the given latitude.
+    fn extent(self, latitude: f64) -> (f64, f64) {
+        let (half_width, half_height) = match self {
+            Self::Radius(radius) => (radius, radius),
+            Self::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+        let lat_delta = (half_height / EARTH_RADIUS_METERS).to_degrees();
+        // Degrees of longitude are shortest at the latitude furthest from the
+        // equator.
+        let furthest = latitude.abs() + lat_delta;
+        let lon_delta = if furthest >= 90.0 {
+            360.0
+        } else {
+            (half_width / EARTH_RADIUS_METERS / furthest.to_radians().cos()).to_degrees()
+        };
+        (lon_delta, lat_delta)
+    }
+}
+
+/// Finds ranges of geohashes that together cover `shape` around `center`, so
+/// that only those score ranges of a sorted set need to be searched.
+///
+/// The ranges are the nine geohash areas around `center`, at the most precise
+/// step where they still cover the whole shape.
+pub fn covering_ranges(center: Coordinates, shape: Shape) -> Vec<Range<u64>> {
+    let (lon_delta, lat_delta) = shape.extent(center.latitude);
+    let mut step = STEP;
+    while step > 1 {
+        let cells = f64::from(1u32 << step);
+        let cell_width = (LONGITUDE_MAX - LONGITUDE_MIN) / cells;
+        let cell_height = (LATITUDE_MAX - LATITUDE_MIN) / cells;
+        if lon_delta <= cell_width && lat_delta <= cell_height {
+            break;
+        }
+        step -= 1;
+    }
+
+    let cells = 1i64 << step;
+    let (lon, lat) = cell(center, step);
+    let mut hashes = Vec::new();
+    for lat_offset in -1..=1 {
+        let lat = i64::from(lat) + lat_offset;
+        if !(0..cells).contains(&lat) {
+            continue;
+        }
+        for lon_offset in -1..=1 {
+            // Longitudes wrap around at 180 degrees.
+            let lon = (i64::from(lon) + lon_offset).rem_euclid(cells);
+            let to_u32 = |i: i64| u32::try_from(i).expect("cells fit in u32");
+            hashes.push(interleave(to_u32(lat), to_u32(lon)));
+        }
+    }
+    hashes.sort_unstable();
+    hashes.dedup();
+
+    let shift = 2 * (STEP - step);
+    hashes
+        .into_iter()
+        .map(|hash| hash << shift..(hash + 1) << shift)
+        .collect()
+}
+
+/// Units for distances in geo commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceUnit {
+    Meters,
+    Kilometers,
+    Miles,
+    Feet,
+}
+
+impl DistanceUnit {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "m" => Some(Self::Meters),
+            "km" => Some(Self::Kilometers),
+            "mi" => Some(Self::Miles),
+            "ft" => Some(Self::Feet),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Meters => "m",
+            Self::Kilometers => "km",
+            Self::Miles => "mi",
+            Self::Feet => "ft",
+        }
+    }
+
+    pub const fn meters(self) -> f64 {
+        match self {
+            Self::Meters => 1.0,
+            Self::Kilometers => 1000.0,
+            Self::Miles => 1609.34,
+            Self::Feet => 0.3048,
+        }
+    }
+}
+
+/// Finds the indexes of the area containing `coords` at a given step, as
+/// (longitude, latitude).
+fn cell(coords: Coordinates, step: u32) -> (u32, u32) {
+    (
+        index(coords.longitude, LONGITUDE_MIN, LONGITUDE_MAX, step),
+        index(coords.latitude, LATITUDE_MIN, LATITUDE_MAX, step),
+    )
+}
+
+/// Finds which of `2^step` equal parts of `min..=max` contains `value`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn index(value: f64, min: f64, max: f64, step: u32) -> u32 {
+    let offset = (value - min) / (max - min);
+    ((offset * f64::from(1u32 << step)) as u32).min((1 << step) - 1)
+}
+
+/// Interleaves the bits of `x` and `y`, with `x` in the even bits.
+fn interleave(x: u32, y: u32) -> u64 {
+    spread(x) | (spread(y) << 1)
+}
+
+/// The inverse of `interleave`.
+const fn deinterleave(hash: u64) -> (u32, u32) {
+    (squash(hash), squash(hash >> 1))
+}
+
+/// Spreads the bits of `x` out to the even bits of the result.
+fn spread(x: u32) -> u64 {
+    let mut x = u64::from(x);
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Collects the even bits of `x`, the inverse of `spread`.
+#[allow(clippy::cast_possible_truncation)]
+const fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    (x | (x >> 16)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coords(longitude: f64, latitude: f64) -> Coordinates {
+        Coordinates::new(longitude, latitude).unwrap()
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        // Scores from the Redis documentation's GEOADD example.
+        let palermo = coords(13.361_389, 38.115_556);
+        assert_eq!(encode(palermo), 3_479_099_956_230_698);
+        assert_eq!(
+            encode(coords(15.087_269, 37.502_669)),
+            3_479_447_370_796_909
+        );
+
+        let decoded = decode(encode(palermo));
+        assert!((decoded.longitude - palermo.longitude).abs() < 1e-5);
+        assert!((decoded.latitude - palermo.latitude).abs() < 1e-5);
+
+        let corner = decode(encode(coords(180.0, LATITUDE_MAX)));
+        assert!(corner.longitude < 180.0 && corner.latitude < LATITUDE_MAX);
+        assert_eq!(to_geohash_string(decoded), "sqc8b49rny0");
+
+        assert!(Coordinates::new(180.1, 0.0).is_none());
+        assert!(Coordinates::new(0.0, -86.0).is_none());
+    }
+
+    #[test]
+    fn distances_and_shapes() {
+        // Redis measures between the decoded locations, which gives the
+        // documented GEODIST result.
+        let palermo = decode(encode(coords(13.361_389, 38.115_556)));
+        let catania = decode(encode(coords(15.087_269, 37.502_669)));
+        assert_eq!(format!("{:.4}", distance(palermo, catania)), "166274.1516");
+
+        let center = coords(15.0, 37.0);
+        assert!(Shape::Radius(200_000.0).contains(center, palermo).is_some());
+        assert!(Shape::Radius(100_000.0).contains(center, palermo).is_none());
+        let wide = Shape::Box {
+            width: 400_000.0,
+            height: 100_000.0,
+        };
+        assert!(wide.contains(center, catania).is_none());
+        assert!(wide.scale(2.0).contains(center, catania).is_some());
+    }
+
+    #[test]
+    fn covering_ranges_contain_nearby_points() {
+        let center = coords(179.9, 10.0);
+        for shape in [
+            Shape::Radius(50_000.0),
+            Shape::Box {
+                width: 1_000_000.0,
+                height: 10.0,
+            },
+        ] {
+            let ranges = covering_ranges(center, shape);
+            // Points across the antimeridian are covered too.
+            for point in [coords(-179.9, 10.0), coords(179.7, 10.0), center] {
+                let hash = encode(point);
+                assert!(ranges.iter().any(|range| range.contains(&hash)));
+            }
+        }
+        assert_eq!(covering_ranges(center, Shape::Radius(1e9)).len(), 4);
+    }
+}
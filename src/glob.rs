@@ -0,0 +1,222 @@
+//! Binary-safe glob-style pattern matching, used by commands like `SCAN` and
+//! `KEYS`. This is a port of Redis' `stringmatchlen`. Supported patterns:
+//!
+//! - `?` matches any single byte
+//! - `*` matches any sequence of bytes, including the empty sequence
+//! - `[abc]`, `[^abc]` and `[a-z]` match (or don't match) a set of bytes
+//! - `\x` matches `x` literally
+
+/// Returns whether `string` matches the glob `pattern`.
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let mut skip_longer_matches = false;
+    matches_impl(pattern, string, false, &mut skip_longer_matches, 0)
+}
+
+/// Like `matches`, but ASCII letters are compared case-insensitively.
+pub fn matches_nocase(pattern: &[u8], string: &[u8]) -> bool {
+    let mut skip_longer_matches = false;
+    matches_impl(pattern, string, true, &mut skip_longer_matches, 0)
+}
+
+/// Patterns with more nested `*`s than this never match, to protect against
+/// stack overflows.
+const MAX_NESTING: usize = 1000;
+
+fn matches_impl(
+    pattern: &[u8],
+    string: &[u8],
+    nocase: bool,
+    skip_longer_matches: &mut bool,
+    nesting: usize,
+) -> bool {
+    if nesting > MAX_NESTING {
+        return false;
+    }
+
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let (mut p, mut s) = (0, 0);
+    while p < pattern.len() && s < string.len() {
+        match pattern[p] {
+            b'*' => {
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                while s < string.len() {
+                    let rest_matches = matches_impl(
+                        &pattern[p + 1..],
+                        &string[s..],
+                        nocase,
+                        skip_longer_matches,
+                        nesting + 1,
+                    );
+                    if rest_matches {
+                        return true;
+                    }
+                    // If a nested `*` already tried every suffix of the string,
+                    // trying longer matches here can't help. This avoids
+                    // exponential blowup with patterns like `*a*a*a*b`.
+                    if *skip_longer_matches {
+                        return false;
+                    }
+                    s += 1;
+                }
+                *skip_longer_matches = true;
+                return false;
+            }
+            b'?' => s += 1,
+            b'[' => {
+                let (matched, consumed) = match_set(&pattern[p + 1..], string[s], nocase);
+                if !matched {
+                    return false;
+                }
+                p += consumed;
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if !eq(pattern[p], string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                if !eq(c, string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+
+    // Trailing `*`s match the empty string.
+    while pattern.get(p) == Some(&b'*') {
+        p += 1;
+    }
+    p == pattern.len() && s == string.len()
+}
+
+/// Matches `c` against a `[...]` set. `pattern` starts just after the opening
+/// `[`. Returns whether `c` matched and how many bytes of `pattern` the set
+/// used, including the closing `]`.
+fn match_set(pattern: &[u8], c: u8, nocase: bool) -> (bool, usize) {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let mut p = 0;
+    let negate = pattern.first() == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+    let mut matched = false;
+    loop {
+        match pattern.get(p) {
+            // An unterminated set is treated as if it were closed at the end of
+            // the pattern.
+            None => break,
+            Some(b']') => {
+                p += 1;
+                break;
+            }
+            Some(b'\\') if p + 1 < pattern.len() => {
+                p += 1;
+                matched |= eq(pattern[p], c);
+            }
+            Some(&start) if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() => {
+                let end = pattern[p + 2];
+                let (mut lo, mut hi) = (start.min(end), start.max(end));
+                let mut c = c;
+                if nocase {
+                    lo = lo.to_ascii_lowercase();
+                    hi = hi.to_ascii_lowercase();
+                    c = c.to_ascii_lowercase();
+                }
+                matched |= lo <= c && c <= hi;
+                p += 2;
+            }
+            Some(&other) => matched |= eq(other, c),
+        }
+        p += 1;
+    }
+    (matched != negate, p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal() {
+        assert!(matches(b"hello", b"hello"));
+        assert!(!matches(b"hello", b"hellO"));
+        assert!(matches_nocase(b"hello", b"hellO"));
+        assert!(!matches(b"hello", b"hello!"));
+        assert!(!matches(b"hello!", b"hello"));
+        assert!(matches(b"", b""));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches(b"*", b""));
+        assert!(matches(b"*", b"anything"));
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(!matches(b"h?llo", b"hllo"));
+        assert!(matches(b"h*llo", b"hllo"));
+        assert!(matches(b"h*llo", b"heeeello"));
+        assert!(matches(b"user:*:name", b"user:1234:name"));
+        assert!(!matches(b"user:*:name", b"user:1234:age"));
+        assert!(matches(b"a**b", b"ab"));
+    }
+
+    #[test]
+    fn sets() {
+        assert!(matches(b"h[ae]llo", b"hello"));
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-b]llo", b"hbllo"));
+        assert!(matches(b"h[b-a]llo", b"hbllo"));
+        assert!(!matches(b"h[a-b]llo", b"hcllo"));
+        assert!(matches_nocase(b"h[A-B]llo", b"hbllo"));
+        assert!(matches(b"[\\]]", b"]"));
+        assert!(matches(b"[abc", b"c"));
+        assert!(!matches(b"[", b"c"));
+        assert!(matches(b"[a]*", b"abc"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches(b"h\\*llo", b"h*llo"));
+        assert!(!matches(b"h\\*llo", b"hello"));
+        assert!(matches(b"\\?", b"?"));
+    }
+
+    #[test]
+    fn binary_safe() {
+        assert!(matches(b"a?c", b"a\x00c"));
+        assert!(matches(b"*\xff", b"\x00\x01\xff"));
+    }
+
+    #[test]
+    fn pathological_pattern_is_fast() {
+        let pattern = b"a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b";
+        let string = [b'a'; 100];
+        assert!(!matches(pattern, &string));
+    }
+}
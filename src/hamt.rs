@@ -0,0 +1,766 @@
+//! A persistent hash map, implemented as a hash array mapped trie. See
+//! <https://en.wikipedia.org/wiki/Hash_array_mapped_trie>.
+//!
+//! Nodes are shared between clones with `Arc`, so cloning a map is O(1), and
+//! modifying a clone copies only the nodes on the path to the modified key.
+//! This lets background saves serialize a consistent copy of the keyspace
+//! while the core keeps writing to it, much like Redis relies on the
+//! copy-on-write pages of a forked child.
+//!
+//! The trie also never needs rehashing. A `HashMap` that outgrows its table
+//! moves every entry into a bigger one, which stalls the core for as long as
+//! that takes on a huge keyspace, and Redis works around the same problem by
+//! rehashing its dictionaries incrementally. Here an insert adds or splits at
+//! most one node per level, so growing the keyspace costs the same for every
+//! command.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::ops::Index;
+use std::sync::Arc;
+
+/// The number of hash bits consumed by each level of the trie.
+const BITS: u32 = 5;
+
+/// Every key's hash is consumed after this many bits, so keys that still
+/// share a path at this depth have the same hash.
+const HASH_BITS: u32 = u64::BITS;
+
+/// A persistent hash map with O(1) clones. The API mirrors the subset of
+/// `HashMap` that the keyspace uses.
+#[derive(Clone)]
+pub struct HashTrieMap<K, V, S = RandomState> {
+    root: Branch<K, V>,
+    len: usize,
+    hasher: S,
+}
+
+#[derive(Clone)]
+enum Node<K, V> {
+    Branch(Branch<K, V>),
+    Leaf(Leaf<K, V>),
+}
+
+/// An interior node. Only children that exist are stored, and `bitmap` has a
+/// bit set for each of them.
+#[derive(Clone)]
+struct Branch<K, V> {
+    bitmap: u32,
+    children: Vec<Arc<Node<K, V>>>,
+}
+
+/// The entries whose keys have the same full hash, which is nearly always
+/// just one.
+#[derive(Clone)]
+struct Leaf<K, V> {
+    hash: u64,
+    entries: Vec<(K, V)>,
+}
+
+impl<K, V> HashTrieMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> HashTrieMap<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            root: Branch::default(),
+            len: 0,
+            hasher,
+        }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over the entries in an arbitrary order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            branches: vec![self.root.children.iter()],
+            entries: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    /// Iterates over the entries in trie order, starting with the first entry
+    /// whose position is at least `cursor`, and yields each with its
+    /// position. An entry's position depends only on its hash, so `SCAN` can
+    /// resume from a cursor without walking the entries before it.
+    pub fn scan(&self, cursor: u64) -> Scan<'_, K, V> {
+        let hash = hash_at(cursor);
+        let mut branches = Vec::new();
+        let mut branch = &self.root;
+        let mut shift = 0;
+        loop {
+            let (bit, index) = branch.slot(hash, shift);
+            if branch.bitmap & bit == 0 {
+                branches.push(branch.children[index..].iter());
+                break;
+            }
+            match &*branch.children[index] {
+                Node::Branch(next) => {
+                    branches.push(branch.children[index + 1..].iter());
+                    branch = next;
+                    shift += BITS;
+                }
+                Node::Leaf(leaf) => {
+                    // The leaf's path can diverge from the cursor's below
+                    // this level, putting it before the cursor.
+                    let skip = usize::from(position(leaf.hash) < cursor);
+                    branches.push(branch.children[index + skip..].iter());
+                    break;
+                }
+            }
+        }
+        Scan {
+            branches,
+            entries: [].iter(),
+            position: 0,
+        }
+    }
+
+    /// Picks an entry at random, choosing a child at each level of the trie
+    /// with `below(n)`, which must return a number less than `n`. Entries in
+    /// sparse parts of the trie are picked more often, so like Redis'
+    /// `dictGetRandomKey` this is only good enough for sampling.
+    pub fn random_entry(&self, mut below: impl FnMut(usize) -> usize) -> Option<(&K, &V)> {
+        let mut branch = &self.root;
+        loop {
+            if branch.children.is_empty() {
+                return None;
+            }
+            match &*branch.children[below(branch.children.len())] {
+                Node::Branch(next) => branch = next,
+                Node::Leaf(leaf) => {
+                    let (key, value) = &leaf.entries[below(leaf.entries.len())];
+                    return Some((key, value));
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S> HashTrieMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key);
+        let mut branch = &self.root;
+        let mut shift = 0;
+        loop {
+            let child = branch.child(hash, shift)?;
+            match &**child {
+                Node::Branch(next) => branch = next,
+                Node::Leaf(leaf) => return leaf.get(hash, key),
+            }
+            shift += BITS;
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+}
+
+impl<K, V, S> HashTrieMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Looks up a key for modification. Nodes on the path to the key that are
+    /// shared with clones of the map are copied first.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        // Don't copy any nodes if there's nothing to modify.
+        if !self.contains_key(key) {
+            return None;
+        }
+        let hash = self.hasher.hash_one(key);
+        self.root.get_mut(hash, 0, key)
+    }
+
+    /// Inserts a key, returning the value it replaced, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hasher.hash_one(&key);
+        let replaced = self.root.insert(hash, 0, key, value);
+        if replaced.is_none() {
+            self.len += 1;
+        }
+        replaced
+    }
+
+    /// Removes a key, returning its value if it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.contains_key(key) {
+            return None;
+        }
+        let hash = self.hasher.hash_one(key);
+        let removed = self.root.remove(hash, 0, key);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+}
+
+/// The position of a hash in trie order. Each level of the trie consumes the
+/// next `BITS` of the hash from the low end, so those chunks become digits
+/// from the high end.
+fn position(hash: u64) -> u64 {
+    let mut position = 0;
+    let mut shift = 0;
+    while shift < HASH_BITS {
+        let width = BITS.min(HASH_BITS - shift);
+        position = (position << width) | ((hash >> shift) & ((1 << width) - 1));
+        shift += BITS;
+    }
+    position
+}
+
+/// The hash at a position in trie order, the inverse of `position`.
+fn hash_at(position: u64) -> u64 {
+    let mut hash = 0;
+    let mut shift = 0;
+    let mut remaining = HASH_BITS;
+    while shift < HASH_BITS {
+        let width = BITS.min(HASH_BITS - shift);
+        remaining -= width;
+        hash |= ((position >> remaining) & ((1 << width) - 1)) << shift;
+        shift += BITS;
+    }
+    hash
+}
+
+impl<K, V> Branch<K, V> {
+    /// The bit in `bitmap` and the index in `children` where the child for
+    /// `hash` goes at this level.
+    const fn slot(&self, hash: u64, shift: u32) -> (u32, usize) {
+        let bit = 1 << ((hash >> shift) & ((1 << BITS) - 1));
+        let index = (self.bitmap & (bit - 1)).count_ones() as usize;
+        (bit, index)
+    }
+
+    fn child(&self, hash: u64, shift: u32) -> Option<&Arc<Node<K, V>>> {
+        let (bit, index) = self.slot(hash, shift);
+        (self.bitmap & bit != 0).then(|| &self.children[index])
+    }
+}
+
+impl<K, V> Branch<K, V>
+where
+    K: Eq + Clone,
+    V: Clone,
+{
+    fn get_mut<Q>(&mut self, hash: u64, shift: u32, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let (bit, index) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        match Arc::make_mut(&mut self.children[index]) {
+            Node::Branch(branch) => branch.get_mut(hash, shift + BITS, key),
+            Node::Leaf(leaf) => leaf.get_mut(hash, key),
+        }
+    }
+
+    fn insert(&mut self, hash: u64, shift: u32, key: K, value: V) -> Option<V> {
+        let (bit, index) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            self.bitmap |= bit;
+            self.children
+                .insert(index, Arc::new(Node::Leaf(Leaf::new(hash, key, value))));
+            return None;
+        }
+
+        let child = &mut self.children[index];
+        if let Node::Leaf(leaf) = &**child {
+            if leaf.hash != hash {
+                // Push the existing leaf down to make room for the new one.
+                let branch = Self::pair(
+                    shift + BITS,
+                    leaf.hash,
+                    Arc::clone(child),
+                    hash,
+                    Arc::new(Node::Leaf(Leaf::new(hash, key, value))),
+                );
+                *child = Arc::new(Node::Branch(branch));
+                return None;
+            }
+        }
+        match Arc::make_mut(child) {
+            Node::Branch(branch) => branch.insert(hash, shift + BITS, key, value),
+            Node::Leaf(leaf) => leaf.insert(key, value),
+        }
+    }
+
+    /// Creates a branch at `shift` that holds two nodes with different
+    /// hashes, adding more levels until the hashes diverge.
+    fn pair(
+        shift: u32,
+        hash1: u64,
+        node1: Arc<Node<K, V>>,
+        hash2: u64,
+        node2: Arc<Node<K, V>>,
+    ) -> Self {
+        debug_assert!(shift < HASH_BITS, "different hashes diverge in time");
+        let mut branch = Self::default();
+        let (bit1, _) = branch.slot(hash1, shift);
+        let (bit2, _) = branch.slot(hash2, shift);
+        branch.bitmap = bit1 | bit2;
+        branch.children = match bit1.cmp(&bit2) {
+            Ordering::Equal => {
+                let inner = Self::pair(shift + BITS, hash1, node1, hash2, node2);
+                vec![Arc::new(Node::Branch(inner))]
+            }
+            Ordering::Less => vec![node1, node2],
+            Ordering::Greater => vec![node2, node1],
+        };
+        branch
+    }
+
+    fn remove<Q>(&mut self, hash: u64, shift: u32, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let (bit, index) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        let child = &mut self.children[index];
+        let removed = match Arc::make_mut(child) {
+            Node::Branch(branch) => branch.remove(hash, shift + BITS, key),
+            Node::Leaf(leaf) => leaf.remove(key),
+        };
+
+        // Keep the trie compact: drop empty children, and replace branches
+        // left with a single leaf by the leaf itself.
+        match &**child {
+            Node::Leaf(leaf) if leaf.entries.is_empty() => {
+                self.bitmap &= !bit;
+                self.children.remove(index);
+            }
+            Node::Branch(branch)
+                if branch.children.len() == 1 && matches!(*branch.children[0], Node::Leaf(_)) =>
+            {
+                let leaf = Arc::clone(&branch.children[0]);
+                *child = leaf;
+            }
+            _ => {}
+        }
+        removed
+    }
+}
+
+impl<K, V> Default for Branch<K, V> {
+    fn default() -> Self {
+        Self {
+            bitmap: 0,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl<K, V> Leaf<K, V> {
+    fn new(hash: u64, key: K, value: V) -> Self {
+        Self {
+            hash,
+            entries: vec![(key, value)],
+        }
+    }
+
+    fn get<Q>(&self, hash: u64, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        if self.hash != hash {
+            return None;
+        }
+        self.entries
+            .iter()
+            .find(|(k, _)| k.borrow() == key)
+            .map(|(_, v)| v)
+    }
+
+    fn get_mut<Q>(&mut self, hash: u64, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        if self.hash != hash {
+            return None;
+        }
+        self.entries
+            .iter_mut()
+            .find(|(k, _)| k.borrow() == key)
+            .map(|(_, v)| v)
+    }
+}
+
+impl<K: Eq, V> Leaf<K, V> {
+    /// Inserts a key with this leaf's hash.
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some((_, v)) = self.entries.iter_mut().find(|(k, _)| *k == key) {
+            return Some(std::mem::replace(v, value));
+        }
+        self.entries.push((key, value));
+        None
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let index = self.entries.iter().position(|(k, _)| k.borrow() == key)?;
+        Some(self.entries.swap_remove(index).1)
+    }
+}
+
+/// An iterator over the entries of a `HashTrieMap`.
+pub struct Iter<'a, K, V> {
+    /// The children left to visit in each branch on the path to the current
+    /// leaf.
+    branches: Vec<std::slice::Iter<'a, Arc<Node<K, V>>>>,
+    entries: std::slice::Iter<'a, (K, V)>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.entries.next() {
+                self.remaining -= 1;
+                return Some((key, value));
+            }
+            let branch = self.branches.last_mut()?;
+            match branch.next().map(|child| &**child) {
+                None => {
+                    self.branches.pop();
+                }
+                Some(Node::Branch(branch)) => self.branches.push(branch.children.iter()),
+                Some(Node::Leaf(leaf)) => self.entries = leaf.entries.iter(),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+/// An iterator over the entries of a `HashTrieMap` in trie order, from
+/// `HashTrieMap::scan`.
+pub struct Scan<'a, K, V> {
+    /// The children left to visit in each branch on the path to the current
+    /// leaf.
+    branches: Vec<std::slice::Iter<'a, Arc<Node<K, V>>>>,
+    entries: std::slice::Iter<'a, (K, V)>,
+
+    /// The position of the current leaf.
+    position: u64,
+}
+
+impl<'a, K, V> Iterator for Scan<'a, K, V> {
+    type Item = (u64, (&'a K, &'a V));
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.entries.next() {
+                return Some((self.position, (key, value)));
+            }
+            let branch = self.branches.last_mut()?;
+            match branch.next().map(|child| &**child) {
+                None => {
+                    self.branches.pop();
+                }
+                Some(Node::Branch(branch)) => self.branches.push(branch.children.iter()),
+                Some(Node::Leaf(leaf)) => {
+                    self.position = position(leaf.hash);
+                    self.entries = leaf.entries.iter();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a HashTrieMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Clone, V: Clone, S> IntoIterator for HashTrieMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = std::vec::IntoIter<(K, V)>;
+
+    /// Moves the entries out of nodes that aren't shared with other maps, and
+    /// clones the rest.
+    fn into_iter(self) -> Self::IntoIter {
+        fn drain<K: Clone, V: Clone>(node: Arc<Node<K, V>>, entries: &mut Vec<(K, V)>) {
+            match Arc::unwrap_or_clone(node) {
+                Node::Branch(branch) => {
+                    for child in branch.children {
+                        drain(child, entries);
+                    }
+                }
+                Node::Leaf(leaf) => entries.extend(leaf.entries),
+            }
+        }
+
+        let mut entries = Vec::with_capacity(self.len);
+        for child in self.root.children {
+            drain(child, &mut entries);
+        }
+        entries.into_iter()
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for HashTrieMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for HashTrieMap<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S: Default> Default for HashTrieMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S, Q> Index<&Q> for HashTrieMap<K, V, S>
+where
+    K: Hash + Eq + Borrow<Q>,
+    S: BuildHasher,
+    Q: Hash + Eq + ?Sized,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("key not found in map")
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for HashTrieMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// A hasher that only keeps the low byte of integer keys, so keys
+    /// frequently share paths and collide.
+    #[derive(Default)]
+    struct LowByteHasher(u64);
+
+    impl Hasher for LowByteHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, _bytes: &[u8]) {
+            unimplemented!("only integer keys are supported");
+        }
+
+        fn write_u16(&mut self, n: u16) {
+            // Spread the bits out, so paths share some levels but not all.
+            self.0 = u64::from(n & 0xff).wrapping_mul(0x0101_0101_0101_0101);
+        }
+    }
+
+    type Colliding = BuildHasherDefault<LowByteHasher>;
+
+    fn check<S: BuildHasher>(map: &HashTrieMap<u16, u16, S>, model: &HashMap<u16, u16>) {
+        assert_eq!(map.len(), model.len());
+        assert_eq!(map.iter().len(), model.len());
+        let entries: HashMap<u16, u16> = map.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(&entries, model);
+        for (key, value) in model {
+            assert_eq!(map.get(key), Some(value));
+        }
+    }
+
+    proptest! {
+        // Compares the map against a `HashMap` after a random series of
+        // inserts, updates and removes, with hashes that collide a lot.
+        #[test]
+        fn matches_hashmap(ops in prop::collection::vec((0u8..3, 0u16..600, any::<u16>()), 0..300)) {
+            let mut map: HashTrieMap<u16, u16, Colliding> = HashTrieMap::default();
+            let mut model = HashMap::new();
+            for (op, key, value) in ops {
+                match op {
+                    0 => prop_assert_eq!(map.insert(key, value), model.insert(key, value)),
+                    1 => prop_assert_eq!(map.remove(&key), model.remove(&key)),
+                    _ => {
+                        if let Some(v) = map.get_mut(&key) {
+                            *v = value;
+                        }
+                        if let Some(v) = model.get_mut(&key) {
+                            *v = value;
+                        }
+                    }
+                }
+            }
+            check(&map, &model);
+        }
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut map = HashTrieMap::new();
+        let mut model = HashMap::new();
+        for key in 0..1000 {
+            map.insert(key, key);
+            model.insert(key, key);
+        }
+
+        let snapshot = map.clone();
+        let snapshot_model = model.clone();
+        for key in (0..1000).step_by(3) {
+            map.remove(&key);
+            model.remove(&key);
+        }
+        for key in (1..1000).step_by(3) {
+            *map.get_mut(&key).unwrap() += 1;
+            *model.get_mut(&key).unwrap() += 1;
+        }
+        map.insert(5000, 1);
+        model.insert(5000, 1);
+
+        check(&map, &model);
+        check(&snapshot, &snapshot_model);
+    }
+
+    #[test]
+    fn random_entries_cover_the_map() {
+        let mut map: HashTrieMap<u16, u16, Colliding> = HashTrieMap::default();
+        assert_eq!(map.random_entry(|_| 0), None);
+        for key in 0..50 {
+            map.insert(key, key);
+        }
+        let mut rng = crate::random::Rng::with_seed(7);
+        let mut seen = HashMap::new();
+        for _ in 0..5000 {
+            let (key, value) = map.random_entry(|n| rng.below(n)).unwrap();
+            seen.insert(*key, *value);
+        }
+        check(&map, &seen);
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        for hash in [0, 1, 31, 32, 0x0101_0101_0101_0101, u64::MAX - 1, u64::MAX] {
+            assert_eq!(hash_at(position(hash)), hash);
+        }
+        assert_eq!(position(1), 1 << 59);
+        assert_eq!(position(1 << 60), 1);
+    }
+
+    #[test]
+    fn scan_resumes_in_trie_order() {
+        let mut map: HashTrieMap<u16, u16, Colliding> = HashTrieMap::default();
+        for key in 0..600 {
+            map.insert(key, key);
+        }
+        let all: Vec<(u64, u16)> = map.scan(0).map(|(pos, (key, _))| (pos, *key)).collect();
+        assert_eq!(all.len(), map.len());
+        assert!(all.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+        // Starting at any entry's position, or just after it, skips exactly
+        // the entries before it.
+        for (pos, _) in &all {
+            for cursor in [*pos, pos.wrapping_add(1)] {
+                let rest: Vec<(u64, u16)> = map
+                    .scan(cursor)
+                    .map(|(pos, (key, _))| (pos, *key))
+                    .collect();
+                let expected: Vec<(u64, u16)> = all
+                    .iter()
+                    .copied()
+                    .filter(|(pos, _)| *pos >= cursor)
+                    .collect();
+                assert_eq!(rest, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn into_iter_moves_or_clones_entries() {
+        let map: HashTrieMap<u16, u16> = (0..100).map(|key| (key, key * 2)).collect();
+        let snapshot = map.clone();
+        let mut entries: Vec<(u16, u16)> = map.into_iter().collect();
+        entries.sort_unstable();
+        assert_eq!(
+            entries,
+            (0..100).map(|key| (key, key * 2)).collect::<Vec<_>>()
+        );
+        assert_eq!(snapshot[&42], 84);
+        assert!(!snapshot.contains_key(&100));
+    }
+}
@@ -0,0 +1,241 @@
+//! Hashes, which map fields to values. See
+//! <https://redis.io/docs/data-types/hashes/>.
+//!
+//! Small hashes are stored as an array of field and value pairs, which saves
+//! a hash table's overhead when there are millions of tiny hashes. Lookups
+//! search the array, so a hash is converted to a hash table once it grows
+//! past `MAX_LISTPACK_ENTRIES` fields or gets a field or value longer than
+//! `MAX_LISTPACK_VALUE` bytes, the limits Redis uses for its listpack
+//! encoding. Unlike a listpack, the fields and values are still allocated
+//! separately. Hashes are never converted back.
+
+use std::collections::{hash_map, HashMap};
+use std::slice;
+
+use crate::string::RedisString;
+
+/// Redis' default `hash-max-listpack-entries`.
+pub const MAX_LISTPACK_ENTRIES: usize = 128;
+
+/// Redis' default `hash-max-listpack-value`.
+pub const MAX_LISTPACK_VALUE: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Hash {
+    encoding: Encoding,
+}
+
+#[derive(Debug, Clone)]
+enum Encoding {
+    /// Fields and their values in insertion order.
+    Listpack(Vec<(RedisString, RedisString)>),
+    HashTable(HashMap<RedisString, RedisString>),
+}
+
+impl Hash {
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => pairs.len(),
+            Encoding::HashTable(map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, field: &RedisString) -> Option<&RedisString> {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => pairs.iter().find(|(f, _)| f == field).map(|(_, v)| v),
+            Encoding::HashTable(map) => map.get(field),
+        }
+    }
+
+    pub fn contains_key(&self, field: &RedisString) -> bool {
+        self.get(field).is_some()
+    }
+
+    /// Sets a field, returning its previous value.
+    pub fn insert(&mut self, field: RedisString, value: RedisString) -> Option<RedisString> {
+        if let Encoding::Listpack(pairs) = &self.encoding {
+            let fits = field.len() <= MAX_LISTPACK_VALUE
+                && value.len() <= MAX_LISTPACK_VALUE
+                && (pairs.len() < MAX_LISTPACK_ENTRIES || pairs.iter().any(|(f, _)| *f == field));
+            if !fits {
+                self.convert_to_hash_table();
+            }
+        }
+        match &mut self.encoding {
+            Encoding::Listpack(pairs) => {
+                if let Some((_, current)) = pairs.iter_mut().find(|(f, _)| *f == field) {
+                    return Some(std::mem::replace(current, value));
+                }
+                pairs.push((field, value));
+                None
+            }
+            Encoding::HashTable(map) => map.insert(field, value),
+        }
+    }
+
+    /// Removes a field, returning its value.
+    pub fn remove(&mut self, field: &RedisString) -> Option<RedisString> {
+        match &mut self.encoding {
+            Encoding::Listpack(pairs) => {
+                let index = pairs.iter().position(|(f, _)| f == field)?;
+                Some(pairs.remove(index).1)
+            }
+            Encoding::HashTable(map) => map.remove(field),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => Iter::Listpack(pairs.iter()),
+            Encoding::HashTable(map) => Iter::HashTable(map.iter()),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &RedisString> {
+        self.iter().map(|(field, _)| field)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &RedisString> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    pub const fn is_listpack(&self) -> bool {
+        matches!(self.encoding, Encoding::Listpack(_))
+    }
+
+    /// How the hash is stored, as reported by `OBJECT ENCODING`.
+    pub const fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Listpack(_) => "array",
+            Encoding::HashTable(_) => "hashtable",
+        }
+    }
+
+    fn convert_to_hash_table(&mut self) {
+        if let Encoding::Listpack(pairs) = &mut self.encoding {
+            let map = std::mem::take(pairs).into_iter().collect();
+            self.encoding = Encoding::HashTable(map);
+        }
+    }
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Listpack(Vec::new()),
+        }
+    }
+}
+
+/// Hashes are equal when they have the same fields with the same values,
+/// regardless of how they're stored.
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(field, value)| other.get(field) == Some(value))
+    }
+}
+
+impl Eq for Hash {}
+
+pub enum Iter<'a> {
+    Listpack(slice::Iter<'a, (RedisString, RedisString)>),
+    HashTable(hash_map::Iter<'a, RedisString, RedisString>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a RedisString, &'a RedisString);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Listpack(pairs) => pairs.next().map(|(field, value)| (field, value)),
+            Self::HashTable(map) => map.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Listpack(pairs) => pairs.size_hint(),
+            Self::HashTable(map) => map.size_hint(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Hash {
+    type Item = (&'a RedisString, &'a RedisString);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<(RedisString, RedisString)> for Hash {
+    fn from_iter<I: IntoIterator<Item = (RedisString, RedisString)>>(iter: I) -> Self {
+        let mut hash = Self::default();
+        for (field, value) in iter {
+            hash.insert(field, value);
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(s: &str) -> RedisString {
+        RedisString::from(s)
+    }
+
+    #[test]
+    fn listpack_operations() {
+        let mut hash = Hash::default();
+        assert_eq!(hash.insert(s("b"), s("1")), None);
+        assert_eq!(hash.insert(s("a"), s("2")), None);
+        assert_eq!(hash.insert(s("b"), s("3")), Some(s("1")));
+        assert_eq!(hash.len(), 2);
+        assert_eq!(hash.get(&s("b")), Some(&s("3")));
+        assert!(!hash.contains_key(&s("c")));
+
+        // Fields stay in insertion order.
+        let fields: Vec<_> = hash.keys().cloned().collect();
+        assert_eq!(fields, [s("b"), s("a")]);
+
+        assert_eq!(hash.remove(&s("b")), Some(s("3")));
+        assert_eq!(hash.remove(&s("b")), None);
+        assert_eq!(hash.encoding(), "array");
+    }
+
+    #[test]
+    fn converts_to_hash_table() {
+        let mut hash: Hash = (0..MAX_LISTPACK_ENTRIES)
+            .map(|n| (RedisString::from(n.to_string()), s("v")))
+            .collect();
+        assert_eq!(hash.encoding(), "array");
+        let listpack = hash.clone();
+        hash.insert(s("one more"), s("v"));
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.len(), MAX_LISTPACK_ENTRIES + 1);
+        assert_eq!(hash.get(&s("7")), Some(&s("v")));
+
+        // Removing fields doesn't convert it back.
+        hash.remove(&s("one more"));
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash, listpack);
+
+        let mut hash = Hash::default();
+        hash.insert(s("field"), s("short"));
+        hash.insert(
+            s("field"),
+            RedisString::from("x".repeat(MAX_LISTPACK_VALUE + 1)),
+        );
+        assert_eq!(hash.encoding(), "hashtable");
+    }
+}
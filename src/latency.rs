@@ -0,0 +1,287 @@
+//! The latency monitor, which `LATENCY` reports. See
+//! <https://redis.io/docs/management/optimization/latency-monitor/>.
+//!
+//! It records latency spikes: events that took at least
+//! `latency-monitor-threshold`. Each event keeps its most recent spikes, at
+//! most one per second, and the worst it's ever had.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::db::unix_time_millis;
+
+/// How many spikes each event keeps, like Redis' `LATENCY_TS_LEN`.
+const HISTORY_LEN: usize = 160;
+
+/// Something whose latency is monitored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// Running a command on the core thread.
+    Command,
+
+    /// Copying the dataset to save it in the background, which is what
+    /// Redis forks for.
+    Fork,
+
+    /// Removing expired keys that weren't accessed.
+    ExpireCycle,
+}
+
+impl Event {
+    pub const ALL: [Self; 3] = [Self::Command, Self::Fork, Self::ExpireCycle];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Command => "command",
+            Self::Fork => "fork",
+            Self::ExpireCycle => "expire-cycle",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event| event.name() == name)
+    }
+}
+
+/// A latency spike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Unix time in seconds.
+    pub time: i64,
+
+    /// The latency in milliseconds. Spikes in the same second are merged,
+    /// keeping the worst.
+    pub latency: u64,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    samples: VecDeque<Sample>,
+
+    /// The worst latency in milliseconds since the event was last reset.
+    max: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct LatencyMonitor {
+    events: BTreeMap<Event, History>,
+}
+
+impl LatencyMonitor {
+    /// Records a spike that just happened.
+    pub fn record(&mut self, event: Event, duration: Duration) {
+        let latency = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        self.record_at(event, latency, unix_time_millis() / 1000);
+    }
+
+    fn record_at(&mut self, event: Event, latency: u64, time: i64) {
+        let history = self.events.entry(event).or_default();
+        history.max = history.max.max(latency);
+        match history.samples.back_mut() {
+            Some(last) if last.time == time => last.latency = last.latency.max(latency),
+            _ => {
+                if history.samples.len() == HISTORY_LEN {
+                    history.samples.pop_front();
+                }
+                history.samples.push_back(Sample { time, latency });
+            }
+        }
+    }
+
+    /// The latest spike of every event that's had one, and its worst
+    /// latency.
+    pub fn latest(&self) -> Vec<(Event, Sample, u64)> {
+        self.events
+            .iter()
+            .filter_map(|(&event, history)| {
+                let latest = history.samples.back()?;
+                Some((event, *latest, history.max))
+            })
+            .collect()
+    }
+
+    /// An event's spikes, oldest first.
+    pub fn history(&self, event: Event) -> Vec<Sample> {
+        self.events
+            .get(&event)
+            .map(|history| history.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forgets the spikes of `events`, or of every event if it's empty.
+    /// Returns how many events had spikes.
+    pub fn reset(&mut self, events: &[Event]) -> usize {
+        if events.is_empty() {
+            let count = self.events.len();
+            self.events.clear();
+            return count;
+        }
+        events
+            .iter()
+            .filter(|event| self.events.remove(event).is_some())
+            .count()
+    }
+
+    /// A report on the spikes for `LATENCY DOCTOR`, with advice for the
+    /// events that had them. `enabled` is whether the monitor is on.
+    pub fn doctor(&self, enabled: bool) -> String {
+        if !enabled {
+            return "I'm sorry, Dave, I can't do that. Latency monitoring is disabled in this \
+                Redis instance. You may use \"CONFIG SET latency-monitor-threshold \
+                <milliseconds>.\" in order to enable it."
+                .to_string();
+        }
+        if self.events.is_empty() {
+            return "Dave, no latency spike was observed during the lifetime of this Redis \
+                instance, not in the slightest bit. I honestly think you ought to sleep tonight."
+                .to_string();
+        }
+
+        let mut report = "Dave, I have observed latency spikes in this Redis instance. You don't \
+            mind talking about it, do you Dave?\n\n"
+            .to_string();
+        for (number, (event, history)) in self.events.iter().enumerate() {
+            let stats = HistoryStats::new(&history.samples);
+            let _ = writeln!(
+                report,
+                "{}. {}: {} latency spikes (average {}ms, mean deviation {}ms, period {} sec). \
+                 Worst all time event {}ms.",
+                number + 1,
+                event.name(),
+                history.samples.len(),
+                stats.average,
+                stats.mean_deviation,
+                stats.period,
+                history.max,
+            );
+        }
+        report.push_str("\nI have a few advices for you:\n\n");
+        for event in self.events.keys() {
+            let advice = match event {
+                Event::Command => {
+                    "- Check INFO commandstats for commands with a high usec_per_call. Commands \
+                     that are O(N) in the size of a value, like LRANGE or SMEMBERS on big keys, \
+                     block every other client while they run."
+                }
+                Event::Fork => {
+                    "- BGSAVE, BGREWRITEAOF and full syncs with replicas copy the dataset \
+                     before saving it, which takes longer the more keys there are."
+                }
+                Event::ExpireCycle => {
+                    "- Many keys expired at the same time. Consider spreading their expire \
+                     times out, like by adding a random number of seconds to them."
+                }
+            };
+            report.push_str(advice);
+            report.push('\n');
+        }
+        report
+    }
+}
+
+/// Figures `LATENCY DOCTOR` reports for an event's spikes.
+struct HistoryStats {
+    /// The average latency, in milliseconds.
+    average: u64,
+
+    /// The average difference of a latency from the average.
+    mean_deviation: u64,
+
+    /// The average number of seconds between spikes.
+    period: i64,
+}
+
+impl HistoryStats {
+    fn new(samples: &VecDeque<Sample>) -> Self {
+        let count = u64::try_from(samples.len()).unwrap_or(u64::MAX).max(1);
+        let average = samples.iter().map(|sample| sample.latency).sum::<u64>() / count;
+        let mean_deviation = samples
+            .iter()
+            .map(|sample| sample.latency.abs_diff(average))
+            .sum::<u64>()
+            / count;
+        let period = match (samples.front(), samples.back()) {
+            (Some(first), Some(last)) if samples.len() > 1 => {
+                (last.time - first.time) / i64::try_from(samples.len() - 1).unwrap_or(i64::MAX)
+            }
+            _ => 0,
+        };
+        Self {
+            average,
+            mean_deviation,
+            period,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_spikes() {
+        let mut monitor = LatencyMonitor::default();
+        monitor.record_at(Event::Command, 10, 100);
+        // Spikes in the same second are merged.
+        monitor.record_at(Event::Command, 30, 100);
+        monitor.record_at(Event::Command, 20, 101);
+        monitor.record_at(Event::Fork, 5, 102);
+
+        assert_eq!(
+            monitor.history(Event::Command),
+            [
+                Sample {
+                    time: 100,
+                    latency: 30
+                },
+                Sample {
+                    time: 101,
+                    latency: 20
+                },
+            ]
+        );
+        assert_eq!(monitor.history(Event::ExpireCycle), []);
+        assert_eq!(
+            monitor.latest(),
+            [
+                (
+                    Event::Command,
+                    Sample {
+                        time: 101,
+                        latency: 20
+                    },
+                    30
+                ),
+                (
+                    Event::Fork,
+                    Sample {
+                        time: 102,
+                        latency: 5
+                    },
+                    5
+                ),
+            ]
+        );
+        assert!(monitor.doctor(true).contains(
+            "1. command: 2 latency spikes (average 25ms, mean deviation 5ms, period 1 sec). \
+             Worst all time event 30ms.\n"
+        ));
+
+        assert_eq!(monitor.reset(&[Event::Fork, Event::ExpireCycle]), 1);
+        assert_eq!(monitor.latest().len(), 1);
+        assert_eq!(monitor.reset(&[]), 1);
+        assert!(monitor.doctor(true).starts_with("Dave, no latency spike"));
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut monitor = LatencyMonitor::default();
+        for time in 0..1000 {
+            monitor.record_at(Event::ExpireCycle, 1, time);
+        }
+        let history = monitor.history(Event::ExpireCycle);
+        assert_eq!(history.len(), HISTORY_LEN);
+        assert_eq!(history[0].time, 1000 - 160);
+    }
+}
@@ -0,0 +1,37 @@
+//! Background freeing of values that were removed from the keyspace, so
+//! commands like `UNLINK` and `FLUSHALL ASYNC` don't stall the core worker
+//! thread while deallocating huge values.
+
+use std::thread;
+
+use crossbeam_channel::Sender;
+
+/// A `LazyFree` owns a dedicated thread that drops whatever values are sent to
+/// it.
+#[derive(Debug)]
+pub struct LazyFree {
+    sender: Sender<Box<dyn Send>>,
+}
+
+impl LazyFree {
+    /// Starts the lazy-free thread. The thread exits once the `LazyFree` is
+    /// dropped and all pending values are freed.
+    pub fn start() -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded::<Box<dyn Send>>();
+        thread::spawn(move || {
+            while let Ok(value) = receiver.recv() {
+                drop(value);
+            }
+        });
+        Self { sender }
+    }
+
+    /// Frees `value` on the lazy-free thread.
+    pub fn free<T: Send + 'static>(&self, value: T) {
+        if let Err(err) = self.sender.send(Box::new(value)) {
+            // The thread is gone, so free the value here instead.
+            log::warn!("lazy-free thread is not running, freeing inline");
+            drop(err.0);
+        }
+    }
+}
@@ -3,12 +3,54 @@
     clippy::cargo_common_metadata,
     clippy::doc_markdown,
     clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
     clippy::module_name_repetitions,
     clippy::must_use_candidate,
     clippy::new_without_default
 )]
 
+pub mod acl;
+pub mod aof;
+pub mod bitmap;
+pub mod blocking;
+pub mod cluster;
 pub mod command;
+pub mod command_table;
+pub mod config;
+pub mod crc16;
+pub mod crc64;
+pub mod db;
+pub mod debug;
+pub mod evict;
+pub mod function;
+pub mod geo;
+pub mod glob;
+pub mod hamt;
+pub mod hash;
+pub mod latency;
+pub mod lazyfree;
+pub mod mem_size;
+pub mod pubsub;
+pub mod random;
+pub mod rdb;
+pub mod replica;
+pub mod replication;
 pub mod resp;
+#[cfg(feature = "serde")]
+pub mod resp_serde;
+pub mod scan;
+pub mod script;
+pub mod sentinel;
 pub mod server;
+pub mod set;
+pub mod sha1;
+pub mod sha256;
+pub mod skiplist;
+pub mod snapshot;
+pub mod sort;
+pub mod stats;
+pub mod stream;
 pub mod string;
+pub mod tls;
+pub mod tracking;
+pub mod zset;
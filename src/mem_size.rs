@@ -0,0 +1,166 @@
+//! Estimating how many bytes values take up, for `maxmemory` and `MEMORY
+//! USAGE`.
+//!
+//! Sizes are estimated from the data a value holds plus a fixed overhead per
+//! element, not measured from what's actually allocated. Each `Db` caches the
+//! size of every entry and keeps a running total, so finding how much memory
+//! the keyspace uses never has to walk it.
+
+use std::collections::VecDeque;
+
+use crate::hash::Hash;
+use crate::set::Set;
+use crate::stream::{Fields, RangeBound, Stream, StreamId};
+use crate::string::{RedisString, StringValue};
+use crate::zset::SortedSet;
+
+/// The bytes each element of a collection takes up besides its data, for
+/// pointers and allocator bookkeeping.
+const ELEMENT_OVERHEAD: usize = 16;
+
+/// The bytes each entry of a listpack takes up besides its data, for its
+/// encoding header and back length.
+const LISTPACK_ENTRY_OVERHEAD: usize = 2;
+
+pub trait MemSize {
+    /// Roughly how many bytes the value takes up.
+    fn mem_size(&self) -> usize;
+}
+
+impl MemSize for RedisString {
+    fn mem_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl MemSize for StringValue {
+    fn mem_size(&self) -> usize {
+        match self {
+            // Shared integers don't take up anything per key.
+            Self::Int(_) if self.is_shared() => 0,
+            Self::Int(_) => size_of::<i64>(),
+            Self::Raw(s) => s.mem_size(),
+        }
+    }
+}
+
+impl MemSize for VecDeque<RedisString> {
+    fn mem_size(&self) -> usize {
+        self.iter().map(list_element_size).sum()
+    }
+}
+
+impl MemSize for Hash {
+    fn mem_size(&self) -> usize {
+        let listpack = self.is_listpack();
+        self.iter()
+            .map(|pair| hash_field_size(listpack, pair))
+            .sum()
+    }
+}
+
+impl MemSize for Set {
+    fn mem_size(&self) -> usize {
+        // Intsets are a flat array, so there's no need to format members.
+        self.intset_width().map_or_else(
+            || {
+                self.iter()
+                    .map(|member| set_member_size(None, &member))
+                    .sum()
+            },
+            |width| self.len() * width,
+        )
+    }
+}
+
+impl MemSize for SortedSet {
+    fn mem_size(&self) -> usize {
+        let listpack = self.is_listpack();
+        self.iter()
+            .map(|(member, _)| zset_member_size(listpack, member))
+            .sum()
+    }
+}
+
+impl MemSize for Stream {
+    fn mem_size(&self) -> usize {
+        self.range(RangeBound::Min, RangeBound::Max)
+            .map(|(_, fields)| stream_entry_size(fields))
+            .sum()
+    }
+}
+
+pub fn list_element_size(element: &RedisString) -> usize {
+    element.len() + ELEMENT_OVERHEAD
+}
+
+/// The size of a field and its value, which take up a listpack entry each
+/// in small hashes.
+pub fn hash_field_size(listpack: bool, (field, value): (&RedisString, &RedisString)) -> usize {
+    let overhead = if listpack {
+        2 * LISTPACK_ENTRY_OVERHEAD
+    } else {
+        ELEMENT_OVERHEAD
+    };
+    field.len() + value.len() + overhead
+}
+
+/// The size of a member, which takes up `intset_width` bytes in sets of
+/// integers.
+pub fn set_member_size(intset_width: Option<usize>, member: &RedisString) -> usize {
+    intset_width.unwrap_or(member.len() + ELEMENT_OVERHEAD)
+}
+
+/// The size of a member and its score, which take up a listpack entry each
+/// in small sorted sets.
+pub fn zset_member_size(listpack: bool, member: &RedisString) -> usize {
+    let overhead = if listpack {
+        2 * LISTPACK_ENTRY_OVERHEAD
+    } else {
+        ELEMENT_OVERHEAD
+    };
+    member.len() + size_of::<f64>() + overhead
+}
+
+pub fn stream_entry_size(fields: &Fields) -> usize {
+    let fields: usize = fields
+        .iter()
+        .map(|(field, value)| field.len() + value.len() + ELEMENT_OVERHEAD)
+        .sum();
+    size_of::<StreamId>() + fields + ELEMENT_OVERHEAD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zset::Score;
+
+    #[test]
+    fn collection_sizes() {
+        let s = |s: &str| RedisString::from(s);
+        assert_eq!(s("hello").mem_size(), 5);
+        assert_eq!(StringValue::from("hello").mem_size(), 5);
+        assert_eq!(StringValue::from("12").mem_size(), 0);
+        assert_eq!(StringValue::from("123456").mem_size(), 8);
+
+        let list = VecDeque::from([s("a"), s("bc")]);
+        assert_eq!(list.mem_size(), 3 + 2 * ELEMENT_OVERHEAD);
+
+        // Small hashes and sorted sets are listpacks, which take up less.
+        let mut hash: Hash = std::iter::once((s("field"), s("value"))).collect();
+        assert_eq!(hash.mem_size(), 10 + 2 * LISTPACK_ENTRY_OVERHEAD);
+        hash.insert(s("big"), RedisString::from("x".repeat(100)));
+        assert_eq!(hash.mem_size(), 10 + 103 + 2 * ELEMENT_OVERHEAD);
+
+        // Sets of integers are intsets, which are smaller still.
+        let mut set: Set = [s("1"), s("2")].into_iter().collect();
+        assert_eq!(set.mem_size(), 2 * 2);
+        set.insert(s("a"));
+        assert_eq!(set.mem_size(), 3 + 3 * ELEMENT_OVERHEAD);
+        assert_eq!(Set::default().mem_size(), 0);
+
+        let mut zset = SortedSet::default();
+        zset.insert(s("member"), Score::new(1.0).unwrap());
+        assert_eq!(zset.mem_size(), 6 + 8 + 2 * LISTPACK_ENTRY_OVERHEAD);
+    }
+}
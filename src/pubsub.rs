@@ -0,0 +1,201 @@
+//! Bookkeeping for pub/sub.
+//!
+//! Clients subscribe to channels by name with `SUBSCRIBE`, or to every channel
+//! matching a glob-style pattern with `PSUBSCRIBE`, and are pushed the
+//! messages sent to those channels with `PUBLISH`. See
+//! <https://redis.io/docs/manual/pubsub/>.
+//!
+//! Like Redis, channel and pattern subscriptions are kept apart: a client
+//! subscribed to both `news` and `n*` gets a message published to `news`
+//! twice, and a client's subscription count includes both kinds.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::glob;
+use crate::string::RedisString;
+
+/// The subscriptions of every client.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    /// Subscribers to each channel.
+    channels: HashMap<RedisString, BTreeSet<usize>>,
+
+    /// Subscribers to each pattern, ordered so messages go out in a stable
+    /// order.
+    patterns: BTreeMap<RedisString, BTreeSet<usize>>,
+
+    /// The channels and patterns each client is subscribed to.
+    clients: HashMap<usize, Subscriptions>,
+}
+
+#[derive(Debug, Default)]
+struct Subscriptions {
+    channels: BTreeSet<RedisString>,
+    patterns: BTreeSet<RedisString>,
+}
+
+impl PubSub {
+    /// Subscribes a client to a channel, returning how many channels and
+    /// patterns it's subscribed to now.
+    pub fn subscribe(&mut self, client: usize, channel: &RedisString) -> usize {
+        let subscriptions = self.clients.entry(client).or_default();
+        if subscriptions.channels.insert(channel.clone()) {
+            self.channels
+                .entry(channel.clone())
+                .or_default()
+                .insert(client);
+        }
+        self.count(client)
+    }
+
+    /// Unsubscribes a client from a channel, returning how many channels and
+    /// patterns it's still subscribed to.
+    pub fn unsubscribe(&mut self, client: usize, channel: &RedisString) -> usize {
+        let removed = self
+            .clients
+            .get_mut(&client)
+            .is_some_and(|subscriptions| subscriptions.channels.remove(channel));
+        if removed {
+            if let Some(clients) = self.channels.get_mut(channel) {
+                clients.remove(&client);
+                if clients.is_empty() {
+                    self.channels.remove(channel);
+                }
+            }
+        }
+        self.forget_if_unsubscribed(client)
+    }
+
+    /// Subscribes a client to every channel matching `pattern`, returning
+    /// how many channels and patterns it's subscribed to now.
+    pub fn psubscribe(&mut self, client: usize, pattern: &RedisString) -> usize {
+        let subscriptions = self.clients.entry(client).or_default();
+        if subscriptions.patterns.insert(pattern.clone()) {
+            self.patterns
+                .entry(pattern.clone())
+                .or_default()
+                .insert(client);
+        }
+        self.count(client)
+    }
+
+    /// Unsubscribes a client from a pattern, returning how many channels and
+    /// patterns it's still subscribed to.
+    pub fn punsubscribe(&mut self, client: usize, pattern: &RedisString) -> usize {
+        let removed = self
+            .clients
+            .get_mut(&client)
+            .is_some_and(|subscriptions| subscriptions.patterns.remove(pattern));
+        if removed {
+            if let Some(clients) = self.patterns.get_mut(pattern) {
+                clients.remove(&client);
+                if clients.is_empty() {
+                    self.patterns.remove(pattern);
+                }
+            }
+        }
+        self.forget_if_unsubscribed(client)
+    }
+
+    /// The channels a client is subscribed to, for `UNSUBSCRIBE` without
+    /// arguments.
+    pub fn channels(&self, client: usize) -> Vec<RedisString> {
+        self.clients
+            .get(&client)
+            .map(|subscriptions| subscriptions.channels.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The patterns a client is subscribed to, for `PUNSUBSCRIBE` without
+    /// arguments.
+    pub fn patterns(&self, client: usize) -> Vec<RedisString> {
+        self.clients
+            .get(&client)
+            .map(|subscriptions| subscriptions.patterns.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drops every subscription of a client that disconnected.
+    pub fn remove_client(&mut self, client: usize) {
+        for channel in self.channels(client) {
+            self.unsubscribe(client, &channel);
+        }
+        for pattern in self.patterns(client) {
+            self.punsubscribe(client, &pattern);
+        }
+    }
+
+    /// The clients a message published to `channel` goes to, along with the
+    /// pattern it matched for pattern subscribers. A client gets one message
+    /// per matching subscription.
+    pub fn receivers(&self, channel: &RedisString) -> Vec<(usize, Option<&RedisString>)> {
+        let subscribers = self
+            .channels
+            .get(channel)
+            .into_iter()
+            .flatten()
+            .map(|client| (*client, None));
+        let pattern_subscribers = self
+            .patterns
+            .iter()
+            .filter(|(pattern, _)| glob::matches(pattern.as_bytes(), channel.as_bytes()))
+            .flat_map(|(pattern, clients)| {
+                clients.iter().map(move |client| (*client, Some(pattern)))
+            });
+        subscribers.chain(pattern_subscribers).collect()
+    }
+
+    /// How many channels and patterns a client is subscribed to.
+    pub fn count(&self, client: usize) -> usize {
+        self.clients.get(&client).map_or(0, |subscriptions| {
+            subscriptions.channels.len() + subscriptions.patterns.len()
+        })
+    }
+
+    fn forget_if_unsubscribed(&mut self, client: usize) -> usize {
+        let count = self.count(client);
+        if count == 0 {
+            self.clients.remove(&client);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_and_patterns_are_counted_separately() {
+        let mut pubsub = PubSub::default();
+        let news = RedisString::from("news");
+        let pattern = RedisString::from("n*");
+        assert_eq!(pubsub.subscribe(1, &news), 1);
+        assert_eq!(pubsub.subscribe(1, &news), 1);
+        assert_eq!(pubsub.psubscribe(1, &pattern), 2);
+        assert_eq!(pubsub.psubscribe(2, &pattern), 1);
+        assert_eq!(pubsub.psubscribe(2, &RedisString::from("x?")), 2);
+
+        assert_eq!(
+            pubsub.receivers(&news),
+            [(1, None), (1, Some(&pattern)), (2, Some(&pattern))]
+        );
+        assert_eq!(
+            pubsub.receivers(&RedisString::from("xy")),
+            [(2, Some(&RedisString::from("x?")))]
+        );
+        assert!(pubsub.receivers(&RedisString::from("other")).is_empty());
+
+        // Unsubscribing from a channel leaves pattern subscriptions alone.
+        assert_eq!(pubsub.unsubscribe(1, &news), 1);
+        assert_eq!(pubsub.unsubscribe(1, &news), 1);
+        assert_eq!(pubsub.patterns(1), std::slice::from_ref(&pattern));
+        assert_eq!(pubsub.punsubscribe(1, &pattern), 0);
+        assert_eq!(pubsub.receivers(&news), [(2, Some(&pattern))]);
+
+        pubsub.remove_client(2);
+        assert!(pubsub.receivers(&news).is_empty());
+        assert!(pubsub.clients.is_empty());
+        assert!(pubsub.patterns.is_empty());
+    }
+}
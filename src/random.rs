@@ -0,0 +1,86 @@
+//! A small pseudo-random number generator for commands that pick random
+//! elements, like `ZRANDMEMBER`. It doesn't need to be cryptographically
+//! secure, just cheap and good enough to sample with.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// An xorshift64* generator. See <https://vigna.di.unimi.it/ftp/papers/xorshift.pdf>.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rng {
+    /// Creates a generator with a random seed, taken from the random keys the
+    /// standard library uses for `HashMap`.
+    pub fn new() -> Self {
+        Self::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    pub const fn with_seed(seed: u64) -> Self {
+        // The state must never be zero.
+        Self { state: seed | 1 }
+    }
+
+    pub const fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a random index less than `len`, which must not be 0.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn below(&mut self, len: usize) -> usize {
+        assert!(len > 0, "can't pick from an empty range");
+        (self.next_u64() % len as u64) as usize
+    }
+
+    /// Returns a random number in `[0, 1)`.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fill the mantissa exactly.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Shuffles the first `count` items into a uniformly random selection of
+    /// `items`, in random order.
+    pub fn partial_shuffle<T>(&mut self, items: &mut [T], count: usize) {
+        for i in 0..count.min(items.len()) {
+            let j = i + self.below(items.len() - i);
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn below_stays_in_range() {
+        let mut rng = Rng::with_seed(42);
+        let mut seen = [false; 5];
+        for _ in 0..1000 {
+            seen[rng.below(5)] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn partial_shuffle_is_a_permutation() {
+        let mut rng = Rng::with_seed(7);
+        let mut items: Vec<u32> = (0..20).collect();
+        rng.partial_shuffle(&mut items, 5);
+        let mut sorted = items.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+    }
+}
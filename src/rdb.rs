@@ -0,0 +1,1343 @@
+//! Serialization of values in Redis' RDB format, which is used for `DUMP` and
+//! `RESTORE` payloads and for snapshot files. See
+//! <https://rdb.fnordig.de/file_format.html> for a description of the format.
+
+use std::io::{self, Read, Write};
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+
+use crate::crc64::crc64;
+use crate::db::{unix_time_millis, Db, Entry, Value};
+use crate::string::{RedisString, StringValue};
+use crate::zset::Score;
+
+/// The RDB format version we write.
+pub const RDB_VERSION: u16 = 11;
+
+/// The newest RDB format version we can read, which is the one Redis 7.4
+/// writes. Its new value types for hashes with field expirations aren't
+/// supported.
+pub const MAX_RDB_VERSION: u16 = 12;
+
+/// Value type identifiers. Besides the plain encodings we write, Redis writes
+/// small collections in compact encodings, which we can read.
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// Quicklist node containers in `TYPE_LIST_QUICKLIST_2`.
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+/// Opcodes that introduce the non-value records of a snapshot file.
+const OPCODE_SLOT_INFO: u8 = 0xf4;
+const OPCODE_FUNCTION2: u8 = 0xf5;
+const OPCODE_MODULE_AUX: u8 = 0xf7;
+const OPCODE_IDLE: u8 = 0xf8;
+const OPCODE_FREQ: u8 = 0xf9;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+/// Special string encodings, stored in the low bits of a length byte whose two
+/// high bits are set.
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// Strings longer than this are LZF-compressed, as in Redis.
+const LZF_MIN_LEN: usize = 20;
+
+/// Opcodes in the data of module values, which we skip.
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_FLOAT: u64 = 3;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+/// Serializes a value as a `DUMP` payload: the RDB-encoded value followed by a
+/// footer with the RDB version and a CRC-64 checksum.
+pub fn dump(value: &Value) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    write_object(&mut payload, value)?;
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    Ok(payload)
+}
+
+/// Deserializes a `DUMP` payload, verifying its footer.
+pub fn restore(payload: &[u8]) -> Result<Value> {
+    let mut reader = verify_payload(payload)?;
+    let value = read_object(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(eyre!("trailing bytes after value"));
+    }
+    Ok(value)
+}
+
+/// Checks the version and checksum in a `DUMP` payload's footer, and returns
+/// the payload without the footer.
+pub fn verify_payload(payload: &[u8]) -> Result<&[u8]> {
+    let Some(footer_start) = payload.len().checked_sub(10) else {
+        return Err(eyre!("payload is too short"));
+    };
+    let (data, mut footer) = payload.split_at(footer_start);
+    let version = u16::from_le_bytes(read_array(&mut footer)?);
+    if version > MAX_RDB_VERSION {
+        return Err(eyre!("unsupported RDB version {version}"));
+    }
+    let crc = u64::from_le_bytes(read_array(&mut footer)?);
+    if crc64(0, &payload[..payload.len() - 8]) != crc {
+        return Err(eyre!("checksum mismatch"));
+    }
+    Ok(data)
+}
+
+/// Writes a snapshot of every database: a header, the keys of each non-empty
+/// database with their expiration times, and a footer with a CRC-64 checksum.
+/// Expired keys are left out.
+///
+/// Streams can't be serialized, so they are skipped with a warning.
+pub fn write_snapshot<W: Write>(writer: W, dbs: &[Db]) -> Result<()> {
+    let mut writer = ChecksumWriter {
+        inner: writer,
+        crc: 0,
+    };
+    write!(writer, "REDIS{RDB_VERSION:04}")?;
+
+    let now = unix_time_millis();
+    for (index, db) in dbs.iter().enumerate() {
+        let entries: Vec<_> = db
+            .entries()
+            .iter()
+            .filter(|(key, entry)| {
+                if matches!(entry.value, Value::Stream(_)) {
+                    log::warn!("skipping stream {key:?}, which can't be saved");
+                    return false;
+                }
+                !db.is_expired(key, now)
+            })
+            .collect();
+        if entries.is_empty() {
+            continue;
+        }
+
+        writer.write_all(&[OPCODE_SELECTDB])?;
+        write_length(&mut writer, index as u64)?;
+        writer.write_all(&[OPCODE_RESIZEDB])?;
+        write_length(&mut writer, entries.len() as u64)?;
+        let expires = entries
+            .iter()
+            .filter(|(key, _)| db.expires_at(key).is_some());
+        write_length(&mut writer, expires.count() as u64)?;
+
+        for (key, entry) in entries {
+            if let Some(expires_at) = db.expires_at(key) {
+                writer.write_all(&[OPCODE_EXPIRETIME_MS])?;
+                writer.write_all(&expires_at.to_le_bytes())?;
+            }
+            writer.write_all(&[value_type(&entry.value)?])?;
+            write_string(&mut writer, key.as_bytes())?;
+            write_value(&mut writer, &entry.value)?;
+        }
+    }
+
+    writer.write_all(&[OPCODE_EOF])?;
+    let crc = writer.crc;
+    writer.inner.write_all(&crc.to_le_bytes())?;
+    writer.inner.flush()?;
+    Ok(())
+}
+
+/// Reads a snapshot into `num_databases` databases. Snapshots written by
+/// `redis-server` can be read too.
+///
+/// Keys that have expired since the snapshot was taken are dropped, and keys
+/// holding streams or module values are skipped with a warning.
+pub fn read_snapshot<R: Read>(reader: R, num_databases: usize) -> Result<Vec<Db>> {
+    let mut reader = ChecksumReader {
+        inner: reader,
+        crc: 0,
+        offset: 0,
+    };
+    let dbs = read_snapshot_data(&mut reader, num_databases);
+    dbs.wrap_err_with(|| format!("invalid RDB data at offset {}", reader.offset))
+}
+
+#[allow(clippy::too_many_lines)]
+fn read_snapshot_data<R: Read>(
+    mut reader: &mut ChecksumReader<R>,
+    num_databases: usize,
+) -> Result<Vec<Db>> {
+    let header: [u8; 9] = read_array(&mut reader).wrap_err("failed to read header")?;
+    let version = header
+        .strip_prefix(b"REDIS")
+        .and_then(|version| std::str::from_utf8(version).ok())
+        .and_then(|version| version.parse::<u16>().ok())
+        .ok_or_else(|| eyre!("not an RDB file"))?;
+    if version > MAX_RDB_VERSION {
+        return Err(eyre!("unsupported RDB version {version}"));
+    }
+
+    let now = unix_time_millis();
+    let mut dbs: Vec<Db> = (0..num_databases).map(|_| Db::default()).collect();
+    let mut db = 0;
+    let mut expires_at = None;
+    loop {
+        let opcode = read_u8(&mut reader).wrap_err("failed to read opcode")?;
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => {
+                let index = read_length(&mut reader)?;
+                db = usize::try_from(index)
+                    .ok()
+                    .filter(|&index| index < num_databases)
+                    .ok_or_else(|| eyre!("database index {index} is out of range"))?;
+            }
+            OPCODE_RESIZEDB => {
+                // Only a hint for preallocating the database.
+                read_length(&mut reader)?;
+                read_length(&mut reader)?;
+            }
+            OPCODE_EXPIRETIME_MS => {
+                expires_at = Some(i64::from_le_bytes(read_array(&mut reader)?));
+            }
+            OPCODE_EXPIRETIME => {
+                let secs = i32::from_le_bytes(read_array(&mut reader)?);
+                expires_at = Some(i64::from(secs) * 1000);
+            }
+            OPCODE_AUX => {
+                let field = RedisString::from(read_string(&mut reader)?);
+                let value = RedisString::from(read_string(&mut reader)?);
+                log::debug!("RDB aux field {field:?}: {value:?}");
+            }
+            OPCODE_IDLE => {
+                read_length(&mut reader)?;
+            }
+            OPCODE_FREQ => {
+                read_u8(&mut reader)?;
+            }
+            OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    read_length(&mut reader)?;
+                }
+            }
+            OPCODE_FUNCTION2 => {
+                read_string(&mut reader)?;
+                log::warn!("skipping function library, since functions aren't supported");
+            }
+            OPCODE_MODULE_AUX => {
+                read_length(&mut reader)?;
+                skip_module_data(&mut reader).wrap_err("failed to skip module data")?;
+                log::warn!("skipping module data, since modules aren't supported");
+            }
+            value_type => {
+                let key =
+                    RedisString::from(read_string(&mut reader).wrap_err("failed to read key")?);
+                let expires_at = expires_at.take();
+                let value = match value_type {
+                    TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                        skip_stream(&mut reader, value_type)
+                            .wrap_err_with(|| format!("failed to skip stream {key:?}"))?;
+                        log::warn!("skipping stream {key:?}, since streams can't be loaded");
+                        continue;
+                    }
+                    TYPE_MODULE_2 => {
+                        read_length(&mut reader)?;
+                        skip_module_data(&mut reader)
+                            .wrap_err_with(|| format!("failed to skip module value {key:?}"))?;
+                        log::warn!("skipping module value {key:?}, since modules aren't supported");
+                        continue;
+                    }
+                    _ => read_value(&mut reader, value_type)
+                        .wrap_err_with(|| format!("failed to read value of key {key:?}"))?,
+                };
+                if expires_at.is_none_or(|t| t > now) {
+                    dbs[db].insert(key.clone(), Entry::new(value));
+                    dbs[db].set_expires_at(&key, expires_at);
+                }
+            }
+        }
+    }
+
+    // Versions before 5 have no checksum, and a checksum of 0 means it was
+    // disabled when the file was written.
+    let crc = reader.crc;
+    if version >= 5 {
+        let expected = u64::from_le_bytes(read_array(&mut reader.inner)?);
+        reader.offset += 8;
+        if expected != 0 && expected != crc {
+            return Err(eyre!("checksum mismatch"));
+        }
+    }
+    Ok(dbs)
+}
+
+/// Computes the CRC-64 of everything read through it, and counts the bytes so
+/// errors can point at where the data is invalid.
+struct ChecksumReader<R> {
+    inner: R,
+    crc: u64,
+    offset: u64,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc = crc64(self.crc, &buf[..read]);
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+/// Computes the CRC-64 of everything written through it.
+struct ChecksumWriter<W> {
+    inner: W,
+    crc: u64,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc = crc64(self.crc, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes a value's type followed by the value itself.
+pub fn write_object<W: Write>(writer: &mut W, value: &Value) -> Result<()> {
+    writer.write_all(&[value_type(value)?])?;
+    write_value(writer, value)
+}
+
+fn value_type(value: &Value) -> Result<u8> {
+    match value {
+        Value::String(_) => Ok(TYPE_STRING),
+        Value::List(_) => Ok(TYPE_LIST),
+        Value::Set(_) => Ok(TYPE_SET),
+        Value::Hash(_) => Ok(TYPE_HASH),
+        Value::ZSet(_) => Ok(TYPE_ZSET_2),
+        // Streams are stored as a radix tree of listpacks, which we don't
+        // implement.
+        Value::Stream(_) => Err(eyre!("streams can't be serialized")),
+    }
+}
+
+fn write_value<W: Write>(writer: &mut W, value: &Value) -> Result<()> {
+    match value {
+        Value::String(s) => write_string(writer, s.to_redis_string().as_bytes()),
+        Value::List(list) => {
+            // Lists use the original linked-list encoding, which is simpler
+            // than the quicklist encoding newer Redis versions write, and which
+            // they can still load.
+            write_length(writer, list.len() as u64)?;
+            for elem in list {
+                write_string(writer, elem.as_bytes())?;
+            }
+            Ok(())
+        }
+        Value::Set(set) => {
+            write_length(writer, set.len() as u64)?;
+            for member in set {
+                write_string(writer, member.as_bytes())?;
+            }
+            Ok(())
+        }
+        Value::Hash(hash) => {
+            write_length(writer, hash.len() as u64)?;
+            for (field, value) in hash {
+                write_string(writer, field.as_bytes())?;
+                write_string(writer, value.as_bytes())?;
+            }
+            Ok(())
+        }
+        Value::ZSet(zset) => {
+            // Scores are stored as little-endian binary doubles.
+            write_length(writer, zset.len() as u64)?;
+            for (member, score) in zset {
+                write_string(writer, member.as_bytes())?;
+                writer.write_all(&score.value().to_le_bytes())?;
+            }
+            Ok(())
+        }
+        Value::Stream(_) => Err(eyre!("streams can't be serialized")),
+    }
+}
+
+/// Reads a value's type followed by the value itself.
+pub fn read_object<R: Read>(reader: &mut R) -> Result<Value> {
+    let value_type = read_u8(reader).wrap_err("failed to read value type")?;
+    read_value(reader, value_type)
+}
+
+fn read_value<R: Read>(reader: &mut R, value_type: u8) -> Result<Value> {
+    match value_type {
+        TYPE_STRING => Ok(Value::String(StringValue::from(RedisString::from(
+            read_string(reader)?,
+        )))),
+        TYPE_LIST => {
+            let len = read_length(reader)?;
+            let list = (0..len)
+                .map(|_| read_string(reader).map(RedisString::from))
+                .collect::<Result<_>>()?;
+            Ok(Value::List(list))
+        }
+        TYPE_SET => {
+            let len = read_length(reader)?;
+            let set = (0..len)
+                .map(|_| read_string(reader).map(RedisString::from))
+                .collect::<Result<_>>()?;
+            Ok(Value::Set(set))
+        }
+        TYPE_ZSET => {
+            // The original sorted set encoding, with scores as strings.
+            let len = read_length(reader)?;
+            let zset = (0..len)
+                .map(|_| {
+                    let member = RedisString::from(read_string(reader)?);
+                    Ok((member, read_string_score(reader)?))
+                })
+                .collect::<Result<_>>()?;
+            Ok(Value::ZSet(zset))
+        }
+        TYPE_HASH => {
+            let len = read_length(reader)?;
+            let hash = (0..len)
+                .map(|_| {
+                    let field = RedisString::from(read_string(reader)?);
+                    let value = RedisString::from(read_string(reader)?);
+                    Ok((field, value))
+                })
+                .collect::<Result<_>>()?;
+            Ok(Value::Hash(hash))
+        }
+        TYPE_ZSET_2 => {
+            let len = read_length(reader)?;
+            let zset = (0..len)
+                .map(|_| {
+                    let member = RedisString::from(read_string(reader)?);
+                    let score = f64::from_le_bytes(read_array(reader)?);
+                    let score = Score::new(score).ok_or_else(|| eyre!("score is NaN"))?;
+                    Ok((member, score))
+                })
+                .collect::<Result<_>>()?;
+            Ok(Value::ZSet(zset))
+        }
+        TYPE_HASH_ZIPMAP => hash_from(parse_zipmap(&read_string(reader)?)?),
+        TYPE_LIST_ZIPLIST => Ok(list_from(parse_ziplist(&read_string(reader)?)?)),
+        TYPE_SET_INTSET => Ok(set_from(parse_intset(&read_string(reader)?)?)),
+        TYPE_ZSET_ZIPLIST => zset_from(parse_ziplist(&read_string(reader)?)?),
+        TYPE_HASH_ZIPLIST => hash_from(parse_ziplist(&read_string(reader)?)?),
+        TYPE_LIST_QUICKLIST => {
+            let len = read_length(reader)?;
+            let mut elements = Vec::new();
+            for _ in 0..len {
+                elements.extend(parse_ziplist(&read_string(reader)?)?);
+            }
+            Ok(list_from(elements))
+        }
+        TYPE_HASH_LISTPACK => hash_from(parse_listpack(&read_string(reader)?)?),
+        TYPE_ZSET_LISTPACK => zset_from(parse_listpack(&read_string(reader)?)?),
+        TYPE_LIST_QUICKLIST_2 => {
+            let len = read_length(reader)?;
+            let mut elements = Vec::new();
+            for _ in 0..len {
+                match read_length(reader)? {
+                    // Large elements are stored on their own.
+                    QUICKLIST_NODE_PLAIN => elements.push(read_string(reader)?),
+                    QUICKLIST_NODE_PACKED => {
+                        elements.extend(parse_listpack(&read_string(reader)?)?);
+                    }
+                    container => return Err(eyre!("invalid quicklist container {container}")),
+                }
+            }
+            Ok(list_from(elements))
+        }
+        TYPE_SET_LISTPACK => Ok(set_from(parse_listpack(&read_string(reader)?)?)),
+        _ => Err(eyre!("unsupported value type {value_type}")),
+    }
+}
+
+fn list_from(elements: Vec<Vec<u8>>) -> Value {
+    Value::List(elements.into_iter().map(RedisString::from).collect())
+}
+
+fn set_from(elements: Vec<Vec<u8>>) -> Value {
+    Value::Set(elements.into_iter().map(RedisString::from).collect())
+}
+
+/// Builds a hash from alternating fields and values.
+fn hash_from(elements: Vec<Vec<u8>>) -> Result<Value> {
+    let hash = pairs(elements)?
+        .map(|(field, value)| (RedisString::from(field), RedisString::from(value)))
+        .collect();
+    Ok(Value::Hash(hash))
+}
+
+/// Builds a sorted set from alternating members and scores.
+fn zset_from(elements: Vec<Vec<u8>>) -> Result<Value> {
+    let zset = pairs(elements)?
+        .map(|(member, score)| Ok((RedisString::from(member), parse_score(&score)?)))
+        .collect::<Result<_>>()?;
+    Ok(Value::ZSet(zset))
+}
+
+fn pairs(elements: Vec<Vec<u8>>) -> Result<impl Iterator<Item = (Vec<u8>, Vec<u8>)>> {
+    if !elements.len().is_multiple_of(2) {
+        return Err(eyre!("odd number of elements in a hash or sorted set"));
+    }
+    let mut elements = elements.into_iter();
+    Ok(std::iter::from_fn(move || {
+        Some((elements.next()?, elements.next()?))
+    }))
+}
+
+fn parse_score(score: &[u8]) -> Result<Score> {
+    std::str::from_utf8(score)
+        .ok()
+        .and_then(|score| score.parse::<f64>().ok())
+        .and_then(Score::new)
+        .ok_or_else(|| eyre!("invalid score {:?}", RedisString::from(score)))
+}
+
+/// Reads a score stored as a string whose length is a single byte, with
+/// special lengths for NaN and infinities.
+fn read_string_score<R: Read>(reader: &mut R) -> Result<Score> {
+    match read_u8(reader)? {
+        253 => Err(eyre!("score is NaN")),
+        254 => Ok(Score::new(f64::INFINITY).expect("infinity is a valid score")),
+        255 => Ok(Score::new(f64::NEG_INFINITY).expect("infinity is a valid score")),
+        len => parse_score(&read_bytes(reader, u64::from(len))?),
+    }
+}
+
+/// Decodes a ziplist, the compact encoding Redis used for small lists, hashes
+/// and sorted sets before Redis 7.
+fn parse_ziplist(mut data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let data = &mut data;
+    // Total bytes, offset of the last entry, and number of entries.
+    read_array::<_, 10>(data)?;
+
+    let mut elements = Vec::new();
+    loop {
+        // Each entry starts with the length of the previous one, and the list
+        // ends with a byte that no length starts with.
+        match read_u8(data)? {
+            0xff => break,
+            0xfe => {
+                read_array::<_, 4>(data)?;
+            }
+            _ => {}
+        }
+        let encoding = read_u8(data)?;
+        let element = match encoding {
+            0x00..=0x3f => read_bytes(data, u64::from(encoding))?,
+            0x40..=0x7f => {
+                let len = u64::from(encoding & 0x3f) << 8 | u64::from(read_u8(data)?);
+                read_bytes(data, len)?
+            }
+            0x80 => {
+                let len = u32::from_be_bytes(read_array(data)?);
+                read_bytes(data, u64::from(len))?
+            }
+            0xc0 => i16::from_le_bytes(read_array(data)?)
+                .to_string()
+                .into_bytes(),
+            0xd0 => i32::from_le_bytes(read_array(data)?)
+                .to_string()
+                .into_bytes(),
+            0xe0 => i64::from_le_bytes(read_array(data)?)
+                .to_string()
+                .into_bytes(),
+            0xf0 => read_i24(data)?.to_string().into_bytes(),
+            0xfe => i8::from_le_bytes(read_array(data)?)
+                .to_string()
+                .into_bytes(),
+            // Small integers are stored in the encoding itself, offset by one.
+            0xf1..=0xfd => ((encoding & 0x0f) - 1).to_string().into_bytes(),
+            _ => return Err(eyre!("invalid ziplist encoding {encoding:#x}")),
+        };
+        elements.push(element);
+    }
+    Ok(elements)
+}
+
+/// Decodes a listpack, the compact encoding Redis 7 uses for small lists,
+/// hashes, sets and sorted sets.
+fn parse_listpack(mut data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let data = &mut data;
+    // Total bytes and number of elements.
+    read_array::<_, 6>(data)?;
+
+    let mut elements = Vec::new();
+    loop {
+        let encoding = read_u8(data)?;
+        let (element, entry_len) = match encoding {
+            0xff => break,
+            0x00..=0x7f => (encoding.to_string().into_bytes(), 1),
+            0x80..=0xbf => {
+                let len = u64::from(encoding & 0x3f);
+                (read_bytes(data, len)?, 1 + len)
+            }
+            0xc0..=0xdf => {
+                // A 13-bit signed integer.
+                let i = i16::from(encoding & 0x1f) << 8 | i16::from(read_u8(data)?);
+                let i = if i >= 1 << 12 { i - (1 << 13) } else { i };
+                (i.to_string().into_bytes(), 2)
+            }
+            0xe0..=0xef => {
+                let len = u64::from(encoding & 0x0f) << 8 | u64::from(read_u8(data)?);
+                (read_bytes(data, len)?, 2 + len)
+            }
+            0xf0 => {
+                let len = u64::from(u32::from_le_bytes(read_array(data)?));
+                (read_bytes(data, len)?, 5 + len)
+            }
+            0xf1 => (
+                i16::from_le_bytes(read_array(data)?)
+                    .to_string()
+                    .into_bytes(),
+                3,
+            ),
+            0xf2 => (read_i24(data)?.to_string().into_bytes(), 4),
+            0xf3 => (
+                i32::from_le_bytes(read_array(data)?)
+                    .to_string()
+                    .into_bytes(),
+                5,
+            ),
+            0xf4 => (
+                i64::from_le_bytes(read_array(data)?)
+                    .to_string()
+                    .into_bytes(),
+                9,
+            ),
+            _ => return Err(eyre!("invalid listpack encoding {encoding:#x}")),
+        };
+        elements.push(element);
+
+        // Each entry ends with its length, for traversing the listpack
+        // backwards, which takes up to 5 bytes.
+        let backlen_size = match entry_len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2_097_150 => 3,
+            2_097_151..=268_435_454 => 4,
+            _ => 5,
+        };
+        read_bytes(data, backlen_size)?;
+    }
+    Ok(elements)
+}
+
+/// Decodes an intset, the encoding Redis uses for small sets of integers.
+fn parse_intset(mut data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let data = &mut data;
+    let width = u32::from_le_bytes(read_array(data)?);
+    let len = u32::from_le_bytes(read_array(data)?);
+    (0..len)
+        .map(|_| {
+            let i = match width {
+                2 => i64::from(i16::from_le_bytes(read_array(data)?)),
+                4 => i64::from(i32::from_le_bytes(read_array(data)?)),
+                8 => i64::from_le_bytes(read_array(data)?),
+                _ => return Err(eyre!("invalid intset encoding {width}")),
+            };
+            Ok(i.to_string().into_bytes())
+        })
+        .collect()
+}
+
+/// Decodes a zipmap, the encoding Redis used for small hashes before Redis
+/// 2.6. Returns alternating fields and values.
+fn parse_zipmap(mut data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let data = &mut data;
+    // Number of entries, if there are fewer than 254.
+    read_u8(data)?;
+
+    let read_len = |data: &mut &[u8]| -> Result<Option<u64>> {
+        match read_u8(data)? {
+            0xff => Ok(None),
+            0xfe => Ok(Some(u64::from(u32::from_le_bytes(read_array(data)?)))),
+            len => Ok(Some(u64::from(len))),
+        }
+    };
+    let mut elements = Vec::new();
+    while let Some(len) = read_len(data)? {
+        elements.push(read_bytes(data, len)?);
+        let len = read_len(data)?.ok_or_else(|| eyre!("zipmap field has no value"))?;
+        // Values can be followed by unused bytes.
+        let free = read_u8(data)?;
+        elements.push(read_bytes(data, len)?);
+        read_bytes(data, u64::from(free))?;
+    }
+    Ok(elements)
+}
+
+/// Skips a stream, which we can't load. The layout depends on the stream
+/// type, since later versions store more metadata.
+fn skip_stream<R: Read>(reader: &mut R, value_type: u8) -> Result<()> {
+    let nodes = read_length(reader)?;
+    for _ in 0..nodes {
+        // Each node is a master entry ID and a listpack of entries.
+        read_string(reader)?;
+        read_string(reader)?;
+    }
+    // Length and last ID, and in later versions the first ID, the max deleted
+    // ID and the number of entries ever added.
+    let metadata = if value_type == TYPE_STREAM_LISTPACKS {
+        3
+    } else {
+        8
+    };
+    for _ in 0..metadata {
+        read_length(reader)?;
+    }
+
+    let groups = read_length(reader)?;
+    for _ in 0..groups {
+        read_string(reader)?;
+        read_length(reader)?;
+        read_length(reader)?;
+        if value_type != TYPE_STREAM_LISTPACKS {
+            // Entries read.
+            read_length(reader)?;
+        }
+        let pending = read_length(reader)?;
+        for _ in 0..pending {
+            // ID and delivery time, and the delivery count.
+            read_array::<_, 24>(reader)?;
+            read_length(reader)?;
+        }
+        let consumers = read_length(reader)?;
+        for _ in 0..consumers {
+            read_string(reader)?;
+            read_array::<_, 8>(reader)?;
+            if value_type == TYPE_STREAM_LISTPACKS_3 {
+                // Active time.
+                read_array::<_, 8>(reader)?;
+            }
+            let pending = read_length(reader)?;
+            for _ in 0..pending {
+                read_array::<_, 16>(reader)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Skips the opcode-tagged data of a module value or module aux field.
+fn skip_module_data<R: Read>(reader: &mut R) -> Result<()> {
+    loop {
+        match read_length(reader)? {
+            MODULE_OPCODE_EOF => return Ok(()),
+            MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                read_length(reader)?;
+            }
+            MODULE_OPCODE_FLOAT => {
+                read_array::<_, 4>(reader)?;
+            }
+            MODULE_OPCODE_DOUBLE => {
+                read_array::<_, 8>(reader)?;
+            }
+            MODULE_OPCODE_STRING => {
+                read_string(reader)?;
+            }
+            opcode => return Err(eyre!("invalid module opcode {opcode}")),
+        }
+    }
+}
+
+/// A decoded length prefix, which is either a plain length or a special string
+/// encoding.
+#[derive(Debug, PartialEq, Eq)]
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+#[allow(clippy::cast_possible_truncation)]
+pub fn write_length<W: Write>(writer: &mut W, len: u64) -> Result<()> {
+    if len < 1 << 6 {
+        writer.write_all(&[len as u8])?;
+    } else if len < 1 << 14 {
+        writer.write_all(&[0x40 | (len >> 8) as u8, len as u8])?;
+    } else if let Ok(len) = u32::try_from(len) {
+        writer.write_all(&[0x80])?;
+        writer.write_all(&len.to_be_bytes())?;
+    } else {
+        writer.write_all(&[0x81])?;
+        writer.write_all(&len.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_length_or_encoding<R: Read>(reader: &mut R) -> Result<Length> {
+    let first = read_u8(reader).wrap_err("failed to read length")?;
+    let len = match first >> 6 {
+        0 => u64::from(first & 0x3f),
+        1 => {
+            let second = read_u8(reader)?;
+            u64::from(first & 0x3f) << 8 | u64::from(second)
+        }
+        2 => match first {
+            0x80 => u64::from(u32::from_be_bytes(read_array(reader)?)),
+            0x81 => u64::from_be_bytes(read_array(reader)?),
+            _ => return Err(eyre!("invalid length byte {first:#x}")),
+        },
+        _ => return Ok(Length::Encoded(first & 0x3f)),
+    };
+    Ok(Length::Len(len))
+}
+
+pub fn read_length<R: Read>(reader: &mut R) -> Result<u64> {
+    match read_length_or_encoding(reader)? {
+        Length::Len(len) => Ok(len),
+        Length::Encoded(enc) => Err(eyre!("expected length, got string encoding {enc}")),
+    }
+}
+
+/// Writes a string, using the compact integer encoding when possible and
+/// compressing long strings with LZF.
+pub fn write_string<W: Write>(writer: &mut W, s: &[u8]) -> Result<()> {
+    if let Some(i) = RedisString::from(s).to_i64() {
+        if let Ok(i) = i8::try_from(i) {
+            writer.write_all(&[0xc0 | ENC_INT8])?;
+            writer.write_all(&i.to_le_bytes())?;
+            return Ok(());
+        } else if let Ok(i) = i16::try_from(i) {
+            writer.write_all(&[0xc0 | ENC_INT16])?;
+            writer.write_all(&i.to_le_bytes())?;
+            return Ok(());
+        } else if let Ok(i) = i32::try_from(i) {
+            writer.write_all(&[0xc0 | ENC_INT32])?;
+            writer.write_all(&i.to_le_bytes())?;
+            return Ok(());
+        }
+    }
+
+    // Like Redis, only compress long strings, and only when it saves space.
+    if s.len() > LZF_MIN_LEN {
+        let compressed = lzf_compress(s);
+        if compressed.len() + 4 <= s.len() {
+            writer.write_all(&[0xc0 | ENC_LZF])?;
+            write_length(writer, compressed.len() as u64)?;
+            write_length(writer, s.len() as u64)?;
+            writer.write_all(&compressed)?;
+            return Ok(());
+        }
+    }
+
+    write_length(writer, s.len() as u64)?;
+    writer.write_all(s)?;
+    Ok(())
+}
+
+pub fn read_string<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    match read_length_or_encoding(reader)? {
+        Length::Len(len) => read_bytes(reader, len),
+        Length::Encoded(ENC_INT8) => {
+            let i = i8::from_le_bytes(read_array(reader)?);
+            Ok(i.to_string().into_bytes())
+        }
+        Length::Encoded(ENC_INT16) => {
+            let i = i16::from_le_bytes(read_array(reader)?);
+            Ok(i.to_string().into_bytes())
+        }
+        Length::Encoded(ENC_INT32) => {
+            let i = i32::from_le_bytes(read_array(reader)?);
+            Ok(i.to_string().into_bytes())
+        }
+        Length::Encoded(ENC_LZF) => {
+            let compressed_len = read_length(reader)?;
+            let len = read_length(reader)?;
+            let compressed = read_bytes(reader, compressed_len)?;
+            let len = usize::try_from(len).wrap_err("string length overflows usize")?;
+            lzf_decompress(&compressed, len)
+        }
+        Length::Encoded(enc) => Err(eyre!("unsupported string encoding {enc}")),
+    }
+}
+
+/// Compresses data with LZF, in the format read by [`lzf_decompress`]. Matches
+/// are found with a hash table of the last position each 3-byte sequence was
+/// seen at.
+#[allow(clippy::cast_possible_truncation)]
+fn lzf_compress(input: &[u8]) -> Vec<u8> {
+    const MAX_OFFSET: usize = 1 << 13;
+    const MAX_MATCH: usize = (1 << 8) + (1 << 3);
+
+    // Small inputs don't need a big table, which has to be initialized each time.
+    let table_bits = input
+        .len()
+        .next_power_of_two()
+        .clamp(1 << 4, 1 << 14)
+        .trailing_zeros();
+    let hash = |i: usize| {
+        let v = u32::from_be_bytes([0, input[i], input[i + 1], input[i + 2]]);
+        (v.wrapping_mul(0x9e37_79b1) >> (32 - table_bits)) as usize
+    };
+    let mut table = vec![usize::MAX; 1 << table_bits];
+
+    let mut output = Vec::with_capacity(input.len());
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i + 2 < input.len() {
+        let candidate = std::mem::replace(&mut table[hash(i)], i);
+        let is_match = candidate < i
+            && i - candidate <= MAX_OFFSET
+            && input[candidate..candidate + 3] == input[i..i + 3];
+        if !is_match {
+            i += 1;
+            continue;
+        }
+
+        let max_len = MAX_MATCH.min(input.len() - i);
+        let mut len = 3;
+        while len < max_len && input[candidate + len] == input[i + len] {
+            len += 1;
+        }
+        push_lzf_literals(&mut output, &input[literal_start..i]);
+        let offset = i - candidate - 1;
+        let run = len - 2;
+        if run < 7 {
+            output.push((run << 5 | offset >> 8) as u8);
+        } else {
+            output.push((7 << 5 | offset >> 8) as u8);
+            output.push((run - 7) as u8);
+        }
+        output.push(offset as u8);
+
+        for j in i + 1..(i + len).min(input.len() - 2) {
+            table[hash(j)] = j;
+        }
+        i += len;
+        literal_start = i;
+    }
+    push_lzf_literals(&mut output, &input[literal_start..]);
+    output
+}
+
+/// Writes literal bytes as LZF literal runs of at most 32 bytes each.
+#[allow(clippy::cast_possible_truncation)]
+fn push_lzf_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(1 << 5) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    }
+}
+
+/// Decompresses LZF-compressed data, which Redis uses for long strings.
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    let truncated = || eyre!("truncated LZF data");
+    let byte = |i: usize| input.get(i).map(|&b| usize::from(b)).ok_or_else(truncated);
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < input.len() && output.len() <= len {
+        let ctrl = byte(i)?;
+        i += 1;
+        if ctrl < 1 << 5 {
+            // A run of literal bytes.
+            let literal = input.get(i..=i + ctrl).ok_or_else(truncated)?;
+            output.extend_from_slice(literal);
+            i += ctrl + 1;
+        } else {
+            // A back reference to earlier output, which may overlap the bytes
+            // being copied.
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += byte(i)?;
+                i += 1;
+            }
+            let offset = (ctrl & 0x1f) << 8 | byte(i)?;
+            i += 1;
+            let start = output
+                .len()
+                .checked_sub(offset + 1)
+                .ok_or_else(|| eyre!("invalid LZF back reference"))?;
+            for j in start..start + run + 2 {
+                output.push(output[j]);
+            }
+        }
+    }
+    if output.len() != len {
+        return Err(eyre!(
+            "LZF data decompressed to {} bytes instead of {len}",
+            output.len()
+        ));
+    }
+    Ok(output)
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(eyre!("unexpected end of data in string"));
+    }
+    Ok(buf)
+}
+
+/// Reads a little-endian 24-bit signed integer.
+fn read_i24<R: Read>(reader: &mut R) -> Result<i32> {
+    let [a, b, c] = read_array(reader)?;
+    Ok(i32::from_le_bytes([0, a, b, c]) >> 8)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let [byte] = read_array(reader)?;
+    Ok(byte)
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader
+        .read_exact(&mut buf)
+        .wrap_err("unexpected end of data")?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_round_trip() {
+        for len in [0, 1, 63, 64, 16383, 16384, u64::from(u32::MAX), u64::MAX] {
+            let mut buf = Vec::new();
+            write_length(&mut buf, len).unwrap();
+            assert_eq!(read_length(&mut buf.as_slice()).unwrap(), len);
+        }
+    }
+
+    #[test]
+    fn string_round_trip() {
+        let cases: [&[u8]; 7] = [
+            b"",
+            b"hello",
+            b"12",
+            b"-300",
+            b"70000",
+            b"0123",
+            b"\xff\x00",
+        ];
+        for s in cases {
+            let mut buf = Vec::new();
+            write_string(&mut buf, s).unwrap();
+            assert_eq!(read_string(&mut buf.as_slice()).unwrap(), s);
+        }
+    }
+
+    #[test]
+    fn restore_redis_payload() {
+        // Example from the Redis documentation for DUMP, which stores the
+        // integer 10 and uses RDB version 9.
+        let payload = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+        assert_eq!(
+            restore(payload).unwrap(),
+            Value::String(StringValue::from("10"))
+        );
+    }
+
+    #[test]
+    fn dump_round_trip() {
+        let list = ["a", "1", ""].into_iter().map(RedisString::from).collect();
+        let values = [
+            Value::String(StringValue::from("10")),
+            Value::String(StringValue::from("hello")),
+            Value::String(StringValue::from("")),
+            Value::List(list),
+            Value::Hash(
+                [("field", "value"), ("n", "12")]
+                    .into_iter()
+                    .map(|(f, v)| (RedisString::from(f), RedisString::from(v)))
+                    .collect(),
+            ),
+            Value::Set(["a", "1"].into_iter().map(RedisString::from).collect()),
+            Value::ZSet(
+                [("a", 1.5), ("b", f64::NEG_INFINITY), ("c", -0.25)]
+                    .into_iter()
+                    .map(|(m, s)| (RedisString::from(m), Score::new(s).unwrap()))
+                    .collect(),
+            ),
+        ];
+        for value in values {
+            let dumped = dump(&value).unwrap();
+            assert_eq!(restore(&dumped).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn snapshot_layout() {
+        let mut db = Db::default();
+        let key = RedisString::from("k");
+        db.insert(
+            key.clone(),
+            Entry::new(Value::String(StringValue::from("v"))),
+        );
+        db.set_expires_at(&key, Some(i64::MAX));
+        let mut snapshot = Vec::new();
+        write_snapshot(&mut snapshot, &[Db::default(), db]).unwrap();
+
+        let (data, crc) = snapshot.split_at(snapshot.len() - 8);
+        assert_eq!(crc, crc64(0, data).to_le_bytes());
+        let mut expected = b"REDIS0011\xfe\x01\xfb\x01\x01\xfc".to_vec();
+        expected.extend_from_slice(&i64::MAX.to_le_bytes());
+        expected.extend_from_slice(b"\x00\x01k\x01v\xff");
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let insert = |db: &mut Db, key: &str, value: &str, expires_at| {
+            let key = RedisString::from(key);
+            db.insert(
+                key.clone(),
+                Entry::new(Value::String(StringValue::from(value))),
+            );
+            db.set_expires_at(&key, expires_at);
+        };
+        let mut dbs = vec![Db::default(), Db::default(), Db::default()];
+        insert(&mut dbs[0], "a", "1", None);
+        insert(&mut dbs[2], "b", "hello", Some(i64::MAX));
+        // Expired keys are left out.
+        insert(&mut dbs[2], "c", "gone", Some(1));
+
+        let mut snapshot = Vec::new();
+        write_snapshot(&mut snapshot, &dbs).unwrap();
+        let loaded = read_snapshot(snapshot.as_slice(), 3).unwrap();
+        assert_eq!(loaded[0].entries().len(), 1);
+        assert!(loaded[1].entries().is_empty());
+        let b = &loaded[2].entries()[&RedisString::from("b")];
+        assert_eq!(b.value, Value::String(StringValue::from("hello")));
+        assert_eq!(
+            loaded[2].expires_at(&RedisString::from("b")),
+            Some(i64::MAX)
+        );
+        assert!(!loaded[2].entries().contains_key(&RedisString::from("c")));
+
+        // Not enough databases to load into.
+        let err = read_snapshot(snapshot.as_slice(), 2).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "database index 2 is out of range"
+        );
+    }
+
+    #[test]
+    fn read_snapshot_rejects_corruption() {
+        let mut db = Db::default();
+        db.insert(
+            RedisString::from("key"),
+            Entry::new(Value::String(StringValue::from("value"))),
+        );
+        let mut snapshot = Vec::new();
+        write_snapshot(&mut snapshot, &[db]).unwrap();
+
+        let mut corrupted = snapshot.clone();
+        *corrupted.iter_mut().rev().nth(9).unwrap() ^= 0xff;
+        let err = read_snapshot(corrupted.as_slice(), 1).unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "checksum mismatch");
+        // The checksum is checked once the whole snapshot has been read.
+        assert_eq!(
+            err.to_string(),
+            format!("invalid RDB data at offset {}", corrupted.len())
+        );
+
+        assert!(read_snapshot(&snapshot[..snapshot.len() - 12], 1).is_err());
+        assert!(read_snapshot(&b"REDIS9999\xff"[..], 1).is_err());
+        assert!(read_snapshot(&b"NOTREDIS!"[..], 1).is_err());
+    }
+
+    fn read_encoded(value_type: u8, data: &[u8]) -> Value {
+        let mut buf = Vec::new();
+        write_string(&mut buf, data).unwrap();
+        read_value(&mut buf.as_slice(), value_type).unwrap()
+    }
+
+    fn strings(strings: &[&str]) -> Vec<RedisString> {
+        strings.iter().map(|s| RedisString::from(*s)).collect()
+    }
+
+    #[test]
+    fn read_compact_encodings() {
+        // "a", 5 and 300, with a 10 byte header that we ignore.
+        let ziplist = b"\0\0\0\0\0\0\0\0\0\0\x00\x01a\x03\xf6\x02\xc0\x2c\x01\xff";
+        assert_eq!(
+            read_encoded(TYPE_LIST_ZIPLIST, ziplist),
+            Value::List(strings(&["a", "5", "300"]).into())
+        );
+
+        // "hello", 7, -1 and 1000, with a 6 byte header that we ignore.
+        let listpack = b"\0\0\0\0\0\0\x85hello\x06\x07\x01\xdf\xff\x02\xf1\xe8\x03\x03\xff";
+        assert_eq!(
+            read_encoded(TYPE_SET_LISTPACK, listpack),
+            Value::Set(strings(&["hello", "7", "-1", "1000"]).into_iter().collect())
+        );
+
+        let listpack = b"\0\0\0\0\0\0\x81m\x02\x831.5\x04\x81n\x02\x02\x01\xff";
+        let zset = [("m", 1.5), ("n", 2.0)]
+            .into_iter()
+            .map(|(m, s)| (RedisString::from(m), Score::new(s).unwrap()))
+            .collect();
+        assert_eq!(
+            read_encoded(TYPE_ZSET_LISTPACK, listpack),
+            Value::ZSet(zset)
+        );
+
+        let intset = b"\x02\0\0\0\x02\0\0\0\x01\x00\xfe\xff";
+        assert_eq!(
+            read_encoded(TYPE_SET_INTSET, intset),
+            Value::Set(strings(&["1", "-2"]).into_iter().collect())
+        );
+
+        let zipmap = b"\x01\x01f\x01\x00v\xff";
+        assert_eq!(
+            read_encoded(TYPE_HASH_ZIPMAP, zipmap),
+            Value::Hash(
+                std::iter::once((RedisString::from("f"), RedisString::from("v"))).collect()
+            )
+        );
+    }
+
+    #[test]
+    fn read_quicklist_and_lzf() {
+        // An LZF-compressed "aaaaaaaaaa": one literal byte and a back
+        // reference repeating it.
+        let lzf = [0xc0 | ENC_LZF, 0x05, 0x0a, 0x00, b'a', 0xe0, 0x00, 0x00];
+        assert_eq!(read_string(&mut lzf.as_slice()).unwrap(), b"aaaaaaaaaa");
+
+        // A plain node holding "big" and a packed node holding "x".
+        let mut buf = Vec::new();
+        write_length(&mut buf, 2).unwrap();
+        write_length(&mut buf, QUICKLIST_NODE_PLAIN).unwrap();
+        write_string(&mut buf, b"big").unwrap();
+        write_length(&mut buf, QUICKLIST_NODE_PACKED).unwrap();
+        write_string(&mut buf, b"\0\0\0\0\0\0\x81x\x02\xff").unwrap();
+        assert_eq!(
+            read_value(&mut buf.as_slice(), TYPE_LIST_QUICKLIST_2).unwrap(),
+            Value::List(strings(&["big", "x"]).into())
+        );
+
+        assert!(lzf_decompress(&[0x00, b'a', 0xe0, 0x00, 0x05], 10).is_err());
+        assert!(lzf_decompress(&[0x05, b'a'], 6).is_err());
+    }
+
+    #[test]
+    fn lzf_round_trip() {
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(500);
+        let long_run = vec![b'x'; 1000];
+        // Repeats at the largest distance a back reference can reach, and
+        // just beyond it.
+        let noise = |i: u32| i.wrapping_mul(0x9e37_79b1).to_be_bytes()[0];
+        let far: Vec<u8> = (0..20_000).map(|i| noise(i % 8192)).collect();
+        let too_far: Vec<u8> = (0..20_000).map(|i| noise(i % 8193)).collect();
+        let cases: [&[u8]; 7] = [
+            b"",
+            b"a",
+            b"abcabcabc",
+            text.as_bytes(),
+            &long_run,
+            &far,
+            &too_far,
+        ];
+        for input in cases {
+            let compressed = lzf_compress(input);
+            assert_eq!(lzf_decompress(&compressed, input.len()).unwrap(), input);
+        }
+
+        // Long, repetitive strings are compressed when written.
+        let mut buf = Vec::new();
+        write_string(&mut buf, text.as_bytes()).unwrap();
+        assert_eq!(buf[0], 0xc0 | ENC_LZF);
+        assert!(buf.len() < text.len() / 10);
+        assert_eq!(read_string(&mut buf.as_slice()).unwrap(), text.as_bytes());
+
+        // Incompressible ones are written as is.
+        let random: Vec<u8> = (0..64).map(noise).collect();
+        buf.clear();
+        write_string(&mut buf, &random).unwrap();
+        assert_eq!(buf[0], 64);
+    }
+
+    #[test]
+    fn read_redis_snapshot() {
+        let mut snapshot = b"REDIS0012".to_vec();
+        snapshot.push(OPCODE_AUX);
+        write_string(&mut snapshot, b"redis-ver").unwrap();
+        write_string(&mut snapshot, b"7.4.0").unwrap();
+        snapshot.extend_from_slice(&[OPCODE_SELECTDB, 0x00, OPCODE_RESIZEDB, 0x03, 0x01]);
+
+        // A stream with no entries or groups, which is skipped.
+        snapshot.push(TYPE_STREAM_LISTPACKS_3);
+        write_string(&mut snapshot, b"stream").unwrap();
+        snapshot.extend_from_slice(&[0; 10]);
+
+        // An old-style sorted set, with string scores.
+        snapshot.extend_from_slice(&[OPCODE_FREQ, 0x05, TYPE_ZSET]);
+        write_string(&mut snapshot, b"zset").unwrap();
+        snapshot.push(0x02);
+        write_string(&mut snapshot, b"a").unwrap();
+        snapshot.extend_from_slice(b"\x032.5");
+        write_string(&mut snapshot, b"b").unwrap();
+        snapshot.push(254);
+
+        // A key with an expiration time in seconds.
+        snapshot.push(OPCODE_EXPIRETIME);
+        snapshot.extend_from_slice(&i32::MAX.to_le_bytes());
+        snapshot.push(TYPE_STRING);
+        write_string(&mut snapshot, b"key").unwrap();
+        write_string(&mut snapshot, b"value").unwrap();
+
+        // A checksum of 0 means checksums were disabled.
+        snapshot.push(OPCODE_EOF);
+        snapshot.extend_from_slice(&[0; 8]);
+
+        let dbs = read_snapshot(snapshot.as_slice(), 1).unwrap();
+        let expires_at = dbs[0].expires_at(&RedisString::from("key"));
+        let db = dbs[0].entries();
+        assert_eq!(db.len(), 2);
+        assert!(!db.contains_key(&RedisString::from("stream")));
+        let zset = [("a", 2.5), ("b", f64::INFINITY)]
+            .into_iter()
+            .map(|(m, s)| (RedisString::from(m), Score::new(s).unwrap()))
+            .collect();
+        assert_eq!(db[&RedisString::from("zset")].value, Value::ZSet(zset));
+        assert_eq!(expires_at, Some(i64::from(i32::MAX) * 1000));
+    }
+
+    #[test]
+    fn restore_rejects_corruption() {
+        let mut dumped = dump(&Value::String(StringValue::from("hello"))).unwrap();
+        dumped[2] ^= 0xff;
+        assert!(restore(&dumped).is_err());
+        assert!(restore(b"short").is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! The replica side of replication. See
+//! <https://redis.io/docs/management/replication/>.
+//!
+//! `REPLICAOF` starts a thread that connects to the master, does the
+//! handshake, loads the snapshot the master sends, and then forwards the
+//! replication stream to the core. The thread reconnects whenever the link
+//! drops, asking the master to continue the stream from where it left off and
+//! falling back to a full sync if the master can't. The master can be
+//! `redis-server` or another redis-clone server.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::command::{Command, CommandResponse, PSync, ReplConf, Select};
+use crate::db::Db;
+use crate::rdb;
+use crate::resp::Message;
+use crate::string::RedisString;
+
+/// How long to wait before reconnecting to the master after the link drops.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How often to tell the master how much of the stream has been processed.
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Something the master sent, for the core to apply.
+#[derive(Debug)]
+pub enum MasterEvent {
+    /// A snapshot of the master's dataset, which replaces ours. `id` and
+    /// `offset` are where the snapshot leaves off in the master's
+    /// replication stream, which we continue from now on.
+    Sync {
+        id: String,
+        offset: u64,
+        dbs: Vec<Db>,
+    },
+
+    /// A write command from the replication stream, to run in database `db`.
+    Command(usize, Command),
+}
+
+/// `MasterLink` is the connection to the master of a replica. Dropping it
+/// closes the connection and stops the thread replicating from the master.
+#[derive(Debug)]
+pub struct MasterLink {
+    host: String,
+    port: u16,
+    events: Receiver<MasterEvent>,
+    shared: Arc<Shared>,
+}
+
+/// State shared with the replication thread.
+#[derive(Debug, Default)]
+struct Shared {
+    stopped: AtomicBool,
+
+    /// The connection to the master, if connected, so that stopping the link
+    /// can shut it down.
+    stream: Mutex<Option<TcpStream>>,
+
+    /// The master's replication offset as of the last command received.
+    offset: AtomicU64,
+
+    /// Whether we're connected and synced with the master.
+    up: AtomicBool,
+}
+
+impl MasterLink {
+    /// Starts replicating from the master at `host:port`. `listening_port` is
+    /// the port this server accepts connections on, which is reported to the
+    /// master.
+    pub fn start(host: String, port: u16, listening_port: u16, num_databases: usize) -> Self {
+        let (sender, events) = crossbeam_channel::unbounded();
+        let shared = Arc::new(Shared::default());
+        let link = Replicator {
+            host: host.clone(),
+            port,
+            listening_port,
+            num_databases,
+            shared: Arc::clone(&shared),
+            events: sender,
+            master_id: None,
+            db: 0,
+        };
+        thread::spawn(move || link.run());
+        Self {
+            host,
+            port,
+            events,
+            shared,
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub const fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Events from the master, in the order they must be applied.
+    pub const fn events(&self) -> &Receiver<MasterEvent> {
+        &self.events
+    }
+
+    /// The master's replication offset as of the last command received.
+    pub fn offset(&self) -> u64 {
+        self.shared.offset.load(Ordering::Relaxed)
+    }
+
+    /// Whether the link is connected and synced with the master.
+    pub fn is_up(&self) -> bool {
+        self.shared.up.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MasterLink {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        self.shared.disconnect();
+    }
+}
+
+impl Shared {
+    fn disconnect(&self) {
+        self.up.store(false, Ordering::Relaxed);
+        let stream = self.stream.lock().expect("couldn't lock stream").take();
+        if let Some(stream) = stream {
+            // Unblocks the replication thread's reads.
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// The replication thread's side of a `MasterLink`.
+struct Replicator {
+    host: String,
+    port: u16,
+    listening_port: u16,
+    num_databases: usize,
+    shared: Arc<Shared>,
+    events: Sender<MasterEvent>,
+
+    /// The master's replication id, once we've synced with it.
+    master_id: Option<String>,
+
+    /// The database the replication stream last selected.
+    db: usize,
+}
+
+/// How the master agreed to sync in reply to `PSYNC`.
+#[derive(Debug, PartialEq, Eq)]
+enum PSyncReply {
+    /// The master sends a snapshot as of `offset`, followed by the stream.
+    Full { id: String, offset: u64 },
+
+    /// The master continues the stream from the offset we asked for.
+    Continue { id: Option<String> },
+}
+
+impl Replicator {
+    fn run(mut self) {
+        while !self.shared.stopped.load(Ordering::Relaxed) {
+            match self.replicate() {
+                Ok(()) => log::info!("master {}:{} closed the connection", self.host, self.port),
+                Err(e) if !self.shared.stopped.load(Ordering::Relaxed) => {
+                    log::warn!("replicating from {}:{} failed: {e:?}", self.host, self.port);
+                }
+                Err(_) => {}
+            }
+            self.shared.disconnect();
+            thread::sleep(RETRY_DELAY);
+        }
+        log::info!("stopped replicating from {}:{}", self.host, self.port);
+    }
+
+    /// Connects to the master, syncs with it, and then applies the
+    /// replication stream until the connection closes.
+    fn replicate(&mut self) -> Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .wrap_err_with(|| format!("failed to connect to {}:{}", self.host, self.port))?;
+        *self.shared.stream.lock().expect("couldn't lock stream") = Some(stream.try_clone()?);
+        // The link may have been dropped while we were connecting.
+        if self.shared.stopped.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        log::info!("connected to master {}:{}", self.host, self.port);
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let writer = Arc::new(Mutex::new(stream));
+        let reply = self.handshake(&mut writer.lock().expect("lock writer"), &mut reader)?;
+        match reply {
+            PSyncReply::Full { id, offset } => {
+                // Forget the old stream in case loading the snapshot fails.
+                self.master_id = None;
+                let snapshot = read_snapshot_payload(&mut reader)?;
+                let dbs = rdb::read_snapshot(&snapshot[..], self.num_databases)
+                    .wrap_err("failed to load snapshot from master")?;
+                self.shared.offset.store(offset, Ordering::Relaxed);
+                self.send(MasterEvent::Sync {
+                    id: id.clone(),
+                    offset,
+                    dbs,
+                })?;
+                self.master_id = Some(id);
+                self.db = 0;
+                log::info!("synced with master {}:{}", self.host, self.port);
+            }
+            PSyncReply::Continue { id } => {
+                if id.is_some() {
+                    self.master_id = id;
+                }
+                log::info!("continuing stream from master {}:{}", self.host, self.port);
+            }
+        }
+
+        self.shared.up.store(true, Ordering::Relaxed);
+
+        let ack_writer = Arc::clone(&writer);
+        let shared = Arc::clone(&self.shared);
+        thread::spawn(move || send_acks(&ack_writer, &shared));
+
+        while let Some(message) = Message::parse_resp(&mut reader)? {
+            let len = message.to_bytes().len() as u64;
+            match Command::parse_resp(&message) {
+                Ok(Command::Select(Select { index })) => {
+                    self.db = usize::try_from(index)
+                        .ok()
+                        .filter(|db| *db < self.num_databases)
+                        .ok_or_else(|| eyre!("master selected invalid database {index}"))?;
+                }
+                Ok(Command::Ping) => {}
+                Ok(Command::ReplConf(ReplConf::GetAck)) => {
+                    let offset = self.shared.offset.load(Ordering::Relaxed);
+                    send_ack(&mut *writer.lock().expect("lock writer"), offset)?;
+                }
+                Ok(command) => self.send(MasterEvent::Command(self.db, command))?,
+                Err(e) => log::warn!("skipping command from master: {e}"),
+            }
+            self.shared.offset.fetch_add(len, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Introduces ourselves to the master and asks it to continue the stream
+    /// we were replicating, if any, or else for a full sync.
+    fn handshake<R: BufRead>(&self, writer: &mut TcpStream, reader: &mut R) -> Result<PSyncReply> {
+        request(writer, reader, &Command::Ping)?;
+        request(
+            writer,
+            reader,
+            &Command::ReplConf(ReplConf::Options(vec![(
+                "listening-port".to_string(),
+                RedisString::from(self.listening_port.to_string()),
+            )])),
+        )?;
+        request(
+            writer,
+            reader,
+            &Command::ReplConf(ReplConf::Options(vec![(
+                "capa".to_string(),
+                RedisString::from("psync2"),
+            )])),
+        )?;
+        let psync = match &self.master_id {
+            // The master expects the offset of the next byte we need.
+            Some(id) => PSync {
+                repl_id: RedisString::from(id.as_str()),
+                offset: i64::try_from(self.shared.offset.load(Ordering::Relaxed) + 1)?,
+            },
+            None => PSync {
+                repl_id: RedisString::from("?"),
+                offset: -1,
+            },
+        };
+        let reply = request(writer, reader, &Command::PSync(psync))?;
+        let CommandResponse::Status(status) = &reply else {
+            return Err(eyre!("unexpected reply to PSYNC: {reply:?}"));
+        };
+        parse_psync_reply(status)
+    }
+
+    fn send(&self, event: MasterEvent) -> Result<()> {
+        self.events
+            .send(event)
+            .map_err(|_| eyre!("replication link was dropped"))
+    }
+}
+
+/// Sends a command to the master and reads its reply, which must not be an
+/// error.
+fn request<R: BufRead>(
+    writer: &mut TcpStream,
+    reader: &mut R,
+    command: &Command,
+) -> Result<CommandResponse> {
+    command.to_resp().serialize_resp(writer)?;
+    let reply = Message::parse_resp(reader)?
+        .ok_or_else(|| eyre!("master closed the connection during the handshake"))?;
+    match CommandResponse::parse_resp(reply)? {
+        CommandResponse::Error(e) => Err(eyre!("master replied to {command:?} with: {e}")),
+        reply => Ok(reply),
+    }
+}
+
+fn parse_psync_reply(status: &str) -> Result<PSyncReply> {
+    match status.split(' ').collect::<Vec<_>>()[..] {
+        ["FULLRESYNC", id, offset] => Ok(PSyncReply::Full {
+            id: id.to_string(),
+            offset: offset
+                .parse()
+                .wrap_err_with(|| format!("invalid offset in {status:?}"))?,
+        }),
+        ["CONTINUE"] => Ok(PSyncReply::Continue { id: None }),
+        ["CONTINUE", id] => Ok(PSyncReply::Continue {
+            id: Some(id.to_string()),
+        }),
+        _ => Err(eyre!("unexpected reply to PSYNC: {status:?}")),
+    }
+}
+
+/// Reads the snapshot that follows `+FULLRESYNC`, which is sent like a bulk
+/// string without the trailing CRLF.
+fn read_snapshot_payload<R: BufRead>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut line = String::new();
+    // The master sends newlines to keep the connection alive while it creates
+    // the snapshot.
+    while line.is_empty() || line == "\n" {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(eyre!("master closed the connection before the snapshot"));
+        }
+    }
+    let len: usize = line
+        .strip_prefix('$')
+        .and_then(|len| len.strip_suffix("\r\n"))
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| eyre!("invalid snapshot header: {line:?}"))?;
+    let mut snapshot = vec![0; len];
+    reader
+        .read_exact(&mut snapshot)
+        .wrap_err("snapshot ended early")?;
+    Ok(snapshot)
+}
+
+fn send_ack<W: Write>(writer: &mut W, offset: u64) -> Result<()> {
+    Command::ReplConf(ReplConf::Ack(offset))
+        .to_resp()
+        .serialize_resp(writer)
+}
+
+/// Acknowledges the stream periodically, until the connection is closed.
+fn send_acks(writer: &Mutex<TcpStream>, shared: &Shared) {
+    loop {
+        thread::sleep(ACK_PERIOD);
+        let offset = shared.offset.load(Ordering::Relaxed);
+        if send_ack(&mut *writer.lock().expect("lock writer"), offset).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_payload() {
+        let mut reader = &b"\n\n$5\r\nREDIS*1\r\n"[..];
+        assert_eq!(read_snapshot_payload(&mut reader).unwrap(), b"REDIS");
+        assert_eq!(reader, b"*1\r\n");
+
+        assert!(read_snapshot_payload(&mut &b"$5\r\nRED"[..]).is_err());
+        assert!(read_snapshot_payload(&mut &b"EOF:abc\r\n"[..]).is_err());
+        assert!(read_snapshot_payload(&mut &b""[..]).is_err());
+    }
+
+    #[test]
+    fn psync_reply() {
+        assert_eq!(
+            parse_psync_reply("FULLRESYNC abc 42").unwrap(),
+            PSyncReply::Full {
+                id: "abc".to_string(),
+                offset: 42
+            }
+        );
+        assert_eq!(
+            parse_psync_reply("CONTINUE").unwrap(),
+            PSyncReply::Continue { id: None }
+        );
+        assert_eq!(
+            parse_psync_reply("CONTINUE def").unwrap(),
+            PSyncReply::Continue {
+                id: Some("def".to_string())
+            }
+        );
+        assert!(parse_psync_reply("FULLRESYNC abc x").is_err());
+        assert!(parse_psync_reply("NOMASTERLINK").is_err());
+    }
+}
@@ -0,0 +1,402 @@
+//! The master side of replication. See
+//! <https://redis.io/docs/management/replication/>.
+//!
+//! A replica connects like any other client, sends its options with
+//! `REPLCONF`, and then asks for the dataset with `PSYNC`. The master replies
+//! with `+FULLRESYNC <replication id> <offset>` followed by an RDB snapshot,
+//! and from then on sends the replica every write command it runs. That
+//! sequence of commands is the replication stream, and the offset counts the
+//! bytes of it produced so far.
+//!
+//! The most recent part of the stream is kept in a backlog, so a replica that
+//! reconnects can send `PSYNC <replication id> <offset>` to continue where it
+//! left off instead of syncing the whole dataset again.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::Result;
+use crossbeam_channel::Receiver;
+
+use crate::command::{Command, ErrorReply, ReplConf, Select};
+use crate::db::Db;
+use crate::random::Rng;
+use crate::rdb;
+use crate::string::RedisString;
+
+/// How often the master pings its replicas, so they can tell the link is
+/// alive even when there are no writes, like Redis' `repl-ping-replica-period`.
+const PING_PERIOD: Duration = Duration::from_secs(10);
+
+/// How many bytes of the stream the backlog keeps, like Redis' default
+/// `repl-backlog-size`.
+pub const BACKLOG_SIZE: usize = 1024 * 1024;
+
+/// `Replication` keeps track of a master's replicas and the replication
+/// stream sent to them.
+#[derive(Debug)]
+pub struct Replication {
+    /// Identifies this master's replication stream, as 40 hex characters.
+    id: String,
+
+    /// The number of bytes of the replication stream produced so far.
+    offset: u64,
+
+    /// Options sent with `REPLCONF` by clients that haven't sent `PSYNC` yet.
+    handshakes: BTreeMap<usize, Replica>,
+
+    /// Replicas that have been sent a snapshot, keyed by client.
+    replicas: BTreeMap<usize, Replica>,
+
+    /// The database the stream last selected, so `SELECT` is only sent when
+    /// a command runs in a different one.
+    db: Option<usize>,
+
+    /// Bytes added to the stream that haven't been sent to replicas yet.
+    pending: Vec<u8>,
+
+    /// The last `BACKLOG_SIZE` bytes of the stream. It's created when the
+    /// first replica connects, and the stream is produced from then on even
+    /// while there are no replicas.
+    backlog: Option<VecDeque<u8>>,
+
+    last_ping: Instant,
+}
+
+/// A replica connected to this master.
+#[derive(Debug, Clone, Default)]
+pub struct Replica {
+    /// The replica's IP address, which it may announce with `REPLCONF
+    /// ip-address`. Otherwise it's the address it connected from.
+    pub ip: Option<String>,
+
+    /// The port the replica accepts connections on, as opposed to the port it
+    /// connected from.
+    pub listening_port: Option<u16>,
+
+    /// Capabilities the replica supports, like `psync2`.
+    pub capabilities: Vec<RedisString>,
+
+    /// The stream offset the replica last acknowledged with `REPLCONF ACK`.
+    pub ack_offset: u64,
+
+    /// When the replica last acknowledged the stream, or when it was added if
+    /// it hasn't yet.
+    pub ack_time: Option<Instant>,
+}
+
+impl Replication {
+    pub fn new(rng: &mut Rng) -> Self {
+        let mut id = String::new();
+        for _ in 0..3 {
+            write!(id, "{:016x}", rng.next_u64()).expect("writing to a String can't fail");
+        }
+        id.truncate(40);
+        Self {
+            id,
+            offset: 0,
+            handshakes: BTreeMap::new(),
+            replicas: BTreeMap::new(),
+            db: None,
+            pending: Vec::new(),
+            backlog: None,
+            last_ping: Instant::now(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Takes over a master's replication stream after syncing with it, so
+    /// this server's stream continues from `offset` in the one identified by
+    /// `id`.
+    pub fn follow(&mut self, id: String, offset: u64) {
+        self.id = id;
+        self.offset = offset;
+        self.db = None;
+        self.pending.clear();
+        if let Some(backlog) = &mut self.backlog {
+            backlog.clear();
+        }
+    }
+
+    pub fn has_replicas(&self) -> bool {
+        !self.replicas.is_empty()
+    }
+
+    /// The number of bytes of the stream in the backlog, if there is one.
+    pub fn backlog_len(&self) -> Option<usize> {
+        self.backlog.as_ref().map(VecDeque::len)
+    }
+
+    /// Whether writes are added to the stream, which they are from the time
+    /// the first replica connects.
+    pub const fn is_streaming(&self) -> bool {
+        self.backlog.is_some()
+    }
+
+    pub fn replicas(&self) -> impl Iterator<Item = (usize, &Replica)> {
+        self.replicas
+            .iter()
+            .map(|(client, replica)| (*client, replica))
+    }
+
+    /// Records the `REPLCONF` options a client sent before `PSYNC`.
+    pub fn configure(
+        &mut self,
+        client: usize,
+        options: &[(String, RedisString)],
+    ) -> Result<(), ErrorReply> {
+        let replica = self.handshakes.entry(client).or_default();
+        for (name, value) in options {
+            match name.as_str() {
+                "listening-port" => {
+                    let port = String::from_utf8_lossy(value.as_bytes())
+                        .parse()
+                        .map_err(|_| ErrorReply::err("value is not an integer or out of range"))?;
+                    replica.listening_port = Some(port);
+                }
+                "capa" => replica.capabilities.push(value.clone()),
+                "ip-address" => {
+                    replica.ip = Some(String::from_utf8_lossy(value.as_bytes()).into_owned());
+                }
+                _ => {
+                    return Err(ErrorReply::err(format!(
+                        "Unrecognized REPLCONF option: {name}"
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers a client that sent `PSYNC` as a replica, which is sent the
+    /// rest of the stream after its snapshot.
+    pub fn add_replica(&mut self, client: usize) {
+        let mut replica = self.handshakes.remove(&client).unwrap_or_default();
+        replica.ack_offset = self.offset;
+        replica.ack_time = Some(Instant::now());
+        self.replicas.insert(client, replica);
+        self.backlog.get_or_insert_with(VecDeque::new);
+        // The new replica hasn't selected a database yet.
+        self.db = None;
+    }
+
+    /// Registers a client that sent `PSYNC <id> <offset>` as a replica if it
+    /// can continue from the backlog, returning the part of the stream it
+    /// missed. Like in Redis, `offset` is one more than the offset the replica
+    /// has processed. Returns `None` if the replica needs a full sync instead.
+    pub fn continue_replica(&mut self, client: usize, id: &[u8], offset: i64) -> Option<Vec<u8>> {
+        let backlog = self.backlog.as_ref()?;
+        let offset = u64::try_from(offset).ok()?;
+        // The offset of the first byte in the backlog.
+        let start = self.offset - backlog.len() as u64 + 1;
+        if id != self.id.as_bytes() || offset < start || offset > self.offset + 1 {
+            return None;
+        }
+        let skip = usize::try_from(offset - start).expect("backlog fits in memory");
+        let missed = backlog.range(skip..).copied().collect();
+
+        let mut replica = self.handshakes.remove(&client).unwrap_or_default();
+        replica.ack_offset = offset - 1;
+        replica.ack_time = Some(Instant::now());
+        self.replicas.insert(client, replica);
+        Some(missed)
+    }
+
+    pub fn remove_replica(&mut self, client: usize) {
+        self.handshakes.remove(&client);
+        self.replicas.remove(&client);
+    }
+
+    /// Records a replica's `REPLCONF ACK`.
+    pub fn ack(&mut self, client: usize, offset: u64) {
+        if let Some(replica) = self.replicas.get_mut(&client) {
+            replica.ack_offset = offset;
+            replica.ack_time = Some(Instant::now());
+        }
+    }
+
+    /// The number of replicas that have acknowledged the stream up to
+    /// `offset`.
+    pub fn num_acked(&self, offset: u64) -> usize {
+        self.replicas
+            .values()
+            .filter(|replica| replica.ack_offset >= offset)
+            .count()
+    }
+
+    /// Asks the replicas to acknowledge the stream right away, instead of
+    /// waiting for their next periodic `ACK`.
+    pub fn request_acks(&mut self) {
+        if self.has_replicas() {
+            self.feed_command(&Command::ReplConf(ReplConf::GetAck));
+        }
+    }
+
+    /// Adds a write command that ran in database `db` to the stream.
+    pub fn feed(&mut self, db: usize, command: &Command) {
+        if !self.is_streaming() {
+            return;
+        }
+        if self.db != Some(db) {
+            let index = i64::try_from(db).expect("database index fits in i64");
+            self.feed_command(&Command::Select(Select { index }));
+            self.db = Some(db);
+        }
+        self.feed_command(command);
+    }
+
+    /// Pings the replicas if they haven't been pinged for a while.
+    pub fn ping_if_due(&mut self, now: Instant) {
+        if !self.has_replicas() || now.duration_since(self.last_ping) < PING_PERIOD {
+            return;
+        }
+        self.feed_command(&Command::Ping);
+        self.last_ping = now;
+    }
+
+    fn feed_command(&mut self, command: &Command) {
+        let bytes = command.to_resp().to_bytes();
+        self.offset += bytes.len() as u64;
+        if let Some(backlog) = &mut self.backlog {
+            backlog.extend(&bytes);
+            let excess = backlog.len().saturating_sub(BACKLOG_SIZE);
+            backlog.drain(..excess);
+        }
+        if self.has_replicas() {
+            self.pending.extend(bytes);
+        }
+    }
+
+    /// Takes the part of the stream that hasn't been sent to replicas yet.
+    pub fn take_pending(&mut self) -> Option<Vec<u8>> {
+        (!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending))
+    }
+}
+
+/// Serializes `dbs` as an RDB snapshot for a replica's full sync on a
+/// background thread, which sends the snapshot once it's done.
+pub fn start_snapshot(dbs: Vec<Db>) -> Receiver<Result<Vec<u8>>> {
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let mut snapshot = Vec::new();
+        let written = rdb::write_snapshot(&mut snapshot, &dbs).map(|()| snapshot);
+        // The replica may have disconnected in the meantime.
+        let _ = sender.send(written);
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::command::Set;
+
+    #[test]
+    fn stream_selects_databases() {
+        let mut replication = Replication::new(&mut Rng::with_seed(1));
+        assert_eq!(replication.id().len(), 40);
+        let set = Command::Set(Set {
+            key: RedisString::from("key"),
+            value: RedisString::from("value"),
+        });
+
+        // Nothing is streamed without replicas.
+        replication.feed(0, &set);
+        assert_eq!(replication.take_pending(), None);
+        assert_eq!(replication.offset(), 0);
+
+        replication
+            .configure(
+                7,
+                &[("listening-port".to_string(), RedisString::from("6380"))],
+            )
+            .unwrap();
+        replication.add_replica(7);
+        replication.feed(0, &set);
+        replication.feed(0, &set);
+        replication.feed(2, &set);
+
+        let select = |index| Command::Select(Select { index }).to_resp().to_bytes();
+        let set_bytes = set.to_resp().to_bytes();
+        let expected = [
+            select(0),
+            set_bytes.clone(),
+            set_bytes.clone(),
+            select(2),
+            set_bytes,
+        ]
+        .concat();
+        assert_eq!(replication.offset(), expected.len() as u64);
+        assert_eq!(replication.take_pending(), Some(expected));
+        assert_eq!(replication.take_pending(), None);
+
+        replication.ack(7, 12);
+        let replicas: Vec<_> = replication.replicas().collect();
+        assert_eq!(replicas.len(), 1);
+        assert_eq!(replicas[0].1.listening_port, Some(6380));
+        assert_eq!(replicas[0].1.ack_offset, 12);
+
+        assert!(replication
+            .configure(8, &[("bogus".to_string(), RedisString::from("1"))])
+            .is_err());
+        replication.remove_replica(7);
+        assert!(!replication.has_replicas());
+    }
+
+    #[test]
+    fn continue_from_backlog() {
+        let mut replication = Replication::new(&mut Rng::with_seed(1));
+        let id = replication.id().as_bytes().to_vec();
+        // There's no backlog until a replica connects.
+        assert_eq!(replication.continue_replica(1, &id, 1), None);
+
+        replication.add_replica(1);
+        replication.feed(0, &Command::Ping);
+        assert!(replication.take_pending().is_some());
+        replication.remove_replica(1);
+        // The stream goes on without replicas.
+        replication.feed(0, &Command::Ping);
+        assert_eq!(replication.take_pending(), None);
+        let stream = [
+            Command::Select(Select { index: 0 }).to_resp().to_bytes(),
+            Command::Ping.to_resp().to_bytes(),
+            Command::Ping.to_resp().to_bytes(),
+        ]
+        .concat();
+        let end = i64::try_from(stream.len()).unwrap();
+        assert_eq!(replication.offset(), stream.len() as u64);
+
+        // Replicas ask for the offset after the last one they processed.
+        assert_eq!(
+            replication.continue_replica(2, &id, 1),
+            Some(stream.clone())
+        );
+        assert_eq!(
+            replication.continue_replica(3, &id, end - 13),
+            Some(stream[stream.len() - 14..].to_vec())
+        );
+        assert_eq!(replication.continue_replica(4, &id, end + 1), Some(vec![]));
+        assert_eq!(replication.continue_replica(5, &id, end + 2), None);
+        assert_eq!(replication.continue_replica(6, b"other", 1), None);
+        let replicas: Vec<usize> = replication.replicas().map(|(id, _)| id).collect();
+        assert_eq!(replicas, [2, 3, 4]);
+
+        // Old parts of the stream are dropped from the backlog.
+        let set = Command::Set(Set {
+            key: RedisString::from("key"),
+            value: RedisString::from(vec![b'x'; BACKLOG_SIZE]),
+        });
+        replication.feed(0, &set);
+        assert_eq!(replication.continue_replica(7, &id, 1), None);
+    }
+}
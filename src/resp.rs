@@ -1,13 +1,51 @@
 //! Implements the RESP (REdis Serialization Protocol) protocol. See
 //! <https://redis.io/docs/reference/protocol-spec/>.
 
-use std::io::{BufRead, Write};
+use std::fmt::{self, Write as _};
+use std::io::{BufRead, Read, Write};
+use std::str::FromStr;
 
-use color_eyre::eyre::{eyre, Result, WrapErr};
+use color_eyre::eyre::{eyre, Report, Result, WrapErr};
 
 use crate::string::RedisString;
 
+/// Limits on the messages `Message::parse_resp_with_limits` accepts, so a
+/// client can't make the server allocate arbitrarily large buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The longest bulk string, like Redis' `proto-max-bulk-len`.
+    pub max_bulk_len: usize,
+
+    /// The most elements in an array.
+    pub max_multibulk_len: usize,
+
+    /// The longest line, including its CRLF. This covers simple strings and
+    /// the headers of bulk strings and arrays.
+    pub max_inline_len: usize,
+
+    /// How deeply arrays may nest, so parsing a message can't overflow the
+    /// stack. Requests are flat, but some replies, like `XREAD`'s, nest a
+    /// few levels.
+    pub max_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024 * 1024,
+            max_inline_len: 64 * 1024,
+            max_depth: 128,
+        }
+    }
+}
+
+/// The most memory reserved up front for a bulk string or array, so claimed
+/// lengths only cost memory once the data actually arrives.
+const MAX_PREALLOC: usize = 64 * 1024;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Message {
     /// Simple Strings are used to transmit non binary-safe strings with minimal
     /// overhead. They cannot contain a CR or LF character.
@@ -21,9 +59,22 @@ pub enum Message {
     /// up to 512 MB in length.
     BulkString(Option<RedisString>),
 
+    /// Integers are used to return counts, booleans (0 or 1), and other
+    /// numeric results. They are transmitted as a signed 64 bit integer.
+    Integer(i64),
+
     /// Arrays are collections of RESP commands. Notably, arrays are used to
     /// send commands from the client to the Redis server.
     Array(Vec<Message>),
+
+    /// A null array is returned instead of a null bulk string by commands
+    /// that otherwise return arrays.
+    NullArray,
+
+    /// Pushes are RESP3 out-of-band messages, like pub/sub messages, that the
+    /// server sends outside the request/response cycle. They are framed like
+    /// arrays, but start with a '>' character.
+    Push(Vec<Self>),
 }
 
 impl Message {
@@ -31,6 +82,14 @@ impl Message {
         Self::BulkString(Some(RedisString::from(s)))
     }
 
+    /// Serializes the message to a new buffer.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.serialize_resp(&mut buf)
+            .expect("writing to a Vec can't fail");
+        buf
+    }
+
     pub fn serialize_resp<W>(&self, writer: &mut W) -> Result<()>
     where
         W: Write,
@@ -46,6 +105,11 @@ impl Message {
                 writer.write_all(s.as_bytes())?;
                 writer.write_all(b"\r\n")?;
             }
+            Self::Integer(i) => {
+                writer.write_all(b":")?;
+                writer.write_all(i.to_string().as_bytes())?;
+                writer.write_all(b"\r\n")?;
+            }
             Self::BulkString(s) => {
                 writer.write_all(b"$")?;
                 match s {
@@ -63,15 +127,9 @@ impl Message {
                     }
                 }
             }
-            Self::Array(msgs) => {
-                writer.write_all(b"*")?;
-                writer.write_all(msgs.len().to_string().as_bytes())?;
-                writer.write_all(b"\r\n")?;
-
-                for msg in msgs {
-                    msg.serialize_resp(writer)?;
-                }
-            }
+            Self::Array(msgs) => serialize_elements(b"*", msgs, writer)?,
+            Self::NullArray => writer.write_all(b"*-1\r\n")?,
+            Self::Push(msgs) => serialize_elements(b">", msgs, writer)?,
         }
 
         Ok(())
@@ -81,73 +139,434 @@ impl Message {
     ///
     /// A return value of `Ok(None)` indicates that the reader is empty.
     pub fn parse_resp<R>(reader: &mut R) -> Result<Option<Self>>
+    where
+        R: BufRead,
+    {
+        Self::parse_resp_with_limits(reader, &Limits::default())
+    }
+
+    /// Like `parse_resp`, but fails on messages that exceed `limits`.
+    pub fn parse_resp_with_limits<R>(reader: &mut R, limits: &Limits) -> Result<Option<Self>>
+    where
+        R: BufRead,
+    {
+        Self::parse_nested(reader, limits, 0)
+    }
+
+    /// Parses a message inside `depth` arrays.
+    fn parse_nested<R>(reader: &mut R, limits: &Limits, depth: usize) -> Result<Option<Self>>
     where
         R: BufRead,
     {
         let mut line = String::new();
-        reader.read_line(&mut line)?;
+        reader
+            .by_ref()
+            .take(limits.max_inline_len as u64)
+            .read_line(&mut line)?;
 
         if line.is_empty() {
             return Ok(None);
         }
+        if line.len() == limits.max_inline_len && !line.ends_with('\n') {
+            return Err(eyre!("too big inline request"));
+        }
 
         let line = strip_trailing_crlf(&line)
             .wrap_err_with(|| eyre!("line didn't end with CRLF: {line:?}"))?;
 
-        let resp = match line.chars().next() {
-            Some('+') => Self::SimpleString(line[1..].to_string()),
-            Some('-') => Self::Error(line[1..].to_string()),
-            Some('$') => {
-                let len: i32 = line[1..]
-                    .parse::<i32>()
-                    .wrap_err("invalid bulk string length")?;
-
-                if len >= 0 {
-                    #[allow(clippy::cast_sign_loss)]
-                    let mut buf = vec![0; len as usize];
-                    reader
-                        .read_exact(&mut buf)
-                        .wrap_err(eyre!("failed to read into buf"))?;
-
-                    // Ensure trailing CRLF!
-                    let mut trailing_crlf = [0; 2];
-                    reader
-                        .read_exact(&mut trailing_crlf)
-                        .wrap_err(eyre!("failed to read trailing CRLF"))?;
-
-                    Self::BulkString(Some(RedisString::from(buf)))
-                } else if len == -1 {
-                    Self::BulkString(None)
-                } else {
-                    return Err(eyre!("invalid bulk string length"));
+        let resp = match parse_header(line, limits, depth)? {
+            Header::Complete(message) => message,
+            Header::BulkString(len) => {
+                let mut buf = Vec::with_capacity(len.min(MAX_PREALLOC));
+                reader
+                    .by_ref()
+                    .take(len as u64)
+                    .read_to_end(&mut buf)
+                    .wrap_err(eyre!("failed to read into buf"))?;
+                if buf.len() < len {
+                    return Err(eyre!("bulk string ended early"));
+                }
+
+                // Ensure trailing CRLF!
+                let mut trailing_crlf = [0; 2];
+                reader
+                    .read_exact(&mut trailing_crlf)
+                    .wrap_err(eyre!("failed to read trailing CRLF"))?;
+                if &trailing_crlf != b"\r\n" {
+                    return Err(eyre!("bulk string didn't end with CRLF"));
                 }
+
+                Self::BulkString(Some(RedisString::from(buf)))
             }
-            Some('*') => {
-                let num_msgs = line[1..]
-                    .parse::<usize>()
-                    .wrap_err("could not parse array length")?;
-                let mut msgs = Vec::with_capacity(num_msgs);
-                for i in 0..num_msgs {
-                    let msg = Self::parse_resp(reader)
-                        .wrap_err(eyre!("failed to parse array elem {i}"))?
-                        .ok_or_else(|| eyre!("empty string at array elem {i}"))?;
-
-                    msgs.push(msg);
+            Header::Array(len) => Self::Array(parse_elements(len, reader, limits, depth)?),
+            Header::Push(len) => Self::Push(parse_elements(len, reader, limits, depth)?),
+        };
+
+        Ok(Some(resp))
+    }
+}
+
+/// Renders messages like `redis-cli` does, with quoted bulk strings and
+/// numbered array elements.
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SimpleString(s) => f.write_str(s),
+            Self::Error(e) => write!(f, "(error) {e}"),
+            Self::Integer(i) => write!(f, "(integer) {i}"),
+            Self::BulkString(None) | Self::NullArray => f.write_str("(nil)"),
+            Self::BulkString(Some(s)) => f.write_str(&quote(s.as_bytes())),
+            Self::Array(msgs) | Self::Push(msgs) if msgs.is_empty() => f.write_str("(empty array)"),
+            Self::Array(msgs) | Self::Push(msgs) => {
+                // Indexes are right-aligned, and nested elements line up
+                // under their parent's first element.
+                let width = msgs.len().to_string().len();
+                for (i, msg) in msgs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("\n")?;
+                    }
+                    for (j, line) in msg.to_string().lines().enumerate() {
+                        if j == 0 {
+                            write!(f, "{:>width$}) {line}", i + 1)?;
+                        } else {
+                            write!(f, "\n{:width$}  {line}", "")?;
+                        }
+                    }
                 }
-                Self::Array(msgs)
+                Ok(())
             }
-            Some(c) => return Err(eyre!("invalid message start: {c}")),
-            None => {
-                return Err(eyre!(
-                    "somehow no char even though we checked for empty string"
-                ))
+        }
+    }
+}
+
+/// Parses a command in the inline form, like `SET key "hello world"`, into an
+/// array of bulk strings. Arguments can be quoted like in `redis-cli`.
+impl FromStr for Message {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let args = split_args(s)?;
+        Ok(Self::Array(
+            args.into_iter()
+                .map(|arg| Self::BulkString(Some(RedisString::from(arg))))
+                .collect(),
+        ))
+    }
+}
+
+/// Quotes a string like `redis-cli`, escaping quotes and unprintable bytes.
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for byte in bytes {
+        match byte {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b' '..=b'~' => quoted.push(char::from(*byte)),
+            _ => {
+                let _ = write!(quoted, "\\x{byte:02x}");
             }
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Splits an inline command into arguments, like Redis' `sdssplitargs`.
+/// Double-quoted arguments understand the escapes `quote` produces, and
+/// single-quoted arguments are taken literally except for `\'`.
+fn split_args(line: &str) -> Result<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Ok(args);
         };
 
-        Ok(Some(resp))
+        let mut arg = Vec::new();
+        match first {
+            '"' => loop {
+                match chars.next() {
+                    None => return Err(eyre!("unbalanced quotes")),
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => arg.push(b'\n'),
+                        Some('r') => arg.push(b'\r'),
+                        Some('t') => arg.push(b'\t'),
+                        Some('a') => arg.push(0x07),
+                        Some('b') => arg.push(0x08),
+                        Some('x') => {
+                            let hex: String = chars.by_ref().take(2).collect();
+                            let byte = u8::from_str_radix(&hex, 16)
+                                .map_err(|_| eyre!("invalid escape: \\x{hex}"))?;
+                            arg.push(byte);
+                        }
+                        Some(c) => push_char(&mut arg, c),
+                        None => return Err(eyre!("unbalanced quotes")),
+                    },
+                    Some(c) => push_char(&mut arg, c),
+                }
+            },
+            '\'' => loop {
+                match chars.next() {
+                    None => return Err(eyre!("unbalanced quotes")),
+                    Some('\'') => break,
+                    Some('\\') if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        arg.push(b'\'');
+                    }
+                    Some(c) => push_char(&mut arg, c),
+                }
+            },
+            c => {
+                push_char(&mut arg, c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    push_char(&mut arg, c);
+                }
+            }
+        }
+        // Like Redis, a closing quote must end the argument.
+        if matches!(first, '"' | '\'') && chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return Err(eyre!("closing quote must be followed by a space"));
+        }
+        args.push(arg);
     }
 }
 
+fn push_char(arg: &mut Vec<u8>, c: char) {
+    arg.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// An incremental RESP parser for non-blocking I/O. Bytes are fed in as they
+/// arrive, and messages come out once all of their bytes have been fed.
+///
+/// Decoding picks up where the last feed left off. The elements of arrays
+/// that are still arriving are kept, so large arrays that trickle in are only
+/// decoded once.
+#[derive(Debug, Default)]
+pub struct RespDecoder {
+    /// Bytes fed that haven't been decoded yet.
+    buf: Vec<u8>,
+
+    /// The arrays the next element belongs to, outermost first.
+    arrays: Vec<PartialArray>,
+
+    /// The bytes of the current message that are decoded into `arrays`.
+    decoded: usize,
+    limits: Limits,
+}
+
+/// An array whose elements are still arriving.
+#[derive(Debug)]
+struct PartialArray {
+    push: bool,
+    len: usize,
+    elems: Vec<Message>,
+}
+
+impl RespDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a decoder that fails on messages that exceed `limits`.
+    pub const fn with_limits(limits: Limits) -> Self {
+        Self {
+            buf: Vec::new(),
+            arrays: Vec::new(),
+            decoded: 0,
+            limits,
+        }
+    }
+
+    /// Buffers `bytes` and decodes the next message, or returns `None` if no
+    /// complete message has been fed yet. Feed an empty slice to get any other
+    /// messages that are already buffered.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Option<Message>> {
+        self.buf.extend_from_slice(bytes);
+        let mut pos = 0;
+        let message = self.decode(&mut pos);
+        self.buf.drain(..pos);
+        let message = message?;
+        if message.is_some() {
+            self.decoded = 0;
+        } else {
+            self.decoded += pos;
+        }
+        Ok(message)
+    }
+
+    /// Decodes messages and array headers from `buf` starting at `pos`, until
+    /// a message is complete or `buf` runs out.
+    fn decode(&mut self, pos: &mut usize) -> Result<Option<Message>> {
+        'elements: loop {
+            let depth = self.arrays.len();
+            let Some((header, len)) = decode_header(&self.buf[*pos..], &self.limits, depth)? else {
+                return Ok(None);
+            };
+            *pos += len;
+            let mut message = match header {
+                Header::Complete(message) => message,
+                Header::BulkString(_) => unreachable!("bulk strings are decoded with their header"),
+                Header::Array(0) => Message::Array(Vec::new()),
+                Header::Push(0) => Message::Push(Vec::new()),
+                Header::Array(len) | Header::Push(len) => {
+                    self.arrays.push(PartialArray {
+                        push: matches!(header, Header::Push(_)),
+                        len,
+                        elems: Vec::with_capacity(len.min(MAX_PREALLOC)),
+                    });
+                    continue;
+                }
+            };
+
+            // The message may be the last element of the arrays it's in.
+            while let Some(array) = self.arrays.last_mut() {
+                array.elems.push(message);
+                if array.elems.len() < array.len {
+                    continue 'elements;
+                }
+                let array = self.arrays.pop().expect("array exists");
+                message = if array.push {
+                    Message::Push(array.elems)
+                } else {
+                    Message::Array(array.elems)
+                };
+            }
+            return Ok(Some(message));
+        }
+    }
+
+    /// The number of bytes fed that aren't part of a decoded message yet.
+    pub const fn buffered(&self) -> usize {
+        self.decoded + self.buf.len()
+    }
+}
+
+/// The first line of a message.
+enum Header {
+    /// A message that fits on its line.
+    Complete(Message),
+
+    /// A bulk string with this many bytes on the following line.
+    BulkString(usize),
+
+    /// An array with this many elements following it.
+    Array(usize),
+    Push(usize),
+}
+
+/// Parses the first line of a message inside `depth` arrays, without its
+/// CRLF.
+fn parse_header(line: &str, limits: &Limits, depth: usize) -> Result<Header> {
+    let header = match line.chars().next() {
+        Some('+') => Header::Complete(Message::SimpleString(line[1..].to_string())),
+        Some('-') => Header::Complete(Message::Error(line[1..].to_string())),
+        Some(':') => Header::Complete(Message::Integer(
+            line[1..].parse::<i64>().wrap_err("invalid integer")?,
+        )),
+        Some('$') if &line[1..] == "-1" => Header::Complete(Message::BulkString(None)),
+        Some('$') => {
+            let len = line[1..]
+                .parse::<usize>()
+                .ok()
+                .filter(|len| *len <= limits.max_bulk_len)
+                .ok_or_else(|| eyre!("invalid bulk length"))?;
+            Header::BulkString(len)
+        }
+        Some('*') if &line[1..] == "-1" => Header::Complete(Message::NullArray),
+        Some('*' | '>') if depth >= limits.max_depth => {
+            return Err(eyre!("arrays are nested too deeply"));
+        }
+        Some('*') => Header::Array(parse_multibulk_len(&line[1..], limits)?),
+        Some('>') => Header::Push(parse_multibulk_len(&line[1..], limits)?),
+        Some(c) => return Err(eyre!("invalid message start: {c}")),
+        None => return Err(eyre!("empty line")),
+    };
+    Ok(header)
+}
+
+fn parse_multibulk_len(len: &str, limits: &Limits) -> Result<usize> {
+    len.parse::<usize>()
+        .ok()
+        .filter(|len| *len <= limits.max_multibulk_len)
+        .ok_or_else(|| eyre!("invalid multibulk length"))
+}
+
+/// Decodes the header of the message at the start of `buf` inside `depth`
+/// arrays, along with the data of a bulk string, returning it along with the
+/// number of bytes it took up. Returns `None` if `buf` ends before the header
+/// or bulk string does.
+fn decode_header(buf: &[u8], limits: &Limits, depth: usize) -> Result<Option<(Header, usize)>> {
+    let search = &buf[..buf.len().min(limits.max_inline_len)];
+    let Some(newline) = search.iter().position(|b| *b == b'\n') else {
+        if search.len() == limits.max_inline_len {
+            return Err(eyre!("too big inline request"));
+        }
+        return Ok(None);
+    };
+    let line = std::str::from_utf8(&buf[..=newline]).wrap_err("line is not valid UTF-8")?;
+    let line =
+        strip_trailing_crlf(line).wrap_err_with(|| eyre!("line didn't end with CRLF: {line:?}"))?;
+    let pos = newline + 1;
+
+    match parse_header(line, limits, depth)? {
+        Header::BulkString(len) => {
+            let Some(data) = buf.get(pos..pos + len + 2) else {
+                return Ok(None);
+            };
+            let (data, trailing_crlf) = data.split_at(len);
+            if trailing_crlf != b"\r\n" {
+                return Err(eyre!("bulk string didn't end with CRLF"));
+            }
+            let message = Message::BulkString(Some(RedisString::from(data)));
+            Ok(Some((Header::Complete(message), pos + len + 2)))
+        }
+        header => Ok(Some((header, pos))),
+    }
+}
+
+/// Writes an array-like message: its length, then each element.
+fn serialize_elements<W>(prefix: &[u8], msgs: &[Message], writer: &mut W) -> Result<()>
+where
+    W: Write,
+{
+    writer.write_all(prefix)?;
+    writer.write_all(msgs.len().to_string().as_bytes())?;
+    writer.write_all(b"\r\n")?;
+
+    for msg in msgs {
+        msg.serialize_resp(writer)?;
+    }
+    Ok(())
+}
+
+/// Reads the elements of an array-like message inside `depth` arrays.
+fn parse_elements<R>(
+    num_msgs: usize,
+    reader: &mut R,
+    limits: &Limits,
+    depth: usize,
+) -> Result<Vec<Message>>
+where
+    R: BufRead,
+{
+    let mut msgs = Vec::with_capacity(num_msgs.min(MAX_PREALLOC));
+    for i in 0..num_msgs {
+        let msg = Message::parse_nested(reader, limits, depth + 1)
+            .wrap_err(eyre!("failed to parse array elem {i}"))?
+            .ok_or_else(|| eyre!("empty string at array elem {i}"))?;
+
+        msgs.push(msg);
+    }
+    Ok(msgs)
+}
+
 fn strip_trailing_crlf(s: &str) -> Result<&str> {
     s.strip_suffix("\r\n")
         .ok_or_else(|| eyre!("string does not end with CRLF"))
@@ -168,6 +587,8 @@ mod tests {
         let leaf = prop_oneof![
             any::<String>().prop_map(Message::SimpleString),
             any::<String>().prop_map(Message::Error),
+            any::<i64>().prop_map(Message::Integer),
+            Just(Message::NullArray),
             any::<Option<Vec<u8>>>().prop_map(|b| Message::BulkString(b.map(RedisString::from))),
         ];
 
@@ -175,7 +596,12 @@ mod tests {
             8,   // 8 levels deep
             256, // Shoot for maximum size of 256 nodes
             10,  // We put up to 10 items per collection
-            |inner| prop::collection::vec(inner, 0..10).prop_map(Message::Array),
+            |inner| {
+                prop_oneof![
+                    prop::collection::vec(inner.clone(), 0..10).prop_map(Message::Array),
+                    prop::collection::vec(inner, 0..10).prop_map(Message::Push),
+                ]
+            },
         )
     }
 
@@ -187,6 +613,168 @@ mod tests {
             let got = Message::parse_resp(&mut buf.as_slice()).unwrap();
             assert_eq!(Some(msg), got);
         }
+
+        #[test]
+        fn decode_byte_by_byte(msg in arb_message()) {
+            let mut buf = Vec::new();
+            msg.serialize_resp(&mut buf).unwrap();
+            let mut decoder = RespDecoder::new();
+            let (last, rest) = buf.split_last().unwrap();
+            for byte in rest {
+                assert_eq!(decoder.feed(&[*byte]).unwrap(), None);
+            }
+            assert_eq!(decoder.feed(&[*last]).unwrap(), Some(msg));
+            assert_eq!(decoder.buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn display_like_redis_cli() {
+        let message = Message::Array(vec![
+            Message::bulk_string("a \"quoted\"\n\x01"),
+            Message::Integer(1),
+            Message::Array(vec![Message::NullArray, Message::Array(Vec::new())]),
+            Message::Error("ERR oops".to_string()),
+            Message::SimpleString("OK".to_string()),
+            Message::BulkString(None),
+            Message::Integer(7),
+            Message::Integer(8),
+            Message::Integer(9),
+            Message::Array(vec![Message::Integer(10), Message::Integer(11)]),
+        ]);
+        assert_eq!(
+            message.to_string(),
+            [
+                r#" 1) "a \"quoted\"\n\x01""#,
+                " 2) (integer) 1",
+                " 3) 1) (nil)",
+                "    2) (empty array)",
+                " 4) (error) ERR oops",
+                " 5) OK",
+                " 6) (nil)",
+                " 7) (integer) 7",
+                " 8) (integer) 8",
+                " 9) (integer) 9",
+                "10) 1) (integer) 10",
+                "    2) (integer) 11",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn parse_inline() {
+        let message: Message = r#"SET  "hello world" 'it\'s' "\x00\\" plain"#.parse().unwrap();
+        assert_eq!(
+            message,
+            Message::Array(vec![
+                Message::bulk_string("SET"),
+                Message::bulk_string("hello world"),
+                Message::bulk_string("it's"),
+                Message::BulkString(Some(RedisString::from(vec![0, b'\\']))),
+                Message::bulk_string("plain"),
+            ])
+        );
+        assert_eq!(
+            "PING".parse::<Message>().unwrap().to_bytes(),
+            b"*1\r\n$4\r\nPING\r\n"
+        );
+        assert!("GET \"unbalanced".parse::<Message>().is_err());
+        assert!("GET \"a\"b".parse::<Message>().is_err());
+    }
+
+    #[test]
+    fn decode_buffered_messages() {
+        let mut decoder = RespDecoder::new();
+        assert_eq!(
+            decoder.feed(b"+OK\r\n$5\r\nhello\r\n*1\r\n").unwrap(),
+            Some(Message::SimpleString("OK".to_string()))
+        );
+        assert_eq!(
+            decoder.feed(b"").unwrap(),
+            Some(Message::bulk_string("hello"))
+        );
+        assert_eq!(decoder.feed(b"").unwrap(), None);
+        assert_eq!(decoder.buffered(), 4);
+        assert_eq!(
+            decoder.feed(b":1\r\n").unwrap(),
+            Some(Message::Array(vec![Message::Integer(1)]))
+        );
+
+        // The elements of a partial array are only decoded once.
+        let mut decoder = RespDecoder::new();
+        assert_eq!(decoder.feed(b"*2\r\n$5\r\nhello\r\n:1").unwrap(), None);
+        assert_eq!(decoder.buf, b":1");
+        assert_eq!(decoder.buffered(), 17);
+        assert_eq!(
+            decoder.feed(b"\r\n").unwrap(),
+            Some(Message::Array(vec![
+                Message::bulk_string("hello"),
+                Message::Integer(1)
+            ]))
+        );
+        assert_eq!(decoder.buffered(), 0);
+
+        let limits = Limits {
+            max_bulk_len: 5,
+            max_multibulk_len: 2,
+            max_inline_len: 8,
+            max_depth: 1,
+        };
+        let decode = |bytes: &[u8]| RespDecoder::with_limits(limits).feed(bytes);
+        assert!(decode(b"$6\r\n").is_err());
+        assert!(decode(b"*3\r\n").is_err());
+        assert!(decode(b"+too long").is_err());
+        assert!(decode(b"$5\r\nhello!!").is_err());
+        assert!(decode(b"?\r\n").is_err());
+        assert!(decode(b"*1\r\n*1\r\n").is_err());
+    }
+
+    #[test]
+    fn parse_with_limits() {
+        let limits = Limits {
+            max_bulk_len: 5,
+            max_multibulk_len: 2,
+            max_inline_len: 8,
+            max_depth: 1,
+        };
+        let parse = |bytes: &[u8]| Message::parse_resp_with_limits(&mut &bytes[..], &limits);
+
+        assert_eq!(
+            parse(b"*2\r\n$5\r\nhello\r\n+OK\r\n").unwrap(),
+            Some(Message::Array(vec![
+                Message::bulk_string("hello"),
+                Message::SimpleString("OK".to_string()),
+            ]))
+        );
+        assert!(parse(b"$6\r\nhello!\r\n").is_err());
+        assert!(parse(b"$-2\r\n").is_err());
+        assert!(parse(b"*3\r\n+a\r\n+b\r\n+c\r\n").is_err());
+        assert!(parse(b"*1\r\n*3\r\n").is_err());
+        assert!(parse(b"*1\r\n*1\r\n+a\r\n").is_err());
+        assert!(parse(b"+too long\r\n").is_err());
+
+        // Lengths are checked before anything is allocated, and a claimed
+        // length doesn't need to be backed by data.
+        let huge = format!("${}\r\n", usize::MAX);
+        assert!(Message::parse_resp(&mut huge.as_bytes()).is_err());
+        assert!(Message::parse_resp(&mut &b"$1000000\r\nshort\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn deeply_nested_arrays() {
+        // Nested arrays are rejected before they can overflow the stack.
+        let nested = b"*1\r\n".repeat(5000);
+        let error = Message::parse_resp(&mut &nested[..]).unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "arrays are nested too deeply"
+        );
+        assert!(RespDecoder::new().feed(&nested).is_err());
+
+        let mut nested = b"*1\r\n".repeat(128);
+        nested.extend_from_slice(b":1\r\n");
+        assert!(Message::parse_resp(&mut &nested[..]).unwrap().is_some());
     }
 
     #[test]
@@ -218,6 +806,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn integer_round_trip() {
+        assert_message_round_trip(Message::Integer(0), b":0\r\n");
+        assert_message_round_trip(Message::Integer(1000), b":1000\r\n");
+        assert_message_round_trip(Message::Integer(-42), b":-42\r\n");
+    }
+
     #[test]
     fn bulk_string_round_trip() {
         assert_message_round_trip(Message::BulkString(None), b"$-1\r\n");
@@ -237,6 +832,7 @@ mod tests {
     #[test]
     fn array_round_trip() {
         assert_message_round_trip(Message::Array(Vec::new()), b"*0\r\n");
+        assert_message_round_trip(Message::NullArray, b"*-1\r\n");
         assert_message_round_trip(
             Message::Array(vec![Message::SimpleString("OK".to_string())]),
             b"*1\r\n+OK\r\n",
@@ -259,4 +855,15 @@ mod tests {
             b"*4\r\n*1\r\n+nested\r\n+OK\r\n$20\r\nhello\r\nwith\r\nnewline\r\n+blah\r\n",
         );
     }
+
+    #[test]
+    fn push_round_trip() {
+        assert_message_round_trip(
+            Message::Push(vec![
+                Message::bulk_string("invalidate"),
+                Message::Array(vec![Message::bulk_string("key")]),
+            ]),
+            b">2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nkey\r\n",
+        );
+    }
 }
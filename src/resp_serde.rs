@@ -0,0 +1,927 @@
+//! Maps Rust values to and from RESP messages with serde, behind the `serde`
+//! feature.
+//!
+//! The mapping follows how Redis itself replies:
+//!
+//! - Integers and booleans are RESP integers. When deserializing, bulk strings
+//!   holding integers are accepted too, since that's how hashes store them.
+//! - Strings, bytes and floats are bulk strings.
+//! - `None` and `()` are nil.
+//! - Sequences and tuples are arrays.
+//! - Maps and structs are flat arrays of alternating keys and values, like
+//!   `HGETALL` replies.
+//! - Unit enum variants are their name, and other variants are an array of
+//!   their name and their contents.
+
+use std::fmt;
+
+use color_eyre::eyre::Result;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Visitor};
+use serde::ser::{self, Serialize};
+
+use crate::resp::Message;
+use crate::string::RedisString;
+
+/// Converts a value to a RESP message.
+pub fn to_message<T>(value: &T) -> Result<Message>
+where
+    T: Serialize + ?Sized,
+{
+    Ok(value.serialize(Serializer)?)
+}
+
+/// Converts a RESP message to a value. Error replies fail with their message.
+pub fn from_message<T>(message: Message) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    Ok(T::deserialize(Deserializer(message))?)
+}
+
+#[derive(Debug)]
+struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Message;
+    type Error = Error;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant;
+    type SerializeMap = SerializeArray;
+    type SerializeStruct = SerializeArray;
+    type SerializeStructVariant = SerializeVariant;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<Message, Error> {
+        Ok(Message::Integer(i64::from(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Message, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Message, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Message, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Message, Error> {
+        Ok(Message::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Message, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Message, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Message, Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Message, Error> {
+        let v = i64::try_from(v).map_err(|_| Error(format!("integer {v} is out of range")))?;
+        self.serialize_i64(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Message, Error> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Message, Error> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<Message, Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Message, Error> {
+        Ok(Message::bulk_string(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Message, Error> {
+        Ok(Message::BulkString(Some(RedisString::from(v))))
+    }
+
+    fn serialize_none(self) -> Result<Message, Error> {
+        Ok(Message::BulkString(None))
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Message, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Message, Error> {
+        self.serialize_none()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Message, Error> {
+        self.serialize_none()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Message, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Message, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Message, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(Message::Array(vec![
+            Message::bulk_string(variant),
+            value.serialize(self)?,
+        ]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, Error> {
+        Ok(SerializeArray(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant, Error> {
+        Ok(SerializeVariant {
+            variant,
+            fields: SerializeArray(Vec::with_capacity(len)),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeArray, Error> {
+        self.serialize_seq(len.map(|len| len * 2))
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len * 2))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant, Error> {
+        Ok(SerializeVariant {
+            variant,
+            fields: SerializeArray(Vec::with_capacity(len * 2)),
+        })
+    }
+}
+
+/// Collects the elements of an array, or the flattened entries of a map or
+/// struct.
+struct SerializeArray(Vec<Message>);
+
+impl SerializeArray {
+    fn push<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.0.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(Message::Array(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(Message::Array(self.0))
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(Message::Array(self.0))
+    }
+}
+
+impl ser::SerializeMap for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(key)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(Message::Array(self.0))
+    }
+}
+
+impl ser::SerializeStruct for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(key)?;
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(Message::Array(self.0))
+    }
+}
+
+/// Collects the fields of a tuple or struct enum variant.
+struct SerializeVariant {
+    variant: &'static str,
+    fields: SerializeArray,
+}
+
+impl SerializeVariant {
+    fn into_message(self) -> Message {
+        Message::Array(vec![
+            Message::bulk_string(self.variant),
+            Message::Array(self.fields.0),
+        ])
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.fields.push(value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(self.into_message())
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.fields.push(key)?;
+        self.fields.push(value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(self.into_message())
+    }
+}
+
+struct Deserializer(Message);
+
+impl Deserializer {
+    fn integer(self) -> Result<i64, Error> {
+        match self.0 {
+            Message::Integer(i) => Ok(i),
+            Message::BulkString(Some(s)) => s
+                .to_i64()
+                .ok_or_else(|| Error(format!("expected an integer, got {s:?}"))),
+            other => Err(unexpected(&other, "an integer")),
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn float(self) -> Result<f64, Error> {
+        match self.0 {
+            Message::Integer(i) => Ok(i as f64),
+            Message::BulkString(Some(s)) => String::try_from(s.clone())
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .ok_or_else(|| Error(format!("expected a float, got {s:?}"))),
+            other => Err(unexpected(&other, "a float")),
+        }
+    }
+
+    const fn is_nil(&self) -> bool {
+        matches!(self.0, Message::BulkString(None) | Message::NullArray)
+    }
+}
+
+fn unexpected(message: &Message, expected: &str) -> Error {
+    Error(format!("expected {expected}, got {message:?}"))
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Message::SimpleString(s) => visitor.visit_string(s),
+            Message::Error(e) => Err(Error(e)),
+            Message::Integer(i) => visitor.visit_i64(i),
+            Message::BulkString(Some(s)) => match String::try_from(s) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            Message::BulkString(None) | Message::NullArray => visitor.visit_none(),
+            Message::Array(msgs) | Message::Push(msgs) => {
+                visitor.visit_seq(SeqDeserializer(msgs.into_iter()))
+            }
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.integer()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            i => Err(Error(format!("expected a boolean, got {i}"))),
+        }
+    }
+
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i64(self.integer()?)
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_f64(self.float()?)
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Message::Integer(i) => visitor.visit_string(i.to_string()),
+            other => Self(other).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Message::BulkString(Some(s)) => visitor.visit_byte_buf(Vec::from(s)),
+            Message::SimpleString(s) => visitor.visit_byte_buf(s.into_bytes()),
+            other => Self(other).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.is_nil() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.is_nil() {
+            visitor.visit_unit()
+        } else {
+            Err(unexpected(&self.0, "nil"))
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Message::Array(msgs) | Message::Push(msgs) => {
+                visitor.visit_seq(SeqDeserializer(msgs.into_iter()))
+            }
+            other => Err(unexpected(&other, "an array")),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Message::Array(msgs) | Message::Push(msgs) if msgs.len() % 2 == 0 => {
+                visitor.visit_map(MapDeserializer(msgs.into_iter()))
+            }
+            other => Err(unexpected(&other, "an array of keys and values")),
+        }
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Message::Array(msgs) => {
+                let [variant, value]: [Message; 2] = msgs
+                    .try_into()
+                    .map_err(|_| Error("expected a variant name and its contents".to_string()))?;
+                visitor.visit_enum(EnumDeserializer {
+                    variant,
+                    value: Some(value),
+                })
+            }
+            variant @ (Message::SimpleString(_) | Message::BulkString(Some(_))) => visitor
+                .visit_enum(EnumDeserializer {
+                    variant,
+                    value: None,
+                }),
+            other => Err(unexpected(&other, "an enum variant")),
+        }
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
+
+struct SeqDeserializer(std::vec::IntoIter<Message>);
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.0
+            .next()
+            .map(|msg| seed.deserialize(Deserializer(msg)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+/// Reads a flat array of alternating keys and values, which must have an even
+/// length.
+struct MapDeserializer(std::vec::IntoIter<Message>);
+
+impl<'de> de::MapAccess<'de> for MapDeserializer {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        self.0
+            .next()
+            .map(|msg| seed.deserialize(Deserializer(msg)))
+            .transpose()
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let msg = self.0.next().expect("map arrays have an even length");
+        seed.deserialize(Deserializer(msg))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len() / 2)
+    }
+}
+
+struct EnumDeserializer {
+    variant: Message,
+
+    /// The variant's contents, or `None` for unit variants.
+    value: Option<Message>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, VariantDeserializer), Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(Deserializer(self.variant))?;
+        Ok((variant, VariantDeserializer(self.value)))
+    }
+}
+
+struct VariantDeserializer(Option<Message>);
+
+impl VariantDeserializer {
+    fn contents(self) -> Result<Deserializer, Error> {
+        self.0
+            .map(Deserializer)
+            .ok_or_else(|| Error("expected a variant with contents".to_string()))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        self.0
+            .map_or(Ok(()), |msg| Err(unexpected(&msg, "a unit variant")))
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.contents()?)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self.contents()?, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_map(self.contents()?, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+        admin: bool,
+        email: Option<String>,
+        tags: Vec<String>,
+        role: Role,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Guest,
+        Member(u64),
+        Moderator { forums: Vec<String> },
+    }
+
+    fn round_trip<T>(value: &T, expected: &Message)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+    {
+        let message = to_message(value).unwrap();
+        assert_eq!(&message, expected);
+        assert_eq!(&from_message::<T>(message).unwrap(), value);
+    }
+
+    #[test]
+    fn structs_are_flat_arrays() {
+        let user = User {
+            name: "alice".to_string(),
+            age: 30,
+            admin: false,
+            email: None,
+            tags: vec!["a".to_string()],
+            role: Role::Member(7),
+        };
+        round_trip(
+            &user,
+            &Message::Array(vec![
+                Message::bulk_string("name"),
+                Message::bulk_string("alice"),
+                Message::bulk_string("age"),
+                Message::Integer(30),
+                Message::bulk_string("admin"),
+                Message::Integer(0),
+                Message::bulk_string("email"),
+                Message::BulkString(None),
+                Message::bulk_string("tags"),
+                Message::Array(vec![Message::bulk_string("a")]),
+                Message::bulk_string("role"),
+                Message::Array(vec![Message::bulk_string("Member"), Message::Integer(7)]),
+            ]),
+        );
+    }
+
+    #[test]
+    fn enums_and_maps() {
+        round_trip(&Role::Guest, &Message::bulk_string("Guest"));
+        round_trip(
+            &Role::Moderator {
+                forums: vec!["rust".to_string()],
+            },
+            &Message::Array(vec![
+                Message::bulk_string("Moderator"),
+                Message::Array(vec![
+                    Message::bulk_string("forums"),
+                    Message::Array(vec![Message::bulk_string("rust")]),
+                ]),
+            ]),
+        );
+        round_trip(
+            &BTreeMap::from([("a".to_string(), 1.5)]),
+            &Message::Array(vec![Message::bulk_string("a"), Message::bulk_string("1.5")]),
+        );
+    }
+
+    #[test]
+    fn hash_replies_parse_integers() {
+        // HGETALL returns every value as a bulk string.
+        let reply = Message::Array(vec![
+            Message::bulk_string("count"),
+            Message::bulk_string("42"),
+        ]);
+        let map: BTreeMap<String, i64> = from_message(reply).unwrap();
+        assert_eq!(map, BTreeMap::from([("count".to_string(), 42)]));
+
+        assert!(from_message::<String>(Message::Error("ERR oops".to_string())).is_err());
+        assert!(
+            from_message::<BTreeMap<String, i64>>(Message::Array(vec![Message::bulk_string(
+                "odd"
+            )]))
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Cursor-based iteration shared by `SCAN` and friends. See
+//! <https://redis.io/commands/scan/>.
+//!
+//! A cursor is a position in a stable ordering of a collection's elements,
+//! where each element's position is a deterministic hash of it. Iteration
+//! returns elements in position order, so every element that is present for
+//! the whole iteration is returned exactly once, even if other elements are
+//! added or removed in between calls.
+//!
+//! The keyspace is a hash trie, whose own order works the same way, so `SCAN`
+//! resumes from the cursor's place in the trie and only visits the entries
+//! it returns. Hashes, sets and sorted sets are hashed and filtered in full
+//! on each call.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// The default number of elements to return per call when `COUNT` isn't
+/// given.
+pub const DEFAULT_COUNT: usize = 10;
+
+/// Returns the stable cursor position of an element.
+pub fn position<K: Hash + ?Sized>(key: &K) -> u64 {
+    // `DefaultHasher::new()` always uses the same keys, so the hash is stable
+    // for the lifetime of the process.
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Selects roughly `count` items at or after `cursor` from `items`, in cursor
+/// order.
+///
+/// `position_of` must return each item's position, usually by calling
+/// `position` on the item's key. Returns the cursor for the next call, which
+/// is 0 once iteration is complete.
+///
+/// More than `count` items may be returned if several items share a position.
+pub fn scan<T, I, F>(items: I, cursor: u64, count: usize, position_of: F) -> (u64, Vec<T>)
+where
+    I: IntoIterator<Item = T>,
+    F: Fn(&T) -> u64,
+{
+    let mut candidates: Vec<(u64, T)> = items
+        .into_iter()
+        .map(|item| (position_of(&item), item))
+        .filter(|(pos, _)| *pos >= cursor)
+        .collect();
+
+    let count = count.max(1);
+    if candidates.len() <= count {
+        candidates.sort_unstable_by_key(|(pos, _)| *pos);
+        return (0, candidates.into_iter().map(|(_, item)| item).collect());
+    }
+
+    candidates.select_nth_unstable_by_key(count - 1, |(pos, _)| *pos);
+    let last = candidates[count - 1].0;
+    let mut selected: Vec<(u64, T)> = candidates
+        .into_iter()
+        .filter(|(pos, _)| *pos <= last)
+        .collect();
+    selected.sort_unstable_by_key(|(pos, _)| *pos);
+
+    // If the last position is u64::MAX then we are done, and wrapping around
+    // to 0 signals that.
+    let next_cursor = last.wrapping_add(1);
+    (
+        next_cursor,
+        selected.into_iter().map(|(_, item)| item).collect(),
+    )
+}
+
+/// Takes `count` items from `items`, which yields each item with its
+/// position in increasing order, starting from some cursor. Items sharing the
+/// last position are taken too, so the next call can start after it.
+///
+/// Returns the cursor for the next call, which is 0 once `items` runs out.
+pub fn take<T, I>(items: I, count: usize) -> (u64, Vec<T>)
+where
+    I: IntoIterator<Item = (u64, T)>,
+{
+    let count = count.max(1);
+    let mut items = items.into_iter().peekable();
+    let mut taken = Vec::new();
+    while let Some((pos, item)) = items.next() {
+        taken.push(item);
+        if taken.len() < count {
+            continue;
+        }
+        match items.peek() {
+            None => break,
+            Some((next, _)) if *next != pos => return (pos.wrapping_add(1), taken),
+            Some(_) => {}
+        }
+    }
+    (0, taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use crate::hamt::HashTrieMap;
+
+    #[test]
+    fn scan_returns_everything_once() {
+        let items: HashSet<String> = (0..1000).map(|i| format!("key:{i}")).collect();
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        let mut calls = 0;
+        loop {
+            let (next, batch) = scan(items.iter(), cursor, 7, |s| position(*s));
+            calls += 1;
+            for item in batch {
+                assert!(seen.insert(item.clone()), "duplicate item {item}");
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        assert_eq!(seen, items);
+        assert!(calls >= 1000 / 7);
+    }
+
+    #[test]
+    fn scan_tolerates_concurrent_modification() {
+        let mut items: HashSet<String> = (0..100).map(|i| format!("key:{i}")).collect();
+        let stable: HashSet<String> = (0..50).map(|i| format!("key:{i}")).collect();
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        let mut i = 0;
+        loop {
+            let (next, batch) = scan(items.iter(), cursor, 5, |s| position(*s));
+            seen.extend(batch.into_iter().cloned());
+
+            // Churn the unstable half of the items.
+            items.remove(&format!("key:{}", 50 + i % 50));
+            items.insert(format!("new:{i}"));
+            i += 1;
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        assert!(stable.is_subset(&seen));
+    }
+
+    #[test]
+    fn take_only_visits_what_it_returns() {
+        let map: HashTrieMap<String, ()> = (0..10_000).map(|i| (format!("key:{i}"), ())).collect();
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let mut visited = 0;
+            let entries = map.scan(cursor).inspect(|_| visited += 1);
+            let (next, batch) = take(entries, 10);
+            // The entry after the batch is peeked at to find the cursor.
+            assert!(visited <= batch.len() + 1, "visited {visited} entries");
+            for (key, ()) in batch {
+                assert!(seen.insert(key.clone()), "duplicate key {key}");
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen.len(), map.len());
+    }
+
+    #[test]
+    fn scan_empty() {
+        let items: Vec<String> = Vec::new();
+        let (next, batch) = scan(items.iter(), 0, 10, |s| position(*s));
+        assert_eq!(next, 0);
+        assert!(batch.is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+//! The script cache behind `SCRIPT LOAD` and `EVALSHA`.
+//!
+//! Scripts are named by the SHA-1 of their body, so clients can send a script
+//! once and then run it by its digest. Like Redis, the cache isn't persisted
+//! or replicated.
+
+use std::collections::HashMap;
+
+use crate::sha1::sha1_hex;
+use crate::string::RedisString;
+
+#[derive(Debug, Default)]
+pub struct ScriptCache {
+    /// Script bodies by their lower-case hex SHA-1.
+    scripts: HashMap<String, RedisString>,
+}
+
+impl ScriptCache {
+    /// Caches a script, returning its SHA-1.
+    pub fn load(&mut self, script: RedisString) -> String {
+        let sha = sha1_hex(script.as_bytes());
+        self.scripts.entry(sha.clone()).or_insert(script);
+        sha
+    }
+
+    /// Looks up a script by its SHA-1, in either case.
+    pub fn get(&self, sha: &RedisString) -> Option<&RedisString> {
+        let sha = std::str::from_utf8(sha.as_bytes()).ok()?;
+        self.scripts.get(&sha.to_ascii_lowercase())
+    }
+
+    pub fn contains(&self, sha: &RedisString) -> bool {
+        self.get(sha).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Roughly how many bytes the cached scripts and their names take up.
+    pub fn approximate_size(&self) -> usize {
+        self.scripts
+            .iter()
+            .map(|(sha, script)| sha.len() + script.len())
+            .sum()
+    }
+
+    /// Empties the cache, returning the scripts so the caller can choose
+    /// where to free them.
+    pub fn flush(&mut self) -> HashMap<String, RedisString> {
+        std::mem::take(&mut self.scripts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_and_flush() {
+        let mut cache = ScriptCache::default();
+        let sha = cache.load(RedisString::from("return 1"));
+        assert_eq!(sha, "e0e1f9fabfc9d4800c877a703b823ac0578ff8db");
+        assert_eq!(cache.load(RedisString::from("return 1")), sha);
+        assert_eq!(cache.len(), 1);
+
+        assert_eq!(
+            cache.get(&RedisString::from(sha.to_uppercase().as_str())),
+            Some(&RedisString::from("return 1"))
+        );
+        assert!(!cache.contains(&RedisString::from(
+            "ffffffffffffffffffffffffffffffffffffffff"
+        )));
+
+        assert_eq!(cache.flush().len(), 1);
+        assert!(cache.is_empty());
+        assert!(!cache.contains(&RedisString::from(sha.as_str())));
+    }
+}
@@ -0,0 +1,599 @@
+//! A minimal monitor in the style of Redis Sentinel. See
+//! <https://redis.io/docs/management/sentinel/>.
+//!
+//! Each monitor pings a master, and learns about its replicas from `INFO
+//! replication`. When the master hasn't answered for `down_after`, the monitor
+//! considers it subjectively down and asks its peers whether they agree. Once
+//! `quorum` monitors agree, the master is objectively down and the monitors
+//! elect a leader, which promotes the replica with the most replicated data
+//! with `REPLICAOF NO ONE` and points the other replicas at it. Monitors that
+//! didn't lead the failover switch to the new master once they see a replica
+//! reporting itself as a master.
+//!
+//! Peers talk to each other with `SENTINEL IS-MASTER-DOWN-BY-ADDR`, which
+//! Redis Sentinel uses both to ask whether the master is down and to ask for
+//! votes.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+
+use crate::command::{Command, CommandResponse, Info, ReplicaOf};
+use crate::random::Rng;
+use crate::resp::Message;
+
+/// How often the master and replicas are checked.
+const CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// How long to wait for a server or peer to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The address of a server or peer monitor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Addr {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Addr {
+    /// Parses `host:port`.
+    pub fn parse(s: &str) -> Result<Self> {
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| eyre!("expected host:port, got {s:?}"))?;
+        let port = port
+            .parse()
+            .wrap_err_with(|| format!("invalid port in {s:?}"))?;
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The master to monitor at startup.
+    pub master: Addr,
+
+    /// How many monitors, including this one, must agree the master is down
+    /// before failing over.
+    pub quorum: usize,
+
+    /// How long the master may go without answering before it's considered
+    /// down, like Sentinel's `down-after-milliseconds`.
+    pub down_after: Duration,
+
+    /// How long to wait before trying again after a failover attempt, like
+    /// Sentinel's `failover-timeout`.
+    pub failover_timeout: Duration,
+
+    /// The other monitors watching the same master.
+    pub peers: Vec<Addr>,
+}
+
+impl Config {
+    pub const fn new(master: Addr) -> Self {
+        Self {
+            master,
+            quorum: 1,
+            down_after: Duration::from_secs(5),
+            failover_timeout: Duration::from_secs(30),
+            peers: Vec::new(),
+        }
+    }
+}
+
+/// `Sentinel` monitors a master and fails over to one of its replicas when
+/// enough monitors agree that it's down.
+#[derive(Debug)]
+pub struct Sentinel {
+    /// Identifies this monitor in leader elections, as 40 hex characters.
+    id: String,
+    config: Config,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    master: Addr,
+    replicas: Vec<Addr>,
+
+    /// When the master last answered a `PING`.
+    last_reply: Instant,
+
+    /// Old masters this monitor failed over from, which are made replicas of
+    /// the new master once they're back.
+    demoted: Vec<Addr>,
+
+    /// The latest election epoch seen, which increases with every failover
+    /// attempt.
+    epoch: u64,
+
+    /// The epoch of the last vote cast, and who it went to.
+    vote: Option<(u64, String)>,
+
+    /// When this monitor last tried to fail over.
+    failover_attempt: Option<Instant>,
+}
+
+impl Sentinel {
+    pub fn new(config: Config) -> Self {
+        let mut rng = Rng::new();
+        let mut id = String::new();
+        for _ in 0..3 {
+            write!(id, "{:016x}", rng.next_u64()).expect("writing to a String can't fail");
+        }
+        id.truncate(40);
+        let state = State {
+            master: config.master.clone(),
+            replicas: Vec::new(),
+            last_reply: Instant::now(),
+            demoted: Vec::new(),
+            epoch: 0,
+            vote: None,
+            failover_attempt: None,
+        };
+        Self {
+            id,
+            config,
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The master currently monitored.
+    pub fn master(&self) -> Addr {
+        self.state().master.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("couldn't lock sentinel state")
+    }
+
+    /// Answers peers and clients on `listener` in the background, and checks
+    /// the master forever.
+    pub fn run(self: &Arc<Self>, listener: TcpListener) {
+        let sentinel = Arc::clone(self);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let sentinel = Arc::clone(&sentinel);
+                thread::spawn(move || {
+                    if let Err(e) = sentinel.serve(stream) {
+                        log::warn!("error serving connection: {e:?}");
+                    }
+                });
+            }
+        });
+
+        loop {
+            self.check();
+            thread::sleep(CHECK_PERIOD);
+        }
+    }
+
+    /// Checks on the master and its replicas once, failing over if the master
+    /// is down.
+    pub fn check(&self) {
+        let master = self.master();
+        let info = request(&master, &info_command()).ok().and_then(as_info);
+        let Some(info) = info else {
+            self.check_down_master(&master);
+            return;
+        };
+        let now = Instant::now();
+        let mut state = self.state();
+        state.last_reply = now;
+
+        // Another monitor may have failed over and made our master a replica.
+        if info.get("role").map(String::as_str) == Some("slave") {
+            if let Some(new_master) = info_master(&info) {
+                log::info!("{master} is now a replica of {new_master}, following it");
+                state.switch_master(new_master, now);
+            }
+            return;
+        }
+        state.replicas = info_replicas(&info);
+
+        let demoted = std::mem::take(&mut state.demoted);
+        drop(state);
+        let mut still_down = Vec::new();
+        for old in demoted {
+            let replica_of = Command::ReplicaOf(ReplicaOf {
+                master: Some((master.host.clone(), master.port)),
+            });
+            match request(&old, &replica_of) {
+                Ok(CommandResponse::Ok | CommandResponse::Status(_)) => {
+                    log::info!("made old master {old} a replica of {master}");
+                }
+                _ => still_down.push(old),
+            }
+        }
+        self.state().demoted.extend(still_down);
+    }
+
+    /// Decides what to do about a master that didn't answer.
+    fn check_down_master(&self, master: &Addr) {
+        let now = Instant::now();
+        let (replicas, epoch, retrying) = {
+            let state = self.state();
+            if now.duration_since(state.last_reply) < self.config.down_after {
+                return;
+            }
+            let retrying = state
+                .failover_attempt
+                .is_some_and(|at| now.duration_since(at) < self.config.failover_timeout);
+            (state.replicas.clone(), state.epoch, retrying)
+        };
+        log::warn!("master {master} is subjectively down");
+
+        // A monitor that won an election may have already promoted a replica.
+        let promoted = replicas.iter().find(|replica| {
+            request(replica, &info_command())
+                .ok()
+                .and_then(as_info)
+                .is_some_and(|info| info.get("role").map(String::as_str) == Some("master"))
+        });
+        if let Some(promoted) = promoted {
+            log::info!("replica {promoted} was promoted, following it");
+            self.state().switch_master(promoted.clone(), now);
+            return;
+        }
+        if retrying {
+            return;
+        }
+
+        let agreeing = 1 + self
+            .config
+            .peers
+            .iter()
+            .filter(|peer| ask_peer(peer, master, epoch, "*").is_ok_and(|(down, ..)| down))
+            .count();
+        if agreeing < self.config.quorum {
+            return;
+        }
+        log::warn!("master {master} is objectively down ({agreeing} monitors agree)");
+
+        // Ask for votes in a new epoch, voting for ourselves first.
+        let epoch = {
+            let mut state = self.state();
+            state.epoch += 1;
+            state.vote = Some((state.epoch, self.id.clone()));
+            state.failover_attempt = Some(now);
+            state.epoch
+        };
+        let votes = 1 + self
+            .config
+            .peers
+            .iter()
+            .filter(|peer| {
+                ask_peer(peer, master, epoch, &self.id).is_ok_and(|(_, leader, leader_epoch)| {
+                    leader == self.id && leader_epoch == epoch
+                })
+            })
+            .count();
+        let monitors = self.config.peers.len() + 1;
+        let majority = monitors / 2 + 1;
+        if votes < majority.max(self.config.quorum) {
+            log::info!("lost election for epoch {epoch} with {votes} votes");
+            return;
+        }
+        log::info!("won election for epoch {epoch} with {votes} votes");
+        if let Err(e) = self.failover(master, &replicas) {
+            log::error!("failover from {master} failed: {e:?}");
+        }
+    }
+
+    /// Promotes the replica with the most replicated data, and makes the
+    /// other replicas replicate it.
+    fn failover(&self, master: &Addr, replicas: &[Addr]) -> Result<()> {
+        let promoted = replicas
+            .iter()
+            .filter_map(|replica| {
+                let info = request(replica, &info_command()).ok().and_then(as_info)?;
+                let offset: u64 = info.get("slave_repl_offset")?.parse().ok()?;
+                Some((offset, replica))
+            })
+            .max_by_key(|(offset, _)| *offset)
+            .map(|(_, replica)| replica.clone())
+            .ok_or_else(|| eyre!("no replica is reachable"))?;
+
+        log::info!("promoting {promoted} to master");
+        match request(&promoted, &Command::ReplicaOf(ReplicaOf { master: None }))? {
+            CommandResponse::Ok => {}
+            reply => return Err(eyre!("{promoted} replied to REPLICAOF with {reply:?}")),
+        }
+        let replica_of = Command::ReplicaOf(ReplicaOf {
+            master: Some((promoted.host.clone(), promoted.port)),
+        });
+        for replica in replicas.iter().filter(|replica| **replica != promoted) {
+            if let Err(e) = request(replica, &replica_of) {
+                log::warn!("failed to point {replica} at {promoted}: {e:?}");
+            }
+        }
+
+        let mut state = self.state();
+        state.switch_master(promoted, Instant::now());
+        state.demoted.push(master.clone());
+        drop(state);
+        Ok(())
+    }
+
+    /// Answers `PING`, `SENTINEL IS-MASTER-DOWN-BY-ADDR` from peers, and
+    /// `SENTINEL GET-MASTER-ADDR-BY-NAME` from clients looking for the master.
+    fn serve(&self, stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        while let Some(message) = Message::parse_resp(&mut reader)? {
+            let args = match message {
+                Message::Array(args) => args
+                    .into_iter()
+                    .map(|arg| match arg {
+                        Message::BulkString(Some(arg)) => {
+                            Some(String::from_utf8_lossy(arg.as_bytes()).into_owned())
+                        }
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<String>>>(),
+                _ => None,
+            };
+            let reply = args.map_or_else(
+                || Message::Error("ERR invalid request".to_string()),
+                |args| self.handle(&args),
+            );
+            reply.serialize_resp(&mut writer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn handle(&self, args: &[String]) -> Message {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args[..] {
+            [ping] if ping.eq_ignore_ascii_case("PING") => {
+                Message::SimpleString("PONG".to_string())
+            }
+            [sentinel, subcommand, host, port, epoch, id]
+                if sentinel.eq_ignore_ascii_case("SENTINEL")
+                    && subcommand.eq_ignore_ascii_case("IS-MASTER-DOWN-BY-ADDR") =>
+            {
+                let (Ok(port), Ok(epoch)) = (port.parse(), epoch.parse()) else {
+                    return Message::Error("ERR invalid port or epoch".to_string());
+                };
+                let addr = Addr {
+                    host: host.to_string(),
+                    port,
+                };
+                let (down, leader, leader_epoch) =
+                    self.is_master_down_by_addr(&addr, epoch, id, Instant::now());
+                Message::Array(vec![
+                    Message::Integer(i64::from(down)),
+                    Message::bulk_string(&leader),
+                    Message::Integer(i64::try_from(leader_epoch).unwrap_or(i64::MAX)),
+                ])
+            }
+            [sentinel, subcommand, _name]
+                if sentinel.eq_ignore_ascii_case("SENTINEL")
+                    && subcommand.eq_ignore_ascii_case("GET-MASTER-ADDR-BY-NAME") =>
+            {
+                let master = self.master();
+                Message::Array(vec![
+                    Message::bulk_string(&master.host),
+                    Message::bulk_string(&master.port.to_string()),
+                ])
+            }
+            _ => Message::Error(format!("ERR unknown command {args:?}")),
+        }
+    }
+
+    /// Answers whether the master at `addr` is down from this monitor's point
+    /// of view. Unless `id` is `*`, it's also a request for our vote in
+    /// `epoch`, which goes to the first monitor that asks. Returns whether the
+    /// master is down, and who we voted for in which epoch.
+    fn is_master_down_by_addr(
+        &self,
+        addr: &Addr,
+        epoch: u64,
+        id: &str,
+        now: Instant,
+    ) -> (bool, String, u64) {
+        let mut state = self.state();
+        let down =
+            state.master == *addr && now.duration_since(state.last_reply) >= self.config.down_after;
+        if id != "*" && state.vote.as_ref().is_none_or(|(voted, _)| epoch > *voted) {
+            state.vote = Some((epoch, id.to_string()));
+            state.epoch = state.epoch.max(epoch);
+        }
+        match &state.vote {
+            Some((voted, leader)) if id != "*" => (down, leader.clone(), *voted),
+            _ => (down, "*".to_string(), 0),
+        }
+    }
+}
+
+impl State {
+    fn switch_master(&mut self, master: Addr, now: Instant) {
+        self.replicas.retain(|replica| *replica != master);
+        self.master = master;
+        self.last_reply = now;
+        self.failover_attempt = None;
+    }
+}
+
+fn info_command() -> Command {
+    Command::Info(Info {
+        sections: vec!["replication".to_string()],
+    })
+}
+
+/// Sends a command to a server or peer and reads its reply.
+fn request(addr: &Addr, command: &Command) -> Result<CommandResponse> {
+    request_message(addr, &command.to_resp())
+}
+
+fn request_message(addr: &Addr, message: &Message) -> Result<CommandResponse> {
+    let socket_addr = (addr.host.as_str(), addr.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| eyre!("couldn't resolve {addr}"))?;
+    let mut stream = TcpStream::connect_timeout(&socket_addr, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    message.serialize_resp(&mut stream)?;
+    let reply = Message::parse_resp(&mut BufReader::new(stream))?
+        .ok_or_else(|| eyre!("{addr} closed the connection"))?;
+    CommandResponse::parse_resp(reply)
+}
+
+/// Asks a peer whether it thinks `master` is down, and for its vote in
+/// `epoch` unless `id` is `*`.
+fn ask_peer(peer: &Addr, master: &Addr, epoch: u64, id: &str) -> Result<(bool, String, u64)> {
+    let message = Message::Array(
+        [
+            "SENTINEL",
+            "IS-MASTER-DOWN-BY-ADDR",
+            &master.host,
+            &master.port.to_string(),
+            &epoch.to_string(),
+            id,
+        ]
+        .into_iter()
+        .map(Message::bulk_string)
+        .collect(),
+    );
+    match request_message(peer, &message)? {
+        CommandResponse::Array(reply) => match &reply[..] {
+            [CommandResponse::Integer(down), CommandResponse::BulkString(Some(leader)), CommandResponse::Integer(leader_epoch)] => {
+                Ok((
+                    *down == 1,
+                    String::from_utf8_lossy(leader.as_bytes()).into_owned(),
+                    u64::try_from(*leader_epoch)?,
+                ))
+            }
+            _ => Err(eyre!("unexpected reply from {peer}: {reply:?}")),
+        },
+        reply => Err(eyre!("unexpected reply from {peer}: {reply:?}")),
+    }
+}
+
+/// Parses the `field:value` lines of an `INFO` reply.
+fn as_info(reply: CommandResponse) -> Option<HashMap<String, String>> {
+    let CommandResponse::BulkString(Some(info)) = reply else {
+        return None;
+    };
+    Some(parse_info(&String::from_utf8_lossy(info.as_bytes())))
+}
+
+fn parse_info(info: &str) -> HashMap<String, String> {
+    info.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(field, value)| (field.to_string(), value.to_string()))
+        .collect()
+}
+
+/// The replicas a master lists in `INFO`, as `slave0:ip=...,port=...,...`.
+fn info_replicas(info: &HashMap<String, String>) -> Vec<Addr> {
+    let mut replicas: Vec<(usize, Addr)> = info
+        .iter()
+        .filter_map(|(field, value)| {
+            let index = field.strip_prefix("slave")?.parse().ok()?;
+            let fields: HashMap<&str, &str> =
+                value.split(',').filter_map(|f| f.split_once('=')).collect();
+            let addr = Addr {
+                host: (*fields.get("ip")?).to_string(),
+                port: fields.get("port")?.parse().ok()?,
+            };
+            Some((index, addr))
+        })
+        .collect();
+    replicas.sort_by_key(|(index, _)| *index);
+    replicas.into_iter().map(|(_, addr)| addr).collect()
+}
+
+/// The master a replica lists in `INFO`.
+fn info_master(info: &HashMap<String, String>) -> Option<Addr> {
+    Some(Addr {
+        host: info.get("master_host")?.clone(),
+        port: info.get("master_port")?.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_replication_info() {
+        let info = parse_info(
+            "# Replication\r\nrole:master\r\nconnected_slaves:2\r\n\
+             slave1:ip=10.0.0.3,port=6381,state=online,offset=10,lag=0\r\n\
+             slave0:ip=10.0.0.2,port=6380,state=online,offset=12,lag=1\r\n\
+             master_repl_offset:12\r\n",
+        );
+        assert_eq!(info["role"], "master");
+        assert_eq!(
+            info_replicas(&info),
+            [
+                Addr::parse("10.0.0.2:6380").unwrap(),
+                Addr::parse("10.0.0.3:6381").unwrap(),
+            ]
+        );
+        assert_eq!(info_master(&info), None);
+
+        let info = parse_info("role:slave\r\nmaster_host:10.0.0.1\r\nmaster_port:6379\r\n");
+        assert_eq!(
+            info_master(&info),
+            Some(Addr::parse("10.0.0.1:6379").unwrap())
+        );
+        assert!(Addr::parse("localhost").is_err());
+    }
+
+    #[test]
+    fn votes_go_to_first_monitor_to_ask() {
+        let master = Addr::parse("127.0.0.1:6379").unwrap();
+        let mut config = Config::new(master.clone());
+        config.down_after = Duration::from_secs(5);
+        let sentinel = Sentinel::new(config);
+        let now = Instant::now();
+        let later = now + Duration::from_secs(10);
+
+        // Asking whether the master is down doesn't cast a vote.
+        assert_eq!(
+            sentinel.is_master_down_by_addr(&master, 1, "*", now),
+            (false, "*".to_string(), 0)
+        );
+        let other = Addr::parse("127.0.0.1:6380").unwrap();
+        assert!(!sentinel.is_master_down_by_addr(&other, 1, "*", later).0);
+
+        assert_eq!(
+            sentinel.is_master_down_by_addr(&master, 1, "a", later),
+            (true, "a".to_string(), 1)
+        );
+        assert_eq!(
+            sentinel.is_master_down_by_addr(&master, 1, "b", later),
+            (true, "a".to_string(), 1)
+        );
+        assert_eq!(
+            sentinel.is_master_down_by_addr(&master, 2, "b", later),
+            (true, "b".to_string(), 2)
+        );
+        assert_eq!(sentinel.state().epoch, 2);
+    }
+}
@@ -1,17 +1,93 @@
 //! Core server functionality for redis-clone.
 
-use std::collections::HashMap;
-use std::io::{BufReader, BufWriter, Write};
-use std::net::{TcpListener, TcpStream};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use color_eyre::eyre::{eyre, Result, WrapErr};
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{select, Receiver, Sender, TryRecvError};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
 
-use crate::command::{Command, CommandResponse, Get, Set};
-use crate::resp::Message;
-use crate::string::RedisString;
+use crate::acl::{self, Category, Denial, DenialReason, User, Users, DEFAULT_USER};
+use crate::aof::{self, Aof, AofConfig};
+use crate::bitmap;
+use crate::blocking::{BlockedClient, BlockedClients};
+use crate::cluster::{self, ClusterState, Routing};
+use crate::command::{
+    Acl, Aggregate, Auth, BLMPop, BLMove, BPop, BitCount, BitPos, BitRange, BitUnit, Client,
+    ClientPause, ClientTracking, Cluster, Command, CommandQuery, CommandResponse, Comparison,
+    Config, DebugCommand, Del, Dump, ErrorCode, ErrorReply, Eval, EvalSha, Existence, Expire,
+    ExpireTime, FCall, Flush, FlushMode, GeoAdd, GeoDist, GeoHash, GeoOrigin, GeoPos, GeoSearch,
+    Get, GetBit, HDel, HExists, HGet, HGetAll, HKeys, HLen, HMGet, HScan, HSet, HSetNx, HStrLen,
+    HVals, Hello, Info, InsertPosition, LIndex, LInsert, LLen, LMPop, LMove, LRange, LRem, LSet,
+    Latency, Limit, ListEnd, Memory, Move, Object, PSubscribe, PSync, PUnsubscribe, PauseMode,
+    Persist, Pop, Publish, Push, ReplConf, ReplicaOf, Restore, SAdd, SCard, SInterCard, SIsMember,
+    SMIsMember, SMembers, SRem, SScan, Scan, Script, Select, Set, SetBit, SetOp, SetOperation,
+    Sort, SortOrder, Subscribe, TimeUnit, Touch, Ttl, Unlink, Unsubscribe, Wait, XAck, XAdd,
+    XAutoClaim, XClaim, XDel, XGroup, XInfo, XLen, XPending, XRange, XRead, XReadGroup, XTrim,
+    ZAdd, ZCard, ZCount, ZIncrBy, ZMScore, ZRandMember, ZRange, ZRangeBy, ZRank, ZRem, ZScan,
+    ZScore, ZSetOp,
+};
+use crate::command_table::{self, BeginSearch, CommandSpec, FindKeys, Flag, KeyFlag, KeySpec};
+use crate::config::ServerConfig;
+use crate::db::{unix_time_millis, Db, Entry, Value, WrongType, ENTRY_OVERHEAD};
+use crate::debug::{self, DebugAccess, DEBUG_DISABLED_MESSAGE};
+use crate::evict::{self, EvictionPolicy, MaxMemoryConfig};
+use crate::function::{Context, Functions, ServerFunction};
+use crate::geo::{self, Coordinates};
+use crate::glob;
+use crate::latency::{self, LatencyMonitor};
+use crate::lazyfree::LazyFree;
+use crate::pubsub::PubSub;
+use crate::random::Rng;
+use crate::rdb;
+use crate::replica::{MasterEvent, MasterLink};
+use crate::replication::{self, Replication};
+use crate::resp::{Limits, Message};
+use crate::scan;
+use crate::script::ScriptCache;
+use crate::set;
+use crate::snapshot::{self, Snapshots, DEFAULT_SNAPSHOT_PATH};
+use crate::sort;
+use crate::stats::{self, CommandStats};
+use crate::stream::{
+    Claim, Fields, GroupReadId, ReadId, Stream, StreamId, DEFAULT_AUTOCLAIM_COUNT,
+};
+use crate::string::{RedisString, StringValue};
+use crate::tls::{self, Acceptor};
+use crate::tracking::Tracking;
+use crate::zset::{Score, ScoreBound, ScoreRange, SortedSet};
+
+/// The number of logical databases a server has unless configured otherwise.
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// The Redis version this server tells clients it is in `HELLO`, since some
+/// clients check it before using newer commands.
+const REDIS_VERSION: &str = "7.0.0";
+
+/// How often the core worker does time-based work like active expiration,
+/// matching Redis' default `hz` of 10.
+const CRON_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many keys with a TTL active expiration checks in each database at a
+/// time, like Redis' `ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP`.
+const EXPIRE_SAMPLE_SIZE: usize = 20;
+
+/// Active expiration keeps sampling a database while more than this percent
+/// of a sample had expired, like Redis' `active-expire-effort` default.
+const EXPIRE_STALE_PERCENT: usize = 25;
+
+/// The most time one run of `cron` spends expiring keys, a quarter of
+/// `CRON_INTERVAL` like Redis' `ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC`.
+const EXPIRE_TIME_LIMIT: Duration = Duration::from_millis(25);
 
 /// A `Server` is a redis-clone server.
 ///
@@ -22,83 +98,579 @@ use crate::string::RedisString;
 pub struct Server {
     next_thread_id: ThreadId,
 
-    /// Used for child threads to register their response channels so the core
-    /// worker thread knows where to send responses.
-    response_channels: Arc<Mutex<HashMap<ThreadId, Sender<CommandResponse>>>>,
+    /// Settings the server starts with, which `CONFIG SET` can change later.
+    config: ServerConfig,
+
+    /// Functions clients can call with `FCALL`.
+    functions: Functions,
+
+    /// The cluster this server is part of, if cluster mode is enabled.
+    cluster: Option<ClusterState>,
 
-    /// Used for sending commands to the core worker thread.
-    command_sender: Sender<(ThreadId, Command)>,
+    /// Used for child threads to register their outgoing message queues so the
+    /// core worker thread knows where to send responses and pushes.
+    response_channels: Arc<Mutex<HashMap<ThreadId, Sender<Outgoing>>>>,
+
+    /// The users clients authenticate as, and what they're allowed to do.
+    /// Client threads check every command against them, and the core changes
+    /// them with `ACL SETUSER` and `CONFIG SET requirepass`.
+    acl: Arc<Mutex<Users>>,
+
+    /// Whether protected mode is on, which the core changes with `CONFIG SET
+    /// protected-mode`.
+    protected_mode: Arc<AtomicBool>,
+
+    /// Per-command statistics for `INFO commandstats`. The core records the
+    /// commands it runs, and client threads the ones they answer or reject.
+    stats: Arc<Mutex<CommandStats>>,
+
+    /// The server's threads, which a `ShutdownHandle` stops.
+    workers: Arc<Workers>,
 
     /// Used for the core worker thread to receive commands for processing.
-    command_receiver: Receiver<(ThreadId, Command)>,
+    command_receiver: Receiver<Request>,
+}
+
+/// Stops a running server from another thread, like an embedder's or a
+/// test's. See `Server::shutdown_handle`.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    workers: Arc<Workers>,
+}
+
+impl ShutdownHandle {
+    /// Stops the server. It stops accepting connections, answers the
+    /// commands clients already sent, gets the dataset to disk, and waits for
+    /// its threads to exit. `Server::start` returns once shutdown starts.
+    ///
+    /// Blocked and paused clients are let go, as if they timed out.
+    pub fn shutdown(&self) -> Result<()> {
+        let (clients, core) = {
+            let mut running = self
+                .workers
+                .running
+                .lock()
+                .map_err(|_| eyre!("couldn't lock workers"))?;
+            running.stop = None;
+            running.command_sender = None;
+            (std::mem::take(&mut running.clients), running.core.take())
+        };
+        log::info!("shutting down");
+
+        // Client threads stop once they've answered what they already read.
+        for (thread_id, client) in &clients {
+            if let Err(e) = client.socket.shutdown(Shutdown::Read) {
+                log::info!("failed to shut down connection: [{thread_id}] {e}");
+            }
+        }
+        for (thread_id, client) in clients {
+            if client.thread.join().is_err() {
+                log::error!("client thread panicked: [{thread_id}]");
+            }
+        }
+
+        // The core stops once the last client thread is gone, and it flushes
+        // persistence on the way out.
+        match core {
+            Some(core) => core.join().map_err(|_| eyre!("core thread panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+/// A server's threads, shared with its shutdown handles.
+#[derive(Debug)]
+struct Workers {
+    /// Disconnected once shutdown starts, which wakes the accept loop and the
+    /// core.
+    stopping: Receiver<()>,
+    running: Mutex<Running>,
+}
+
+#[derive(Debug)]
+struct Running {
+    /// Dropped to start shutting down.
+    stop: Option<Sender<()>>,
+
+    /// The core's command channel, which each client thread gets a clone of.
+    /// Once shutdown drops it, no more clients are accepted, and the core
+    /// stops after the last client thread does.
+    command_sender: Option<Sender<Request>>,
+
+    clients: HashMap<ThreadId, ClientWorker>,
+    core: Option<JoinHandle<Result<()>>>,
+}
+
+#[derive(Debug)]
+struct ClientWorker {
+    /// The client's connection, which shutdown closes for reading so the
+    /// thread stops.
+    socket: TcpStream,
+    thread: JoinHandle<()>,
 }
 
 type ThreadId = usize;
+type DbIndex = usize;
+
+/// A command a client thread sends to the core.
+#[derive(Debug)]
+struct Request {
+    client: ThreadId,
+
+    /// The client's currently selected database.
+    db: DbIndex,
+
+    /// The name the command's statistics are kept under.
+    name: String,
+    command: Command,
+}
+
+/// The error clients get when protected mode refuses their connection, which
+/// is the same as Redis'.
+const PROTECTED_MODE_MESSAGE: &str = "Redis is running in protected mode because protected \
+    mode is enabled and no password is set for the default user. In this mode connections are \
+    only accepted from the loopback interface. If you want to connect from external computers \
+    to Redis you may adopt one of the following solutions: 1) Just disable protected mode \
+    sending the command 'CONFIG SET protected-mode no' from the loopback interface by \
+    connecting to Redis from the same host the server is running, however MAKE SURE Redis is \
+    not publicly accessible from internet if you do so. Use CONFIG REWRITE to make this \
+    change permanent. 2) Alternatively you can just disable the protected mode by editing the \
+    Redis configuration file, and setting the protected mode option to 'no', and then \
+    restarting the server. 3) If you started the server manually just for testing, restart it \
+    with the '--protected-mode no' option. 4) Set up an authentication password for the \
+    default user. NOTE: You only need to do one of the above things in order for the server \
+    to start accepting connections from the outside.";
+
+/// A pause set by `CLIENT PAUSE`.
+#[derive(Debug)]
+struct Pause {
+    mode: PauseMode,
+    until: Instant,
+}
+
+/// Stands in for the client when applying commands from the master, which
+/// doesn't have a client thread.
+const MASTER_CLIENT: ThreadId = ThreadId::MAX;
+
+/// A message queued to be written to a client's connection.
+#[derive(Debug)]
+enum Outgoing {
+    /// The response to the command the client is waiting on.
+    Reply(CommandResponse),
+
+    /// An out-of-band message, like a pub/sub message or a key invalidation,
+    /// sent as a RESP3 push frame.
+    Push(Vec<CommandResponse>),
+
+    /// The snapshot for a replica's full sync, which is sent as a bulk string
+    /// without the trailing CRLF once the background thread producing it is
+    /// done. Messages queued after it wait until it's sent.
+    Snapshot(Receiver<Result<Vec<u8>>>),
+
+    /// Part of the replication stream, which is sent as is.
+    Replication(Arc<[u8]>),
+}
 
 impl Server {
     pub fn new() -> Self {
-        let (command_sender, command_receiver) =
-            crossbeam_channel::unbounded::<(ThreadId, Command)>();
+        Self::with_databases(DEFAULT_DATABASES)
+    }
+
+    /// Creates a server with the given number of logical databases.
+    pub fn with_databases(num_databases: usize) -> Self {
+        Self::with_config(ServerConfig {
+            databases: num_databases,
+            ..ServerConfig::default()
+        })
+    }
+
+    /// Creates a server with the given settings, like those loaded from a
+    /// config file with `ServerConfig::load`.
+    pub fn with_config(config: ServerConfig) -> Self {
+        let (command_sender, command_receiver) = crossbeam_channel::unbounded::<Request>();
+        let mut stats = CommandStats::default();
+        stats.set_latency_tracking(config.latency_tracking);
+        let (stop, stopping) = crossbeam_channel::bounded(0);
+        let running = Running {
+            stop: Some(stop),
+            command_sender: Some(command_sender),
+            clients: HashMap::new(),
+            core: None,
+        };
         Self {
             next_thread_id: 0,
+            acl: Arc::new(Mutex::new(Users::with_config(&config))),
+            protected_mode: Arc::new(AtomicBool::new(config.protected_mode)),
+            stats: Arc::new(Mutex::new(stats)),
+            config,
+            functions: Functions::default(),
+            cluster: None,
             response_channels: Arc::new(Mutex::new(HashMap::new())),
-            command_sender,
+            workers: Arc::new(Workers {
+                stopping,
+                running: Mutex::new(running),
+            }),
             command_receiver,
         }
     }
 
+    /// Sets the limits on the size of client requests. Requests that exceed
+    /// them get a protocol error.
+    pub const fn set_limits(&mut self, limits: Limits) {
+        self.config.limits = limits;
+    }
+
+    /// Sets the file `SAVE` and `BGSAVE` write snapshots to, and that is
+    /// loaded when the server starts.
+    pub fn set_snapshot_path(&mut self, path: impl Into<PathBuf>) {
+        self.config.snapshot_path = path.into();
+    }
+
+    /// Enables the append-only file. When the server starts, it loads the AOF
+    /// instead of the snapshot, and then appends every write command to it.
+    pub fn enable_aof(&mut self, config: AofConfig) {
+        self.config.appendonly = true;
+        self.config.aof = config;
+    }
+
+    /// Sets the file `CONFIG REWRITE` saves the server's settings to.
+    pub fn set_config_file(&mut self, path: impl Into<PathBuf>) {
+        self.config.config_file = Some(path.into());
+    }
+
+    /// Enables cluster mode. The server only serves keys in the hash slots
+    /// `cluster` says it owns, and redirects clients elsewhere for the rest.
+    pub fn enable_cluster(&mut self, cluster: ClusterState) {
+        self.cluster = Some(cluster);
+    }
+
+    /// Limits how much memory the keyspace may use. Beyond the limit, keys are
+    /// evicted according to the policy, or if none can be, commands that
+    /// could use more memory fail with `OOM`.
+    pub const fn set_maxmemory(&mut self, config: MaxMemoryConfig) {
+        self.config.maxmemory = config;
+    }
+
+    /// Registers a function that clients can call with `FCALL name numkeys
+    /// [key ...] [arg ...]`. See `ServerFunction`.
+    pub fn register_function(
+        &mut self,
+        name: impl Into<String>,
+        function: impl ServerFunction + 'static,
+    ) {
+        self.functions.register(name, function);
+    }
+
+    /// A handle that stops the server from another thread, since `start`
+    /// doesn't return until then.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            workers: Arc::clone(&self.workers),
+        }
+    }
+
+    /// Whether connections from `addr` are refused by protected mode, which
+    /// only lets loopback clients in while the server listens on every
+    /// address without a password, like Redis.
+    fn is_protected_from(&self, addr: SocketAddr) -> bool {
+        self.protected_mode.load(Ordering::Relaxed)
+            && self.config.bind.is_empty()
+            && !addr.ip().to_canonical().is_loopback()
+            && self
+                .acl
+                .lock()
+                .expect("couldn't lock ACL")
+                .get(DEFAULT_USER)
+                .is_some_and(User::nopass)
+    }
+
     fn get_thread_id(&mut self) -> ThreadId {
         let id = self.next_thread_id;
         self.next_thread_id += 1;
         id
     }
 
+    /// Starts the server on `addr`, ignoring the `bind` and `port` settings.
     pub fn start<A>(&mut self, addr: A) -> Result<()>
     where
-        A: std::net::ToSocketAddrs,
+        A: ToSocketAddrs,
     {
-        self.start_core_worker_thread();
+        let listener = listen(addr, self.config.tcp_backlog)
+            .wrap_err_with(|| eyre!("failed to start server"))?;
+        self.serve(vec![listener])
+    }
 
-        let listener = TcpListener::bind(addr).wrap_err_with(|| eyre!("failed to start server"))?;
-        log::info!("Listening on {}", listener.local_addr()?);
+    /// Starts the server on every `bind` address, at `port`. Like Redis, an
+    /// address starting with `-` is skipped if it isn't available.
+    pub fn start_bound(&mut self) -> Result<()> {
+        let mut listeners = Vec::new();
+        for bind in self.config.bind_addrs() {
+            let addr = (bind.host.as_str(), self.config.port);
+            match listen(addr, self.config.tcp_backlog) {
+                Ok(listener) => listeners.push(listener),
+                Err(e) if bind.optional => log::warn!("skipping bind address {}: {e}", bind.host),
+                Err(e) => {
+                    return Err(e).wrap_err_with(|| eyre!("failed to listen on {}", bind.host));
+                }
+            }
+        }
+        if listeners.is_empty() {
+            return Err(eyre!("failed to listen on any bind address"));
+        }
+        self.serve(listeners)
+    }
 
-        for stream in listener.incoming() {
-            let stream = stream?;
-            self.start_next_client_thread(stream)?;
+    /// Loads the dataset and accepts connections on `listeners`, the first of
+    /// which is the server's address. Like Redis, the listeners are opened
+    /// before loading, but connections are only accepted after.
+    fn serve(&mut self, listeners: Vec<TcpListener>) -> Result<()> {
+        // Load the snapshot before accepting connections, so clients never see
+        // a partially loaded keyspace.
+        let config = &self.config;
+        let mut core = ServerCore::new(config.databases);
+        core.snapshots = Snapshots::new(config.snapshot_path.clone());
+        core.functions = self.functions.clone();
+        core.cluster.clone_from(&self.cluster);
+        if config.appendonly {
+            // The AOF has every write, so it's more up to date than the
+            // snapshot.
+            core.load_aof(&config.aof)?;
+            core.aof = Some(Aof::open(&config.aof)?);
+        } else if let Some(dbs) = snapshot::load(&config.snapshot_path, config.databases)? {
+            core.dbs = dbs;
+        }
+        core.set_maxmemory(config.maxmemory.clone());
+        core.config = config.clone();
+        core.acl = Arc::clone(&self.acl);
+        core.protected_mode = Arc::clone(&self.protected_mode);
+        core.stats = Arc::clone(&self.stats);
+        if let Some(path) = &config.aclfile {
+            self.acl
+                .lock()
+                .expect("couldn't lock ACL")
+                .load(path)
+                .wrap_err("failed to load ACL file")?;
+        }
+
+        // Each TLS listener is on the same address as a plain one.
+        let mut tls_listeners = Vec::new();
+        if config.tls.port != 0 {
+            let acceptor = Acceptor::new(&config.tls).wrap_err("failed to set up TLS")?;
+            for listener in &listeners {
+                let tls_addr = SocketAddr::new(listener.local_addr()?.ip(), config.tls.port);
+                let tls_listener = listen(tls_addr, config.tcp_backlog)
+                    .wrap_err_with(|| eyre!("failed to listen for TLS on {tls_addr}"))?;
+                tls_listeners.push((tls_listener, acceptor.clone()));
+            }
+        }
+        core.port = listeners[0].local_addr()?.port();
+        self.start_core_worker_thread(core);
+
+        let (connection_sender, connections) = crossbeam_channel::unbounded();
+        let stopping = self.workers.stopping.clone();
+        let mut acceptors = Vec::new();
+        for (tls_listener, acceptor) in tls_listeners {
+            let tls_addr = tls_listener.local_addr()?;
+            log::info!("Listening for TLS on {tls_addr}");
+            let thread = accept_connections(
+                tls_listener,
+                Some(acceptor),
+                connection_sender.clone(),
+                stopping.clone(),
+            );
+            acceptors.push((tls_addr, thread));
+        }
+        for listener in listeners {
+            let local_addr = listener.local_addr()?;
+            log::info!("Listening on {local_addr}");
+            let thread =
+                accept_connections(listener, None, connection_sender.clone(), stopping.clone());
+            acceptors.push((local_addr, thread));
+        }
+        drop(connection_sender);
+        loop {
+            select! {
+                recv(connections) -> stream => {
+                    let Ok(stream) = stream else {
+                        break;
+                    };
+                    self.start_next_client_thread(stream?)?;
+                }
+                recv(stopping) -> _ => break,
+            }
         }
 
+        // The accept threads are stuck waiting for connections until they get
+        // one, after which they see the shutdown and close their listeners.
+        for (addr, thread) in acceptors {
+            wake_listener(addr);
+            if thread.join().is_err() {
+                log::error!("accept thread panicked");
+            }
+        }
         Ok(())
     }
 
-    fn start_core_worker_thread(&mut self) {
+    #[allow(clippy::too_many_lines)]
+    fn start_core_worker_thread(&mut self, mut core: ServerCore) {
         let command_receiver = self.command_receiver.clone();
         let core_response_channels = self.response_channels.clone();
-        thread::spawn(move || {
-            let mut core = ServerCore::new();
-            while let Ok((thread_id, command)) = command_receiver.recv() {
-                log::info!("core thread got command: [{thread_id}] {command:?}");
-                let response = core.process_command(command);
-                log::info!("core thread response: [{thread_id}] {response:?}");
+        let workers = Arc::clone(&self.workers);
+        let core = thread::spawn(move || {
+            // Returns whether the client is still connected.
+            let send = |thread_id: ThreadId, outgoing: Outgoing| {
+                log::info!("core thread sending: [{thread_id}] {outgoing:?}");
+                // Pushes can target clients that have since disconnected, so a
+                // missing channel isn't an error.
                 core_response_channels
                     .lock()
                     .expect("couldn't lock response channels")
                     .get(&thread_id)
-                    .expect("no response channel for thread")
-                    .send(response)
-                    .expect("failed to send response");
+                    // The channel's receiver is dropped when the client
+                    // disconnects.
+                    .is_some_and(|channel| channel.send(outgoing).is_ok())
+            };
+            let send_pushes = |core: &mut ServerCore| {
+                for (thread_id, push) in std::mem::take(&mut core.pushes) {
+                    if !send(thread_id, Outgoing::Push(push)) {
+                        core.pubsub.remove_client(thread_id);
+                    }
+                }
+            };
+            let run_command = |core: &mut ServerCore, request: Request| {
+                let thread_id = request.client;
+                let response = core.process_request(request);
+                // Pushes go out before the reply, like Redis, so a client
+                // subscribing to several channels gets their
+                // confirmations in order.
+                send_pushes(core);
+                if let Some(response) = response {
+                    send(thread_id, Outgoing::Reply(response));
+                } else {
+                    log::info!("core thread sent no reply: [{thread_id}]");
+                }
+                for (thread_id, sync) in std::mem::take(&mut core.syncs) {
+                    send(thread_id, sync);
+                }
+            };
+            let cron = crossbeam_channel::tick(CRON_INTERVAL);
+            loop {
+                // Wake up in time to time out blocked clients, and to end a
+                // pause.
+                let deadline = core
+                    .blocked
+                    .next_deadline()
+                    .into_iter()
+                    .chain(core.pause.as_ref().map(|pause| pause.until))
+                    .min()
+                    .map_or_else(crossbeam_channel::never, crossbeam_channel::at);
+                let master = core
+                    .master
+                    .as_ref()
+                    .map_or_else(crossbeam_channel::never, |link| link.events().clone());
+                let stopping = if core.shutting_down {
+                    crossbeam_channel::never()
+                } else {
+                    workers.stopping.clone()
+                };
+                select! {
+                    recv(command_receiver) -> received => {
+                        let Ok(request) = received else {
+                            break;
+                        };
+                        log::info!(
+                            "core thread got command: [{}] (db {}) {:?}",
+                            request.client,
+                            request.db,
+                            request.command
+                        );
+                        if core.is_paused(&request.command) {
+                            log::info!("core thread paused command: [{}]", request.client);
+                            core.paused_commands.push_back(request);
+                        } else {
+                            run_command(&mut core, request);
+                        }
+                    }
+                    recv(master) -> event => {
+                        // The link only stops when it's dropped.
+                        if let Ok(event) = event {
+                            core.process_master_event(event);
+                        }
+                    }
+                    recv(cron) -> _ => core.cron(),
+                    recv(deadline) -> _ => {}
+                    recv(stopping) -> _ => {
+                        log::info!("core thread shutting down");
+                        core.shutting_down = true;
+                    }
+                }
+                if core
+                    .pause
+                    .as_ref()
+                    .is_some_and(|pause| pause.until <= Instant::now())
+                {
+                    core.pause = None;
+                }
+                while let Some(request) = core.next_unpaused_command() {
+                    run_command(&mut core, request);
+                }
+                for (thread_id, response) in core.unblock_clients() {
+                    send(thread_id, Outgoing::Reply(response));
+                }
+                send_pushes(&mut core);
+                if let Some(stream) = core.replication.take_pending() {
+                    let stream: Arc<[u8]> = stream.into();
+                    let replicas: Vec<ThreadId> =
+                        core.replication.replicas().map(|(id, _)| id).collect();
+                    for replica in replicas {
+                        if !send(replica, Outgoing::Replication(Arc::clone(&stream))) {
+                            log::info!("replica disconnected: [{replica}]");
+                            core.replication.remove_replica(replica);
+                        }
+                    }
+                }
             }
+            core.flush_persistence()
         });
-
-        // TODO - handle shutdown
+        self.workers
+            .running
+            .lock()
+            .expect("couldn't lock workers")
+            .core = Some(core);
     }
 
-    fn start_next_client_thread(&mut self, stream: TcpStream) -> Result<()> {
+    fn start_next_client_thread(&mut self, stream: impl Into<tls::Stream>) -> Result<()> {
+        let mut stream = stream.into();
         let addr = stream.peer_addr()?;
         log::info!("connection received from {addr}");
+        if self.is_protected_from(addr) {
+            // Like Redis, the client is told why before it's disconnected.
+            log::warn!("refusing connection from {addr} in protected mode");
+            let error =
+                CommandResponse::Error(ErrorReply::new(ErrorCode::Denied, PROTECTED_MODE_MESSAGE));
+            if let Err(e) = error.to_resp().serialize_resp(&mut stream) {
+                log::info!("failed to send protected mode error to {addr}: {e}");
+            }
+            return Ok(());
+        }
+        let workers = Arc::clone(&self.workers);
+        // Holding the lock until the client thread is registered means
+        // shutdown can't miss it.
+        let mut running = workers
+            .running
+            .lock()
+            .map_err(|_| eyre!("couldn't lock workers"))?;
+        let Some(command_sender) = running.command_sender.clone() else {
+            log::info!("refusing connection from {addr} while shutting down");
+            return Ok(());
+        };
+        if let Err(e) = configure_socket(stream.socket(), &self.config) {
+            log::warn!("failed to set socket options for {addr}: {e}");
+        }
 
-        // Create thread ID and channel for this client.
-        let (response_sender, response_receiver) =
-            crossbeam_channel::unbounded::<CommandResponse>();
+        // Create thread ID and outgoing message queue for this client.
+        let (outgoing_sender, outgoing_receiver) = crossbeam_channel::unbounded::<Outgoing>();
+        let (replied_sender, replied_receiver) = crossbeam_channel::unbounded::<()>();
         let thread_id = self.get_thread_id();
         {
             // New scope to ensure lock is released before we spawn the thread.
@@ -107,17 +679,57 @@ impl Server {
                 .map_err(|_| {
                     eyre!("lock was poisoned during a previous access and can no longer be locked")
                 })?
-                .insert(thread_id, response_sender);
+                .insert(thread_id, outgoing_sender.clone());
         }
 
+        // All writes to the connection go through a dedicated writer thread, so
+        // the core can push messages while the client thread waits on reads.
+        let mut writer = BufWriter::new(stream.try_clone()?);
+        let socket = stream.socket().try_clone()?;
+        let writer_thread = thread::spawn(move || {
+            if let Err(e) = write_outgoing(&outgoing_receiver, &mut writer, &replied_sender) {
+                log::error!("error in writer thread: {e}");
+            }
+        });
+
         let mut client_thread = ClientThread::new(
             thread_id,
-            addr.to_string(),
-            self.command_sender.clone(),
-            response_receiver,
+            addr,
+            self.config.databases,
+            self.config.limits,
+            self.config.enable_debug_command,
+            Arc::clone(&self.acl),
+            Arc::clone(&self.stats),
+            command_sender,
+            outgoing_sender,
+            replied_receiver,
             stream,
         );
-        thread::spawn(move || client_thread.run_loop());
+        let response_channels = self.response_channels.clone();
+        let client_workers = Arc::clone(&workers);
+        let thread = thread::spawn(move || {
+            client_thread.run_loop();
+            // Dropping the last sender stops the writer thread, which is
+            // waited for so that shutdown knows the replies were sent.
+            drop(client_thread);
+            response_channels
+                .lock()
+                .expect("couldn't lock response channels")
+                .remove(&thread_id);
+            if writer_thread.join().is_err() {
+                log::error!("writer thread panicked: [{thread_id}]");
+            }
+            client_workers
+                .running
+                .lock()
+                .expect("couldn't lock workers")
+                .clients
+                .remove(&thread_id);
+        });
+        running
+            .clients
+            .insert(thread_id, ClientWorker { socket, thread });
+        drop(running);
 
         Ok(())
     }
@@ -126,31 +738,83 @@ impl Server {
 #[derive(Debug)]
 struct ClientThread {
     thread_id: ThreadId,
-    client_addr: String,
-    command_sender: Sender<(ThreadId, Command)>,
-    response_receiver: Receiver<CommandResponse>,
-    writer: BufWriter<TcpStream>,
-    reader: BufReader<TcpStream>,
+    client_addr: SocketAddr,
+    num_databases: usize,
+    limits: Limits,
+
+    /// Whether the client may run `DEBUG`, which depends on where it
+    /// connected from.
+    debug_allowed: bool,
+
+    /// The database selected with `SELECT`, which is sent to the core with
+    /// every command.
+    db: DbIndex,
+
+    acl: Arc<Mutex<Users>>,
+
+    /// The user the client is authenticated as, whose permissions every
+    /// command is checked against.
+    user: String,
+
+    /// Whether the client can run commands. Clients that connect while the
+    /// default user has a password have to `AUTH` first.
+    authenticated: bool,
+
+    /// Where the commands the client thread answers or rejects itself are
+    /// recorded.
+    stats: Arc<Mutex<CommandStats>>,
+    command_sender: Sender<Request>,
+
+    /// Queue for replies the client thread produces itself, like parse errors.
+    outgoing: Sender<Outgoing>,
+
+    /// Signaled by the writer thread after each reply is written, so only one
+    /// command is in flight at a time.
+    replied: Receiver<()>,
+    reader: BufReader<tls::Stream>,
+
+    /// Whether the client is a replica that has sent `PSYNC`. Replicas get
+    /// the replication stream instead of replies.
+    replica: bool,
 }
 
 impl ClientThread {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         thread_id: ThreadId,
-        client_addr: String,
-        command_sender: Sender<(ThreadId, Command)>,
-        response_receiver: Receiver<CommandResponse>,
-        stream: TcpStream,
+        client_addr: SocketAddr,
+        num_databases: usize,
+        limits: Limits,
+        debug_access: DebugAccess,
+        acl: Arc<Mutex<Users>>,
+        stats: Arc<Mutex<CommandStats>>,
+        command_sender: Sender<Request>,
+        outgoing: Sender<Outgoing>,
+        replied: Receiver<()>,
+        stream: tls::Stream,
     ) -> Self {
-        let write_stream = stream.try_clone().expect("failed to clone stream");
-        let writer = BufWriter::new(write_stream);
         let reader = BufReader::new(stream);
+        let authenticated = acl
+            .lock()
+            .expect("couldn't lock ACL")
+            .get(DEFAULT_USER)
+            .is_some_and(|user| user.is_enabled() && user.nopass());
         Self {
             thread_id,
             client_addr,
+            num_databases,
+            limits,
+            debug_allowed: debug_access.allows(client_addr),
+            db: 0,
+            acl,
+            user: DEFAULT_USER.to_string(),
+            authenticated,
+            stats,
             command_sender,
-            response_receiver,
-            writer,
+            outgoing,
+            replied,
             reader,
+            replica: false,
         }
     }
 
@@ -162,113 +826,10336 @@ impl ClientThread {
     }
 
     fn loop_iteration(&mut self) -> Result<()> {
-        while let Some(response) = self.process_next_message() {
-            let response = response.to_resp();
-
-            log::info!("sending response: {response:?}");
-            response
-                .serialize_resp(&mut self.writer)
-                .expect("error in client thread");
-            self.writer.flush()?;
+        while self.process_next_message()? {
+            // Replicas only send acknowledgements, which get no reply.
+            if self.replica {
+                continue;
+            }
+            // Wait for the reply to be written before reading the next
+            // command, so replies stay in order even when the core parks the
+            // client.
+            self.replied
+                .recv()
+                .wrap_err("writer thread stopped before sending reply")?;
         }
 
         Ok(())
     }
 
-    fn process_next_message(&mut self) -> Option<CommandResponse> {
-        let message = match Message::parse_resp(&mut self.reader) {
+    /// Reads the next command and gets it answered, either here or by the
+    /// core. Returns `false` once the connection should be closed, either
+    /// because the client closed it or because it sent a malformed message.
+    fn process_next_message(&mut self) -> Result<bool> {
+        let message = match Message::parse_resp_with_limits(&mut self.reader, &self.limits) {
             Ok(Some(m)) => m,
             Ok(None) => {
-                return None;
+                return Ok(false);
             }
             Err(e) => {
-                return Some(CommandResponse::Error(format!(
-                    "error parsing message: {e}"
-                )));
+                // There's no telling where the next message starts, so give up
+                // on the connection like Redis does. The writer thread still
+                // sends the error before the socket is closed.
+                log::warn!("protocol error from {}: {e:?}", self.client_addr);
+                self.reply(CommandResponse::Error(ErrorReply::err(format!(
+                    "Protocol error: {}",
+                    e.root_cause()
+                ))))?;
+                return Ok(false);
             }
         };
-        log::info!("received message: {message:?}");
+        let command = Command::parse_resp(&message);
+        // Passwords are kept out of the logs.
+        if !matches!(
+            command,
+            Ok(Command::Auth(_) | Command::Hello(_) | Command::Acl(Acl::SetUser { .. }))
+        ) {
+            log::info!("received message: {message:?}");
+        }
 
-        let command = match Command::parse_resp(&message) {
+        let mut command = match command {
             Ok(c) => c,
             Err(e) => {
-                return Some(CommandResponse::Error(format!("error parsing RESP: {e}")));
+                self.reply(CommandResponse::Error(ErrorReply::err(e.to_string())))?;
+                return Ok(true);
             }
         };
         log::info!("parsed command: {command:?}");
+        let name = message_arg(&message, 0).unwrap_or_default();
+        let stat_name = stats::stat_name(&name, message_arg(&message, 1).as_deref());
+        let start = Instant::now();
+
+        // Authentication is per-connection state, so it's handled here before
+        // anything else.
+        if let Some(response) = self.authenticate(&mut command) {
+            if matches!(command, Command::Auth(_) | Command::Hello(_)) {
+                self.record_call(&stat_name, start, &response);
+            } else {
+                self.record_rejected(&stat_name);
+            }
+            self.reply(response)?;
+            return Ok(true);
+        }
+        // Like Redis, clients are disconnected when their user is deleted.
+        let Some(permitted) = self.check_permissions(&name, &command) else {
+            return Ok(false);
+        };
+        if let Err(error) = permitted {
+            self.record_rejected(&stat_name);
+            self.reply(CommandResponse::Error(error))?;
+            return Ok(true);
+        }
+        if matches!(command, Command::Debug(_)) && !self.debug_allowed {
+            self.record_rejected(&stat_name);
+            self.reply(CommandResponse::Error(ErrorReply::err(
+                DEBUG_DISABLED_MESSAGE,
+            )))?;
+            return Ok(true);
+        }
+
+        // The selected database is per-connection state, so SELECT is handled
+        // here instead of in the core.
+        if let Command::Select(Select { index }) = command {
+            let response = self.select(index);
+            self.record_call(&stat_name, start, &response);
+            self.reply(response)?;
+            return Ok(true);
+        }
+        // So is the user the client is authenticated as.
+        if command == Command::Acl(Acl::WhoAmI) {
+            let user = RedisString::from(self.user.as_str());
+            let response = CommandResponse::BulkString(Some(user));
+            self.record_call(&stat_name, start, &response);
+            self.reply(response)?;
+            return Ok(true);
+        }
+
+        if let Command::PSync(_) = command {
+            self.replica = true;
+        }
+        // Replicas are listed with the address they connected from, unless
+        // they announce a different one.
+        if let Command::ReplConf(ReplConf::Options(options)) = &mut command {
+            if !options.iter().any(|(name, _)| name == "ip-address") {
+                let ip = RedisString::from(self.client_addr.ip().to_string());
+                options.push(("ip-address".to_string(), ip));
+            }
+        }
 
-        // Send command off to core, and await the response.
+        // Send command off to core, which queues the response for the writer
+        // thread.
+        let request = Request {
+            client: self.thread_id,
+            db: self.db,
+            name: stat_name,
+            command,
+        };
         self.command_sender
-            .send((self.thread_id, command))
+            .send(request)
             .expect("failed to send command");
-        let response = self
-            .response_receiver
-            .recv()
-            .expect("failed to receive response");
 
-        Some(response)
+        Ok(true)
+    }
+
+    /// Records a command the client thread answered itself.
+    fn record_call(&self, name: &str, start: Instant, response: &CommandResponse) {
+        let failed = matches!(response, CommandResponse::Error(_));
+        self.stats
+            .lock()
+            .expect("couldn't lock stats")
+            .record_call(name, start.elapsed(), failed);
+    }
+
+    /// Records a command that was refused before it ran, because the client
+    /// isn't authenticated or its user isn't allowed to run it.
+    fn record_rejected(&self, name: &str) {
+        self.stats
+            .lock()
+            .expect("couldn't lock stats")
+            .record_rejected(name);
+    }
+
+    fn reply(&self, response: CommandResponse) -> Result<()> {
+        self.outgoing
+            .send(Outgoing::Reply(response))
+            .wrap_err("writer thread stopped")
+    }
+
+    /// Handles `AUTH`, and the authentication in `HELLO`, which is then
+    /// answered by the core. Returns the reply if the command shouldn't go to
+    /// the core, either because it was `AUTH` or because the client isn't
+    /// authenticated.
+    fn authenticate(&mut self, command: &mut Command) -> Option<CommandResponse> {
+        match command {
+            Command::Auth(auth) => Some(self.auth(auth)),
+            Command::Hello(Hello { protover, auth }) => {
+                // Only RESP2 is supported.
+                if protover.is_some_and(|protover| protover != 2) {
+                    return Some(CommandResponse::Error(ErrorReply::new(
+                        ErrorCode::NoProto,
+                        "unsupported protocol version",
+                    )));
+                }
+                if let Some(auth) = auth.take() {
+                    let response = self.auth(&auth);
+                    if response != CommandResponse::Ok {
+                        return Some(response);
+                    }
+                }
+                (!self.authenticated).then(|| {
+                    CommandResponse::Error(ErrorReply::new(
+                        ErrorCode::NoAuth,
+                        "HELLO must be called with the client already authenticated, \
+                         otherwise the HELLO <proto> AUTH <user> <pass> option can be used \
+                         to authenticate the client and select the RESP protocol version \
+                         at the same time",
+                    ))
+                })
+            }
+            _ if !self.authenticated => Some(CommandResponse::Error(ErrorReply::new(
+                ErrorCode::NoAuth,
+                "Authentication required.",
+            ))),
+            _ => None,
+        }
+    }
+
+    fn auth(&mut self, auth: &Auth) -> CommandResponse {
+        let username = auth.username.as_ref().map_or_else(
+            || DEFAULT_USER.to_string(),
+            |username| String::from_utf8_lossy(username.as_bytes()).into_owned(),
+        );
+        let mut acl = self.acl.lock().expect("couldn't lock ACL");
+        let user = acl.get(&username);
+        // Like Redis, a default user without a password accepts any password,
+        // but AUTH with only a password is probably a mistake then.
+        if auth.username.is_none() && user.is_some_and(User::nopass) {
+            return CommandResponse::Error(ErrorReply::err(
+                "AUTH <password> called without any password configured for the default user. \
+                 Are you sure your configuration is correct?",
+            ));
+        }
+        if user.is_some_and(|user| user.authenticates(auth.password.as_bytes())) {
+            drop(acl);
+            self.user = username;
+            self.authenticated = true;
+            return CommandResponse::Ok;
+        }
+        let denial = Denial {
+            reason: DenialReason::Auth,
+            object: "AUTH".to_string(),
+            username,
+            client_info: self.client_info(),
+        };
+        acl.log_denial(denial, unix_time_millis());
+        drop(acl);
+        CommandResponse::Error(ErrorReply::new(
+            ErrorCode::WrongPass,
+            "invalid username-password pair or user is disabled.",
+        ))
+    }
+
+    /// Describes the client for the `ACL LOG`.
+    fn client_info(&self) -> String {
+        format!(
+            "id={} addr={} db={} user={}",
+            self.thread_id, self.client_addr, self.db, self.user
+        )
+    }
+
+    /// Checks that the client's user may run the command, which is named
+    /// `name`, on the keys and channels it uses. Returns the `NOPERM` error if
+    /// not, after logging the denial, or `None` if the user no longer exists.
+    fn check_permissions(
+        &self,
+        name: &str,
+        command: &Command,
+    ) -> Option<std::result::Result<(), ErrorReply>> {
+        let mut acl = self.acl.lock().expect("couldn't lock ACL");
+        let Some((reason, object)) = find_denial(acl.get(&self.user)?, name, command) else {
+            return Some(Ok(()));
+        };
+        let denial = Denial {
+            reason,
+            object,
+            username: self.user.clone(),
+            client_info: self.client_info(),
+        };
+        acl.log_denial(denial, unix_time_millis());
+        drop(acl);
+
+        let message = match reason {
+            DenialReason::Command => format!(
+                "User {} has no permissions to run the '{name}' command",
+                self.user
+            ),
+            DenialReason::Key => "No permissions to access a key".to_string(),
+            DenialReason::Channel => "No permissions to access a channel".to_string(),
+            DenialReason::Auth => unreachable!("AUTH is checked by auth"),
+        };
+        Some(Err(ErrorReply::new(ErrorCode::NoPerm, message)))
+    }
+
+    fn select(&mut self, index: i64) -> CommandResponse {
+        match usize::try_from(index) {
+            Ok(index) if index < self.num_databases => {
+                self.db = index;
+                CommandResponse::Ok
+            }
+            _ => CommandResponse::Error(ErrorReply::err("DB index is out of range")),
+        }
+    }
+}
+
+/// The argument at `index` of a command message, lower-cased, like its name.
+fn message_arg(message: &Message, index: usize) -> Option<String> {
+    let Message::Array(elems) = message else {
+        return None;
+    };
+    match elems.get(index)? {
+        Message::BulkString(Some(arg)) => {
+            Some(String::from_utf8_lossy(arg.as_bytes()).to_lowercase())
+        }
+        Message::SimpleString(arg) => Some(arg.to_lowercase()),
+        _ => None,
+    }
+}
+
+/// Finds what `user` may not do in `command`, which is named `name`: run the
+/// command at all, or access one of its keys or channels. Returns why, and the
+/// command, key or channel that was denied.
+fn find_denial(user: &User, name: &str, command: &Command) -> Option<(DenialReason, String)> {
+    // Like Redis, HELLO can always be run, since it authenticates. Unknown
+    // commands get their usual error from the core.
+    let exempt = matches!(command, Command::Hello(_) | Command::RawCommand(_));
+    if !exempt && !user.can_run(name) {
+        return Some((DenialReason::Command, name.to_string()));
+    }
+
+    let denied_key = match key_access(command) {
+        KeyAccess::Read(keys) => keys
+            .into_iter()
+            .find(|key| !user.can_access_key(key.as_bytes(), false)),
+        KeyAccess::Write(keys) => keys
+            .into_iter()
+            .find(|key| !user.can_access_key(key.as_bytes(), true)),
+        KeyAccess::WriteAll => None,
+    };
+    if let Some(key) = denied_key {
+        let key = String::from_utf8_lossy(key.as_bytes()).into_owned();
+        return Some((DenialReason::Key, key));
+    }
+
+    let denied_channel = match command {
+        Command::Subscribe(Subscribe { channels }) => channels
+            .iter()
+            .find(|channel| !user.can_access_channel(channel.as_bytes(), false)),
+        Command::Publish(Publish { channel, .. }) => {
+            Some(channel).filter(|channel| !user.can_access_channel(channel.as_bytes(), false))
+        }
+        Command::PSubscribe(PSubscribe { patterns }) => patterns
+            .iter()
+            .find(|pattern| !user.can_access_channel(pattern.as_bytes(), true)),
+        _ => None,
+    };
+    denied_channel.map(|channel| {
+        let channel = String::from_utf8_lossy(channel.as_bytes()).into_owned();
+        (DenialReason::Channel, channel)
+    })
+}
+
+/// Listens on `addr` with room for `backlog` connections waiting to be
+/// accepted, which `TcpListener::bind` doesn't let callers choose.
+fn listen(addr: impl ToSocketAddrs, backlog: u32) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        // Like `TcpListener::bind`, so the server can restart while
+        // connections from before are still in TIME_WAIT.
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        let listening = socket
+            .bind(&addr.into())
+            .and_then(|()| socket.listen(i32::try_from(backlog).unwrap_or(i32::MAX)));
+        match listening {
+            Ok(()) => return Ok(socket.into()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+/// Applies the `tcp-nodelay` and `tcp-keepalive` settings to a client's
+/// connection.
+fn configure_socket(socket: &TcpStream, config: &ServerConfig) -> io::Result<()> {
+    socket.set_nodelay(config.tcp_nodelay)?;
+    if let Some(interval) = config.tcp_keepalive {
+        SockRef::from(socket).set_tcp_keepalive(&TcpKeepalive::new().with_time(interval))?;
+    }
+    Ok(())
+}
+
+/// Accepts connections on `listener` in the background, wrapping them in TLS
+/// if there's an `acceptor`, and sends them to the main thread.
+fn accept_connections(
+    listener: TcpListener,
+    acceptor: Option<Acceptor>,
+    connections: Sender<Result<tls::Stream>>,
+    stopping: Receiver<()>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if stopping.try_recv() == Err(TryRecvError::Disconnected) {
+                break;
+            }
+            let stream = stream
+                .map_err(Into::into)
+                .and_then(|stream| match &acceptor {
+                    Some(acceptor) => acceptor.accept(stream),
+                    None => Ok(tls::Stream::Tcp(stream)),
+                });
+            // The main thread only stops listening when it fails.
+            if connections.send(stream).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Connects to a listener so its accept thread wakes up.
+fn wake_listener(addr: SocketAddr) {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    if let Err(e) = TcpStream::connect((ip, addr.port())) {
+        log::warn!("failed to wake listener on {addr}: {e}");
+    }
+}
+
+/// Writes a client's outgoing messages to its connection in the order they
+/// were queued, signaling `replied` after each reply. Returns once every
+/// sender for the queue has been dropped.
+fn write_outgoing<W>(
+    outgoing: &Receiver<Outgoing>,
+    writer: &mut W,
+    replied: &Sender<()>,
+) -> Result<()>
+where
+    W: Write,
+{
+    while let Ok(message) = outgoing.recv() {
+        let (response, is_reply) = match message {
+            Outgoing::Reply(response) => (response, true),
+            Outgoing::Push(items) => (CommandResponse::Push(items), false),
+            Outgoing::Snapshot(snapshot) => {
+                let snapshot = snapshot
+                    .recv()
+                    .wrap_err("snapshot thread stopped")?
+                    .wrap_err("failed to create snapshot for replica")?;
+                log::info!("sending {} byte snapshot to replica", snapshot.len());
+                write!(writer, "${}\r\n", snapshot.len())?;
+                writer.write_all(&snapshot)?;
+                writer.flush()?;
+                continue;
+            }
+            Outgoing::Replication(stream) => {
+                writer.write_all(&stream)?;
+                writer.flush()?;
+                continue;
+            }
+        };
+        // Connections only speak RESP2, which has no push type, so like
+        // Redis, pushes like pub/sub messages are sent as arrays.
+        let message = match response {
+            CommandResponse::Push(items) => CommandResponse::Array(items),
+            response => response,
+        }
+        .to_resp();
+        log::info!("sending message: {message:?}");
+        message.serialize_resp(writer)?;
+        writer.flush()?;
+        if is_reply {
+            // The client thread is gone if nobody is listening, and the
+            // queue will be closed shortly.
+            let _ = replied.send(());
+        }
     }
+    Ok(())
 }
 
 /// A `ServerCore` is primary command processor of the redis-clone server. It
 /// contains the key-value store and the logic for handling commands.
 #[derive(Debug)]
 struct ServerCore {
-    key_value: HashMap<RedisString, RedisString>,
+    dbs: Vec<Db>,
+
+    /// Frees values removed by UNLINK and FLUSHALL ASYNC in the background.
+    lazy_free: LazyFree,
+
+    /// Clients parked by blocking commands like `BLPOP`.
+    blocked: BlockedClients,
+
+    /// Keys that blocked clients are waiting on and that may now be ready, in
+    /// the order they were signaled.
+    ready_keys: VecDeque<(DbIndex, RedisString)>,
+
+    /// The pause set by `CLIENT PAUSE`, if clients are paused.
+    pause: Option<Pause>,
+
+    /// Commands held back by the pause, in the order they arrived. They run
+    /// once it ends.
+    paused_commands: VecDeque<Request>,
+
+    /// Whether `cron` removes expired keys, which `DEBUG SET-ACTIVE-EXPIRE`
+    /// turns off.
+    active_expire: bool,
+
+    /// The database active expiration starts with next, so that one with
+    /// lots of expired keys doesn't use up every run's time.
+    expire_db: DbIndex,
+
+    /// Whether the server is shutting down. Blocked and paused clients are
+    /// let go so their threads can stop.
+    shutting_down: bool,
+
+    /// Used by commands that pick random elements.
+    rng: Rng,
+
+    /// Clients with client-side caching enabled by `CLIENT TRACKING`.
+    tracking: Tracking,
+
+    /// Out-of-band messages to push to clients once the current command has
+    /// been processed.
+    pushes: Vec<(ThreadId, Vec<CommandResponse>)>,
+
+    /// Channels and patterns clients are subscribed to with `SUBSCRIBE` and
+    /// `PSUBSCRIBE`.
+    pubsub: PubSub,
+
+    /// Scripts cached by `SCRIPT LOAD`, run with `EVALSHA`.
+    scripts: ScriptCache,
+
+    /// Functions registered by the crate embedding the server.
+    functions: Functions,
+
+    /// Which node serves each hash slot, if cluster mode is enabled.
+    cluster: Option<ClusterState>,
+
+    /// Clients that sent `ASKING`, whose next command may use a slot this
+    /// node is importing.
+    asking: HashSet<ThreadId>,
+
+    /// Clients that sent `READONLY`, whose reads are served by this node
+    /// when it's a replica instead of being redirected to its master.
+    readonly: HashSet<ThreadId>,
+
+    /// The server's settings, which `CONFIG SET` changes at runtime.
+    config: ServerConfig,
+
+    /// The users, shared with the client threads that check permissions.
+    acl: Arc<Mutex<Users>>,
+
+    /// Whether protected mode is on, shared with the main thread that accepts
+    /// connections.
+    protected_mode: Arc<AtomicBool>,
+
+    /// How many keys were evicted to stay under `maxmemory`.
+    evicted_keys: u64,
+
+    /// Per-command statistics, shared with the client threads.
+    stats: Arc<Mutex<CommandStats>>,
+
+    /// Latency spikes, recorded when `latency-monitor-threshold` is set.
+    latency: LatencyMonitor,
+
+    /// Snapshots written by `SAVE` and `BGSAVE`.
+    snapshots: Snapshots,
+
+    /// The append-only file that write commands are logged to, if enabled.
+    aof: Option<Aof>,
+
+    /// Replicas of this server and the stream of writes sent to them.
+    replication: Replication,
+
+    /// What to send replicas right after the reply to their `PSYNC`: either a
+    /// snapshot being created for a full sync, or the part of the stream they
+    /// missed.
+    syncs: Vec<(ThreadId, Outgoing)>,
+
+    /// The master this server replicates, set with `REPLICAOF`.
+    master: Option<MasterLink>,
+
+    /// The port clients connect to, which replicas report to their master.
+    port: u16,
 }
 
 impl ServerCore {
-    fn new() -> Self {
+    fn new(num_databases: usize) -> Self {
+        let mut rng = Rng::new();
         Self {
-            key_value: HashMap::new(),
+            dbs: (0..num_databases).map(|_| Db::default()).collect(),
+            lazy_free: LazyFree::start(),
+            blocked: BlockedClients::default(),
+            ready_keys: VecDeque::new(),
+            pause: None,
+            paused_commands: VecDeque::new(),
+            active_expire: true,
+            expire_db: 0,
+            shutting_down: false,
+            replication: Replication::new(&mut rng),
+            rng,
+            tracking: Tracking::default(),
+            pushes: Vec::new(),
+            pubsub: PubSub::default(),
+            scripts: ScriptCache::default(),
+            functions: Functions::default(),
+            cluster: None,
+            asking: HashSet::new(),
+            readonly: HashSet::new(),
+            config: ServerConfig {
+                databases: num_databases,
+                ..ServerConfig::default()
+            },
+            acl: Arc::default(),
+            protected_mode: Arc::default(),
+            evicted_keys: 0,
+            stats: Arc::default(),
+            latency: LatencyMonitor::default(),
+            snapshots: Snapshots::new(DEFAULT_SNAPSHOT_PATH),
+            aof: None,
+            syncs: Vec::new(),
+            master: None,
+            port: 6379,
         }
     }
 
-    fn process_command(&mut self, command: Command) -> CommandResponse {
-        match command {
-            Command::Ping => CommandResponse::Pong,
-            Command::Get(Get { key }) => {
-                let value = self.key_value.get(&key);
-                CommandResponse::BulkString(value.cloned())
-            }
-            Command::Set(Set { key, value }) => {
-                self.key_value.insert(key, value);
-                CommandResponse::Ok
+    /// Loads the dataset by replaying the AOF. Returns
+    /// `false` if there is no AOF yet.
+    fn load_aof(&mut self, config: &AofConfig) -> Result<bool> {
+        let num_databases = self.dbs.len();
+        aof::replay(config, num_databases, |record| match record {
+            aof::Record::Snapshot(dbs) => self.dbs = dbs,
+            aof::Record::Command(db, command) => {
+                self.process_command(db, command);
             }
-            Command::RawCommand(c) => CommandResponse::Error(format!("unknown command: {c:?}")),
-        }
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Processes a command from a client thread like `process_client_command`,
+    /// recording how long it took.
+    fn process_request(&mut self, request: Request) -> Option<CommandResponse> {
+        let start = Instant::now();
+        let response = self.process_client_command(request.client, request.db, request.command);
+        // Blocked commands are counted when they're called, not when they're
+        // served.
+        let failed = matches!(response, Some(CommandResponse::Error(_)));
+        let duration = start.elapsed();
+        self.stats.lock().expect("couldn't lock stats").record_call(
+            &request.name,
+            duration,
+            failed,
+        );
+        self.record_latency(latency::Event::Command, duration);
+        response
+    }
 
-    #[test]
-    fn test_ping() {
-        let mut core = ServerCore::new();
-        let response = core.process_command(Command::Ping);
-        assert_eq!(response, CommandResponse::Pong);
+    /// Records an event with the latency monitor if it took at least
+    /// `latency-monitor-threshold`.
+    fn record_latency(&mut self, event: latency::Event, duration: Duration) {
+        if self
+            .config
+            .latency_monitor_threshold
+            .is_some_and(|threshold| duration >= threshold)
+        {
+            self.latency.record(event, duration);
+        }
     }
 
-    #[test]
-    fn test_set_get() {
-        let mut core = ServerCore::new();
+    /// Processes a command from a client. Returns `None` if the command
+    /// blocked the client, in which case its response comes later from
+    /// `unblock_clients`, or if the command gets no reply, like `REPLCONF
+    /// ACK`.
+    #[allow(clippy::too_many_lines)]
+    fn process_client_command(
+        &mut self,
+        client: ThreadId,
+        db: DbIndex,
+        command: Command,
+    ) -> Option<CommandResponse> {
+        // ASKING only applies to the command right after it.
+        let asking = self.asking.remove(&client);
 
-        let set_command = Command::Set(Set {
-            key: RedisString::from("key"),
-            value: RedisString::from("value"),
-        });
-        let response = core.process_command(set_command);
+        // Tracking is per-connection state, so it's handled here where the
+        // client is known. Pausing is handled with it, since the worker
+        // thread checks the pause before running commands.
+        match command {
+            Command::Client(Client::Tracking(tracking)) => {
+                return Some(self.client_tracking(client, tracking));
+            }
+            Command::Client(Client::Pause(pause)) => {
+                self.pause_clients(&pause);
+                return Some(CommandResponse::Ok);
+            }
+            Command::Client(Client::Unpause) => {
+                self.pause = None;
+                return Some(CommandResponse::Ok);
+            }
+            _ => {}
+        }
+
+        // HELLO reports the client's ID, so it's handled here too.
+        if let Command::Hello(_) = command {
+            return Some(self.hello(client));
+        }
+
+        // So is replication, since the connection becomes a replication link.
+        match command {
+            Command::ReplConf(ReplConf::Options(options)) => {
+                return Some(match self.replication.configure(client, &options) {
+                    Ok(()) => CommandResponse::Ok,
+                    Err(e) => CommandResponse::Error(e),
+                });
+            }
+            Command::ReplConf(ReplConf::Ack(offset)) => {
+                self.replication.ack(client, offset);
+                return None;
+            }
+            Command::PSync(psync) => return Some(self.psync(client, &psync)),
+            Command::Wait(wait) => return self.wait(client, wait),
+            _ => {}
+        }
+
+        // And so are subscriptions.
+        match command {
+            Command::Subscribe(Subscribe { channels }) => {
+                return Some(self.subscribe(client, "subscribe", channels, PubSub::subscribe));
+            }
+            Command::Unsubscribe(Unsubscribe { mut channels }) => {
+                if channels.is_empty() {
+                    channels = self.pubsub.channels(client);
+                }
+                return Some(self.subscribe(client, "unsubscribe", channels, PubSub::unsubscribe));
+            }
+            Command::PSubscribe(PSubscribe { patterns }) => {
+                return Some(self.subscribe(client, "psubscribe", patterns, PubSub::psubscribe));
+            }
+            Command::PUnsubscribe(PUnsubscribe { mut patterns }) => {
+                if patterns.is_empty() {
+                    patterns = self.pubsub.patterns(client);
+                }
+                return Some(self.subscribe(
+                    client,
+                    "punsubscribe",
+                    patterns,
+                    PubSub::punsubscribe,
+                ));
+            }
+            // Subscribed clients get their replies mixed in with messages,
+            // so like Redis, PING replies with an array a client can tell
+            // apart from `PONG` sent as a message.
+            Command::Ping if self.pubsub.count(client) > 0 => {
+                return Some(CommandResponse::Array(vec![
+                    CommandResponse::BulkString(Some(RedisString::from("pong"))),
+                    CommandResponse::BulkString(Some(RedisString::from(""))),
+                ]));
+            }
+            _ => {}
+        }
+
+        // As are the cluster flags.
+        if matches!(
+            command,
+            Command::Asking | Command::ReadOnly | Command::ReadWrite
+        ) {
+            return Some(self.set_cluster_flag(client, &command));
+        }
+
+        if let Some(redirect) = self.cluster_redirect(client, db, &command, asking) {
+            return Some(CommandResponse::Error(redirect));
+        }
+
+        // Replicas only take writes from their master, which don't come
+        // through here.
+        if self.master.is_some() && self.may_write(&command) {
+            return Some(CommandResponse::Error(ErrorReply::new(
+                ErrorCode::ReadOnly,
+                "You can't write against a read only replica.",
+            )));
+        }
+
+        if !self.evict_keys() && may_use_memory(&command) {
+            return Some(CommandResponse::Error(ErrorReply::new(
+                ErrorCode::Oom,
+                "command not allowed when used memory > 'maxmemory'.",
+            )));
+        }
+
+        let mut command = command;
+        if let Command::XRead(xread) = &mut command {
+            // Pin down `$` now, so that the client only gets entries added
+            // while it's blocked.
+            for (key, id) in xread.keys.iter().zip(&mut xread.ids) {
+                if *id == ReadId::Last {
+                    let last_id = match self.dbs[db].get_stream(key) {
+                        Ok(Some(stream)) => stream.last_id(),
+                        _ => StreamId::MIN,
+                    };
+                    *id = ReadId::After(last_id);
+                }
+            }
+        }
+
+        let blocking = blocking_keys(&command).map(|(keys, timeout)| (keys.to_vec(), timeout));
+        if let Some((keys, timeout)) = blocking {
+            // Only block if none of the keys are ready. Otherwise the command
+            // can be served (or fail with WRONGTYPE) right away.
+            if !keys
+                .iter()
+                .any(|key| key_ready(&mut self.dbs[db], key, &command))
+            {
+                self.blocked.block(BlockedClient {
+                    client,
+                    db,
+                    keys,
+                    command,
+                    deadline: (!timeout.is_zero()).then(|| Instant::now() + timeout),
+                    replication_offset: None,
+                });
+                return None;
+            }
+        }
+        Some(self.process_tracked_command(client, db, command))
+    }
+
+    /// Pauses clients, or extends the current pause. Like Redis, a pause only
+    /// ever gets longer and more restrictive until it ends.
+    fn pause_clients(&mut self, pause: &ClientPause) {
+        let until = Instant::now() + pause.timeout;
+        let (mode, until) = self.pause.as_ref().map_or((pause.mode, until), |current| {
+            (current.mode.max(pause.mode), current.until.max(until))
+        });
+        self.pause = Some(Pause { mode, until });
+    }
+
+    /// Whether `command` has to wait for the pause to end.
+    fn is_paused(&self, command: &Command) -> bool {
+        let Some(pause) = self.pause.as_ref().filter(|_| !self.shutting_down) else {
+            return false;
+        };
+        // Unpausing has to get through, and replicas keep acknowledging the
+        // replication stream.
+        if matches!(
+            command,
+            Command::Client(Client::Pause(_) | Client::Unpause)
+                | Command::ReplConf(_)
+                | Command::PSync(_)
+        ) {
+            return false;
+        }
+        match pause.mode {
+            PauseMode::All => true,
+            PauseMode::Write => !matches!(key_access(command), KeyAccess::Read(_)),
+        }
+    }
+
+    /// Gets the dataset to disk before the server exits, like Redis does on
+    /// `SHUTDOWN`. Background saves and rewrites are waited for, and then the
+    /// AOF is synced, or without one, a snapshot is saved.
+    fn flush_persistence(&mut self) -> Result<()> {
+        self.snapshots.wait();
+        if let Some(aof) = self.aof.take() {
+            aof.close();
+            return Ok(());
+        }
+        self.snapshots
+            .save(&self.dbs)
+            .wrap_err("failed to save snapshot on shutdown")
+    }
+
+    /// Takes the first paused command that can run now, if any.
+    fn next_unpaused_command(&mut self) -> Option<Request> {
+        let index = self
+            .paused_commands
+            .iter()
+            .position(|request| !self.is_paused(&request.command))?;
+        self.paused_commands.remove(index)
+    }
+
+    fn set_maxmemory(&mut self, config: MaxMemoryConfig) {
+        // Databases only count accesses when they're used for eviction.
+        let lfu = config.policy.is_lfu().then_some(config.lfu);
+        for db in &mut self.dbs {
+            db.lfu = lfu;
+        }
+        self.config.maxmemory = config;
+    }
+
+    /// Evicts keys until the keyspace fits in `maxmemory`, if it's set.
+    /// Returns whether it fits.
+    fn evict_keys(&mut self) -> bool {
+        // Replicas leave eviction to their master, and delete the keys it
+        // evicts when they get its `DEL`s.
+        if self.config.maxmemory.limit == 0 || self.master.is_some() {
+            return true;
+        }
+        while self.used_memory() > self.config.maxmemory.limit {
+            let Some((db, key)) =
+                evict::pick_victim(&self.dbs, &self.config.maxmemory, &mut self.rng)
+            else {
+                return false;
+            };
+            self.dbs[db].remove(&key);
+            log::info!("evicted key {key:?} from db {db}");
+            self.evicted_keys += 1;
+            self.invalidate(std::slice::from_ref(&key));
+            self.propagate(db, &Command::Del(Del { keys: vec![key] }));
+        }
+        true
+    }
+
+    fn config(&mut self, config: Config) -> CommandResponse {
+        match config {
+            Config::Get { patterns } => CommandResponse::Array(
+                self.config
+                    .get(&patterns)
+                    .into_iter()
+                    .flat_map(|(name, value)| {
+                        [
+                            CommandResponse::BulkString(Some(RedisString::from(name))),
+                            CommandResponse::BulkString(Some(RedisString::from(value))),
+                        ]
+                    })
+                    .collect(),
+            ),
+            Config::Set { pairs } => {
+                let previous = self.config.clone();
+                if let Err(e) = self.config.set(&pairs) {
+                    return CommandResponse::Error(ErrorReply::err(e));
+                }
+                match self.apply_config(&previous) {
+                    Ok(()) => CommandResponse::Ok,
+                    Err(e) => {
+                        self.config = previous;
+                        CommandResponse::Error(ErrorReply::err(format!("CONFIG SET failed - {e}")))
+                    }
+                }
+            }
+            Config::Rewrite if self.config.config_file.is_none() => CommandResponse::Error(
+                ErrorReply::err("The server is running without a config file"),
+            ),
+            Config::Rewrite => match self.config.rewrite() {
+                Ok(()) => CommandResponse::Ok,
+                Err(e) => {
+                    CommandResponse::Error(ErrorReply::err(format!("Rewriting config file: {e}")))
+                }
+            },
+            Config::ResetStat => {
+                self.stats.lock().expect("couldn't lock stats").reset();
+                self.evicted_keys = 0;
+                CommandResponse::Ok
+            }
+        }
+    }
+
+    /// Puts settings changed by `CONFIG SET` into effect. Changes that fail to
+    /// apply leave the server as it was.
+    fn apply_config(&mut self, previous: &ServerConfig) -> Result<()> {
+        let config = self.config.clone();
+        if config.appendonly != previous.appendonly {
+            if config.appendonly {
+                // Like Redis, turning the AOF on rewrites it from the dataset,
+                // since whatever is there is out of date.
+                let mut aof = Aof::open(&config.aof)?;
+                aof.start_rewrite(&self.dbs)?;
+                self.aof = Some(aof);
+            } else if let Some(mut aof) = self.aof.take() {
+                aof.wait_for_rewrite();
+            }
+        } else if config.aof != previous.aof {
+            if let Some(aof) = &mut self.aof {
+                aof.set_config(&config.aof)?;
+            }
+        }
+        if config.snapshot_path != previous.snapshot_path {
+            self.snapshots.set_path(config.snapshot_path.clone());
+        }
+        if config.requirepass != previous.requirepass {
+            // Like Redis, clients that already authenticated stay that way.
+            self.acl
+                .lock()
+                .expect("couldn't lock ACL")
+                .set_requirepass(config.requirepass.as_deref());
+        }
+        if config.protected_mode != previous.protected_mode {
+            self.protected_mode
+                .store(config.protected_mode, Ordering::Relaxed);
+        }
+        if config.latency_tracking != previous.latency_tracking {
+            self.stats
+                .lock()
+                .expect("couldn't lock stats")
+                .set_latency_tracking(config.latency_tracking);
+        }
+        if config.acllog_max_len != previous.acllog_max_len {
+            self.acl
+                .lock()
+                .expect("couldn't lock ACL")
+                .set_log_max_len(config.acllog_max_len);
+        }
+        if config.maxmemory != previous.maxmemory {
+            self.set_maxmemory(config.maxmemory);
+            // Like Redis, lowering the limit evicts keys right away.
+            self.evict_keys();
+        }
+        Ok(())
+    }
+
+    fn acl(&self, acl: Acl) -> CommandResponse {
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let mut users = self.acl.lock().expect("couldn't lock ACL");
+        match acl {
+            Acl::SetUser { username, rules } => match users.set_user(&username, &rules) {
+                Ok(()) => CommandResponse::Ok,
+                Err(e) => CommandResponse::Error(ErrorReply::err(e)),
+            },
+            Acl::GetUser { username } => {
+                let Some(user) = users.get(&username) else {
+                    return CommandResponse::NullArray;
+                };
+                let description = user.describe();
+                info_map(vec![
+                    (
+                        "flags",
+                        CommandResponse::Array(description.flags.iter().map(|f| bulk(f)).collect()),
+                    ),
+                    (
+                        "passwords",
+                        CommandResponse::Array(
+                            description.passwords.iter().map(|p| bulk(p)).collect(),
+                        ),
+                    ),
+                    ("commands", bulk(&description.commands)),
+                    ("keys", bulk(&description.keys)),
+                    ("channels", bulk(&description.channels)),
+                    ("selectors", CommandResponse::Array(Vec::new())),
+                ])
+            }
+            Acl::DelUser { usernames } => match users.delete(&usernames) {
+                Ok(deleted) => CommandResponse::Integer(len_to_i64(deleted)),
+                Err(e) => CommandResponse::Error(ErrorReply::err(e)),
+            },
+            Acl::List => {
+                CommandResponse::Array(users.list().iter().map(|line| bulk(line)).collect())
+            }
+            Acl::WhoAmI => unreachable!("ACL WHOAMI is handled by the client thread"),
+            Acl::Cat { category: None } => {
+                CommandResponse::Array(Category::ALL.into_iter().map(|c| bulk(c.name())).collect())
+            }
+            Acl::Cat {
+                category: Some(name),
+            } => {
+                let Some(category) = Category::parse(&name) else {
+                    return CommandResponse::Error(ErrorReply::err(format!(
+                        "Unknown category '{name}'"
+                    )));
+                };
+                CommandResponse::Array(
+                    acl::category_commands(category)
+                        .into_iter()
+                        .map(bulk)
+                        .collect(),
+                )
+            }
+            Acl::Log { count } => acl_log(&users, count),
+            Acl::LogReset => {
+                users.reset_log();
+                CommandResponse::Ok
+            }
+            Acl::Load | Acl::Save => {
+                let Some(path) = &self.config.aclfile else {
+                    return CommandResponse::Error(ErrorReply::err(
+                        "This Redis instance is not configured to use an ACL file. You may want \
+                         to specify users via the ACL SETUSER command and then issue a CONFIG \
+                         REWRITE (assuming you have a Redis configuration file set) in order to \
+                         store users in the Redis configuration.",
+                    ));
+                };
+                let result = if acl == Acl::Load {
+                    users.load(path)
+                } else {
+                    users.save(path)
+                };
+                match result {
+                    Ok(()) => CommandResponse::Ok,
+                    Err(e) => CommandResponse::Error(ErrorReply::err(format!("{e:#}"))),
+                }
+            }
+        }
+    }
+
+    /// Describes the server to a client, after the client thread has handled
+    /// any authentication.
+    fn hello(&self, client: ThreadId) -> CommandResponse {
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let mode = if self.cluster.is_some() {
+            "cluster"
+        } else {
+            "standalone"
+        };
+        let role = if self.master.is_some() {
+            "replica"
+        } else {
+            "master"
+        };
+        info_map(vec![
+            ("server", bulk("redis")),
+            ("version", bulk(REDIS_VERSION)),
+            ("proto", CommandResponse::Integer(2)),
+            ("id", CommandResponse::Integer(len_to_i64(client))),
+            ("mode", bulk(mode)),
+            ("role", bulk(role)),
+            ("modules", CommandResponse::Array(Vec::new())),
+        ])
+    }
+
+    fn memory(&mut self, db: DbIndex, memory: &Memory) -> CommandResponse {
+        // Like Redis, `MEMORY USAGE` samples collections by default.
+        const DEFAULT_USAGE_SAMPLES: usize = 5;
+
+        match memory {
+            Memory::Usage { key, samples } => {
+                let samples = samples.unwrap_or(DEFAULT_USAGE_SAMPLES);
+                self.dbs[db]
+                    .peek_entry(key)
+                    .map_or(CommandResponse::BulkString(None), |entry| {
+                        // Measuring every element is what the cached size
+                        // already did.
+                        let size = if samples == 0 {
+                            entry.mem_size()
+                        } else {
+                            entry.sampled_size(key, samples)
+                        };
+                        CommandResponse::Integer(len_to_i64(size))
+                    })
+            }
+            Memory::Stats => self.memory_stats(),
+            Memory::Doctor => {
+                CommandResponse::BulkString(Some(RedisString::from(self.memory_doctor())))
+            }
+        }
+    }
+
+    fn latency(&mut self, latency: &Latency) -> CommandResponse {
+        let integer = |n: u64| CommandResponse::Integer(i64::try_from(n).unwrap_or(i64::MAX));
+        // Like Redis, unknown events are ignored.
+        let events = |names: &[String]| -> Vec<latency::Event> {
+            names
+                .iter()
+                .filter_map(|name| latency::Event::parse(name))
+                .collect()
+        };
+        match latency {
+            Latency::Latest => CommandResponse::Array(
+                self.latency
+                    .latest()
+                    .into_iter()
+                    .map(|(event, latest, max)| {
+                        CommandResponse::Array(vec![
+                            CommandResponse::BulkString(Some(RedisString::from(event.name()))),
+                            CommandResponse::Integer(latest.time),
+                            integer(latest.latency),
+                            integer(max),
+                        ])
+                    })
+                    .collect(),
+            ),
+            Latency::History { event } => CommandResponse::Array(
+                events(std::slice::from_ref(event))
+                    .into_iter()
+                    .flat_map(|event| self.latency.history(event))
+                    .map(|sample| {
+                        CommandResponse::Array(vec![
+                            CommandResponse::Integer(sample.time),
+                            integer(sample.latency),
+                        ])
+                    })
+                    .collect(),
+            ),
+            Latency::Reset { events: names } => {
+                // Resetting only named events that are all unknown resets
+                // nothing, rather than everything.
+                let events = events(names);
+                let reset = if !names.is_empty() && events.is_empty() {
+                    0
+                } else {
+                    self.latency.reset(&events)
+                };
+                CommandResponse::Integer(len_to_i64(reset))
+            }
+            Latency::Doctor => {
+                let enabled = self.config.latency_monitor_threshold.is_some();
+                CommandResponse::BulkString(Some(RedisString::from(self.latency.doctor(enabled))))
+            }
+        }
+    }
+
+    /// The reply to `MEMORY STATS`, with the fields of Redis' that apply to
+    /// this server.
+    #[allow(clippy::cast_precision_loss)]
+    fn memory_stats(&self) -> CommandResponse {
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let integer = |n: usize| CommandResponse::Integer(len_to_i64(n));
+
+        let backlog = self.replication.backlog_len().unwrap_or(0);
+        let scripts = self.scripts.approximate_size();
+        let mut overhead = backlog + scripts;
+        let mut keys = 0;
+        let mut db_stats = Vec::new();
+        for (index, db) in self.dbs.iter().enumerate() {
+            if db.entries().is_empty() {
+                continue;
+            }
+            let db_overhead = db.entries().len() * ENTRY_OVERHEAD;
+            overhead += db_overhead;
+            keys += db.entries().len();
+            db_stats.push(bulk(&format!("db.{index}")));
+            db_stats.push(CommandResponse::Array(vec![
+                bulk("overhead.hashtable.main"),
+                integer(db_overhead),
+            ]));
+        }
+        let total = self.used_memory() + backlog + scripts;
+        let dataset = total - overhead;
+
+        let mut stats = vec![
+            bulk("total.allocated"),
+            integer(total),
+            bulk("replication.backlog"),
+            integer(backlog),
+            bulk("lua.caches"),
+            integer(scripts),
+        ];
+        stats.extend(db_stats);
+        stats.extend([
+            bulk("overhead.total"),
+            integer(overhead),
+            bulk("keys.count"),
+            integer(keys),
+            bulk("keys.bytes-per-key"),
+            integer(total.checked_div(keys).unwrap_or(0)),
+            bulk("dataset.bytes"),
+            integer(dataset),
+            bulk("dataset.percentage"),
+            bulk(
+                &if total == 0 {
+                    0.0
+                } else {
+                    dataset as f64 * 100.0 / total as f64
+                }
+                .to_string(),
+            ),
+        ]);
+        CommandResponse::Array(stats)
+    }
+
+    /// The reply to `MEMORY DOCTOR`: a diagnosis of memory problems, in the
+    /// style of Redis'.
+    fn memory_doctor(&self) -> String {
+        // Below this, there's too little data to say anything useful.
+        const MIN_USED_MEMORY: usize = 5 * 1024 * 1024;
+
+        let used = self.used_memory();
+        if used < MIN_USED_MEMORY {
+            return "Hi Sam, this instance is empty or is using very little memory, my issues \
+                detector can't be used in these conditions. Please, leave for your mission on \
+                Earth and fill it with some data. The new Sam and I will be back to our \
+                programming as soon as I finished rebooting."
+                .to_string();
+        }
+
+        let mut issues = Vec::new();
+        let limit = self.config.maxmemory.limit;
+        if limit != 0 && used > limit / 10 * 9 {
+            let consequence = if self.config.maxmemory.policy == EvictionPolicy::NoEviction {
+                "writes will fail with OOM once it's reached, since the policy is noeviction"
+            } else {
+                "keys will be evicted once it's reached"
+            };
+            issues.push(format!(
+                " * High memory usage: the dataset uses {used} bytes, over 90% of maxmemory \
+                 ({limit} bytes), and {consequence}."
+            ));
+        }
+        if self.evicted_keys > 0 {
+            issues.push(format!(
+                " * Evictions: {} keys were evicted to stay under maxmemory. Consider raising \
+                 maxmemory if they're still needed.",
+                self.evicted_keys
+            ));
+        }
+
+        if issues.is_empty() {
+            return "Hi Sam, I can't find any memory issue in your instance. I can only account \
+                for what occurs on this base."
+                .to_string();
+        }
+        format!(
+            "Sam, I detected a few issues in this Redis instance memory implants:\n\n{}\n\n\
+             I'm here to keep you safe, Sam. I want to help you.\n",
+            issues.join("\n\n")
+        )
+    }
+
+    /// Roughly how many bytes the keyspace takes up, which is what
+    /// `maxmemory` limits.
+    fn used_memory(&self) -> usize {
+        self.dbs.iter().map(Db::used_memory).sum()
+    }
+
+    /// Handles `ASKING`, `READONLY` and `READWRITE`.
+    fn set_cluster_flag(&mut self, client: ThreadId, command: &Command) -> CommandResponse {
+        if self.cluster.is_none() {
+            return CommandResponse::Error(ErrorReply::err(
+                "This instance has cluster support disabled",
+            ));
+        }
+        match command {
+            Command::Asking => self.asking.insert(client),
+            Command::ReadOnly => self.readonly.insert(client),
+            _ => self.readonly.remove(&client),
+        };
+        CommandResponse::Ok
+    }
+
+    /// In cluster mode, where to send a command for keys this node doesn't
+    /// serve, or why it can't be served at all.
+    fn cluster_redirect(
+        &mut self,
+        client: ThreadId,
+        db: DbIndex,
+        command: &Command,
+        asking: bool,
+    ) -> Option<ErrorReply> {
+        let cluster = self.cluster.as_ref()?;
+        let keys = command_keys(command);
+        let slot = cluster::key_slot(keys.first()?.as_bytes());
+        if keys[1..]
+            .iter()
+            .any(|key| cluster::key_slot(key.as_bytes()) != slot)
+        {
+            return Some(ErrorReply::new(
+                ErrorCode::CrossSlot,
+                "Keys in request don't hash to the same slot",
+            ));
+        }
+        let replica_of = self
+            .master
+            .as_ref()
+            .filter(|_| {
+                self.readonly.contains(&client) && matches!(key_access(command), KeyAccess::Read(_))
+            })
+            .map(|link| (link.host(), link.port()));
+        let routing = Routing { asking, replica_of };
+        let db = &mut self.dbs[db];
+        cluster.redirect(slot, routing, || {
+            keys.iter().all(|key| db.peek_entry(key).is_some())
+        })
+    }
+
+    /// Processes a command for `client`, keeping client-side caching up to
+    /// date: keys the client reads are tracked, and tracking clients are told
+    /// about keys the command may have modified.
+    fn process_tracked_command(
+        &mut self,
+        client: ThreadId,
+        db: DbIndex,
+        command: Command,
+    ) -> CommandResponse {
+        if self.tracking.is_empty() {
+            return self.process_logged_command(db, command);
+        }
+
+        let access = key_access(&command);
+        let response = self.process_logged_command(db, command);
+        match access {
+            KeyAccess::Read(keys) => self.tracking.track_reads(client, &keys),
+            KeyAccess::Write(keys) => self.invalidate(&keys),
+            KeyAccess::WriteAll => self.invalidate_all(),
+        }
+        response
+    }
+
+    /// Updates a client's subscriptions to each of `names` with `update`.
+    /// Like Redis, each one is confirmed with a push of its own: all but the
+    /// last are queued in `pushes`, and the last is the reply.
+    fn subscribe(
+        &mut self,
+        client: ThreadId,
+        kind: &str,
+        names: Vec<RedisString>,
+        update: fn(&mut PubSub, usize, &RedisString) -> usize,
+    ) -> CommandResponse {
+        let confirmation = |name: Option<RedisString>, count: usize| {
+            vec![
+                CommandResponse::BulkString(Some(RedisString::from(kind))),
+                CommandResponse::BulkString(name),
+                CommandResponse::Integer(len_to_i64(count)),
+            ]
+        };
+        let mut confirmations: Vec<Vec<CommandResponse>> = names
+            .into_iter()
+            .map(|name| {
+                let count = update(&mut self.pubsub, client, &name);
+                confirmation(Some(name), count)
+            })
+            .collect();
+        // Unsubscribing from everything when there's nothing to unsubscribe
+        // from is still confirmed.
+        let last = confirmations
+            .pop()
+            .unwrap_or_else(|| confirmation(None, self.pubsub.count(client)));
+        self.pushes
+            .extend(confirmations.into_iter().map(|push| (client, push)));
+        CommandResponse::Push(last)
+    }
+
+    /// Pushes a message to every client subscribed to `channel`, returning
+    /// how many messages were sent.
+    fn publish(&mut self, Publish { channel, message }: Publish) -> CommandResponse {
+        let receivers = self.pubsub.receivers(&channel);
+        let num_receivers = receivers.len();
+        for (client, pattern) in receivers {
+            let bulk = |s: &RedisString| CommandResponse::BulkString(Some(s.clone()));
+            let push = pattern.map_or_else(
+                || {
+                    vec![
+                        CommandResponse::BulkString(Some(RedisString::from("message"))),
+                        bulk(&channel),
+                        bulk(&message),
+                    ]
+                },
+                |pattern| {
+                    vec![
+                        CommandResponse::BulkString(Some(RedisString::from("pmessage"))),
+                        bulk(pattern),
+                        bulk(&channel),
+                        bulk(&message),
+                    ]
+                },
+            );
+            self.pushes.push((client, push));
+        }
+        CommandResponse::Integer(len_to_i64(num_receivers))
+    }
+
+    fn script(&mut self, script: Script) -> CommandResponse {
+        match script {
+            Script::Load { script } => {
+                let sha = self.scripts.load(script);
+                CommandResponse::BulkString(Some(RedisString::from(sha.as_str())))
+            }
+            Script::Exists { shas } => CommandResponse::Array(
+                shas.iter()
+                    .map(|sha| CommandResponse::Integer(i64::from(self.scripts.contains(sha))))
+                    .collect(),
+            ),
+            Script::Flush(Flush { mode }) => {
+                let flushed = self.scripts.flush();
+                if mode == Some(FlushMode::Async) {
+                    self.lazy_free.free(flushed);
+                }
+                CommandResponse::Ok
+            }
+        }
+    }
+
+    fn cluster(&mut self, command: &Cluster) -> CommandResponse {
+        let Some(cluster) = &mut self.cluster else {
+            return CommandResponse::Error(ErrorReply::err(
+                "This instance has cluster support disabled",
+            ));
+        };
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let result = match command {
+            Cluster::AddSlots { slots } => cluster.add_slots(slots),
+            Cluster::SetSlot { slot, state } => cluster.set_slot(*slot, state),
+            Cluster::KeySlot { key } => {
+                let slot = cluster::key_slot(key.as_bytes());
+                return CommandResponse::Integer(i64::from(slot));
+            }
+            Cluster::Info => return bulk(&cluster.info()),
+            Cluster::MyId => return bulk(&cluster.myself().id),
+            Cluster::Nodes => return bulk(&cluster.describe_nodes()),
+            Cluster::Slots => return cluster_slots(cluster),
+            Cluster::Shards => return cluster_shards(cluster),
+        };
+        match result {
+            Ok(()) => CommandResponse::Ok,
+            Err(e) => CommandResponse::Error(e),
+        }
+    }
+
+    /// Whether `command` may modify the keyspace: the command table flags it
+    /// as a write, or it runs a script or function that isn't read-only.
+    fn may_write(&self, command: &Command) -> bool {
+        match command {
+            Command::Eval(Eval { read_only, .. }) | Command::EvalSha(EvalSha { read_only, .. }) => {
+                return !read_only;
+            }
+            Command::FCall(FCall { function, .. }) => {
+                return self
+                    .functions
+                    .get(function)
+                    .is_some_and(|function| !function.read_only());
+            }
+            _ => {}
+        }
+        let Message::Array(args) = command.to_resp() else {
+            return false;
+        };
+        let args: Vec<&[u8]> = args
+            .iter()
+            .filter_map(|arg| match arg {
+                Message::BulkString(Some(arg)) => Some(arg.as_bytes()),
+                _ => None,
+            })
+            .collect();
+        command_table::resolve(&args).is_some_and(|spec| spec.flags().contains(&Flag::Write))
+    }
+
+    fn fcall(&mut self, db: DbIndex, fcall: &FCall) -> CommandResponse {
+        let Some(function) = self.functions.get(&fcall.function) else {
+            return CommandResponse::Error(ErrorReply::err("Function not found"));
+        };
+        let read_only = function.read_only();
+        if fcall.read_only && !read_only {
+            return CommandResponse::Error(ErrorReply::err(
+                "Can not execute a script with write flag using *_ro command.",
+            ));
+        }
+        let mut context = FunctionContext {
+            core: self,
+            db,
+            read_only,
+        };
+        let response = function.call(&mut context, &fcall.keys, &fcall.args);
+        if !fcall.read_only {
+            for key in &fcall.keys {
+                self.signal_key_ready(db, key);
+            }
+        }
+        response
+    }
+
+    /// Turns tracking on or off for `client`.
+    fn client_tracking(
+        &mut self,
+        client: ThreadId,
+        tracking: Option<ClientTracking>,
+    ) -> CommandResponse {
+        let Some(ClientTracking { mode, redirect }) = tracking else {
+            self.tracking.disable(client);
+            return CommandResponse::Ok;
+        };
+        // RESP2 can't push invalidation messages on the tracking connection
+        // itself, so they have to go to another one.
+        let Some(redirect) = redirect else {
+            return CommandResponse::Error(ErrorReply::err(
+                "Tracking without REDIRECT needs RESP3, which isn't supported",
+            ));
+        };
+        let Ok(redirect) = ThreadId::try_from(redirect) else {
+            return CommandResponse::Error(ErrorReply::err(
+                "The client ID you want redirect to does not exist",
+            ));
+        };
+        self.tracking.enable(client, mode, redirect);
+        CommandResponse::Ok
+    }
+
+    /// Tells tracking clients that `keys` may have been modified.
+    fn invalidate(&mut self, keys: &[RedisString]) {
+        for (client, keys) in self.tracking.invalidate(keys) {
+            let keys = keys
+                .into_iter()
+                .map(|key| CommandResponse::BulkString(Some(key)))
+                .collect();
+            self.push_invalidation(client, CommandResponse::Array(keys));
+        }
+    }
+
+    /// Tells tracking clients that every key may have been modified.
+    fn invalidate_all(&mut self) {
+        for client in self.tracking.invalidate_all() {
+            self.push_invalidation(client, CommandResponse::NullArray);
+        }
+    }
+
+    /// Sends an invalidation message to the client tracking redirects to.
+    /// Like Redis with RESP2 clients, it's only sent if the client is in
+    /// pub/sub mode, since otherwise it would be mistaken for a reply.
+    fn push_invalidation(&mut self, client: ThreadId, keys: CommandResponse) {
+        if self.pubsub.count(client) > 0 {
+            self.pushes.push((client, invalidation(keys)));
+        }
+    }
+
+    /// Does periodic work that no command triggers, like Redis' `serverCron`.
+    /// Runs every `CRON_INTERVAL`.
+    fn cron(&mut self) {
+        // Actively expire keys, so keys that are never accessed again don't
+        // take up memory forever. Replicas wait for their master to delete
+        // them instead. Like Redis, keys don't expire while clients are
+        // paused, so the keyspace stays as it was, like during a failover.
+        if self.active_expire && self.master.is_none() && self.pause.is_none() {
+            let start = Instant::now();
+            self.active_expire_cycle(start + EXPIRE_TIME_LIMIT);
+            self.record_latency(latency::Event::ExpireCycle, start.elapsed());
+        }
+
+        self.replication.ping_if_due(Instant::now());
+
+        // Collect finished background work, so a rewritten AOF is swapped in
+        // even if no more writes come in.
+        self.snapshots.in_progress();
+        if let Some(aof) = &mut self.aof {
+            aof.rewrite_in_progress();
+        }
+    }
+
+    /// Removes expired keys by sampling the keys with a TTL, like Redis'
+    /// `activeExpireCycle`. A database is sampled again while more than
+    /// `EXPIRE_STALE_PERCENT` of the last sample had expired, and the cycle
+    /// stops at `deadline`, so its work doesn't grow with the keyspace.
+    fn active_expire_cycle(&mut self, deadline: Instant) {
+        let now = unix_time_millis();
+        for _ in 0..self.dbs.len() {
+            let db = self.expire_db;
+            self.expire_db = (db + 1) % self.dbs.len();
+            let mut expired = Vec::new();
+            let out_of_time = loop {
+                let (checked, removed) = self.dbs[db].expire_sample(now, EXPIRE_SAMPLE_SIZE);
+                let stale = removed.len() * 100 > checked * EXPIRE_STALE_PERCENT;
+                expired.extend(removed);
+                let out_of_time = Instant::now() >= deadline;
+                if !stale || out_of_time {
+                    break out_of_time;
+                }
+            };
+            if !expired.is_empty() {
+                log::info!("expired {} keys in db {db}", expired.len());
+                self.invalidate(&expired);
+                self.propagate(db, &Command::Del(Del { keys: expired }));
+            }
+            if out_of_time {
+                return;
+            }
+        }
+    }
+
+    /// Processes a command, appending it to the AOF and propagating it to
+    /// replicas if it's a write command that succeeded. Keys the command
+    /// found expired are propagated as a `DEL` before it.
+    fn process_logged_command(&mut self, db: DbIndex, command: Command) -> CommandResponse {
+        let logging = self.aof.is_some() || self.replication.is_streaming();
+        let logged = (logging && !matches!(key_access(&command), KeyAccess::Read(_)))
+            .then(|| command.clone());
+        let response = self.process_command(db, command);
+
+        for index in 0..self.dbs.len() {
+            let expired = self.dbs[index].take_expired();
+            if logging && !expired.is_empty() {
+                self.propagate(index, &Command::Del(Del { keys: expired }));
+            }
+        }
+        if let Some(logged) = logged {
+            if !matches!(response, CommandResponse::Error(_)) {
+                let logged = aof::deterministic(logged, &response, unix_time_millis());
+                self.propagate(db, &logged);
+            }
+        }
+        response
+    }
+
+    /// Appends a write command that ran in database `db` to the AOF and the
+    /// replication stream.
+    fn propagate(&mut self, db: DbIndex, command: &Command) {
+        if let Some(aof) = &mut self.aof {
+            aof.append(db, command);
+        }
+        self.replication.feed(db, command);
+    }
+
+    /// Applies what the master sent, if this server is a replica.
+    fn process_master_event(&mut self, event: MasterEvent) {
+        match event {
+            MasterEvent::Sync {
+                id,
+                offset,
+                mut dbs,
+            } => {
+                let keys: usize = dbs.iter().map(|db| db.entries().len()).sum();
+                log::info!("loaded {keys} keys from master");
+                let lfu = self
+                    .config
+                    .maxmemory
+                    .policy
+                    .is_lfu()
+                    .then_some(self.config.maxmemory.lfu);
+                for db in &mut dbs {
+                    db.replica = true;
+                    db.lfu = lfu;
+                }
+                let replaced = std::mem::replace(&mut self.dbs, dbs);
+                self.lazy_free.free(replaced);
+                self.invalidate_all();
+                self.replication.follow(id, offset);
+                // Commands appended to the AOF so far are for the old dataset.
+                if let Some(aof) = &mut self.aof {
+                    if let Err(e) = aof.start_rewrite(&self.dbs) {
+                        log::error!("failed to rewrite AOF after syncing with master: {e:?}");
+                    }
+                }
+            }
+            MasterEvent::Command(db, command) => {
+                let response = self.process_tracked_command(MASTER_CLIENT, db, command);
+                if let CommandResponse::Error(e) = response {
+                    log::warn!("command from master failed: {e}");
+                }
+            }
+        }
+    }
+
+    /// Starts or stops replicating another server.
+    fn replica_of(&mut self, ReplicaOf { master }: ReplicaOf) -> CommandResponse {
+        let Some((host, port)) = master else {
+            if self.master.take().is_some() {
+                log::info!("no longer a replica, now a master");
+            }
+            for db in &mut self.dbs {
+                db.replica = false;
+            }
+            return CommandResponse::Ok;
+        };
+        if self
+            .master
+            .as_ref()
+            .is_some_and(|link| link.host() == host && link.port() == port)
+        {
+            return CommandResponse::Status("OK Already connected to specified master".to_string());
+        }
+        log::info!("replicating from {host}:{port}");
+        for db in &mut self.dbs {
+            db.replica = true;
+        }
+        self.master = Some(MasterLink::start(host, port, self.port, self.dbs.len()));
+        CommandResponse::Ok
+    }
+
+    /// `INFO`. Like Redis, the per-command sections are left out by default,
+    /// and only included with `all`, `everything`, or their names.
+    fn info(&self, Info { sections }: &Info) -> CommandResponse {
+        let all = sections.is_empty()
+            || sections
+                .iter()
+                .any(|section| matches!(section.as_str(), "default" | "all" | "everything"));
+        let everything = sections
+            .iter()
+            .any(|section| matches!(section.as_str(), "all" | "everything"));
+        let mut info = String::new();
+        if all || sections.iter().any(|section| section == "memory") {
+            info.push_str(&self.memory_info());
+        }
+        if all || sections.iter().any(|section| section == "stats") {
+            info.push_str(&self.stats_info());
+        }
+        if all || sections.iter().any(|section| section == "replication") {
+            info.push_str(&self.replication_info());
+        }
+        let stats = self.stats.lock().expect("couldn't lock stats");
+        if everything || sections.iter().any(|section| section == "commandstats") {
+            info.push_str(&stats.commandstats_info());
+        }
+        if everything || sections.iter().any(|section| section == "latencystats") {
+            info.push_str(&stats.latencystats_info(&self.config.latency_percentiles));
+        }
+        drop(stats);
+        CommandResponse::BulkString(Some(RedisString::from(info)))
+    }
+
+    /// The memory section of `INFO`.
+    fn memory_info(&self) -> String {
+        let lines = [
+            "# Memory".to_string(),
+            format!("used_memory:{}", self.used_memory()),
+            format!("maxmemory:{}", self.config.maxmemory.limit),
+            format!("maxmemory_policy:{}", self.config.maxmemory.policy.name()),
+        ];
+        lines.join("\r\n") + "\r\n"
+    }
+
+    /// The stats section of `INFO`.
+    fn stats_info(&self) -> String {
+        let lines = [
+            "# Stats".to_string(),
+            format!("evicted_keys:{}", self.evicted_keys),
+        ];
+        lines.join("\r\n") + "\r\n"
+    }
+
+    /// The replication section of `INFO`, with the same fields as Redis so
+    /// tools that monitor Redis replication work with this server too.
+    fn replication_info(&self) -> String {
+        let mut lines = vec!["# Replication".to_string()];
+        let offset = if let Some(link) = &self.master {
+            let status = if link.is_up() { "up" } else { "down" };
+            lines.extend([
+                "role:slave".to_string(),
+                format!("master_host:{}", link.host()),
+                format!("master_port:{}", link.port()),
+                format!("master_link_status:{status}"),
+                format!("slave_repl_offset:{}", link.offset()),
+                "slave_read_only:1".to_string(),
+            ]);
+            link.offset()
+        } else {
+            lines.push("role:master".to_string());
+            self.replication.offset()
+        };
+
+        let now = Instant::now();
+        lines.push(format!(
+            "connected_slaves:{}",
+            self.replication.replicas().count()
+        ));
+        for (i, (_, replica)) in self.replication.replicas().enumerate() {
+            let lag = replica
+                .ack_time
+                .map_or(0, |time| now.duration_since(time).as_secs());
+            lines.push(format!(
+                "slave{i}:ip={},port={},state=online,offset={},lag={lag}",
+                replica.ip.as_deref().unwrap_or("?"),
+                replica.listening_port.unwrap_or(0),
+                replica.ack_offset,
+            ));
+        }
+
+        let backlog_len = self.replication.backlog_len();
+        lines.extend([
+            format!("master_replid:{}", self.replication.id()),
+            format!("master_repl_offset:{offset}"),
+            format!("repl_backlog_active:{}", u8::from(backlog_len.is_some())),
+            format!("repl_backlog_size:{}", replication::BACKLOG_SIZE),
+            format!(
+                "repl_backlog_first_byte_offset:{}",
+                backlog_len.map_or(0, |len| self.replication.offset() - len as u64 + 1)
+            ),
+            format!("repl_backlog_histlen:{}", backlog_len.unwrap_or(0)),
+        ]);
+        lines.join("\r\n") + "\r\n"
+    }
+
+    /// Makes `client` a replica. It continues from the backlog if it can, and
+    /// otherwise gets a snapshot of the dataset, which is created in the
+    /// background and sent after the reply.
+    fn psync(&mut self, client: ThreadId, psync: &PSync) -> CommandResponse {
+        let continued =
+            self.replication
+                .continue_replica(client, psync.repl_id.as_bytes(), psync.offset);
+        if let Some(missed) = continued {
+            log::info!("continuing replica [{client}] from offset {}", psync.offset);
+            self.syncs
+                .push((client, Outgoing::Replication(missed.into())));
+            return CommandResponse::Status(format!("CONTINUE {}", self.replication.id()));
+        }
+
+        self.replication.add_replica(client);
+        let start = Instant::now();
+        let snapshot = replication::start_snapshot(self.dbs.clone());
+        self.record_latency(latency::Event::Fork, start.elapsed());
+        self.syncs.push((client, Outgoing::Snapshot(snapshot)));
+        log::info!("starting full sync with replica [{client}]");
+        CommandResponse::Status(format!(
+            "FULLRESYNC {} {}",
+            self.replication.id(),
+            self.replication.offset()
+        ))
+    }
+
+    /// Replies with the number of replicas that have acknowledged the writes
+    /// made so far if there are at least `num_replicas` of them, and otherwise
+    /// blocks `client` until there are or it times out.
+    fn wait(&mut self, client: ThreadId, wait: Wait) -> Option<CommandResponse> {
+        if self.master.is_some() {
+            return Some(CommandResponse::Error(ErrorReply::err(
+                "WAIT cannot be used with replica instances",
+            )));
+        }
+        let offset = self.replication.offset();
+        let num_acked = self.replication.num_acked(offset);
+        if num_acked >= wait.num_replicas {
+            return Some(CommandResponse::Integer(len_to_i64(num_acked)));
+        }
+        self.replication.request_acks();
+        let timeout = wait.timeout;
+        self.blocked.block(BlockedClient {
+            client,
+            db: 0,
+            keys: Vec::new(),
+            command: Command::Wait(wait),
+            deadline: (!timeout.is_zero()).then(|| Instant::now() + timeout),
+            replication_offset: Some(offset),
+        });
+        None
+    }
+
+    /// Records that `key` may now be ready for clients blocked on it.
+    fn signal_key_ready(&mut self, db: DbIndex, key: &RedisString) {
+        if self.blocked.is_blocked_on(db, key)
+            && !self.ready_keys.iter().any(|(d, k)| *d == db && k == key)
+        {
+            self.ready_keys.push_back((db, key.clone()));
+        }
+    }
+
+    /// Serves blocked clients whose keys are ready, and times out clients
+    /// whose deadline has passed. Returns the responses for every client that
+    /// was unblocked.
+    fn unblock_clients(&mut self) -> Vec<(ThreadId, CommandResponse)> {
+        let mut responses = Vec::new();
+        while let Some((db, key)) = self.ready_keys.pop_front() {
+            for client in self.blocked.blocked_on(db, &key) {
+                // Earlier clients may have emptied the list again, and stream
+                // readers may be waiting for entries after a later ID.
+                let command = self.blocked.command(client).expect("client is blocked");
+                if !key_ready(&mut self.dbs[db], &key, command) {
+                    continue;
+                }
+                let client = self.blocked.unblock(client).expect("client is blocked");
+                // Blocking commands never block when processed here, and may
+                // signal more keys, like the destination of `BLMOVE`.
+                let response =
+                    self.process_tracked_command(client.client, client.db, client.command);
+                responses.push((client.client, response));
+            }
+        }
+
+        let replication = &self.replication;
+        for client in self
+            .blocked
+            .take_acknowledged(|offset| replication.num_acked(offset))
+        {
+            let offset = client.replication_offset.expect("client is waiting");
+            let num_acked = replication.num_acked(offset);
+            responses.push((
+                client.client,
+                CommandResponse::Integer(len_to_i64(num_acked)),
+            ));
+        }
+
+        // Shutting down times every client out.
+        let timed_out = if self.shutting_down {
+            self.blocked.take_all()
+        } else {
+            self.blocked.take_timed_out(Instant::now())
+        };
+        for client in timed_out {
+            // `WAIT` replies with how many replicas got there in time.
+            let response = match client.replication_offset {
+                Some(offset) => {
+                    CommandResponse::Integer(len_to_i64(self.replication.num_acked(offset)))
+                }
+                None => CommandResponse::NullArray,
+            };
+            responses.push((client.client, response));
+        }
+        responses
+    }
+
+    fn process_command(&mut self, db: DbIndex, command: Command) -> CommandResponse {
+        // Writes can modify values in place, so the values they touch are
+        // measured again to keep each database's memory total up to date.
+        let written = match key_access(&command) {
+            KeyAccess::Write(keys) => keys,
+            KeyAccess::Read(_) | KeyAccess::WriteAll => Vec::new(),
+        };
+        let response = self.execute_command(db, command);
+        for key in &written {
+            self.dbs[db].refresh_mem_size(key);
+        }
+        response
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn execute_command(&mut self, db: DbIndex, command: Command) -> CommandResponse {
+        match command {
+            Command::Ping => CommandResponse::Pong,
+            Command::Get(Get { key }) => match self.dbs[db].get_entry(&key) {
+                None => CommandResponse::BulkString(None),
+                Some(Entry {
+                    value: Value::String(value),
+                    ..
+                }) => CommandResponse::BulkString(Some(value.to_redis_string().into_owned())),
+                Some(_) => wrong_type_error(),
+            },
+            Command::Set(Set { key, value }) => {
+                let entry = Entry::new(Value::String(StringValue::from(value)));
+                self.dbs[db].insert(key, entry);
+                CommandResponse::Ok
+            }
+            Command::Expire(expire) => self.expire(db, expire),
+            Command::Ttl(Ttl { key, unit }) => {
+                let now = unix_time_millis();
+                let exists = self.dbs[db].peek_entry(&key).is_some();
+                let ttl = match (exists, self.dbs[db].expires_at(&key)) {
+                    (false, _) => -2,
+                    (true, None) => -1,
+                    (true, Some(t)) => {
+                        let millis = (t - now).max(0);
+                        match unit {
+                            TimeUnit::Seconds => (millis + 500) / 1000,
+                            TimeUnit::Milliseconds => millis,
+                        }
+                    }
+                };
+                CommandResponse::Integer(ttl)
+            }
+            Command::ExpireTime(ExpireTime { key, unit }) => {
+                let exists = self.dbs[db].peek_entry(&key).is_some();
+                let expire_time = match (exists, self.dbs[db].expires_at(&key)) {
+                    (false, _) => -2,
+                    (true, None) => -1,
+                    (true, Some(t)) => match unit {
+                        TimeUnit::Seconds => t / 1000,
+                        TimeUnit::Milliseconds => t,
+                    },
+                };
+                CommandResponse::Integer(expire_time)
+            }
+            Command::Persist(Persist { key }) => {
+                let removed = self.dbs[db].get_entry(&key).is_some()
+                    && self.dbs[db].set_expires_at(&key, None).is_some();
+                CommandResponse::Integer(i64::from(removed))
+            }
+            Command::Scan(scan) => self.scan(db, scan),
+            Command::FlushDb(Flush { mode }) => {
+                let flushed = self.dbs[db].flush();
+                if mode == Some(FlushMode::Async) {
+                    self.lazy_free.free(flushed);
+                }
+                CommandResponse::Ok
+            }
+            Command::FlushAll(Flush { mode }) => {
+                let flushed: Vec<Db> = self.dbs.iter_mut().map(Db::flush).collect();
+                if mode == Some(FlushMode::Async) {
+                    self.lazy_free.free(flushed);
+                }
+                CommandResponse::Ok
+            }
+            Command::Save => match self.snapshots.save(&self.dbs) {
+                Ok(()) => CommandResponse::Ok,
+                Err(e) => {
+                    log::error!("error saving snapshot: {e:?}");
+                    CommandResponse::Error(ErrorReply::err(e.root_cause().to_string()))
+                }
+            },
+            Command::BgSave => {
+                let start = Instant::now();
+                let started = self.snapshots.start_background_save(&self.dbs);
+                self.record_latency(latency::Event::Fork, start.elapsed());
+                match started {
+                    Ok(()) => CommandResponse::Status("Background saving started".to_string()),
+                    Err(e) => CommandResponse::Error(ErrorReply::err(e.to_string())),
+                }
+            }
+            Command::LastSave => CommandResponse::Integer(self.snapshots.last_save()),
+            Command::BgRewriteAof => {
+                let Some(aof) = &mut self.aof else {
+                    return CommandResponse::Error(ErrorReply::err("Append only file is disabled"));
+                };
+                let start = Instant::now();
+                let started = aof.start_rewrite(&self.dbs);
+                self.record_latency(latency::Event::Fork, start.elapsed());
+                match started {
+                    Ok(()) => CommandResponse::Status(
+                        "Background append only file rewriting started".to_string(),
+                    ),
+                    Err(e) => CommandResponse::Error(ErrorReply::err(e.to_string())),
+                }
+            }
+            Command::Del(Del { keys }) => {
+                let removed = self.dbs[db].remove_keys(keys);
+                CommandResponse::Integer(len_to_i64(removed.len()))
+            }
+            Command::Unlink(Unlink { keys }) => {
+                let removed = self.dbs[db].remove_keys(keys);
+                let count = removed.len();
+                self.lazy_free.free(removed);
+                CommandResponse::Integer(len_to_i64(count))
+            }
+            Command::Touch(Touch { keys }) => {
+                let count = keys
+                    .iter()
+                    .filter(|key| self.dbs[db].get_entry(key).is_some())
+                    .count();
+                CommandResponse::Integer(len_to_i64(count))
+            }
+            Command::Dump(Dump { key }) => match self.dbs[db].get_entry(&key) {
+                None => CommandResponse::BulkString(None),
+                Some(entry) => match rdb::dump(&entry.value) {
+                    Ok(payload) => CommandResponse::BulkString(Some(RedisString::from(payload))),
+                    Err(e) => CommandResponse::Error(ErrorReply::err(e.to_string())),
+                },
+            },
+            Command::Restore(restore) => self.restore(db, restore),
+            Command::Object(object) => self.object(db, &object),
+            Command::Move(Move { key, db: target }) => self.move_key(db, &key, target),
+            Command::Sort(sort) | Command::SortRo(sort) => self.sort(db, sort),
+            Command::Push(push) => self.push(db, push),
+            Command::Pop(pop) => self.pop(db, &pop),
+            // When processed here, blocking commands behave as if they timed
+            // out immediately. Clients are blocked by `process_client_command`.
+            Command::LMPop(lmpop) | Command::BLMPop(BLMPop { lmpop, .. }) => self.lmpop(db, lmpop),
+            Command::LMove(lmove) | Command::BLMove(BLMove { lmove, .. }) => self.lmove(db, &lmove),
+            Command::BPop(BPop { keys, end, .. }) => match self.pop_first(db, keys, end, 1) {
+                Ok(Some((key, mut elements))) => CommandResponse::Array(vec![
+                    CommandResponse::BulkString(Some(key)),
+                    CommandResponse::BulkString(elements.pop()),
+                ]),
+                Ok(None) => CommandResponse::NullArray,
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::LLen(LLen { key }) => match self.dbs[db].get_list(&key) {
+                Ok(list) => CommandResponse::Integer(len_to_i64(list.map_or(0, |l| l.len()))),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::LRange(LRange { key, start, stop }) => match self.dbs[db].get_list(&key) {
+                Ok(None) => CommandResponse::Array(vec![]),
+                Ok(Some(list)) => CommandResponse::Array(
+                    list_range(list.len(), start, stop)
+                        .map(|i| CommandResponse::BulkString(Some(list[i].clone())))
+                        .collect(),
+                ),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::LIndex(LIndex { key, index }) => match self.dbs[db].get_list(&key) {
+                Ok(list) => {
+                    let element = list.and_then(|list| {
+                        let index = list_index(list.len(), index)?;
+                        Some(list[index].clone())
+                    });
+                    CommandResponse::BulkString(element)
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::LSet(lset) => self.lset(db, lset),
+            Command::LInsert(linsert) => self.linsert(db, linsert),
+            Command::LRem(lrem) => self.lrem(db, &lrem),
+            Command::LTrim(LRange { key, start, stop }) => {
+                match self.dbs[db].get_list(&key) {
+                    Ok(Some(list)) => {
+                        let range = list_range(list.len(), start, stop);
+                        list.truncate(range.end);
+                        list.drain(..range.start);
+                        self.dbs[db].remove_if_empty(&key);
+                    }
+                    Ok(None) => {}
+                    Err(WrongType) => return wrong_type_error(),
+                }
+                CommandResponse::Ok
+            }
+            Command::HSet(HSet { key, pairs }) => {
+                let Ok(hash) = self.dbs[db].get_or_create_hash(&key) else {
+                    return wrong_type_error();
+                };
+                let added = pairs
+                    .into_iter()
+                    .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+                    .count();
+                CommandResponse::Integer(len_to_i64(added))
+            }
+            Command::HGet(HGet { key, field }) => match self.dbs[db].get_hash(&key) {
+                Ok(hash) => CommandResponse::BulkString(hash.and_then(|h| h.get(&field).cloned())),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::HDel(HDel { key, fields }) => {
+                let removed = match self.dbs[db].get_hash(&key) {
+                    Ok(Some(hash)) => fields.iter().filter(|f| hash.remove(f).is_some()).count(),
+                    Ok(None) => 0,
+                    Err(WrongType) => return wrong_type_error(),
+                };
+                self.dbs[db].remove_if_empty(&key);
+                CommandResponse::Integer(len_to_i64(removed))
+            }
+            Command::HGetAll(HGetAll { key }) => match self.dbs[db].get_hash(&key) {
+                Ok(hash) => bulk_string_array(
+                    hash.as_deref()
+                        .into_iter()
+                        .flatten()
+                        .flat_map(|(field, value)| [field.clone(), value.clone()])
+                        .collect(),
+                ),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::HScan(hscan) => self.hscan(db, &hscan),
+            Command::HMGet(HMGet { key, fields }) => match self.dbs[db].get_hash(&key) {
+                Ok(hash) => CommandResponse::Array(
+                    fields
+                        .iter()
+                        .map(|f| {
+                            CommandResponse::BulkString(
+                                hash.as_ref().and_then(|h| h.get(f).cloned()),
+                            )
+                        })
+                        .collect(),
+                ),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::HKeys(HKeys { key }) => match self.dbs[db].get_hash(&key) {
+                Ok(hash) => {
+                    bulk_string_array(hash.into_iter().flat_map(|h| h.keys().cloned()).collect())
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::HVals(HVals { key }) => match self.dbs[db].get_hash(&key) {
+                Ok(hash) => {
+                    bulk_string_array(hash.into_iter().flat_map(|h| h.values().cloned()).collect())
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::HLen(HLen { key }) => match self.dbs[db].get_hash(&key) {
+                Ok(hash) => CommandResponse::Integer(len_to_i64(hash.map_or(0, |h| h.len()))),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::HExists(HExists { key, field }) => match self.dbs[db].get_hash(&key) {
+                Ok(hash) => CommandResponse::Integer(i64::from(
+                    hash.is_some_and(|h| h.contains_key(&field)),
+                )),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::HSetNx(HSetNx { key, field, value }) => {
+                let Ok(hash) = self.dbs[db].get_or_create_hash(&key) else {
+                    return wrong_type_error();
+                };
+                let added = !hash.contains_key(&field);
+                if added {
+                    hash.insert(field, value);
+                }
+                CommandResponse::Integer(i64::from(added))
+            }
+            Command::HStrLen(HStrLen { key, field }) => match self.dbs[db].get_hash(&key) {
+                Ok(hash) => {
+                    let len = hash.and_then(|h| h.get(&field)).map_or(0, RedisString::len);
+                    CommandResponse::Integer(len_to_i64(len))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::SAdd(SAdd { key, members }) => {
+                let Ok(set) = self.dbs[db].get_or_create_set(&key) else {
+                    return wrong_type_error();
+                };
+                let added = members
+                    .into_iter()
+                    .filter(|m| set.insert(m.clone()))
+                    .count();
+                CommandResponse::Integer(len_to_i64(added))
+            }
+            Command::SRem(SRem { key, members }) => {
+                let removed = match self.dbs[db].get_set(&key) {
+                    Ok(Some(set)) => members.iter().filter(|m| set.remove(m)).count(),
+                    Ok(None) => 0,
+                    Err(WrongType) => return wrong_type_error(),
+                };
+                self.dbs[db].remove_if_empty(&key);
+                CommandResponse::Integer(len_to_i64(removed))
+            }
+            Command::SMembers(SMembers { key }) => match self.dbs[db].get_set(&key) {
+                Ok(set) => bulk_string_array(
+                    set.into_iter()
+                        .flat_map(|s| s.iter().map(Cow::into_owned))
+                        .collect(),
+                ),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::SIsMember(SIsMember { key, member }) => match self.dbs[db].get_set(&key) {
+                Ok(set) => {
+                    CommandResponse::Integer(i64::from(set.is_some_and(|s| s.contains(&member))))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::SMIsMember(SMIsMember { key, members }) => match self.dbs[db].get_set(&key) {
+                Ok(set) => CommandResponse::Array(
+                    members
+                        .iter()
+                        .map(|m| {
+                            let is_member = set.as_ref().is_some_and(|s| s.contains(m));
+                            CommandResponse::Integer(i64::from(is_member))
+                        })
+                        .collect(),
+                ),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::SCard(SCard { key }) => match self.dbs[db].get_set(&key) {
+                Ok(set) => CommandResponse::Integer(len_to_i64(set.map_or(0, |s| s.len()))),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::SetOp(set_op) => self.set_op(db, set_op),
+            Command::SInterCard(SInterCard { keys, limit }) => match self.dbs[db].get_sets(&keys) {
+                Ok(sets) => {
+                    let limit = limit.filter(|&l| l > 0).unwrap_or(usize::MAX);
+                    CommandResponse::Integer(len_to_i64(intersection_size(&sets, limit)))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::SScan(sscan) => self.sscan(db, &sscan),
+            Command::ZAdd(zadd) => self.zadd(db, zadd),
+            Command::ZScore(ZScore { key, member }) => match self.dbs[db].get_zset(&key) {
+                Ok(zset) => {
+                    let score = zset.and_then(|z| z.score(&member));
+                    CommandResponse::BulkString(score.map(|s| RedisString::from(s.to_string())))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::ZRem(ZRem { key, members }) => {
+                let removed = match self.dbs[db].get_zset(&key) {
+                    Ok(Some(zset)) => members.iter().filter(|m| zset.remove(m).is_some()).count(),
+                    Ok(None) => 0,
+                    Err(WrongType) => return wrong_type_error(),
+                };
+                self.dbs[db].remove_if_empty(&key);
+                CommandResponse::Integer(len_to_i64(removed))
+            }
+            Command::ZRange(zrange) => self.zrange(db, &zrange),
+            Command::ZRank(zrank) => self.zrank(db, &zrank),
+            Command::ZIncrBy(ZIncrBy {
+                key,
+                increment,
+                member,
+            }) => self.zadd(
+                db,
+                ZAdd {
+                    key,
+                    existence: None,
+                    comparison: None,
+                    ch: false,
+                    incr: true,
+                    members: vec![(increment, member)],
+                },
+            ),
+            Command::ZSetOp(zset_op) => self.zset_op(db, zset_op),
+            Command::ZScan(zscan) => self.zscan(db, &zscan),
+            Command::ZRandMember(zrandmember) => self.zrandmember(db, &zrandmember),
+            Command::ZMScore(ZMScore { key, members }) => match self.dbs[db].get_zset(&key) {
+                Ok(zset) => CommandResponse::Array(
+                    members
+                        .iter()
+                        .map(|m| {
+                            let score = zset.as_ref().and_then(|z| z.score(m));
+                            CommandResponse::BulkString(
+                                score.map(|s| RedisString::from(s.to_string())),
+                            )
+                        })
+                        .collect(),
+                ),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::ZCount(ZCount { key, range }) => match self.dbs[db].get_zset(&key) {
+                Ok(zset) => {
+                    let count = zset.map_or(0, |z| z.range_by_score(&range).count());
+                    CommandResponse::Integer(len_to_i64(count))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::ZCard(ZCard { key }) => match self.dbs[db].get_zset(&key) {
+                Ok(zset) => CommandResponse::Integer(len_to_i64(zset.map_or(0, |z| z.len()))),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::SetBit(SetBit { key, offset, value }) => {
+                match self.dbs[db].get_or_create_string(&key) {
+                    Ok(s) => {
+                        let previous = bitmap::set_bit(s.as_mut_vec(), bit_offset(offset), value);
+                        CommandResponse::Integer(i64::from(previous))
+                    }
+                    Err(WrongType) => wrong_type_error(),
+                }
+            }
+            Command::GetBit(GetBit { key, offset }) => match self.dbs[db].get_string(&key) {
+                Ok(s) => {
+                    let bit = s.is_some_and(|s| {
+                        bitmap::get_bit(s.to_redis_string().as_bytes(), bit_offset(offset))
+                    });
+                    CommandResponse::Integer(i64::from(bit))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::BitCount(BitCount { key, range }) => match self.dbs[db].get_string(&key) {
+                Ok(None) => CommandResponse::Integer(0),
+                Ok(Some(s)) => {
+                    let s = s.to_redis_string();
+                    let bytes = s.as_bytes();
+                    let bits = match range {
+                        None => 0..bytes.len() * 8,
+                        Some(BitRange { start, end, unit }) => {
+                            bit_range(bytes.len(), start, end, unit)
+                        }
+                    };
+                    CommandResponse::Integer(len_to_i64(bitmap::count_ones(bytes, bits)))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::BitPos(bitpos) => self.bitpos(db, &bitpos),
+            Command::XAdd(XAdd { key, id, fields }) => {
+                let stream = match self.dbs[db].get_or_create_stream(&key) {
+                    Ok(stream) => stream,
+                    Err(WrongType) => return wrong_type_error(),
+                };
+                let now = u64::try_from(unix_time_millis()).unwrap_or(0);
+                let Some(id) = stream.next_id(id, now) else {
+                    return CommandResponse::Error(ErrorReply::err(
+                        "The ID specified in XADD is equal or smaller than the target stream top item",
+                    ));
+                };
+                stream.insert(id, fields);
+                self.signal_key_ready(db, &key);
+                CommandResponse::BulkString(Some(RedisString::from(id.to_string())))
+            }
+            Command::XLen(XLen { key }) => match self.dbs[db].get_stream(&key) {
+                Ok(stream) => CommandResponse::Integer(len_to_i64(stream.map_or(0, |s| s.len()))),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::XRange(xrange) => self.xrange(db, &xrange),
+            // When processed here, XREAD never blocks, like the list commands
+            // above.
+            Command::XRead(xread) => self.xread(db, &xread),
+            Command::XGroup(XGroup::Create {
+                key,
+                group,
+                id,
+                mkstream,
+            }) => {
+                let stream = if mkstream {
+                    self.dbs[db].get_or_create_stream(&key).map(Some)
+                } else {
+                    self.dbs[db].get_stream(&key)
+                };
+                let stream = match stream {
+                    Ok(Some(stream)) => stream,
+                    Ok(None) => {
+                        return CommandResponse::Error(ErrorReply::err(
+                            "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+                        ))
+                    }
+                    Err(WrongType) => return wrong_type_error(),
+                };
+                let id = match id {
+                    ReadId::After(id) => id,
+                    ReadId::Last => stream.last_id(),
+                };
+                if stream.create_group(group, id) {
+                    CommandResponse::Ok
+                } else {
+                    CommandResponse::Error(ErrorReply::new(
+                        ErrorCode::BusyGroup,
+                        "Consumer Group name already exists",
+                    ))
+                }
+            }
+            Command::XReadGroup(xreadgroup) => self.xreadgroup(db, &xreadgroup),
+            Command::XAck(XAck { key, group, ids }) => match self.dbs[db].get_stream(&key) {
+                Ok(stream) => {
+                    let acked = stream.and_then(|stream| stream.ack(&group, &ids));
+                    CommandResponse::Integer(len_to_i64(acked.unwrap_or(0)))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::XPending(xpending) => self.xpending(db, &xpending),
+            Command::XClaim(xclaim) => self.xclaim(db, &xclaim),
+            Command::XAutoClaim(xautoclaim) => self.xautoclaim(db, &xautoclaim),
+            Command::XTrim(XTrim { key, trim }) => match self.dbs[db].get_stream(&key) {
+                Ok(stream) => {
+                    let trimmed = stream.map_or(0, |stream| stream.trim(&trim));
+                    CommandResponse::Integer(len_to_i64(trimmed))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::XDel(XDel { key, ids }) => match self.dbs[db].get_stream(&key) {
+                Ok(stream) => {
+                    let deleted = stream.map_or(0, |stream| stream.delete(&ids));
+                    CommandResponse::Integer(len_to_i64(deleted))
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::XInfo(xinfo) => self.xinfo(db, &xinfo),
+            Command::GeoAdd(GeoAdd {
+                key,
+                existence,
+                ch,
+                members,
+            }) => self.zadd(
+                db,
+                ZAdd {
+                    key,
+                    existence,
+                    comparison: None,
+                    ch,
+                    incr: false,
+                    members: members
+                        .into_iter()
+                        .map(|(coords, member)| (geo::to_score(geo::encode(coords)), member))
+                        .collect(),
+                },
+            ),
+            Command::GeoSearch(geosearch) => self.geosearch(db, &geosearch),
+            Command::GeoDist(GeoDist {
+                key,
+                member1,
+                member2,
+                unit,
+            }) => match self.dbs[db].get_zset(&key) {
+                Ok(zset) => {
+                    let position = |member| zset.as_ref()?.score(member).map(geo_position);
+                    let distance = position(&member1)
+                        .zip(position(&member2))
+                        .map(|(a, b)| geo::distance(a, b) / unit.meters());
+                    CommandResponse::BulkString(
+                        distance.map(|d| RedisString::from(format!("{d:.4}"))),
+                    )
+                }
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::GeoPos(GeoPos { key, members }) => match self.dbs[db].get_zset(&key) {
+                Ok(zset) => CommandResponse::Array(
+                    members
+                        .iter()
+                        .map(|member| {
+                            zset.as_ref()
+                                .and_then(|zset| zset.score(member))
+                                .map_or(CommandResponse::NullArray, |score| {
+                                    coordinates_response(geo_position(score))
+                                })
+                        })
+                        .collect(),
+                ),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::GeoHash(GeoHash { key, members }) => match self.dbs[db].get_zset(&key) {
+                Ok(zset) => CommandResponse::Array(
+                    members
+                        .iter()
+                        .map(|member| {
+                            let score = zset.as_ref().and_then(|zset| zset.score(member));
+                            CommandResponse::BulkString(score.map(|score| {
+                                RedisString::from(geo::to_geohash_string(geo_position(score)))
+                            }))
+                        })
+                        .collect(),
+                ),
+                Err(WrongType) => wrong_type_error(),
+            },
+            Command::Select(_) => unreachable!("SELECT is handled by the client thread"),
+            Command::Client(_) => unreachable!("CLIENT is handled by process_client_command"),
+            Command::Auth(_) => unreachable!("AUTH is handled by the client thread"),
+            Command::Hello(_) => unreachable!("HELLO is handled by process_client_command"),
+            Command::ReplConf(_) | Command::PSync(_) | Command::Wait(_) => {
+                unreachable!("replication is handled by process_client_command")
+            }
+            Command::ReplicaOf(replica_of) => self.replica_of(replica_of),
+            Command::Info(info) => self.info(&info),
+            Command::Command(query) => command_query(&query),
+            Command::Subscribe(_)
+            | Command::Unsubscribe(_)
+            | Command::PSubscribe(_)
+            | Command::PUnsubscribe(_) => {
+                unreachable!("subscriptions are handled by process_client_command")
+            }
+            Command::Publish(publish) => self.publish(publish),
+            Command::Script(script) => self.script(script),
+            Command::Eval(Eval { script, .. }) => {
+                // Like Redis, scripts run with EVAL are cached for EVALSHA.
+                self.scripts.load(script);
+                CommandResponse::Error(ErrorReply::err("scripting is not supported"))
+            }
+            Command::FCall(fcall) => self.fcall(db, &fcall),
+            Command::Cluster(cluster) => self.cluster(&cluster),
+            Command::Asking | Command::ReadOnly | Command::ReadWrite => {
+                unreachable!("cluster flags are handled by process_client_command")
+            }
+            Command::Memory(memory) => self.memory(db, &memory),
+            Command::Latency(latency) => self.latency(&latency),
+            Command::Debug(debug) => self.debug(db, &debug),
+            Command::Config(config) => self.config(config),
+            Command::Acl(acl) => self.acl(acl),
+            Command::EvalSha(EvalSha { sha, .. }) => {
+                if self.scripts.contains(&sha) {
+                    CommandResponse::Error(ErrorReply::err("scripting is not supported"))
+                } else {
+                    CommandResponse::Error(ErrorReply::new(
+                        ErrorCode::NoScript,
+                        "No matching script. Please use EVAL.",
+                    ))
+                }
+            }
+            Command::RawCommand(c) => {
+                CommandResponse::Error(ErrorReply::err(format!("unknown command: {c:?}")))
+            }
+        }
+    }
+
+    fn expire(&mut self, db: DbIndex, expire: Expire) -> CommandResponse {
+        let Expire {
+            key,
+            time,
+            existence,
+            comparison,
+        } = expire;
+
+        let Some(expires_at) = time.to_unix_millis(unix_time_millis()) else {
+            return CommandResponse::Error(ErrorReply::err("invalid expire time"));
+        };
+        if self.dbs[db].get_entry(&key).is_none() {
+            return CommandResponse::Integer(0);
+        }
+        let current = self.dbs[db].expires_at(&key);
+
+        // A key without a TTL is treated as having an infinite TTL for GT and
+        // LT.
+        let allowed = match (existence, current) {
+            (Some(Existence::Nx), Some(_)) | (Some(Existence::Xx), None) => false,
+            _ => match (comparison, current) {
+                (None, _) | (Some(Comparison::Lt), None) => true,
+                (Some(Comparison::Gt), None) => false,
+                (Some(Comparison::Gt), Some(current)) => expires_at > current,
+                (Some(Comparison::Lt), Some(current)) => expires_at < current,
+            },
+        };
+        if !allowed {
+            return CommandResponse::Integer(0);
+        }
+
+        // Expiration times in the past delete the key immediately.
+        if expires_at <= unix_time_millis() {
+            self.dbs[db].remove(&key);
+        } else {
+            self.dbs[db].set_expires_at(&key, Some(expires_at));
+        }
+        CommandResponse::Integer(1)
+    }
+
+    fn restore(&mut self, db: DbIndex, restore: Restore) -> CommandResponse {
+        let Restore {
+            key,
+            ttl,
+            payload,
+            replace,
+            absttl,
+            idletime,
+        } = restore;
+
+        if ttl < 0 {
+            return CommandResponse::Error(ErrorReply::err("Invalid TTL value, must be >= 0"));
+        }
+        if idletime.is_some_and(|t| t < 0) {
+            return CommandResponse::Error(ErrorReply::err("Invalid IDLETIME value, must be >= 0"));
+        }
+        if !replace && self.dbs[db].get_entry(&key).is_some() {
+            return CommandResponse::Error(ErrorReply::new(
+                ErrorCode::BusyKey,
+                "Target key name already exists.",
+            ));
+        }
+
+        let value = match rdb::restore(payload.as_bytes()) {
+            Ok(value) => value,
+            Err(_) if rdb::verify_payload(payload.as_bytes()).is_err() => {
+                return CommandResponse::Error(ErrorReply::err(
+                    "DUMP payload version or checksum are wrong",
+                ));
+            }
+            Err(_) => return CommandResponse::Error(ErrorReply::err("Bad data format")),
+        };
+
+        let now = unix_time_millis();
+        let expires_at = match (ttl, absttl) {
+            (0, _) => None,
+            (ttl, true) => Some(ttl),
+            (ttl, false) => Some(now.saturating_add(ttl)),
+        };
+        // Restoring an already expired key just deletes it.
+        if expires_at.is_some_and(|t| t <= now) {
+            self.dbs[db].remove(&key);
+            return CommandResponse::Ok;
+        }
+
+        let mut entry = Entry::new(value);
+        if let Some(idletime) = idletime {
+            entry.last_access = now.saturating_sub(idletime.saturating_mul(1000));
+        }
+        self.dbs[db].insert(key.clone(), entry);
+        self.dbs[db].set_expires_at(&key, expires_at);
+        self.signal_key_ready(db, &key);
+        CommandResponse::Ok
+    }
+
+    fn object(&mut self, db: DbIndex, object: &Object) -> CommandResponse {
+        let (Object::Encoding { key }
+        | Object::RefCount { key }
+        | Object::IdleTime { key }
+        | Object::Freq { key }) = object;
+        let Some(entry) = self.dbs[db].peek_entry(key) else {
+            return CommandResponse::BulkString(None);
+        };
+        // Like Redis, only one of the access time and frequency is reported,
+        // depending on which the eviction policy uses.
+        let lfu = self.config.maxmemory.policy.is_lfu();
+        match object {
+            Object::Encoding { .. } => {
+                CommandResponse::BulkString(Some(RedisString::from(entry.encoding())))
+            }
+            Object::RefCount { .. } => CommandResponse::Integer(refcount(entry)),
+            Object::IdleTime { .. } if lfu => CommandResponse::Error(ErrorReply::err(
+                "An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.",
+            )),
+            Object::IdleTime { .. } => {
+                let idle_millis = (unix_time_millis() - entry.last_access).max(0);
+                CommandResponse::Integer(idle_millis / 1000)
+            }
+            Object::Freq { .. } if lfu => {
+                let count = entry
+                    .frequency
+                    .count(unix_time_millis(), &self.config.maxmemory.lfu);
+                CommandResponse::Integer(i64::from(count))
+            }
+            Object::Freq { .. } => CommandResponse::Error(ErrorReply::err(
+                "An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.",
+            )),
+        }
+    }
+
+    fn debug(&mut self, db: DbIndex, debug: &DebugCommand) -> CommandResponse {
+        match debug {
+            // Sleeping here blocks every client, like a slow command.
+            DebugCommand::Sleep { duration } => {
+                thread::sleep(*duration);
+                CommandResponse::Ok
+            }
+            DebugCommand::Object { key } => {
+                let Some(entry) = self.dbs[db].peek_entry(key) else {
+                    return CommandResponse::Error(ErrorReply::err("no such key"));
+                };
+                let serialized_length = rdb::dump(&entry.value).map_or(0, |payload| payload.len());
+                // Like Redis' LRU clock, the access time is reported in
+                // seconds, wrapping around at 24 bits.
+                let lru = (entry.last_access / 1000) & ((1 << 24) - 1);
+                let idle = (unix_time_millis() - entry.last_access).max(0) / 1000;
+                CommandResponse::Status(format!(
+                    "Value at:{:p} refcount:{} encoding:{} serializedlength:{serialized_length} \
+                     lru:{lru} lru_seconds_idle:{idle}",
+                    &entry.value,
+                    refcount(entry),
+                    entry.encoding(),
+                ))
+            }
+            DebugCommand::SetActiveExpire { enabled } => {
+                self.active_expire = *enabled;
+                CommandResponse::Ok
+            }
+            DebugCommand::StringMatchLen => {
+                debug::stringmatch_len_fuzz(&mut self.rng);
+                CommandResponse::Status("Apparently Redis did not crash: test passed".to_string())
+            }
+        }
+    }
+
+    fn move_key(&mut self, db: DbIndex, key: &RedisString, target: i64) -> CommandResponse {
+        let Some(target) = usize::try_from(target)
+            .ok()
+            .filter(|&target| target < self.dbs.len())
+        else {
+            return CommandResponse::Error(ErrorReply::err("index out of range"));
+        };
+        if target == db {
+            return CommandResponse::Error(ErrorReply::err(
+                "source and destination objects are the same",
+            ));
+        }
+
+        // The entry moves as is, so its value, TTL and access time are kept.
+        if self.dbs[db].get_entry(key).is_none() || self.dbs[target].peek_entry(key).is_some() {
+            return CommandResponse::Integer(0);
+        }
+        let expires_at = self.dbs[db].expires_at(key);
+        let entry = self.dbs[db].remove(key).expect("entry was just looked up");
+        self.dbs[target].insert(key.clone(), entry);
+        self.dbs[target].set_expires_at(key, expires_at);
+        self.signal_key_ready(target, key);
+        CommandResponse::Integer(1)
+    }
+
+    fn push(&mut self, db: DbIndex, push: Push) -> CommandResponse {
+        let Ok(list) = self.dbs[db].get_or_create_list(&push.key) else {
+            return wrong_type_error();
+        };
+        for element in push.elements {
+            match push.end {
+                ListEnd::Left => list.push_front(element),
+                ListEnd::Right => list.push_back(element),
+            }
+        }
+        let len = list.len();
+        self.signal_key_ready(db, &push.key);
+        CommandResponse::Integer(len_to_i64(len))
+    }
+
+    fn pop(&mut self, db: DbIndex, pop: &Pop) -> CommandResponse {
+        let list = match self.dbs[db].get_list(&pop.key) {
+            Ok(Some(list)) => list,
+            Ok(None) if pop.count.is_some() => return CommandResponse::NullArray,
+            Ok(None) => return CommandResponse::BulkString(None),
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let response = match pop.count {
+            None => CommandResponse::BulkString(pop_elements(list, pop.end, 1).pop()),
+            Some(count) => bulk_string_array(pop_elements(list, pop.end, count)),
+        };
+        self.dbs[db].remove_if_empty(&pop.key);
+        response
+    }
+
+    fn lmpop(&mut self, db: DbIndex, lmpop: LMPop) -> CommandResponse {
+        match self.pop_first(db, lmpop.keys, lmpop.end, lmpop.count.unwrap_or(1)) {
+            Ok(Some((key, elements))) => CommandResponse::Array(vec![
+                CommandResponse::BulkString(Some(key)),
+                bulk_string_array(elements),
+            ]),
+            Ok(None) => CommandResponse::NullArray,
+            Err(WrongType) => wrong_type_error(),
+        }
+    }
+
+    fn lmove(&mut self, db: DbIndex, lmove: &LMove) -> CommandResponse {
+        match self.dbs[db].get_list(&lmove.source) {
+            Ok(Some(_)) => {}
+            Ok(None) => return CommandResponse::BulkString(None),
+            Err(WrongType) => return wrong_type_error(),
+        }
+        // Check the destination before popping so a failed move doesn't lose
+        // the element.
+        if self.dbs[db].get_list(&lmove.destination).is_err() {
+            return wrong_type_error();
+        }
+
+        let source = self.dbs[db]
+            .get_list(&lmove.source)
+            .ok()
+            .flatten()
+            .expect("source was just looked up");
+        let element = pop_elements(source, lmove.from, 1)
+            .pop()
+            .expect("lists are never empty");
+        self.dbs[db].remove_if_empty(&lmove.source);
+
+        let destination = self.dbs[db]
+            .get_or_create_list(&lmove.destination)
+            .expect("destination was just looked up");
+        match lmove.to {
+            ListEnd::Left => destination.push_front(element.clone()),
+            ListEnd::Right => destination.push_back(element.clone()),
+        }
+        self.signal_key_ready(db, &lmove.destination);
+        CommandResponse::BulkString(Some(element))
+    }
+
+    /// Pops up to `count` elements from the first non-empty list among `keys`,
+    /// and returns that list's key along with the elements.
+    fn pop_first(
+        &mut self,
+        db: DbIndex,
+        keys: Vec<RedisString>,
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<(RedisString, Vec<RedisString>)>, WrongType> {
+        for key in keys {
+            let Some(list) = self.dbs[db].get_list(&key)? else {
+                continue;
+            };
+            let elements = pop_elements(list, end, count);
+            self.dbs[db].remove_if_empty(&key);
+            return Ok(Some((key, elements)));
+        }
+        Ok(None)
+    }
+
+    fn lset(&mut self, db: DbIndex, lset: LSet) -> CommandResponse {
+        let list = match self.dbs[db].get_list(&lset.key) {
+            Ok(Some(list)) => list,
+            Ok(None) => return CommandResponse::Error(ErrorReply::err("no such key")),
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let Some(index) = list_index(list.len(), lset.index) else {
+            return CommandResponse::Error(ErrorReply::err("index out of range"));
+        };
+        list[index] = lset.element;
+        CommandResponse::Ok
+    }
+
+    fn linsert(&mut self, db: DbIndex, linsert: LInsert) -> CommandResponse {
+        let list = match self.dbs[db].get_list(&linsert.key) {
+            Ok(Some(list)) => list,
+            Ok(None) => return CommandResponse::Integer(0),
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let Some(pivot) = list.iter().position(|e| *e == linsert.pivot) else {
+            return CommandResponse::Integer(-1);
+        };
+        let index = match linsert.position {
+            InsertPosition::Before => pivot,
+            InsertPosition::After => pivot + 1,
+        };
+        list.insert(index, linsert.element);
+        CommandResponse::Integer(len_to_i64(list.len()))
+    }
+
+    fn lrem(&mut self, db: DbIndex, lrem: &LRem) -> CommandResponse {
+        let list = match self.dbs[db].get_list(&lrem.key) {
+            Ok(Some(list)) => list,
+            Ok(None) => return CommandResponse::Integer(0),
+            Err(WrongType) => return wrong_type_error(),
+        };
+
+        let matches = list.iter().filter(|e| **e == lrem.element).count();
+        let removed = match usize::try_from(lrem.count.unsigned_abs()) {
+            Ok(0) | Err(_) => matches,
+            Ok(count) => count.min(matches),
+        };
+        // Which matching elements to remove, numbered from the head.
+        let to_remove = if lrem.count < 0 {
+            matches - removed..matches
+        } else {
+            0..removed
+        };
+        let mut match_number = 0;
+        list.retain(|e| {
+            if *e != lrem.element {
+                return true;
+            }
+            match_number += 1;
+            !to_remove.contains(&(match_number - 1))
+        });
+
+        self.dbs[db].remove_if_empty(&lrem.key);
+        CommandResponse::Integer(len_to_i64(removed))
+    }
+
+    fn sort(&mut self, db: DbIndex, sort: Sort) -> CommandResponse {
+        let elements = match self.dbs[db].get_entry(&sort.key).map(|e| &e.value) {
+            None => Vec::new(),
+            Some(Value::List(list)) => list.iter().cloned().collect(),
+            Some(Value::Set(set)) => set.iter().map(Cow::into_owned).collect(),
+            Some(Value::ZSet(zset)) => zset.iter().map(|(member, _)| member.clone()).collect(),
+            Some(_) => return wrong_type_error(),
+        };
+
+        let result = sort::sort(elements, &sort, |lookup| {
+            let value = &self.dbs[db].get_entry(&lookup.key)?.value;
+            match (value, &lookup.field) {
+                (Value::String(s), None) => Some(s.to_redis_string().into_owned()),
+                (Value::Hash(hash), Some(field)) => hash.get(field).cloned(),
+                _ => None,
+            }
+        });
+        let values = match result {
+            Ok(values) => values,
+            Err(err) => return CommandResponse::Error(ErrorReply::err(err.to_string())),
+        };
+
+        match sort.store {
+            None => CommandResponse::Array(
+                values
+                    .into_iter()
+                    .map(CommandResponse::BulkString)
+                    .collect(),
+            ),
+            Some(destination) => {
+                let len = values.len();
+                // Like Redis, an empty result deletes the destination.
+                if values.is_empty() {
+                    self.dbs[db].remove(&destination);
+                } else {
+                    let list = values.into_iter().map(Option::unwrap_or_default).collect();
+                    let entry = Entry::new(Value::List(list));
+                    self.dbs[db].insert(destination.clone(), entry);
+                    self.signal_key_ready(db, &destination);
+                }
+                CommandResponse::Integer(len_to_i64(len))
+            }
+        }
+    }
+
+    fn set_op(&mut self, db: DbIndex, set_op: SetOp) -> CommandResponse {
+        let SetOp { op, keys, store } = set_op;
+        let result = match self.dbs[db].get_sets(&keys) {
+            Ok(sets) => combine_sets(op, &sets),
+            Err(WrongType) => return wrong_type_error(),
+        };
+
+        match store {
+            None => bulk_string_array(result.iter().map(Cow::into_owned).collect()),
+            Some(destination) => {
+                let len = result.len();
+                // Like Redis, an empty result deletes the destination.
+                if result.is_empty() {
+                    self.dbs[db].remove(&destination);
+                } else {
+                    let entry = Entry::new(Value::Set(result));
+                    self.dbs[db].insert(destination, entry);
+                }
+                CommandResponse::Integer(len_to_i64(len))
+            }
+        }
+    }
+
+    fn zadd(&mut self, db: DbIndex, zadd: ZAdd) -> CommandResponse {
+        let ZAdd {
+            key,
+            existence,
+            comparison,
+            ch,
+            incr,
+            members,
+        } = zadd;
+        let Ok(zset) = self.dbs[db].get_or_create_zset(&key) else {
+            return wrong_type_error();
+        };
+
+        let (mut added, mut changed) = (0, 0);
+        let mut incr_result = None;
+        for (score, member) in members {
+            let current = zset.score(&member);
+            match (existence, current) {
+                (Some(Existence::Nx), Some(_)) | (Some(Existence::Xx), None) => continue,
+                _ => {}
+            }
+
+            let score = if incr {
+                let current = current.map_or(0.0, Score::value);
+                let Some(score) = Score::new(current + score.value()) else {
+                    self.dbs[db].remove_if_empty(&key);
+                    return CommandResponse::Error(ErrorReply::err(
+                        "resulting score is not a number (NaN)",
+                    ));
+                };
+                score
+            } else {
+                score
+            };
+
+            if let Some(current) = current {
+                let skip = match comparison {
+                    Some(Comparison::Gt) => score <= current,
+                    Some(Comparison::Lt) => score >= current,
+                    None => false,
+                };
+                if skip {
+                    continue;
+                }
+                if score != current {
+                    changed += 1;
+                }
+            } else {
+                added += 1;
+            }
+            zset.insert(member, score);
+            incr_result = Some(score);
+        }
+
+        // With XX, nothing may have been added to a new sorted set.
+        self.dbs[db].remove_if_empty(&key);
+        if incr {
+            CommandResponse::BulkString(incr_result.map(|s| RedisString::from(s.to_string())))
+        } else if ch {
+            CommandResponse::Integer(added + changed)
+        } else {
+            CommandResponse::Integer(added)
+        }
+    }
+
+    fn zrange(&mut self, db: DbIndex, zrange: &ZRange) -> CommandResponse {
+        let zset = match self.dbs[db].get_zset(&zrange.key) {
+            Ok(Some(zset)) => zset,
+            Ok(None) => return CommandResponse::Array(vec![]),
+            Err(WrongType) => return wrong_type_error(),
+        };
+
+        let members = match &zrange.by {
+            ZRangeBy::Rank { start, stop } => {
+                let len = zset.len();
+                let range = list_range(len, *start, *stop);
+                if zrange.rev {
+                    // Reversed ranks count down from the last member.
+                    let range = len - range.end..len - range.start;
+                    zset.range_by_rank(range).rev().collect()
+                } else {
+                    zset.range_by_rank(range).collect()
+                }
+            }
+            ZRangeBy::Score(range) if zrange.rev => {
+                apply_limit(zset.range_by_score(range).rev(), zrange.limit)
+            }
+            ZRangeBy::Score(range) => apply_limit(zset.range_by_score(range), zrange.limit),
+            ZRangeBy::Lex(range) if zrange.rev => {
+                apply_limit(zset.range_by_lex(range).rev(), zrange.limit)
+            }
+            ZRangeBy::Lex(range) => apply_limit(zset.range_by_lex(range), zrange.limit),
+        };
+
+        let mut elements = Vec::new();
+        for (member, score) in members {
+            elements.push(CommandResponse::BulkString(Some(member.clone())));
+            if zrange.withscores {
+                elements.push(CommandResponse::BulkString(Some(RedisString::from(
+                    score.to_string(),
+                ))));
+            }
+        }
+        CommandResponse::Array(elements)
+    }
+
+    fn zset_op(&mut self, db: DbIndex, zset_op: ZSetOp) -> CommandResponse {
+        // Check types (and expire keys) first, since the inputs are borrowed
+        // immutably below.
+        for key in &zset_op.keys {
+            match self.dbs[db].get_entry(key).map(|e| &e.value) {
+                None | Some(Value::Set(_) | Value::ZSet(_)) => {}
+                Some(_) => return wrong_type_error(),
+            }
+        }
+        let inputs: Vec<_> = zset_op
+            .keys
+            .iter()
+            .map(
+                |key| match self.dbs[db].entries().get(key).map(|e| &e.value) {
+                    Some(Value::Set(set)) => ZSetInput::Set(set),
+                    Some(Value::ZSet(zset)) => ZSetInput::ZSet(zset),
+                    _ => ZSetInput::Missing,
+                },
+            )
+            .collect();
+        let result = combine_zsets(&zset_op, &inputs);
+
+        match zset_op.store {
+            None => {
+                let mut elements = Vec::new();
+                for (member, score) in &result {
+                    elements.push(CommandResponse::BulkString(Some(member.clone())));
+                    if zset_op.withscores {
+                        elements.push(CommandResponse::BulkString(Some(RedisString::from(
+                            score.to_string(),
+                        ))));
+                    }
+                }
+                CommandResponse::Array(elements)
+            }
+            Some(destination) => {
+                let len = result.len();
+                // Like Redis, an empty result deletes the destination.
+                if result.is_empty() {
+                    self.dbs[db].remove(&destination);
+                } else {
+                    let entry = Entry::new(Value::ZSet(result));
+                    self.dbs[db].insert(destination, entry);
+                }
+                CommandResponse::Integer(len_to_i64(len))
+            }
+        }
+    }
+
+    fn zrandmember(&mut self, db: DbIndex, zrandmember: &ZRandMember) -> CommandResponse {
+        let zset = match self.dbs[db].get_zset(&zrandmember.key) {
+            Ok(zset) => zset,
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let mut members: Vec<_> = zset.iter().flat_map(|z| z.iter()).collect();
+
+        let Some(count) = zrandmember.count else {
+            if members.is_empty() {
+                return CommandResponse::BulkString(None);
+            }
+            let (member, _) = members[self.rng.below(members.len())];
+            return CommandResponse::BulkString(Some(member.clone()));
+        };
+
+        let count = usize::try_from(count.unsigned_abs()).unwrap_or(usize::MAX);
+        let picked = if members.is_empty() {
+            Vec::new()
+        } else if zrandmember.count.is_some_and(|c| c < 0) {
+            // Members may repeat.
+            (0..count)
+                .map(|_| members[self.rng.below(members.len())])
+                .collect()
+        } else {
+            self.rng.partial_shuffle(&mut members, count);
+            members.truncate(count);
+            members
+        };
+
+        let mut elements = Vec::new();
+        for (member, score) in picked {
+            elements.push(CommandResponse::BulkString(Some(member.clone())));
+            if zrandmember.withscores {
+                elements.push(CommandResponse::BulkString(Some(RedisString::from(
+                    score.to_string(),
+                ))));
+            }
+        }
+        CommandResponse::Array(elements)
+    }
+
+    fn zrank(&mut self, db: DbIndex, zrank: &ZRank) -> CommandResponse {
+        let zset = match self.dbs[db].get_zset(&zrank.key) {
+            Ok(zset) => zset,
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let rank = zset.and_then(|zset| {
+            let rank = zset.rank(&zrank.member)?;
+            let rank = if zrank.rev {
+                zset.len() - 1 - rank
+            } else {
+                rank
+            };
+            Some((rank, zset.score(&zrank.member)?))
+        });
+
+        match (rank, zrank.withscore) {
+            (None, false) => CommandResponse::BulkString(None),
+            (None, true) => CommandResponse::NullArray,
+            (Some((rank, _)), false) => CommandResponse::Integer(len_to_i64(rank)),
+            (Some((rank, score)), true) => CommandResponse::Array(vec![
+                CommandResponse::Integer(len_to_i64(rank)),
+                CommandResponse::BulkString(Some(RedisString::from(score.to_string()))),
+            ]),
+        }
+    }
+
+    fn hscan(&mut self, db: DbIndex, hscan: &HScan) -> CommandResponse {
+        let hash = match self.dbs[db].get_hash(&hscan.key) {
+            Ok(hash) => hash,
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let count = hscan.count.unwrap_or(scan::DEFAULT_COUNT);
+        let (next_cursor, fields) = scan::scan(
+            hash.as_deref().into_iter().flatten(),
+            hscan.cursor,
+            count,
+            |(f, _)| scan::position(*f),
+        );
+
+        let mut elements = Vec::new();
+        for (field, value) in fields {
+            if let Some(pattern) = &hscan.pattern {
+                if !glob::matches(pattern.as_bytes(), field.as_bytes()) {
+                    continue;
+                }
+            }
+            elements.push(CommandResponse::BulkString(Some(field.clone())));
+            if !hscan.novalues {
+                elements.push(CommandResponse::BulkString(Some(value.clone())));
+            }
+        }
+
+        CommandResponse::Array(vec![
+            CommandResponse::BulkString(Some(RedisString::from(next_cursor.to_string()))),
+            CommandResponse::Array(elements),
+        ])
+    }
+
+    fn sscan(&mut self, db: DbIndex, sscan: &SScan) -> CommandResponse {
+        let set = match self.dbs[db].get_set(&sscan.key) {
+            Ok(set) => set,
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let count = sscan.count.unwrap_or(scan::DEFAULT_COUNT);
+        let (next_cursor, members) = scan::scan(
+            set.into_iter().flat_map(|s| s.iter()),
+            sscan.cursor,
+            count,
+            |m| scan::position(&**m),
+        );
+
+        let mut elements = Vec::new();
+        for member in members {
+            if let Some(pattern) = &sscan.pattern {
+                if !glob::matches(pattern.as_bytes(), member.as_bytes()) {
+                    continue;
+                }
+            }
+            elements.push(CommandResponse::BulkString(Some(member.into_owned())));
+        }
+
+        CommandResponse::Array(vec![
+            CommandResponse::BulkString(Some(RedisString::from(next_cursor.to_string()))),
+            CommandResponse::Array(elements),
+        ])
+    }
+
+    fn zscan(&mut self, db: DbIndex, zscan: &ZScan) -> CommandResponse {
+        let zset = match self.dbs[db].get_zset(&zscan.key) {
+            Ok(zset) => zset,
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let count = zscan.count.unwrap_or(scan::DEFAULT_COUNT);
+        let (next_cursor, members) = scan::scan(
+            zset.iter().flat_map(|z| z.iter()),
+            zscan.cursor,
+            count,
+            |(m, _)| scan::position(*m),
+        );
+
+        let mut elements = Vec::new();
+        for (member, score) in members {
+            if let Some(pattern) = &zscan.pattern {
+                if !glob::matches(pattern.as_bytes(), member.as_bytes()) {
+                    continue;
+                }
+            }
+            elements.push(CommandResponse::BulkString(Some(member.clone())));
+            elements.push(CommandResponse::BulkString(Some(RedisString::from(
+                score.to_string(),
+            ))));
+        }
+
+        CommandResponse::Array(vec![
+            CommandResponse::BulkString(Some(RedisString::from(next_cursor.to_string()))),
+            CommandResponse::Array(elements),
+        ])
+    }
+
+    fn bitpos(&mut self, db: DbIndex, bitpos: &BitPos) -> CommandResponse {
+        let s = match self.dbs[db].get_string(&bitpos.key) {
+            Ok(Some(s)) => s.to_redis_string(),
+            // A missing key is an empty string, which is all clear bits.
+            Ok(None) => return CommandResponse::Integer(if bitpos.bit { -1 } else { 0 }),
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let bytes = s.as_bytes();
+
+        let start = bitpos.start.unwrap_or(0);
+        let end = bitpos.end.unwrap_or(-1);
+        let bits = bit_range(bytes.len(), start, end, bitpos.unit);
+        if bits.is_empty() {
+            return CommandResponse::Integer(-1);
+        }
+        match bitmap::position(bytes, bitpos.bit, bits.clone()) {
+            Some(position) => CommandResponse::Integer(len_to_i64(position)),
+            // Without an explicit end, the string is treated as if it were
+            // padded with clear bits, so the first one is just past the end.
+            None if !bitpos.bit && bitpos.end.is_none() => {
+                CommandResponse::Integer(len_to_i64(bits.end))
+            }
+            None => CommandResponse::Integer(-1),
+        }
+    }
+
+    fn xrange(&mut self, db: DbIndex, xrange: &XRange) -> CommandResponse {
+        let stream = match self.dbs[db].get_stream(&xrange.key) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => return CommandResponse::Array(vec![]),
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let entries = stream.range(xrange.start, xrange.end);
+        let count = xrange.count.unwrap_or(usize::MAX);
+        let entries: Vec<_> = if xrange.rev {
+            entries.rev().take(count).collect()
+        } else {
+            entries.take(count).collect()
+        };
+        CommandResponse::Array(
+            entries
+                .into_iter()
+                .map(|(id, fields)| stream_entry(*id, fields))
+                .collect(),
+        )
+    }
+
+    fn xread(&mut self, db: DbIndex, xread: &XRead) -> CommandResponse {
+        // Check types (and expire keys) first, since the streams are borrowed
+        // immutably below.
+        for key in &xread.keys {
+            if self.dbs[db].get_stream(key).is_err() {
+                return wrong_type_error();
+            }
+        }
+
+        let mut streams = Vec::new();
+        for (key, id) in xread.keys.iter().zip(&xread.ids) {
+            let Some(Entry {
+                value: Value::Stream(stream),
+                ..
+            }) = self.dbs[db].entries().get(key)
+            else {
+                continue;
+            };
+            let id = match id {
+                ReadId::After(id) => *id,
+                ReadId::Last => stream.last_id(),
+            };
+            let entries: Vec<_> = stream
+                .after(id)
+                .take(xread.count.unwrap_or(usize::MAX))
+                .map(|(id, fields)| stream_entry(*id, fields))
+                .collect();
+            if !entries.is_empty() {
+                streams.push(CommandResponse::Array(vec![
+                    CommandResponse::BulkString(Some(key.clone())),
+                    CommandResponse::Array(entries),
+                ]));
+            }
+        }
+
+        if streams.is_empty() {
+            CommandResponse::NullArray
+        } else {
+            CommandResponse::Array(streams)
+        }
+    }
+
+    fn xreadgroup(&mut self, db: DbIndex, xreadgroup: &XReadGroup) -> CommandResponse {
+        let now = unix_time_millis();
+        let mut streams = Vec::new();
+        for (key, id) in xreadgroup.keys.iter().zip(&xreadgroup.ids) {
+            let entries = match self.dbs[db].get_stream(key) {
+                Ok(stream) => stream.and_then(|stream| {
+                    stream.read_group(
+                        &xreadgroup.group,
+                        &xreadgroup.consumer,
+                        *id,
+                        xreadgroup.count.unwrap_or(usize::MAX),
+                        xreadgroup.noack,
+                        now,
+                    )
+                }),
+                Err(WrongType) => return wrong_type_error(),
+            };
+            let Some(entries) = entries else {
+                return CommandResponse::Error(ErrorReply::new(
+                    ErrorCode::NoGroup,
+                    format!(
+                        "{} in XREADGROUP with GROUP option",
+                        no_group_message(key, &xreadgroup.group)
+                    ),
+                ));
+            };
+            // Like Redis, reading new entries omits streams without any,
+            // while re-reading pending entries always includes the stream.
+            if entries.is_empty() && *id == GroupReadId::New {
+                continue;
+            }
+            let entries = entries
+                .into_iter()
+                .map(|(id, fields)| {
+                    // Entries deleted after they were delivered have no fields.
+                    fields.map_or_else(
+                        || {
+                            CommandResponse::Array(vec![
+                                CommandResponse::BulkString(Some(RedisString::from(
+                                    id.to_string(),
+                                ))),
+                                CommandResponse::NullArray,
+                            ])
+                        },
+                        |fields| stream_entry(id, &fields),
+                    )
+                })
+                .collect();
+            streams.push(CommandResponse::Array(vec![
+                CommandResponse::BulkString(Some(key.clone())),
+                CommandResponse::Array(entries),
+            ]));
+        }
+
+        if streams.is_empty() {
+            CommandResponse::NullArray
+        } else {
+            CommandResponse::Array(streams)
+        }
+    }
+
+    fn geosearch(&mut self, db: DbIndex, geosearch: &GeoSearch) -> CommandResponse {
+        let zset = match self.dbs[db].get_zset(&geosearch.key) {
+            Ok(Some(zset)) => zset,
+            Ok(None) => return CommandResponse::Array(vec![]),
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let center = match &geosearch.from {
+            GeoOrigin::LonLat(coords) => *coords,
+            GeoOrigin::Member(member) => match zset.score(member) {
+                Some(score) => geo_position(score),
+                None => {
+                    return CommandResponse::Error(ErrorReply::err(
+                        "could not decode requested zset member",
+                    ))
+                }
+            },
+        };
+
+        let shape = geosearch.shape.scale(geosearch.unit.meters());
+        let mut matches = Vec::new();
+        'search: for hashes in geo::covering_ranges(center, shape) {
+            let scores = ScoreRange {
+                min: ScoreBound {
+                    score: geo::to_score(hashes.start),
+                    exclusive: false,
+                },
+                max: ScoreBound {
+                    score: geo::to_score(hashes.end),
+                    exclusive: true,
+                },
+            };
+            for (member, score) in zset.range_by_score(&scores) {
+                let hash = geo::from_score(score);
+                let coords = geo::decode(hash);
+                let Some(distance) = shape.contains(center, coords) else {
+                    continue;
+                };
+                matches.push((member, distance, hash, coords));
+                if geosearch.any && Some(matches.len()) == geosearch.count {
+                    break 'search;
+                }
+            }
+        }
+
+        let default_order = (geosearch.count.is_some() && !geosearch.any).then_some(SortOrder::Asc);
+        match geosearch.order.or(default_order) {
+            Some(SortOrder::Asc) => matches.sort_by(|a, b| a.1.total_cmp(&b.1)),
+            Some(SortOrder::Desc) => matches.sort_by(|a, b| b.1.total_cmp(&a.1)),
+            None => {}
+        }
+        matches.truncate(geosearch.count.unwrap_or(usize::MAX));
+
+        let plain = !(geosearch.with_coord || geosearch.with_dist || geosearch.with_hash);
+        CommandResponse::Array(
+            matches
+                .into_iter()
+                .map(|(member, distance, hash, coords)| {
+                    let member = CommandResponse::BulkString(Some(member.clone()));
+                    if plain {
+                        return member;
+                    }
+                    let mut item = vec![member];
+                    if geosearch.with_dist {
+                        let distance = distance / geosearch.unit.meters();
+                        item.push(CommandResponse::BulkString(Some(RedisString::from(
+                            format!("{distance:.4}"),
+                        ))));
+                    }
+                    if geosearch.with_hash {
+                        item.push(CommandResponse::Integer(
+                            i64::try_from(hash).expect("geohashes are 52 bits"),
+                        ));
+                    }
+                    if geosearch.with_coord {
+                        item.push(coordinates_response(coords));
+                    }
+                    CommandResponse::Array(item)
+                })
+                .collect(),
+        )
+    }
+
+    fn xinfo(&mut self, db: DbIndex, xinfo: &XInfo) -> CommandResponse {
+        let (XInfo::Stream { key } | XInfo::Groups { key } | XInfo::Consumers { key, .. }) = xinfo;
+        let stream = match self.dbs[db].get_stream(key) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => return CommandResponse::Error(ErrorReply::err("no such key")),
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let id_string =
+            |id: StreamId| CommandResponse::BulkString(Some(RedisString::from(id.to_string())));
+        let entry = |entry: Option<(&StreamId, &Fields)>| {
+            entry.map_or(CommandResponse::BulkString(None), |(id, fields)| {
+                stream_entry(*id, fields)
+            })
+        };
+
+        match xinfo {
+            XInfo::Stream { .. } => info_map(vec![
+                ("length", CommandResponse::Integer(len_to_i64(stream.len()))),
+                ("last-generated-id", id_string(stream.last_id())),
+                ("max-deleted-entry-id", id_string(stream.max_deleted_id())),
+                (
+                    "entries-added",
+                    CommandResponse::Integer(
+                        i64::try_from(stream.entries_added()).unwrap_or(i64::MAX),
+                    ),
+                ),
+                (
+                    "recorded-first-entry-id",
+                    id_string(stream.first_entry().map_or(StreamId::MIN, |(id, _)| *id)),
+                ),
+                (
+                    "groups",
+                    CommandResponse::Integer(len_to_i64(stream.groups().len())),
+                ),
+                ("first-entry", entry(stream.first_entry())),
+                ("last-entry", entry(stream.last_entry())),
+            ]),
+            XInfo::Groups { .. } => CommandResponse::Array(
+                stream
+                    .groups()
+                    .iter()
+                    .map(|(name, group)| {
+                        info_map(vec![
+                            ("name", CommandResponse::BulkString(Some(name.clone()))),
+                            (
+                                "consumers",
+                                CommandResponse::Integer(len_to_i64(group.consumers.len())),
+                            ),
+                            (
+                                "pending",
+                                CommandResponse::Integer(len_to_i64(group.pending.len())),
+                            ),
+                            ("last-delivered-id", id_string(group.last_delivered)),
+                        ])
+                    })
+                    .collect(),
+            ),
+            XInfo::Consumers { group: name, .. } => {
+                let Some(group) = stream.groups().get(name) else {
+                    return no_group_error(key, name);
+                };
+                let now = unix_time_millis();
+                CommandResponse::Array(
+                    group
+                        .consumers
+                        .iter()
+                        .map(|(name, consumer)| {
+                            info_map(vec![
+                                ("name", CommandResponse::BulkString(Some(name.clone()))),
+                                (
+                                    "pending",
+                                    CommandResponse::Integer(len_to_i64(group.pending_count(name))),
+                                ),
+                                (
+                                    "idle",
+                                    CommandResponse::Integer((now - consumer.seen_at).max(0)),
+                                ),
+                            ])
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    fn xpending(&mut self, db: DbIndex, xpending: &XPending) -> CommandResponse {
+        let no_group = || no_group_error(&xpending.key, &xpending.group);
+        let group = match self.dbs[db].get_stream(&xpending.key) {
+            Ok(Some(stream)) => match stream.groups().get(&xpending.group) {
+                Some(group) => group,
+                None => return no_group(),
+            },
+            Ok(None) => return no_group(),
+            Err(WrongType) => return wrong_type_error(),
+        };
+
+        let now = unix_time_millis();
+        let Some(range) = &xpending.range else {
+            // The summary form: the number of pending entries, the smallest
+            // and largest IDs, and how many each consumer owns.
+            let (Some(first), Some(last)) = (
+                group.pending.keys().next(),
+                group.pending.keys().next_back(),
+            ) else {
+                return CommandResponse::Array(vec![
+                    CommandResponse::Integer(0),
+                    CommandResponse::BulkString(None),
+                    CommandResponse::BulkString(None),
+                    CommandResponse::NullArray,
+                ]);
+            };
+            let mut consumers: BTreeMap<&RedisString, usize> = BTreeMap::new();
+            for pending in group.pending.values() {
+                *consumers.entry(&pending.consumer).or_default() += 1;
+            }
+            let consumers = consumers
+                .into_iter()
+                .map(|(consumer, count)| {
+                    CommandResponse::Array(vec![
+                        CommandResponse::BulkString(Some(consumer.clone())),
+                        CommandResponse::BulkString(Some(RedisString::from(count.to_string()))),
+                    ])
+                })
+                .collect();
+            return CommandResponse::Array(vec![
+                CommandResponse::Integer(len_to_i64(group.pending.len())),
+                CommandResponse::BulkString(Some(RedisString::from(first.to_string()))),
+                CommandResponse::BulkString(Some(RedisString::from(last.to_string()))),
+                CommandResponse::Array(consumers),
+            ]);
+        };
+
+        let entries = group
+            .pending_range(range.start, range.end)
+            .filter(|(_, pending)| {
+                range
+                    .consumer
+                    .as_ref()
+                    .is_none_or(|consumer| pending.consumer == *consumer)
+            })
+            .map(|(id, pending)| (id, pending, (now - pending.delivered_at).max(0)))
+            .filter(|(_, _, idle)| range.min_idle.is_none_or(|min_idle| *idle >= min_idle))
+            .take(range.count)
+            .map(|(id, pending, idle)| {
+                CommandResponse::Array(vec![
+                    CommandResponse::BulkString(Some(RedisString::from(id.to_string()))),
+                    CommandResponse::BulkString(Some(pending.consumer.clone())),
+                    CommandResponse::Integer(idle),
+                    CommandResponse::Integer(i64::try_from(pending.deliveries).unwrap_or(i64::MAX)),
+                ])
+            })
+            .collect();
+        CommandResponse::Array(entries)
+    }
+
+    fn xclaim(&mut self, db: DbIndex, xclaim: &XClaim) -> CommandResponse {
+        let now = unix_time_millis();
+        let claim = Claim {
+            min_idle: xclaim.min_idle,
+            delivered_at: xclaim.time.or_else(|| xclaim.idle.map(|idle| now - idle)),
+            retry_count: xclaim.retry_count,
+            force: xclaim.force,
+            justid: xclaim.justid,
+        };
+        let stream = match self.dbs[db].get_stream(&xclaim.key) {
+            Ok(stream) => stream,
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let Some((stream, claimed)) = stream.and_then(|stream| {
+            let claimed =
+                stream.claim(&xclaim.group, &xclaim.consumer, &xclaim.ids, &claim, now)?;
+            Some((stream, claimed))
+        }) else {
+            return no_group_error(&xclaim.key, &xclaim.group);
+        };
+        claimed_entries(stream, &claimed, xclaim.justid)
+    }
+
+    fn xautoclaim(&mut self, db: DbIndex, xautoclaim: &XAutoClaim) -> CommandResponse {
+        let now = unix_time_millis();
+        let claim = Claim {
+            min_idle: xautoclaim.min_idle,
+            justid: xautoclaim.justid,
+            ..Claim::default()
+        };
+        let stream = match self.dbs[db].get_stream(&xautoclaim.key) {
+            Ok(stream) => stream,
+            Err(WrongType) => return wrong_type_error(),
+        };
+        let Some((stream, (next, claimed, deleted))) = stream.and_then(|stream| {
+            let result = stream.auto_claim(
+                &xautoclaim.group,
+                &xautoclaim.consumer,
+                xautoclaim.start,
+                xautoclaim.count.unwrap_or(DEFAULT_AUTOCLAIM_COUNT),
+                &claim,
+                now,
+            )?;
+            Some((stream, result))
+        }) else {
+            return no_group_error(&xautoclaim.key, &xautoclaim.group);
+        };
+        let id_string =
+            |id: StreamId| CommandResponse::BulkString(Some(RedisString::from(id.to_string())));
+        CommandResponse::Array(vec![
+            id_string(next),
+            claimed_entries(stream, &claimed, xautoclaim.justid),
+            CommandResponse::Array(deleted.into_iter().map(id_string).collect()),
+        ])
+    }
+
+    fn scan(&mut self, db: DbIndex, scan: Scan) -> CommandResponse {
+        let Scan {
+            cursor,
+            pattern,
+            count,
+            value_type,
+        } = scan;
+
+        let now = unix_time_millis();
+        let count = count.unwrap_or(scan::DEFAULT_COUNT);
+        let (next_cursor, entries) = scan::take(self.dbs[db].entries().scan(cursor), count);
+
+        let mut keys = Vec::new();
+        let mut expired = Vec::new();
+        for (key, entry) in entries {
+            if self.dbs[db].is_expired(key, now) {
+                expired.push(key.clone());
+                continue;
+            }
+            if let Some(pattern) = &pattern {
+                if !glob::matches(pattern.as_bytes(), key.as_bytes()) {
+                    continue;
+                }
+            }
+            if let Some(value_type) = &value_type {
+                if !value_type
+                    .as_bytes()
+                    .eq_ignore_ascii_case(entry.value.type_name().as_bytes())
+                {
+                    continue;
+                }
+            }
+            keys.push(CommandResponse::BulkString(Some(key.clone())));
+        }
+        for key in expired {
+            self.dbs[db].expire(&key);
+        }
+
+        CommandResponse::Array(vec![
+            CommandResponse::BulkString(Some(RedisString::from(next_cursor.to_string()))),
+            CommandResponse::Array(keys),
+        ])
+    }
+}
+
+/// The server as a function called in database `db` sees it.
+struct FunctionContext<'a> {
+    core: &'a mut ServerCore,
+    db: DbIndex,
+    read_only: bool,
+}
+
+impl Context for FunctionContext<'_> {
+    fn db(&mut self) -> &mut Db {
+        &mut self.core.dbs[self.db]
+    }
+
+    fn call(&mut self, args: &[RedisString]) -> CommandResponse {
+        let names: Vec<&[u8]> = args.iter().map(RedisString::as_bytes).collect();
+        let Some(spec) = command_table::resolve(&names) else {
+            return CommandResponse::Error(ErrorReply::err(
+                "Unknown Redis command called from script",
+            ));
+        };
+        let message = Message::Array(
+            args.iter()
+                .map(|arg| Message::BulkString(Some(arg.clone())))
+                .collect(),
+        );
+        let command = match Command::parse_resp(&message) {
+            Ok(command) => command,
+            Err(e) => return CommandResponse::Error(ErrorReply::err(e.to_string())),
+        };
+        // Besides the commands flagged `noscript`, the ones the client
+        // thread or `process_client_command` handle need a connection.
+        let needs_connection = matches!(
+            command,
+            Command::Select(_)
+                | Command::Client(_)
+                | Command::Asking
+                | Command::ReadOnly
+                | Command::ReadWrite
+                | Command::Acl(Acl::WhoAmI)
+        );
+        let flags = spec.flags();
+        if needs_connection || flags.contains(&Flag::NoScript) {
+            return CommandResponse::Error(ErrorReply::err(
+                "This Redis command is not allowed from script",
+            ));
+        }
+        if self.read_only && flags.contains(&Flag::Write) {
+            return CommandResponse::Error(ErrorReply::err(
+                "Write commands are not allowed from read-only scripts.",
+            ));
+        }
+        self.core.process_command(self.db, command)
+    }
+}
+
+/// The keys a blocking command waits on, and for how long.
+fn blocking_keys(command: &Command) -> Option<(&[RedisString], Duration)> {
+    match command {
+        Command::BPop(BPop { keys, timeout, .. })
+        | Command::XRead(XRead {
+            keys,
+            block: Some(timeout),
+            ..
+        }) => Some((keys, *timeout)),
+        Command::BLMPop(BLMPop { lmpop, timeout }) => Some((&lmpop.keys, *timeout)),
+        Command::BLMove(BLMove { lmove, timeout }) => {
+            Some((std::slice::from_ref(&lmove.source), *timeout))
+        }
+        _ => None,
+    }
+}
+
+/// How a command accesses the keyspace, which decides what client-side caching
+/// tracks and invalidates.
+#[derive(Debug)]
+enum KeyAccess {
+    /// A read-only command, whose keys are tracked for the client.
+    Read(Vec<RedisString>),
+
+    /// A command that may modify these keys.
+    Write(Vec<RedisString>),
+
+    /// A command that may modify every key, like `FLUSHALL`.
+    WriteAll,
+}
+
+#[allow(clippy::too_many_lines)]
+fn key_access(command: &Command) -> KeyAccess {
+    let read = |keys: &[RedisString]| KeyAccess::Read(keys.to_vec());
+    let write = |keys: &[RedisString]| KeyAccess::Write(keys.to_vec());
+    match command {
+        Command::Ping
+        | Command::Save
+        | Command::BgSave
+        | Command::LastSave
+        | Command::BgRewriteAof
+        | Command::Scan(_)
+        | Command::Select(_)
+        | Command::Client(_)
+        | Command::ReplConf(_)
+        | Command::PSync(_)
+        | Command::ReplicaOf(_)
+        | Command::Wait(_)
+        | Command::Info(_)
+        | Command::Command(_)
+        | Command::Subscribe(_)
+        | Command::Unsubscribe(_)
+        | Command::PSubscribe(_)
+        | Command::PUnsubscribe(_)
+        | Command::Publish(_)
+        | Command::Script(_)
+        | Command::Cluster(_)
+        | Command::Asking
+        | Command::ReadOnly
+        | Command::ReadWrite
+        | Command::Memory(Memory::Stats | Memory::Doctor)
+        | Command::Latency(_)
+        | Command::Debug(
+            DebugCommand::Sleep { .. }
+            | DebugCommand::SetActiveExpire { .. }
+            | DebugCommand::StringMatchLen,
+        )
+        | Command::Config(_)
+        | Command::Acl(_)
+        | Command::Auth(_)
+        | Command::Hello(_)
+        | Command::RawCommand(_) => read(&[]),
+        Command::FlushDb(_) | Command::FlushAll(_) => KeyAccess::WriteAll,
+
+        Command::Get(Get { key })
+        | Command::Ttl(Ttl { key, .. })
+        | Command::ExpireTime(ExpireTime { key, .. })
+        | Command::Dump(Dump { key })
+        | Command::Object(
+            Object::Encoding { key }
+            | Object::RefCount { key }
+            | Object::IdleTime { key }
+            | Object::Freq { key },
+        )
+        | Command::Memory(Memory::Usage { key, .. })
+        | Command::Debug(DebugCommand::Object { key })
+        | Command::LLen(LLen { key })
+        | Command::LRange(LRange { key, .. })
+        | Command::LIndex(LIndex { key, .. })
+        | Command::HGet(HGet { key, .. })
+        | Command::HGetAll(HGetAll { key })
+        | Command::HMGet(HMGet { key, .. })
+        | Command::HKeys(HKeys { key })
+        | Command::HVals(HVals { key })
+        | Command::HLen(HLen { key })
+        | Command::HExists(HExists { key, .. })
+        | Command::HStrLen(HStrLen { key, .. })
+        | Command::HScan(HScan { key, .. })
+        | Command::SMembers(SMembers { key })
+        | Command::SIsMember(SIsMember { key, .. })
+        | Command::SMIsMember(SMIsMember { key, .. })
+        | Command::SCard(SCard { key })
+        | Command::SScan(SScan { key, .. })
+        | Command::ZScore(ZScore { key, .. })
+        | Command::ZCard(ZCard { key })
+        | Command::ZRange(ZRange { key, .. })
+        | Command::ZRank(ZRank { key, .. })
+        | Command::ZCount(ZCount { key, .. })
+        | Command::ZRandMember(ZRandMember { key, .. })
+        | Command::ZMScore(ZMScore { key, .. })
+        | Command::ZScan(ZScan { key, .. })
+        | Command::GetBit(GetBit { key, .. })
+        | Command::BitCount(BitCount { key, .. })
+        | Command::BitPos(BitPos { key, .. })
+        | Command::XLen(XLen { key })
+        | Command::XRange(XRange { key, .. })
+        | Command::XPending(XPending { key, .. })
+        | Command::XInfo(
+            XInfo::Stream { key } | XInfo::Groups { key } | XInfo::Consumers { key, .. },
+        )
+        | Command::GeoSearch(GeoSearch { key, .. })
+        | Command::GeoDist(GeoDist { key, .. })
+        | Command::GeoPos(GeoPos { key, .. })
+        | Command::GeoHash(GeoHash { key, .. }) => read(std::slice::from_ref(key)),
+        Command::Touch(Touch { keys })
+        | Command::SInterCard(SInterCard { keys, .. })
+        | Command::XRead(XRead { keys, .. })
+        | Command::Eval(Eval {
+            keys,
+            read_only: true,
+            ..
+        })
+        | Command::EvalSha(EvalSha {
+            keys,
+            read_only: true,
+            ..
+        })
+        | Command::FCall(FCall {
+            keys,
+            read_only: true,
+            ..
+        }) => read(keys),
+        Command::Sort(sort) | Command::SortRo(sort) => sort.store.as_ref().map_or_else(
+            || read(std::slice::from_ref(&sort.key)),
+            |store| write(std::slice::from_ref(store)),
+        ),
+        Command::SetOp(SetOp { keys, store, .. }) | Command::ZSetOp(ZSetOp { keys, store, .. }) => {
+            store
+                .as_ref()
+                .map_or_else(|| read(keys), |store| write(std::slice::from_ref(store)))
+        }
+
+        Command::Set(Set { key, .. })
+        | Command::Expire(Expire { key, .. })
+        | Command::Persist(Persist { key })
+        | Command::Restore(Restore { key, .. })
+        | Command::Move(Move { key, .. })
+        | Command::Push(Push { key, .. })
+        | Command::Pop(Pop { key, .. })
+        | Command::LSet(LSet { key, .. })
+        | Command::LInsert(LInsert { key, .. })
+        | Command::LRem(LRem { key, .. })
+        | Command::LTrim(LRange { key, .. })
+        | Command::HSet(HSet { key, .. })
+        | Command::HDel(HDel { key, .. })
+        | Command::HSetNx(HSetNx { key, .. })
+        | Command::SAdd(SAdd { key, .. })
+        | Command::SRem(SRem { key, .. })
+        | Command::ZAdd(ZAdd { key, .. })
+        | Command::ZRem(ZRem { key, .. })
+        | Command::ZIncrBy(ZIncrBy { key, .. })
+        | Command::SetBit(SetBit { key, .. })
+        | Command::XAdd(XAdd { key, .. })
+        | Command::XGroup(XGroup::Create { key, .. })
+        | Command::XAck(XAck { key, .. })
+        | Command::XClaim(XClaim { key, .. })
+        | Command::XAutoClaim(XAutoClaim { key, .. })
+        | Command::XTrim(XTrim { key, .. })
+        | Command::XDel(XDel { key, .. })
+        | Command::GeoAdd(GeoAdd { key, .. }) => write(std::slice::from_ref(key)),
+        Command::Del(Del { keys })
+        | Command::Unlink(Unlink { keys })
+        | Command::BPop(BPop { keys, .. })
+        | Command::XReadGroup(XReadGroup { keys, .. })
+        // Scripts can write to any of their keys, unless they're read-only.
+        | Command::Eval(Eval {
+            keys,
+            read_only: false,
+            ..
+        })
+        | Command::EvalSha(EvalSha {
+            keys,
+            read_only: false,
+            ..
+        })
+        | Command::FCall(FCall {
+            keys,
+            read_only: false,
+            ..
+        }) => write(keys),
+        Command::LMPop(lmpop) | Command::BLMPop(BLMPop { lmpop, .. }) => write(&lmpop.keys),
+        Command::LMove(lmove) | Command::BLMove(BLMove { lmove, .. }) => {
+            write(&[lmove.source.clone(), lmove.destination.clone()])
+        }
+    }
+}
+
+/// Whether a command can add data, so it fails when the keyspace is over
+/// `maxmemory` and nothing can be evicted. Writes that only remove data are
+/// still allowed, since they're how clients free up memory.
+fn may_use_memory(command: &Command) -> bool {
+    match command {
+        Command::Del(_)
+        | Command::Unlink(_)
+        | Command::Expire(_)
+        | Command::Persist(_)
+        | Command::FlushDb(_)
+        | Command::FlushAll(_)
+        | Command::Move(_)
+        | Command::Pop(_)
+        | Command::LMPop(_)
+        | Command::BPop(_)
+        | Command::BLMPop(_)
+        | Command::LRem(_)
+        | Command::LTrim(_)
+        | Command::HDel(_)
+        | Command::SRem(_)
+        | Command::ZRem(_)
+        | Command::XAck(_)
+        | Command::XTrim(_)
+        | Command::XDel(_) => false,
+        _ => !matches!(key_access(command), KeyAccess::Read(_)),
+    }
+}
+
+/// Every key a command touches, which in cluster mode must all be in the same
+/// slot. Unlike `key_access`, this includes the keys that commands storing
+/// their result only read from.
+fn command_keys(command: &Command) -> Vec<RedisString> {
+    match command {
+        Command::Sort(sort) | Command::SortRo(sort) => std::iter::once(&sort.key)
+            .chain(&sort.store)
+            .cloned()
+            .collect(),
+        Command::SetOp(SetOp { keys, store, .. }) | Command::ZSetOp(ZSetOp { keys, store, .. }) => {
+            keys.iter().chain(store).cloned().collect()
+        }
+        _ => match key_access(command) {
+            KeyAccess::Read(keys) | KeyAccess::Write(keys) => keys,
+            KeyAccess::WriteAll => Vec::new(),
+        },
+    }
+}
+
+/// An invalidation message for client-side caching, which is a pub/sub
+/// message on `__redis__:invalidate`. `keys` is null when every key was
+/// invalidated.
+fn invalidation(keys: CommandResponse) -> Vec<CommandResponse> {
+    vec![
+        CommandResponse::BulkString(Some(RedisString::from("message"))),
+        CommandResponse::BulkString(Some(RedisString::from("__redis__:invalidate"))),
+        keys,
+    ]
+}
+
+/// The reply to `CLUSTER SLOTS`: each range of slots with the node serving
+/// it.
+fn cluster_slots(cluster: &ClusterState) -> CommandResponse {
+    CommandResponse::Array(
+        cluster
+            .slot_ranges()
+            .into_iter()
+            .map(|(range, node)| {
+                CommandResponse::Array(vec![
+                    CommandResponse::Integer(i64::from(*range.start())),
+                    CommandResponse::Integer(i64::from(*range.end())),
+                    CommandResponse::Array(vec![
+                        CommandResponse::BulkString(Some(RedisString::from(node.host.as_str()))),
+                        CommandResponse::Integer(i64::from(node.port)),
+                        CommandResponse::BulkString(Some(RedisString::from(node.id.as_str()))),
+                    ]),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// The reply to `CLUSTER SHARDS`. Without replicas, every node is a shard of
+/// its own.
+fn cluster_shards(cluster: &ClusterState) -> CommandResponse {
+    let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+    CommandResponse::Array(
+        cluster
+            .nodes()
+            .iter()
+            .map(|node| {
+                let slots = cluster
+                    .node_slot_ranges(node)
+                    .into_iter()
+                    .flat_map(|range| [*range.start(), *range.end()])
+                    .map(|slot| CommandResponse::Integer(i64::from(slot)))
+                    .collect();
+                let node = CommandResponse::Array(vec![
+                    bulk("id"),
+                    bulk(&node.id),
+                    bulk("port"),
+                    CommandResponse::Integer(i64::from(node.port)),
+                    bulk("ip"),
+                    bulk(&node.host),
+                    bulk("endpoint"),
+                    bulk(&node.host),
+                    bulk("role"),
+                    bulk("master"),
+                    bulk("replication-offset"),
+                    CommandResponse::Integer(0),
+                    bulk("health"),
+                    bulk("online"),
+                ]);
+                CommandResponse::Array(vec![
+                    bulk("slots"),
+                    CommandResponse::Array(slots),
+                    bulk("nodes"),
+                    CommandResponse::Array(vec![node]),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// Whether a command that's blocked on `key` can be served. Keys holding the
+/// wrong type are ready, so the command can fail with WRONGTYPE.
+fn key_ready(db: &mut Db, key: &RedisString, command: &Command) -> bool {
+    let Some(entry) = db.peek_entry(key) else {
+        return false;
+    };
+    match (command, &entry.value) {
+        (Command::XRead(xread), Value::Stream(stream)) => {
+            xread.keys.iter().zip(&xread.ids).any(|(k, id)| {
+                let ReadId::After(id) = id else {
+                    unreachable!("`$` is resolved before blocking")
+                };
+                k == key && stream.after(*id).next().is_some()
+            })
+        }
+        _ => true,
+    }
+}
+
+/// Computes the intersection, union or difference of `sets`, where missing
+/// keys are treated as empty sets.
+fn combine_sets(op: SetOperation, sets: &[Option<&set::Set>]) -> set::Set {
+    let Some((first, rest)) = sets.split_first() else {
+        return set::Set::default();
+    };
+    match op {
+        SetOperation::Inter => {
+            if sets.iter().any(Option::is_none) {
+                return set::Set::default();
+            }
+            let mut sets: Vec<_> = sets.iter().flatten().collect();
+            // Checking the members of the smallest set against the others is
+            // the cheapest way to intersect.
+            sets.sort_by_key(|set| set.len());
+            sets[0]
+                .iter()
+                .filter(|member| sets[1..].iter().all(|set| set.contains(member)))
+                .map(Cow::into_owned)
+                .collect()
+        }
+        SetOperation::Union => sets
+            .iter()
+            .flatten()
+            .flat_map(|set| set.iter().map(Cow::into_owned))
+            .collect(),
+        SetOperation::Diff => first
+            .iter()
+            .flat_map(|set| set.iter())
+            .filter(|member| !rest.iter().flatten().any(|set| set.contains(member)))
+            .map(Cow::into_owned)
+            .collect(),
+    }
+}
+
+/// Counts the members of the intersection of `sets`, stopping early once the
+/// count reaches `limit`.
+fn intersection_size(sets: &[Option<&set::Set>], limit: usize) -> usize {
+    let Some(mut sets) = sets.iter().copied().collect::<Option<Vec<_>>>() else {
+        return 0;
+    };
+    sets.sort_by_key(|set| set.len());
+    sets[0]
+        .iter()
+        .filter(|member| sets[1..].iter().all(|set| set.contains(member)))
+        .take(limit)
+        .count()
+}
+
+/// An input to `ZUNION` and friends: a sorted set, or a plain set whose
+/// members all have a score of 1.
+enum ZSetInput<'a> {
+    Missing,
+    Set(&'a set::Set),
+    ZSet(&'a SortedSet),
+}
+
+impl ZSetInput<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Self::Missing => 0,
+            Self::Set(set) => set.len(),
+            Self::ZSet(zset) => zset.len(),
+        }
+    }
+
+    fn score(&self, member: &RedisString) -> Option<f64> {
+        match self {
+            Self::Missing => None,
+            Self::Set(set) => set.contains(member).then_some(1.0),
+            Self::ZSet(zset) => zset.score(member).map(Score::value),
+        }
+    }
+
+    fn members(&self) -> Vec<(Cow<'_, RedisString>, f64)> {
+        match self {
+            Self::Missing => Vec::new(),
+            Self::Set(set) => set.iter().map(|member| (member, 1.0)).collect(),
+            Self::ZSet(zset) => zset
+                .iter()
+                .map(|(m, score)| (Cow::Borrowed(m), score.value()))
+                .collect(),
+        }
+    }
+}
+
+/// Computes the union, intersection or difference of sorted sets, applying
+/// the weights and aggregation of `zset_op`.
+fn combine_zsets(zset_op: &ZSetOp, inputs: &[ZSetInput]) -> SortedSet {
+    // Like Redis, NaN scores from multiplying or adding infinities become 0.
+    let nan_to_zero = |score: f64| if score.is_nan() { 0.0 } else { score };
+    let weighted = |i: usize, score: f64| {
+        let weight = zset_op.weights.get(i).map_or(1.0, |w| w.value());
+        nan_to_zero(score * weight)
+    };
+    let aggregate = |a: f64, b: f64| match zset_op.aggregate.unwrap_or(Aggregate::Sum) {
+        Aggregate::Sum => nan_to_zero(a + b),
+        Aggregate::Min => a.min(b),
+        Aggregate::Max => a.max(b),
+    };
+
+    let Some((first, rest)) = inputs.split_first() else {
+        return SortedSet::default();
+    };
+    let scores: Vec<(RedisString, f64)> = match zset_op.op {
+        SetOperation::Union => {
+            let mut scores: HashMap<RedisString, f64> = HashMap::new();
+            for (i, input) in inputs.iter().enumerate() {
+                for (member, score) in input.members() {
+                    let score = weighted(i, score);
+                    scores
+                        .entry(member.into_owned())
+                        .and_modify(|total| *total = aggregate(*total, score))
+                        .or_insert(score);
+                }
+            }
+            scores.into_iter().collect()
+        }
+        SetOperation::Inter => {
+            let smallest = inputs
+                .iter()
+                .min_by_key(|input| input.len())
+                .expect("there is at least one input");
+            smallest
+                .members()
+                .into_iter()
+                .filter_map(|(member, _)| {
+                    let mut total = None;
+                    for (i, input) in inputs.iter().enumerate() {
+                        let score = weighted(i, input.score(&member)?);
+                        total = Some(total.map_or(score, |total| aggregate(total, score)));
+                    }
+                    Some((member.into_owned(), total?))
+                })
+                .collect()
+        }
+        SetOperation::Diff => first
+            .members()
+            .into_iter()
+            .filter(|(member, _)| rest.iter().all(|input| input.score(member).is_none()))
+            .map(|(member, score)| (member.into_owned(), score))
+            .collect(),
+    };
+
+    scores
+        .into_iter()
+        .map(|(member, score)| (member, Score::new(score).expect("NaN scores became 0")))
+        .collect()
+}
+
+/// Applies a `LIMIT offset count` option to `items`. Like Redis, a negative
+/// offset selects nothing and a negative count selects everything after the
+/// offset.
+fn apply_limit<I: Iterator>(items: I, limit: Option<Limit>) -> Vec<I::Item> {
+    let Some(Limit { offset, count }) = limit else {
+        return items.collect();
+    };
+    let Ok(offset) = usize::try_from(offset) else {
+        return Vec::new();
+    };
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    items.skip(offset).take(count).collect()
+}
+
+/// Pops up to `count` elements from one end of a list.
+fn pop_elements(list: &mut VecDeque<RedisString>, end: ListEnd, count: usize) -> Vec<RedisString> {
+    let count = count.min(list.len());
+    match end {
+        ListEnd::Left => list.drain(..count).collect(),
+        ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+    }
+}
+
+fn bulk_string_array(strings: Vec<RedisString>) -> CommandResponse {
+    CommandResponse::Array(
+        strings
+            .into_iter()
+            .map(|s| CommandResponse::BulkString(Some(s)))
+            .collect(),
+    )
+}
+
+/// Resolves a possibly negative list index, returning `None` if it's out of
+/// range.
+fn list_index(len: usize, index: i64) -> Option<usize> {
+    let len = len_to_i64(len);
+    let index = if index < 0 { index + len } else { index };
+    if (0..len).contains(&index) {
+        usize::try_from(index).ok()
+    } else {
+        None
+    }
+}
+
+/// Resolves an inclusive range of possibly negative list indexes, clamping it
+/// to the list's bounds.
+fn list_range(len: usize, start: i64, stop: i64) -> std::ops::Range<usize> {
+    let len = len_to_i64(len);
+    let start = if start < 0 { start + len } else { start }.max(0);
+    let stop = if stop < 0 { stop + len } else { stop }.min(len - 1);
+    if start > stop {
+        return 0..0;
+    }
+    let to_usize = |i: i64| usize::try_from(i).expect("index was clamped to the list");
+    to_usize(start)..to_usize(stop) + 1
+}
+
+/// Converts an inclusive range of byte or bit indexes from `BITCOUNT` or
+/// `BITPOS` into a range of bit offsets in a string of `len` bytes.
+fn bit_range(len: usize, start: i64, end: i64, unit: BitUnit) -> std::ops::Range<usize> {
+    match unit {
+        BitUnit::Byte => {
+            let bytes = bitmap::clamp_range(len, start, end);
+            bytes.start * 8..bytes.end * 8
+        }
+        BitUnit::Bit => bitmap::clamp_range(len * 8, start, end),
+    }
+}
+
+/// Formats a stream entry as an array of its ID and a flat array of its fields
+/// and values.
+fn stream_entry(id: StreamId, fields: &Fields) -> CommandResponse {
+    let fields = fields
+        .iter()
+        .flat_map(|(field, value)| [field.clone(), value.clone()])
+        .collect();
+    CommandResponse::Array(vec![
+        CommandResponse::BulkString(Some(RedisString::from(id.to_string()))),
+        bulk_string_array(fields),
+    ])
+}
+
+/// Formats entries claimed by `XCLAIM` or `XAUTOCLAIM`, or just their IDs.
+fn claimed_entries(stream: &Stream, ids: &[StreamId], justid: bool) -> CommandResponse {
+    CommandResponse::Array(
+        ids.iter()
+            .map(|id| {
+                if justid {
+                    return CommandResponse::BulkString(Some(RedisString::from(id.to_string())));
+                }
+                let fields = stream.get(*id).expect("claimed entries exist");
+                stream_entry(*id, fields)
+            })
+            .collect(),
+    )
+}
+
+/// Decodes the location stored as a geo member's score.
+fn geo_position(score: Score) -> Coordinates {
+    geo::decode(geo::from_score(score))
+}
+
+/// Formats coordinates as an array of longitude and latitude.
+fn coordinates_response(coords: Coordinates) -> CommandResponse {
+    let degrees = |d: f64| {
+        let score = Score::new(d).expect("coordinates aren't NaN");
+        CommandResponse::BulkString(Some(RedisString::from(score.to_string())))
+    };
+    CommandResponse::Array(vec![degrees(coords.longitude), degrees(coords.latitude)])
+}
+
+/// Formats `XINFO` and `HELLO` replies as a flat array of field names and values.
+/// Describes the most recent `count` entries in the `ACL LOG`, or all of them.
+fn acl_log(users: &Users, count: Option<usize>) -> CommandResponse {
+    let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+    let now = unix_time_millis();
+    let entries = users
+        .log()
+        .take(count.unwrap_or(usize::MAX))
+        .map(|entry| {
+            #[allow(clippy::cast_precision_loss)]
+            let age = (now - entry.created) as f64 / 1000.0;
+            info_map(vec![
+                ("count", CommandResponse::Integer(len_to_i64(entry.count))),
+                ("reason", bulk(entry.denial.reason.name())),
+                ("context", bulk("toplevel")),
+                ("object", bulk(&entry.denial.object)),
+                ("username", bulk(&entry.denial.username)),
+                ("age-seconds", bulk(&age.to_string())),
+                ("client-info", bulk(&entry.denial.client_info)),
+                (
+                    "entry-id",
+                    CommandResponse::Integer(len_to_i64(entry.entry_id)),
+                ),
+                ("timestamp-created", CommandResponse::Integer(entry.created)),
+                (
+                    "timestamp-last-updated",
+                    CommandResponse::Integer(entry.updated),
+                ),
+            ])
+        })
+        .collect();
+    CommandResponse::Array(entries)
+}
+
+/// The reference count `OBJECT REFCOUNT` and `DEBUG OBJECT` report. Like
+/// Redis, shared integers report the largest refcount, since they're never
+/// freed.
+fn refcount(entry: &Entry) -> i64 {
+    match &entry.value {
+        Value::String(s) if s.is_shared() => i64::from(i32::MAX),
+        _ => 1,
+    }
+}
+
+/// Answers `COMMAND` from the command table.
+fn command_query(query: &CommandQuery) -> CommandResponse {
+    let specs = |names: &[String]| -> Vec<Option<&'static CommandSpec>> {
+        if names.is_empty() {
+            command_table::COMMANDS.iter().map(Some).collect()
+        } else {
+            names.iter().map(|name| command_table::find(name)).collect()
+        }
+    };
+    match query {
+        CommandQuery::All => {
+            CommandResponse::Array(command_table::COMMANDS.iter().map(command_info).collect())
+        }
+        CommandQuery::Count => CommandResponse::Integer(
+            i64::try_from(command_table::COMMANDS.len()).expect("command count fits in i64"),
+        ),
+        // Unknown commands get a nil.
+        CommandQuery::Info { names } => CommandResponse::Array(
+            specs(names)
+                .into_iter()
+                .map(|spec| spec.map_or(CommandResponse::NullArray, command_info))
+                .collect(),
+        ),
+        // Unknown commands are left out.
+        CommandQuery::Docs { names } => CommandResponse::Array(
+            specs(names)
+                .into_iter()
+                .flatten()
+                .flat_map(|spec| {
+                    let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+                    let doc = [
+                        ("summary", spec.summary),
+                        ("since", spec.since),
+                        ("group", spec.group),
+                    ];
+                    [
+                        bulk(spec.name),
+                        CommandResponse::Array(
+                            doc.into_iter()
+                                .flat_map(|(field, value)| [bulk(field), bulk(value)])
+                                .collect(),
+                        ),
+                    ]
+                })
+                .collect(),
+        ),
+        CommandQuery::GetKeys { args } => command_get_keys(args, false),
+        CommandQuery::GetKeysAndFlags { args } => command_get_keys(args, true),
+    }
+}
+
+/// Answers `COMMAND GETKEYS` and, `with_flags`, `COMMAND GETKEYSANDFLAGS`
+/// from the key specs of the command `args` call.
+fn command_get_keys(args: &[RedisString], with_flags: bool) -> CommandResponse {
+    let args: Vec<&[u8]> = args.iter().map(RedisString::as_bytes).collect();
+    let Some(spec) = command_table::resolve(&args) else {
+        return CommandResponse::Error(ErrorReply::err("Invalid command specified"));
+    };
+    let arg_count = len_to_i64(args.len());
+    if (spec.arity >= 0 && arg_count != spec.arity) || arg_count < -spec.arity {
+        return CommandResponse::Error(ErrorReply::err(
+            "Invalid number of arguments specified for command",
+        ));
+    }
+    let keys = spec.find_keys(&args);
+    if keys.is_empty() && !spec.flags().contains(&Flag::NoMandatoryKeys) {
+        return CommandResponse::Error(ErrorReply::err("The command has no key arguments"));
+    }
+    CommandResponse::Array(
+        keys.into_iter()
+            .map(|(index, flags)| {
+                let key = CommandResponse::BulkString(Some(RedisString::from(args[index])));
+                if with_flags {
+                    CommandResponse::Array(vec![key, key_flags(flags)])
+                } else {
+                    key
+                }
+            })
+            .collect(),
+    )
+}
+
+/// A command's entry in `COMMAND INFO`: its name, arity, flags, first key,
+/// last key, key step, ACL categories, tips, key specifications and
+/// subcommands. Tips and subcommands are left empty.
+fn command_info(spec: &CommandSpec) -> CommandResponse {
+    let status = |s: &str| CommandResponse::Status(s.to_string());
+    let (first_key, last_key, step) = spec.legacy_key_range();
+    CommandResponse::Array(vec![
+        CommandResponse::BulkString(Some(RedisString::from(spec.name))),
+        CommandResponse::Integer(spec.arity),
+        CommandResponse::Array(
+            spec.flags()
+                .into_iter()
+                .map(|flag| status(flag.name()))
+                .collect(),
+        ),
+        CommandResponse::Integer(first_key),
+        CommandResponse::Integer(last_key),
+        CommandResponse::Integer(step),
+        CommandResponse::Array(
+            spec.categories()
+                .into_iter()
+                .map(|category| status(&format!("@{}", category.name())))
+                .collect(),
+        ),
+        CommandResponse::Array(Vec::new()),
+        CommandResponse::Array(spec.key_specs.iter().map(key_spec_info).collect()),
+        CommandResponse::Array(Vec::new()),
+    ])
+}
+
+/// A key spec as `COMMAND INFO` reports it.
+fn key_spec_info(spec: &KeySpec) -> CommandResponse {
+    let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+    let integer = |n: usize| CommandResponse::Integer(len_to_i64(n));
+    let (begin_type, begin_spec) = match spec.begin {
+        BeginSearch::Index(index) => ("index", vec![("index", integer(index))]),
+        BeginSearch::Keyword {
+            keyword,
+            start_from,
+        } => (
+            "keyword",
+            vec![
+                ("keyword", bulk(keyword)),
+                ("startfrom", CommandResponse::Integer(start_from)),
+            ],
+        ),
+    };
+    let (find_type, find_spec) = match spec.find {
+        FindKeys::Range {
+            last_key,
+            step,
+            limit,
+        } => (
+            "range",
+            vec![
+                ("lastkey", CommandResponse::Integer(last_key)),
+                ("keystep", integer(step)),
+                ("limit", integer(limit)),
+            ],
+        ),
+        FindKeys::KeyNum {
+            key_num_index,
+            first_key,
+            step,
+        } => (
+            "keynum",
+            vec![
+                ("keynumidx", integer(key_num_index)),
+                ("firstkey", integer(first_key)),
+                ("keystep", integer(step)),
+            ],
+        ),
+    };
+    info_map(vec![
+        ("flags", key_flags(spec.flags)),
+        (
+            "begin_search",
+            info_map(vec![
+                ("type", bulk(begin_type)),
+                ("spec", info_map(begin_spec)),
+            ]),
+        ),
+        (
+            "find_keys",
+            info_map(vec![
+                ("type", bulk(find_type)),
+                ("spec", info_map(find_spec)),
+            ]),
+        ),
+    ])
+}
+
+fn key_flags(flags: &[KeyFlag]) -> CommandResponse {
+    CommandResponse::Array(
+        flags
+            .iter()
+            .map(|flag| CommandResponse::Status(flag.name().to_string()))
+            .collect(),
+    )
+}
+
+fn info_map(fields: Vec<(&str, CommandResponse)>) -> CommandResponse {
+    CommandResponse::Array(
+        fields
+            .into_iter()
+            .flat_map(|(name, value)| {
+                [
+                    CommandResponse::BulkString(Some(RedisString::from(name))),
+                    value,
+                ]
+            })
+            .collect(),
+    )
+}
+
+fn no_group_error(key: &RedisString, group: &RedisString) -> CommandResponse {
+    CommandResponse::Error(ErrorReply::new(
+        ErrorCode::NoGroup,
+        no_group_message(key, group),
+    ))
+}
+
+fn no_group_message(key: &RedisString, group: &RedisString) -> String {
+    format!(
+        "No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key.as_bytes()),
+        String::from_utf8_lossy(group.as_bytes())
+    )
+}
+
+fn bit_offset(offset: u64) -> usize {
+    usize::try_from(offset).expect("bit offsets are limited to 32 bits")
+}
+
+fn wrong_type_error() -> CommandResponse {
+    CommandResponse::Error(ErrorReply::wrong_type())
+}
+
+/// Converts a collection length to a RESP integer.
+fn len_to_i64(len: usize) -> i64 {
+    i64::try_from(len).expect("length overflows i64")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use crate::command::{Expiration, PendingRange, SlotState};
+    use crate::geo::{DistanceUnit, Shape};
+    use crate::stream::{GroupReadId, NewId, RangeBound, ReadId, Trim, TrimStrategy};
+    use crate::tracking::TrackingMode;
+    use crate::zset::{LexBound, LexRange, ScoreBound, ScoreRange};
+
+    #[test]
+    fn test_protocol_error_closes_connection() {
+        let mut server = Server::new();
+        server.start_core_worker_thread(ServerCore::new(DEFAULT_DATABASES));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.start_next_client_thread(stream).unwrap();
+
+        // Command errors keep the connection open, but a malformed message
+        // closes it after the error is sent.
+        client
+            .write_all(b"*1\r\n$4\r\nNOPE\r\n*1\r\n$-5\r\n*1\r\n$4\r\nPING\r\n")
+            .unwrap();
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "-ERR unknown command 'NOPE', with args beginning with: \r\n\
+             -ERR Protocol error: invalid bulk length\r\n"
+        );
+
+        // So does nesting arrays deep enough to overflow the stack, which
+        // doesn't need authentication.
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.start_next_client_thread(stream).unwrap();
+        client.write_all(&b"*1\r\n".repeat(200)).unwrap();
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "-ERR Protocol error: arrays are nested too deeply\r\n"
+        );
+    }
+
+    #[test]
+    fn test_client_pause() {
+        let mut server = Server::new();
+        server.start_core_worker_thread(ServerCore::new(DEFAULT_DATABASES));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut connect = || {
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            server.start_next_client_thread(stream).unwrap();
+            (BufReader::new(client.try_clone().unwrap()), client)
+        };
+        let (mut reader, mut writer) = connect();
+        let (mut paused_reader, mut paused_writer) = connect();
+        let send = |writer: &mut TcpStream, args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            writer.write_all(&message.to_bytes()).unwrap();
+        };
+        let reply = |reader: &mut BufReader<TcpStream>| {
+            Message::parse_resp(reader).unwrap().unwrap().to_string()
+        };
+
+        send(&mut writer, &["CLIENT", "PAUSE", "100000", "WRITE"]);
+        assert_eq!(reply(&mut reader), "OK");
+
+        // Writes wait for the pause to end, but reads go ahead.
+        send(&mut paused_writer, &["SET", "a", "1"]);
+        send(&mut writer, &["GET", "a"]);
+        assert_eq!(reply(&mut reader), "(nil)");
+        send(&mut writer, &["CLIENT", "UNPAUSE"]);
+        assert_eq!(reply(&mut reader), "OK");
+        assert_eq!(reply(&mut paused_reader), "OK");
+        send(&mut writer, &["GET", "a"]);
+        assert_eq!(reply(&mut reader), "\"1\"");
+
+        // Pauses end by themselves too.
+        let start = Instant::now();
+        send(&mut writer, &["CLIENT", "PAUSE", "50"]);
+        assert_eq!(reply(&mut reader), "OK");
+        send(&mut paused_writer, &["PING"]);
+        assert_eq!(reply(&mut paused_reader), "PONG");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_debug_access() {
+        for (access, allowed) in [(DebugAccess::No, false), (DebugAccess::Local, true)] {
+            let mut server = Server::with_config(ServerConfig {
+                enable_debug_command: access,
+                ..ServerConfig::default()
+            });
+            server.start_core_worker_thread(ServerCore::new(DEFAULT_DATABASES));
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            server.start_next_client_thread(stream).unwrap();
+            let mut reader = BufReader::new(client.try_clone().unwrap());
+
+            let message = Message::Array(
+                ["DEBUG", "SET-ACTIVE-EXPIRE", "1"]
+                    .iter()
+                    .map(|a| Message::bulk_string(a))
+                    .collect(),
+            );
+            client.write_all(&message.to_bytes()).unwrap();
+            let reply = Message::parse_resp(&mut reader)
+                .unwrap()
+                .unwrap()
+                .to_string();
+            if allowed {
+                assert_eq!(reply, "OK");
+            } else {
+                assert_eq!(reply, format!("(error) ERR {DEBUG_DISABLED_MESSAGE}"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_start_bound() {
+        let dir = std::env::temp_dir().join(format!("redis-clone-bind-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let bind = |addresses: &[&str]| ServerConfig {
+            bind: addresses.iter().map(ToString::to_string).collect(),
+            port,
+            snapshot_path: dir.join("dump.rdb"),
+            ..ServerConfig::default()
+        };
+
+        // 192.0.2.1 is reserved for documentation, so it's never local.
+        let mut server = Server::with_config(bind(&["127.0.0.1", "192.0.2.1"]));
+        assert!(server.start_bound().is_err());
+
+        // Optional addresses that aren't available are skipped.
+        let mut server = Server::with_config(bind(&["127.0.0.1", "127.0.0.2", "-192.0.2.1"]));
+        let shutdown = server.shutdown_handle();
+        let started = thread::spawn(move || server.start_bound());
+        for host in ["127.0.0.1", "127.0.0.2"] {
+            let mut client = loop {
+                if let Ok(client) = TcpStream::connect((host, port)) {
+                    break client;
+                }
+                thread::sleep(Duration::from_millis(10));
+            };
+            let ping = Message::Array(vec![Message::bulk_string("PING")]);
+            client.write_all(&ping.to_bytes()).unwrap();
+            let mut reader = BufReader::new(client);
+            let reply = Message::parse_resp(&mut reader).unwrap().unwrap();
+            assert_eq!(reply.to_string(), "PONG");
+        }
+        shutdown.shutdown().unwrap();
+        started.join().unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let dir = std::env::temp_dir().join(format!("redis-clone-shutdown-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.rdb");
+        let mut server = Server::new();
+        server.set_snapshot_path(&path);
+        let shutdown = server.shutdown_handle();
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let started = thread::spawn(move || server.start(("127.0.0.1", port)));
+        let connect = || loop {
+            if let Ok(client) = TcpStream::connect(("127.0.0.1", port)) {
+                break (BufReader::new(client.try_clone().unwrap()), client);
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let send = |writer: &mut TcpStream, args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            writer.write_all(&message.to_bytes()).unwrap();
+        };
+        let (mut reader, mut writer) = connect();
+        let (mut blocked_reader, mut blocked_writer) = connect();
+        send(&mut writer, &["SET", "key", "value"]);
+        assert_eq!(
+            Message::parse_resp(&mut reader)
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "OK"
+        );
+        send(&mut blocked_writer, &["BLPOP", "list", "0"]);
+        thread::sleep(Duration::from_millis(50));
+
+        shutdown.shutdown().unwrap();
+        started.join().unwrap().unwrap();
+
+        // Blocked clients time out, and then every connection is closed.
+        assert_eq!(
+            Message::parse_resp(&mut blocked_reader)
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "(nil)"
+        );
+        assert!(Message::parse_resp(&mut blocked_reader).unwrap().is_none());
+        assert!(Message::parse_resp(&mut reader).unwrap().is_none());
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+
+        let dbs = snapshot::load(&path, DEFAULT_DATABASES).unwrap().unwrap();
+        assert!(dbs[0].entries().contains_key(&RedisString::from("key")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_command_stats() {
+        let mut server = Server::with_config(ServerConfig {
+            requirepass: Some("secret".to_string()),
+            ..ServerConfig::default()
+        });
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.acl = Arc::clone(&server.acl);
+        core.stats = Arc::clone(&server.stats);
+        server.start_core_worker_thread(core);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.start_next_client_thread(stream).unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut send = |args: &[&str]| {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            client.write_all(&message.to_bytes()).unwrap();
+            Message::parse_resp(&mut reader)
+                .unwrap()
+                .unwrap()
+                .to_string()
+        };
+        let stat = |name: &str| {
+            let info = server.stats.lock().unwrap().commandstats_info();
+            let prefix = format!("cmdstat_{name}:");
+            let line = info.lines().find(|line| line.starts_with(&prefix))?;
+            // Leave out the timings, which vary.
+            let (calls, _) = line[prefix.len()..].split_once(',').unwrap();
+            let (_, counts) = line.split_once(",rejected_calls=").unwrap();
+            Some(format!("{calls},rejected_calls={counts}"))
+        };
+
+        send(&["GET", "a"]);
+        send(&["AUTH", "secret"]);
+        send(&["SET", "a", "x"]);
+        send(&["LPUSH", "a", "1"]);
+        send(&["CLIENT", "PAUSE", "0"]);
+        assert_eq!(
+            stat("get").unwrap(),
+            "calls=0,rejected_calls=1,failed_calls=0"
+        );
+        assert_eq!(
+            stat("auth").unwrap(),
+            "calls=1,rejected_calls=0,failed_calls=0"
+        );
+        assert_eq!(
+            stat("set").unwrap(),
+            "calls=1,rejected_calls=0,failed_calls=0"
+        );
+        assert_eq!(
+            stat("lpush").unwrap(),
+            "calls=1,rejected_calls=0,failed_calls=1"
+        );
+        assert_eq!(
+            stat("client|pause").unwrap(),
+            "calls=1,rejected_calls=0,failed_calls=0"
+        );
+
+        // The reset itself is counted afterwards.
+        assert_eq!(send(&["CONFIG", "RESETSTAT"]), "OK");
+        assert_eq!(stat("get"), None);
+        assert_eq!(
+            stat("config|resetstat").unwrap(),
+            "calls=1,rejected_calls=0,failed_calls=0"
+        );
+        let info = send(&["INFO", "latencystats"]);
+        assert!(info.contains("latency_percentiles_usec_config|resetstat:p50="));
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let listener = listen("127.0.0.1:0", 16).unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        configure_socket(&stream, &ServerConfig::default()).unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+
+        let config = ServerConfig {
+            tcp_keepalive: None,
+            tcp_nodelay: false,
+            ..ServerConfig::default()
+        };
+        configure_socket(&stream, &config).unwrap();
+        assert!(!stream.nodelay().unwrap());
+    }
+
+    #[test]
+    fn test_protected_mode() {
+        let remote: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let server = Server::new();
+        assert!(server.is_protected_from(remote));
+        assert!(!server.is_protected_from("127.0.0.1:5000".parse().unwrap()));
+        assert!(!server.is_protected_from("[::1]:5000".parse().unwrap()));
+        assert!(!server.is_protected_from("[::ffff:127.0.0.1]:5000".parse().unwrap()));
+
+        // A password, a bind address, or turning protected mode off lets
+        // remote clients connect.
+        for config in [
+            ServerConfig {
+                requirepass: Some("secret".to_string()),
+                ..ServerConfig::default()
+            },
+            ServerConfig {
+                bind: vec!["0.0.0.0".to_string()],
+                ..ServerConfig::default()
+            },
+            ServerConfig {
+                protected_mode: false,
+                ..ServerConfig::default()
+            },
+        ] {
+            assert!(!Server::with_config(config).is_protected_from(remote));
+        }
+    }
+
+    #[test]
+    fn test_auth() {
+        let mut server = Server::with_config(ServerConfig {
+            requirepass: Some("secret".to_string()),
+            ..ServerConfig::default()
+        });
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.acl = Arc::clone(&server.acl);
+        server.start_core_worker_thread(core);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.start_next_client_thread(stream).unwrap();
+
+        let commands: &[&[&str]] = &[
+            &["PING"],
+            &["AUTH", "wrong"],
+            &["HELLO", "3"],
+            &["HELLO", "2"],
+            &["HELLO", "2", "AUTH", "default", "secret"],
+            &["PING"],
+        ];
+        for args in commands {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            client.write_all(&message.to_bytes()).unwrap();
+        }
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "-NOAUTH Authentication required.\r\n\
+             -WRONGPASS invalid username-password pair or user is disabled.\r\n\
+             -NOPROTO unsupported protocol version\r\n\
+             -NOAUTH HELLO must be called with the client already authenticated, otherwise \
+             the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the \
+             client and select the RESP protocol version at the same time\r\n\
+             *14\r\n$6\r\nserver\r\n$5\r\nredis\r\n$7\r\nversion\r\n$5\r\n7.0.0\r\n\
+             $5\r\nproto\r\n:2\r\n$2\r\nid\r\n:0\r\n$4\r\nmode\r\n$10\r\nstandalone\r\n\
+             $4\r\nrole\r\n$6\r\nmaster\r\n$7\r\nmodules\r\n*0\r\n\
+             +PONG\r\n"
+        );
+    }
+
+    #[test]
+    fn test_acl() {
+        let mut server = Server::new();
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.acl = Arc::clone(&server.acl);
+        let acl = |core: &mut ServerCore, acl| core.process_command(0, Command::Acl(acl));
+        let rules = ["on", ">secret", "~cache:*", "&news.*", "+@read", "+publish"];
+        let response = acl(
+            &mut core,
+            Acl::SetUser {
+                username: "alice".to_string(),
+                rules: rules.iter().map(ToString::to_string).collect(),
+            },
+        );
+        assert_eq!(response, CommandResponse::Ok);
+        let response = acl(
+            &mut core,
+            Acl::GetUser {
+                username: "alice".to_string(),
+            },
+        );
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        assert_eq!(
+            response,
+            CommandResponse::Array(vec![
+                bulk("flags"),
+                CommandResponse::Array(vec![bulk("on")]),
+                bulk("passwords"),
+                CommandResponse::Array(vec![bulk(
+                    "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+                )]),
+                bulk("commands"),
+                bulk("-@all +@read +publish"),
+                bulk("keys"),
+                bulk("~cache:*"),
+                bulk("channels"),
+                bulk("&news.*"),
+                bulk("selectors"),
+                CommandResponse::Array(vec![]),
+            ])
+        );
+        let response = acl(
+            &mut core,
+            Acl::GetUser {
+                username: "bob".to_string(),
+            },
+        );
+        assert_eq!(response, CommandResponse::NullArray);
+        let response = acl(&mut core, Acl::Cat { category: None });
+        assert!(matches!(response, CommandResponse::Array(names) if names.len() == 19));
+        let response = acl(
+            &mut core,
+            Acl::Cat {
+                category: Some("nope".to_string()),
+            },
+        );
+        assert!(matches!(response, CommandResponse::Error(_)));
+
+        server.start_core_worker_thread(core);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.start_next_client_thread(stream).unwrap();
+
+        let commands: &[&[&str]] = &[
+            &["AUTH", "alice", "wrong"],
+            &["AUTH", "alice", "secret"],
+            &["GET", "cache:1"],
+            &["GET", "other"],
+            &["DEL", "cache:1"],
+            &["PUBLISH", "other", "hi"],
+            &["PUBLISH", "news.tech", "hi"],
+        ];
+        for args in commands {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            client.write_all(&message.to_bytes()).unwrap();
+        }
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n\
+             +OK\r\n\
+             $-1\r\n\
+             -NOPERM No permissions to access a key\r\n\
+             -NOPERM User alice has no permissions to run the 'del' command\r\n\
+             -NOPERM No permissions to access a channel\r\n\
+             :0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_acl_log_and_file() {
+        let mut server = Server::new();
+        let rules = ["on", ">secret", "~cache:*", "+@read"].map(String::from);
+        server
+            .acl
+            .lock()
+            .unwrap()
+            .set_user("alice", &rules)
+            .unwrap();
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.acl = Arc::clone(&server.acl);
+        server.start_core_worker_thread(core);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.start_next_client_thread(stream).unwrap();
+
+        let commands: &[&[&str]] = &[
+            &["ACL", "WHOAMI"],
+            &["AUTH", "alice", "wrong"],
+            &["AUTH", "alice", "secret"],
+            &["GET", "other"],
+            &["GET", "other"],
+            &["ACL", "WHOAMI"],
+        ];
+        for args in commands {
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            client.write_all(&message.to_bytes()).unwrap();
+        }
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "$7\r\ndefault\r\n\
+             -WRONGPASS invalid username-password pair or user is disabled.\r\n\
+             +OK\r\n\
+             -NOPERM No permissions to access a key\r\n\
+             -NOPERM No permissions to access a key\r\n\
+             -NOPERM User alice has no permissions to run the 'acl' command\r\n"
+        );
+
+        // Repeated denials share an entry, and the most recent come first.
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.acl = Arc::clone(&server.acl);
+        let acl = |core: &mut ServerCore, acl| core.process_command(0, Command::Acl(acl));
+        let CommandResponse::Array(entries) = acl(&mut core, Acl::Log { count: None }) else {
+            panic!("expected an array");
+        };
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let fields: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                let CommandResponse::Array(fields) = entry else {
+                    panic!("expected an array");
+                };
+                (fields[1].clone(), fields[3].clone(), fields[7].clone())
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (CommandResponse::Integer(1), bulk("command"), bulk("acl")),
+                (CommandResponse::Integer(2), bulk("key"), bulk("other")),
+                (CommandResponse::Integer(1), bulk("auth"), bulk("AUTH")),
+            ]
+        );
+        let response = acl(&mut core, Acl::Log { count: Some(1) });
+        assert!(matches!(response, CommandResponse::Array(entries) if entries.len() == 1));
+        assert_eq!(acl(&mut core, Acl::LogReset), CommandResponse::Ok);
+        assert_eq!(
+            acl(&mut core, Acl::Log { count: None }),
+            CommandResponse::Array(vec![])
+        );
+
+        // Users are saved to and loaded from the ACL file.
+        assert!(matches!(
+            acl(&mut core, Acl::Save),
+            CommandResponse::Error(_)
+        ));
+        let dir = std::env::temp_dir().join(format!("redis-clone-aclfile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        core.config.aclfile = Some(dir.join("users.acl"));
+        assert_eq!(acl(&mut core, Acl::Save), CommandResponse::Ok);
+        let rules = vec!["nocommands".to_string()];
+        let response = acl(
+            &mut core,
+            Acl::SetUser {
+                username: "alice".to_string(),
+                rules,
+            },
+        );
+        assert_eq!(response, CommandResponse::Ok);
+        assert_eq!(acl(&mut core, Acl::Load), CommandResponse::Ok);
+        assert_eq!(
+            acl(&mut core, Acl::List),
+            CommandResponse::Array(vec![
+                bulk(&format!(
+                    "user alice on #{} ~cache:* -@all +@read",
+                    crate::sha256::sha256_hex(b"secret")
+                )),
+                bulk("user default on nopass ~* &* +@all"),
+            ])
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_replicate_from_master() {
+        let mut master = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut master, "before", "value");
+        let master_id = master.replication.id().to_string();
+        let mut server = Server::new();
+        server.start_core_worker_thread(master);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                server.start_next_client_thread(stream.unwrap()).unwrap();
+            }
+        });
+
+        let mut replica = ServerCore::new(DEFAULT_DATABASES);
+        replica.process_command(
+            0,
+            Command::ReplicaOf(ReplicaOf {
+                master: Some(("127.0.0.1".to_string(), port)),
+            }),
+        );
+        let next_event = |replica: &ServerCore| {
+            let link = replica.master.as_ref().unwrap();
+            link.events().recv_timeout(Duration::from_secs(5)).unwrap()
+        };
+        let event = next_event(&replica);
+        replica.process_master_event(event);
+        assert_eq!(
+            get(&mut replica, "before"),
+            CommandResponse::BulkString(Some(RedisString::from("value")))
+        );
+        // The replica continues the master's replication stream.
+        assert_eq!(replica.replication.id(), master_id);
+
+        // Writes to the master are streamed to the replica.
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nafter\r\n$5\r\nvalue\r\n")
+            .unwrap();
+        let event = next_event(&replica);
+        assert!(matches!(event, MasterEvent::Command(0, Command::Set(_))));
+        replica.process_master_event(event);
+        assert_eq!(
+            get(&mut replica, "after"),
+            CommandResponse::BulkString(Some(RedisString::from("value")))
+        );
+
+        let info = replica.process_command(0, Command::Info(Info { sections: vec![] }));
+        let CommandResponse::BulkString(Some(info)) = info else {
+            panic!("expected a bulk string");
+        };
+        let info = String::try_from(info).unwrap();
+        assert!(info.contains("role:slave\r\n"));
+        assert!(info.contains(&format!("master_port:{port}\r\n")));
+        assert!(info.contains("master_link_status:up\r\n"));
+        assert!(info.contains(&format!("master_replid:{master_id}\r\n")));
+
+        // Replicating the same master again is a no-op.
+        let response = replica.process_command(
+            0,
+            Command::ReplicaOf(ReplicaOf {
+                master: Some(("127.0.0.1".to_string(), port)),
+            }),
+        );
+        assert_eq!(
+            response,
+            CommandResponse::Status("OK Already connected to specified master".to_string())
+        );
+        replica.process_command(0, Command::ReplicaOf(ReplicaOf { master: None }));
+        assert!(replica.master.is_none());
+    }
+
+    #[test]
+    fn test_replica_refuses_writes() {
+        // The master doesn't need to be up for the replica to refuse writes.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut replica = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut replica, "after", "value");
+        replica.process_command(
+            0,
+            Command::ReplicaOf(ReplicaOf {
+                master: Some(("127.0.0.1".to_string(), port)),
+            }),
+        );
+
+        // Clients can read from the replica, but not write to it.
+        let response = replica.process_client_command(
+            1,
+            0,
+            Command::Get(Get {
+                key: RedisString::from("after"),
+            }),
+        );
+        assert_eq!(
+            response,
+            Some(CommandResponse::BulkString(Some(RedisString::from(
+                "value"
+            ))))
+        );
+        let response = replica.process_client_command(
+            1,
+            0,
+            Command::Del(Del {
+                keys: vec![RedisString::from("after")],
+            }),
+        );
+        let Some(CommandResponse::Error(e)) = response else {
+            panic!("expected an error, got {response:?}");
+        };
+        assert_eq!(e.code, ErrorCode::ReadOnly);
+
+        // Writes are what the command table flags, so like in Redis, SORT is
+        // refused even without STORE, and clients use SORT_RO instead.
+        let sort = |name: &str| {
+            let args = [name, "after"].map(Message::bulk_string);
+            Command::parse_resp(&Message::Array(args.to_vec())).unwrap()
+        };
+        let response = replica.process_client_command(1, 0, sort("SORT"));
+        let Some(CommandResponse::Error(e)) = response else {
+            panic!("expected an error, got {response:?}");
+        };
+        assert_eq!(e.code, ErrorCode::ReadOnly);
+        let response = replica.process_client_command(1, 0, sort("SORT_RO"));
+        assert!(
+            !matches!(
+                response,
+                Some(CommandResponse::Error(ErrorReply {
+                    code: ErrorCode::ReadOnly,
+                    ..
+                }))
+            ),
+            "{response:?}"
+        );
+
+        // Only read-only scripts may run on the replica.
+        let eval = |read_only| {
+            Command::Eval(Eval {
+                script: RedisString::from("return redis.call('GET', KEYS[1])"),
+                keys: vec![RedisString::from("after")],
+                args: vec![],
+                read_only,
+            })
+        };
+        let response = replica.process_client_command(1, 0, eval(false));
+        let Some(CommandResponse::Error(e)) = response else {
+            panic!("expected an error, got {response:?}");
+        };
+        assert_eq!(e.code, ErrorCode::ReadOnly);
+        let response = replica.process_client_command(1, 0, eval(true));
+        let Some(CommandResponse::Error(e)) = response else {
+            panic!("expected an error, got {response:?}");
+        };
+        assert_ne!(e.code, ErrorCode::ReadOnly);
+    }
+
+    #[test]
+    fn test_write_outgoing() {
+        let (outgoing_sender, outgoing_receiver) = crossbeam_channel::unbounded();
+        let (replied_sender, replied_receiver) = crossbeam_channel::unbounded();
+        outgoing_sender
+            .send(Outgoing::Push(vec![CommandResponse::BulkString(Some(
+                "hello".into(),
+            ))]))
+            .unwrap();
+        outgoing_sender
+            .send(Outgoing::Reply(CommandResponse::Ok))
+            .unwrap();
+        drop(outgoing_sender);
+
+        let mut written = Vec::new();
+        write_outgoing(&outgoing_receiver, &mut written, &replied_sender).unwrap();
+        assert_eq!(written, b"*1\r\n$5\r\nhello\r\n+OK\r\n");
+        assert_eq!(replied_receiver.try_recv(), Ok(()));
+        assert!(replied_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_write_outgoing_replication() {
+        let (outgoing_sender, outgoing_receiver) = crossbeam_channel::unbounded();
+        let (replied_sender, _replied_receiver) = crossbeam_channel::unbounded();
+        let (snapshot_sender, snapshot_receiver) = crossbeam_channel::bounded(1);
+        outgoing_sender
+            .send(Outgoing::Reply(CommandResponse::Status(
+                "FULLRESYNC id 0".to_string(),
+            )))
+            .unwrap();
+        outgoing_sender
+            .send(Outgoing::Snapshot(snapshot_receiver))
+            .unwrap();
+        outgoing_sender
+            .send(Outgoing::Replication(Arc::from(
+                &b"*1\r\n$4\r\nPING\r\n"[..],
+            )))
+            .unwrap();
+        drop(outgoing_sender);
+
+        // The stream waits for the snapshot.
+        snapshot_sender.send(Ok(b"REDIS".to_vec())).unwrap();
+        let mut written = Vec::new();
+        write_outgoing(&outgoing_receiver, &mut written, &replied_sender).unwrap();
+        assert_eq!(
+            written,
+            b"+FULLRESYNC id 0\r\n$5\r\nREDIS*1\r\n$4\r\nPING\r\n"
+        );
+    }
+
+    #[test]
+    fn test_ping() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let response = core.process_command(0, Command::Ping);
+        assert_eq!(response, CommandResponse::Pong);
+    }
+
+    #[test]
+    fn test_set_get() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+
+        let set_command = Command::Set(Set {
+            key: RedisString::from("key"),
+            value: RedisString::from("value"),
+        });
+        let response = core.process_command(0, set_command);
+        assert_eq!(response, CommandResponse::Ok);
+
+        let get_command = Command::Get(Get {
+            key: RedisString::from("key"),
+        });
+        let response = core.process_command(0, get_command);
+        assert_eq!(
+            response,
+            CommandResponse::BulkString(Some(RedisString::from("value")))
+        );
+    }
+
+    #[test]
+    fn test_expire_and_ttl() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "key", "value");
+
+        let response = core.process_command(0, ttl("key", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(-1));
+        let response = core.process_command(0, ttl("missing", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(-2));
+
+        let response = core.process_command(0, expire("key", Expiration::Seconds(100), None, None));
+        assert_eq!(response, CommandResponse::Integer(1));
+        let response = core.process_command(0, ttl("key", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(100));
+
+        // Expiring in the past deletes the key.
+        let response = core.process_command(
+            0,
+            expire("key", Expiration::UnixMilliseconds(1), None, None),
+        );
+        assert_eq!(response, CommandResponse::Integer(1));
+        assert_eq!(get(&mut core, "key"), CommandResponse::BulkString(None));
+
+        // Expiring a missing key does nothing.
+        let response = core.process_command(0, expire("key", Expiration::Seconds(100), None, None));
+        assert_eq!(response, CommandResponse::Integer(0));
+    }
+
+    #[test]
+    fn test_replication_full_sync() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "before", "value");
+
+        let replconf = Command::ReplConf(ReplConf::Options(vec![(
+            "listening-port".to_string(),
+            RedisString::from("6380"),
+        )]));
+        assert_eq!(
+            core.process_client_command(1, 0, replconf),
+            Some(CommandResponse::Ok)
+        );
+        let psync = Command::PSync(PSync {
+            repl_id: RedisString::from("?"),
+            offset: -1,
+        });
+        let expected = format!("FULLRESYNC {} 0", core.replication.id());
+        assert_eq!(
+            core.process_client_command(1, 0, psync),
+            Some(CommandResponse::Status(expected))
+        );
+
+        // The snapshot has the dataset as of the PSYNC.
+        let (replica, Outgoing::Snapshot(snapshot)) = core.syncs.pop().unwrap() else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!(replica, 1);
+        let snapshot = snapshot.recv().unwrap().unwrap();
+        let dbs = rdb::read_snapshot(&snapshot[..], DEFAULT_DATABASES).unwrap();
+        assert!(dbs[0].entries().contains_key(&RedisString::from("before")));
+
+        // Later writes are streamed, but reads and failed writes aren't.
+        let set_after = Command::Set(Set {
+            key: RedisString::from("after"),
+            value: RedisString::from("value"),
+        });
+        core.process_client_command(2, 3, set_after.clone());
+        core.process_client_command(
+            2,
+            3,
+            Command::Get(Get {
+                key: RedisString::from("after"),
+            }),
+        );
+        core.process_client_command(
+            2,
+            3,
+            Command::Push(Push {
+                key: RedisString::from("after"),
+                end: ListEnd::Left,
+                elements: vec![RedisString::from("x")],
+            }),
+        );
+        let stream = [
+            Command::Select(Select { index: 3 }).to_resp().to_bytes(),
+            set_after.to_resp().to_bytes(),
+        ]
+        .concat();
+        assert_eq!(core.replication.offset(), stream.len() as u64);
+        assert_eq!(core.replication.take_pending(), Some(stream));
+
+        assert_eq!(
+            core.process_client_command(1, 0, Command::ReplConf(ReplConf::Ack(10))),
+            None
+        );
+        let replicas: Vec<_> = core.replication.replicas().collect();
+        assert_eq!(replicas[0].1.ack_offset, 10);
+        assert_eq!(replicas[0].1.listening_port, Some(6380));
+
+        // A replica that reconnects continues from the backlog.
+        core.replication.remove_replica(1);
+        let offset = i64::try_from(core.replication.offset()).unwrap();
+        core.process_client_command(2, 3, set_after.clone());
+        let psync = Command::PSync(PSync {
+            repl_id: RedisString::from(core.replication.id()),
+            offset: offset + 1,
+        });
+        let expected = format!("CONTINUE {}", core.replication.id());
+        assert_eq!(
+            core.process_client_command(4, 0, psync),
+            Some(CommandResponse::Status(expected))
+        );
+        let (replica, Outgoing::Replication(missed)) = core.syncs.pop().unwrap() else {
+            panic!("expected the missed stream");
+        };
+        assert_eq!(replica, 4);
+        assert_eq!(&missed[..], set_after.to_resp().to_bytes());
+    }
+
+    #[test]
+    fn test_wait_for_replicas() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let wait = |num_replicas, timeout| {
+            Command::Wait(Wait {
+                num_replicas,
+                timeout,
+            })
+        };
+        assert_eq!(
+            core.process_client_command(1, 0, wait(0, Duration::ZERO)),
+            Some(CommandResponse::Integer(0))
+        );
+
+        let psync = Command::PSync(PSync {
+            repl_id: RedisString::from("?"),
+            offset: -1,
+        });
+        core.process_client_command(2, 0, psync);
+        let set = Command::Set(Set {
+            key: RedisString::from("key"),
+            value: RedisString::from("value"),
+        });
+        core.process_client_command(1, 0, set);
+        let offset = core.replication.offset();
+
+        // The client blocks until the replica acknowledges its write, and the
+        // replica is asked to acknowledge right away.
+        assert_eq!(
+            core.process_client_command(1, 0, wait(1, Duration::ZERO)),
+            None
+        );
+        let pending = core.replication.take_pending().unwrap();
+        let getack = Command::ReplConf(ReplConf::GetAck).to_resp().to_bytes();
+        assert!(pending.ends_with(&getack));
+        core.process_client_command(2, 0, Command::ReplConf(ReplConf::Ack(offset - 1)));
+        assert_eq!(core.unblock_clients(), vec![]);
+        core.process_client_command(2, 0, Command::ReplConf(ReplConf::Ack(offset)));
+        assert_eq!(
+            core.unblock_clients(),
+            vec![(1, CommandResponse::Integer(1))]
+        );
+
+        // On timeout, the client gets the number of replicas that did
+        // acknowledge.
+        let offset = core.replication.offset();
+        core.process_client_command(2, 0, Command::ReplConf(ReplConf::Ack(offset)));
+        assert_eq!(
+            core.process_client_command(1, 0, wait(2, Duration::from_millis(10))),
+            None
+        );
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            core.unblock_clients(),
+            vec![(1, CommandResponse::Integer(1))]
+        );
+    }
+
+    #[test]
+    fn test_command_query() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let mut query = |query| {
+            core.process_command(0, Command::Command(query))
+                .to_resp()
+                .to_string()
+        };
+        let count = command_table::COMMANDS.len();
+        assert_eq!(query(CommandQuery::Count), format!("(integer) {count}"));
+        assert!(query(CommandQuery::All).contains(&format!("\n{count})  1) \"geohash\"")));
+
+        assert_eq!(
+            query(CommandQuery::Info {
+                names: vec!["get".to_string(), "nope".to_string()],
+            }),
+            "1)  1) \"get\"\n    \
+                 2) (integer) 2\n    \
+                 3) 1) readonly\n       \
+                    2) fast\n    \
+                 4) (integer) 1\n    \
+                 5) (integer) 1\n    \
+                 6) (integer) 1\n    \
+                 7) 1) @read\n       \
+                    2) @string\n       \
+                    3) @fast\n    \
+                 8) (empty array)\n    \
+                 9) 1) 1) \"flags\"\n          \
+                       2) 1) RO\n             \
+                          2) access\n          \
+                       3) \"begin_search\"\n          \
+                       4) 1) \"type\"\n             \
+                          2) \"index\"\n             \
+                          3) \"spec\"\n             \
+                          4) 1) \"index\"\n                \
+                             2) (integer) 1\n          \
+                       5) \"find_keys\"\n          \
+                       6) 1) \"type\"\n             \
+                          2) \"range\"\n             \
+                          3) \"spec\"\n             \
+                          4) 1) \"lastkey\"\n                \
+                             2) (integer) 0\n                \
+                             3) \"keystep\"\n                \
+                             4) (integer) 1\n                \
+                             5) \"limit\"\n                \
+                             6) (integer) 0\n   \
+                10) (empty array)\n\
+             2) (nil)"
+        );
+        assert_eq!(
+            query(CommandQuery::Docs {
+                names: vec!["nope".to_string(), "llen".to_string()],
+            }),
+            "1) \"llen\"\n\
+             2) 1) \"summary\"\n   \
+                2) \"Returns the length of a list.\"\n   \
+                3) \"since\"\n   \
+                4) \"1.0.0\"\n   \
+                5) \"group\"\n   \
+                6) \"list\""
+        );
+
+        let mut get_keys = |args: &str, with_flags| {
+            let args = args.split(' ').map(RedisString::from).collect();
+            query(if with_flags {
+                CommandQuery::GetKeysAndFlags { args }
+            } else {
+                CommandQuery::GetKeys { args }
+            })
+        };
+        assert_eq!(
+            get_keys("ZUNIONSTORE dest 2 a b WEIGHTS 1 2", false),
+            "1) \"dest\"\n2) \"a\"\n3) \"b\""
+        );
+        assert_eq!(
+            get_keys("LMOVE a b LEFT RIGHT", true),
+            "1) 1) \"a\"\n   \
+                2) 1) RW\n      \
+                   2) access\n      \
+                   3) delete\n\
+             2) 1) \"b\"\n   \
+                2) 1) RW\n      \
+                   2) insert"
+        );
+        assert_eq!(
+            get_keys("NOPE k", false),
+            "(error) ERR Invalid command specified"
+        );
+        assert_eq!(
+            get_keys("GET a b", false),
+            "(error) ERR Invalid number of arguments specified for command"
+        );
+        assert_eq!(
+            get_keys("PING", false),
+            "(error) ERR The command has no key arguments"
+        );
+        assert_eq!(get_keys("EVAL script 0", false), "(empty array)");
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let query = |core: &mut ServerCore, latency| {
+            core.process_command(0, Command::Latency(latency))
+                .to_resp()
+                .to_string()
+        };
+        // The monitor is off by default.
+        core.record_latency(latency::Event::Command, Duration::from_secs(1));
+        assert_eq!(query(&mut core, Latency::Latest), "(empty array)");
+        assert!(query(&mut core, Latency::Doctor).contains("Latency monitoring is disabled"));
+
+        core.config.latency_monitor_threshold = Some(Duration::from_millis(100));
+        core.record_latency(latency::Event::Fork, Duration::from_millis(99));
+        core.record_latency(latency::Event::Fork, Duration::from_millis(150));
+        let latest = query(&mut core, Latency::Latest);
+        assert!(latest.starts_with("1) 1) \"fork\"\n   2) (integer) "));
+        assert!(latest.ends_with("\n   3) (integer) 150\n   4) (integer) 150"));
+        let history = query(
+            &mut core,
+            Latency::History {
+                event: "fork".to_string(),
+            },
+        );
+        assert!(history.starts_with("1) 1) (integer) "));
+        assert!(history.ends_with("\n   2) (integer) 150"));
+        assert!(query(&mut core, Latency::Doctor).contains("1. fork: 1 latency spikes"));
+
+        let reset = |events: &[&str]| Latency::Reset {
+            events: events.iter().map(ToString::to_string).collect(),
+        };
+        assert_eq!(query(&mut core, reset(&["nope"])), "(integer) 0");
+        assert_eq!(query(&mut core, reset(&["fork", "command"])), "(integer) 1");
+        assert_eq!(query(&mut core, Latency::Latest), "(empty array)");
+    }
+
+    #[test]
+    fn test_key_specs_match_command_keys() {
+        // Key specs are what clients see, while the server finds keys from
+        // parsed commands, so the two have to agree.
+        for line in [
+            "GET k",
+            "SET k v",
+            "DEL a b c",
+            "BLPOP a b 0",
+            "LMOVE a b LEFT RIGHT",
+            "SORT k BY w STORE dest",
+            "SUNIONSTORE dest a b",
+            "ZINTERSTORE dest 2 a b",
+            "EVAL script 2 a b arg",
+            "XREAD COUNT 1 STREAMS a b 0 0",
+            "XREADGROUP GROUP g c STREAMS a 0",
+            "OBJECT ENCODING k",
+        ] {
+            let args: Vec<&str> = line.split(' ').collect();
+            let message = Message::Array(args.iter().map(|a| Message::bulk_string(a)).collect());
+            let mut expected = command_keys(&Command::parse_resp(&message).unwrap());
+            expected.sort();
+
+            let args: Vec<&[u8]> = args.iter().map(|a| a.as_bytes()).collect();
+            let spec = command_table::resolve(&args).unwrap();
+            let mut keys: Vec<RedisString> = spec
+                .find_keys(&args)
+                .into_iter()
+                .map(|(index, _)| RedisString::from(args[index]))
+                .collect();
+            keys.sort();
+            assert_eq!(keys, expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_info_replication() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let info = |core: &mut ServerCore| {
+            let info = Command::Info(Info {
+                sections: vec!["replication".to_string()],
+            });
+            let CommandResponse::BulkString(Some(info)) = core.process_command(0, info) else {
+                panic!("expected a bulk string");
+            };
+            String::try_from(info).unwrap()
+        };
+        let text = info(&mut core);
+        assert!(text.starts_with("# Replication\r\nrole:master\r\nconnected_slaves:0\r\n"));
+        assert!(text.contains("repl_backlog_active:0\r\n"));
+
+        let replconf = Command::ReplConf(ReplConf::Options(vec![
+            ("listening-port".to_string(), RedisString::from("6380")),
+            ("ip-address".to_string(), RedisString::from("10.0.0.2")),
+        ]));
+        core.process_client_command(1, 0, replconf);
+        let psync = Command::PSync(PSync {
+            repl_id: RedisString::from("?"),
+            offset: -1,
+        });
+        core.process_client_command(1, 0, psync);
+        let set = Command::Set(Set {
+            key: RedisString::from("key"),
+            value: RedisString::from("value"),
+        });
+        core.process_client_command(2, 0, set);
+        let offset = core.replication.offset();
+        core.process_client_command(1, 0, Command::ReplConf(ReplConf::Ack(offset)));
+
+        let text = info(&mut core);
+        assert!(text.contains("connected_slaves:1\r\n"));
+        assert!(text.contains(&format!(
+            "slave0:ip=10.0.0.2,port=6380,state=online,offset={offset},lag=0\r\n"
+        )));
+        assert!(text.contains(&format!("master_replid:{}\r\n", core.replication.id())));
+        assert!(text.contains(&format!("master_repl_offset:{offset}\r\n")));
+        assert!(text.contains(&format!(
+            "repl_backlog_first_byte_offset:1\r\nrepl_backlog_histlen:{offset}\r\n"
+        )));
+
+        // Other sections are empty for now.
+        let keyspace = Command::Info(Info {
+            sections: vec!["keyspace".to_string()],
+        });
+        assert_eq!(
+            core.process_command(0, keyspace),
+            CommandResponse::BulkString(Some(RedisString::from("")))
+        );
+    }
+
+    #[test]
+    fn test_active_expiration() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "expired", "value");
+        set(&mut core, "volatile", "value");
+        set(&mut core, "persistent", "value");
+        core.process_command(0, expire("volatile", Expiration::Seconds(100), None, None));
+
+        // Expire the key without going through a command, which would delete
+        // it right away.
+        let expired = RedisString::from("expired");
+        core.dbs[0].set_expires_at(&expired, Some(1));
+        assert_eq!(core.dbs[0].entries().len(), 3);
+        assert_eq!(core.dbs[0].expires().len(), 2);
+
+        core.cron();
+        assert_eq!(core.dbs[0].entries().len(), 2);
+        assert!(!core.dbs[0].entries().contains_key(&expired));
+        assert_eq!(core.dbs[0].expires().len(), 1);
+
+        // Overwriting a key clears its TTL.
+        set(&mut core, "volatile", "value");
+        assert!(core.dbs[0].expires().is_empty());
+    }
+
+    #[test]
+    fn test_active_expire_cycle() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        for i in 0..1000 {
+            let key = format!("expired:{i}");
+            set(&mut core, &key, "value");
+            core.dbs[0].set_expires_at(&RedisString::from(key), Some(1));
+        }
+        for i in 0..5 {
+            set(&mut core, &format!("persistent:{i}"), "value");
+        }
+
+        // Every sample comes up expired, so the cycle keeps going until no
+        // keys with a TTL are left.
+        core.active_expire_cycle(Instant::now() + Duration::from_secs(10));
+        assert_eq!(core.dbs[0].entries().len(), 5);
+        assert!(core.dbs[0].expires().is_empty());
+    }
+
+    #[test]
+    fn test_active_expire_cycle_is_bounded() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        for i in 0..5000 {
+            let key = format!("key:{i}");
+            set(&mut core, &key, "value");
+            core.process_command(0, expire(&key, Expiration::Seconds(100), None, None));
+        }
+
+        // A sample of keys that haven't expired ends the cycle right away.
+        let (checked, expired) = core.dbs[0].expire_sample(unix_time_millis(), 20);
+        assert_eq!(checked, 20);
+        assert!(expired.is_empty());
+
+        // Out of time, a cycle checks one sample even if every key expired.
+        // The sample can pick a key twice, so it may remove fewer.
+        for i in 0..5000 {
+            let key = RedisString::from(format!("key:{i}"));
+            core.dbs[0].set_expires_at(&key, Some(1));
+        }
+        core.active_expire_cycle(Instant::now());
+        let left = core.dbs[0].expires().len();
+        assert!(
+            (5000 - EXPIRE_SAMPLE_SIZE..5000).contains(&left),
+            "{left} left"
+        );
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let debug = |debug| Command::Debug(debug);
+
+        let start = Instant::now();
+        let sleep = debug(DebugCommand::Sleep {
+            duration: Duration::from_millis(20),
+        });
+        assert_eq!(core.process_command(0, sleep), CommandResponse::Ok);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let object = || {
+            debug(DebugCommand::Object {
+                key: RedisString::from("key"),
+            })
+        };
+        assert_eq!(
+            core.process_command(0, object()),
+            CommandResponse::Error(ErrorReply::err("no such key"))
+        );
+        set(&mut core, "key", "value");
+        let CommandResponse::Status(text) = core.process_command(0, object()) else {
+            panic!("expected a status reply");
+        };
+        assert!(text.starts_with("Value at:"), "{text}");
+        assert!(text.contains(" refcount:1 encoding:embstr "), "{text}");
+
+        // Expired keys stay until active expiration is back on.
+        let key = RedisString::from("key");
+        core.dbs[0].set_expires_at(&key, Some(1));
+        let disable = debug(DebugCommand::SetActiveExpire { enabled: false });
+        assert_eq!(core.process_command(0, disable), CommandResponse::Ok);
+        core.cron();
+        assert!(core.dbs[0].entries().contains_key(&key));
+        let enable = debug(DebugCommand::SetActiveExpire { enabled: true });
+        assert_eq!(core.process_command(0, enable), CommandResponse::Ok);
+        core.cron();
+        assert!(core.dbs[0].entries().is_empty());
+
+        assert_eq!(
+            core.process_command(0, debug(DebugCommand::StringMatchLen)),
+            CommandResponse::Status("Apparently Redis did not crash: test passed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expiration_propagated_as_del() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let psync = Command::PSync(PSync {
+            repl_id: RedisString::from("?"),
+            offset: -1,
+        });
+        core.process_client_command(1, 0, psync);
+        for key in ["lazy", "active", "replicated"] {
+            set(&mut core, key, "value");
+            let key = RedisString::from(key);
+            core.dbs[0].set_expires_at(&key, Some(1));
+        }
+        let del = |key: &str| {
+            Command::Del(Del {
+                keys: vec![RedisString::from(key)],
+            })
+            .to_resp()
+            .to_bytes()
+        };
+
+        // Keys expired by a read are deleted on replicas too.
+        let get_lazy = Command::Get(Get {
+            key: RedisString::from("lazy"),
+        });
+        assert_eq!(
+            core.process_client_command(2, 0, get_lazy),
+            Some(CommandResponse::BulkString(None))
+        );
+        let select = Command::Select(Select { index: 0 }).to_resp().to_bytes();
+        assert_eq!(
+            core.replication.take_pending(),
+            Some([select, del("lazy")].concat())
+        );
+
+        // So are keys expired by a replica's master. A replica's own expired
+        // keys are hidden, but stay until the master deletes them.
+        core.dbs[0].replica = true;
+        assert_eq!(
+            get(&mut core, "replicated"),
+            CommandResponse::BulkString(None)
+        );
+        assert!(core.dbs[0]
+            .entries()
+            .contains_key(&RedisString::from("replicated")));
+        core.dbs[0].replica = false;
+
+        core.cron();
+        let pending = core.replication.take_pending().unwrap();
+        let message = Message::parse_resp(&mut &pending[..]).unwrap().unwrap();
+        let Command::Del(Del { mut keys }) = Command::parse_resp(&message).unwrap() else {
+            panic!("expected DEL, got {message:?}");
+        };
+        keys.sort();
+        assert_eq!(keys, ["active", "replicated"].map(RedisString::from));
+        assert!(core.dbs[0].entries().is_empty());
+    }
+
+    #[test]
+    fn test_expire_conditions() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "key", "value");
+
+        let nx = Some(Existence::Nx);
+        let xx = Some(Existence::Xx);
+        let gt = Some(Comparison::Gt);
+        let lt = Some(Comparison::Lt);
+
+        // Key has no TTL: XX and GT fail, NX and LT succeed.
+        let time = Expiration::Seconds(100);
+        assert_eq!(
+            core.process_command(0, expire("key", time, xx, None)),
+            CommandResponse::Integer(0)
+        );
+        assert_eq!(
+            core.process_command(0, expire("key", time, None, gt)),
+            CommandResponse::Integer(0)
+        );
+        assert_eq!(
+            core.process_command(0, expire("key", time, xx, lt)),
+            CommandResponse::Integer(0)
+        );
+        assert_eq!(
+            core.process_command(0, expire("key", time, nx, None)),
+            CommandResponse::Integer(1)
+        );
+
+        // Key has a TTL of 100 seconds now.
+        let longer = Expiration::Seconds(200);
+        let shorter = Expiration::Seconds(50);
+        assert_eq!(
+            core.process_command(0, expire("key", time, nx, None)),
+            CommandResponse::Integer(0)
+        );
+        assert_eq!(
+            core.process_command(0, expire("key", shorter, None, gt)),
+            CommandResponse::Integer(0)
+        );
+        assert_eq!(
+            core.process_command(0, expire("key", longer, xx, gt)),
+            CommandResponse::Integer(1)
+        );
+        assert_eq!(
+            core.process_command(0, expire("key", longer, None, lt)),
+            CommandResponse::Integer(0)
+        );
+        assert_eq!(
+            core.process_command(0, expire("key", shorter, None, lt)),
+            CommandResponse::Integer(1)
+        );
+
+        let response = core.process_command(0, ttl("key", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(50));
+    }
+
+    #[test]
+    fn test_persist_and_expire_time() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "key", "value");
+
+        let response = core.process_command(0, expire_time("key", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(-1));
+        let response = core.process_command(0, expire_time("missing", TimeUnit::Milliseconds));
+        assert_eq!(response, CommandResponse::Integer(-2));
+        assert_eq!(
+            core.process_command(0, persist("key")),
+            CommandResponse::Integer(0)
+        );
+
+        let at = Expiration::UnixMilliseconds(33_177_117_420_123);
+        core.process_command(0, expire("key", at, None, None));
+        let response = core.process_command(0, expire_time("key", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(33_177_117_420));
+        let response = core.process_command(0, expire_time("key", TimeUnit::Milliseconds));
+        assert_eq!(response, CommandResponse::Integer(33_177_117_420_123));
+
+        assert_eq!(
+            core.process_command(0, persist("key")),
+            CommandResponse::Integer(1)
+        );
+        let response = core.process_command(0, ttl("key", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(-1));
+        assert_eq!(
+            core.process_command(0, persist("missing")),
+            CommandResponse::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        for i in 0..100 {
+            set(&mut core, &format!("key:{i}"), "value");
+            set(&mut core, &format!("other:{i}"), "value");
+        }
+
+        let mut found = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let response = core.process_command(
+                0,
+                Command::Scan(Scan {
+                    cursor,
+                    pattern: Some(RedisString::from("key:*")),
+                    count: Some(15),
+                    value_type: Some(RedisString::from("STRING")),
+                }),
+            );
+            let CommandResponse::Array(response) = response else {
+                panic!("expected array response, got {response:?}");
+            };
+            let [CommandResponse::BulkString(Some(next)), CommandResponse::Array(keys)] =
+                response.as_slice()
+            else {
+                panic!("unexpected scan response {response:?}");
+            };
+            found.extend(keys.iter().cloned());
+            cursor = String::try_from(next.clone()).unwrap().parse().unwrap();
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        let mut expected: Vec<_> = (0..100)
+            .map(|i| CommandResponse::BulkString(Some(RedisString::from(format!("key:{i}")))))
+            .collect();
+        let sort_key = |r: &CommandResponse| format!("{r:?}");
+        found.sort_by_key(sort_key);
+        expected.sort_by_key(sort_key);
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        for mode in [None, Some(FlushMode::Sync), Some(FlushMode::Async)] {
+            set(&mut core, "key", "value");
+            let response = core.process_command(0, Command::FlushAll(Flush { mode }));
+            assert_eq!(response, CommandResponse::Ok);
+            assert_eq!(get(&mut core, "key"), CommandResponse::BulkString(None));
+        }
+    }
+
+    #[test]
+    fn test_del_unlink_touch() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "a", "1");
+        set(&mut core, "b", "2");
+        set(&mut core, "c", "3");
+
+        let keys = |keys: &[&str]| keys.iter().map(|k| RedisString::from(*k)).collect();
+
+        let response = core.process_command(
+            0,
+            Command::Touch(Touch {
+                keys: keys(&["a", "b", "missing"]),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(2));
+
+        let response = core.process_command(
+            0,
+            Command::Del(Del {
+                keys: keys(&["a", "missing", "a"]),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(1));
+        assert_eq!(get(&mut core, "a"), CommandResponse::BulkString(None));
+
+        let response = core.process_command(
+            0,
+            Command::Unlink(Unlink {
+                keys: keys(&["b", "c", "missing"]),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(2));
+        assert_eq!(get(&mut core, "b"), CommandResponse::BulkString(None));
+        assert_eq!(get(&mut core, "c"), CommandResponse::BulkString(None));
+    }
+
+    #[test]
+    fn test_dump_restore() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "key", "value");
+        core.process_command(0, expire("key", Expiration::Seconds(100), None, None));
+
+        let response = core.process_command(
+            0,
+            Command::Dump(Dump {
+                key: RedisString::from("key"),
+            }),
+        );
+        let CommandResponse::BulkString(Some(payload)) = response else {
+            panic!("expected payload, got {response:?}");
+        };
+        let restore = |key: &str, ttl: i64, payload: &RedisString, replace: bool| {
+            Command::Restore(Restore {
+                key: RedisString::from(key),
+                ttl,
+                payload: payload.clone(),
+                replace,
+                absttl: false,
+                idletime: None,
+            })
+        };
+
+        // Restoring over an existing key needs REPLACE.
+        let response = core.process_command(0, restore("key", 0, &payload, false));
+        assert_eq!(
+            response,
+            CommandResponse::Error(ErrorReply::new(
+                ErrorCode::BusyKey,
+                "Target key name already exists."
+            ))
+        );
+        let response = core.process_command(0, restore("key", 0, &payload, true));
+        assert_eq!(response, CommandResponse::Ok);
+        let response = core.process_command(0, ttl("key", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(-1));
+
+        let response = core.process_command(0, restore("copy", 5000, &payload, false));
+        assert_eq!(response, CommandResponse::Ok);
+        assert_eq!(
+            get(&mut core, "copy"),
+            CommandResponse::BulkString(Some(RedisString::from("value")))
+        );
+        let response = core.process_command(0, ttl("copy", TimeUnit::Seconds));
+        assert_eq!(response, CommandResponse::Integer(5));
+
+        let mut corrupt = Vec::from(payload);
+        corrupt[1] ^= 0xff;
+        let response = core.process_command(0, restore("other", 0, &corrupt.into(), false));
+        assert_eq!(
+            response,
+            CommandResponse::Error(ErrorReply::err(
+                "DUMP payload version or checksum are wrong"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = std::env::temp_dir().join(format!("redis-clone-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.rdb");
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.snapshots = Snapshots::new(&path);
+        set(&mut core, "key", "value");
+
+        let CommandResponse::Integer(started) = core.process_command(0, Command::LastSave) else {
+            panic!("expected LASTSAVE to return an integer");
+        };
+        assert_eq!(core.process_command(0, Command::Save), CommandResponse::Ok);
+        assert!(std::fs::metadata(&path).is_ok());
+
+        let response = core.process_command(0, Command::BgSave);
+        assert_eq!(
+            response,
+            CommandResponse::Status("Background saving started".to_string())
+        );
+        assert!(core.snapshots.wait());
+        let CommandResponse::Integer(last_save) = core.process_command(0, Command::LastSave) else {
+            panic!("expected LASTSAVE to return an integer");
+        };
+        assert!(last_save >= started);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_config() {
+        let dir = std::env::temp_dir().join(format!("redis-clone-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.config.aof.path = dir.join("appendonly.aof");
+        let config =
+            |core: &mut ServerCore, config| core.process_command(0, Command::Config(config));
+        let set_config = |core: &mut ServerCore, pairs: &[(&str, &str)]| {
+            let pairs = pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            config(core, Config::Set { pairs })
+        };
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+
+        let response = config(
+            &mut core,
+            Config::Get {
+                patterns: vec!["maxmemory-p*".to_string()],
+            },
+        );
+        assert_eq!(
+            response,
+            CommandResponse::Array(vec![bulk("maxmemory-policy"), bulk("noeviction")])
+        );
+
+        // Lowering maxmemory evicts keys right away.
+        for i in 0..10 {
+            set(&mut core, &format!("key:{i}"), &"x".repeat(100));
+        }
+        let response = set_config(
+            &mut core,
+            &[
+                ("maxmemory-policy", "allkeys-random"),
+                ("maxmemory", "1000"),
+            ],
+        );
+        assert_eq!(response, CommandResponse::Ok);
+        assert!(core.used_memory() <= 1000);
+        assert!(core.evicted_keys > 0);
+        set_config(&mut core, &[("maxmemory", "0")]);
+
+        // Client threads check passwords as soon as they're changed.
+        let response = set_config(&mut core, &[("requirepass", "secret")]);
+        assert_eq!(response, CommandResponse::Ok);
+        let acl = core.acl.lock().unwrap();
+        assert!(acl.get(DEFAULT_USER).unwrap().authenticates(b"secret"));
+        assert!(!acl.get(DEFAULT_USER).unwrap().authenticates(b"other"));
+        drop(acl);
+
+        let response = set_config(&mut core, &[("databases", "2")]);
+        assert!(matches!(response, CommandResponse::Error(_)));
+        let response = set_config(&mut core, &[("dbfilename", "other.rdb")]);
+        assert_eq!(response, CommandResponse::Ok);
+        assert_eq!(core.snapshots.path(), std::path::Path::new("other.rdb"));
+
+        // Turning on the AOF writes the dataset to it, and later writes are
+        // appended in order even when the fsync policy changes in between.
+        let response = set_config(
+            &mut core,
+            &[("appendonly", "yes"), ("appendfsync", "always")],
+        );
+        assert_eq!(response, CommandResponse::Ok);
+        assert!(core.aof.as_mut().unwrap().wait_for_rewrite());
+        let set_after = |core: &mut ServerCore, value: &str| {
+            let set = Command::Set(Set {
+                key: RedisString::from("after"),
+                value: RedisString::from(value),
+            });
+            core.process_client_command(0, 0, set);
+        };
+        set_after(&mut core, "1");
+        set_config(&mut core, &[("appendfsync", "no")]);
+        set_after(&mut core, "2");
+        set_config(&mut core, &[("appendfsync", "always")]);
+        let mut loaded = ServerCore::new(DEFAULT_DATABASES);
+        assert!(loaded.load_aof(&core.config.aof).unwrap());
+        assert_eq!(get(&mut loaded, "after"), bulk("2"));
+        assert_eq!(loaded.dbs[0].entries().len(), core.dbs[0].entries().len());
+
+        let response = set_config(&mut core, &[("appendonly", "no")]);
+        assert_eq!(response, CommandResponse::Ok);
+        assert!(core.aof.is_none());
+
+        assert_eq!(
+            config(&mut core, Config::Rewrite),
+            CommandResponse::Error(ErrorReply::err(
+                "The server is running without a config file"
+            ))
+        );
+        core.config.config_file = Some(dir.join("redis.conf"));
+        assert_eq!(config(&mut core, Config::Rewrite), CommandResponse::Ok);
+        let rewritten = std::fs::read_to_string(dir.join("redis.conf")).unwrap();
+        assert!(rewritten.contains("maxmemory-policy allkeys-random\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_aof() {
+        let dir = std::env::temp_dir().join(format!("redis-clone-aof-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = AofConfig {
+            path: dir.join("appendonly.aof"),
+            fsync: aof::FsyncPolicy::Always,
+            use_rdb_preamble: false,
+            ..AofConfig::default()
+        };
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.aof = Some(Aof::open(&config).unwrap());
+        let set = Command::Set(Set {
+            key: RedisString::from("key"),
+            value: RedisString::from("value"),
+        });
+        core.process_client_command(0, 0, set);
+        core.process_client_command(0, 0, expire("key", Expiration::Seconds(100), None, None));
+        let xadd = Command::XAdd(XAdd {
+            key: RedisString::from("stream"),
+            id: NewId::Auto,
+            fields: vec![(RedisString::from("f"), RedisString::from("v"))],
+        });
+        let Some(CommandResponse::BulkString(Some(id))) = core.process_client_command(0, 1, xadd)
+        else {
+            panic!("expected XADD to return an ID");
+        };
+        // Reads and failed writes aren't logged.
+        get(&mut core, "key");
+        let push = Command::Push(Push {
+            key: RedisString::from("key"),
+            end: ListEnd::Left,
+            elements: vec![RedisString::from("a")],
+        });
+        core.process_client_command(0, 0, push);
+
+        let mut replayed = Vec::new();
+        aof::replay(&config, DEFAULT_DATABASES, |record| {
+            if let aof::Record::Command(db, command) = record {
+                replayed.push((db, command));
+            }
+        })
+        .unwrap();
+        assert_eq!(replayed.len(), 3);
+        let Command::Expire(expire) = &replayed[1].1 else {
+            panic!("expected EXPIRE, got {:?}", replayed[1]);
+        };
+        assert!(matches!(expire.time, Expiration::UnixMilliseconds(_)));
+
+        let mut restored = ServerCore::new(DEFAULT_DATABASES);
+        for (db, command) in replayed {
+            restored.process_command(db, command);
+        }
+        let key = &restored.dbs[0].entries()[&RedisString::from("key")];
+        assert_eq!(key.value, Value::String(StringValue::from("value")));
+        assert_eq!(
+            restored.dbs[0].expires_at(&RedisString::from("key")),
+            core.dbs[0].expires_at(&RedisString::from("key"))
+        );
+        let stream = restored.dbs[1].get_stream(&RedisString::from("stream"));
+        assert_eq!(
+            stream.unwrap().unwrap().last_id().to_string(),
+            String::from_utf8_lossy(id.as_bytes())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Adds a key of each type for rewrites to reproduce: some in database 0
+    /// and a stream with a consumer group and pending entries in database 3.
+    fn add_one_of_each(core: &mut ServerCore) {
+        for i in 0..10 {
+            let set = Command::Set(Set {
+                key: RedisString::from("string"),
+                value: RedisString::from(i.to_string()),
+            });
+            core.process_client_command(0, 0, set);
+        }
+        core.process_client_command(0, 0, expire("string", Expiration::Seconds(100), None, None));
+        let elements: Vec<_> = (0..100).map(|i| i.to_string()).collect();
+        rpush(
+            core,
+            "list",
+            &elements.iter().map(String::as_str).collect::<Vec<_>>(),
+        );
+        sadd(core, "set", &["a", "b", "c"]);
+        zadd(core, "zset", &[(1.5, "a"), (-2.0, "b")]);
+        let hset = Command::HSet(HSet {
+            key: RedisString::from("hash"),
+            pairs: vec![(RedisString::from("f"), RedisString::from("v"))],
+        });
+        core.process_command(0, hset);
+
+        let stream = RedisString::from("stream");
+        for seq in 1..=3 {
+            let xadd = Command::XAdd(XAdd {
+                key: stream.clone(),
+                id: NewId::Explicit(StreamId { ms: 1, seq }),
+                fields: vec![(RedisString::from("f"), RedisString::from("v"))],
+            });
+            core.process_command(3, xadd);
+        }
+        let create = Command::XGroup(XGroup::Create {
+            key: stream.clone(),
+            group: RedisString::from("group"),
+            id: ReadId::After(StreamId::MIN),
+            mkstream: false,
+        });
+        core.process_command(3, create);
+        let xreadgroup = Command::XReadGroup(XReadGroup {
+            group: RedisString::from("group"),
+            consumer: RedisString::from("consumer"),
+            count: Some(2),
+            noack: false,
+            keys: vec![stream],
+            ids: vec![GroupReadId::New],
+        });
+        core.process_command(3, xreadgroup);
+    }
+
+    #[test]
+    fn test_bgrewriteaof() {
+        let dir = std::env::temp_dir().join(format!("redis-clone-rewrite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = AofConfig {
+            path: dir.join("appendonly.aof"),
+            fsync: aof::FsyncPolicy::Always,
+            use_rdb_preamble: false,
+            ..AofConfig::default()
+        };
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        assert_eq!(
+            core.process_command(0, Command::BgRewriteAof),
+            CommandResponse::Error(ErrorReply::err("Append only file is disabled"))
+        );
+        core.aof = Some(Aof::open(&config).unwrap());
+
+        add_one_of_each(&mut core);
+        let stream = RedisString::from("stream");
+
+        let started = core.process_command(0, Command::BgRewriteAof);
+        assert_eq!(
+            started,
+            CommandResponse::Status("Background append only file rewriting started".to_string())
+        );
+        // Writes during the rewrite end up in the rewritten AOF.
+        let set = Command::Set(Set {
+            key: RedisString::from("during"),
+            value: RedisString::from("rewrite"),
+        });
+        core.process_client_command(0, 5, set);
+        assert!(core.aof.as_mut().unwrap().wait_for_rewrite());
+
+        let mut restored = ServerCore::new(DEFAULT_DATABASES);
+        let mut commands = 0;
+        aof::replay(&config, DEFAULT_DATABASES, |record| {
+            if let aof::Record::Command(db, command) = record {
+                restored.process_command(db, command);
+                commands += 1;
+            }
+        })
+        .unwrap();
+        // SET and PEXPIREAT for the string, two RPUSHes for the list, one
+        // command for each of the other keys, XADDs for the stream entries,
+        // XGROUP CREATE and XCLAIMs for the pending entries, and the SET
+        // during the rewrite.
+        assert_eq!(commands, 2 + 2 + 3 + 3 + 1 + 2 + 1);
+        let keys = [
+            (0, "string"),
+            (0, "list"),
+            (0, "set"),
+            (0, "zset"),
+            (0, "hash"),
+            (5, "during"),
+        ];
+        for (db, key) in keys {
+            let key = RedisString::from(key);
+            let original = &core.dbs[db].entries()[&key];
+            let restored_db = &restored.dbs[db];
+            let restored = &restored_db.entries()[&key];
+            assert_eq!(restored.value, original.value, "{key:?}");
+            assert_eq!(
+                restored_db.expires_at(&key),
+                core.dbs[db].expires_at(&key),
+                "{key:?}"
+            );
+        }
+        let stream_value = |core: &ServerCore| match &core.dbs[3].entries()[&stream].value {
+            Value::Stream(stream) => stream.clone(),
+            value => panic!("expected a stream, got {value:?}"),
+        };
+        let (original, restored) = (stream_value(&core), stream_value(&restored));
+        assert_eq!(
+            restored
+                .range(RangeBound::Min, RangeBound::Max)
+                .collect::<Vec<_>>(),
+            original
+                .range(RangeBound::Min, RangeBound::Max)
+                .collect::<Vec<_>>()
+        );
+        let group = RedisString::from("group");
+        assert_eq!(
+            restored.groups()[&group].pending,
+            original.groups()[&group].pending
+        );
+        assert_eq!(
+            restored.groups()[&group].last_delivered,
+            original.groups()[&group].last_delivered
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_aof_rdb_preamble() {
+        let dir = std::env::temp_dir().join(format!("redis-clone-preamble-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = AofConfig {
+            path: dir.join("appendonly.aof"),
+            fsync: aof::FsyncPolicy::Always,
+            ..AofConfig::default()
+        };
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        core.aof = Some(Aof::open(&config).unwrap());
+        set(&mut core, "key", "value");
+        let xadd = Command::XAdd(XAdd {
+            key: RedisString::from("stream"),
+            id: NewId::Explicit(StreamId { ms: 1, seq: 1 }),
+            fields: vec![(RedisString::from("f"), RedisString::from("v"))],
+        });
+        core.process_command(2, xadd);
+
+        core.process_command(0, Command::BgRewriteAof);
+        let set = Command::Set(Set {
+            key: RedisString::from("during"),
+            value: RedisString::from("rewrite"),
+        });
+        core.process_client_command(0, 4, set);
+        assert!(core.aof.as_mut().unwrap().wait_for_rewrite());
+        assert!(std::fs::read(&config.path).unwrap().starts_with(b"REDIS"));
+
+        let mut restored = ServerCore::new(DEFAULT_DATABASES);
+        assert!(restored.load_aof(&config).unwrap());
+        assert_eq!(
+            get(&mut restored, "key"),
+            CommandResponse::BulkString(Some(RedisString::from("value")))
+        );
+        let during = &restored.dbs[4].entries()[&RedisString::from("during")];
+        assert_eq!(during.value, Value::String(StringValue::from("rewrite")));
+        let stream = RedisString::from("stream");
+        assert_eq!(
+            restored.dbs[2].entries()[&stream].value,
+            core.dbs[2].entries()[&stream].value
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_object() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "int", "12345");
+        set(&mut core, "short", "hello");
+        set(&mut core, "long", &"x".repeat(100));
+
+        let encoding = |core: &mut ServerCore, key: &str| {
+            core.process_command(
+                0,
+                Command::Object(Object::Encoding {
+                    key: RedisString::from(key),
+                }),
+            )
+        };
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        assert_eq!(encoding(&mut core, "int"), bulk("int"));
+        assert_eq!(encoding(&mut core, "short"), bulk("embstr"));
+        assert_eq!(encoding(&mut core, "long"), bulk("raw"));
+        assert_eq!(
+            encoding(&mut core, "missing"),
+            CommandResponse::BulkString(None)
+        );
+
+        // Small sorted sets are arrays until they get a long member, and stay
+        // skip lists after it's removed.
+        zadd(&mut core, "zset", &[(1.0, "a"), (2.0, "b")]);
+        assert_eq!(encoding(&mut core, "zset"), bulk("array"));
+        let long = "x".repeat(100);
+        zadd(&mut core, "zset", &[(3.0, &long)]);
+        assert_eq!(encoding(&mut core, "zset"), bulk("skiplist"));
+        core.process_command(
+            0,
+            Command::ZRem(ZRem {
+                key: RedisString::from("zset"),
+                members: vec![RedisString::from(long.as_str())],
+            }),
+        );
+        assert_eq!(encoding(&mut core, "zset"), bulk("skiplist"));
+
+        // Sets of integers are intsets until they get a member that isn't an
+        // integer, and stay hash tables after it's removed.
+        sadd(&mut core, "set", &["1", "-20", "300"]);
+        assert_eq!(encoding(&mut core, "set"), bulk("intset"));
+        sadd(&mut core, "set", &["a"]);
+        assert_eq!(encoding(&mut core, "set"), bulk("hashtable"));
+        core.process_command(
+            0,
+            Command::SRem(SRem {
+                key: RedisString::from("set"),
+                members: vec![RedisString::from("a")],
+            }),
+        );
+        assert_eq!(encoding(&mut core, "set"), bulk("hashtable"));
+        let many: Vec<String> = (0..=set::MAX_INTSET_ENTRIES)
+            .map(|n| n.to_string())
+            .collect();
+        let many: Vec<&str> = many.iter().map(String::as_str).collect();
+        sadd(&mut core, "big", &many);
+        assert_eq!(encoding(&mut core, "big"), bulk("hashtable"));
+        rpush(&mut core, "list", &["a", "b"]);
+        assert_eq!(encoding(&mut core, "list"), bulk("deque"));
+
+        let response = core.process_command(
+            0,
+            Command::Object(Object::RefCount {
+                key: RedisString::from("int"),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(1));
+        set(&mut core, "shared", "100");
+        assert_eq!(encoding(&mut core, "shared"), bulk("int"));
+        let response = core.process_command(
+            0,
+            Command::Object(Object::RefCount {
+                key: RedisString::from("shared"),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(i64::from(i32::MAX)));
+
+        // Editing an integer in place makes it a raw string.
+        core.process_command(
+            0,
+            Command::SetBit(SetBit {
+                key: RedisString::from("shared"),
+                offset: 1,
+                value: true,
+            }),
+        );
+        assert_eq!(encoding(&mut core, "shared"), bulk("embstr"));
+        assert_eq!(get(&mut core, "shared"), bulk("q00"));
+    }
+
+    #[test]
+    fn test_object_idletime() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        // Restore a key with a known idle time, and make sure OBJECT doesn't
+        // reset it.
+        let payload = rdb::dump(&Value::String(StringValue::from("value"))).unwrap();
+        core.process_command(
+            0,
+            Command::Restore(Restore {
+                key: RedisString::from("idle"),
+                ttl: 0,
+                payload: RedisString::from(payload),
+                replace: false,
+                absttl: false,
+                idletime: Some(100),
+            }),
+        );
+        for _ in 0..2 {
+            let response = core.process_command(
+                0,
+                Command::Object(Object::IdleTime {
+                    key: RedisString::from("idle"),
+                }),
+            );
+            assert_eq!(response, CommandResponse::Integer(100));
+        }
+        get(&mut core, "idle");
+        let response = core.process_command(
+            0,
+            Command::Object(Object::IdleTime {
+                key: RedisString::from("idle"),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(0));
+    }
+
+    #[test]
+    fn test_databases_are_separate() {
+        let mut core = ServerCore::new(2);
+        set(&mut core, "key", "value");
+        let response = core.process_command(
+            1,
+            Command::Get(Get {
+                key: RedisString::from("key"),
+            }),
+        );
+        assert_eq!(response, CommandResponse::BulkString(None));
+
+        // FLUSHDB only flushes the current database.
+        core.process_command(
+            1,
+            Command::Set(Set {
+                key: RedisString::from("key"),
+                value: RedisString::from("other"),
+            }),
+        );
+        core.process_command(1, Command::FlushDb(Flush { mode: None }));
+        assert_eq!(
+            get(&mut core, "key"),
+            CommandResponse::BulkString(Some(RedisString::from("value")))
+        );
+
+        core.process_command(1, Command::FlushAll(Flush { mode: None }));
+        assert_eq!(get(&mut core, "key"), CommandResponse::BulkString(None));
+    }
+
+    #[test]
+    fn test_move() {
+        let mut core = ServerCore::new(2);
+        set(&mut core, "key", "value");
+        core.process_command(0, expire("key", Expiration::Seconds(100), None, None));
+
+        let move_key = |core: &mut ServerCore, db: DbIndex, target: i64| {
+            core.process_command(
+                db,
+                Command::Move(Move {
+                    key: RedisString::from("key"),
+                    db: target,
+                }),
+            )
+        };
+        assert_eq!(
+            move_key(&mut core, 0, 0),
+            CommandResponse::Error(ErrorReply::err(
+                "source and destination objects are the same"
+            ))
+        );
+        assert_eq!(
+            move_key(&mut core, 0, 2),
+            CommandResponse::Error(ErrorReply::err("index out of range"))
+        );
+        assert_eq!(move_key(&mut core, 0, 1), CommandResponse::Integer(1));
+        assert_eq!(get(&mut core, "key"), CommandResponse::BulkString(None));
+        let ttl = core.process_command(
+            1,
+            Command::Ttl(Ttl {
+                key: RedisString::from("key"),
+                unit: TimeUnit::Seconds,
+            }),
+        );
+        assert_eq!(ttl, CommandResponse::Integer(100));
+
+        // Moving fails if the key is missing or already exists in the target.
+        assert_eq!(move_key(&mut core, 0, 1), CommandResponse::Integer(0));
+        set(&mut core, "key", "other");
+        assert_eq!(move_key(&mut core, 0, 1), CommandResponse::Integer(0));
+        assert_eq!(
+            get(&mut core, "key"),
+            CommandResponse::BulkString(Some(RedisString::from("other")))
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let response = core.process_command(
+            0,
+            Command::Sort(Sort {
+                key: RedisString::from("missing"),
+                ..Sort::default()
+            }),
+        );
+        assert_eq!(response, CommandResponse::Array(vec![]));
+
+        set(&mut core, "key", "value");
+        let response = core.process_command(
+            0,
+            Command::SortRo(Sort {
+                key: RedisString::from("key"),
+                ..Sort::default()
+            }),
+        );
+        assert!(matches!(response, CommandResponse::Error(_)));
+
+        set(&mut core, "dest", "value");
+        let response = core.process_command(
+            0,
+            Command::Sort(Sort {
+                key: RedisString::from("missing"),
+                store: Some(RedisString::from("dest")),
+                ..Sort::default()
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(0));
+        assert_eq!(get(&mut core, "dest"), CommandResponse::BulkString(None));
+
+        rpush(&mut core, "list", &["3", "1", "2"]);
+        set(&mut core, "weight_1", "30");
+        set(&mut core, "weight_2", "20");
+        let response = core.process_command(
+            0,
+            Command::Sort(Sort {
+                key: RedisString::from("list"),
+                by: Some(RedisString::from("weight_*")),
+                get: vec![RedisString::from("#"), RedisString::from("missing_*")],
+                store: Some(RedisString::from("dest")),
+                ..Sort::default()
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(6));
+        assert_eq!(
+            lrange(&mut core, "dest"),
+            bulk_strings(&["3", "", "2", "", "1", ""])
+        );
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        assert_eq!(
+            rpush(&mut core, "list", &["a", "b"]),
+            CommandResponse::Integer(2)
+        );
+        let response = core.process_command(
+            0,
+            Command::Push(Push {
+                key: RedisString::from("list"),
+                end: ListEnd::Left,
+                elements: vec![RedisString::from("c"), RedisString::from("d")],
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(4));
+        assert_eq!(llen(&mut core, "list"), CommandResponse::Integer(4));
+
+        let response = pop(&mut core, ListEnd::Left, None);
+        assert_eq!(
+            response,
+            CommandResponse::BulkString(Some(RedisString::from("d")))
+        );
+        let response = pop(&mut core, ListEnd::Right, Some(2));
+        assert_eq!(response, bulk_strings(&["b", "a"]));
+
+        // Popping more than the list holds returns what's left and deletes the
+        // key.
+        let response = pop(&mut core, ListEnd::Right, Some(10));
+        assert_eq!(response, bulk_strings(&["c"]));
+        assert_eq!(llen(&mut core, "list"), CommandResponse::Integer(0));
+        assert!(core.dbs[0].entries().is_empty());
+
+        let response = pop(&mut core, ListEnd::Left, None);
+        assert_eq!(response, CommandResponse::BulkString(None));
+        let response = pop(&mut core, ListEnd::Left, Some(1));
+        assert_eq!(response, CommandResponse::NullArray);
+    }
+
+    #[test]
+    fn test_list_wrong_type() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        set(&mut core, "string", "value");
+        rpush(&mut core, "list", &["a"]);
+
+        let is_wrong_type = |response: &CommandResponse| matches!(response, CommandResponse::Error(e) if e.code == ErrorCode::WrongType);
+        assert!(is_wrong_type(&rpush(&mut core, "string", &["a"])));
+        assert!(is_wrong_type(&llen(&mut core, "string")));
+        assert!(is_wrong_type(&get(&mut core, "list")));
+
+        // SET overwrites values of any type.
+        set(&mut core, "list", "value");
+        assert_eq!(
+            get(&mut core, "list"),
+            CommandResponse::BulkString(Some(RedisString::from("value")))
+        );
+    }
+
+    #[test]
+    fn test_list_range_and_index() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        rpush(&mut core, "list", &["a", "b", "c", "d"]);
+
+        let mut range = |start, stop| {
+            core.process_command(
+                0,
+                Command::LRange(LRange {
+                    key: RedisString::from("list"),
+                    start,
+                    stop,
+                }),
+            )
+        };
+        assert_eq!(range(0, -1), bulk_strings(&["a", "b", "c", "d"]));
+        assert_eq!(range(1, 2), bulk_strings(&["b", "c"]));
+        assert_eq!(range(-2, 100), bulk_strings(&["c", "d"]));
+        assert_eq!(range(-100, 0), bulk_strings(&["a"]));
+        assert_eq!(range(2, 1), bulk_strings(&[]));
+        assert_eq!(range(4, 10), bulk_strings(&[]));
+        assert_eq!(range(0, -5), bulk_strings(&[]));
+
+        let mut index = |index| {
+            core.process_command(
+                0,
+                Command::LIndex(LIndex {
+                    key: RedisString::from("list"),
+                    index,
+                }),
+            )
+        };
+        assert_eq!(
+            index(0),
+            CommandResponse::BulkString(Some(RedisString::from("a")))
+        );
+        assert_eq!(
+            index(-1),
+            CommandResponse::BulkString(Some(RedisString::from("d")))
+        );
+        assert_eq!(index(4), CommandResponse::BulkString(None));
+        assert_eq!(index(-5), CommandResponse::BulkString(None));
+    }
+
+    #[test]
+    fn test_lset() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let lset = |core: &mut ServerCore, index| {
+            core.process_command(
+                0,
+                Command::LSet(LSet {
+                    key: RedisString::from("list"),
+                    index,
+                    element: RedisString::from("x"),
+                }),
+            )
+        };
+        assert_eq!(
+            lset(&mut core, 0),
+            CommandResponse::Error(ErrorReply::err("no such key"))
+        );
+
+        rpush(&mut core, "list", &["a", "b", "c"]);
+        assert_eq!(lset(&mut core, -1), CommandResponse::Ok);
+        assert_eq!(lset(&mut core, 1), CommandResponse::Ok);
+        assert_eq!(
+            lset(&mut core, 3),
+            CommandResponse::Error(ErrorReply::err("index out of range"))
+        );
+        assert_eq!(lrange(&mut core, "list"), bulk_strings(&["a", "x", "x"]));
+    }
+
+    #[test]
+    fn test_linsert() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let linsert = |core: &mut ServerCore, position, pivot: &str| {
+            core.process_command(
+                0,
+                Command::LInsert(LInsert {
+                    key: RedisString::from("list"),
+                    position,
+                    pivot: RedisString::from(pivot),
+                    element: RedisString::from("x"),
+                }),
+            )
+        };
+        assert_eq!(
+            linsert(&mut core, InsertPosition::Before, "a"),
+            CommandResponse::Integer(0)
+        );
+        assert!(core.dbs[0].entries().is_empty());
+
+        rpush(&mut core, "list", &["a", "b", "a"]);
+        assert_eq!(
+            linsert(&mut core, InsertPosition::Before, "missing"),
+            CommandResponse::Integer(-1)
+        );
+        // Only the first matching pivot is used.
+        assert_eq!(
+            linsert(&mut core, InsertPosition::After, "a"),
+            CommandResponse::Integer(4)
+        );
+        assert_eq!(
+            linsert(&mut core, InsertPosition::Before, "a"),
+            CommandResponse::Integer(5)
+        );
+        assert_eq!(
+            lrange(&mut core, "list"),
+            bulk_strings(&["x", "a", "x", "b", "a"])
+        );
+    }
+
+    #[test]
+    fn test_lrem() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let lrem = |core: &mut ServerCore, count| {
+            core.process_command(
+                0,
+                Command::LRem(LRem {
+                    key: RedisString::from("list"),
+                    count,
+                    element: RedisString::from("a"),
+                }),
+            )
+        };
+        assert_eq!(lrem(&mut core, 0), CommandResponse::Integer(0));
+
+        rpush(&mut core, "list", &["a", "b", "a", "c", "a", "a"]);
+        assert_eq!(lrem(&mut core, 1), CommandResponse::Integer(1));
+        assert_eq!(
+            lrange(&mut core, "list"),
+            bulk_strings(&["b", "a", "c", "a", "a"])
+        );
+        assert_eq!(lrem(&mut core, -2), CommandResponse::Integer(2));
+        assert_eq!(lrange(&mut core, "list"), bulk_strings(&["b", "a", "c"]));
+        assert_eq!(lrem(&mut core, 10), CommandResponse::Integer(1));
+        assert_eq!(lrange(&mut core, "list"), bulk_strings(&["b", "c"]));
+
+        // Removing every element deletes the key.
+        core.process_command(0, Command::FlushDb(Flush { mode: None }));
+        rpush(&mut core, "list", &["a", "a"]);
+        assert_eq!(lrem(&mut core, 0), CommandResponse::Integer(2));
+        assert!(core.dbs[0].entries().is_empty());
+    }
+
+    #[test]
+    fn test_ltrim() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let ltrim = |core: &mut ServerCore, start, stop| {
+            core.process_command(
+                0,
+                Command::LTrim(LRange {
+                    key: RedisString::from("list"),
+                    start,
+                    stop,
+                }),
+            )
+        };
+        assert_eq!(ltrim(&mut core, 0, 1), CommandResponse::Ok);
+
+        rpush(&mut core, "list", &["a", "b", "c", "d", "e"]);
+        assert_eq!(ltrim(&mut core, 1, -2), CommandResponse::Ok);
+        assert_eq!(lrange(&mut core, "list"), bulk_strings(&["b", "c", "d"]));
+        assert_eq!(ltrim(&mut core, -2, 100), CommandResponse::Ok);
+        assert_eq!(lrange(&mut core, "list"), bulk_strings(&["c", "d"]));
+
+        // An empty range deletes the key.
+        assert_eq!(ltrim(&mut core, 1, 0), CommandResponse::Ok);
+        assert!(core.dbs[0].entries().is_empty());
+    }
+
+    #[test]
+    fn test_lmpop() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let lmpop = |core: &mut ServerCore, end, count| {
+            core.process_command(
+                0,
+                Command::LMPop(LMPop {
+                    keys: vec![RedisString::from("missing"), RedisString::from("list")],
+                    end,
+                    count,
+                }),
+            )
+        };
+        assert_eq!(
+            lmpop(&mut core, ListEnd::Left, None),
+            CommandResponse::NullArray
+        );
+
+        rpush(&mut core, "list", &["a", "b", "c"]);
+        let popped = |elements: &[&str]| {
+            CommandResponse::Array(vec![
+                CommandResponse::BulkString(Some(RedisString::from("list"))),
+                bulk_strings(elements),
+            ])
+        };
+        assert_eq!(lmpop(&mut core, ListEnd::Left, None), popped(&["a"]));
+        assert_eq!(
+            lmpop(&mut core, ListEnd::Right, Some(5)),
+            popped(&["c", "b"])
+        );
+        assert!(core.dbs[0].entries().is_empty());
+
+        set(&mut core, "missing", "value");
+        assert!(matches!(
+            lmpop(&mut core, ListEnd::Left, None),
+            CommandResponse::Error(_)
+        ));
+    }
+
+    fn blpop(keys: &[&str], timeout: Duration) -> Command {
+        Command::BPop(BPop {
+            keys: keys.iter().map(|k| RedisString::from(*k)).collect(),
+            end: ListEnd::Left,
+            timeout,
+        })
+    }
+
+    #[test]
+    fn test_bpop_serves_immediately() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        rpush(&mut core, "b", &["1", "2"]);
+        let response = core.process_client_command(1, 0, blpop(&["a", "b"], Duration::ZERO));
+        assert_eq!(
+            response,
+            Some(CommandResponse::Array(vec![
+                CommandResponse::BulkString(Some(RedisString::from("b"))),
+                CommandResponse::BulkString(Some(RedisString::from("1"))),
+            ]))
+        );
+
+        set(&mut core, "a", "value");
+        let response = core.process_client_command(1, 0, blpop(&["a", "b"], Duration::ZERO));
+        assert!(matches!(response, Some(CommandResponse::Error(_))));
+    }
+
+    #[test]
+    fn test_bpop_blocks_until_push() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        assert_eq!(
+            core.process_client_command(1, 0, blpop(&["list"], Duration::ZERO)),
+            None
+        );
+        assert_eq!(
+            core.process_client_command(2, 0, blpop(&["other", "list"], Duration::ZERO)),
+            None
+        );
+        assert_eq!(core.unblock_clients(), vec![]);
+
+        // Clients are served in the order they blocked.
+        rpush(&mut core, "list", &["a"]);
+        let served = |client, element: &str| {
+            (
+                client,
+                CommandResponse::Array(vec![
+                    CommandResponse::BulkString(Some(RedisString::from("list"))),
+                    CommandResponse::BulkString(Some(RedisString::from(element))),
+                ]),
+            )
+        };
+        assert_eq!(core.unblock_clients(), vec![served(1, "a")]);
+        assert!(core.dbs[0].entries().is_empty());
+
+        rpush(&mut core, "list", &["b", "c"]);
+        assert_eq!(core.unblock_clients(), vec![served(2, "b")]);
+        assert_eq!(lrange(&mut core, "list"), bulk_strings(&["c"]));
+    }
+
+    #[test]
+    fn test_bpop_timeout() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let response =
+            core.process_client_command(1, 0, blpop(&["list"], Duration::from_millis(10)));
+        assert_eq!(response, None);
+        assert!(core.blocked.next_deadline().is_some());
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            core.unblock_clients(),
+            vec![(1, CommandResponse::NullArray)]
+        );
+        assert_eq!(core.blocked.next_deadline(), None);
+
+        // The client no longer gets elements pushed to the list.
+        rpush(&mut core, "list", &["a"]);
+        assert_eq!(core.unblock_clients(), vec![]);
+    }
+
+    #[test]
+    fn test_lmove() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let lmove = |core: &mut ServerCore, source: &str, destination: &str| {
+            core.process_command(
+                0,
+                Command::LMove(LMove {
+                    source: RedisString::from(source),
+                    destination: RedisString::from(destination),
+                    from: ListEnd::Right,
+                    to: ListEnd::Left,
+                }),
+            )
+        };
+        assert_eq!(
+            lmove(&mut core, "a", "b"),
+            CommandResponse::BulkString(None)
+        );
+
+        rpush(&mut core, "a", &["1", "2"]);
+        assert_eq!(
+            lmove(&mut core, "a", "a"),
+            CommandResponse::BulkString(Some(RedisString::from("2")))
+        );
+        assert_eq!(lrange(&mut core, "a"), bulk_strings(&["2", "1"]));
+
+        set(&mut core, "string", "value");
+        assert!(matches!(
+            lmove(&mut core, "a", "string"),
+            CommandResponse::Error(_)
+        ));
+        assert_eq!(lrange(&mut core, "a"), bulk_strings(&["2", "1"]));
+
+        lmove(&mut core, "a", "b");
+        lmove(&mut core, "a", "b");
+        assert_eq!(lrange(&mut core, "b"), bulk_strings(&["2", "1"]));
+        assert_eq!(llen(&mut core, "a"), CommandResponse::Integer(0));
+    }
+
+    #[test]
+    fn test_blmove_and_blmpop() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let blmove = Command::BLMove(BLMove {
+            lmove: LMove {
+                source: RedisString::from("queue"),
+                destination: RedisString::from("processing"),
+                from: ListEnd::Right,
+                to: ListEnd::Left,
+            },
+            timeout: Duration::ZERO,
+        });
+        let blmpop = Command::BLMPop(BLMPop {
+            lmpop: LMPop {
+                keys: vec![RedisString::from("processing")],
+                end: ListEnd::Left,
+                count: Some(10),
+            },
+            timeout: Duration::ZERO,
+        });
+        assert_eq!(core.process_client_command(1, 0, blmove), None);
+        assert_eq!(core.process_client_command(2, 0, blmpop), None);
+
+        // The element moved by BLMOVE wakes up BLMPOP in turn.
+        rpush(&mut core, "queue", &["job"]);
+        assert_eq!(
+            core.unblock_clients(),
+            vec![
+                (
+                    1,
+                    CommandResponse::BulkString(Some(RedisString::from("job")))
+                ),
+                (
+                    2,
+                    CommandResponse::Array(vec![
+                        CommandResponse::BulkString(Some(RedisString::from("processing"))),
+                        bulk_strings(&["job"]),
+                    ])
+                ),
+            ]
+        );
+        assert!(core.dbs[0].entries().is_empty());
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let response = core.process_command(
+            0,
+            Command::HSet(HSet {
+                key: RedisString::from("hash"),
+                pairs: vec![
+                    (RedisString::from("a"), RedisString::from("1")),
+                    (RedisString::from("b"), RedisString::from("2")),
+                ],
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(2));
+
+        // Updating an existing field doesn't count as adding one.
+        let response = core.process_command(
+            0,
+            Command::HSet(HSet {
+                key: RedisString::from("hash"),
+                pairs: vec![
+                    (RedisString::from("a"), RedisString::from("10")),
+                    (RedisString::from("c"), RedisString::from("3")),
+                ],
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(1));
+
+        let hget = |core: &mut ServerCore, field: &str| {
+            core.process_command(
+                0,
+                Command::HGet(HGet {
+                    key: RedisString::from("hash"),
+                    field: RedisString::from(field),
+                }),
+            )
+        };
+        assert_eq!(
+            hget(&mut core, "a"),
+            CommandResponse::BulkString(Some(RedisString::from("10")))
+        );
+        assert_eq!(
+            hget(&mut core, "missing"),
+            CommandResponse::BulkString(None)
+        );
+
+        let hdel = |core: &mut ServerCore, fields: &[&str]| {
+            core.process_command(
+                0,
+                Command::HDel(HDel {
+                    key: RedisString::from("hash"),
+                    fields: fields.iter().map(|f| RedisString::from(*f)).collect(),
+                }),
+            )
+        };
+        assert_eq!(
+            hdel(&mut core, &["a", "missing"]),
+            CommandResponse::Integer(1)
+        );
+
+        let response = core.process_command(
+            0,
+            Command::HGetAll(HGetAll {
+                key: RedisString::from("hash"),
+            }),
+        );
+        let CommandResponse::Array(elements) = response else {
+            panic!("expected an array, got {response:?}");
+        };
+        // Fields come back in no particular order, so sort the pairs.
+        let mut pairs: Vec<_> = elements
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+        pairs.sort_by_key(|(field, _)| format!("{field:?}"));
+        assert_eq!(
+            pairs,
+            vec![
+                (
+                    CommandResponse::BulkString(Some(RedisString::from("b"))),
+                    CommandResponse::BulkString(Some(RedisString::from("2")))
+                ),
+                (
+                    CommandResponse::BulkString(Some(RedisString::from("c"))),
+                    CommandResponse::BulkString(Some(RedisString::from("3")))
+                ),
+            ]
+        );
+
+        // Deleting the last field deletes the key.
+        assert_eq!(hdel(&mut core, &["b", "c"]), CommandResponse::Integer(2));
+        assert!(core.dbs[0].entries().is_empty());
+    }
+
+    #[test]
+    fn test_hash_fields() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let key = || RedisString::from("hash");
+        let hsetnx = |core: &mut ServerCore, field: &str, value: &str| {
+            core.process_command(
+                0,
+                Command::HSetNx(HSetNx {
+                    key: key(),
+                    field: RedisString::from(field),
+                    value: RedisString::from(value),
+                }),
+            )
+        };
+        assert_eq!(hsetnx(&mut core, "a", "hello"), CommandResponse::Integer(1));
+        assert_eq!(hsetnx(&mut core, "a", "other"), CommandResponse::Integer(0));
+        assert_eq!(hsetnx(&mut core, "b", "2"), CommandResponse::Integer(1));
+
+        let response = core.process_command(
+            0,
+            Command::HMGet(HMGet {
+                key: key(),
+                fields: vec![RedisString::from("a"), RedisString::from("missing")],
+            }),
+        );
+        assert_eq!(
+            response,
+            CommandResponse::Array(vec![
+                CommandResponse::BulkString(Some(RedisString::from("hello"))),
+                CommandResponse::BulkString(None),
+            ])
+        );
+
+        let response = core.process_command(0, Command::HLen(HLen { key: key() }));
+        assert_eq!(response, CommandResponse::Integer(2));
+        let response = core.process_command(
+            0,
+            Command::HExists(HExists {
+                key: key(),
+                field: RedisString::from("b"),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(1));
+        let response = core.process_command(
+            0,
+            Command::HStrLen(HStrLen {
+                key: key(),
+                field: RedisString::from("a"),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(5));
+
+        let sorted = |response: CommandResponse| {
+            let CommandResponse::Array(mut elements) = response else {
+                panic!("expected an array, got {response:?}");
+            };
+            elements.sort_by_key(|e| format!("{e:?}"));
+            CommandResponse::Array(elements)
+        };
+        let response = core.process_command(0, Command::HKeys(HKeys { key: key() }));
+        assert_eq!(sorted(response), bulk_strings(&["a", "b"]));
+        let response = core.process_command(0, Command::HVals(HVals { key: key() }));
+        assert_eq!(sorted(response), bulk_strings(&["2", "hello"]));
+
+        let missing = || RedisString::from("missing");
+        let response = core.process_command(0, Command::HKeys(HKeys { key: missing() }));
+        assert_eq!(response, bulk_strings(&[]));
+        let response = core.process_command(0, Command::HLen(HLen { key: missing() }));
+        assert_eq!(response, CommandResponse::Integer(0));
+    }
+
+    #[test]
+    fn test_hscan() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let pairs = (0..25)
+            .map(|i| {
+                (
+                    RedisString::from(format!("field{i}")),
+                    RedisString::from(i.to_string()),
+                )
+            })
+            .collect();
+        core.process_command(
+            0,
+            Command::HSet(HSet {
+                key: RedisString::from("hash"),
+                pairs,
+            }),
+        );
+
+        let hscan = |core: &mut ServerCore, cursor, novalues| {
+            let response = core.process_command(
+                0,
+                Command::HScan(HScan {
+                    key: RedisString::from("hash"),
+                    cursor,
+                    pattern: Some(RedisString::from("field1*")),
+                    count: Some(7),
+                    novalues,
+                }),
+            );
+            let CommandResponse::Array(mut reply) = response else {
+                panic!("expected an array, got {response:?}");
+            };
+            let Some(CommandResponse::Array(elements)) = reply.pop() else {
+                panic!("expected an array of elements");
+            };
+            let Some(CommandResponse::BulkString(Some(cursor))) = reply.pop() else {
+                panic!("expected a cursor");
+            };
+            (String::try_from(cursor).unwrap().parse().unwrap(), elements)
+        };
+
+        // Walking the whole hash returns each matching field once.
+        let mut cursor = 0;
+        let mut fields = Vec::new();
+        loop {
+            let (next_cursor, elements) = hscan(&mut core, cursor, false);
+            for pair in elements.chunks(2) {
+                let [CommandResponse::BulkString(Some(field)), CommandResponse::BulkString(Some(value))] =
+                    pair
+                else {
+                    panic!("expected a field and a value, got {pair:?}");
+                };
+                assert_eq!(field.as_bytes(), [b"field", value.as_bytes()].concat());
+                fields.push(field.clone());
+            }
+            cursor = next_cursor;
+            if cursor == 0 {
+                break;
+            }
+        }
+        fields.sort_by_key(|f| format!("{f:?}"));
+        let expected: Vec<_> = [
+            "field1", "field10", "field11", "field12", "field13", "field14", "field15", "field16",
+            "field17", "field18", "field19",
+        ]
+        .into_iter()
+        .map(RedisString::from)
+        .collect();
+        assert_eq!(fields, expected);
+
+        let (_, elements) = hscan(&mut core, 0, true);
+        assert!(elements.iter().all(|e| matches!(e, CommandResponse::BulkString(Some(f)) if f.as_bytes().starts_with(b"field1"))));
+    }
+
+    #[test]
+    fn test_set() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        assert_eq!(
+            sadd(&mut core, "set", &["a", "b", "a"]),
+            CommandResponse::Integer(2)
+        );
+        assert_eq!(
+            sadd(&mut core, "set", &["b", "c"]),
+            CommandResponse::Integer(1)
+        );
+        assert_eq!(smembers(&mut core, "set"), strings(&["a", "b", "c"]));
+
+        let sismember = |core: &mut ServerCore, member: &str| {
+            core.process_command(
+                0,
+                Command::SIsMember(SIsMember {
+                    key: RedisString::from("set"),
+                    member: RedisString::from(member),
+                }),
+            )
+        };
+        assert_eq!(sismember(&mut core, "a"), CommandResponse::Integer(1));
+        assert_eq!(sismember(&mut core, "z"), CommandResponse::Integer(0));
+
+        let scard = |core: &mut ServerCore, key: &str| {
+            core.process_command(
+                0,
+                Command::SCard(SCard {
+                    key: RedisString::from(key),
+                }),
+            )
+        };
+        assert_eq!(scard(&mut core, "set"), CommandResponse::Integer(3));
+        assert_eq!(scard(&mut core, "missing"), CommandResponse::Integer(0));
+
+        let srem = |core: &mut ServerCore, members: &[&str]| {
+            core.process_command(
+                0,
+                Command::SRem(SRem {
+                    key: RedisString::from("set"),
+                    members: members.iter().map(|m| RedisString::from(*m)).collect(),
+                }),
+            )
+        };
+        assert_eq!(srem(&mut core, &["a", "z"]), CommandResponse::Integer(1));
+
+        // Removing the last member deletes the key.
+        assert_eq!(srem(&mut core, &["b", "c"]), CommandResponse::Integer(2));
+        assert!(core.dbs[0].entries().is_empty());
+
+        set(&mut core, "string", "value");
+        assert_eq!(sadd(&mut core, "string", &["a"]), wrong_type_error());
+    }
+
+    #[test]
+    fn test_set_op() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        sadd(&mut core, "a", &["1", "2", "3", "4"]);
+        sadd(&mut core, "b", &["2", "3", "5"]);
+        sadd(&mut core, "c", &["3", "6"]);
+
+        let set_op = |core: &mut ServerCore, op, keys: &[&str], store: Option<&str>| {
+            core.process_command(
+                0,
+                Command::SetOp(SetOp {
+                    op,
+                    keys: keys.iter().map(|k| RedisString::from(*k)).collect(),
+                    store: store.map(RedisString::from),
+                }),
+            )
+        };
+        let sorted = |response| {
+            let CommandResponse::Array(mut members) = response else {
+                panic!("expected an array, got {response:?}");
+            };
+            members.sort_by_key(|m| format!("{m:?}"));
+            members
+        };
+
+        let inter = set_op(&mut core, SetOperation::Inter, &["a", "b", "c"], None);
+        assert_eq!(sorted(inter), strings(&["3"]));
+        let union = set_op(&mut core, SetOperation::Union, &["b", "c", "missing"], None);
+        assert_eq!(sorted(union), strings(&["2", "3", "5", "6"]));
+        let diff = set_op(&mut core, SetOperation::Diff, &["a", "b", "missing"], None);
+        assert_eq!(sorted(diff), strings(&["1", "4"]));
+
+        // A missing key is an empty set, so the intersection is empty.
+        let inter = set_op(&mut core, SetOperation::Inter, &["a", "missing"], None);
+        assert_eq!(inter, CommandResponse::Array(vec![]));
+
+        let response = set_op(&mut core, SetOperation::Union, &["a", "c"], Some("dest"));
+        assert_eq!(response, CommandResponse::Integer(5));
+        assert_eq!(
+            smembers(&mut core, "dest"),
+            strings(&["1", "2", "3", "4", "6"])
+        );
+
+        // The destination can be one of the sources, and an empty result
+        // deletes it.
+        let response = set_op(
+            &mut core,
+            SetOperation::Diff,
+            &["dest", "dest"],
+            Some("dest"),
+        );
+        assert_eq!(response, CommandResponse::Integer(0));
+        assert!(core.dbs[0]
+            .entries()
+            .get(&RedisString::from("dest"))
+            .is_none());
+
+        set(&mut core, "string", "value");
+        let response = set_op(&mut core, SetOperation::Inter, &["missing", "string"], None);
+        assert_eq!(response, wrong_type_error());
+    }
+
+    #[test]
+    fn test_smismember_and_sintercard() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        sadd(&mut core, "a", &["1", "2", "3", "4"]);
+        sadd(&mut core, "b", &["2", "3", "4", "5"]);
+
+        let response = core.process_command(
+            0,
+            Command::SMIsMember(SMIsMember {
+                key: RedisString::from("a"),
+                members: vec![RedisString::from("1"), RedisString::from("5")],
+            }),
+        );
+        assert_eq!(
+            response,
+            CommandResponse::Array(vec![
+                CommandResponse::Integer(1),
+                CommandResponse::Integer(0)
+            ])
+        );
+
+        let sintercard = |core: &mut ServerCore, keys: &[&str], limit| {
+            core.process_command(
+                0,
+                Command::SInterCard(SInterCard {
+                    keys: keys.iter().map(|k| RedisString::from(*k)).collect(),
+                    limit,
+                }),
+            )
+        };
+        assert_eq!(
+            sintercard(&mut core, &["a", "b"], None),
+            CommandResponse::Integer(3)
+        );
+        assert_eq!(
+            sintercard(&mut core, &["a", "b"], Some(2)),
+            CommandResponse::Integer(2)
+        );
+        // A limit of 0 means no limit.
+        assert_eq!(
+            sintercard(&mut core, &["a", "b"], Some(0)),
+            CommandResponse::Integer(3)
+        );
+        assert_eq!(
+            sintercard(&mut core, &["a", "missing"], None),
+            CommandResponse::Integer(0)
+        );
+
+        set(&mut core, "string", "value");
+        assert_eq!(
+            sintercard(&mut core, &["missing", "string"], None),
+            wrong_type_error()
+        );
+    }
+
+    #[test]
+    fn test_sscan() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let members: Vec<String> = (0..40).map(|i| format!("m{i}")).collect();
+        let members: Vec<&str> = members.iter().map(String::as_str).collect();
+        sadd(&mut core, "set", &members);
+
+        // Walking the whole set returns each matching member once.
+        let mut cursor = 0;
+        let mut found = Vec::new();
+        loop {
+            let response = core.process_command(
+                0,
+                Command::SScan(SScan {
+                    key: RedisString::from("set"),
+                    cursor,
+                    pattern: Some(RedisString::from("m3*")),
+                    count: Some(6),
+                }),
+            );
+            let CommandResponse::Array(reply) = response else {
+                panic!("expected an array, got {response:?}");
+            };
+            let [CommandResponse::BulkString(Some(next_cursor)), CommandResponse::Array(elements)] =
+                reply.as_slice()
+            else {
+                panic!("expected a cursor and elements, got {reply:?}");
+            };
+            found.extend(elements.iter().cloned());
+            cursor = String::try_from(next_cursor.clone())
+                .unwrap()
+                .parse()
+                .unwrap();
+            if cursor == 0 {
+                break;
+            }
+        }
+        found.sort_by_key(|m| format!("{m:?}"));
+        assert_eq!(
+            found,
+            strings(&["m3", "m30", "m31", "m32", "m33", "m34", "m35", "m36", "m37", "m38", "m39"])
+        );
+
+        let response = core.process_command(
+            0,
+            Command::SScan(SScan {
+                key: RedisString::from("missing"),
+                cursor: 0,
+                pattern: None,
+                count: None,
+            }),
+        );
+        assert_eq!(
+            response,
+            CommandResponse::Array(vec![
+                CommandResponse::BulkString(Some(RedisString::from("0"))),
+                CommandResponse::Array(vec![]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_zset() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        assert_eq!(
+            zadd(&mut core, "zset", &[(1.0, "a"), (2.5, "b"), (3.0, "a")]),
+            CommandResponse::Integer(2)
+        );
+        assert_eq!(zscore(&mut core, "a"), bulk("3"));
+        assert_eq!(
+            zscore(&mut core, "missing"),
+            CommandResponse::BulkString(None)
+        );
+
+        let zcard = |core: &mut ServerCore| {
+            core.process_command(
+                0,
+                Command::ZCard(ZCard {
+                    key: RedisString::from("zset"),
+                }),
+            )
+        };
+        assert_eq!(zcard(&mut core), CommandResponse::Integer(2));
+
+        let zrem = |core: &mut ServerCore, members: &[&str]| {
+            core.process_command(
+                0,
+                Command::ZRem(ZRem {
+                    key: RedisString::from("zset"),
+                    members: members.iter().map(|m| RedisString::from(*m)).collect(),
+                }),
+            )
+        };
+        assert_eq!(
+            zrem(&mut core, &["a", "missing"]),
+            CommandResponse::Integer(1)
+        );
+        assert_eq!(zrem(&mut core, &["b", "c"]), CommandResponse::Integer(1));
+        assert!(core.dbs[0].entries().is_empty());
+
+        set(&mut core, "string", "value");
+        assert_eq!(zadd(&mut core, "string", &[(1.0, "a")]), wrong_type_error());
+    }
+
+    #[test]
+    fn test_zadd_flags() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let zadd_with =
+            |core: &mut ServerCore, zadd: ZAdd| core.process_command(0, Command::ZAdd(zadd));
+        let flags = |members: &[(f64, &str)]| ZAdd {
+            key: RedisString::from("zset"),
+            existence: None,
+            comparison: None,
+            ch: false,
+            incr: false,
+            members: members
+                .iter()
+                .map(|(s, m)| (Score::new(*s).unwrap(), RedisString::from(*m)))
+                .collect(),
+        };
+
+        // XX on a missing key doesn't create an empty sorted set.
+        let response = zadd_with(
+            &mut core,
+            ZAdd {
+                existence: Some(Existence::Xx),
+                ..flags(&[(1.0, "a")])
+            },
+        );
+        assert_eq!(response, CommandResponse::Integer(0));
+        assert!(core.dbs[0].entries().is_empty());
+
+        zadd(&mut core, "zset", &[(3.0, "a"), (2.5, "b")]);
+
+        // CH counts updated members too.
+        let response = zadd_with(
+            &mut core,
+            ZAdd {
+                ch: true,
+                ..flags(&[(4.0, "a"), (2.5, "b"), (1.0, "c")])
+            },
+        );
+        assert_eq!(response, CommandResponse::Integer(2));
+
+        // NX only adds, XX only updates, and GT/LT only move scores one way.
+        let response = zadd_with(
+            &mut core,
+            ZAdd {
+                existence: Some(Existence::Nx),
+                ..flags(&[(10.0, "a"), (5.0, "d")])
+            },
+        );
+        assert_eq!(response, CommandResponse::Integer(1));
+        assert_eq!(zscore(&mut core, "a"), bulk("4"));
+        let response = zadd_with(
+            &mut core,
+            ZAdd {
+                existence: Some(Existence::Xx),
+                ch: true,
+                ..flags(&[(10.0, "a"), (5.0, "e")])
+            },
+        );
+        assert_eq!(response, CommandResponse::Integer(1));
+        assert_eq!(zscore(&mut core, "e"), CommandResponse::BulkString(None));
+        let response = zadd_with(
+            &mut core,
+            ZAdd {
+                comparison: Some(Comparison::Gt),
+                ch: true,
+                ..flags(&[(1.0, "a"), (6.0, "d")])
+            },
+        );
+        assert_eq!(response, CommandResponse::Integer(1));
+        assert_eq!(zscore(&mut core, "a"), bulk("10"));
+
+        // INCR replies with the new score, or nil if the update was skipped.
+        let response = zadd_with(
+            &mut core,
+            ZAdd {
+                incr: true,
+                ..flags(&[(-0.5, "a")])
+            },
+        );
+        assert_eq!(response, bulk("9.5"));
+        let response = zadd_with(
+            &mut core,
+            ZAdd {
+                incr: true,
+                comparison: Some(Comparison::Gt),
+                ..flags(&[(-1.0, "a")])
+            },
+        );
+        assert_eq!(response, CommandResponse::BulkString(None));
+        zadd(&mut core, "zset", &[(f64::INFINITY, "inf")]);
+        let response = zadd_with(
+            &mut core,
+            ZAdd {
+                incr: true,
+                ..flags(&[(f64::NEG_INFINITY, "inf")])
+            },
+        );
+        assert!(matches!(response, CommandResponse::Error(_)));
+        assert_eq!(zscore(&mut core, "inf"), bulk("inf"));
+    }
+
+    #[test]
+    fn test_zrange() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        zadd(
+            &mut core,
+            "zset",
+            &[
+                (1.0, "a"),
+                (2.0, "b"),
+                (3.0, "c"),
+                (3.0, "d"),
+                (f64::INFINITY, "e"),
+            ],
+        );
+        let zrange = |core: &mut ServerCore, args: &[&str]| {
+            let mut message = vec![Message::bulk_string("ZRANGE"), Message::bulk_string("zset")];
+            message.extend(args.iter().map(|a| Message::bulk_string(a)));
+            let command = Command::parse_resp(&Message::Array(message)).unwrap();
+            core.process_command(0, command)
+        };
+
+        assert_eq!(
+            zrange(&mut core, &["0", "-1"]),
+            bulk_strings(&["a", "b", "c", "d", "e"])
+        );
+        assert_eq!(
+            zrange(&mut core, &["1", "2", "WITHSCORES"]),
+            bulk_strings(&["b", "2", "c", "3"])
+        );
+        assert_eq!(
+            zrange(&mut core, &["0", "1", "REV"]),
+            bulk_strings(&["e", "d"])
+        );
+        assert_eq!(zrange(&mut core, &["5", "10"]), bulk_strings(&[]));
+
+        assert_eq!(
+            zrange(&mut core, &["(1", "3", "BYSCORE"]),
+            bulk_strings(&["b", "c", "d"])
+        );
+        assert_eq!(
+            zrange(
+                &mut core,
+                &[
+                    "+inf",
+                    "2",
+                    "BYSCORE",
+                    "REV",
+                    "LIMIT",
+                    "1",
+                    "2",
+                    "WITHSCORES"
+                ]
+            ),
+            bulk_strings(&["d", "3", "c", "3"])
+        );
+        assert_eq!(
+            zrange(&mut core, &["-inf", "+inf", "BYSCORE", "LIMIT", "3", "-1"]),
+            bulk_strings(&["d", "e"])
+        );
+        assert_eq!(
+            zrange(&mut core, &["-inf", "+inf", "BYSCORE", "LIMIT", "-1", "1"]),
+            bulk_strings(&[])
+        );
+
+        zadd(&mut core, "lex", &[(0.0, "a"), (0.0, "b"), (0.0, "c")]);
+        let response = core.process_command(
+            0,
+            Command::ZRange(ZRange {
+                key: RedisString::from("lex"),
+                by: ZRangeBy::Lex(LexRange {
+                    min: LexBound::Exclusive(RedisString::from("a")),
+                    max: LexBound::Max,
+                }),
+                rev: true,
+                limit: None,
+                withscores: false,
+            }),
+        );
+        assert_eq!(response, bulk_strings(&["c", "b"]));
+
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::ZRange(ZRange {
+                    key: RedisString::from("missing"),
+                    by: ZRangeBy::Rank { start: 0, stop: -1 },
+                    rev: false,
+                    limit: None,
+                    withscores: false,
+                })
+            ),
+            bulk_strings(&[])
+        );
+    }
+
+    #[test]
+    fn test_zrank_zincrby_zcount() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        zadd(&mut core, "zset", &[(10.0, "a"), (20.0, "b"), (30.0, "c")]);
+
+        let zrank = |core: &mut ServerCore, member: &str, rev, withscore| {
+            core.process_command(
+                0,
+                Command::ZRank(ZRank {
+                    key: RedisString::from("zset"),
+                    member: RedisString::from(member),
+                    rev,
+                    withscore,
+                }),
+            )
+        };
+        assert_eq!(
+            zrank(&mut core, "a", false, false),
+            CommandResponse::Integer(0)
+        );
+        assert_eq!(
+            zrank(&mut core, "a", true, false),
+            CommandResponse::Integer(2)
+        );
+        assert_eq!(
+            zrank(&mut core, "b", false, true),
+            CommandResponse::Array(vec![
+                CommandResponse::Integer(1),
+                CommandResponse::BulkString(Some(RedisString::from("20"))),
+            ])
+        );
+        assert_eq!(
+            zrank(&mut core, "missing", false, false),
+            CommandResponse::BulkString(None)
+        );
+        assert_eq!(
+            zrank(&mut core, "missing", false, true),
+            CommandResponse::NullArray
+        );
+
+        let zincrby = |core: &mut ServerCore, increment: f64, member: &str| {
+            core.process_command(
+                0,
+                Command::ZIncrBy(ZIncrBy {
+                    key: RedisString::from("zset"),
+                    increment: Score::new(increment).unwrap(),
+                    member: RedisString::from(member),
+                }),
+            )
+        };
+        assert_eq!(
+            zincrby(&mut core, 25.0, "a"),
+            CommandResponse::BulkString(Some(RedisString::from("35")))
+        );
+        assert_eq!(
+            zincrby(&mut core, 0.5, "d"),
+            CommandResponse::BulkString(Some(RedisString::from("0.5")))
+        );
+        assert_eq!(
+            zrank(&mut core, "a", false, false),
+            CommandResponse::Integer(3)
+        );
+
+        let zcount = |core: &mut ServerCore, min: &str, max: &str| {
+            let bound = |s: &str| ScoreBound::parse(&RedisString::from(s)).unwrap();
+            core.process_command(
+                0,
+                Command::ZCount(ZCount {
+                    key: RedisString::from("zset"),
+                    range: ScoreRange {
+                        min: bound(min),
+                        max: bound(max),
+                    },
+                }),
+            )
+        };
+        assert_eq!(
+            zcount(&mut core, "-inf", "+inf"),
+            CommandResponse::Integer(4)
+        );
+        assert_eq!(zcount(&mut core, "(20", "35"), CommandResponse::Integer(2));
+        assert_eq!(zcount(&mut core, "40", "50"), CommandResponse::Integer(0));
+    }
+
+    #[test]
+    fn test_zset_op() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        zadd(&mut core, "z1", &[(1.0, "a"), (2.0, "b"), (3.0, "c")]);
+        zadd(&mut core, "z2", &[(10.0, "b"), (20.0, "c"), (30.0, "d")]);
+        sadd(&mut core, "set", &["c", "d"]);
+        let run = |core: &mut ServerCore, args: &[&str]| {
+            let message = args.iter().map(|a| Message::bulk_string(a)).collect();
+            let command = Command::parse_resp(&Message::Array(message)).unwrap();
+            core.process_command(0, command)
+        };
+
+        assert_eq!(
+            run(&mut core, &["ZUNION", "2", "z1", "z2", "WITHSCORES"]),
+            bulk_strings(&["a", "1", "b", "12", "c", "23", "d", "30"])
+        );
+        assert_eq!(
+            run(
+                &mut core,
+                &[
+                    "ZINTER",
+                    "2",
+                    "z1",
+                    "z2",
+                    "WEIGHTS",
+                    "2",
+                    "1",
+                    "AGGREGATE",
+                    "MIN",
+                    "WITHSCORES"
+                ]
+            ),
+            bulk_strings(&["b", "4", "c", "6"])
+        );
+        assert_eq!(
+            run(&mut core, &["ZDIFF", "2", "z1", "z2", "WITHSCORES"]),
+            bulk_strings(&["a", "1"])
+        );
+
+        // Plain sets count as sorted sets with every score 1.
+        assert_eq!(
+            run(
+                &mut core,
+                &["ZINTER", "2", "z2", "set", "AGGREGATE", "MAX", "WITHSCORES"]
+            ),
+            bulk_strings(&["c", "20", "d", "30"])
+        );
+        assert_eq!(
+            run(&mut core, &["ZINTER", "2", "z1", "missing"]),
+            bulk_strings(&[])
+        );
+
+        assert_eq!(
+            run(&mut core, &["ZUNIONSTORE", "dest", "2", "z1", "set"]),
+            CommandResponse::Integer(4)
+        );
+        assert_eq!(
+            run(&mut core, &["ZRANGE", "dest", "0", "-1", "WITHSCORES"]),
+            bulk_strings(&["a", "1", "d", "1", "b", "2", "c", "4"])
+        );
+        assert_eq!(
+            run(&mut core, &["ZDIFFSTORE", "dest", "2", "z1", "z1"]),
+            CommandResponse::Integer(0)
+        );
+        assert!(core.dbs[0]
+            .entries()
+            .get(&RedisString::from("dest"))
+            .is_none());
+
+        // Infinite scores that cancel out become 0.
+        zadd(&mut core, "inf", &[(f64::INFINITY, "a")]);
+        zadd(&mut core, "neginf", &[(f64::NEG_INFINITY, "a")]);
+        assert_eq!(
+            run(&mut core, &["ZUNION", "2", "inf", "neginf", "WITHSCORES"]),
+            bulk_strings(&["a", "0"])
+        );
+
+        set(&mut core, "string", "value");
+        assert_eq!(
+            run(&mut core, &["ZUNION", "2", "z1", "string"]),
+            wrong_type_error()
+        );
+    }
+
+    #[test]
+    fn test_zrandmember_and_zmscore() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        zadd(&mut core, "zset", &[(1.0, "a"), (2.0, "b"), (3.0, "c")]);
+        let zrandmember = |core: &mut ServerCore, key: &str, count, withscores| {
+            let response = core.process_command(
+                0,
+                Command::ZRandMember(ZRandMember {
+                    key: RedisString::from(key),
+                    count,
+                    withscores,
+                }),
+            );
+            match response {
+                CommandResponse::Array(elements) => elements,
+                response => vec![response],
+            }
+        };
+        let members = strings(&["a", "b", "c"]);
+
+        let picked = zrandmember(&mut core, "zset", None, false);
+        assert!(picked.len() == 1 && members.contains(&picked[0]));
+
+        // A positive count returns distinct members, at most all of them.
+        let mut picked = zrandmember(&mut core, "zset", Some(5), false);
+        picked.sort_by_key(|m| format!("{m:?}"));
+        assert_eq!(picked, members);
+        let picked = zrandmember(&mut core, "zset", Some(2), false);
+        assert!(picked.len() == 2 && picked[0] != picked[1]);
+
+        // A negative count may repeat members.
+        let picked = zrandmember(&mut core, "zset", Some(-10), false);
+        assert!(picked.len() == 10 && picked.iter().all(|m| members.contains(m)));
+
+        let picked = zrandmember(&mut core, "zset", Some(1), true);
+        let expected_score = match &picked[0] {
+            CommandResponse::BulkString(Some(m)) if m.as_bytes() == b"a" => "1",
+            CommandResponse::BulkString(Some(m)) if m.as_bytes() == b"b" => "2",
+            _ => "3",
+        };
+        assert_eq!(picked[1], strings(&[expected_score])[0]);
+
+        assert_eq!(
+            zrandmember(&mut core, "missing", None, false),
+            vec![CommandResponse::BulkString(None)]
+        );
+        assert!(zrandmember(&mut core, "missing", Some(-3), false).is_empty());
+
+        let response = core.process_command(
+            0,
+            Command::ZMScore(ZMScore {
+                key: RedisString::from("zset"),
+                members: vec![RedisString::from("b"), RedisString::from("missing")],
+            }),
+        );
+        assert_eq!(
+            response,
+            CommandResponse::Array(vec![
+                CommandResponse::BulkString(Some(RedisString::from("2"))),
+                CommandResponse::BulkString(None),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_bitmaps() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let key = || RedisString::from("bits");
+        let setbit = |core: &mut ServerCore, offset, value| {
+            core.process_command(
+                0,
+                Command::SetBit(SetBit {
+                    key: key(),
+                    offset,
+                    value,
+                }),
+            )
+        };
+        assert_eq!(setbit(&mut core, 7, true), CommandResponse::Integer(0));
+        assert_eq!(setbit(&mut core, 7, true), CommandResponse::Integer(1));
+        assert_eq!(setbit(&mut core, 17, true), CommandResponse::Integer(0));
+        assert_eq!(setbit(&mut core, 7, false), CommandResponse::Integer(1));
+        assert_eq!(
+            core.process_command(0, Command::Get(Get { key: key() })),
+            CommandResponse::BulkString(Some(RedisString::from(b"\x00\x00\x40" as &[u8])))
+        );
+        let getbit = |core: &mut ServerCore, offset| {
+            core.process_command(0, Command::GetBit(GetBit { key: key(), offset }))
+        };
+        assert_eq!(getbit(&mut core, 17), CommandResponse::Integer(1));
+        assert_eq!(getbit(&mut core, 1000), CommandResponse::Integer(0));
+
+        // Examples from the Redis documentation.
+        set(&mut core, "bits", "foobar");
+        let bitcount = |core: &mut ServerCore, range| {
+            core.process_command(0, Command::BitCount(BitCount { key: key(), range }))
+        };
+        let range = |start, end, unit| Some(BitRange { start, end, unit });
+        assert_eq!(bitcount(&mut core, None), CommandResponse::Integer(26));
+        assert_eq!(
+            bitcount(&mut core, range(1, 1, BitUnit::Byte)),
+            CommandResponse::Integer(6)
+        );
+        assert_eq!(
+            bitcount(&mut core, range(5, 30, BitUnit::Bit)),
+            CommandResponse::Integer(17)
+        );
+        assert_eq!(
+            bitcount(&mut core, range(-2, -100, BitUnit::Byte)),
+            CommandResponse::Integer(0)
+        );
+
+        core.process_command(0, Command::Del(Del { keys: vec![key()] }));
+        assert_eq!(bitcount(&mut core, None), CommandResponse::Integer(0));
+
+        sadd(&mut core, "set", &["a"]);
+        let response = core.process_command(
+            0,
+            Command::SetBit(SetBit {
+                key: RedisString::from("set"),
+                offset: 0,
+                value: true,
+            }),
+        );
+        assert_eq!(response, wrong_type_error());
+    }
+
+    #[test]
+    fn test_bitpos() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let key = || RedisString::from("bits");
+        let bitpos = |core: &mut ServerCore, bit, start, end, unit| {
+            core.process_command(
+                0,
+                Command::BitPos(BitPos {
+                    key: key(),
+                    bit,
+                    start,
+                    end,
+                    unit,
+                }),
+            )
+        };
+        let set_bytes = |core: &mut ServerCore, bytes: &[u8]| {
+            core.process_command(
+                0,
+                Command::Set(Set {
+                    key: key(),
+                    value: RedisString::from(bytes),
+                }),
+            );
+        };
+        set_bytes(&mut core, b"\xff\xf0\x00");
+        assert_eq!(
+            bitpos(&mut core, false, None, None, BitUnit::Byte),
+            CommandResponse::Integer(12)
+        );
+        set_bytes(&mut core, b"\x00\xff\xf0");
+        assert_eq!(
+            bitpos(&mut core, true, Some(2), None, BitUnit::Byte),
+            CommandResponse::Integer(16)
+        );
+        assert_eq!(
+            bitpos(&mut core, true, Some(7), Some(15), BitUnit::Bit),
+            CommandResponse::Integer(8)
+        );
+        set_bytes(&mut core, b"\xff\xff\xff");
+        assert_eq!(
+            bitpos(&mut core, false, None, None, BitUnit::Byte),
+            CommandResponse::Integer(24)
+        );
+        assert_eq!(
+            bitpos(&mut core, false, Some(0), Some(-1), BitUnit::Byte),
+            CommandResponse::Integer(-1)
+        );
+        set_bytes(&mut core, b"\x00\x00\x00");
+        assert_eq!(
+            bitpos(&mut core, true, None, None, BitUnit::Byte),
+            CommandResponse::Integer(-1)
+        );
+
+        core.process_command(0, Command::Del(Del { keys: vec![key()] }));
+        assert_eq!(
+            bitpos(&mut core, false, None, None, BitUnit::Byte),
+            CommandResponse::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let xadd = |core: &mut ServerCore, id: &str, fields: &[(&str, &str)]| {
+            core.process_command(
+                0,
+                Command::XAdd(XAdd {
+                    key: RedisString::from("stream"),
+                    id: NewId::parse(&RedisString::from(id)).unwrap(),
+                    fields: fields
+                        .iter()
+                        .map(|(f, v)| (RedisString::from(*f), RedisString::from(*v)))
+                        .collect(),
+                }),
+            )
+        };
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        assert_eq!(xadd(&mut core, "1-1", &[("a", "1")]), bulk("1-1"));
+        assert_eq!(xadd(&mut core, "1-*", &[("b", "2")]), bulk("1-2"));
+        assert_eq!(xadd(&mut core, "2", &[("c", "3"), ("d", "4")]), bulk("2-0"));
+        assert!(matches!(
+            xadd(&mut core, "1-5", &[("e", "5")]),
+            CommandResponse::Error(_)
+        ));
+        let CommandResponse::BulkString(Some(auto)) = xadd(&mut core, "*", &[("e", "5")]) else {
+            panic!("expected an ID");
+        };
+        let auto = StreamId::parse(&auto, 0).unwrap();
+        assert!(auto > StreamId { ms: 2, seq: 0 });
+
+        let response = core.process_command(
+            0,
+            Command::XLen(XLen {
+                key: RedisString::from("stream"),
+            }),
+        );
+        assert_eq!(response, CommandResponse::Integer(4));
+
+        let xrange = |core: &mut ServerCore, start, end, count, rev| {
+            core.process_command(
+                0,
+                Command::XRange(XRange {
+                    key: RedisString::from("stream"),
+                    start,
+                    end,
+                    count,
+                    rev,
+                }),
+            )
+        };
+        let id = |ms, seq| StreamId { ms, seq };
+        assert_eq!(
+            xrange(
+                &mut core,
+                RangeBound::Exclusive(id(1, 1)),
+                RangeBound::Inclusive(id(2, 0)),
+                None,
+                false
+            ),
+            CommandResponse::Array(vec![
+                CommandResponse::Array(vec![bulk("1-2"), bulk_strings(&["b", "2"])]),
+                CommandResponse::Array(vec![bulk("2-0"), bulk_strings(&["c", "3", "d", "4"])]),
+            ])
+        );
+        assert_eq!(
+            xrange(&mut core, RangeBound::Min, RangeBound::Max, Some(1), true),
+            CommandResponse::Array(vec![CommandResponse::Array(vec![
+                bulk(&auto.to_string()),
+                bulk_strings(&["e", "5"]),
+            ])])
+        );
+        assert_eq!(
+            xrange(&mut core, RangeBound::Max, RangeBound::Min, None, false),
+            CommandResponse::Array(vec![])
+        );
+
+        set(&mut core, "string", "value");
+        let response = core.process_command(
+            0,
+            Command::XLen(XLen {
+                key: RedisString::from("string"),
+            }),
+        );
+        assert_eq!(response, wrong_type_error());
+    }
+
+    #[test]
+    fn test_xread() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let xadd = |core: &mut ServerCore, key: &str, id: &str| {
+            core.process_command(
+                0,
+                Command::XAdd(XAdd {
+                    key: RedisString::from(key),
+                    id: NewId::parse(&RedisString::from(id)).unwrap(),
+                    fields: vec![(RedisString::from("f"), RedisString::from(id))],
+                }),
+            )
+        };
+        let xread = |keys: &[&str], ids: &[&str], block| {
+            Command::XRead(XRead {
+                count: Some(1),
+                block,
+                keys: keys.iter().map(|k| RedisString::from(*k)).collect(),
+                ids: ids
+                    .iter()
+                    .map(|id| ReadId::parse(&RedisString::from(*id)).unwrap())
+                    .collect(),
+            })
+        };
+        let entries = |key: &str, ids: &[&str]| {
+            CommandResponse::Array(vec![
+                CommandResponse::BulkString(Some(RedisString::from(key))),
+                CommandResponse::Array(
+                    ids.iter()
+                        .map(|id| {
+                            CommandResponse::Array(vec![
+                                CommandResponse::BulkString(Some(RedisString::from(*id))),
+                                bulk_strings(&["f", id]),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ])
+        };
+
+        xadd(&mut core, "a", "1-1");
+        xadd(&mut core, "a", "1-2");
+        assert_eq!(
+            core.process_command(0, xread(&["a", "b"], &["0", "0"], None)),
+            CommandResponse::Array(vec![entries("a", &["1-1"])])
+        );
+        assert_eq!(
+            core.process_command(0, xread(&["a"], &["1-2"], None)),
+            CommandResponse::NullArray
+        );
+        assert_eq!(
+            core.process_command(0, xread(&["a"], &["$"], None)),
+            CommandResponse::NullArray
+        );
+
+        // Readers waiting for new entries are all woken by the next XADD.
+        let block = Some(Duration::ZERO);
+        assert_eq!(
+            core.process_client_command(1, 0, xread(&["a"], &["$"], block)),
+            None
+        );
+        assert_eq!(
+            core.process_client_command(2, 0, xread(&["b", "a"], &["$", "1-2"], block)),
+            None
+        );
+        assert_eq!(
+            core.process_client_command(3, 0, xread(&["b"], &["0"], block)),
+            None
+        );
+        xadd(&mut core, "a", "2-0");
+        assert_eq!(
+            core.unblock_clients(),
+            vec![
+                (1, CommandResponse::Array(vec![entries("a", &["2-0"])])),
+                (2, CommandResponse::Array(vec![entries("a", &["2-0"])])),
+            ]
+        );
+        xadd(&mut core, "b", "5-0");
+        assert_eq!(
+            core.unblock_clients(),
+            vec![(3, CommandResponse::Array(vec![entries("b", &["5-0"])]))]
+        );
+
+        // Entries that are already there are returned right away.
+        assert_eq!(
+            core.process_client_command(1, 0, xread(&["a"], &["1-2"], block)),
+            Some(CommandResponse::Array(vec![entries("a", &["2-0"])]))
+        );
+
+        let response = core.process_client_command(
+            1,
+            0,
+            xread(&["a"], &["$"], Some(Duration::from_millis(10))),
+        );
+        assert_eq!(response, None);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            core.unblock_clients(),
+            vec![(1, CommandResponse::NullArray)]
+        );
+
+        set(&mut core, "string", "value");
+        assert_eq!(
+            core.process_client_command(1, 0, xread(&["string"], &["0"], block)),
+            Some(wrong_type_error())
+        );
+    }
+
+    #[test]
+    fn test_consumer_groups() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let s = RedisString::from;
+        let id = |ms| StreamId { ms, seq: 0 };
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let xgroup = |mkstream| {
+            Command::XGroup(XGroup::Create {
+                key: s("s"),
+                group: s("g"),
+                id: ReadId::After(StreamId::MIN),
+                mkstream,
+            })
+        };
+        let xreadgroup = |consumer: &str, id| {
+            Command::XReadGroup(XReadGroup {
+                group: s("g"),
+                consumer: RedisString::from(consumer),
+                count: None,
+                noack: false,
+                keys: vec![s("s")],
+                ids: vec![id],
+            })
+        };
+        let entry = |id: &str| CommandResponse::Array(vec![bulk(id), bulk_strings(&["f", id])]);
+
+        let CommandResponse::Error(e) = core.process_command(0, xgroup(false)) else {
+            panic!("expected an error");
+        };
+        assert!(e.message.contains("MKSTREAM"));
+        assert_eq!(core.process_command(0, xgroup(true)), CommandResponse::Ok);
+        let CommandResponse::Error(e) = core.process_command(0, xgroup(true)) else {
+            panic!("expected an error");
+        };
+        assert_eq!(e.code, ErrorCode::BusyGroup);
+
+        for (ms, name) in [(1, "1-0"), (2, "2-0")] {
+            core.process_command(
+                0,
+                Command::XAdd(XAdd {
+                    key: s("s"),
+                    id: NewId::Explicit(id(ms)),
+                    fields: vec![(s("f"), s(name))],
+                }),
+            );
+        }
+        assert_eq!(
+            core.process_command(0, xreadgroup("alice", GroupReadId::New)),
+            CommandResponse::Array(vec![CommandResponse::Array(vec![
+                bulk("s"),
+                CommandResponse::Array(vec![entry("1-0"), entry("2-0")]),
+            ])])
+        );
+        assert_eq!(
+            core.process_command(0, xreadgroup("bob", GroupReadId::New)),
+            CommandResponse::NullArray
+        );
+        assert_eq!(
+            core.process_command(0, xreadgroup("bob", GroupReadId::After(StreamId::MIN))),
+            CommandResponse::Array(vec![CommandResponse::Array(vec![
+                bulk("s"),
+                CommandResponse::Array(vec![]),
+            ])])
+        );
+
+        let CommandResponse::Error(e) = core.process_command(
+            0,
+            Command::XPending(XPending {
+                key: s("s"),
+                group: s("nope"),
+                range: None,
+            }),
+        ) else {
+            panic!("expected an error");
+        };
+        assert_eq!(e.code, ErrorCode::NoGroup);
+    }
+
+    /// Creates group "g" on stream "s" and has "alice" read its two entries,
+    /// 1-0 and 2-0, so they're pending.
+    fn read_two_entries(core: &mut ServerCore) {
+        let s = RedisString::from;
+        let id = |ms| StreamId { ms, seq: 0 };
+        core.process_command(
+            0,
+            Command::XGroup(XGroup::Create {
+                key: s("s"),
+                group: s("g"),
+                id: ReadId::After(StreamId::MIN),
+                mkstream: true,
+            }),
+        );
+        for (ms, name) in [(1, "1-0"), (2, "2-0")] {
+            core.process_command(
+                0,
+                Command::XAdd(XAdd {
+                    key: s("s"),
+                    id: NewId::Explicit(id(ms)),
+                    fields: vec![(s("f"), s(name))],
+                }),
+            );
+        }
+        core.process_command(
+            0,
+            Command::XReadGroup(XReadGroup {
+                group: s("g"),
+                consumer: s("alice"),
+                count: None,
+                noack: false,
+                keys: vec![s("s")],
+                ids: vec![GroupReadId::New],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_pending_and_claim() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let s = RedisString::from;
+        let id = |ms| StreamId { ms, seq: 0 };
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let xclaim = |consumer: &str, min_idle, justid| {
+            Command::XClaim(XClaim {
+                key: s("s"),
+                group: s("g"),
+                consumer: RedisString::from(consumer),
+                min_idle,
+                ids: vec![id(1), id(2), id(9)],
+                idle: None,
+                time: None,
+                retry_count: None,
+                force: false,
+                justid,
+            })
+        };
+        let entry = |id: &str| CommandResponse::Array(vec![bulk(id), bulk_strings(&["f", id])]);
+        read_two_entries(&mut core);
+
+        let xpending = |range| {
+            Command::XPending(XPending {
+                key: s("s"),
+                group: s("g"),
+                range,
+            })
+        };
+        assert_eq!(
+            core.process_command(0, xpending(None)),
+            CommandResponse::Array(vec![
+                CommandResponse::Integer(2),
+                bulk("1-0"),
+                bulk("2-0"),
+                CommandResponse::Array(vec![bulk_strings(&["alice", "2"])]),
+            ])
+        );
+
+        // Nothing has been idle for an hour, so nothing is claimed.
+        assert_eq!(
+            core.process_command(0, xclaim("bob", 3_600_000, false)),
+            CommandResponse::Array(vec![])
+        );
+        assert_eq!(
+            core.process_command(0, xclaim("bob", 0, false)),
+            CommandResponse::Array(vec![entry("1-0"), entry("2-0")])
+        );
+        let range = PendingRange {
+            min_idle: None,
+            start: RangeBound::Min,
+            end: RangeBound::Max,
+            count: 1,
+            consumer: Some(s("bob")),
+        };
+        let CommandResponse::Array(pending) = core.process_command(0, xpending(Some(range))) else {
+            panic!("expected an array");
+        };
+        let [CommandResponse::Array(fields)] = pending.as_slice() else {
+            panic!("expected one pending entry");
+        };
+        assert_eq!(fields[..2], [bulk("1-0"), bulk("bob")]);
+        assert_eq!(fields[3], CommandResponse::Integer(2));
+
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::XAck(XAck {
+                    key: s("s"),
+                    group: s("g"),
+                    ids: vec![id(1)],
+                })
+            ),
+            CommandResponse::Integer(1)
+        );
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::XAutoClaim(XAutoClaim {
+                    key: s("s"),
+                    group: s("g"),
+                    consumer: s("alice"),
+                    min_idle: 0,
+                    start: StreamId::MIN,
+                    count: None,
+                    justid: true,
+                })
+            ),
+            CommandResponse::Array(vec![
+                bulk("0-0"),
+                bulk_strings(&["2-0"]),
+                CommandResponse::Array(vec![]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_stream_trim_and_info() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let s = RedisString::from;
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        for ms in 1..=5 {
+            core.process_command(
+                0,
+                Command::XAdd(XAdd {
+                    key: s("s"),
+                    id: NewId::Explicit(StreamId { ms, seq: 0 }),
+                    fields: vec![(s("f"), s("v"))],
+                }),
+            );
+        }
+        let xtrim = |strategy, approx| {
+            Command::XTrim(XTrim {
+                key: s("s"),
+                trim: Trim {
+                    strategy,
+                    approx,
+                    limit: None,
+                },
+            })
+        };
+        assert_eq!(
+            core.process_command(0, xtrim(TrimStrategy::MaxLen(1), true)),
+            CommandResponse::Integer(0)
+        );
+        assert_eq!(
+            core.process_command(0, xtrim(TrimStrategy::MaxLen(4), false)),
+            CommandResponse::Integer(1)
+        );
+        assert_eq!(
+            core.process_command(
+                0,
+                xtrim(TrimStrategy::MinId(StreamId { ms: 3, seq: 0 }), false)
+            ),
+            CommandResponse::Integer(1)
+        );
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::XDel(XDel {
+                    key: s("s"),
+                    ids: vec![StreamId { ms: 5, seq: 0 }, StreamId { ms: 9, seq: 0 }],
+                })
+            ),
+            CommandResponse::Integer(1)
+        );
+
+        let entry = |id| CommandResponse::Array(vec![bulk(id), bulk_strings(&["f", "v"])]);
+        assert_eq!(
+            core.process_command(0, Command::XInfo(XInfo::Stream { key: s("s") })),
+            CommandResponse::Array(vec![
+                bulk("length"),
+                CommandResponse::Integer(2),
+                bulk("last-generated-id"),
+                bulk("5-0"),
+                bulk("max-deleted-entry-id"),
+                bulk("5-0"),
+                bulk("entries-added"),
+                CommandResponse::Integer(5),
+                bulk("recorded-first-entry-id"),
+                bulk("3-0"),
+                bulk("groups"),
+                CommandResponse::Integer(0),
+                bulk("first-entry"),
+                entry("3-0"),
+                bulk("last-entry"),
+                entry("4-0"),
+            ])
+        );
+
+        assert_eq!(
+            core.process_command(0, Command::XInfo(XInfo::Stream { key: s("nope") })),
+            CommandResponse::Error(ErrorReply::err("no such key"))
+        );
+    }
+
+    #[test]
+    fn test_xinfo_groups() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let s = RedisString::from;
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        core.process_command(
+            0,
+            Command::XGroup(XGroup::Create {
+                key: s("s"),
+                group: s("g"),
+                id: ReadId::Last,
+                mkstream: true,
+            }),
+        );
+        assert_eq!(
+            core.process_command(0, Command::XInfo(XInfo::Groups { key: s("s") })),
+            CommandResponse::Array(vec![CommandResponse::Array(vec![
+                bulk("name"),
+                bulk("g"),
+                bulk("consumers"),
+                CommandResponse::Integer(0),
+                bulk("pending"),
+                CommandResponse::Integer(0),
+                bulk("last-delivered-id"),
+                bulk("0-0"),
+            ])])
+        );
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::XInfo(XInfo::Consumers {
+                    key: s("s"),
+                    group: s("g"),
+                })
+            ),
+            CommandResponse::Array(vec![])
+        );
+    }
+
+    /// Runs GEOADD on the "Sicily" key with `(longitude, latitude, member)`
+    /// triples.
+    fn geoadd(core: &mut ServerCore, members: &[(f64, f64, &str)]) -> CommandResponse {
+        let members = members
+            .iter()
+            .map(|(lon, lat, member)| {
+                (
+                    Coordinates::new(*lon, *lat).unwrap(),
+                    RedisString::from(*member),
+                )
+            })
+            .collect();
+        core.process_command(
+            0,
+            Command::GeoAdd(GeoAdd {
+                key: RedisString::from("Sicily"),
+                existence: None,
+                ch: false,
+                members,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_geo() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let search = |shape, with| GeoSearch {
+            key: RedisString::from("Sicily"),
+            from: GeoOrigin::LonLat(Coordinates::new(15.0, 37.0).unwrap()),
+            shape,
+            unit: DistanceUnit::Kilometers,
+            order: Some(SortOrder::Asc),
+            count: None,
+            any: false,
+            with_coord: with,
+            with_dist: with,
+            with_hash: false,
+        };
+
+        // The examples from the Redis documentation.
+        assert_eq!(
+            geoadd(
+                &mut core,
+                &[
+                    (13.361_389, 38.115_556, "Palermo"),
+                    (15.087_269, 37.502_669, "Catania"),
+                ]
+            ),
+            CommandResponse::Integer(2)
+        );
+        geoadd(
+            &mut core,
+            &[
+                (12.758_489, 38.788_135, "edge1"),
+                (17.241_510, 38.788_135, "edge2"),
+            ],
+        );
+        assert_eq!(
+            core.process_command(0, Command::GeoSearch(search(Shape::Radius(200.0), false))),
+            bulk_strings(&["Catania", "Palermo"])
+        );
+
+        let box_search = search(
+            Shape::Box {
+                width: 400.0,
+                height: 400.0,
+            },
+            true,
+        );
+        let CommandResponse::Array(results) =
+            core.process_command(0, Command::GeoSearch(box_search.clone()))
+        else {
+            panic!("expected an array");
+        };
+        let summary: Vec<_> = results
+            .iter()
+            .map(|result| {
+                let CommandResponse::Array(fields) = result else {
+                    panic!("expected an array");
+                };
+                (fields[0].clone(), fields[1].clone())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (bulk("Catania"), bulk("56.4413")),
+                (bulk("Palermo"), bulk("190.4424")),
+                (bulk("edge2"), bulk("279.7403")),
+                (bulk("edge1"), bulk("279.7405")),
+            ]
+        );
+
+        // COUNT returns the closest matches, unless ANY is given.
+        let count = |order, any| {
+            Command::GeoSearch(GeoSearch {
+                order,
+                count: Some(1),
+                any,
+                with_coord: false,
+                with_dist: false,
+                ..box_search.clone()
+            })
+        };
+        assert_eq!(
+            core.process_command(0, count(None, false)),
+            bulk_strings(&["Catania"])
+        );
+        assert_eq!(
+            core.process_command(0, count(Some(SortOrder::Desc), false)),
+            bulk_strings(&["edge1"])
+        );
+        let CommandResponse::Array(any) = core.process_command(0, count(None, true)) else {
+            panic!("expected an array");
+        };
+        assert_eq!(any.len(), 1);
+    }
+
+    #[test]
+    fn test_geo_members() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        geoadd(
+            &mut core,
+            &[
+                (13.361_389, 38.115_556, "Palermo"),
+                (15.087_269, 37.502_669, "Catania"),
+            ],
+        );
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::ZScore(ZScore {
+                    key: RedisString::from("Sicily"),
+                    member: RedisString::from("Palermo"),
+                })
+            ),
+            bulk("3479099956230698")
+        );
+
+        let geodist = |member2: &str, unit| {
+            Command::GeoDist(GeoDist {
+                key: RedisString::from("Sicily"),
+                member1: RedisString::from("Palermo"),
+                member2: RedisString::from(member2),
+                unit,
+            })
+        };
+        assert_eq!(
+            core.process_command(0, geodist("Catania", DistanceUnit::Meters)),
+            bulk("166274.1516")
+        );
+        assert_eq!(
+            core.process_command(0, geodist("Catania", DistanceUnit::Miles)),
+            bulk("103.3182")
+        );
+        assert_eq!(
+            core.process_command(0, geodist("Rome", DistanceUnit::Meters)),
+            CommandResponse::BulkString(None)
+        );
+
+        let members = vec![RedisString::from("Catania"), RedisString::from("Rome")];
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::GeoHash(GeoHash {
+                    key: RedisString::from("Sicily"),
+                    members: members.clone(),
+                })
+            ),
+            CommandResponse::Array(vec![bulk("sqdtr74hyu0"), CommandResponse::BulkString(None)])
+        );
+        let CommandResponse::Array(positions) = core.process_command(
+            0,
+            Command::GeoPos(GeoPos {
+                key: RedisString::from("Sicily"),
+                members,
+            }),
+        ) else {
+            panic!("expected an array");
+        };
+        assert_eq!(positions[1], CommandResponse::NullArray);
+        let CommandResponse::Array(catania) = &positions[0] else {
+            panic!("expected coordinates");
+        };
+        let degrees = |response: &CommandResponse| {
+            let CommandResponse::BulkString(Some(s)) = response else {
+                panic!("expected a bulk string");
+            };
+            Score::parse(s).unwrap().value()
+        };
+        assert!((degrees(&catania[0]) - 15.087_269).abs() < 1e-5);
+        assert!((degrees(&catania[1]) - 37.502_669).abs() < 1e-5);
+
+        let from_missing = Command::GeoSearch(GeoSearch {
+            key: RedisString::from("Sicily"),
+            from: GeoOrigin::Member(RedisString::from("Rome")),
+            shape: Shape::Radius(200.0),
+            unit: DistanceUnit::Kilometers,
+            order: None,
+            count: None,
+            any: false,
+            with_coord: false,
+            with_dist: false,
+            with_hash: false,
+        });
+        assert_eq!(
+            core.process_command(0, from_missing),
+            CommandResponse::Error(ErrorReply::err("could not decode requested zset member"))
+        );
+    }
+
+    #[test]
+    fn test_zscan() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let members: Vec<(f64, String)> =
+            (0..30).map(|i| (f64::from(i), format!("m{i}"))).collect();
+        let members: Vec<(f64, &str)> = members.iter().map(|(s, m)| (*s, m.as_str())).collect();
+        zadd(&mut core, "zset", &members);
+
+        // Walking the whole sorted set returns each matching member once,
+        // along with its score.
+        let mut cursor = 0;
+        let mut found = Vec::new();
+        loop {
+            let response = core.process_command(
+                0,
+                Command::ZScan(ZScan {
+                    key: RedisString::from("zset"),
+                    cursor,
+                    pattern: Some(RedisString::from("m2*")),
+                    count: Some(4),
+                }),
+            );
+            let CommandResponse::Array(reply) = response else {
+                panic!("expected an array, got {response:?}");
+            };
+            let [CommandResponse::BulkString(Some(next_cursor)), CommandResponse::Array(elements)] =
+                reply.as_slice()
+            else {
+                panic!("expected a cursor and elements, got {reply:?}");
+            };
+            for pair in elements.chunks(2) {
+                let [CommandResponse::BulkString(Some(member)), CommandResponse::BulkString(Some(score))] =
+                    pair
+                else {
+                    panic!("expected a member and a score, got {pair:?}");
+                };
+                assert_eq!(member.as_bytes(), [b"m", score.as_bytes()].concat());
+                found.push(member.clone());
+            }
+            cursor = String::try_from(next_cursor.clone())
+                .unwrap()
+                .parse()
+                .unwrap();
+            if cursor == 0 {
+                break;
+            }
+        }
+        found.sort_by_key(|m| format!("{m:?}"));
+        let expected: Vec<_> = [
+            "m2", "m20", "m21", "m22", "m23", "m24", "m25", "m26", "m27", "m28", "m29",
+        ]
+        .into_iter()
+        .map(RedisString::from)
+        .collect();
+        assert_eq!(found, expected);
+    }
+
+    fn zadd(core: &mut ServerCore, key: &str, members: &[(f64, &str)]) -> CommandResponse {
+        core.process_command(
+            0,
+            Command::ZAdd(ZAdd {
+                key: RedisString::from(key),
+                existence: None,
+                comparison: None,
+                ch: false,
+                incr: false,
+                members: members
+                    .iter()
+                    .map(|(s, m)| (Score::new(*s).unwrap(), RedisString::from(*m)))
+                    .collect(),
+            }),
+        )
+    }
+
+    fn zscore(core: &mut ServerCore, member: &str) -> CommandResponse {
+        core.process_command(
+            0,
+            Command::ZScore(ZScore {
+                key: RedisString::from("zset"),
+                member: RedisString::from(member),
+            }),
+        )
+    }
+
+    fn sadd(core: &mut ServerCore, key: &str, members: &[&str]) -> CommandResponse {
+        core.process_command(
+            0,
+            Command::SAdd(SAdd {
+                key: RedisString::from(key),
+                members: members.iter().map(|m| RedisString::from(*m)).collect(),
+            }),
+        )
+    }
+
+    /// The members of a set, sorted since sets are unordered.
+    fn smembers(core: &mut ServerCore, key: &str) -> Vec<CommandResponse> {
+        let response = core.process_command(
+            0,
+            Command::SMembers(SMembers {
+                key: RedisString::from(key),
+            }),
+        );
+        let CommandResponse::Array(mut members) = response else {
+            panic!("expected an array, got {response:?}");
+        };
+        members.sort_by_key(|m| format!("{m:?}"));
+        members
+    }
+
+    fn strings(strs: &[&str]) -> Vec<CommandResponse> {
+        strs.iter()
+            .map(|s| CommandResponse::BulkString(Some(RedisString::from(*s))))
+            .collect()
+    }
+
+    fn rpush(core: &mut ServerCore, key: &str, elements: &[&str]) -> CommandResponse {
+        core.process_command(
+            0,
+            Command::Push(Push {
+                key: RedisString::from(key),
+                end: ListEnd::Right,
+                elements: elements.iter().map(|e| RedisString::from(*e)).collect(),
+            }),
+        )
+    }
+
+    fn pop(core: &mut ServerCore, end: ListEnd, count: Option<usize>) -> CommandResponse {
+        core.process_command(
+            0,
+            Command::Pop(Pop {
+                key: RedisString::from("list"),
+                end,
+                count,
+            }),
+        )
+    }
+
+    fn llen(core: &mut ServerCore, key: &str) -> CommandResponse {
+        core.process_command(
+            0,
+            Command::LLen(LLen {
+                key: RedisString::from(key),
+            }),
+        )
+    }
+
+    fn lrange(core: &mut ServerCore, key: &str) -> CommandResponse {
+        core.process_command(
+            0,
+            Command::LRange(LRange {
+                key: RedisString::from(key),
+                start: 0,
+                stop: -1,
+            }),
+        )
+    }
+
+    fn bulk_strings(strs: &[&str]) -> CommandResponse {
+        CommandResponse::Array(
+            strs.iter()
+                .map(|s| CommandResponse::BulkString(Some(RedisString::from(*s))))
+                .collect(),
+        )
+    }
+
+    fn set(core: &mut ServerCore, key: &str, value: &str) {
+        let response = core.process_command(
+            0,
+            Command::Set(Set {
+                key: RedisString::from(key),
+                value: RedisString::from(value),
+            }),
+        );
         assert_eq!(response, CommandResponse::Ok);
+    }
 
-        let get_command = Command::Get(Get {
-            key: RedisString::from("key"),
+    fn get(core: &mut ServerCore, key: &str) -> CommandResponse {
+        core.process_command(
+            0,
+            Command::Get(Get {
+                key: RedisString::from(key),
+            }),
+        )
+    }
+
+    fn expire(
+        key: &str,
+        time: Expiration,
+        existence: Option<Existence>,
+        comparison: Option<Comparison>,
+    ) -> Command {
+        Command::Expire(Expire {
+            key: RedisString::from(key),
+            time,
+            existence,
+            comparison,
+        })
+    }
+
+    fn ttl(key: &str, unit: TimeUnit) -> Command {
+        Command::Ttl(Ttl {
+            key: RedisString::from(key),
+            unit,
+        })
+    }
+
+    fn expire_time(key: &str, unit: TimeUnit) -> Command {
+        Command::ExpireTime(ExpireTime {
+            key: RedisString::from(key),
+            unit,
+        })
+    }
+
+    fn persist(key: &str) -> Command {
+        Command::Persist(Persist {
+            key: RedisString::from(key),
+        })
+    }
+
+    #[test]
+    fn test_client_tracking() {
+        let mut core = ServerCore::new(DEFAULT_DATABASES);
+        let key = |k: &str| RedisString::from(k);
+        let get = |k: &str| Command::Get(Get { key: key(k) });
+        let set = |k: &str| {
+            Command::Set(Set {
+                key: key(k),
+                value: key("value"),
+            })
+        };
+        let tracking = |mode, redirect| {
+            Command::Client(Client::Tracking(Some(ClientTracking { mode, redirect })))
+        };
+        let invalidated = |keys: &[&str]| {
+            invalidation(CommandResponse::Array(
+                keys.iter()
+                    .map(|k| CommandResponse::BulkString(Some(key(k))))
+                    .collect(),
+            ))
+        };
+
+        // Connections speak RESP2, so messages have to be redirected.
+        assert_eq!(
+            core.process_client_command(1, 0, tracking(TrackingMode::Default, None)),
+            Some(CommandResponse::Error(ErrorReply::err(
+                "Tracking without REDIRECT needs RESP3, which isn't supported"
+            )))
+        );
+
+        // Client 1 caches the keys it reads, and client 2 watches a prefix.
+        // Their messages go to clients 11 and 12, which subscribe to them.
+        for client in [11, 12] {
+            let subscribe = Command::Subscribe(Subscribe {
+                channels: vec![key("__redis__:invalidate")],
+            });
+            core.process_client_command(client, 0, subscribe);
+        }
+        assert_eq!(
+            core.process_client_command(1, 0, tracking(TrackingMode::Default, Some(11))),
+            Some(CommandResponse::Ok)
+        );
+        core.process_client_command(
+            2,
+            0,
+            tracking(TrackingMode::Broadcast(vec![key("user:")]), Some(12)),
+        );
+        core.process_client_command(1, 0, get("user:1"));
+        core.process_client_command(1, 0, get("other"));
+        assert!(core.pushes.is_empty());
+
+        core.process_client_command(3, 0, set("user:1"));
+        assert_eq!(
+            std::mem::take(&mut core.pushes),
+            vec![
+                (11, invalidated(&["user:1"])),
+                (12, invalidated(&["user:1"]))
+            ]
+        );
+
+        // Keys are only invalidated once until they are read again.
+        core.process_client_command(3, 0, set("user:1"));
+        core.process_client_command(3, 0, set("other"));
+        assert_eq!(
+            std::mem::take(&mut core.pushes),
+            vec![
+                (12, invalidated(&["user:1"])),
+                (11, invalidated(&["other"]))
+            ]
+        );
+
+        // Messages are only sent to clients in pub/sub mode, since others
+        // would take them for replies.
+        core.process_client_command(
+            2,
+            0,
+            tracking(TrackingMode::Broadcast(Vec::new()), Some(13)),
+        );
+        core.process_client_command(3, 0, set("user:1"));
+        assert!(std::mem::take(&mut core.pushes).is_empty());
+
+        core.process_client_command(2, 0, Command::Client(Client::Tracking(None)));
+        core.process_client_command(3, 0, Command::FlushAll(Flush { mode: None }));
+        assert_eq!(
+            core.pushes,
+            vec![(11, invalidation(CommandResponse::NullArray))]
+        );
+    }
+
+    #[test]
+    fn test_pubsub() {
+        let mut core = ServerCore::new(1);
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let strings = |strings: &[&str]| strings.iter().map(|s| RedisString::from(*s)).collect();
+        let publish = |core: &mut ServerCore, channel: &str| {
+            core.process_client_command(
+                9,
+                0,
+                Command::Publish(Publish {
+                    channel: RedisString::from(channel),
+                    message: RedisString::from("hi"),
+                }),
+            )
+        };
+
+        // Each channel is confirmed, with the last confirmation as the reply.
+        assert_eq!(
+            core.process_client_command(
+                1,
+                0,
+                Command::Subscribe(Subscribe {
+                    channels: strings(&["news.tech", "other"]),
+                })
+            ),
+            Some(CommandResponse::Push(vec![
+                bulk("subscribe"),
+                bulk("other"),
+                CommandResponse::Integer(2),
+            ]))
+        );
+        assert_eq!(
+            std::mem::take(&mut core.pushes),
+            vec![(
+                1,
+                vec![
+                    bulk("subscribe"),
+                    bulk("news.tech"),
+                    CommandResponse::Integer(1)
+                ]
+            )]
+        );
+        assert_eq!(
+            core.process_client_command(
+                2,
+                0,
+                Command::PSubscribe(PSubscribe {
+                    patterns: strings(&["news.*"]),
+                })
+            ),
+            Some(CommandResponse::Push(vec![
+                bulk("psubscribe"),
+                bulk("news.*"),
+                CommandResponse::Integer(1),
+            ]))
+        );
+
+        assert_eq!(
+            publish(&mut core, "news.tech"),
+            Some(CommandResponse::Integer(2))
+        );
+        assert_eq!(
+            std::mem::take(&mut core.pushes),
+            vec![
+                (1, vec![bulk("message"), bulk("news.tech"), bulk("hi")]),
+                (
+                    2,
+                    vec![
+                        bulk("pmessage"),
+                        bulk("news.*"),
+                        bulk("news.tech"),
+                        bulk("hi")
+                    ]
+                ),
+            ]
+        );
+
+        // Unsubscribing from channels leaves patterns alone.
+        assert_eq!(
+            core.process_client_command(
+                2,
+                0,
+                Command::Unsubscribe(Unsubscribe { channels: vec![] })
+            ),
+            Some(CommandResponse::Push(vec![
+                bulk("unsubscribe"),
+                CommandResponse::BulkString(None),
+                CommandResponse::Integer(1),
+            ]))
+        );
+        core.process_client_command(1, 0, Command::Unsubscribe(Unsubscribe { channels: vec![] }));
+        core.pushes.clear();
+        assert_eq!(
+            publish(&mut core, "news.tech"),
+            Some(CommandResponse::Integer(1))
+        );
+        core.pushes.clear();
+
+        core.process_client_command(
+            2,
+            0,
+            Command::PUnsubscribe(PUnsubscribe { patterns: vec![] }),
+        );
+        assert_eq!(
+            publish(&mut core, "news.tech"),
+            Some(CommandResponse::Integer(0))
+        );
+        assert!(core.pushes.is_empty());
+    }
+
+    #[test]
+    fn test_pubsub_ping() {
+        let mut core = ServerCore::new(1);
+        let bulk = |s: &str| CommandResponse::BulkString(Some(RedisString::from(s)));
+        let ping = |core: &mut ServerCore| core.process_client_command(1, 0, Command::Ping);
+        core.process_client_command(
+            1,
+            0,
+            Command::PSubscribe(PSubscribe {
+                patterns: vec![RedisString::from("news.*")],
+            }),
+        );
+
+        // Subscribed clients get PING's reply as an array, and the usual reply
+        // once they've unsubscribed from everything.
+        assert_eq!(
+            ping(&mut core),
+            Some(CommandResponse::Array(vec![bulk("pong"), bulk("")]))
+        );
+        core.process_client_command(
+            1,
+            0,
+            Command::PUnsubscribe(PUnsubscribe { patterns: vec![] }),
+        );
+        assert_eq!(ping(&mut core), Some(CommandResponse::Pong));
+    }
+
+    #[test]
+    fn test_script_cache() {
+        let mut core = ServerCore::new(1);
+        let sha = "e0e1f9fabfc9d4800c877a703b823ac0578ff8db";
+        let exists = |core: &mut ServerCore| {
+            core.process_command(
+                0,
+                Command::Script(Script::Exists {
+                    shas: vec![RedisString::from(sha), RedisString::from("nope")],
+                }),
+            )
+        };
+        let evalsha = Command::EvalSha(EvalSha {
+            sha: RedisString::from(sha),
+            keys: vec![],
+            args: vec![],
+            read_only: false,
         });
-        let response = core.process_command(get_command);
+
         assert_eq!(
-            response,
-            CommandResponse::BulkString(Some(RedisString::from("value")))
+            core.process_command(0, evalsha.clone()),
+            CommandResponse::Error(ErrorReply::new(
+                ErrorCode::NoScript,
+                "No matching script. Please use EVAL."
+            ))
+        );
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::Script(Script::Load {
+                    script: RedisString::from("return 1"),
+                })
+            ),
+            CommandResponse::BulkString(Some(RedisString::from(sha)))
+        );
+        assert_eq!(
+            exists(&mut core),
+            CommandResponse::Array(vec![
+                CommandResponse::Integer(1),
+                CommandResponse::Integer(0)
+            ])
+        );
+        assert!(matches!(
+            core.process_command(0, evalsha),
+            CommandResponse::Error(ErrorReply {
+                code: ErrorCode::Err,
+                ..
+            })
+        ));
+
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::Script(Script::Flush(Flush {
+                    mode: Some(FlushMode::Async)
+                }))
+            ),
+            CommandResponse::Ok
+        );
+        assert_eq!(
+            exists(&mut core),
+            CommandResponse::Array(vec![
+                CommandResponse::Integer(0),
+                CommandResponse::Integer(0)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_fcall() {
+        struct StrLen;
+
+        impl ServerFunction for StrLen {
+            fn call(
+                &self,
+                context: &mut dyn Context,
+                keys: &[RedisString],
+                _: &[RedisString],
+            ) -> CommandResponse {
+                match context.db().get_string(&keys[0]) {
+                    Ok(s) => CommandResponse::Integer(len_to_i64(s.map_or(0, |s| s.len()))),
+                    Err(WrongType) => wrong_type_error(),
+                }
+            }
+
+            fn read_only(&self) -> bool {
+                true
+            }
+        }
+
+        let mut core = ServerCore::new(1);
+        core.functions.register(
+            "append_all",
+            |context: &mut dyn Context, keys: &[RedisString], args: &[RedisString]| {
+                let Ok(s) = context.db().get_or_create_string(&keys[0]) else {
+                    return wrong_type_error();
+                };
+                for arg in args {
+                    s.as_mut_vec().extend_from_slice(arg.as_bytes());
+                }
+                CommandResponse::Integer(len_to_i64(s.len()))
+            },
+        );
+        core.functions.register("strlen", StrLen);
+        let fcall = |function: &str, args: &[&str], read_only| {
+            Command::FCall(FCall {
+                function: RedisString::from(function),
+                keys: vec![RedisString::from("key")],
+                args: args.iter().map(|arg| RedisString::from(*arg)).collect(),
+                read_only,
+            })
+        };
+
+        assert_eq!(
+            core.process_command(0, fcall("append_all", &["ab", "c"], false)),
+            CommandResponse::Integer(3)
+        );
+        assert_eq!(
+            get(&mut core, "key"),
+            CommandResponse::BulkString(Some(RedisString::from("abc")))
+        );
+        assert_eq!(
+            core.process_command(0, fcall("strlen", &[], true)),
+            CommandResponse::Integer(3)
+        );
+
+        // Functions that may write can't be called with FCALL_RO.
+        let response = core.process_command(0, fcall("append_all", &["d"], true));
+        assert!(matches!(response, CommandResponse::Error(_)));
+        assert_eq!(
+            get(&mut core, "key"),
+            CommandResponse::BulkString(Some(RedisString::from("abc")))
+        );
+
+        let response = core.process_command(0, fcall("missing", &[], false));
+        assert!(matches!(response, CommandResponse::Error(_)));
+    }
+
+    #[test]
+    fn test_function_calls() {
+        // Runs its arguments as a command.
+        struct Call {
+            read_only: bool,
+        }
+
+        impl ServerFunction for Call {
+            fn call(
+                &self,
+                context: &mut dyn Context,
+                _: &[RedisString],
+                args: &[RedisString],
+            ) -> CommandResponse {
+                context.call(args)
+            }
+
+            fn read_only(&self) -> bool {
+                self.read_only
+            }
+        }
+
+        let mut core = ServerCore::new(2);
+        core.functions.register("call", Call { read_only: false });
+        core.functions.register("call_ro", Call { read_only: true });
+        let fcall = |core: &mut ServerCore, function: &str, args: &str| {
+            core.process_command(
+                0,
+                Command::FCall(FCall {
+                    function: RedisString::from(function),
+                    keys: vec![],
+                    args: args.split(' ').map(RedisString::from).collect(),
+                    read_only: function.ends_with("_ro"),
+                }),
+            )
+        };
+
+        assert_eq!(fcall(&mut core, "call", "SET key 1"), CommandResponse::Ok);
+        assert_eq!(
+            fcall(&mut core, "call_ro", "GET key"),
+            CommandResponse::BulkString(Some(RedisString::from("1")))
+        );
+
+        // Read-only functions can't run anything the command table flags as
+        // a write.
+        for args in ["SET key 2", "DEL key", "SORT key", "FLUSHALL"] {
+            assert_eq!(
+                fcall(&mut core, "call_ro", args),
+                CommandResponse::Error(ErrorReply::err(
+                    "Write commands are not allowed from read-only scripts."
+                )),
+                "{args}"
+            );
+        }
+        assert_eq!(
+            get(&mut core, "key"),
+            CommandResponse::BulkString(Some(RedisString::from("1")))
+        );
+
+        for args in [
+            "SUBSCRIBE channel",
+            "SELECT 1",
+            "FCALL call 0 PING",
+            "WAIT 0 0",
+        ] {
+            assert_eq!(
+                fcall(&mut core, "call", args),
+                CommandResponse::Error(ErrorReply::err(
+                    "This Redis command is not allowed from script"
+                )),
+                "{args}"
+            );
+        }
+        assert_eq!(
+            fcall(&mut core, "call", "NOPE"),
+            CommandResponse::Error(ErrorReply::err("Unknown Redis command called from script"))
+        );
+    }
+
+    #[test]
+    fn test_cluster_redirects() {
+        let mut core = ServerCore::new(1);
+        let other = "b".repeat(40);
+        let mut cluster = ClusterState::new("a".repeat(40), "127.0.0.1", 7000);
+        cluster.add_node(other.clone(), "127.0.0.1", 7001);
+        cluster.assign_slots(8192..=16383, &other).unwrap();
+        core.cluster = Some(cluster);
+        core.process_command(
+            0,
+            Command::Cluster(Cluster::AddSlots {
+                slots: (0..8192).collect(),
+            }),
+        );
+        let get = |core: &mut ServerCore, key: &str| {
+            core.process_client_command(
+                1,
+                0,
+                Command::Get(Get {
+                    key: RedisString::from(key),
+                }),
+            )
+        };
+
+        // "bar" hashes to slot 5061, which this node owns, and "foo" to 12182,
+        // which it doesn't.
+        let response = core.process_client_command(
+            1,
+            0,
+            Command::Set(Set {
+                key: RedisString::from("bar"),
+                value: RedisString::from("1"),
+            }),
+        );
+        assert_eq!(response, Some(CommandResponse::Ok));
+        assert_eq!(
+            get(&mut core, "foo"),
+            Some(CommandResponse::Error(ErrorReply::new(
+                ErrorCode::Moved,
+                "12182 127.0.0.1:7001"
+            )))
+        );
+
+        assert_eq!(
+            core.process_command(
+                0,
+                Command::Cluster(Cluster::KeySlot {
+                    key: RedisString::from("{bar}:count"),
+                })
+            ),
+            CommandResponse::Integer(5061)
+        );
+
+        let response = core.process_command(0, Command::Cluster(Cluster::Slots));
+        let CommandResponse::Array(slots) = response else {
+            panic!("expected an array, got {response:?}");
+        };
+        assert_eq!(
+            slots[1],
+            CommandResponse::Array(vec![
+                CommandResponse::Integer(8192),
+                CommandResponse::Integer(16383),
+                CommandResponse::Array(vec![
+                    CommandResponse::BulkString(Some(RedisString::from("127.0.0.1"))),
+                    CommandResponse::Integer(7001),
+                    CommandResponse::BulkString(Some(RedisString::from("b".repeat(40)))),
+                ]),
+            ])
+        );
+        assert_eq!(
+            core.process_command(0, Command::Cluster(Cluster::MyId)),
+            CommandResponse::BulkString(Some(RedisString::from("a".repeat(40))))
+        );
+
+        // While slot 5061 migrates, only keys that were already moved are
+        // redirected.
+        core.process_command(
+            0,
+            Command::Cluster(Cluster::SetSlot {
+                slot: 5061,
+                state: SlotState::Migrating(other),
+            }),
+        );
+        assert_eq!(
+            get(&mut core, "bar"),
+            Some(CommandResponse::BulkString(Some(RedisString::from("1"))))
+        );
+        core.process_command(
+            0,
+            Command::Del(Del {
+                keys: vec![RedisString::from("bar")],
+            }),
+        );
+        assert_eq!(
+            get(&mut core, "bar"),
+            Some(CommandResponse::Error(ErrorReply::new(
+                ErrorCode::Ask,
+                "5061 127.0.0.1:7001"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_cluster_cross_slot() {
+        let mut core = ServerCore::new(1);
+        let mut cluster = ClusterState::new("a".repeat(40), "127.0.0.1", 7000);
+        cluster.assign_slots(0..=16383, &"a".repeat(40)).unwrap();
+        core.cluster = Some(cluster);
+        let run = |core: &mut ServerCore, line: &str| {
+            let args = line.split(' ').map(Message::bulk_string).collect();
+            let command = Command::parse_resp(&Message::Array(args)).unwrap();
+            core.process_client_command(1, 0, command)
+        };
+
+        // Every key counts, including the ones results are stored in.
+        for line in [
+            "DEL a b",
+            "LMOVE a b LEFT RIGHT",
+            "SUNIONSTORE {t}dest a b",
+            "SUNIONSTORE dest {t}a {t}b",
+            "ZINTERSTORE dest 2 {t}a {t}b",
+            "SORT {t}k STORE dest",
+        ] {
+            let response = run(&mut core, line);
+            assert!(
+                matches!(
+                    &response,
+                    Some(CommandResponse::Error(ErrorReply {
+                        code: ErrorCode::CrossSlot,
+                        ..
+                    }))
+                ),
+                "{line}: {response:?}"
+            );
+        }
+
+        // Keys sharing a hash tag are in the same slot.
+        for line in [
+            "DEL {t}a {t}b",
+            "LMOVE {t}a {t}b LEFT RIGHT",
+            "SUNIONSTORE {t}dest {t}a {t}b",
+            "ZINTERSTORE {t}dest 2 {t}a {t}b",
+            "SORT {t}k STORE {t}dest",
+        ] {
+            let response = run(&mut core, line);
+            assert!(
+                !matches!(&response, Some(CommandResponse::Error(_))),
+                "{line}: {response:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_cluster_connection_flags() {
+        let mut core = ServerCore::new(1);
+        assert!(matches!(
+            core.process_client_command(1, 0, Command::ReadOnly),
+            Some(CommandResponse::Error(_))
+        ));
+
+        let other = "b".repeat(40);
+        let mut cluster = ClusterState::new("a".repeat(40), "127.0.0.1", 7000);
+        cluster.add_node(other.clone(), "127.0.0.1", 7001);
+        cluster.add_slots(&(0..8192).collect::<Vec<_>>()).unwrap();
+        cluster.assign_slots(8192..=16383, &other).unwrap();
+        core.cluster = Some(cluster);
+        let get = |core: &mut ServerCore, client| {
+            core.process_client_command(
+                client,
+                0,
+                Command::Get(Get {
+                    key: RedisString::from("foo"),
+                }),
+            )
+        };
+        let moved = Some(CommandResponse::Error(ErrorReply::new(
+            ErrorCode::Moved,
+            "12182 127.0.0.1:7001",
+        )));
+
+        // While "foo"'s slot is imported, it's only served right after ASKING.
+        core.process_command(
+            0,
+            Command::Cluster(Cluster::SetSlot {
+                slot: 12182,
+                state: SlotState::Importing(other),
+            }),
+        );
+        assert_eq!(get(&mut core, 1), moved);
+        assert_eq!(
+            core.process_client_command(1, 0, Command::Asking),
+            Some(CommandResponse::Ok)
+        );
+        assert_eq!(get(&mut core, 1), Some(CommandResponse::BulkString(None)));
+        assert_eq!(get(&mut core, 1), moved);
+
+        // Replicas serve reads for their master's slots to READONLY clients.
+        core.process_command(
+            0,
+            Command::ReplicaOf(ReplicaOf {
+                master: Some(("127.0.0.1".to_string(), 7001)),
+            }),
+        );
+        core.process_client_command(2, 0, Command::ReadOnly);
+        assert_eq!(get(&mut core, 2), Some(CommandResponse::BulkString(None)));
+        assert_eq!(get(&mut core, 1), moved);
+        let set = Command::Set(Set {
+            key: RedisString::from("foo"),
+            value: RedisString::from("1"),
+        });
+        assert_eq!(core.process_client_command(2, 0, set), moved);
+        core.process_client_command(2, 0, Command::ReadWrite);
+        assert_eq!(get(&mut core, 2), moved);
+        core.process_command(0, Command::ReplicaOf(ReplicaOf { master: None }));
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut core = ServerCore::new(1);
+        let memory =
+            |core: &mut ServerCore, memory| core.process_command(0, Command::Memory(memory));
+        let usage = |key: &str, samples| Memory::Usage {
+            key: RedisString::from(key),
+            samples,
+        };
+        set(&mut core, "foo", "bar");
+        assert_eq!(
+            memory(&mut core, usage("foo", None)),
+            CommandResponse::Integer(len_to_i64(ENTRY_OVERHEAD + 6))
+        );
+        assert_eq!(
+            memory(&mut core, usage("missing", None)),
+            CommandResponse::BulkString(None)
+        );
+
+        // Sampling a few elements of a collection is exact when they're all
+        // the same size.
+        let push = Command::Push(Push {
+            key: RedisString::from("list"),
+            end: ListEnd::Right,
+            elements: (0..100)
+                .map(|n| RedisString::from(format!("{n:03}")))
+                .collect(),
+        });
+        core.process_command(0, push);
+        let sampled = memory(&mut core, usage("list", Some(1)));
+        assert_eq!(sampled, memory(&mut core, usage("list", Some(0))));
+
+        let response = memory(&mut core, Memory::Stats);
+        let CommandResponse::Array(stats) = response else {
+            panic!("expected an array, got {response:?}");
+        };
+        let keys = stats
+            .iter()
+            .position(|field| {
+                *field == CommandResponse::BulkString(Some(RedisString::from("keys.count")))
+            })
+            .unwrap();
+        assert_eq!(stats[keys + 1], CommandResponse::Integer(2));
+
+        let doctor = |core: &mut ServerCore| {
+            let response = memory(core, Memory::Doctor);
+            let CommandResponse::BulkString(Some(diagnosis)) = response else {
+                panic!("expected a bulk string, got {response:?}");
+            };
+            String::try_from(diagnosis).unwrap()
+        };
+        assert!(doctor(&mut core).contains("using very little memory"));
+        set(&mut core, "big", &"x".repeat(6 * 1024 * 1024));
+        assert!(doctor(&mut core).contains("I can't find any memory issue"));
+        core.config.maxmemory.limit = 6 * 1024 * 1024 + 1024 * 1024 / 2;
+        assert!(doctor(&mut core).contains("High memory usage"));
+    }
+
+    #[test]
+    fn test_memory_accounting() {
+        let mut core = ServerCore::new(2);
+        let run = |core: &mut ServerCore, args: &[&str]| {
+            let message = args.iter().map(|a| Message::bulk_string(a)).collect();
+            let command = Command::parse_resp(&Message::Array(message)).unwrap();
+            core.process_command(0, command)
+        };
+        // The running total always matches measuring every key again.
+        let recount = |core: &ServerCore| -> usize {
+            core.dbs
+                .iter()
+                .flat_map(|db| db.entries().iter())
+                .map(|(key, entry)| entry.sampled_size(key, 0))
+                .sum()
+        };
+
+        for args in [
+            &["SET", "s", "value"][..],
+            &["SETBIT", "s", "100", "1"],
+            &["RPUSH", "list", "a", "b", "c"],
+            &["LPOP", "list"],
+            &["LSET", "list", "0", "longer"],
+            &["HSET", "hash", "field", "value", "other", "value"],
+            &["HDEL", "hash", "field"],
+            &["SADD", "set", "a", "b"],
+            &["SINTERSTORE", "dest", "set", "set"],
+            &["ZADD", "zset", "1", "member"],
+            &["XADD", "stream", "*", "field", "value"],
+            &["MOVE", "dest", "1"],
+            &["SREM", "set", "a", "b"],
+            &["DEL", "s"],
+        ] {
+            run(&mut core, args);
+            assert_eq!(core.used_memory(), recount(&core), "after {args:?}");
+        }
+        assert!(core.used_memory() > 0);
+
+        let key = RedisString::from("hash");
+        let entry = core.dbs[0].peek_entry(&key).unwrap();
+        let size = entry.sampled_size(&key, 0);
+        assert_eq!(entry.mem_size(), size);
+
+        // Flushing keeps a database's settings.
+        core.dbs[0].replica = true;
+        run(&mut core, &["FLUSHALL"]);
+        assert_eq!(core.used_memory(), 0);
+        assert!(core.dbs[0].replica);
+    }
+
+    #[test]
+    fn test_maxmemory() {
+        let mut core = ServerCore::new(1);
+        let set = |core: &mut ServerCore, key: &str| {
+            core.process_client_command(
+                1,
+                0,
+                Command::Set(Set {
+                    key: RedisString::from(key),
+                    value: RedisString::from("x".repeat(100)),
+                }),
+            )
+        };
+        let exists = |core: &mut ServerCore, key: &str| {
+            core.dbs[0].peek_entry(&RedisString::from(key)).is_some()
+        };
+        let is_oom = |response: Option<CommandResponse>| matches!(response, Some(CommandResponse::Error(e)) if e.code == ErrorCode::Oom);
+
+        // Each key takes up about 165 bytes, and keys are evicted before a
+        // command runs, so there's room for three.
+        core.config.maxmemory.limit = 400;
+        for key in ["a", "b", "c"] {
+            assert_eq!(set(&mut core, key), Some(CommandResponse::Ok));
+        }
+
+        // Under noeviction, writes fail once the keyspace is full, but
+        // deleting keys is still allowed.
+        assert!(is_oom(set(&mut core, "d")));
+        let response = core.process_client_command(
+            1,
+            0,
+            Command::Del(Del {
+                keys: vec![RedisString::from("a")],
+            }),
+        );
+        assert_eq!(response, Some(CommandResponse::Integer(1)));
+
+        // Volatile policies only evict keys with an expiration.
+        core.config.maxmemory.policy = EvictionPolicy::VolatileRandom;
+        core.dbs[0].set_expires_at(&RedisString::from("b"), Some(unix_time_millis() + 100_000));
+        assert_eq!(set(&mut core, "d"), Some(CommandResponse::Ok));
+        assert_eq!(set(&mut core, "e"), Some(CommandResponse::Ok));
+        assert!(!exists(&mut core, "b"));
+        assert!(is_oom(set(&mut core, "f")));
+
+        // LRU policies evict the key that was used least recently.
+        core.config.maxmemory.policy = EvictionPolicy::AllKeysLru;
+        core.config.maxmemory.samples = 100;
+        for (key, last_access) in [("c", 3), ("d", 1), ("e", 2)] {
+            core.dbs[0]
+                .get_entry(&RedisString::from(key))
+                .unwrap()
+                .last_access = last_access;
+        }
+        assert_eq!(set(&mut core, "f"), Some(CommandResponse::Ok));
+        assert!(!exists(&mut core, "d"));
+        assert!(exists(&mut core, "c") && exists(&mut core, "e"));
+        assert_eq!(core.evicted_keys, 2);
+
+        // LFU policies evict the key that's used least often, and report
+        // access frequencies instead of idle times.
+        // Raise the limit while the keys are read, since any command can
+        // cause an eviction.
+        core.set_maxmemory(MaxMemoryConfig {
+            limit: 500,
+            policy: EvictionPolicy::AllKeysLfu,
+            ..core.config.maxmemory.clone()
+        });
+        for _ in 0..100 {
+            for key in ["c", "e"] {
+                let get = Command::Get(Get {
+                    key: RedisString::from(key),
+                });
+                core.process_client_command(1, 0, get);
+            }
+        }
+        core.config.maxmemory.limit = 400;
+        assert_eq!(set(&mut core, "g"), Some(CommandResponse::Ok));
+        assert!(!exists(&mut core, "f"));
+        let object =
+            |core: &mut ServerCore, object| core.process_command(0, Command::Object(object));
+        let response = object(
+            &mut core,
+            Object::Freq {
+                key: RedisString::from("c"),
+            },
+        );
+        assert!(matches!(response, CommandResponse::Integer(count) if count > 5));
+        let response = object(
+            &mut core,
+            Object::IdleTime {
+                key: RedisString::from("c"),
+            },
         );
+        assert!(matches!(response, CommandResponse::Error(_)));
     }
 }
@@ -0,0 +1,248 @@
+//! Sets of unique strings. See <https://redis.io/docs/data-types/sets/>.
+//!
+//! Like Redis, sets whose members are all integers are stored as a sorted
+//! array of integers (Redis' intset encoding), which takes up far less memory
+//! than a hash table of strings. A set is converted to a hash table when it
+//! gets a member that isn't an integer, or grows past `MAX_INTSET_ENTRIES`
+//! members. Sets are never converted back.
+
+use std::borrow::Cow;
+use std::collections::{hash_set, HashSet};
+use std::slice;
+
+use crate::string::RedisString;
+
+/// Redis' default `set-max-intset-entries`.
+pub const MAX_INTSET_ENTRIES: usize = 512;
+
+#[derive(Debug, Clone)]
+pub struct Set {
+    encoding: Encoding,
+}
+
+#[derive(Debug, Clone)]
+enum Encoding {
+    /// The members in ascending order.
+    IntSet(Vec<i64>),
+    HashTable(HashSet<RedisString>),
+}
+
+impl Set {
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::IntSet(members) => members.len(),
+            Encoding::HashTable(members) => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &RedisString) -> bool {
+        match &self.encoding {
+            Encoding::IntSet(members) => member
+                .to_i64()
+                .is_some_and(|n| members.binary_search(&n).is_ok()),
+            Encoding::HashTable(members) => members.contains(member),
+        }
+    }
+
+    /// Adds a member, returning whether it wasn't already in the set.
+    pub fn insert(&mut self, member: RedisString) -> bool {
+        if let Encoding::IntSet(members) = &mut self.encoding {
+            if let Some(n) = member.to_i64() {
+                match members.binary_search(&n) {
+                    Ok(_) => return false,
+                    Err(index) if members.len() < MAX_INTSET_ENTRIES => {
+                        members.insert(index, n);
+                        return true;
+                    }
+                    Err(_) => {}
+                }
+            }
+            self.convert_to_hash_table();
+        }
+        let Encoding::HashTable(members) = &mut self.encoding else {
+            unreachable!("intsets were converted above");
+        };
+        members.insert(member)
+    }
+
+    /// Removes a member, returning whether it was in the set.
+    pub fn remove(&mut self, member: &RedisString) -> bool {
+        match &mut self.encoding {
+            Encoding::IntSet(members) => {
+                let Some(Ok(index)) = member.to_i64().map(|n| members.binary_search(&n)) else {
+                    return false;
+                };
+                members.remove(index);
+                true
+            }
+            Encoding::HashTable(members) => members.remove(member),
+        }
+    }
+
+    /// Iterates over the members. Members of an intset are formatted as they
+    /// go, since they aren't stored as strings.
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::IntSet(members) => Iter::IntSet(members.iter()),
+            Encoding::HashTable(members) => Iter::HashTable(members.iter()),
+        }
+    }
+
+    /// How many bytes each member of an intset takes up: the fewest that fit
+    /// its smallest and largest members, like Redis. `None` for other sets.
+    pub fn intset_width(&self) -> Option<usize> {
+        let Encoding::IntSet(members) = &self.encoding else {
+            return None;
+        };
+        let fits = |fits: fn(i64) -> bool| {
+            members.first().copied().is_none_or(fits) && members.last().copied().is_none_or(fits)
+        };
+        Some(if fits(|n| i16::try_from(n).is_ok()) {
+            size_of::<i16>()
+        } else if fits(|n| i32::try_from(n).is_ok()) {
+            size_of::<i32>()
+        } else {
+            size_of::<i64>()
+        })
+    }
+
+    /// How the set is stored, as reported by `OBJECT ENCODING`.
+    pub const fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::IntSet(_) => "intset",
+            Encoding::HashTable(_) => "hashtable",
+        }
+    }
+
+    fn convert_to_hash_table(&mut self) {
+        if let Encoding::IntSet(members) = &self.encoding {
+            let members = members
+                .iter()
+                .map(|n| RedisString::from(n.to_string()))
+                .collect();
+            self.encoding = Encoding::HashTable(members);
+        }
+    }
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::IntSet(Vec::new()),
+        }
+    }
+}
+
+/// Sets are equal when they have the same members, regardless of how they're
+/// stored.
+impl PartialEq for Set {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|member| other.contains(&member))
+    }
+}
+
+impl Eq for Set {}
+
+pub enum Iter<'a> {
+    IntSet(slice::Iter<'a, i64>),
+    HashTable(hash_set::Iter<'a, RedisString>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Cow<'a, RedisString>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::IntSet(members) => members
+                .next()
+                .map(|n| Cow::Owned(RedisString::from(n.to_string()))),
+            Self::HashTable(members) => members.next().map(Cow::Borrowed),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::IntSet(members) => members.size_hint(),
+            Self::HashTable(members) => members.size_hint(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Set {
+    type Item = Cow<'a, RedisString>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<RedisString> for Set {
+    fn from_iter<I: IntoIterator<Item = RedisString>>(iter: I) -> Self {
+        let mut set = Self::default();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(s: &str) -> RedisString {
+        RedisString::from(s)
+    }
+
+    #[test]
+    fn intset_operations() {
+        let mut set = Set::default();
+        assert!(set.insert(s("10")));
+        assert!(set.insert(s("-3")));
+        assert!(!set.insert(s("10")));
+        assert!(set.contains(&s("-3")));
+        // Only the canonical form of an integer is a member.
+        assert!(!set.contains(&s("010")));
+        assert_eq!(set.encoding(), "intset");
+        assert_eq!(set.intset_width(), Some(2));
+
+        let members: Vec<_> = set.iter().map(Cow::into_owned).collect();
+        assert_eq!(members, [s("-3"), s("10")]);
+
+        assert!(set.remove(&s("10")));
+        assert!(!set.remove(&s("10")));
+        assert!(!set.remove(&s("abc")));
+        assert_eq!(set.len(), 1);
+
+        set.insert(s(&i64::MAX.to_string()));
+        assert_eq!(set.intset_width(), Some(8));
+    }
+
+    #[test]
+    fn converts_to_hash_table() {
+        let mut set: Set = ["1", "2", "3"].into_iter().map(s).collect();
+        let intset = set.clone();
+        set.insert(s("a"));
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set.intset_width(), None);
+        assert!(set.contains(&s("2")));
+
+        // Removing the non-integer doesn't convert it back.
+        set.remove(&s("a"));
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set, intset);
+
+        let mut set: Set = (0..MAX_INTSET_ENTRIES)
+            .map(|n| RedisString::from(n.to_string()))
+            .collect();
+        assert_eq!(set.encoding(), "intset");
+        set.insert(s("-1"));
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set.len(), MAX_INTSET_ENTRIES + 1);
+    }
+}
@@ -0,0 +1,470 @@
+//! A skip list ordered by score and then member, which keeps sorted set
+//! members in order. See <https://en.wikipedia.org/wiki/Skip_list>.
+//!
+//! Like Redis' `zskiplist`, every link records how many nodes it skips over, so
+//! ranks can be computed on the way down the list and rank and range lookups
+//! take O(log n).
+//!
+//! Nodes live in a `Vec` and refer to each other by index, which keeps this
+//! free of unsafe code. Slots of removed nodes are reused by later inserts.
+
+use crate::random::Rng;
+use crate::string::RedisString;
+use crate::zset::Score;
+
+/// Enough levels for 4^32 elements.
+const MAX_LEVEL: usize = 32;
+
+/// The index of the head node, which holds no element and has every level.
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    next: Option<usize>,
+    /// The number of nodes this link moves forward by. Links to the end of the
+    /// list count as if there were a node after the last one.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    score: Score,
+    member: RedisString,
+    prev: Option<usize>,
+    links: Vec<Link>,
+}
+
+impl Node {
+    fn is_before(&self, score: Score, member: &RedisString) -> bool {
+        (self.score, &self.member) < (score, member)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    /// The number of levels in use, which is at least 1.
+    level: usize,
+    len: usize,
+    rng: Rng,
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        let head = Node {
+            score: Score::new(0.0).expect("0 is not NaN"),
+            member: RedisString::default(),
+            prev: None,
+            links: vec![
+                Link {
+                    next: None,
+                    span: 0
+                };
+                MAX_LEVEL
+            ],
+        };
+        Self {
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: None,
+            level: 1,
+            len: 0,
+            rng: Rng::new(),
+        }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts an element. The member must not already be in the list.
+    pub fn insert(&mut self, score: Score, member: RedisString) {
+        // For each level, the last node before the new one and its rank.
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            while let Some(next) = self.nodes[x].links[i].next {
+                if !self.nodes[next].is_before(score, &member) {
+                    break;
+                }
+                rank[i] += self.nodes[x].links[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = self.random_level();
+        for i in self.level..level {
+            self.nodes[HEAD].links[i] = Link {
+                next: None,
+                span: self.len,
+            };
+        }
+        self.level = self.level.max(level);
+
+        let node = self.alloc(Node {
+            score,
+            member,
+            prev: (update[0] != HEAD).then_some(update[0]),
+            links: Vec::with_capacity(level),
+        });
+        for (i, &prev) in update[..level].iter().enumerate() {
+            let before = self.nodes[prev].links[i];
+            let skipped = rank[0] - rank[i];
+            self.nodes[node].links.push(Link {
+                next: before.next,
+                span: before.span - skipped,
+            });
+            self.nodes[prev].links[i] = Link {
+                next: Some(node),
+                span: skipped + 1,
+            };
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].links[i].span += 1;
+        }
+
+        match self.nodes[node].links[0].next {
+            Some(next) => self.nodes[next].prev = Some(node),
+            None => self.tail = Some(node),
+        }
+        self.len += 1;
+    }
+
+    /// Removes an element, returning whether it was in the list.
+    pub fn remove(&mut self, score: Score, member: &RedisString) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].links[i].next {
+                if !self.nodes[next].is_before(score, member) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let Some(node) = self.nodes[x].links[0].next else {
+            return false;
+        };
+        if self.nodes[node].score != score || self.nodes[node].member != *member {
+            return false;
+        }
+
+        for (i, &prev) in update[..self.level].iter().enumerate() {
+            if self.nodes[prev].links[i].next == Some(node) {
+                let removed = self.nodes[node].links[i];
+                let link = &mut self.nodes[prev].links[i];
+                link.span = link.span + removed.span - 1;
+                link.next = removed.next;
+            } else {
+                self.nodes[prev].links[i].span -= 1;
+            }
+        }
+        let prev = self.nodes[node].prev;
+        match self.nodes[node].links[0].next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => self.tail = prev,
+        }
+        while self.level > 1 && self.nodes[HEAD].links[self.level - 1].next.is_none() {
+            self.level -= 1;
+        }
+        self.len -= 1;
+
+        // Drop the member now rather than when the slot is reused.
+        self.nodes[node].member = RedisString::default();
+        self.nodes[node].links.clear();
+        self.free.push(node);
+        true
+    }
+
+    /// The element's 0-based rank, or `None` if it isn't in the list.
+    pub fn rank(&self, score: Score, member: &RedisString) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].links[i].next {
+                let node = &self.nodes[next];
+                if (node.score, &node.member) > (score, member) {
+                    break;
+                }
+                rank += self.nodes[x].links[i].span;
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].score == score && self.nodes[x].member == *member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Iterates over all elements in order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            list: self,
+            front: self.nodes[HEAD].links[0].next,
+            back: self.tail,
+            remaining: self.len,
+        }
+    }
+
+    /// Iterates over the elements with 0-based ranks in `start..end`.
+    pub fn range_by_rank(&self, start: usize, end: usize) -> Iter<'_> {
+        let end = end.min(self.len);
+        if start >= end {
+            return self.empty();
+        }
+        Iter {
+            list: self,
+            front: self.by_rank(start),
+            back: self.by_rank(end - 1),
+            remaining: end - start,
+        }
+    }
+
+    /// Iterates over the elements between the first one `starts` accepts and
+    /// the last one `ends` accepts. `starts` must reject a prefix of the list
+    /// and accept the rest, and `ends` must accept a prefix and reject the rest.
+    pub fn range_where(
+        &self,
+        starts: impl Fn(Score, &RedisString) -> bool,
+        ends: impl Fn(Score, &RedisString) -> bool,
+    ) -> Iter<'_> {
+        let first = self.skip_while(|node| !starts(node.score, &node.member));
+        let last = self.skip_while(|node| ends(node.score, &node.member));
+        let Some(front) = self.nodes[first.0].links[0].next else {
+            return self.empty();
+        };
+        // `first` is the node before the range and `last` is the last node in
+        // it, so their ranks are the range's start and end.
+        if last.0 == HEAD || last.1 <= first.1 {
+            return self.empty();
+        }
+        Iter {
+            list: self,
+            front: Some(front),
+            back: Some(last.0),
+            remaining: last.1 - first.1,
+        }
+    }
+
+    /// Moves forward from the head as long as `pred` holds, returning the last
+    /// node it holds for and that node's 1-based rank, or the head and 0.
+    fn skip_while(&self, pred: impl Fn(&Node) -> bool) -> (usize, usize) {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].links[i].next {
+                if !pred(&self.nodes[next]) {
+                    break;
+                }
+                rank += self.nodes[x].links[i].span;
+                x = next;
+            }
+        }
+        (x, rank)
+    }
+
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].links[i].next {
+                if traversed + self.nodes[x].links[i].span > target {
+                    break;
+                }
+                traversed += self.nodes[x].links[i].span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    const fn empty(&self) -> Iter<'_> {
+        Iter {
+            list: self,
+            front: None,
+            back: None,
+            remaining: 0,
+        }
+    }
+
+    const fn random_level(&mut self) -> usize {
+        // Each level is a quarter as likely as the one below, like in Redis.
+        let mut level = 1;
+        while level < MAX_LEVEL && self.rng.next_u64().is_multiple_of(4) {
+            level += 1;
+        }
+        level
+    }
+
+    fn alloc(&mut self, node: Node) -> usize {
+        if let Some(index) = self.free.pop() {
+            self.nodes[index] = node;
+            index
+        } else {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        }
+    }
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    front: Option<usize>,
+    back: Option<usize>,
+    remaining: usize,
+}
+
+impl<'a> IntoIterator for &'a SkipList {
+    type Item = (&'a RedisString, Score);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> Iter<'a> {
+    fn element(&self, index: usize) -> (&'a RedisString, Score) {
+        let node = &self.list.nodes[index];
+        (&node.member, node.score)
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a RedisString, Score);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let index = self.front?;
+        self.remaining -= 1;
+        self.front = self.list.nodes[index].links[0].next;
+        Some(self.element(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let index = self.back?;
+        self.remaining -= 1;
+        self.back = self.list.nodes[index].prev;
+        Some(self.element(index))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn element(score: i8, member: u8) -> (Score, RedisString) {
+        (
+            Score::new(f64::from(score)).unwrap(),
+            RedisString::from(vec![member]),
+        )
+    }
+
+    fn collect(iter: Iter<'_>) -> Vec<(Score, RedisString)> {
+        iter.map(|(member, score)| (score, member.clone()))
+            .collect()
+    }
+
+    proptest! {
+        // Compares the skip list against a `BTreeSet` after a random series
+        // of inserts and removes.
+        #[test]
+        fn matches_btreeset(ops in prop::collection::vec((any::<bool>(), -5i8..5, 0u8..20), 0..200)) {
+            let mut list = SkipList::new();
+            let mut model = BTreeSet::new();
+            for (insert, score, member) in ops {
+                let (score, member) = element(score, member);
+                let present = model.contains(&(score, member.clone()));
+                if insert && !present {
+                    list.insert(score, member.clone());
+                    model.insert((score, member));
+                } else if !insert {
+                    prop_assert_eq!(list.remove(score, &member), present);
+                    model.remove(&(score, member));
+                }
+            }
+
+            let expected: Vec<_> = model.iter().cloned().collect();
+            prop_assert_eq!(list.len(), expected.len());
+            prop_assert_eq!(collect(list.iter()), expected.as_slice());
+            let mut reversed = expected.clone();
+            reversed.reverse();
+            let got: Vec<_> = list.iter().rev().map(|(m, s)| (s, m.clone())).collect();
+            prop_assert_eq!(got, reversed);
+
+            for (rank, (score, member)) in expected.iter().enumerate() {
+                prop_assert_eq!(list.rank(*score, member), Some(rank));
+            }
+            for start in 0..=expected.len() {
+                for end in start..=expected.len() + 1 {
+                    let want = expected[start..end.min(expected.len())].to_vec();
+                    prop_assert_eq!(collect(list.range_by_rank(start, end)), want);
+                }
+            }
+
+            let min = Score::new(-2.0).unwrap();
+            let max = Score::new(2.0).unwrap();
+            let want: Vec<_> = expected
+                .iter()
+                .filter(|(score, _)| *score > min && *score <= max)
+                .cloned()
+                .collect();
+            let got = list.range_where(|score, _| score > min, |score, _| score <= max);
+            prop_assert_eq!(got.len(), want.len());
+            prop_assert_eq!(collect(got), want);
+        }
+    }
+
+    #[test]
+    fn iterates_from_both_ends() {
+        let mut list = SkipList::new();
+        for member in 0..5 {
+            let (score, member) = element(1, member);
+            list.insert(score, member);
+        }
+        let mut iter = list.range_by_rank(1, 4);
+        assert_eq!(iter.next().unwrap().0, &RedisString::from(vec![1]));
+        assert_eq!(iter.next_back().unwrap().0, &RedisString::from(vec![3]));
+        assert_eq!(iter.next().unwrap().0, &RedisString::from(vec![2]));
+        assert!(iter.next_back().is_none());
+        assert_eq!(list.rank(element(2, 0).0, &element(2, 0).1), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! Point-in-time snapshots of the keyspace, written by `SAVE` and `BGSAVE` in
+//! the RDB file format.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+
+use crate::db::{unix_time_millis, Db};
+use crate::rdb;
+
+/// The snapshot file a server uses unless configured otherwise, like Redis'
+/// default `dbfilename`.
+pub const DEFAULT_SNAPSHOT_PATH: &str = "dump.rdb";
+
+/// `Snapshots` keeps track of saving the keyspace to the snapshot file.
+#[derive(Debug)]
+pub struct Snapshots {
+    path: PathBuf,
+
+    /// The background save in progress, if any. It returns the Unix time in
+    /// seconds when it finished.
+    background: Option<JoinHandle<Result<i64>>>,
+
+    /// Unix time in seconds of the last successful save, or of when the server
+    /// started if it hasn't saved yet.
+    last_save: i64,
+}
+
+impl Snapshots {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            background: None,
+            last_save: unix_time_secs(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Changes where snapshots are written. A background save in progress
+    /// still writes to the old path.
+    pub fn set_path(&mut self, path: impl Into<PathBuf>) {
+        self.path = path.into();
+    }
+
+    /// Saves the databases, blocking until the snapshot is written.
+    pub fn save(&mut self, dbs: &[Db]) -> Result<()> {
+        if self.in_progress() {
+            return Err(eyre!("Background save already in progress"));
+        }
+        write_snapshot(&self.path, dbs)?;
+        self.last_save = unix_time_secs();
+        Ok(())
+    }
+
+    /// Saves a copy of the databases on a background thread. Copying doesn't
+    /// block the core for long, since the keyspace is a persistent map.
+    pub fn start_background_save(&mut self, dbs: &[Db]) -> Result<()> {
+        if self.in_progress() {
+            return Err(eyre!("Background save already in progress"));
+        }
+        let dbs = dbs.to_vec();
+        let path = self.path.clone();
+        self.background = Some(thread::spawn(move || {
+            write_snapshot(&path, &dbs)?;
+            Ok(unix_time_secs())
+        }));
+        Ok(())
+    }
+
+    /// Whether a background save is still running.
+    pub fn in_progress(&mut self) -> bool {
+        self.poll_background();
+        self.background.is_some()
+    }
+
+    /// Unix time in seconds of the last successful save, as reported by
+    /// `LASTSAVE`.
+    pub fn last_save(&mut self) -> i64 {
+        self.poll_background();
+        self.last_save
+    }
+
+    /// Waits for the background save in progress, if any. Returns whether it
+    /// succeeded.
+    pub fn wait(&mut self) -> bool {
+        let Some(handle) = self.background.take() else {
+            return true;
+        };
+        self.finish_background(handle)
+    }
+
+    /// Collects the result of the background save once it's done.
+    fn poll_background(&mut self) {
+        if self
+            .background
+            .as_ref()
+            .is_some_and(JoinHandle::is_finished)
+        {
+            let handle = self.background.take().expect("background save exists");
+            self.finish_background(handle);
+        }
+    }
+
+    fn finish_background(&mut self, handle: JoinHandle<Result<i64>>) -> bool {
+        match handle.join() {
+            Ok(Ok(finished_at)) => {
+                log::info!("background saving terminated with success");
+                self.last_save = finished_at;
+                true
+            }
+            Ok(Err(e)) => {
+                log::error!("background saving failed: {e:?}");
+                false
+            }
+            Err(_) => {
+                log::error!("background saving thread panicked");
+                false
+            }
+        }
+    }
+}
+
+/// Loads the snapshot at `path` into `num_databases` databases. Returns `None`
+/// if there is no snapshot yet.
+pub fn load(path: &Path, num_databases: usize) -> Result<Option<Vec<Db>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).wrap_err_with(|| format!("failed to open {}", path.display())),
+    };
+    let start = Instant::now();
+    let dbs = rdb::read_snapshot(BufReader::new(file), num_databases)
+        .wrap_err_with(|| format!("failed to load snapshot {}", path.display()))?;
+    let keys: usize = dbs.iter().map(|db| db.entries().len()).sum();
+    log::info!(
+        "loaded {keys} keys from {} in {:?}",
+        path.display(),
+        start.elapsed()
+    );
+    Ok(Some(dbs))
+}
+
+/// Writes a snapshot of the databases to `path`. The snapshot is written to a
+/// temporary file first and then renamed, so a failed save never leaves a
+/// truncated snapshot behind.
+pub fn write_snapshot(path: &Path, dbs: &[Db]) -> Result<()> {
+    let temp_path = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let file = File::create(&temp_path)
+        .wrap_err_with(|| format!("failed to create {}", temp_path.display()))?;
+    let mut writer = BufWriter::new(file);
+    let written = rdb::write_snapshot(&mut writer, dbs).and_then(|()| {
+        let file = writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        file.sync_all()?;
+        Ok(())
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e.wrap_err(format!("failed to write {}", temp_path.display())));
+    }
+    fs::rename(&temp_path, path).wrap_err_with(|| {
+        format!(
+            "failed to rename {} to {}",
+            temp_path.display(),
+            path.display()
+        )
+    })
+}
+
+fn unix_time_secs() -> i64 {
+    unix_time_millis() / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::{Entry, Value};
+    use crate::string::{RedisString, StringValue};
+
+    fn test_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "redis-clone-snapshot-{}-{name}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("dump.rdb")
+    }
+
+    #[test]
+    fn background_save() {
+        let path = test_path("background");
+        let mut db = Db::default();
+        db.insert(
+            RedisString::from("key"),
+            Entry::new(Value::String(StringValue::from("value"))),
+        );
+
+        let mut snapshots = Snapshots::new(&path);
+        snapshots.start_background_save(&[db]).unwrap();
+        assert!(snapshots.wait());
+        assert!(!snapshots.in_progress());
+        assert!(fs::read(&path).unwrap().starts_with(b"REDIS"));
+
+        // Saving again replaces the snapshot.
+        snapshots.save(&[Db::default()]).unwrap();
+        assert!(!fs::read(&path).unwrap().windows(3).any(|w| w == b"key"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn load_snapshot() {
+        let path = test_path("load");
+        assert!(load(&path, 1).unwrap().is_none());
+
+        let mut db = Db::default();
+        db.insert(
+            RedisString::from("key"),
+            Entry::new(Value::String(StringValue::from("value"))),
+        );
+        write_snapshot(&path, &[db]).unwrap();
+        let dbs = load(&path, 1).unwrap().unwrap();
+        assert_eq!(
+            dbs[0].entries()[&RedisString::from("key")].value,
+            Value::String(StringValue::from("value"))
+        );
+
+        fs::write(&path, b"garbage!!").unwrap();
+        let err = load(&path, 1).unwrap_err();
+        assert!(err.to_string().starts_with("failed to load snapshot"));
+        assert_eq!(err.root_cause().to_string(), "not an RDB file");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
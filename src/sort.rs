@@ -0,0 +1,297 @@
+//! The sorting engine behind `SORT` and `SORT_RO`, independent of where the
+//! elements and the values referenced by `BY` and `GET` patterns are stored.
+
+use std::cmp::Ordering;
+
+use color_eyre::eyre::{eyre, Result};
+
+use crate::command::Sort;
+use crate::string::RedisString;
+
+/// A key to look up for an element, plus a hash field if the pattern used the
+/// `key->field` form.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lookup {
+    pub key: RedisString,
+    pub field: Option<RedisString>,
+}
+
+/// Substitutes `element` for the first `*` in `pattern`. Returns `None` if the
+/// pattern has no `*`, in which case nothing should be looked up.
+pub fn substitute(pattern: &RedisString, element: &RedisString) -> Option<Lookup> {
+    let pattern = pattern.as_bytes();
+    let star = pattern.iter().position(|&c| c == b'*')?;
+
+    // A `->` after the `*` (and not at the very end) selects a hash field.
+    let (key_pattern, field) = match find(&pattern[star + 1..], b"->") {
+        Some(arrow) if star + 1 + arrow + 2 < pattern.len() => {
+            let arrow = star + 1 + arrow;
+            (&pattern[..arrow], Some(&pattern[arrow + 2..]))
+        }
+        _ => (pattern, None),
+    };
+
+    let mut key = key_pattern[..star].to_vec();
+    key.extend_from_slice(element.as_bytes());
+    key.extend_from_slice(&key_pattern[star + 1..]);
+    Some(Lookup {
+        key: RedisString::from(key),
+        field: field.map(RedisString::from),
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// What an element is sorted by.
+enum Weight {
+    Number(f64),
+
+    /// With `ALPHA`. Missing weights sort before everything else.
+    Bytes(Option<RedisString>),
+}
+
+/// Sorts `elements` according to `sort`.
+///
+/// Returns the elements themselves, or the values of the `GET` patterns for
+/// each element in order. `lookup` resolves the keys (and hash fields) referenced by patterns.
+pub fn sort<F>(
+    elements: Vec<RedisString>,
+    sort: &Sort,
+    mut lookup: F,
+) -> Result<Vec<Option<RedisString>>>
+where
+    F: FnMut(&Lookup) -> Option<RedisString>,
+{
+    // A BY pattern without a `*` (usually "nosort") means the elements are
+    // returned in their stored order.
+    let dont_sort = sort
+        .by
+        .as_ref()
+        .is_some_and(|by| !by.as_bytes().contains(&b'*'));
+
+    let mut elements = if dont_sort {
+        elements
+    } else {
+        let mut weighted = elements
+            .into_iter()
+            .map(|element| {
+                let weight = sort.by.as_ref().map_or_else(
+                    || Some(element.clone()),
+                    |by| substitute(by, &element).and_then(|l| lookup(&l)),
+                );
+                let weight = if sort.alpha {
+                    Weight::Bytes(weight)
+                } else {
+                    // Missing weights sort as 0.
+                    Weight::Number(weight.as_ref().map_or(Ok(0.0), parse_score)?)
+                };
+                Ok((element, weight))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        weighted.sort_by(|(a, a_weight), (b, b_weight)| {
+            let ordering = compare(a_weight, b_weight).then_with(|| a.as_bytes().cmp(b.as_bytes()));
+            if sort.desc {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        weighted.into_iter().map(|(element, _)| element).collect()
+    };
+
+    if let Some(limit) = sort.limit {
+        let start = usize::try_from(limit.offset)
+            .unwrap_or(0)
+            .min(elements.len());
+        let end = usize::try_from(limit.count)
+            .map_or(elements.len(), |count| start.saturating_add(count))
+            .min(elements.len());
+        elements.truncate(end);
+        elements.drain(..start);
+    }
+
+    if sort.get.is_empty() {
+        return Ok(elements.into_iter().map(Some).collect());
+    }
+    let mut values = Vec::with_capacity(elements.len() * sort.get.len());
+    for element in &elements {
+        for pattern in &sort.get {
+            let value = if pattern.as_bytes() == b"#" {
+                Some(element.clone())
+            } else {
+                substitute(pattern, element).and_then(|l| lookup(&l))
+            };
+            values.push(value);
+        }
+    }
+    Ok(values)
+}
+
+fn parse_score(s: &RedisString) -> Result<f64> {
+    std::str::from_utf8(s.as_bytes())
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or_else(|| eyre!("One or more scores can't be converted into double"))
+}
+
+fn compare(a: &Weight, b: &Weight) -> Ordering {
+    match (a, b) {
+        (Weight::Number(a), Weight::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Weight::Bytes(a), Weight::Bytes(b)) => a
+            .as_ref()
+            .map(RedisString::as_bytes)
+            .cmp(&b.as_ref().map(RedisString::as_bytes)),
+        _ => unreachable!("all weights have the same kind"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::command::Limit;
+
+    fn strings(strs: &[&str]) -> Vec<RedisString> {
+        strs.iter().map(|s| RedisString::from(*s)).collect()
+    }
+
+    fn sorted(
+        elements: &[&str],
+        sort: &Sort,
+        store: &HashMap<&str, &str>,
+    ) -> Vec<Option<RedisString>> {
+        super::sort(strings(elements), sort, |lookup| {
+            assert!(lookup.field.is_none());
+            let key = String::from_utf8(lookup.key.as_bytes().to_vec()).unwrap();
+            store.get(key.as_str()).map(|v| RedisString::from(*v))
+        })
+        .unwrap()
+    }
+
+    fn some(strs: &[&str]) -> Vec<Option<RedisString>> {
+        strings(strs).into_iter().map(Some).collect()
+    }
+
+    #[test]
+    fn substitute_patterns() {
+        let element = RedisString::from("1");
+        let lookup = |pattern: &str| substitute(&RedisString::from(pattern), &element);
+        assert_eq!(lookup("nosort"), None);
+        assert_eq!(
+            lookup("weight_*"),
+            Some(Lookup {
+                key: RedisString::from("weight_1"),
+                field: None,
+            })
+        );
+        assert_eq!(
+            lookup("obj_*->name"),
+            Some(Lookup {
+                key: RedisString::from("obj_1"),
+                field: Some(RedisString::from("name")),
+            })
+        );
+        assert_eq!(
+            lookup("obj_*->"),
+            Some(Lookup {
+                key: RedisString::from("obj_1->"),
+                field: None,
+            })
+        );
+    }
+
+    #[test]
+    fn numeric_and_alpha() {
+        let store = HashMap::new();
+        let mut sort = Sort::default();
+        assert_eq!(
+            sorted(&["3", "-1.5", "10", "2"], &sort, &store),
+            some(&["-1.5", "2", "3", "10"])
+        );
+
+        sort.alpha = true;
+        assert_eq!(
+            sorted(&["3", "-1.5", "10", "2"], &sort, &store),
+            some(&["-1.5", "10", "2", "3"])
+        );
+
+        sort.desc = true;
+        assert_eq!(
+            sorted(&["b", "c", "a"], &sort, &store),
+            some(&["c", "b", "a"])
+        );
+
+        let result = super::sort(strings(&["a"]), &Sort::default(), |_| None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn by_and_get() {
+        let store = HashMap::from([
+            ("weight_a", "3"),
+            ("weight_b", "1"),
+            ("weight_c", "2"),
+            ("name_a", "Alice"),
+            ("name_c", "Carol"),
+        ]);
+        let mut sort = Sort {
+            by: Some(RedisString::from("weight_*")),
+            ..Sort::default()
+        };
+        assert_eq!(
+            sorted(&["a", "b", "c", "d"], &sort, &store),
+            some(&["d", "b", "c", "a"])
+        );
+
+        sort.get = strings(&["#", "name_*"]);
+        assert_eq!(
+            sorted(&["a", "b", "c"], &sort, &store),
+            vec![
+                Some(RedisString::from("b")),
+                None,
+                Some(RedisString::from("c")),
+                Some(RedisString::from("Carol")),
+                Some(RedisString::from("a")),
+                Some(RedisString::from("Alice")),
+            ]
+        );
+
+        // Without a `*`, BY skips sorting entirely.
+        let sort = Sort {
+            by: Some(RedisString::from("nosort")),
+            ..Sort::default()
+        };
+        assert_eq!(
+            sorted(&["c", "a", "b"], &sort, &store),
+            some(&["c", "a", "b"])
+        );
+    }
+
+    #[test]
+    fn limit() {
+        let store = HashMap::new();
+        let elements = ["5", "4", "3", "2", "1"];
+        let with_limit = |offset, count| Sort {
+            limit: Some(Limit { offset, count }),
+            ..Sort::default()
+        };
+        assert_eq!(
+            sorted(&elements, &with_limit(1, 2), &store),
+            some(&["2", "3"])
+        );
+        assert_eq!(
+            sorted(&elements, &with_limit(-1, 2), &store),
+            some(&["1", "2"])
+        );
+        assert_eq!(
+            sorted(&elements, &with_limit(3, -1), &store),
+            some(&["4", "5"])
+        );
+        assert_eq!(sorted(&elements, &with_limit(10, 2), &store), some(&[]));
+    }
+}
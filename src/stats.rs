@@ -0,0 +1,258 @@
+//! Per-command statistics, reported by `INFO commandstats` and `INFO
+//! latencystats` and cleared by `CONFIG RESETSTAT`. See
+//! <https://redis.io/commands/info/>.
+//!
+//! Latencies are kept in a histogram with logarithmic buckets, so memory use
+//! doesn't grow with the number of calls. Percentiles are accurate to within
+//! about 6%.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The percentiles `INFO latencystats` reports by default, like Redis'
+/// `latency-tracking-info-percentiles`.
+pub const DEFAULT_LATENCY_PERCENTILES: [f64; 3] = [50.0, 99.0, 99.9];
+
+/// Commands whose statistics are kept per subcommand, like Redis'
+/// `cmdstat_client|pause`.
+const CONTAINER_COMMANDS: &[&str] = &[
+    "acl", "client", "cluster", "config", "latency", "memory", "object", "script", "xgroup",
+    "xinfo",
+];
+
+/// The name a command's statistics are kept under, given its lower-cased
+/// name and first argument: the name, and for commands with subcommands, the
+/// subcommand after a `|`.
+pub fn stat_name(name: &str, subcommand: Option<&str>) -> String {
+    match subcommand {
+        Some(subcommand) if CONTAINER_COMMANDS.contains(&name) => format!("{name}|{subcommand}"),
+        _ => name.to_string(),
+    }
+}
+
+#[derive(Debug)]
+pub struct CommandStats {
+    by_name: BTreeMap<String, CommandStat>,
+
+    /// Whether latencies are tracked, like Redis' `latency-tracking`.
+    track_latency: bool,
+}
+
+impl Default for CommandStats {
+    fn default() -> Self {
+        Self {
+            by_name: BTreeMap::new(),
+            track_latency: true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CommandStat {
+    calls: u64,
+    duration: Duration,
+
+    /// Calls refused before they ran, like for missing permissions.
+    rejected_calls: u64,
+
+    /// Calls that ran and replied with an error.
+    failed_calls: u64,
+
+    latencies: Histogram,
+}
+
+impl CommandStats {
+    pub const fn set_latency_tracking(&mut self, track_latency: bool) {
+        self.track_latency = track_latency;
+    }
+
+    /// Records a call that ran for `duration`.
+    pub fn record_call(&mut self, name: &str, duration: Duration, failed: bool) {
+        let track_latency = self.track_latency;
+        let stat = self.stat(name);
+        stat.calls += 1;
+        stat.duration += duration;
+        if failed {
+            stat.failed_calls += 1;
+        }
+        if track_latency {
+            stat.latencies.record(duration);
+        }
+    }
+
+    /// Records a call that was refused before it ran.
+    pub fn record_rejected(&mut self, name: &str) {
+        self.stat(name).rejected_calls += 1;
+    }
+
+    fn stat(&mut self, name: &str) -> &mut CommandStat {
+        if !self.by_name.contains_key(name) {
+            self.by_name
+                .insert(name.to_string(), CommandStat::default());
+        }
+        self.by_name.get_mut(name).expect("stat was just added")
+    }
+
+    pub fn reset(&mut self) {
+        self.by_name.clear();
+    }
+
+    /// The `commandstats` section of `INFO`, with a line for every command
+    /// that's been called or rejected.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn commandstats_info(&self) -> String {
+        let mut info = "# Commandstats\r\n".to_string();
+        for (name, stat) in &self.by_name {
+            let usec = stat.duration.as_micros();
+            let usec_per_call = if stat.calls == 0 {
+                0.0
+            } else {
+                stat.duration.as_secs_f64() * 1_000_000.0 / stat.calls as f64
+            };
+            let _ = write!(
+                info,
+                "cmdstat_{name}:calls={},usec={usec},usec_per_call={usec_per_call:.2},\
+                 rejected_calls={},failed_calls={}\r\n",
+                stat.calls, stat.rejected_calls, stat.failed_calls
+            );
+        }
+        info
+    }
+
+    /// The `latencystats` section of `INFO`, with the given percentiles of
+    /// every command's latency in microseconds.
+    pub fn latencystats_info(&self, percentiles: &[f64]) -> String {
+        let mut info = "# Latencystats\r\n".to_string();
+        for (name, stat) in &self.by_name {
+            if stat.latencies.count == 0 {
+                continue;
+            }
+            let values: Vec<String> = percentiles
+                .iter()
+                .map(|&percentile| {
+                    let usec = stat.latencies.percentile(percentile).as_secs_f64() * 1_000_000.0;
+                    format!("p{percentile}={usec:.3}")
+                })
+                .collect();
+            let _ = write!(
+                info,
+                "latency_percentiles_usec_{name}:{}\r\n",
+                values.join(",")
+            );
+        }
+        info
+    }
+}
+
+/// Bits of each value kept below its highest set bit, which decides how
+/// many buckets each power of two is split into.
+const SUB_BUCKET_BITS: u32 = 4;
+
+/// Counts of latencies in nanoseconds. Values below 2^SUB_BUCKET_BITS get a
+/// bucket each, and each larger power of two is split into 2^SUB_BUCKET_BITS
+/// buckets.
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: BTreeMap<u32, u64>,
+    count: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        *self.buckets.entry(bucket(nanos)).or_default() += 1;
+        self.count += 1;
+    }
+
+    /// The smallest latency at least `percentile` percent of calls took at
+    /// most, rounded up to the top of its bucket.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn percentile(&self, percentile: f64) -> Duration {
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (&bucket, &count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(bucket_max(bucket));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+fn bucket(value: u64) -> u32 {
+    let bits = u64::BITS - value.leading_zeros();
+    if bits <= SUB_BUCKET_BITS {
+        // Small enough for a bucket of its own.
+        return u32::try_from(value).expect("small values fit");
+    }
+    let shift = bits - 1 - SUB_BUCKET_BITS;
+    let sub_bucket =
+        u32::try_from((value >> shift) & ((1 << SUB_BUCKET_BITS) - 1)).expect("sub-buckets fit");
+    (1 << SUB_BUCKET_BITS) + (shift << SUB_BUCKET_BITS) + sub_bucket
+}
+
+/// The largest value in a bucket.
+fn bucket_max(bucket: u32) -> u64 {
+    if bucket < 1 << SUB_BUCKET_BITS {
+        return u64::from(bucket);
+    }
+    let shift = (bucket >> SUB_BUCKET_BITS) - 1;
+    let sub_bucket = u64::from(bucket & ((1 << SUB_BUCKET_BITS) - 1));
+    let min = ((1 << SUB_BUCKET_BITS) + sub_bucket) << shift;
+    min + ((1 << shift) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_cover_values() {
+        for value in (0..100_000).chain([u64::MAX / 3, u64::MAX]) {
+            let bucket = bucket(value);
+            assert!(value <= bucket_max(bucket), "{value} above bucket {bucket}");
+            if bucket > 0 {
+                assert!(
+                    value > bucket_max(bucket - 1),
+                    "{value} below bucket {bucket}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn info_sections() {
+        let mut stats = CommandStats::default();
+        for micros in 1..=100 {
+            stats.record_call("get", Duration::from_micros(micros), false);
+        }
+        stats.set_latency_tracking(false);
+        stats.record_call("set", Duration::from_micros(10), true);
+        stats.record_rejected("set");
+        stats.record_rejected(&stat_name("client", Some("pause")));
+        stats.record_rejected(&stat_name("get", Some("key")));
+
+        assert_eq!(
+            stats.commandstats_info(),
+            "# Commandstats\r\n\
+             cmdstat_client|pause:calls=0,usec=0,usec_per_call=0.00,rejected_calls=1,failed_calls=0\r\n\
+             cmdstat_get:calls=100,usec=5050,usec_per_call=50.50,rejected_calls=1,failed_calls=0\r\n\
+             cmdstat_set:calls=1,usec=10,usec_per_call=10.00,rejected_calls=1,failed_calls=1\r\n"
+        );
+        // The percentiles are the tops of their buckets.
+        assert_eq!(
+            stats.latencystats_info(&[50.0, 99.0, 100.0]),
+            "# Latencystats\r\n\
+             latency_percentiles_usec_get:p50=51.199,p99=102.399,p100=102.399\r\n"
+        );
+
+        stats.reset();
+        assert_eq!(stats.commandstats_info(), "# Commandstats\r\n");
+    }
+}
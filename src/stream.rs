@@ -0,0 +1,860 @@
+//! Streams, which are append-only logs of field-value entries identified by
+//! increasing IDs. See <https://redis.io/docs/data-types/streams/>.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+
+use crate::string::RedisString;
+
+/// A stream entry ID, written as `<milliseconds>-<sequence number>`. IDs are
+/// ordered by milliseconds and then sequence number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parses an ID. The sequence number can be left off, in which case it is
+    /// `default_seq`.
+    pub fn parse(s: &RedisString, default_seq: u64) -> Option<Self> {
+        let s = std::str::from_utf8(s.as_bytes()).ok()?;
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms, parse_u64(seq)?),
+            None => (s, default_seq),
+        };
+        Some(Self {
+            ms: parse_u64(ms)?,
+            seq,
+        })
+    }
+
+    /// The smallest ID greater than this one, if there is one.
+    pub const fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self { ms: self.ms, seq }),
+            None => match self.ms.checked_add(1) {
+                Some(ms) => Some(Self { ms, seq: 0 }),
+                None => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Parses a plain unsigned integer, without the sign `str::parse` allows.
+fn parse_u64(s: &str) -> Option<u64> {
+    if s.starts_with('+') {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// The ID to give a new entry in `XADD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewId {
+    /// `*`: generate the whole ID from the current time.
+    Auto,
+
+    /// `<ms>-*`: use the given milliseconds and generate the sequence number.
+    AutoSeq(u64),
+
+    Explicit(StreamId),
+}
+
+impl NewId {
+    pub fn parse(s: &RedisString) -> Option<Self> {
+        if s.as_bytes() == b"*" {
+            return Some(Self::Auto);
+        }
+        if let Some(ms) = s.as_bytes().strip_suffix(b"-*") {
+            let ms = std::str::from_utf8(ms).ok()?;
+            return Some(Self::AutoSeq(parse_u64(ms)?));
+        }
+        StreamId::parse(s, 0).map(Self::Explicit)
+    }
+
+    pub fn to_redis_string(self) -> RedisString {
+        match self {
+            Self::Auto => RedisString::from("*"),
+            Self::AutoSeq(ms) => RedisString::from(format!("{ms}-*")),
+            Self::Explicit(id) => RedisString::from(id.to_string()),
+        }
+    }
+}
+
+/// The ID `XREAD` returns entries after: an ID, or `$` for the last ID in the
+/// stream when the command first runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadId {
+    After(StreamId),
+    Last,
+}
+
+impl ReadId {
+    pub fn parse(s: &RedisString) -> Option<Self> {
+        if s.as_bytes() == b"$" {
+            return Some(Self::Last);
+        }
+        StreamId::parse(s, 0).map(Self::After)
+    }
+
+    pub fn to_redis_string(self) -> RedisString {
+        match self {
+            Self::After(id) => RedisString::from(id.to_string()),
+            Self::Last => RedisString::from("$"),
+        }
+    }
+}
+
+/// One end of an ID range for `XRANGE` and `XREVRANGE`: `-` and `+` for the
+/// smallest and largest possible IDs, an ID, or `(id` for an exclusive bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeBound {
+    Min,
+    Max,
+    Inclusive(StreamId),
+    Exclusive(StreamId),
+}
+
+impl RangeBound {
+    /// Parses a range bound. IDs without a sequence number include the whole
+    /// millisecond, so they get `default_seq`: 0 for the start of a range, and
+    /// the largest sequence number for the end.
+    pub fn parse(s: &RedisString, default_seq: u64) -> Option<Self> {
+        match s.as_bytes() {
+            b"-" => Some(Self::Min),
+            b"+" => Some(Self::Max),
+            [b'(', rest @ ..] => Some(Self::Exclusive(StreamId::parse(
+                &RedisString::from(rest),
+                default_seq,
+            )?)),
+            _ => Some(Self::Inclusive(StreamId::parse(s, default_seq)?)),
+        }
+    }
+
+    pub fn to_redis_string(self) -> RedisString {
+        match self {
+            Self::Min => RedisString::from("-"),
+            Self::Max => RedisString::from("+"),
+            Self::Inclusive(id) => RedisString::from(id.to_string()),
+            Self::Exclusive(id) => RedisString::from(format!("({id}")),
+        }
+    }
+
+    const fn to_bound(self) -> Bound<StreamId> {
+        match self {
+            Self::Min => Bound::Included(StreamId::MIN),
+            Self::Max => Bound::Included(StreamId::MAX),
+            Self::Inclusive(id) => Bound::Included(id),
+            Self::Exclusive(id) => Bound::Excluded(id),
+        }
+    }
+}
+
+/// A stream entry's fields and values, in the order they were added.
+pub type Fields = Vec<(RedisString, RedisString)>;
+
+/// A stream. Unlike other collections, streams are kept around when they
+/// become empty, since they remember the last ID they handed out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    last_id: StreamId,
+    groups: BTreeMap<RedisString, ConsumerGroup>,
+
+    /// The largest ID removed by `delete`.
+    max_deleted_id: StreamId,
+
+    /// How many entries were ever added, including removed ones.
+    entries_added: u64,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub const fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub const fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub const fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn first_entry(&self) -> Option<(&StreamId, &Fields)> {
+        self.entries.first_key_value()
+    }
+
+    pub fn last_entry(&self) -> Option<(&StreamId, &Fields)> {
+        self.entries.last_key_value()
+    }
+
+    /// Picks the ID for a new entry, which must be greater than every ID the
+    /// stream has handed out. Returns `None` if it wouldn't be.
+    pub fn next_id(&self, id: NewId, now_ms: u64) -> Option<StreamId> {
+        let id = match id {
+            // If the clock went backwards, keep using the last ID's
+            // milliseconds so IDs keep increasing.
+            NewId::Auto if now_ms > self.last_id.ms => StreamId { ms: now_ms, seq: 0 },
+            NewId::Auto => self.last_id.next()?,
+            NewId::AutoSeq(ms) if ms == self.last_id.ms => StreamId {
+                ms,
+                seq: self.last_id.seq.checked_add(1)?,
+            },
+            // 0-0 is never a valid ID, so the first sequence number for 0 ms
+            // is 1.
+            NewId::AutoSeq(ms) => StreamId {
+                ms,
+                seq: u64::from(ms == 0),
+            },
+            NewId::Explicit(id) => id,
+        };
+        (id > self.last_id).then_some(id)
+    }
+
+    /// Appends an entry. The ID must come from `next_id`.
+    pub fn insert(&mut self, id: StreamId, fields: Fields) {
+        debug_assert!(id > self.last_id, "stream IDs must increase");
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Removes entries by ID, returning how many existed. Consumer groups
+    /// keep deleted entries pending until they're acknowledged or claimed.
+    pub fn delete(&mut self, ids: &[StreamId]) -> usize {
+        let mut deleted = 0;
+        for id in ids {
+            if self.entries.remove(id).is_some() {
+                self.max_deleted_id = self.max_deleted_id.max(*id);
+                deleted += 1;
+            }
+        }
+        deleted
+    }
+
+    /// Evicts the oldest entries, returning how many were removed.
+    ///
+    /// Redis stores streams in nodes of up to 100 entries, and approximate
+    /// trimming only evicts whole nodes. To behave the same, approximate
+    /// trimming here evicts entries in multiples of `NODE_SIZE`.
+    pub fn trim(&mut self, trim: &Trim) -> usize {
+        let excess = match trim.strategy {
+            TrimStrategy::MaxLen(max_len) => self.entries.len().saturating_sub(max_len),
+            TrimStrategy::MinId(min_id) => self.entries.range(..min_id).count(),
+        };
+        let count = if trim.approx {
+            let limit = match trim.limit {
+                None => NODE_SIZE * DEFAULT_TRIM_LIMIT_NODES,
+                Some(0) => usize::MAX,
+                Some(limit) => limit,
+            };
+            let count = excess.min(limit);
+            count - count % NODE_SIZE
+        } else {
+            excess
+        };
+        for _ in 0..count {
+            self.entries.pop_first();
+        }
+        count
+    }
+
+    /// Iterates over the entries with IDs greater than `id`, in order.
+    pub fn after(&self, id: StreamId) -> impl Iterator<Item = (&StreamId, &Fields)> {
+        self.entries.range((Bound::Excluded(id), Bound::Unbounded))
+    }
+
+    /// Iterates over the entries with IDs between `start` and `end`, in order.
+    pub fn range(
+        &self,
+        start: RangeBound,
+        end: RangeBound,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &Fields)> {
+        range_by_id(&self.entries, start, end)
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<&Fields> {
+        self.entries.get(&id)
+    }
+
+    pub const fn groups(&self) -> &BTreeMap<RedisString, ConsumerGroup> {
+        &self.groups
+    }
+
+    /// Creates a consumer group that will deliver entries after
+    /// `last_delivered`. Returns `false` if the group already exists.
+    pub fn create_group(&mut self, name: RedisString, last_delivered: StreamId) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        let group = ConsumerGroup {
+            last_delivered,
+            ..ConsumerGroup::default()
+        };
+        self.groups.insert(name, group);
+        true
+    }
+
+    /// Reads entries for a consumer in a group, creating the consumer if
+    /// needed. Returns `None` if the group doesn't exist.
+    ///
+    /// New entries are added to the group's pending entries unless `noack`
+    /// is set. Entries that were deleted after being delivered have no
+    /// fields.
+    pub fn read_group(
+        &mut self,
+        group: &RedisString,
+        consumer: &RedisString,
+        id: GroupReadId,
+        count: usize,
+        noack: bool,
+        now: i64,
+    ) -> Option<Vec<(StreamId, Option<Fields>)>> {
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+        match id {
+            GroupReadId::New => {
+                let entries: Vec<_> = self
+                    .entries
+                    .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                    .take(count)
+                    .map(|(id, fields)| (*id, Some(fields.clone())))
+                    .collect();
+                for (id, _) in &entries {
+                    group.last_delivered = *id;
+                    if !noack {
+                        let pending = PendingEntry {
+                            consumer: consumer.clone(),
+                            delivered_at: now,
+                            deliveries: 1,
+                        };
+                        group.pending.insert(*id, pending);
+                    }
+                }
+                Some(entries)
+            }
+            GroupReadId::After(after) => Some(
+                group
+                    .pending
+                    .range((Bound::Excluded(after), Bound::Unbounded))
+                    .filter(|(_, pending)| pending.consumer == *consumer)
+                    .take(count)
+                    .map(|(id, _)| (*id, self.entries.get(id).cloned()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Acknowledges entries, removing them from a group's pending entries.
+    /// Returns how many were pending, or `None` if the group doesn't exist.
+    pub fn ack(&mut self, group: &RedisString, ids: &[StreamId]) -> Option<usize> {
+        let group = self.groups.get_mut(group)?;
+        Some(
+            ids.iter()
+                .filter(|id| group.pending.remove(id).is_some())
+                .count(),
+        )
+    }
+
+    /// Transfers pending entries to `consumer` if they've been idle for at
+    /// least `claim.min_idle` milliseconds, creating the consumer if needed.
+    /// Returns the claimed IDs, or `None` if the group doesn't exist.
+    ///
+    /// Pending entries that were deleted from the stream are dropped rather
+    /// than claimed.
+    pub fn claim(
+        &mut self,
+        group: &RedisString,
+        consumer: &RedisString,
+        ids: &[StreamId],
+        claim: &Claim,
+        now: i64,
+    ) -> Option<Vec<StreamId>> {
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+        let mut claimed = Vec::new();
+        for &id in ids {
+            let exists = self.entries.contains_key(&id);
+            if claim.force && exists {
+                group.pending.entry(id).or_insert_with(|| PendingEntry {
+                    consumer: consumer.clone(),
+                    delivered_at: now,
+                    deliveries: 0,
+                });
+            }
+            let Some(pending) = group.pending.get_mut(&id) else {
+                continue;
+            };
+            if !exists {
+                group.pending.remove(&id);
+                continue;
+            }
+            if now - pending.delivered_at < claim.min_idle {
+                continue;
+            }
+            pending.claim(consumer, claim, now);
+            claimed.push(id);
+        }
+        Some(claimed)
+    }
+
+    /// Like `claim`, but scans the pending entries starting at `start` and
+    /// claims up to `count` idle ones. Returns the ID to continue scanning
+    /// from (0-0 when done), the claimed IDs, and the IDs of pending entries
+    /// that were dropped because they were deleted from the stream.
+    pub fn auto_claim(
+        &mut self,
+        group: &RedisString,
+        consumer: &RedisString,
+        start: StreamId,
+        count: usize,
+        claim: &Claim,
+        now: i64,
+    ) -> Option<(StreamId, Vec<StreamId>, Vec<StreamId>)> {
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+
+        // Like Redis, bound the work done when few entries are idle enough.
+        let mut attempts = count.saturating_mul(10);
+        let mut claimed = Vec::new();
+        let mut deleted = Vec::new();
+        let ids: Vec<StreamId> = group
+            .pending
+            .range(start..)
+            .map(|(id, _)| *id)
+            .take(attempts.saturating_add(1))
+            .collect();
+        let mut next = StreamId::MIN;
+        for id in ids {
+            if claimed.len() == count || attempts == 0 {
+                next = id;
+                break;
+            }
+            attempts -= 1;
+            if !self.entries.contains_key(&id) {
+                deleted.push(id);
+                continue;
+            }
+            let pending = group.pending.get_mut(&id).expect("ID is pending");
+            if now - pending.delivered_at >= claim.min_idle {
+                pending.claim(consumer, claim, now);
+                claimed.push(id);
+            }
+        }
+        for id in &deleted {
+            group.pending.remove(id);
+        }
+        Some((next, claimed, deleted))
+    }
+}
+
+/// Iterates over the values in `map` with IDs between `start` and `end`.
+fn range_by_id<V>(
+    map: &BTreeMap<StreamId, V>,
+    start: RangeBound,
+    end: RangeBound,
+) -> impl DoubleEndedIterator<Item = (&StreamId, &V)> {
+    let (start, end) = (start.to_bound(), end.to_bound());
+    let empty = match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+            s >= e
+        }
+        _ => false,
+    };
+    // `BTreeMap::range` panics on backwards ranges, so skip those.
+    let range = if empty {
+        None
+    } else {
+        Some(map.range((start, end)))
+    };
+    range.into_iter().flatten()
+}
+
+/// Which entries `XREADGROUP` reads for a consumer: `>` for entries never
+/// delivered to the group, or an ID to re-read the consumer's own pending
+/// entries after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupReadId {
+    New,
+    After(StreamId),
+}
+
+impl GroupReadId {
+    pub fn parse(s: &RedisString) -> Option<Self> {
+        if s.as_bytes() == b">" {
+            return Some(Self::New);
+        }
+        StreamId::parse(s, 0).map(Self::After)
+    }
+
+    pub fn to_redis_string(self) -> RedisString {
+        match self {
+            Self::New => RedisString::from(">"),
+            Self::After(id) => RedisString::from(id.to_string()),
+        }
+    }
+}
+
+/// A consumer group, which hands out each entry of a stream to one of its
+/// consumers and tracks which entries haven't been acknowledged yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsumerGroup {
+    /// The last entry delivered to any of the group's consumers.
+    pub last_delivered: StreamId,
+
+    /// Entries delivered to a consumer but not acknowledged yet, also known
+    /// as the pending entries list (PEL).
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<RedisString, Consumer>,
+}
+
+impl ConsumerGroup {
+    /// Iterates over the pending entries with IDs between `start` and `end`.
+    pub fn pending_range(
+        &self,
+        start: RangeBound,
+        end: RangeBound,
+    ) -> impl Iterator<Item = (&StreamId, &PendingEntry)> {
+        range_by_id(&self.pending, start, end)
+    }
+
+    /// The number of pending entries owned by `consumer`.
+    pub fn pending_count(&self, consumer: &RedisString) -> usize {
+        self.pending
+            .values()
+            .filter(|pending| pending.consumer == *consumer)
+            .count()
+    }
+
+    fn touch_consumer(&mut self, name: &RedisString, now: i64) {
+        self.consumers.entry(name.clone()).or_default().seen_at = now;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Consumer {
+    /// When the consumer last read or claimed entries, as a Unix timestamp in
+    /// milliseconds.
+    pub seen_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: RedisString,
+
+    /// When the entry was last delivered, as a Unix timestamp in
+    /// milliseconds.
+    pub delivered_at: i64,
+
+    /// How many times the entry has been delivered.
+    pub deliveries: u64,
+}
+
+impl PendingEntry {
+    fn claim(&mut self, consumer: &RedisString, claim: &Claim, now: i64) {
+        self.consumer = consumer.clone();
+        self.delivered_at = claim.delivered_at.unwrap_or(now);
+        self.deliveries = match claim.retry_count {
+            Some(count) => count,
+            // Claiming just the IDs doesn't count as a delivery.
+            None if claim.justid => self.deliveries,
+            None => self.deliveries + 1,
+        };
+    }
+}
+
+/// How many entries each of Redis' stream nodes holds, which approximate
+/// trimming evicts at a time.
+pub const NODE_SIZE: usize = 100;
+
+/// How many nodes approximate trimming evicts at most if no `LIMIT` is given.
+const DEFAULT_TRIM_LIMIT_NODES: usize = 100;
+
+/// How a stream is trimmed by `XTRIM`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trim {
+    pub strategy: TrimStrategy,
+
+    /// Only evict whole nodes, which may leave more entries than requested.
+    pub approx: bool,
+
+    /// The maximum number of entries to evict. Only allowed with `approx`,
+    /// where 0 means no limit.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// Keep at most this many entries.
+    MaxLen(usize),
+
+    /// Evict entries with IDs less than this one.
+    MinId(StreamId),
+}
+
+/// How many pending entries `XAUTOCLAIM` claims if no `COUNT` is given.
+pub const DEFAULT_AUTOCLAIM_COUNT: usize = 100;
+
+/// Options for claiming pending entries with `XCLAIM` and `XAUTOCLAIM`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Claim {
+    /// Only claim entries that have been idle for at least this many
+    /// milliseconds.
+    pub min_idle: i64,
+
+    /// The delivery time to record, instead of now.
+    pub delivered_at: Option<i64>,
+
+    /// The delivery count to record, instead of incrementing it.
+    pub retry_count: Option<u64>,
+
+    /// Create pending entries for IDs that exist in the stream but aren't
+    /// pending.
+    pub force: bool,
+
+    /// Only the IDs are returned, and the delivery count isn't incremented.
+    pub justid: bool,
+}
+
+impl FromIterator<(StreamId, Fields)> for Stream {
+    fn from_iter<I: IntoIterator<Item = (StreamId, Fields)>>(iter: I) -> Self {
+        let mut stream = Self::default();
+        for (id, fields) in iter {
+            stream.insert(id, fields);
+        }
+        stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(s: &str) -> StreamId {
+        StreamId::parse(&RedisString::from(s), 0).unwrap()
+    }
+
+    #[test]
+    fn parse_ids() {
+        assert_eq!(id("5-3"), StreamId { ms: 5, seq: 3 });
+        assert_eq!(id("5"), StreamId { ms: 5, seq: 0 });
+        assert_eq!(
+            StreamId::parse(&RedisString::from("5"), u64::MAX),
+            Some(StreamId {
+                ms: 5,
+                seq: u64::MAX
+            })
+        );
+        for invalid in ["", "-", "a-1", "1-", "1-2-3", "-1", "+1", "1--1"] {
+            assert_eq!(StreamId::parse(&RedisString::from(invalid), 0), None);
+        }
+        assert_eq!(id("5-3").to_string(), "5-3");
+        assert!(id("1-100") < id("2-0"));
+
+        let new_id = |s: &str| NewId::parse(&RedisString::from(s));
+        assert_eq!(new_id("*"), Some(NewId::Auto));
+        assert_eq!(new_id("12-*"), Some(NewId::AutoSeq(12)));
+        assert_eq!(new_id("12-3"), Some(NewId::Explicit(id("12-3"))));
+        assert_eq!(new_id("*-1"), None);
+    }
+
+    #[test]
+    fn generate_ids() {
+        let mut stream = Stream::default();
+        assert_eq!(stream.next_id(NewId::AutoSeq(0), 100), Some(id("0-1")));
+        assert_eq!(stream.next_id(NewId::Explicit(id("0-0")), 100), None);
+        assert_eq!(stream.next_id(NewId::Auto, 100), Some(id("100-0")));
+
+        stream.insert(id("100-5"), vec![]);
+        assert_eq!(stream.next_id(NewId::Auto, 100), Some(id("100-6")));
+        assert_eq!(stream.next_id(NewId::Auto, 50), Some(id("100-6")));
+        assert_eq!(stream.next_id(NewId::Auto, 200), Some(id("200-0")));
+        assert_eq!(stream.next_id(NewId::AutoSeq(100), 0), Some(id("100-6")));
+        assert_eq!(stream.next_id(NewId::AutoSeq(99), 0), None);
+        assert_eq!(stream.next_id(NewId::Explicit(id("100-5")), 0), None);
+        assert_eq!(
+            stream.next_id(NewId::Explicit(id("100-7")), 0),
+            Some(id("100-7"))
+        );
+
+        stream.insert(StreamId::MAX, vec![]);
+        assert_eq!(stream.next_id(NewId::Auto, 0), None);
+    }
+
+    #[test]
+    fn ranges() {
+        let stream: Stream = ["1-0", "1-1", "2-0", "3-5"]
+            .into_iter()
+            .map(|s| (id(s), vec![]))
+            .collect();
+        let ids = |start, end| {
+            stream
+                .range(start, end)
+                .map(|(id, _)| id.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(RangeBound::Min, RangeBound::Max).len(), 4);
+        assert_eq!(
+            ids(
+                RangeBound::Exclusive(id("1-0")),
+                RangeBound::Inclusive(id("2-0"))
+            ),
+            ["1-1", "2-0"]
+        );
+        assert_eq!(
+            ids(
+                RangeBound::Inclusive(id("3-0")),
+                RangeBound::Inclusive(id("1-0"))
+            ),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            ids(
+                RangeBound::Exclusive(id("2-0")),
+                RangeBound::Exclusive(id("2-0"))
+            ),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            stream
+                .range(RangeBound::Min, RangeBound::Max)
+                .next_back()
+                .unwrap()
+                .0,
+            &id("3-5")
+        );
+    }
+
+    #[test]
+    fn consumer_groups() {
+        let mut stream: Stream = ["1-0", "2-0", "3-0"]
+            .into_iter()
+            .map(|s| (id(s), vec![]))
+            .collect();
+        let (group, alice, bob) = (
+            RedisString::from("g"),
+            RedisString::from("alice"),
+            RedisString::from("bob"),
+        );
+        assert!(stream.create_group(group.clone(), StreamId::MIN));
+        assert!(!stream.create_group(group.clone(), StreamId::MIN));
+
+        let read = stream
+            .read_group(&group, &alice, GroupReadId::New, 2, false, 1000)
+            .unwrap();
+        assert_eq!(read.len(), 2);
+        let read = stream
+            .read_group(&group, &bob, GroupReadId::New, 10, false, 1000)
+            .unwrap();
+        assert_eq!(read, [(id("3-0"), Some(vec![]))]);
+        let read = stream
+            .read_group(
+                &group,
+                &alice,
+                GroupReadId::After(StreamId::MIN),
+                10,
+                false,
+                1000,
+            )
+            .unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(stream.groups()[&group].pending_count(&alice), 2);
+
+        assert_eq!(stream.ack(&group, &[id("1-0"), id("9-0")]), Some(1));
+        assert_eq!(stream.ack(&RedisString::from("nope"), &[]), None);
+
+        // Entries must be idle long enough to be claimed.
+        let claim = Claim {
+            min_idle: 500,
+            ..Claim::default()
+        };
+        let ids = [id("2-0"), id("3-0")];
+        assert_eq!(stream.claim(&group, &bob, &ids, &claim, 1200), Some(vec![]));
+        assert_eq!(
+            stream.claim(&group, &bob, &ids, &claim, 1500),
+            Some(ids.to_vec())
+        );
+        let pending = &stream.groups()[&group].pending[&id("2-0")];
+        assert_eq!(pending.consumer, bob);
+        assert_eq!(pending.deliveries, 2);
+
+        // Deleted entries are dropped from the pending entries.
+        assert_eq!(stream.delete(&[id("3-0"), id("3-0")]), 1);
+        let (next, claimed, deleted) = stream
+            .auto_claim(&group, &alice, StreamId::MIN, 1, &claim, 3000)
+            .unwrap();
+        assert_eq!(
+            (next, claimed, deleted),
+            (id("3-0"), vec![id("2-0")], vec![])
+        );
+        let (next, claimed, deleted) = stream
+            .auto_claim(&group, &alice, next, 1, &claim, 3000)
+            .unwrap();
+        assert_eq!(
+            (next, claimed, deleted),
+            (StreamId::MIN, vec![], vec![id("3-0")])
+        );
+        assert_eq!(stream.groups()[&group].pending.len(), 1);
+    }
+
+    #[test]
+    fn trimming() {
+        let mut stream: Stream = (1..=250)
+            .map(|ms| (StreamId { ms, seq: 0 }, vec![]))
+            .collect();
+        let trim = |strategy, approx, limit| Trim {
+            strategy,
+            approx,
+            limit,
+        };
+
+        // Approximate trimming only evicts whole nodes.
+        assert_eq!(
+            stream.trim(&trim(TrimStrategy::MaxLen(120), true, None)),
+            100
+        );
+        assert_eq!(stream.trim(&trim(TrimStrategy::MaxLen(120), true, None)), 0);
+        assert_eq!(
+            stream.trim(&trim(TrimStrategy::MinId(id("300-0")), true, Some(50))),
+            0
+        );
+        assert_eq!(
+            stream.trim(&trim(TrimStrategy::MaxLen(120), false, None)),
+            30
+        );
+        assert_eq!(stream.first_entry().unwrap().0, &id("131-0"));
+        assert_eq!(
+            stream.trim(&trim(TrimStrategy::MinId(id("200-0")), false, None)),
+            69
+        );
+        assert_eq!(stream.len(), 51);
+        assert_eq!(stream.entries_added(), 250);
+        assert_eq!(stream.max_deleted_id(), StreamId::MIN);
+        assert_eq!(stream.delete(&[id("210-0"), id("205-0"), id("1-0")]), 2);
+        assert_eq!(stream.max_deleted_id(), id("210-0"));
+    }
+}
@@ -1,11 +1,13 @@
 //! Wrapper type for Redis strings. See <https://redis.io/docs/data-types/strings/>.
 
+use std::borrow::Cow;
 use std::fmt;
+use std::sync::LazyLock;
 
 /// A Redis string. This is a wrapper around a `Vec<u8>` that implements `Debug`
 /// in a way that tries to print the string as UTF-8 if possible, and otherwise
 /// prints the raw bytes. Also provides convenience `From` implementations.
-#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RedisString(Vec<u8>);
 
 // This custom Debug impl is the main reason this type exists.
@@ -24,6 +26,23 @@ impl RedisString {
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
+
+    /// Gives mutable access to the bytes, for commands that edit strings in
+    /// place.
+    pub const fn as_mut_vec(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+
+    /// Parses the string as an integer. Like Redis, only the canonical form is
+    /// accepted, so strings like "+1", "01" or " 1" return `None`.
+    pub fn to_i64(&self) -> Option<i64> {
+        // 20 bytes is enough for any i64 including the sign.
+        if self.0.is_empty() || self.0.len() > 20 {
+            return None;
+        }
+        let value = std::str::from_utf8(&self.0).ok()?.parse::<i64>().ok()?;
+        (value.to_string().as_bytes() == self.0.as_slice()).then_some(value)
+    }
 }
 
 impl From<Vec<u8>> for RedisString {
@@ -70,10 +89,226 @@ impl TryFrom<RedisString> for String {
     }
 }
 
+/// Strings are serialized as bytes, except that human-readable formats like
+/// JSON get a string when the bytes are valid UTF-8.
+#[cfg(feature = "serde")]
+impl serde::Serialize for RedisString {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match std::str::from_utf8(&self.0) {
+            Ok(s) if serializer.is_human_readable() => serializer.serialize_str(s),
+            _ => serializer.serialize_bytes(&self.0),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for RedisString {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(RedisStringVisitor)
+    }
+}
+
+/// Accepts strings, bytes, and sequences of bytes, which is how formats
+/// without a bytes type represent them.
+#[cfg(feature = "serde")]
+struct RedisStringVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for RedisStringVisitor {
+    type Value = RedisString;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a string or bytes")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(RedisString::from(v))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(RedisString::from(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(RedisString::from(v))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(RedisString::from(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        Ok(RedisString::from(bytes))
+    }
+}
+
+/// Integers below this are shared rather than stored per key, like Redis'
+/// `OBJ_SHARED_INTEGERS`.
+pub const SHARED_INTEGERS: i64 = 10_000;
+
+/// Every shared integer, formatted once so that reading one doesn't allocate.
+static SHARED: LazyLock<Vec<RedisString>> = LazyLock::new(|| {
+    (0..SHARED_INTEGERS)
+        .map(|n| RedisString::from(n.to_string()))
+        .collect()
+});
+
+/// The value of a string key.
+///
+/// Like Redis, strings that are integers are stored as an `i64` (the `int`
+/// encoding), which is smaller than their digits and common for counters and
+/// flags. Small integers are read from a shared pool of formatted strings
+/// instead of being formatted on every access.
+///
+/// Build values with `From` so integers are detected. Editing a value in
+/// place with `make_raw` leaves it raw, even if it ends up an integer.
+#[derive(Debug, Clone)]
+pub enum StringValue {
+    Int(i64),
+    Raw(RedisString),
+}
+
+impl StringValue {
+    /// The value as a string. Shared integers are borrowed from the pool, and
+    /// other integers are formatted.
+    pub fn to_redis_string(&self) -> Cow<'_, RedisString> {
+        match self {
+            Self::Int(n) => usize::try_from(*n)
+                .ok()
+                .and_then(|n| SHARED.get(n))
+                .map_or_else(
+                    || Cow::Owned(RedisString::from(n.to_string())),
+                    Cow::Borrowed,
+                ),
+            Self::Raw(s) => Cow::Borrowed(s),
+        }
+    }
+
+    /// The length of the value as a string.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Int(_) => self.to_redis_string().len(),
+            Self::Raw(s) => s.len(),
+        }
+    }
+
+    /// Whether the value is an integer from the shared pool.
+    pub fn is_shared(&self) -> bool {
+        matches!(self, Self::Int(n) if (0..SHARED_INTEGERS).contains(n))
+    }
+
+    /// Converts the value to a raw string, for commands that edit strings in
+    /// place.
+    pub fn make_raw(&mut self) -> &mut RedisString {
+        if let Self::Int(_) = self {
+            *self = Self::Raw(self.to_redis_string().into_owned());
+        }
+        let Self::Raw(s) = self else {
+            unreachable!("integers were converted above");
+        };
+        s
+    }
+}
+
+impl Default for StringValue {
+    fn default() -> Self {
+        Self::Raw(RedisString::default())
+    }
+}
+
+impl From<RedisString> for StringValue {
+    fn from(s: RedisString) -> Self {
+        s.to_i64().map_or(Self::Raw(s), Self::Int)
+    }
+}
+
+impl From<&str> for StringValue {
+    fn from(s: &str) -> Self {
+        Self::from(RedisString::from(s))
+    }
+}
+
+/// Values are equal when their strings are, regardless of how they're stored.
+impl PartialEq for StringValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a == b,
+            _ => self.to_redis_string() == other.to_redis_string(),
+        }
+    }
+}
+
+impl Eq for StringValue {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_to_i64() {
+        assert_eq!(RedisString::from("0").to_i64(), Some(0));
+        assert_eq!(RedisString::from("-123").to_i64(), Some(-123));
+        assert_eq!(
+            RedisString::from("9223372036854775807").to_i64(),
+            Some(i64::MAX)
+        );
+        assert_eq!(RedisString::from("9223372036854775808").to_i64(), None);
+        assert_eq!(RedisString::from("").to_i64(), None);
+        assert_eq!(RedisString::from("+1").to_i64(), None);
+        assert_eq!(RedisString::from("01").to_i64(), None);
+        assert_eq!(RedisString::from("-0").to_i64(), None);
+        assert_eq!(RedisString::from(" 1").to_i64(), None);
+        assert_eq!(RedisString::from("1a").to_i64(), None);
+    }
+
+    #[test]
+    fn test_string_value() {
+        let mut value = StringValue::from("42");
+        assert_eq!(value, StringValue::Int(42));
+        assert!(value.is_shared());
+        assert_eq!(value.len(), 2);
+        assert!(matches!(value.to_redis_string(), Cow::Borrowed(_)));
+
+        let big = StringValue::from("-12345678901");
+        assert!(!big.is_shared());
+        assert_eq!(*big.to_redis_string(), RedisString::from("-12345678901"));
+        assert_eq!(
+            StringValue::from("007"),
+            StringValue::Raw(RedisString::from("007"))
+        );
+
+        // Editing a value makes it raw, but it's still equal to the integer.
+        value.make_raw().as_mut_vec().push(b'0');
+        assert_eq!(value, StringValue::Raw(RedisString::from("420")));
+        assert_eq!(value, StringValue::Int(420));
+    }
+
     #[test]
     fn test_debug() {
         let s = RedisString::from("hello");
@@ -0,0 +1,512 @@
+//! Client connections, which are plain TCP or, with the `tls` feature, TLS
+//! over TCP like Redis' `tls-port`. See <https://redis.io/docs/management/security/encryption/>.
+//!
+//! TLS is done with rustls. A connection is shared between the thread that
+//! reads from it and the one that writes to it, so `TlsStream` keeps the
+//! rustls state behind a mutex and only holds it while encrypting or
+//! decrypting, never while waiting on the socket. Encrypted data is written
+//! under a separate lock, so TLS records go out in the order rustls made them.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+
+#[cfg(not(feature = "tls"))]
+use color_eyre::eyre::Result;
+
+/// Settings for the TLS listener, which is disabled unless a port is given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// The port TLS connections are accepted on, or 0 for none.
+    pub port: u16,
+
+    /// The server's certificate chain, in PEM.
+    pub cert_file: Option<PathBuf>,
+
+    /// The private key for `cert_file`, in PEM.
+    pub key_file: Option<PathBuf>,
+
+    /// The CA certificates client certificates are verified against, in PEM.
+    pub ca_cert_file: Option<PathBuf>,
+
+    /// Whether clients have to present a certificate.
+    pub auth_clients: ClientAuth,
+}
+
+/// Whether clients have to present a certificate signed by the CA, like
+/// Redis' `tls-auth-clients`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Clients without a valid certificate are rejected.
+    #[default]
+    Required,
+
+    /// Certificates are verified if clients present one.
+    Optional,
+
+    /// Client certificates aren't requested.
+    No,
+}
+
+impl ClientAuth {
+    const ALL: [Self; 3] = [Self::Required, Self::Optional, Self::No];
+
+    /// The setting's value in `tls-auth-clients`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Required => "yes",
+            Self::Optional => "optional",
+            Self::No => "no",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|auth| auth.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// A connection to a client, or to a server from the client binary.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(TlsStream),
+}
+
+impl Stream {
+    /// Another handle to the same connection, so one thread can read while
+    /// another writes.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.try_clone().map(Self::Tls),
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket().peer_addr()
+    }
+
+    /// The underlying TCP connection.
+    pub const fn socket(&self) -> &TcpStream {
+        match self {
+            Self::Tcp(stream) => stream,
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => &stream.socket,
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Self::Tcp(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Settings for connecting to a server over TLS, like `redis-cli --tls`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientTlsConfig {
+    /// The CA certificates the server's certificate is verified against, in
+    /// PEM.
+    pub ca_cert_file: PathBuf,
+
+    /// The client's certificate chain and private key, in PEM, for servers
+    /// that authenticate clients.
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+}
+
+/// Wraps accepted connections in TLS.
+#[derive(Clone)]
+pub struct Acceptor {
+    #[cfg(feature = "tls")]
+    config: std::sync::Arc<rustls::ServerConfig>,
+
+    // Without the `tls` feature an acceptor can't be made.
+    #[cfg(not(feature = "tls"))]
+    never: std::convert::Infallible,
+}
+
+impl fmt::Debug for Acceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acceptor").finish_non_exhaustive()
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+impl Acceptor {
+    pub fn new(_config: &TlsConfig) -> Result<Self> {
+        Err(not_compiled_in())
+    }
+
+    // It can't be const with the `tls` feature.
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn accept(&self, _stream: TcpStream) -> Result<Stream> {
+        match self.never {}
+    }
+}
+
+/// Connects to a server over TLS.
+#[cfg(not(feature = "tls"))]
+pub fn connect(_host: &str, _port: u16, _config: &ClientTlsConfig) -> Result<Stream> {
+    Err(not_compiled_in())
+}
+
+#[cfg(not(feature = "tls"))]
+fn not_compiled_in() -> color_eyre::eyre::Report {
+    color_eyre::eyre::eyre!("TLS support isn't compiled in; build with `--features tls`")
+}
+
+#[cfg(feature = "tls")]
+pub use self::rustls_stream::TlsStream;
+
+#[cfg(feature = "tls")]
+mod rustls_stream {
+    use std::fmt;
+    use std::fs::File;
+    use std::io::{self, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::ops::Range;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use color_eyre::eyre::{eyre, Result, WrapErr};
+    use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+    use rustls::{Certificate, PrivateKey, RootCertStore, ServerName};
+
+    use super::{Acceptor, ClientAuth, ClientTlsConfig, Stream, TlsConfig};
+
+    /// The most encrypted data read from the socket at once. TLS records are
+    /// at most 16KiB plus a little overhead.
+    const READ_BUFFER_SIZE: usize = 18 * 1024;
+
+    /// A TLS connection. Clones share the same session.
+    pub struct TlsStream {
+        conn: Arc<Mutex<rustls::Connection>>,
+        pub(super) socket: TcpStream,
+
+        /// The socket encrypted data is written to. It's locked before the
+        /// session is unlocked, so writes from clones stay in order.
+        sender: Arc<Mutex<TcpStream>>,
+
+        /// Encrypted data read from the socket, allocated on the first read.
+        encrypted: Vec<u8>,
+
+        /// The part of `encrypted` rustls hasn't taken yet. It takes a few
+        /// KiB at a time, so a full socket read is fed to it over several
+        /// rounds.
+        unread: Range<usize>,
+    }
+
+    impl TlsStream {
+        fn new(conn: impl Into<rustls::Connection>, socket: TcpStream) -> io::Result<Self> {
+            Ok(Self {
+                conn: Arc::new(Mutex::new(conn.into())),
+                sender: Arc::new(Mutex::new(socket.try_clone()?)),
+                socket,
+                encrypted: Vec::new(),
+                unread: 0..0,
+            })
+        }
+
+        pub fn try_clone(&self) -> io::Result<Self> {
+            Ok(Self {
+                conn: Arc::clone(&self.conn),
+                socket: self.socket.try_clone()?,
+                sender: Arc::clone(&self.sender),
+                encrypted: Vec::new(),
+                unread: 0..0,
+            })
+        }
+
+        /// Runs `f` on the session, and then sends whatever rustls has ready,
+        /// like handshake messages or encrypted writes. The session is
+        /// unlocked before writing to the socket.
+        fn session<T>(
+            &self,
+            f: impl FnOnce(&mut rustls::Connection) -> io::Result<T>,
+        ) -> io::Result<T> {
+            let mut conn = self.conn.lock().expect("couldn't lock TLS connection");
+            let result = f(&mut conn);
+            let mut pending = Vec::new();
+            while conn.wants_write() {
+                conn.write_tls(&mut pending)?;
+            }
+            if !pending.is_empty() {
+                let mut sender = self.sender.lock().expect("couldn't lock TLS socket");
+                drop(conn);
+                sender.write_all(&pending)?;
+            }
+            result
+        }
+    }
+
+    impl fmt::Debug for TlsStream {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("TlsStream")
+                .field("socket", &self.socket)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Read for TlsStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.encrypted.is_empty() {
+                self.encrypted.resize(READ_BUFFER_SIZE, 0);
+            }
+            loop {
+                // The handshake may need a reply before the peer sends
+                // anything else, which the session sends.
+                let read = self.session(|conn| match conn.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+                    result => result.map(Some),
+                })?;
+                if let Some(n) = read {
+                    return Ok(n);
+                }
+
+                if self.unread.is_empty() {
+                    // The lock isn't held while waiting, so the other half of
+                    // the connection can keep writing.
+                    let n = (&self.socket).read(&mut self.encrypted)?;
+                    if n == 0 {
+                        // RESP messages delimit themselves, so a peer closing
+                        // the connection without a close_notify alert is
+                        // treated as a normal end of stream.
+                        return Ok(0);
+                    }
+                    self.unread = 0..n;
+                }
+
+                // The decrypted data is read out before more is fed in, so
+                // rustls' plaintext buffer never fills up.
+                let mut encrypted = &self.encrypted[self.unread.clone()];
+                // Any alert explaining a failure is sent before giving up.
+                let processed = self.session(|conn| {
+                    conn.read_tls(&mut encrypted)?;
+                    Ok(conn.process_new_packets())
+                })?;
+                self.unread.start = self.unread.end - encrypted.len();
+                processed.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+        }
+    }
+
+    impl Write for TlsStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            // Writes made during the handshake are buffered until it's done.
+            self.session(|conn| conn.writer().write(buf))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.session(|conn| conn.writer().flush())
+        }
+    }
+
+    impl Acceptor {
+        /// Loads the certificate and key, and the CA if clients are
+        /// authenticated.
+        pub fn new(config: &TlsConfig) -> Result<Self> {
+            let cert_file = config
+                .cert_file
+                .as_deref()
+                .ok_or_else(|| eyre!("tls-cert-file is required for TLS"))?;
+            let key_file = config
+                .key_file
+                .as_deref()
+                .ok_or_else(|| eyre!("tls-key-file is required for TLS"))?;
+            let builder = rustls::ServerConfig::builder().with_safe_defaults();
+            let builder = match (config.auth_clients, &config.ca_cert_file) {
+                (ClientAuth::No, _) => builder.with_no_client_auth(),
+                (_, None) => {
+                    return Err(eyre!(
+                        "tls-ca-cert-file is required to authenticate clients; \
+                         set tls-auth-clients to no to skip it"
+                    ));
+                }
+                (ClientAuth::Required, Some(ca_cert_file)) => builder.with_client_cert_verifier(
+                    AllowAnyAuthenticatedClient::new(load_roots(ca_cert_file)?).boxed(),
+                ),
+                (ClientAuth::Optional, Some(ca_cert_file)) => builder.with_client_cert_verifier(
+                    AllowAnyAnonymousOrAuthenticatedClient::new(load_roots(ca_cert_file)?).boxed(),
+                ),
+            };
+            let server_config = builder
+                .with_single_cert(load_certs(cert_file)?, load_key(key_file)?)
+                .wrap_err("invalid TLS certificate or key")?;
+            Ok(Self {
+                config: Arc::new(server_config),
+            })
+        }
+
+        /// Starts a TLS session on an accepted connection. The handshake
+        /// happens as the connection is first read from and written to.
+        pub fn accept(&self, stream: TcpStream) -> Result<Stream> {
+            let conn = rustls::ServerConnection::new(Arc::clone(&self.config))?;
+            Ok(Stream::Tls(TlsStream::new(conn, stream)?))
+        }
+    }
+
+    /// Connects to a server over TLS, verifying its certificate is for `host`.
+    pub fn connect(host: &str, port: u16, config: &ClientTlsConfig) -> Result<Stream> {
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(load_roots(&config.ca_cert_file)?);
+        let client_config = match (&config.cert_file, &config.key_file) {
+            (Some(cert_file), Some(key_file)) => builder
+                .with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)
+                .wrap_err("invalid TLS certificate or key")?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(eyre!("a client certificate and key must be given together")),
+        };
+        let server_name =
+            ServerName::try_from(host).map_err(|_| eyre!("invalid server name for TLS: {host}"))?;
+        let conn = rustls::ClientConnection::new(Arc::new(client_config), server_name)?;
+        let socket = TcpStream::connect((host, port))
+            .wrap_err_with(|| format!("failed to connect to {host}:{port}"))?;
+        Ok(Stream::Tls(TlsStream::new(conn, socket)?))
+    }
+
+    fn open(path: &Path) -> Result<BufReader<File>> {
+        let file =
+            File::open(path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+        Ok(BufReader::new(file))
+    }
+
+    fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+        let certs = rustls_pemfile::certs(&mut open(path)?)
+            .wrap_err_with(|| format!("invalid certificates in {}", path.display()))?;
+        if certs.is_empty() {
+            return Err(eyre!("no certificates in {}", path.display()));
+        }
+        Ok(certs.into_iter().map(Certificate).collect())
+    }
+
+    fn load_roots(path: &Path) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(path)? {
+            roots
+                .add(&cert)
+                .wrap_err_with(|| format!("invalid CA certificate in {}", path.display()))?;
+        }
+        Ok(roots)
+    }
+
+    fn load_key(path: &Path) -> Result<PrivateKey> {
+        let mut reader = open(path)?;
+        loop {
+            let item = rustls_pemfile::read_one(&mut reader)
+                .wrap_err_with(|| format!("invalid private key in {}", path.display()))?;
+            match item {
+                Some(
+                    rustls_pemfile::Item::RSAKey(key)
+                    | rustls_pemfile::Item::PKCS8Key(key)
+                    | rustls_pemfile::Item::ECKey(key),
+                ) => return Ok(PrivateKey(key)),
+                Some(_) => {}
+                None => return Err(eyre!("no private key in {}", path.display())),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+pub use self::rustls_stream::connect;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_auth_names() {
+        for auth in ClientAuth::ALL {
+            assert_eq!(ClientAuth::parse(auth.name()), Some(auth));
+        }
+        assert_eq!(ClientAuth::parse("OPTIONAL"), Some(ClientAuth::Optional));
+        assert_eq!(ClientAuth::parse("maybe"), None);
+    }
+
+    /// Sends values bigger than a TLS record both ways, so each socket read
+    /// holds several records.
+    #[cfg(feature = "tls")]
+    #[test]
+    fn large_values() {
+        use std::net::TcpListener;
+        use std::path::Path;
+        use std::time::Duration;
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/tls");
+        let acceptor = Acceptor::new(&TlsConfig {
+            cert_file: Some(dir.join("server.crt")),
+            key_file: Some(dir.join("server.key")),
+            auth_clients: ClientAuth::No,
+            ..TlsConfig::default()
+        })
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let value: Vec<u8> = (0..=250).cycle().take(100 * 1024).collect();
+        let len = value.len();
+
+        // The server echoes a greeting, which finishes the handshake, and
+        // then the value.
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut stream = acceptor.accept(socket).unwrap();
+            for len in [5, len] {
+                let mut received = vec![0; len];
+                stream.read_exact(&mut received).unwrap();
+                stream.write_all(&received).unwrap();
+            }
+        });
+
+        let config = ClientTlsConfig {
+            ca_cert_file: dir.join("ca.crt"),
+            ..ClientTlsConfig::default()
+        };
+        let mut client = connect("localhost", port, &config).unwrap();
+        client
+            .socket()
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        for message in [b"hello".as_slice(), &value] {
+            client.write_all(message).unwrap();
+            let mut echoed = vec![0; message.len()];
+            client.read_exact(&mut echoed).unwrap();
+            assert_eq!(echoed, message);
+        }
+        server.join().unwrap();
+    }
+}
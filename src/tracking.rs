@@ -0,0 +1,178 @@
+//! Bookkeeping for client-side caching.
+//!
+//! Clients that enable `CLIENT TRACKING` are sent invalidation messages when
+//! keys they may have cached are modified. See
+//! <https://redis.io/docs/manual/client-side-caching/>. Connections only speak
+//! RESP2, so like Redis with RESP2 clients, the messages go to another
+//! client chosen with `REDIRECT` that's subscribed to `__redis__:invalidate`.
+//!
+//! Like Redis, keys are tracked by name regardless of the database they are in.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::string::RedisString;
+
+/// How a client tracks keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackingMode {
+    /// The server remembers the keys the client reads, and invalidates each
+    /// one once.
+    Default,
+
+    /// `BCAST`: the client is told about every modified key that starts with
+    /// one of the prefixes, or about every key if there are no prefixes.
+    Broadcast(Vec<RedisString>),
+}
+
+/// A client with tracking enabled.
+#[derive(Debug)]
+struct Tracker {
+    mode: TrackingMode,
+
+    /// The client invalidation messages are sent to.
+    redirect: usize,
+}
+
+/// The clients with tracking enabled and the keys they are interested in.
+#[derive(Debug, Default)]
+pub struct Tracking {
+    clients: HashMap<usize, Tracker>,
+
+    /// Keys read by clients in the default mode, and the clients that read
+    /// them.
+    keys: HashMap<RedisString, BTreeSet<usize>>,
+}
+
+impl Tracking {
+    /// Whether no client has tracking enabled.
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Enables tracking for a client, replacing its previous mode, with
+    /// invalidation messages sent to `redirect`.
+    pub fn enable(&mut self, client: usize, mode: TrackingMode, redirect: usize) {
+        self.disable(client);
+        self.clients.insert(client, Tracker { mode, redirect });
+    }
+
+    pub fn disable(&mut self, client: usize) {
+        let removed = self.clients.remove(&client);
+        if removed.is_some_and(|tracker| tracker.mode == TrackingMode::Default) {
+            self.keys.retain(|_, clients| {
+                clients.remove(&client);
+                !clients.is_empty()
+            });
+        }
+    }
+
+    /// Remembers the keys a client read, if it tracks keys in the default
+    /// mode.
+    pub fn track_reads<'a>(
+        &mut self,
+        client: usize,
+        keys: impl IntoIterator<Item = &'a RedisString>,
+    ) {
+        if !self
+            .clients
+            .get(&client)
+            .is_some_and(|tracker| tracker.mode == TrackingMode::Default)
+        {
+            return;
+        }
+        for key in keys {
+            self.keys.entry(key.clone()).or_default().insert(client);
+        }
+    }
+
+    /// Forgets the given modified keys, returning the keys each client that
+    /// receives invalidation messages should be told about.
+    pub fn invalidate<'a>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'a RedisString>,
+    ) -> BTreeMap<usize, Vec<RedisString>> {
+        let mut invalidated: BTreeMap<usize, Vec<RedisString>> = BTreeMap::new();
+        for key in keys {
+            let readers = self.keys.remove(key).unwrap_or_default();
+            let broadcasts =
+                self.clients
+                    .iter()
+                    .filter_map(|(client, tracker)| match &tracker.mode {
+                        TrackingMode::Broadcast(prefixes)
+                            if prefixes.is_empty()
+                                || prefixes
+                                    .iter()
+                                    .any(|p| key.as_bytes().starts_with(p.as_bytes())) =>
+                        {
+                            Some(*client)
+                        }
+                        _ => None,
+                    });
+            for client in readers.into_iter().chain(broadcasts) {
+                let keys = invalidated
+                    .entry(self.clients[&client].redirect)
+                    .or_default();
+                if !keys.contains(key) {
+                    keys.push(key.clone());
+                }
+            }
+        }
+        invalidated
+    }
+
+    /// Forgets every tracked key, like when the keyspace is flushed. Returns
+    /// every client that receives invalidation messages.
+    pub fn invalidate_all(&mut self) -> Vec<usize> {
+        self.keys.clear();
+        let mut clients: Vec<usize> = self
+            .clients
+            .values()
+            .map(|tracker| tracker.redirect)
+            .collect();
+        clients.sort_unstable();
+        clients.dedup();
+        clients
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<RedisString> {
+        keys.iter().map(|k| RedisString::from(*k)).collect()
+    }
+
+    #[test]
+    fn default_mode_invalidates_once() {
+        let mut tracking = Tracking::default();
+        tracking.enable(1, TrackingMode::Default, 11);
+        tracking.track_reads(1, &keys(&["a", "b"]));
+        // Clients without tracking are ignored.
+        tracking.track_reads(2, &keys(&["a"]));
+
+        // Messages go to the client tracking redirects to.
+        let invalidated = tracking.invalidate(&keys(&["a", "c"]));
+        assert_eq!(invalidated, BTreeMap::from([(11, keys(&["a"]))]));
+        assert!(tracking.invalidate(&keys(&["a"])).is_empty());
+
+        tracking.disable(1);
+        assert!(tracking.invalidate(&keys(&["b"])).is_empty());
+    }
+
+    #[test]
+    fn broadcast_mode_matches_prefixes() {
+        let mut tracking = Tracking::default();
+        tracking.enable(1, TrackingMode::Broadcast(keys(&["user:", "post:"])), 11);
+        tracking.enable(2, TrackingMode::Broadcast(Vec::new()), 12);
+        // Clients redirecting to the same one share its messages.
+        tracking.enable(3, TrackingMode::Broadcast(keys(&["other"])), 12);
+
+        let invalidated = tracking.invalidate(&keys(&["user:1", "other"]));
+        assert_eq!(
+            invalidated,
+            BTreeMap::from([(11, keys(&["user:1"])), (12, keys(&["user:1", "other"]))])
+        );
+        assert_eq!(tracking.invalidate_all(), vec![11, 12]);
+    }
+}
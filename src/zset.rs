@@ -0,0 +1,613 @@
+//! Sorted sets, which map members to scores and iterate in score order. See
+//! <https://redis.io/docs/data-types/sorted-sets/>.
+//!
+//! Like hashes, small sorted sets are stored as an array of members and
+//! scores, and converted to a skip list once they grow past
+//! `MAX_LISTPACK_ENTRIES` members or get a member longer than
+//! `MAX_LISTPACK_VALUE` bytes, the limits Redis uses for its listpack
+//! encoding.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::slice;
+
+use crate::skiplist::{self, SkipList};
+use crate::string::RedisString;
+
+/// Redis' default `zset-max-listpack-entries`.
+pub const MAX_LISTPACK_ENTRIES: usize = 128;
+
+/// Redis' default `zset-max-listpack-value`.
+pub const MAX_LISTPACK_VALUE: usize = 64;
+
+/// A sorted set score. Scores are never NaN, so unlike `f64` they are totally
+/// ordered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(f64);
+
+impl Score {
+    /// Returns `None` if `score` is NaN.
+    pub fn new(score: f64) -> Option<Self> {
+        (!score.is_nan()).then_some(Self(score))
+    }
+
+    pub const fn value(self) -> f64 {
+        self.0
+    }
+
+    /// Parses a score like Redis does, accepting `inf`, `+inf` and `-inf` for
+    /// infinite scores.
+    pub fn parse(s: &RedisString) -> Option<Self> {
+        let s = std::str::from_utf8(s.as_bytes()).ok()?;
+        Self::new(s.parse().ok()?)
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).expect("scores are never NaN")
+    }
+}
+
+/// Formats the score the way Redis replies with it: the shortest
+/// representation that parses back to the same score, using exponent notation
+/// for very large or very small scores like `%.17g` does.
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_infinite() {
+            return f.write_str(if self.0 > 0.0 { "inf" } else { "-inf" });
+        }
+
+        let scientific = format!("{:e}", self.0);
+        let (mantissa, exponent) = scientific
+            .split_once('e')
+            .expect("exponent notation always has an exponent");
+        let exponent: i32 = exponent.parse().expect("exponent is an integer");
+        if (-4..17).contains(&exponent) {
+            write!(f, "{}", self.0)
+        } else {
+            let sign = if exponent < 0 { '-' } else { '+' };
+            write!(f, "{mantissa}e{sign}{:02}", exponent.abs())
+        }
+    }
+}
+
+/// One end of a score range. Written as the score, or as `(score` for an
+/// exclusive bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScoreBound {
+    pub score: Score,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    pub fn parse(s: &RedisString) -> Option<Self> {
+        match s.as_bytes().strip_prefix(b"(") {
+            Some(rest) => Some(Self {
+                score: Score::parse(&RedisString::from(rest))?,
+                exclusive: true,
+            }),
+            None => Some(Self {
+                score: Score::parse(s)?,
+                exclusive: false,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ScoreBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.exclusive {
+            f.write_str("(")?;
+        }
+        write!(f, "{}", self.score)
+    }
+}
+
+/// A range of scores, as used by `ZRANGE ... BYSCORE` and `ZCOUNT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScoreRange {
+    pub min: ScoreBound,
+    pub max: ScoreBound,
+}
+
+impl ScoreRange {
+    pub fn contains(&self, score: Score) -> bool {
+        self.above_min(score) && self.below_max(score)
+    }
+
+    pub fn above_min(&self, score: Score) -> bool {
+        if self.min.exclusive {
+            score > self.min.score
+        } else {
+            score >= self.min.score
+        }
+    }
+
+    pub fn below_max(&self, score: Score) -> bool {
+        if self.max.exclusive {
+            score < self.max.score
+        } else {
+            score <= self.max.score
+        }
+    }
+}
+
+/// One end of a lexicographical range: `-` and `+` for the smallest and
+/// largest possible strings, or `[member` and `(member` for inclusive and
+/// exclusive bounds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(RedisString),
+    Exclusive(RedisString),
+}
+
+impl LexBound {
+    pub fn parse(s: &RedisString) -> Option<Self> {
+        match s.as_bytes() {
+            b"-" => Some(Self::Min),
+            b"+" => Some(Self::Max),
+            [b'[', rest @ ..] => Some(Self::Inclusive(RedisString::from(rest))),
+            [b'(', rest @ ..] => Some(Self::Exclusive(RedisString::from(rest))),
+            _ => None,
+        }
+    }
+
+    pub fn to_redis_string(&self) -> RedisString {
+        match self {
+            Self::Min => RedisString::from("-"),
+            Self::Max => RedisString::from("+"),
+            Self::Inclusive(s) => RedisString::from([b"[", s.as_bytes()].concat()),
+            Self::Exclusive(s) => RedisString::from([b"(", s.as_bytes()].concat()),
+        }
+    }
+}
+
+/// A lexicographical range of members, as used by `ZRANGE ... BYLEX`. Only
+/// meaningful when all members have the same score.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl LexRange {
+    pub fn contains(&self, member: &RedisString) -> bool {
+        self.above_min(member) && self.below_max(member)
+    }
+
+    pub fn above_min(&self, member: &RedisString) -> bool {
+        match &self.min {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(min) => member >= min,
+            LexBound::Exclusive(min) => member > min,
+        }
+    }
+
+    pub fn below_max(&self, member: &RedisString) -> bool {
+        match &self.max {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(max) => member <= max,
+            LexBound::Exclusive(max) => member < max,
+        }
+    }
+}
+
+/// A set of unique members ordered by score, with ties ordered by the members'
+/// bytes.
+#[derive(Debug, Clone)]
+pub struct SortedSet {
+    encoding: Encoding,
+}
+
+#[derive(Debug, Clone)]
+enum Encoding {
+    /// Members and their scores in order.
+    Listpack(Vec<(RedisString, Score)>),
+
+    /// Members are kept in a skip list for ordered and rank-based lookups,
+    /// with a map alongside it for looking up scores by member.
+    SkipList {
+        scores: HashMap<RedisString, Score>,
+        ordered: SkipList,
+    },
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Listpack(members) => members.len(),
+            Encoding::SkipList { scores, .. } => scores.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn score(&self, member: &RedisString) -> Option<Score> {
+        match &self.encoding {
+            Encoding::Listpack(members) => members
+                .iter()
+                .find(|(m, _)| m == member)
+                .map(|&(_, score)| score),
+            Encoding::SkipList { scores, .. } => scores.get(member).copied(),
+        }
+    }
+
+    /// Adds a member or updates its score, returning the previous score.
+    pub fn insert(&mut self, member: RedisString, score: Score) -> Option<Score> {
+        if let Encoding::Listpack(members) = &self.encoding {
+            let fits = member.len() <= MAX_LISTPACK_VALUE
+                && (members.len() < MAX_LISTPACK_ENTRIES
+                    || members.iter().any(|(m, _)| *m == member));
+            if !fits {
+                self.convert_to_skip_list();
+            }
+        }
+        match &mut self.encoding {
+            Encoding::Listpack(members) => {
+                let previous = members.iter().position(|(m, _)| *m == member);
+                let previous = previous.map(|index| members.remove(index).1);
+                let index = members.partition_point(|(m, s)| (*s, m) < (score, &member));
+                members.insert(index, (member, score));
+                previous
+            }
+            Encoding::SkipList { scores, ordered } => {
+                let previous = scores.insert(member.clone(), score);
+                if let Some(previous) = previous {
+                    if previous == score {
+                        return Some(previous);
+                    }
+                    ordered.remove(previous, &member);
+                }
+                ordered.insert(score, member);
+                previous
+            }
+        }
+    }
+
+    /// Removes a member, returning its score.
+    pub fn remove(&mut self, member: &RedisString) -> Option<Score> {
+        match &mut self.encoding {
+            Encoding::Listpack(members) => {
+                let index = members.iter().position(|(m, _)| m == member)?;
+                Some(members.remove(index).1)
+            }
+            Encoding::SkipList { scores, ordered } => {
+                let score = scores.remove(member)?;
+                ordered.remove(score, member);
+                Some(score)
+            }
+        }
+    }
+
+    /// The member's 0-based rank, counting from the lowest score.
+    pub fn rank(&self, member: &RedisString) -> Option<usize> {
+        match &self.encoding {
+            Encoding::Listpack(members) => members.iter().position(|(m, _)| m == member),
+            Encoding::SkipList { ordered, .. } => ordered.rank(self.score(member)?, member),
+        }
+    }
+
+    /// Iterates over the members from lowest to highest score.
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::Listpack(members) => Iter::Listpack(members.iter()),
+            Encoding::SkipList { ordered, .. } => Iter::SkipList(ordered.iter()),
+        }
+    }
+
+    /// Iterates over the members with 0-based ranks in `range`, from lowest to
+    /// highest score.
+    pub fn range_by_rank(&self, range: Range<usize>) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::Listpack(members) => {
+                let end = range.end.min(members.len());
+                Iter::Listpack(members[range.start.min(end)..end].iter())
+            }
+            Encoding::SkipList { ordered, .. } => {
+                Iter::SkipList(ordered.range_by_rank(range.start, range.end))
+            }
+        }
+    }
+
+    /// Iterates over the members whose scores are in `range`, from lowest to
+    /// highest score.
+    pub fn range_by_score(&self, range: &ScoreRange) -> Iter<'_> {
+        self.range_where(
+            |score, _| range.above_min(score),
+            |score, _| range.below_max(score),
+        )
+    }
+
+    /// Iterates over the members in `range`, in order.
+    pub fn range_by_lex(&self, range: &LexRange) -> Iter<'_> {
+        self.range_where(
+            |_, member| range.above_min(member),
+            |_, member| range.below_max(member),
+        )
+    }
+
+    /// How the sorted set is stored, as reported by `OBJECT ENCODING`.
+    pub const fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Listpack(_) => "array",
+            Encoding::SkipList { .. } => "skiplist",
+        }
+    }
+
+    pub const fn is_listpack(&self) -> bool {
+        matches!(self.encoding, Encoding::Listpack(_))
+    }
+
+    /// See `SkipList::range_where`.
+    fn range_where(
+        &self,
+        starts: impl Fn(Score, &RedisString) -> bool,
+        ends: impl Fn(Score, &RedisString) -> bool,
+    ) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::Listpack(members) => {
+                let start = members.partition_point(|(m, s)| !starts(*s, m));
+                let end = members.partition_point(|(m, s)| ends(*s, m));
+                Iter::Listpack(members[start.min(end)..end].iter())
+            }
+            Encoding::SkipList { ordered, .. } => Iter::SkipList(ordered.range_where(starts, ends)),
+        }
+    }
+
+    fn convert_to_skip_list(&mut self) {
+        if let Encoding::Listpack(members) = &mut self.encoding {
+            let mut scores = HashMap::with_capacity(members.len());
+            let mut ordered = SkipList::default();
+            for (member, score) in std::mem::take(members) {
+                scores.insert(member.clone(), score);
+                ordered.insert(score, member);
+            }
+            self.encoding = Encoding::SkipList { scores, ordered };
+        }
+    }
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Listpack(Vec::new()),
+        }
+    }
+}
+
+/// Iterates over a sorted set's members and their scores, in order.
+pub enum Iter<'a> {
+    Listpack(slice::Iter<'a, (RedisString, Score)>),
+    SkipList(skiplist::Iter<'a>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a RedisString, Score);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Listpack(members) => members.next().map(|(member, score)| (member, *score)),
+            Self::SkipList(members) => members.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Listpack(members) => members.size_hint(),
+            Self::SkipList(members) => members.size_hint(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Listpack(members) => members.next_back().map(|(member, score)| (member, *score)),
+            Self::SkipList(members) => members.next_back(),
+        }
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a SortedSet {
+    type Item = (&'a RedisString, Score);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Sorted sets are equal when they have the same members with the same scores,
+/// regardless of how they're stored.
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(member, score)| other.score(member) == Some(score))
+    }
+}
+
+impl Eq for SortedSet {}
+
+impl FromIterator<(RedisString, Score)> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = (RedisString, Score)>>(iter: I) -> Self {
+        let mut zset = Self::default();
+        for (member, score) in iter {
+            zset.insert(member, score);
+        }
+        zset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(s: &str) -> Option<Score> {
+        Score::parse(&RedisString::from(s))
+    }
+
+    #[test]
+    fn parse_and_format_scores() {
+        let cases = [
+            ("1", "1"),
+            ("-2.5", "-2.5"),
+            ("0.1", "0.1"),
+            ("1e3", "1000"),
+            ("+inf", "inf"),
+            ("-INF", "-inf"),
+            ("3.0000000000000004", "3.0000000000000004"),
+            ("0.0001", "0.0001"),
+            ("0.00001", "1e-05"),
+            ("1e17", "1e+17"),
+            ("1.5e300", "1.5e+300"),
+        ];
+        for (input, formatted) in cases {
+            assert_eq!(score(input).unwrap().to_string(), formatted, "{input}");
+        }
+        assert_eq!(score("nan"), None);
+        assert_eq!(score("abc"), None);
+        assert_eq!(score(" 1"), None);
+    }
+
+    #[test]
+    fn ordered_iteration() {
+        let mut zset = SortedSet::default();
+        let add = |zset: &mut SortedSet, member: &str, s: &str| {
+            zset.insert(RedisString::from(member), score(s).unwrap())
+        };
+        assert_eq!(add(&mut zset, "b", "2"), None);
+        add(&mut zset, "a", "2");
+        add(&mut zset, "c", "-inf");
+        assert_eq!(add(&mut zset, "d", "5"), None);
+        assert_eq!(add(&mut zset, "d", "1"), score("5"));
+        assert_eq!(zset.len(), 4);
+
+        let members: Vec<_> = zset
+            .iter()
+            .map(|(member, score)| (member.clone(), score.to_string()))
+            .collect();
+        let expected: Vec<_> = [("c", "-inf"), ("d", "1"), ("a", "2"), ("b", "2")]
+            .into_iter()
+            .map(|(m, s)| (RedisString::from(m), s.to_string()))
+            .collect();
+        assert_eq!(members, expected);
+
+        assert_eq!(zset.rank(&RedisString::from("a")), Some(2));
+        assert_eq!(zset.rank(&RedisString::from("missing")), None);
+
+        assert_eq!(zset.remove(&RedisString::from("a")), score("2"));
+        assert_eq!(zset.remove(&RedisString::from("a")), None);
+        assert_eq!(zset.iter().next_back().unwrap().0, &RedisString::from("b"));
+    }
+
+    #[test]
+    fn ranges() {
+        let zset: SortedSet = [("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", 3.0)]
+            .into_iter()
+            .map(|(m, s)| (RedisString::from(m), Score::new(s).unwrap()))
+            .collect();
+        let members = |iter: &mut dyn Iterator<Item = (&RedisString, Score)>| {
+            iter.map(|(m, _)| String::try_from(m.clone()).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let bound = |s: &str| ScoreBound::parse(&RedisString::from(s)).unwrap();
+        let range = ScoreRange {
+            min: bound("(1"),
+            max: bound("3"),
+        };
+        assert_eq!(members(&mut zset.range_by_score(&range)), ["b", "c", "d"]);
+        let range = ScoreRange {
+            min: bound("-inf"),
+            max: bound("(3"),
+        };
+        assert_eq!(members(&mut zset.range_by_score(&range).rev()), ["b", "a"]);
+        assert_eq!(bound("(1.5").to_string(), "(1.5");
+        assert!(ScoreBound::parse(&RedisString::from("(")).is_none());
+
+        let lex = |s: &str| LexBound::parse(&RedisString::from(s)).unwrap();
+        let range = LexRange {
+            min: lex("(a"),
+            max: lex("[c"),
+        };
+        assert_eq!(members(&mut zset.range_by_lex(&range)), ["b", "c"]);
+        let range = LexRange {
+            min: lex("-"),
+            max: lex("+"),
+        };
+        assert_eq!(members(&mut zset.range_by_lex(&range)).len(), 4);
+        assert_eq!(lex("[b").to_redis_string(), RedisString::from("[b"));
+        assert!(LexBound::parse(&RedisString::from("b")).is_none());
+    }
+
+    #[test]
+    fn encodings_agree() {
+        // Scores repeat so that ties are ordered by member.
+        let listpack: SortedSet = (0..MAX_LISTPACK_ENTRIES)
+            .map(|n| {
+                let member = RedisString::from(format!("m{}", n * 7 % MAX_LISTPACK_ENTRIES));
+                (member, score(&(n % 10).to_string()).unwrap())
+            })
+            .collect();
+        assert_eq!(listpack.encoding(), "array");
+
+        // Adding a long member converts it, and removing it doesn't convert
+        // it back.
+        let mut skip_list = listpack.clone();
+        let long = RedisString::from("x".repeat(MAX_LISTPACK_VALUE + 1));
+        skip_list.insert(long.clone(), Score::new(0.0).unwrap());
+        assert_eq!(skip_list.encoding(), "skiplist");
+        skip_list.remove(&long);
+        assert_eq!(skip_list.encoding(), "skiplist");
+        assert_eq!(skip_list, listpack);
+
+        let collect = |iter: Iter<'_>| -> Vec<(RedisString, Score)> {
+            iter.map(|(member, score)| (member.clone(), score))
+                .collect()
+        };
+        assert_eq!(collect(listpack.iter()), collect(skip_list.iter()));
+        for range in [0..5, 20..40, 95..200] {
+            assert_eq!(
+                collect(listpack.range_by_rank(range.clone())),
+                collect(skip_list.range_by_rank(range)),
+            );
+        }
+        let range = ScoreRange {
+            min: ScoreBound::parse(&RedisString::from("(2")).unwrap(),
+            max: ScoreBound::parse(&RedisString::from("5")).unwrap(),
+        };
+        assert_eq!(
+            collect(listpack.range_by_score(&range)),
+            collect(skip_list.range_by_score(&range))
+        );
+        for member in ["m0", "m13", "m99", "missing"] {
+            let member = RedisString::from(member);
+            assert_eq!(listpack.rank(&member), skip_list.rank(&member));
+        }
+
+        let mut zset: SortedSet = listpack;
+        zset.insert(RedisString::from("one more"), Score::new(1.0).unwrap());
+        assert_eq!(zset.encoding(), "skiplist");
+    }
+}